@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mailfmt::{
+    envelope_for,
+    headers::{get_header_value, is_header_line},
+};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(content) = std::str::from_utf8(data) else {
+        return;
+    };
+    for line in content.lines() {
+        let _ = is_header_line(line);
+    }
+    for name in ["from", "date", "subject", "x-k"] {
+        let _ = get_header_value(content, name);
+    }
+    let _ = envelope_for(content);
+});
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mailfmt::{MboxDialect, MboxParser, MboxWriter};
+
+// Parse arbitrary bytes as an mbox and write every message back out, which covers the
+// header lookups and the quoting rules on whatever the parser produces.
+fuzz_target!(|data: &[u8]| {
+    let mut writer = MboxWriter::new(Vec::new(), MboxDialect::Mboxcl);
+    for message in MboxParser::from_reader(data) {
+        let Ok(message) = message else {
+            continue;
+        };
+        let _ = message.header("subject");
+        let _ = message.header("from");
+        writer
+            .append_message(&message)
+            .expect("writing to a Vec does not fail");
+    }
+});
@@ -0,0 +1,781 @@
+//! Anonymization of an mbox, for sharing one that reproduces a problem without giving away
+//! the mail in it.
+
+use crate::{
+    Event, EventHandler, MailfmtError, MessageError, ProgressTotal,
+    atomic::AtomicFile,
+    counting::CountingReader,
+    error::{IoContext, Result},
+    gzip,
+    headers::{SUBJECT_MARKERS, decode_encoded_word},
+    mbox::{MboxParser, check_mbox_input},
+    mime::{Entity, MAX_DEPTH, decode_base64, encode_base64},
+    paths,
+    sample::{SplitMix64, random_seed},
+    writer::{MboxDialect, MboxWriter},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tracing::info_span;
+
+/// Headers describing the structure of a message rather than its content, which are kept as
+/// they are apart from the file names in their parameters.
+const STRUCTURAL_HEADERS: &[&str] = &[
+    "content-type",
+    "content-transfer-encoding",
+    "content-disposition",
+    "mime-version",
+    "date",
+    "status",
+    "x-status",
+    "x-mozilla-status",
+    "x-mozilla-status2",
+];
+
+/// Headers holding addresses, whose addresses and display names are replaced.
+const ADDRESS_HEADERS: &[&str] = &[
+    "from",
+    "to",
+    "cc",
+    "bcc",
+    "reply-to",
+    "sender",
+    "return-path",
+    "delivered-to",
+    "x-original-to",
+    "envelope-to",
+    "errors-to",
+    "mail-followup-to",
+    "mail-reply-to",
+    "disposition-notification-to",
+    "resent-from",
+    "resent-to",
+    "resent-cc",
+    "resent-bcc",
+    "resent-sender",
+];
+
+/// Headers holding message identifiers, whose domains are replaced so that threads survive.
+const ID_HEADERS: &[&str] = &[
+    "message-id",
+    "in-reply-to",
+    "references",
+    "resent-message-id",
+    "content-id",
+];
+
+/// The text bodies are replaced with, repeated as needed. It has no "From " to quote.
+const LOREM: &str = "lorem ipsum dolor sit amet consectetur adipiscing elit sed do eiusmod tempor \
+incididunt ut labore et dolore magna aliqua ut enim ad minim veniam quis nostrud exercitation \
+ullamco laboris nisi ut aliquip ex ea commodo consequat duis aute irure dolor in reprehenderit \
+in voluptate velit esse cillum dolore eu fugiat nulla pariatur excepteur sint occaecat \
+cupidatat non proident sunt in culpa qui officia deserunt mollit anim id est laborum ";
+
+/// The names display names are replaced with, combined from a first and a last name.
+const FIRST_NAMES: &[&str] = &[
+    "Alex", "Blake", "Casey", "Dana", "Eden", "Finley", "Gray", "Harper", "Indy", "Jordan", "Kai",
+    "Logan", "Morgan", "Noa", "Quinn", "Riley",
+];
+const LAST_NAMES: &[&str] = &[
+    "Abbott", "Brooks", "Carter", "Dalton", "Ellis", "Foster", "Garcia", "Hayes", "Ingram",
+    "Jensen", "Keller", "Lambert", "Moreno", "Nolan", "Porter", "Reyes",
+];
+
+/// Options for [`anonymize_mbox`]. Create them with [`AnonymizeOptions::new`] and adjust the
+/// fields as needed.
+pub struct AnonymizeOptions<'a> {
+    pub input_file: PathBuf,
+    pub output_file: PathBuf,
+    /// The seed pseudonyms and scrambled words are derived from, so that the same seed gives
+    /// the same output for the same input. A random one is drawn when none is given, and
+    /// returned in [`Anonymized::seed`]. Anyone who knows the seed can check whether a guessed
+    /// address or word is in the input, so it should only be shared with those who need to
+    /// produce the same output.
+    pub seed: Option<u64>,
+    /// Headers to leave as they are, by name, matched case-insensitively.
+    pub keep_headers: Vec<String>,
+    /// Replace the output file if it already exists.
+    pub overwrite: bool,
+    /// Read the input file even if it does not look like an mbox file.
+    pub force: bool,
+    /// Set to stop after the current message. Nothing is written then.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called with progress and unreadable messages as the anonymization goes on.
+    pub on_event: Option<EventHandler<'a>>,
+}
+
+impl AnonymizeOptions<'_> {
+    pub fn new(input_file: impl Into<PathBuf>, output_file: impl Into<PathBuf>) -> Self {
+        Self {
+            input_file: input_file.into(),
+            output_file: output_file.into(),
+            seed: None,
+            keep_headers: Vec::new(),
+            overwrite: false,
+            force: false,
+            cancel: None,
+            on_event: None,
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
+
+    /// Report a message that could not be anonymized, returning it for the summary.
+    fn failed(&self, item: String, error: MailfmtError) -> Result<MessageError> {
+        self.emit(Event::Failed {
+            item: item.clone(),
+            error: &error,
+        })?;
+        Ok(MessageError { item, error })
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// The outcome of [`anonymize_mbox`].
+#[derive(Debug, Default)]
+pub struct Anonymized {
+    /// How many messages were anonymized.
+    pub messages: usize,
+    /// The messages that could not be read and were left out, in order.
+    pub errors: Vec<MessageError>,
+    /// The seed the output was derived from, which gives the same output again.
+    pub seed: u64,
+    /// Whether the anonymization was cancelled, in which case nothing was written.
+    pub interrupted: bool,
+    pub bytes_written: u64,
+    pub elapsed: Duration,
+}
+
+/// Write an anonymized copy of an mbox file, for sharing one that reproduces a problem without
+/// giving away the mail in it. The structure of every message is kept while its content is
+/// replaced:
+///
+/// - Addresses become pseudonyms such as `user-1a2b3c4d@5e6f7a8b.example`, the same address
+///   always becoming the same pseudonym, and display names become made-up names that also
+///   follow the address. The sender on the separator line is replaced the same way.
+/// - The domains in Message-ID, In-Reply-To, References and Content-ID are replaced like
+///   those of addresses, so that threads survive.
+/// - Content-Type, Content-Transfer-Encoding, Content-Disposition, MIME-Version, Date and the
+///   status headers of mail clients are kept, apart from the file names in their parameters.
+///   Content-Length is dropped, as the length may change.
+/// - Every other header, such as Subject or Received, has its words scrambled into letters and
+///   digits of the same length, the same word always becoming the same scramble. Encoded words
+///   stay encoded words in the same charset and encoding, and reply markers such as "Re:" are
+///   kept.
+/// - Bodies are replaced with lorem ipsum of the same length, line by line, keeping multipart
+///   delimiters, the headers of parts, anonymized like those of messages, and transfer
+///   encodings. Quoted-printable escapes become `=20` and base64 content is replaced before
+///   being encoded again in lines as long as before.
+///
+/// Headers in [`AnonymizeOptions::keep_headers`] are left as they are. Messages that cannot
+/// be read are reported through [`Event::Failed`] and left out.
+///
+/// ```
+/// use mailfmt::{AnonymizeOptions, MboxParser, anonymize_mbox};
+/// use std::{
+///     fs::{self, File},
+///     io::BufReader,
+/// };
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-anonymize-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// fs::write(
+///     dir.join("in.mbox"),
+///     "From alice@example.com Mon Jan 01 00:00:00 2024\n\
+///      From: Alice Liddell <alice@example.com>\n\
+///      Subject: Tea party\n\
+///      \n\
+///      Hello there\n",
+/// )?;
+///
+/// let mut options = AnonymizeOptions::new(dir.join("in.mbox"), dir.join("out.mbox"));
+/// options.seed = Some(42);
+/// anonymize_mbox(&options)?;
+/// let output = fs::read_to_string(dir.join("out.mbox"))?;
+/// assert!(!output.contains("alice") && !output.contains("Tea"));
+/// let mut messages = MboxParser::from_reader(BufReader::new(File::open(dir.join("out.mbox"))?));
+/// let message = messages.next().unwrap()?;
+/// assert_eq!(message.header("subject").unwrap().len(), "Tea party".len());
+/// assert_eq!(message.lines[3].len(), "Hello there".len());
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn anonymize_mbox(options: &AnonymizeOptions<'_>) -> Result<Anonymized> {
+    let _span = info_span!("anonymize_mbox", input = %options.input_file.display()).entered();
+    let start = Instant::now();
+    let (input_file, output_file) = (options.input_file.as_path(), options.output_file.as_path());
+    if output_file.exists() && !options.overwrite {
+        return Err(MailfmtError::OutputExists {
+            path: output_file.to_path_buf(),
+        });
+    }
+    check_mbox_input(input_file, options.force, false)?;
+    if paths::same_file(input_file, output_file) {
+        return Err(MailfmtError::OutputIsInput {
+            output: output_file.to_path_buf(),
+            input: input_file.to_path_buf(),
+        });
+    }
+
+    let input_size =
+        gzip::content_size(input_file).io_context("read metadata of mbox file", input_file)?;
+    let reader =
+        CountingReader::new(gzip::open(input_file, 0).io_context("open mbox file", input_file)?);
+    let bytes_read = reader.counter();
+    let file = AtomicFile::create(output_file).io_context("create mbox file", output_file)?;
+    let mut writer = MboxWriter::new(BufWriter::new(file), MboxDialect::default());
+    let mut anonymized = Anonymized {
+        seed: options.seed.unwrap_or_else(random_seed),
+        ..Anonymized::default()
+    };
+    let anonymizer = Anonymizer {
+        seed: anonymized.seed,
+        keep_headers: &options.keep_headers,
+    };
+    options.emit(Event::Started {
+        total: match input_size {
+            0 => ProgressTotal::Unknown,
+            size => ProgressTotal::Bytes(size),
+        },
+        position: 0,
+    })?;
+    for (index, message) in MboxParser::from_reader(BufReader::new(reader)).enumerate() {
+        if options.is_cancelled() {
+            anonymized.interrupted = true;
+            break;
+        }
+        match message {
+            Ok(message) => {
+                let mut content = anonymizer.entity(&message.lines, 0).join(&b'\n');
+                content.push(b'\n');
+                writer
+                    .append_raw(&content, &anonymizer.envelope(&message.envelope))
+                    .io_context("write mbox file", output_file)?;
+                anonymized.messages += 1;
+            }
+            Err(error) => anonymized
+                .errors
+                .push(options.failed(format!("reading email {index}"), error)?),
+        }
+        options.emit(Event::Progress {
+            position: bytes_read.load(Ordering::Relaxed),
+            processed: index + 1,
+        })?;
+    }
+
+    // An interrupted mbox is never moved into place; dropping it removes its temporary file.
+    anonymized.bytes_written = writer.bytes_written();
+    let file = writer
+        .finish()
+        .and_then(|output| output.into_inner().map_err(|e| e.into_error()))
+        .io_context("flush mbox file", output_file)?;
+    if anonymized.interrupted {
+        anonymized.bytes_written = 0;
+    } else {
+        file.commit()
+            .io_context("move mbox file into place", output_file)?;
+        options.emit(Event::Written(output_file))?;
+    }
+    anonymized.elapsed = start.elapsed();
+    Ok(anonymized)
+}
+
+/// Replaces the content of messages, deriving everything from the seed so that the same input
+/// always gives the same output.
+struct Anonymizer<'a> {
+    seed: u64,
+    keep_headers: &'a [String],
+}
+
+impl Anonymizer<'_> {
+    /// A generator seeded from the seed and a value, so that the same value always gives the
+    /// same numbers.
+    fn rng(&self, kind: &str, value: &str) -> SplitMix64 {
+        let mut hasher = Sha256::new();
+        hasher.update(self.seed.to_le_bytes());
+        hasher.update(kind.as_bytes());
+        hasher.update([0]);
+        hasher.update(value.to_lowercase().as_bytes());
+        let hash = hasher.finalize();
+        SplitMix64(u64::from_le_bytes(hash[..8].try_into().expect("8 bytes")))
+    }
+
+    fn hex(&self, kind: &str, value: &str) -> String {
+        format!("{:08x}", self.rng(kind, value).next() as u32)
+    }
+
+    /// The pseudonym of a domain, under the reserved `.example` top-level domain.
+    fn domain(&self, domain: &str) -> String {
+        format!("{}.example", self.hex("domain", domain))
+    }
+
+    /// The pseudonym of an address. The domain is replaced on its own, so that addresses of
+    /// the same domain keep sharing one.
+    fn address(&self, address: &str) -> String {
+        match address.rsplit_once('@') {
+            Some((_, domain)) => format!(
+                "user-{}@{}",
+                self.hex("address", address),
+                self.domain(domain)
+            ),
+            None => format!("user-{}", self.hex("address", address)),
+        }
+    }
+
+    /// A made-up display name for the owner of an address.
+    fn name(&self, address: &str) -> String {
+        let mut rng = self.rng("name", address);
+        let first = FIRST_NAMES[rng.below(FIRST_NAMES.len() as u64) as usize];
+        let last = LAST_NAMES[rng.below(LAST_NAMES.len() as u64) as usize];
+        format!("{first} {last}")
+    }
+
+    /// The separator line of a message with its sender replaced, keeping its date.
+    fn envelope(&self, envelope: &str) -> String {
+        match envelope.split_once(' ') {
+            Some((sender, date)) if sender.contains('@') => {
+                format!("{} {date}", self.address(sender))
+            }
+            _ => envelope.to_string(),
+        }
+    }
+
+    /// Anonymize a message or part: its header, and its body depending on its type.
+    fn entity(&self, lines: &[Vec<u8>], depth: usize) -> Vec<Vec<u8>> {
+        let entity = Entity::parse(lines);
+        let mut anonymized = self.header(&entity);
+        if entity.header.len() == lines.len() {
+            return anonymized;
+        }
+        anonymized.push(lines[entity.header.len()].clone());
+        let body = entity.body;
+        if entity.content_type.is("multipart")
+            && depth < MAX_DEPTH
+            && let Some(multipart) = entity.multipart()
+        {
+            anonymized.extend(body[..multipart.delimiters[0]].iter().map(|l| lorem(l)));
+            for part in multipart.parts() {
+                anonymized.push(body[part.start - 1].clone());
+                anonymized.extend(self.entity(&body[part], depth + 1));
+            }
+            if multipart.closed {
+                let close = *multipart.delimiters.last().expect("not empty");
+                anonymized.push(body[close].clone());
+                anonymized.extend(body[close + 1..].iter().map(|l| lorem(l)));
+            }
+            return anonymized;
+        }
+        let encoding = entity.transfer_encoding();
+        if entity.content_type.is("message/rfc822")
+            && depth < MAX_DEPTH
+            && ["7bit", "8bit", "binary"].contains(&encoding.as_str())
+        {
+            anonymized.extend(self.entity(body, depth + 1));
+            return anonymized;
+        }
+        match encoding.as_str() {
+            "quoted-printable" => anonymized.extend(body.iter().map(|l| lorem_quoted_printable(l))),
+            "base64" => anonymized.extend(lorem_base64(body)),
+            _ => anonymized.extend(body.iter().map(|l| lorem(l))),
+        }
+        anonymized
+    }
+
+    /// Anonymize the fields of a header block, each as its name calls for.
+    fn header(&self, entity: &Entity<'_>) -> Vec<Vec<u8>> {
+        let mut anonymized = Vec::new();
+        for field in &entity.fields {
+            let lines = &entity.header[field.lines.clone()];
+            let name = field.name.to_ascii_lowercase();
+            if self
+                .keep_headers
+                .iter()
+                .any(|kept| kept.eq_ignore_ascii_case(&name))
+            {
+                anonymized.extend_from_slice(lines);
+            } else if name == "content-length" || name == "lines" {
+                // The length may have changed.
+            } else if ADDRESS_HEADERS.contains(&name.as_str()) {
+                let value = self.addresses(field.value.trim());
+                anonymized.push(format!("{}: {value}", field.name).into_bytes());
+            } else {
+                let rewrite = |text: &str| match name.as_str() {
+                    name if STRUCTURAL_HEADERS.contains(&name) => self.parameters(text),
+                    name if ID_HEADERS.contains(&name) => self.identifiers(text),
+                    _ => self.scramble(text),
+                };
+                for (index, line) in lines.iter().enumerate() {
+                    let line = String::from_utf8_lossy(line);
+                    // A stray continuation line at the start has no name to keep.
+                    let first = index == 0 && !field.name.is_empty();
+                    let rewritten = match line.split_once(':').filter(|_| first) {
+                        Some((name, value)) => format!("{name}:{}", rewrite(value)),
+                        None => rewrite(&line),
+                    };
+                    anonymized.push(rewritten.into_bytes());
+                }
+            }
+        }
+        anonymized
+    }
+
+    /// Replace the addresses and display names of an address list, writing it anew.
+    fn addresses(&self, value: &str) -> String {
+        let mut anonymized = Vec::new();
+        for mailbox in split_outside_quotes(value, ',') {
+            let mut mailbox = mailbox.trim();
+            let mut written = String::new();
+            // A group, as in "Team: a@example.com, b@example.com;".
+            if let Some((group, members)) = mailbox.split_once(':')
+                && !group.contains(['<', '@', '"'])
+            {
+                written.push_str(&format!("{}: ", self.scramble(group.trim())));
+                mailbox = members.trim_start();
+            }
+            let end_of_group = mailbox.ends_with(';');
+            mailbox = mailbox.trim_end_matches(';').trim_end();
+            match mailbox.rfind('<') {
+                Some(start) => {
+                    let address = mailbox[start + 1..].trim_end_matches('>').trim();
+                    let display = mailbox[..start].trim();
+                    if !display.is_empty() {
+                        written.push_str(&format!("\"{}\" ", self.name(address)));
+                    }
+                    match address.is_empty() {
+                        true => written.push_str("<>"),
+                        false => written.push_str(&format!("<{}>", self.address(address))),
+                    }
+                }
+                None => {
+                    let address = strip_comments(mailbox);
+                    match address.contains('@') {
+                        true => written.push_str(&self.address(address.trim())),
+                        false => written.push_str(&self.scramble(address.trim())),
+                    }
+                }
+            }
+            if end_of_group {
+                written.push(';');
+            }
+            anonymized.push(written);
+        }
+        anonymized.join(", ")
+    }
+
+    /// Replace the domain of every `<local@domain>` identifier, keeping their local parts.
+    fn identifiers(&self, text: &str) -> String {
+        let mut anonymized = String::new();
+        let mut rest = text;
+        while let Some(at) = rest.find('@') {
+            let end = rest[at..]
+                .find(['>', ' ', '\t'])
+                .map_or(rest.len(), |end| at + end);
+            anonymized.push_str(&rest[..=at]);
+            anonymized.push_str(&self.domain(&rest[at + 1..end]));
+            rest = &rest[end..];
+        }
+        anonymized.push_str(rest);
+        anonymized
+    }
+
+    /// Scramble the file names in the parameters of a structural header, keeping the rest.
+    fn parameters(&self, text: &str) -> String {
+        split_outside_quotes(text, ';')
+            .into_iter()
+            .map(|parameter| {
+                let Some((name, value)) = parameter.split_once('=') else {
+                    return parameter.to_string();
+                };
+                let key = name.trim().to_ascii_lowercase();
+                let key = key.split('*').next().unwrap_or_default();
+                if key != "name" && key != "filename" {
+                    return parameter.to_string();
+                }
+                if !name.trim_end().ends_with('*') {
+                    return format!("{name}={}", self.scramble(value));
+                }
+                // An RFC 2231 value starts with its charset and language, and its percent-encoded
+                // bytes would give away the characters.
+                let (labels, text) = match value.rsplit_once('\'') {
+                    Some((labels, text)) => (&value[..=labels.len()], text),
+                    None => ("", value),
+                };
+                let text = replace_percent_escapes(text)
+                    .split(PERCENT_UNDERSCORE)
+                    .map(|piece| self.scramble(piece))
+                    .collect::<Vec<_>>()
+                    .join(PERCENT_UNDERSCORE);
+                format!("{name}={labels}{text}")
+            })
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+
+    /// Scramble the words of some header text into letters and digits of the same length,
+    /// keeping everything else, reply markers, and encoded words as encoded words.
+    fn scramble(&self, text: &str) -> String {
+        let mut scrambled = String::with_capacity(text.len());
+        let mut rest = text;
+        while !rest.is_empty() {
+            if rest.starts_with("=?")
+                && let Some((decoded, length)) = decode_encoded_word(rest)
+            {
+                scrambled.push_str(&self.encoded_word(&rest[..length], &decoded));
+                rest = &rest[length..];
+                continue;
+            }
+            let word_length = rest
+                .find(|c: char| !c.is_alphanumeric())
+                .unwrap_or(rest.len());
+            if word_length == 0 {
+                let c = rest.chars().next().expect("not empty");
+                scrambled.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            let (word, after) = rest.split_at(word_length);
+            scrambled.push_str(&self.scramble_word(word, after));
+            rest = after;
+        }
+        scrambled
+    }
+
+    /// Scramble a word, unless it is a reply marker followed by its colon.
+    fn scramble_word(&self, word: &str, after: &str) -> String {
+        if after.starts_with([':', '：'])
+            && SUBJECT_MARKERS
+                .iter()
+                .any(|marker| word.eq_ignore_ascii_case(marker))
+        {
+            return word.to_string();
+        }
+        let mut rng = self.rng("word", word);
+        word.chars()
+            .map(|c| {
+                let (base, choices) = match c {
+                    c if c.is_numeric() => (b'0', 10),
+                    c if c.is_uppercase() => (b'A', 26),
+                    _ => (b'a', 26),
+                };
+                char::from(base + rng.below(choices) as u8)
+            })
+            .collect()
+    }
+
+    /// Scramble the text of an encoded word, encoding it again in its charset and encoding.
+    /// The scrambled text is ASCII, which every charset used in mail can hold.
+    fn encoded_word(&self, word: &str, decoded: &str) -> String {
+        let mut labels = word[2..].splitn(3, '?');
+        let charset = labels.next().unwrap_or_default();
+        let encoding = labels.next().unwrap_or_default();
+        let text = self.scramble(decoded);
+        let encoded = match encoding {
+            "B" | "b" => encode_base64(text.as_bytes()),
+            _ => text
+                .bytes()
+                .map(|b| match b {
+                    b' ' => "_".to_string(),
+                    b if b.is_ascii_alphanumeric() => char::from(b).to_string(),
+                    b => format!("={b:02X}"),
+                })
+                .collect(),
+        };
+        format!("=?{charset}?{encoding}?{encoded}?=")
+    }
+}
+
+/// Split text at a separator, except where it is quoted or inside angle brackets.
+fn split_outside_quotes(text: &str, separator: char) -> Vec<&str> {
+    let (mut pieces, mut start) = (Vec::new(), 0);
+    let (mut quoted, mut bracketed, mut escaped) = (false, false, false);
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '<' if !quoted => bracketed = true,
+            '>' if !quoted => bracketed = false,
+            c if c == separator && !quoted && !bracketed => {
+                pieces.push(&text[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    pieces.push(&text[start..]);
+    pieces
+}
+
+/// Text without its parenthesized comments, as in "alice@example.com (Alice)".
+fn strip_comments(text: &str) -> String {
+    let mut depth = 0usize;
+    text.chars()
+        .filter(|&c| {
+            match c {
+                '(' => depth += 1,
+                ')' if depth > 0 => {
+                    depth -= 1;
+                    return false;
+                }
+                _ => {}
+            }
+            depth == 0
+        })
+        .collect()
+}
+
+/// An underscore, percent-encoded as in RFC 2231 values.
+const PERCENT_UNDERSCORE: &str = "%5F";
+
+/// Replace the percent-encoded bytes of an RFC 2231 value with underscores, encoded the same
+/// way.
+fn replace_percent_escapes(value: &str) -> String {
+    let mut replaced = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('%') {
+        replaced.push_str(&rest[..start]);
+        let escape = &rest[start..];
+        match escape
+            .get(1..3)
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+        {
+            Some(_) => {
+                replaced.push_str(PERCENT_UNDERSCORE);
+                rest = &escape[3..];
+            }
+            None => {
+                replaced.push('%');
+                rest = &escape[1..];
+            }
+        }
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+/// A line of lorem ipsum as long as the given one, keeping its quote markers and line ending.
+fn lorem(line: &[u8]) -> Vec<u8> {
+    let (line, crlf) = match line.strip_suffix(b"\r") {
+        Some(line) => (line, true),
+        None => (line, false),
+    };
+    let quoted = line
+        .iter()
+        .position(|&b| b != b'>' && b != b' ')
+        .unwrap_or(line.len());
+    let mut replaced = line[..quoted].to_vec();
+    replaced.extend(lorem_text(line.len() - quoted));
+    if crlf {
+        replaced.push(b'\r');
+    }
+    replaced
+}
+
+/// Lorem ipsum of exactly `length` bytes, not starting or ending with a space.
+fn lorem_text(length: usize) -> Vec<u8> {
+    let mut text: Vec<u8> = LOREM.bytes().cycle().take(length).collect();
+    if let Some(last) = text.last_mut()
+        && *last == b' '
+    {
+        *last = b'a';
+    }
+    text
+}
+
+/// A quoted-printable line with its text replaced with lorem ipsum of the same length, its
+/// escapes with escaped spaces, and its soft line break kept.
+fn lorem_quoted_printable(line: &[u8]) -> Vec<u8> {
+    let (line, crlf) = match line.strip_suffix(b"\r") {
+        Some(line) => (line, true),
+        None => (line, false),
+    };
+    let (line, soft_break) = match line.strip_suffix(b"=") {
+        Some(line) => (line, true),
+        None => (line, false),
+    };
+    let mut replaced = Vec::with_capacity(line.len() + 2);
+    let mut text = 0;
+    let mut index = 0;
+    while index < line.len() {
+        let escape = line[index] == b'='
+            && line
+                .get(index + 1..index + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit));
+        if escape {
+            replaced.extend(lorem_text(text));
+            text = 0;
+            replaced.extend_from_slice(b"=20");
+            index += 3;
+        } else {
+            text += 1;
+            index += 1;
+        }
+    }
+    replaced.extend(lorem_text(text));
+    if soft_break {
+        replaced.push(b'=');
+    }
+    if crlf {
+        replaced.push(b'\r');
+    }
+    replaced
+}
+
+/// Base64 lines with their content replaced with lorem ipsum of the same length, keeping its
+/// line breaks, and encoded again in lines as long as the first one. Content that cannot be
+/// decoded is replaced line by line.
+fn lorem_base64(lines: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let encoded: Vec<&[u8]> = lines
+        .iter()
+        .map(|line| line.trim_ascii())
+        .take_while(|line| !line.is_empty())
+        .collect();
+    let Some(decoded) = decode_base64(encoded.iter().copied().flatten()) else {
+        return lines.iter().map(|line| lorem(line)).collect();
+    };
+    let mut replaced = Vec::with_capacity(decoded.len());
+    for line in decoded.split_inclusive(|&b| b == b'\n') {
+        let ending = match line {
+            line if line.ends_with(b"\r\n") => 2,
+            line if line.ends_with(b"\n") => 1,
+            _ => 0,
+        };
+        replaced.extend(lorem_text(line.len() - ending));
+        replaced.extend_from_slice(&line[line.len() - ending..]);
+    }
+    let crlf = lines.first().is_some_and(|line| line.ends_with(b"\r"));
+    // Lines of base64 hold whole groups of four characters.
+    let width = encoded.first().map_or(76, |line| line.len()).max(4) / 4 * 4;
+    let mut anonymized: Vec<Vec<u8>> = encode_base64(&replaced)
+        .into_bytes()
+        .chunks(width)
+        .map(|chunk| {
+            let mut line = chunk.to_vec();
+            if crlf {
+                line.push(b'\r');
+            }
+            line
+        })
+        .collect();
+    anonymized.extend(lines[encoded.len()..].iter().map(|line| lorem(line)));
+    anonymized
+}
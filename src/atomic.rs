@@ -0,0 +1,69 @@
+use crate::paths;
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// A file that is written under a temporary name next to its destination and only moved
+/// into place once [`AtomicFile::commit`] is called. Dropping it without committing removes
+/// the temporary file so that no partial output is ever left under the final name.
+pub struct AtomicFile {
+    file: File,
+    temp_path: PathBuf,
+    final_path: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    pub fn create(final_path: &Path) -> io::Result<Self> {
+        // Deeply nested output would otherwise be limited to MAX_PATH on Windows.
+        let final_path = paths::extended_length(final_path).into_owned();
+        let temp_path = Self::temp_path_for(&final_path);
+        Ok(Self {
+            file: File::create(&temp_path)?,
+            temp_path,
+            final_path,
+            committed: false,
+        })
+    }
+
+    /// The path that output is written to until the file is committed.
+    pub fn temp_path_for(final_path: &Path) -> PathBuf {
+        let mut name = OsString::from(final_path.as_os_str());
+        name.push(".mailfmt-tmp");
+        PathBuf::from(name)
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Move the temporary file over the final path. The two are in the same directory, so
+    /// this is a rename within one filesystem and never leaves a partial file behind.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.flush()?;
+        fs::rename(&self.temp_path, &self.final_path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
@@ -0,0 +1,576 @@
+//! Listing and extraction of the attachments in the messages of an mbox.
+
+use crate::{
+    ConversionSummary, Event, EventHandler, MailfmtError, MessageError, ProgressTotal,
+    atomic::AtomicFile,
+    counting::CountingReader,
+    error::{IoContext, Result},
+    gzip,
+    headers::decode_encoded_words,
+    mbox::{MboxParser, check_mbox_input, check_output_directory, create_output_directory},
+    mime::{Entity, for_each_part, structure_problem},
+    paths::GLOB_OPTIONS,
+};
+use glob::Pattern;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tracing::info_span;
+
+/// Options for [`list_attachments`]. Create them with [`AttachmentsOptions::new`] and adjust
+/// the fields as needed.
+pub struct AttachmentsOptions<'a> {
+    pub input_file: PathBuf,
+    /// Leave out attachments smaller than this many bytes once decoded.
+    pub min_size: u64,
+    /// Read the input file even if it does not look like an mbox file.
+    pub force: bool,
+    /// Set to stop after the current message. The list then covers the messages read so far.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called with progress and unreadable messages as the mbox is read.
+    pub on_event: Option<EventHandler<'a>>,
+}
+
+impl AttachmentsOptions<'_> {
+    pub fn new(input_file: impl Into<PathBuf>) -> Self {
+        Self {
+            input_file: input_file.into(),
+            min_size: 0,
+            force: false,
+            cancel: None,
+            on_event: None,
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// The attachments [`list_attachments`] found in an mbox.
+#[derive(Debug, Default)]
+pub struct AttachmentList {
+    /// The attachments in message order, and the messages whose structure could not be parsed.
+    pub entries: Vec<AttachmentEntry>,
+    /// How many messages were read.
+    pub messages: usize,
+    /// The messages that could not be read.
+    pub errors: Vec<MessageError>,
+    /// Whether listing was cancelled before reaching the end of the mbox.
+    pub interrupted: bool,
+    pub elapsed: Duration,
+}
+
+/// An attachment, or a message whose attachments could not be found.
+#[derive(Debug)]
+pub struct AttachmentEntry {
+    /// The index of the message in the mbox.
+    pub message: usize,
+    /// The Date header of the message, as it was written.
+    pub date: Option<String>,
+    /// The From header of the message, decoded.
+    pub sender: Option<String>,
+    /// The attachment, or why the MIME structure of the message could not be parsed.
+    pub attachment: std::result::Result<Attachment, String>,
+}
+
+/// A part of a message that is a file rather than message text: an attachment, a part with a
+/// file name, or a part that is neither text nor multipart.
+#[derive(Debug)]
+pub struct Attachment {
+    pub filename: Option<String>,
+    /// The lowercased media type, such as "application/pdf".
+    pub content_type: String,
+    /// The size in bytes once decoded, or `None` if its transfer encoding could not be decoded.
+    pub size: Option<u64>,
+}
+
+/// List the attachments of every message in an mbox file, reading it once from start to end.
+/// The parts of a message are found by following its multipart structure, including nested
+/// multipart parts.
+///
+/// ```
+/// use mailfmt::{AttachmentsOptions, list_attachments};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-attachments-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// fs::write(
+///     dir.join("in.mbox"),
+///     "From a Mon Jan 01 00:00:00 2024\n\
+///      From: =?utf-8?q?Ren=C3=A9?= <r@example.com>\n\
+///      Content-Type: multipart/mixed; boundary=outer\n\
+///      \n\
+///      --outer\n\
+///      Content-Type: multipart/related; boundary=inner\n\
+///      \n\
+///      --inner\n\
+///      \n\
+///      Hello\n\
+///      --inner\n\
+///      Content-Type: image/png\n\
+///      Content-Disposition: inline; filename=logo.png\n\
+///      \n\
+///      --inner--\n\
+///      --outer\n\
+///      Content-Type: application/pdf\n\
+///      Content-Disposition: attachment; filename*=utf-8''men%C3%BA.pdf\n\
+///      Content-Transfer-Encoding: base64\n\
+///      \n\
+///      JVBERi0=\n\
+///      --outer--\n\
+///      \n\
+///      From b Mon Jan 01 00:00:00 2024\n\
+///      Content-Type: multipart/mixed\n\
+///      \n\
+///      No boundary\n",
+/// )?;
+///
+/// let list = list_attachments(&AttachmentsOptions::new(dir.join("in.mbox")))?;
+/// assert_eq!(list.messages, 2);
+/// let names: Vec<_> = list.entries[..2]
+///     .iter()
+///     .map(|entry| entry.attachment.as_ref().unwrap().filename.as_deref())
+///     .collect();
+/// assert_eq!(names, [Some("logo.png"), Some("menú.pdf")]);
+/// assert_eq!(list.entries[1].sender.as_deref(), Some("René <r@example.com>"));
+/// assert_eq!(list.entries[1].attachment.as_ref().unwrap().size, Some(5));
+/// assert_eq!((list.entries[2].message, list.entries[2].attachment.is_err()), (1, true));
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn list_attachments(options: &AttachmentsOptions<'_>) -> Result<AttachmentList> {
+    let _span = info_span!("list_attachments", input = %options.input_file.display()).entered();
+    let start = Instant::now();
+    let input_file = options.input_file.as_path();
+    check_mbox_input(input_file, options.force, false)?;
+
+    let input_size =
+        gzip::content_size(input_file).io_context("read metadata of mbox file", input_file)?;
+    let reader =
+        CountingReader::new(gzip::open(input_file, 0).io_context("open mbox file", input_file)?);
+    let bytes_read = reader.counter();
+    options.emit(Event::Started {
+        total: match input_size {
+            0 => ProgressTotal::Unknown,
+            size => ProgressTotal::Bytes(size),
+        },
+        position: 0,
+    })?;
+
+    let mut list = AttachmentList::default();
+    for message in MboxParser::from_reader(BufReader::new(reader)) {
+        if options.is_cancelled() {
+            list.interrupted = true;
+            break;
+        }
+        let index = list.messages + list.errors.len();
+        match message {
+            Ok(message) => {
+                list.messages += 1;
+                let entry = |attachment| AttachmentEntry {
+                    message: index,
+                    date: message.header("date").map(str::to_string),
+                    sender: message.header("from").map(decode_encoded_words),
+                    attachment,
+                };
+                match structure_problem(&message.lines) {
+                    Some(problem) => list.entries.push(entry(Err(problem))),
+                    None => for_each_part(&message.lines, &mut |part| {
+                        if let Some(attachment) = attachment(part)
+                            && attachment.size.is_none_or(|size| size >= options.min_size)
+                        {
+                            list.entries.push(entry(Ok(attachment)));
+                        }
+                    }),
+                }
+            }
+            Err(error) => {
+                let item = format!("reading email {index}");
+                options.emit(Event::Failed {
+                    item: item.clone(),
+                    error: &error,
+                })?;
+                list.errors.push(MessageError { item, error });
+            }
+        }
+        options.emit(Event::Progress {
+            position: bytes_read.load(Ordering::Relaxed),
+            processed: list.messages + list.errors.len(),
+        })?;
+    }
+
+    list.elapsed = start.elapsed();
+    Ok(list)
+}
+
+/// Describe a part if it is a file rather than message text.
+fn attachment(part: &Entity<'_>) -> Option<Attachment> {
+    if !part.is_file() {
+        return None;
+    }
+    Some(Attachment {
+        filename: part.filename(),
+        content_type: part.content_type.media_type.to_ascii_lowercase(),
+        size: part.decode_body().map(|content| content.len() as u64),
+    })
+}
+
+/// Options for [`extract_attachments`]. Create them with [`ExtractAttachmentsOptions::new`] and
+/// adjust the fields as needed.
+pub struct ExtractAttachmentsOptions<'a> {
+    pub input_file: PathBuf,
+    pub output_directory: PathBuf,
+    /// Replace existing files in the output directory if they overlap. Files that do not
+    /// overlap are never deleted.
+    pub overwrite: bool,
+    /// Only extract attachments whose media type matches one of these patterns, such as
+    /// `application/pdf` or `image/*`. Every attachment matches if there are none.
+    pub types: Vec<Pattern>,
+    /// Only extract attachments whose file name matches one of these patterns, such as
+    /// `*.docx`. Attachments without a file name never match. Every attachment matches if
+    /// there are none.
+    pub names: Vec<Pattern>,
+    /// Read the input file even if it does not look like an mbox file.
+    pub force: bool,
+    /// Set to stop after the current message. Attachments already written are kept.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called with progress and per-attachment results as the extraction goes on.
+    pub on_event: Option<EventHandler<'a>>,
+}
+
+impl ExtractAttachmentsOptions<'_> {
+    pub fn new(input_file: impl Into<PathBuf>, output_directory: impl Into<PathBuf>) -> Self {
+        Self {
+            input_file: input_file.into(),
+            output_directory: output_directory.into(),
+            overwrite: false,
+            types: Vec::new(),
+            names: Vec::new(),
+            force: false,
+            cancel: None,
+            on_event: None,
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
+
+    /// Report an attachment or message that could not be extracted, returning it for the
+    /// summary.
+    fn failed(&self, item: String, error: MailfmtError) -> Result<MessageError> {
+        self.emit(Event::Failed {
+            item: item.clone(),
+            error: &error,
+        })?;
+        Ok(MessageError { item, error })
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    /// Whether an attachment passes the type and name filters.
+    fn selects(&self, content_type: &str, filename: Option<&str>) -> bool {
+        let matches = |pattern: &Pattern, text| pattern.matches_with(text, GLOB_OPTIONS);
+        (self.types.is_empty() || self.types.iter().any(|glob| matches(glob, content_type)))
+            && (self.names.is_empty()
+                || filename.is_some_and(|name| self.names.iter().any(|glob| matches(glob, name))))
+    }
+}
+
+/// The name of the manifest [`extract_attachments`] writes to the output directory.
+pub const MANIFEST_FILE_NAME: &str = "manifest.jsonl";
+
+/// Decode every attachment in an mbox file, as listed by [`list_attachments`], and write it
+/// to its own file in the output directory.
+///
+/// Files are named after the index of their message and the attachment's file name, such as
+/// `0007_report.pdf`, made safe for the filesystem. Attachments without a file name are
+/// named after their media type, such as `0007_attachment.png`, and a number is added before
+/// the extension when a name is already taken, such as `0007_report-2.pdf`.
+///
+/// A manifest named [`MANIFEST_FILE_NAME`] is written alongside, holding a JSON object on
+/// its own line for each file written with its `file` name, the `message` index and `offset`
+/// of its message in the mbox, the message's `message_id`, `date`, `sender` and `subject`,
+/// and the attachment's original `filename`, `content_type`, `size` and `sha256`.
+///
+/// [`ConversionSummary::converted`] counts the files written, and
+/// [`ConversionSummary::skipped`] the attachments left out by the filters. Attachments whose
+/// transfer encoding cannot be decoded, and messages whose MIME structure cannot be parsed,
+/// are reported with [`Event::Warning`] and not extracted.
+///
+/// ```
+/// use mailfmt::{ExtractAttachmentsOptions, Pattern, extract_attachments};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-extract-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// fs::write(
+///     dir.join("in.mbox"),
+///     "From a Mon Jan 01 00:00:00 2024\n\
+///      Content-Type: multipart/mixed; boundary=b\n\
+///      \n\
+///      --b\n\
+///      \n\
+///      Hello\n\
+///      --b\n\
+///      Content-Type: application/pdf; name=\"=?utf-8?q?r=C3=A9sum=C3=A9.pdf?=\"\n\
+///      Content-Transfer-Encoding: base64\n\
+///      \n\
+///      JVBERi0=\n\
+///      --b\n\
+///      Content-Type: application/pdf\n\
+///      Content-Disposition: attachment; filename*=utf-8''r%C3%A9sum%C3%A9.pdf\n\
+///      \n\
+///      %PDF-\n\
+///      --b\n\
+///      Content-Type: image/png\n\
+///      \n\
+///      png\n\
+///      --b--\n",
+/// )?;
+///
+/// let mut options = ExtractAttachmentsOptions::new(dir.join("in.mbox"), dir.join("out"));
+/// options.types = vec![Pattern::new("application/pdf")?];
+/// let summary = extract_attachments(&options)?;
+/// assert_eq!((summary.converted, summary.skipped), (2, 1));
+/// assert_eq!(fs::read(dir.join("out/0000_résumé.pdf"))?, b"%PDF-");
+/// assert_eq!(fs::read(dir.join("out/0000_résumé-2.pdf"))?, b"%PDF-");
+/// let manifest = fs::read_to_string(dir.join("out/manifest.jsonl"))?;
+/// assert!(manifest.starts_with(r#"{"file":"0000_résumé.pdf","message":0,"offset":0,"#));
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn extract_attachments(options: &ExtractAttachmentsOptions<'_>) -> Result<ConversionSummary> {
+    let _span = info_span!("extract_attachments", input = %options.input_file.display()).entered();
+    let start = Instant::now();
+    let (input_file, output_dir) = (
+        options.input_file.as_path(),
+        options.output_directory.as_path(),
+    );
+    check_mbox_input(input_file, options.force, false)?;
+    check_output_directory(output_dir)?;
+    if output_dir.exists() && !options.overwrite {
+        return Err(MailfmtError::OutputDirectoryExists {
+            path: output_dir.to_path_buf(),
+        });
+    }
+
+    let input_size =
+        gzip::content_size(input_file).io_context("read metadata of mbox file", input_file)?;
+    let reader =
+        CountingReader::new(gzip::open(input_file, 0).io_context("open mbox file", input_file)?);
+    let bytes_read = reader.counter();
+    create_output_directory(output_dir, "create output directory")?;
+    let manifest_path = output_dir.join(MANIFEST_FILE_NAME);
+    let mut manifest = BufWriter::new(
+        AtomicFile::create(&manifest_path).io_context("create manifest", &manifest_path)?,
+    );
+    options.emit(Event::Started {
+        total: match input_size {
+            0 => ProgressTotal::Unknown,
+            size => ProgressTotal::Bytes(size),
+        },
+        position: 0,
+    })?;
+
+    let mut summary = ConversionSummary::default();
+    let mut used_names = HashSet::new();
+    for (index, message) in MboxParser::from_reader(BufReader::new(reader)).enumerate() {
+        if options.is_cancelled() {
+            summary.interrupted = true;
+            break;
+        }
+        match message {
+            Ok(message) => {
+                let mut parts = Vec::new();
+                match structure_problem(&message.lines) {
+                    Some(problem) => options.emit(Event::Warning(format!(
+                        "The MIME structure of email {index} cannot be parsed ({problem}), so its attachments are not extracted"
+                    )))?,
+                    None => for_each_part(&message.lines, &mut |part| {
+                        if part.is_file() {
+                            parts.push(ExtractedPart {
+                                filename: part.filename(),
+                                content_type: part.content_type.media_type.to_ascii_lowercase(),
+                                content: part.decode_body().ok_or_else(|| part.transfer_encoding()),
+                            });
+                        }
+                    }),
+                }
+                let header = |name| message.header(name).map(decode_encoded_words);
+                for part in parts {
+                    if !options.selects(&part.content_type, part.filename.as_deref()) {
+                        summary.skipped += 1;
+                        continue;
+                    }
+                    let content = match part.content {
+                        Ok(content) => content,
+                        Err(encoding) => {
+                            options.emit(Event::Warning(format!(
+                                "Attachment {} of email {index} is not valid {encoding} and is not extracted",
+                                part.filename.as_deref().unwrap_or(&part.content_type)
+                            )))?;
+                            continue;
+                        }
+                    };
+                    let name = unique_file_name(
+                        &mut used_names,
+                        index,
+                        part.filename.as_deref(),
+                        &part.content_type,
+                    );
+                    let path = output_dir.join(&name);
+                    match save_attachment(&path, &content) {
+                        Ok(()) => {
+                            summary.converted += 1;
+                            summary.bytes_written += content.len() as u64;
+                            let entry = json!({
+                                "file": name,
+                                "message": index,
+                                "offset": message.offset,
+                                "message_id": header("message-id"),
+                                "date": header("date"),
+                                "sender": header("from"),
+                                "subject": header("subject"),
+                                "filename": part.filename,
+                                "content_type": part.content_type,
+                                "size": content.len(),
+                                "sha256": format!("{:x}", Sha256::digest(&content)),
+                            });
+                            writeln!(manifest, "{entry}")
+                                .io_context("write manifest", &manifest_path)?;
+                            options.emit(Event::Written(&path))?;
+                        }
+                        Err(error) => {
+                            summary.errors.push(options.failed(
+                                format!("saving attachment {name} of email {index}"),
+                                error,
+                            )?)
+                        }
+                    }
+                }
+            }
+            Err(error) => {
+                let item = format!("reading email {index}");
+                summary.errors.push(options.failed(item, error)?);
+            }
+        }
+        options.emit(Event::Progress {
+            position: bytes_read.load(Ordering::Relaxed),
+            processed: index + 1,
+        })?;
+    }
+
+    // The manifest is kept when interrupted, as it describes the files that were written.
+    manifest
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .io_context("write manifest", &manifest_path)?
+        .commit()
+        .io_context("move manifest into place", &manifest_path)?;
+    summary.elapsed = start.elapsed();
+    Ok(summary)
+}
+
+/// An attachment found in a message, before it is written.
+struct ExtractedPart {
+    filename: Option<String>,
+    content_type: String,
+    /// The decoded content, or the transfer encoding it could not be decoded from.
+    content: std::result::Result<Vec<u8>, String>,
+}
+
+/// The longest file name written, in bytes, which most filesystems allow.
+const MAX_FILE_NAME_LEN: usize = 255;
+
+/// Choose a file name for an attachment that no earlier attachment was given. Names are
+/// compared case-insensitively, as they would be on case-insensitive filesystems.
+fn unique_file_name(
+    used: &mut HashSet<String>,
+    index: usize,
+    filename: Option<&str>,
+    content_type: &str,
+) -> String {
+    let name = filename
+        .map(sanitize_filename::sanitize)
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| format!("attachment.{}", extension_for(content_type)));
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name.as_str(), String::new()),
+    };
+    for counter in 1.. {
+        let suffix = match counter {
+            1 => extension.clone(),
+            n => format!("-{n}{extension}"),
+        };
+        let prefix = format!("{index:04}_");
+        let mut stem_len =
+            (MAX_FILE_NAME_LEN.saturating_sub(prefix.len() + suffix.len())).min(stem.len());
+        while !stem.is_char_boundary(stem_len) {
+            stem_len -= 1;
+        }
+        let candidate = format!("{prefix}{}{suffix}", &stem[..stem_len]);
+        if used.insert(candidate.to_lowercase()) {
+            return candidate;
+        }
+    }
+    unreachable!("a free file name is always found")
+}
+
+/// A file extension for an attachment of the given media type without a file name.
+fn extension_for(content_type: &str) -> &str {
+    match content_type
+        .split_once('/')
+        .map_or("", |(_, subtype)| subtype)
+    {
+        "plain" => "txt",
+        "jpeg" => "jpg",
+        "svg+xml" => "svg",
+        subtype
+            if !subtype.is_empty()
+                && subtype.len() <= 5
+                && subtype.bytes().all(|b| b.is_ascii_alphanumeric()) =>
+        {
+            subtype
+        }
+        _ => "bin",
+    }
+}
+
+/// Write an extracted attachment.
+fn save_attachment(path: &Path, content: &[u8]) -> Result<()> {
+    let mut file = AtomicFile::create(path).io_context("create attachment file", path)?;
+    file.write_all(content)
+        .io_context("write attachment file", path)?;
+    file.commit()
+        .io_context("move attachment file into place", path)
+}
@@ -0,0 +1,299 @@
+//! Reading of Babyl files, the format Emacs Rmail kept mail in before it moved to mbox. Their
+//! messages are turned into the lines of an mbox, so that they are extracted like any other.
+//!
+//! A Babyl file starts with a `BABYL OPTIONS:` section ended by a `^_` line. Every message
+//! then starts with a `^L` line and a line of labels, such as `1, answered,, work,`, and ends
+//! with `^_`. The `*** EOOH ***` line separates the original header of a message from the one
+//! Rmail reformatted for display, which follows it. Messages that were never reformatted have
+//! nothing before the line, and their original header after it.
+
+use crate::{
+    MailfmtError,
+    encoding::strip_bom,
+    error::{IoContext, Result},
+    mbox::{OffsetLine, OffsetLines, is_separator},
+    status,
+    writer::{PLACEHOLDER_DATE, PLACEHOLDER_SENDER, envelope_parts},
+};
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    io::{self, BufRead, Read},
+    iter::Peekable,
+    path::Path,
+    str::FromStr,
+};
+
+/// The format of the file that messages are extracted from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// An mbox file, with messages starting at "From " separator lines.
+    #[default]
+    Mbox,
+    /// A Babyl file written by Emacs Rmail. The original header of every message is kept
+    /// rather than the one reformatted for display, and its labels are written as Status,
+    /// X-Status and X-Keywords headers.
+    Babyl,
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Mbox => "mbox",
+            Self::Babyl => "babyl",
+        })
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mbox" => Ok(Self::Mbox),
+            "babyl" | "rmail" => Ok(Self::Babyl),
+            _ => Err(format!(
+                "unknown input format '{s}', expected mbox or babyl"
+            )),
+        }
+    }
+}
+
+/// What a Babyl file starts with.
+const OPTIONS_START: &[u8] = b"BABYL OPTIONS:";
+
+/// The line separating the original header of a message from the reformatted one.
+const EOOH: &[u8] = b"*** EOOH ***";
+
+/// The line Rmail adds to the original header of a message to show in its summary.
+const SUMMARY_LINE: &[u8] = b"Summary-line:";
+
+/// Check that an input file exists and, unless `force` is set, that it looks like a Babyl file.
+pub(crate) fn check_input(input_file: &Path, force: bool, keep_bom: bool) -> Result<()> {
+    if !input_file.exists() {
+        return Err(MailfmtError::InputNotFound {
+            path: input_file.to_path_buf(),
+        });
+    }
+    if force {
+        return Ok(());
+    }
+    let mut start = Vec::new();
+    File::open(input_file)
+        .and_then(|file| file.take(64).read_to_end(&mut start))
+        .io_context("read Babyl file", input_file)?;
+    let start = if keep_bom { &start } else { strip_bom(&start) };
+    match start.starts_with(OPTIONS_START) {
+        true => Ok(()),
+        false => Err(MailfmtError::NotBabyl {
+            path: input_file.to_path_buf(),
+        }),
+    }
+}
+
+/// Whether data read from where a message was recorded to start begins with one, for checking
+/// that resuming starts at one.
+pub(crate) fn starts_message(data: &[u8]) -> bool {
+    data.strip_prefix(b"\x1f")
+        .unwrap_or(data)
+        .starts_with(b"\x0c")
+}
+
+/// The lines of an input file in `format` as [`MboxParser`] reads them, from a reader that is
+/// positioned at `offset` in the file.
+///
+/// [`MboxParser`]: crate::MboxParser
+pub(crate) fn input_lines<'r, R: BufRead + 'r>(
+    reader: R,
+    offset: u64,
+    keep_bom: bool,
+    format: InputFormat,
+) -> Box<dyn Iterator<Item = io::Result<OffsetLine>> + 'r> {
+    let lines = OffsetLines::new(reader, offset).keep_bom(keep_bom);
+    match format {
+        InputFormat::Mbox => Box::new(lines),
+        InputFormat::Babyl => Box::new(BabylLines::new(lines, offset)),
+    }
+}
+
+/// Count the messages of a Babyl file.
+pub(crate) fn count_messages<R: BufRead>(reader: R, keep_bom: bool) -> io::Result<u64> {
+    let mut count = 0;
+    for line in input_lines(reader, 0, keep_bom, InputFormat::Babyl) {
+        count += u64::from(is_separator(&line?.1));
+    }
+    Ok(count)
+}
+
+/// The lines of a Babyl file turned into those of an mbox. Every message starts with a
+/// separator line made from its From and Date headers, at the offset of its `^L` line, and is
+/// followed by its original header with its labels as headers, and its body. Body lines that
+/// would be taken for separators are quoted with '>', as in an mboxrd.
+struct BabylLines<I: Iterator<Item = io::Result<OffsetLine>>> {
+    lines: Peekable<I>,
+    /// Lines of the message being read, returned before any more are read.
+    pending: VecDeque<io::Result<OffsetLine>>,
+    /// Whether the options section has been read past.
+    started: bool,
+    /// Whether the lines read are in the body of a message.
+    in_body: bool,
+}
+
+impl<I: Iterator<Item = io::Result<OffsetLine>>> BabylLines<I> {
+    /// Read the lines of a Babyl file, starting at its beginning or at a message.
+    fn new(lines: I, offset: u64) -> Self {
+        Self {
+            lines: lines.peekable(),
+            pending: VecDeque::new(),
+            started: offset != 0,
+            in_body: false,
+        }
+    }
+
+    /// The next line of the current message, or `None` at its end.
+    fn next_in_message(&mut self) -> Option<io::Result<OffsetLine>> {
+        self.lines
+            .next_if(|line| !matches!(line, Ok((_, line, _)) if line.starts_with(b"\x1f")))
+    }
+
+    /// Read the labels and header of the message starting at `offset`, queueing its separator
+    /// and header lines.
+    fn start_message(&mut self, offset: u64) {
+        if let Err(error) = self.read_header(offset) {
+            self.pending.push_back(Err(error));
+        }
+        self.in_body = true;
+    }
+
+    fn read_header(&mut self, offset: u64) -> io::Result<()> {
+        let labels = match self.next_in_message().transpose()? {
+            Some((_, labels, _)) => labels,
+            None => Vec::new(),
+        };
+        // The original header of a reformatted message, or the header of one without EOOH.
+        let mut before = Vec::new();
+        let mut eooh = false;
+        while let Some((_, line, _)) = self.next_in_message().transpose()? {
+            if line == EOOH {
+                eooh = true;
+                break;
+            }
+            let blank = line.is_empty();
+            before.push(line);
+            if blank && !matches!(self.lines.peek(), Some(Ok((_, next, _))) if next == EOOH) {
+                break;
+            }
+        }
+        let mut blank = before.last().is_some_and(Vec::is_empty);
+        while before.last().is_some_and(Vec::is_empty) {
+            before.pop();
+        }
+        before.retain(|line| !line.starts_with(SUMMARY_LINE));
+
+        let mut header = before;
+        if eooh {
+            let mut after = Vec::new();
+            blank = false;
+            while let Some((_, line, _)) = self.next_in_message().transpose()? {
+                if line.is_empty() {
+                    blank = true;
+                    break;
+                }
+                after.push(line);
+            }
+            if header.is_empty() {
+                header = after;
+            }
+        }
+
+        let header = status::with_status_headers(header, &label_headers(&labels));
+        let text = String::from_utf8_lossy(&header.join(&b'\n')).into_owned();
+        let (sender, date) = envelope_parts(&text);
+        let separator = format!(
+            "From {} {}",
+            sender.unwrap_or(PLACEHOLDER_SENDER),
+            date.as_deref().unwrap_or(PLACEHOLDER_DATE)
+        );
+        self.pending
+            .push_back(Ok((offset, separator.into_bytes(), true)));
+        // The header lines are given the offset of the message, as they were moved around.
+        for line in header {
+            self.pending.push_back(Ok((offset, quote(line), true)));
+        }
+        if blank {
+            self.pending.push_back(Ok((offset, Vec::new(), true)));
+        }
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = io::Result<OffsetLine>>> Iterator for BabylLines<I> {
+    type Item = io::Result<OffsetLine>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.pending.pop_front() {
+                return Some(line);
+            }
+            let (offset, line, newline) = match self.lines.next()? {
+                Ok(line) => line,
+                Err(error) => return Some(Err(error)),
+            };
+            if self.in_body && !line.starts_with(b"\x1f") {
+                return Some(Ok((offset, quote(line), newline)));
+            }
+            // Between messages, where a ^_ ends the options section or a message, and a ^L,
+            // on the same line or the next, starts the next message.
+            self.in_body = false;
+            let rest = match line.strip_prefix(b"\x1f") {
+                Some(rest) => {
+                    self.started = true;
+                    rest
+                }
+                None => &line[..],
+            };
+            if self.started && rest.starts_with(b"\x0c") {
+                self.start_message(offset);
+            }
+        }
+    }
+}
+
+/// Quote a line that would be taken for a separator.
+fn quote(mut line: Vec<u8>) -> Vec<u8> {
+    if is_separator(&line) {
+        line.insert(0, b'>');
+    }
+    line
+}
+
+/// The headers holding the labels of a message, from its labels line such as
+/// `1, answered, unseen,, work, family,`. Messages without the unseen label were read, the
+/// answered and deleted labels become X-Status flags, and the labels given by the user after
+/// the `,,` are listed by an X-Keywords header.
+fn label_headers(labels: &[u8]) -> Vec<(&'static str, String)> {
+    let labels = String::from_utf8_lossy(labels);
+    let (basic, user) = labels.split_once(",,").unwrap_or((&labels, ""));
+    // The first of the basic labels tells whether the message was reformatted.
+    let basic: Vec<&str> = basic.split(',').skip(1).map(str::trim).collect();
+    let mut flags = Vec::new();
+    if !basic.contains(&"unseen") {
+        flags.extend(["seen", "old"]);
+    }
+    flags.extend(
+        ["answered", "deleted"]
+            .into_iter()
+            .filter(|flag| basic.contains(flag)),
+    );
+    let mut headers = status::status_headers(&flags);
+    let user: Vec<&str> = user
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .collect();
+    if !user.is_empty() {
+        headers.push(("X-Keywords", user.join(", ")));
+    }
+    headers
+}
@@ -0,0 +1,68 @@
+//! Decoding of the charsets declared by messages, for transcoding them to UTF-8.
+
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+
+/// A charset that message text can be decoded from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Charset {
+    Ascii,
+    Utf8,
+    /// Any other charset known to the Encoding Standard, single-byte ones as well as the
+    /// multibyte ones of Chinese, Japanese and Korean text.
+    Other(&'static Encoding),
+}
+
+impl Charset {
+    /// Look up a charset by one of its names, matched case-insensitively. As in browsers,
+    /// ISO-8859-1 is decoded as its superset Windows-1252, since messages declaring it are
+    /// frequently written in the latter, and GB2312 as its superset GBK.
+    pub(crate) fn for_label(label: &str) -> Option<Self> {
+        let label = label.trim();
+        if ["us-ascii", "ascii", "ansi_x3.4-1968"]
+            .iter()
+            .any(|ascii| label.eq_ignore_ascii_case(ascii))
+        {
+            return Some(Self::Ascii);
+        }
+        // Labels such as ISO-2022-KR map to the replacement encoding, which decodes nothing.
+        match Encoding::for_label_no_replacement(label.as_bytes())? {
+            encoding if encoding == UTF_8 => Some(Self::Utf8),
+            encoding => Some(Self::Other(encoding)),
+        }
+    }
+
+    /// The charset assumed for text without a declared charset that is not valid UTF-8.
+    pub(crate) fn fallback() -> Self {
+        Self::Other(WINDOWS_1252)
+    }
+
+    /// Whether ASCII bytes stand for the same text in this charset as in ASCII.
+    pub(crate) fn is_ascii_compatible(self) -> bool {
+        match self {
+            Self::Ascii | Self::Utf8 => true,
+            Self::Other(encoding) => encoding.is_ascii_compatible(),
+        }
+    }
+
+    /// Whether text is valid in this charset, without keeping the decoded text.
+    pub(crate) fn is_valid(self, bytes: &[u8]) -> bool {
+        match self {
+            Self::Ascii => bytes.is_ascii(),
+            Self::Utf8 => std::str::from_utf8(bytes).is_ok(),
+            Self::Other(_) => self.decode(bytes).is_some(),
+        }
+    }
+
+    /// Decode text in this charset, or `None` if it is not valid in it.
+    pub(crate) fn decode(self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Self::Ascii => bytes
+                .is_ascii()
+                .then(|| String::from_utf8(bytes.to_vec()).expect("ASCII is valid UTF-8")),
+            Self::Utf8 => String::from_utf8(bytes.to_vec()).ok(),
+            Self::Other(encoding) => encoding
+                .decode_without_bom_handling_and_without_replacement(bytes)
+                .map(|text| text.into_owned()),
+        }
+    }
+}
@@ -0,0 +1,409 @@
+pub mod anonymize;
+pub mod attachments;
+pub mod completions;
+pub mod config;
+pub mod diff;
+pub mod eml_to_mbox;
+pub mod export_json;
+pub mod export_text;
+pub mod imap;
+pub mod import_json;
+pub mod info;
+pub mod interrupt;
+pub mod man;
+pub mod mbox_to_eml;
+pub mod output;
+pub mod paths;
+pub mod peek;
+pub mod porcelain;
+pub mod prompt;
+pub mod report;
+
+use anyhow::Context;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use mailfmt::{
+    ConversionSummary, Counters, InvalidUtf8, MailfmtError, MessageCount, MessageError,
+    MessageIdIndex, ProgressTotal, headers,
+};
+use serde_json::{Value, json};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+/// The exit code used when the input holds no messages, or none of them could be converted.
+pub const EMPTY_INPUT_EXIT_CODE: u8 = 3;
+
+/// A file to write to, which must not be a directory and has to go in an existing one. Paths
+/// are checked with '~' and environment variables expanded, as the shell would have done, but
+/// returned as they were given, as arguments are expanded later.
+pub fn validate_output_file(s: &str) -> Result<PathBuf, String> {
+    let path = checked_file_path(s)?;
+    match path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        Some(parent) if !parent.is_dir() => Err(format!(
+            "'{s}' cannot be written, as '{}' is not an existing directory",
+            parent.display()
+        )),
+        _ => Ok(PathBuf::from(s)),
+    }
+}
+
+/// A file to read or write, which must not be a directory. Whether it, or the directory it
+/// goes in, exists is left to the conversion, which reports it like any other error.
+pub fn validate_file_path(s: &str) -> Result<PathBuf, String> {
+    checked_file_path(s)?;
+    Ok(PathBuf::from(s))
+}
+
+/// A path given for a file, expanded, if it is not that of a directory.
+fn checked_file_path(s: &str) -> Result<PathBuf, String> {
+    // A trailing separator gives a directory away without looking at the filesystem.
+    if s.ends_with('/') || s.ends_with('\\') {
+        return Err(format!("'{}' appears to be a directory, not a file", s));
+    }
+    let path = paths::expand(Path::new(s)).unwrap_or_else(|_| PathBuf::from(s));
+    match path.is_dir() {
+        true => Err(format!("'{s}' is a directory, not a file")),
+        false => Ok(path),
+    }
+}
+
+/// Only UTF-8 is supported as a charset to transcode messages to.
+pub fn validate_transcode_charset(s: &str) -> Result<String, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "utf-8" | "utf8" => Ok("utf-8".to_string()),
+        _ => Err(format!(
+            "cannot transcode to '{s}', only 'utf-8' is supported"
+        )),
+    }
+}
+
+/// A header name, which is lowercased as headers are matched case-insensitively.
+pub fn validate_header_name(s: &str) -> Result<String, String> {
+    let name = s.trim();
+    match !name.is_empty() && headers::is_header_line(&format!("{name}:")) {
+        true => Ok(name.to_ascii_lowercase()),
+        false => Err(format!("'{s}' is not a valid header name")),
+    }
+}
+
+/// Write a path to stdout for consumption by other programs, terminated by a newline or a NUL character.
+pub fn print_path(path: &Path, null_terminated: bool) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut stdout = std::io::stdout().lock();
+    #[cfg(unix)]
+    stdout.write_all(std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()))?;
+    #[cfg(not(unix))]
+    stdout.write_all(path.to_string_lossy().as_bytes())?;
+    stdout.write_all(if null_terminated { b"\0" } else { b"\n" })?;
+    stdout.flush()
+}
+
+/// Describe how many of the converted items contained invalid UTF-8 and what was done about it.
+pub fn describe_invalid_utf8(count: usize, noun: &str, policy: InvalidUtf8) -> String {
+    format!(
+        "{count} {noun} contained bytes that are not valid UTF-8{}",
+        match policy {
+            InvalidUtf8::Preserve => ", which were kept as they are",
+            InvalidUtf8::Lossy => ", which were replaced with U+FFFD",
+            InvalidUtf8::Strict => " and were counted as errors",
+        }
+    )
+}
+
+/// Describe how many attachments were stripped and how much smaller that made the messages.
+pub fn describe_stripped(summary: &ConversionSummary) -> String {
+    format!(
+        "{} attachments were stripped, saving {}",
+        summary.stripped,
+        HumanBytes(summary.stripped_bytes)
+    )
+}
+
+/// What to pass to get past an error, as a sentence to print after it.
+fn hint(error: &MailfmtError) -> Option<&'static str> {
+    Some(match error {
+        MailfmtError::OutputExists { .. } => {
+            "Use the --overwrite flag to replace it or --append to add to it."
+        }
+        MailfmtError::OutputDirectoryExists { .. } => {
+            "Use the --overwrite flag to replace overlapping files inside of it."
+        }
+        MailfmtError::OutputParentMissing { .. } => "Use the --create-parents flag to create it.",
+        MailfmtError::NotAnMbox { .. } | MailfmtError::NotBabyl { .. } => {
+            "Use the --force flag to convert it anyway."
+        }
+        MailfmtError::EmptyInput { .. } => "Use the --allow-empty flag to accept empty input.",
+        MailfmtError::AllFailed { .. } => "Use the --allow-empty flag to write it anyway.",
+        MailfmtError::LeadingContent { .. } => "Convert it without the --strict flag to skip them.",
+        MailfmtError::ResumeMisaligned { .. } => "Start the extraction again without --resume.",
+        MailfmtError::ImapInsecure { .. } => {
+            "Use an imaps:// URL or the --starttls flag to encrypt the connection, or the --insecure flag to send it anyway."
+        }
+        MailfmtError::InsufficientSpace { .. } => {
+            "Use the --force flag to start anyway, or --no-space-check if the filesystem misreports its free space."
+        }
+        _ => return None,
+    })
+}
+
+/// An error as it is printed and logged: its message, those of the errors that caused it, and
+/// the flag that gets past it, if there is one.
+pub fn describe_error(error: &(dyn Error + 'static)) -> String {
+    let mut message = error.to_string();
+    let mut hint = error.downcast_ref().and_then(self::hint);
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message = format!("{message}: {cause}");
+        hint = hint.or_else(|| cause.downcast_ref().and_then(self::hint));
+        source = cause.source();
+    }
+    match hint {
+        Some(hint) => format!("{}. {hint}", message.trim_end_matches('.')),
+        None => message,
+    }
+}
+
+/// Print the error for an input with nothing to convert, and choose the exit code for it.
+pub fn report_empty_input(error: &MailfmtError) -> ExitCode {
+    if output::json_logs() {
+        tracing::error!(
+            event = "empty_input",
+            error_kind = error.code(),
+            error = describe_error(error)
+        );
+    } else {
+        eprintln!(
+            "{}",
+            output::error(format!("Error: {}", describe_error(error)))
+        );
+    }
+    ExitCode::from(EMPTY_INPUT_EXIT_CODE)
+}
+
+/// Print the count of a --count-only run to stdout on its own, for scripts to read, and choose
+/// the exit code for it.
+pub fn report_count(count: &MessageCount, noun: &str) -> ExitCode {
+    if output::json_logs() {
+        tracing::info!(
+            event = "count",
+            found = count.found,
+            matching = count.matching,
+            interrupted = count.interrupted,
+            duration_ms = output::milliseconds(count.elapsed)
+        );
+    } else if output::verbosity() > 0 {
+        eprintln!(
+            "Counted {} of {} {noun} in {:.1}s",
+            count.matching,
+            count.found,
+            count.elapsed.as_secs_f64()
+        );
+    }
+    println!("{}", count.matching);
+    if count.interrupted {
+        eprintln!("Counting interrupted, the count is incomplete");
+        return ExitCode::from(interrupt::INTERRUPTED_EXIT_CODE);
+    }
+    ExitCode::SUCCESS
+}
+
+/// Describe the Message-IDs found on more than one email, one per line with the emails.
+pub fn describe_duplicates(summary: &ConversionSummary) -> String {
+    if summary.duplicates.is_empty() {
+        return "No Message-ID was found on more than one email".to_string();
+    }
+    let mut description = format!(
+        "{} Message-IDs were found on more than one email:",
+        summary.duplicates.len()
+    );
+    for group in &summary.duplicates {
+        let messages: Vec<String> = group
+            .messages
+            .iter()
+            .map(|message| format!("#{} {:?}", message.index, message.file))
+            .collect();
+        description.push_str(&format!(
+            "\n  <{}> on {} emails: {}",
+            group.message_id,
+            group.messages.len(),
+            messages.join(", ")
+        ));
+    }
+    description
+}
+
+/// Describe the Message-ID index written with a conversion.
+pub fn describe_msgid_index(path: &Path, index: &MessageIdIndex) -> String {
+    format!(
+        "Wrote a Message-ID index of {} entries to {path:?}, {} of them synthetic",
+        index.entries, index.synthetic
+    )
+}
+
+/// How many errors are listed once a conversion is over. The rest are only counted.
+pub const LISTED_ERRORS: usize = 50;
+
+/// Print the errors of a conversion to stderr once it is over, so that they do not scroll
+/// away with the progress bar. Only the first [`LISTED_ERRORS`] are listed, followed by where
+/// to find the rest.
+pub fn print_errors(errors: &[MessageError], error_report: Option<&Path>) {
+    if errors.is_empty() || output::json_logs() {
+        return;
+    }
+    eprintln!("{}", output::error("Errors:"));
+    for error in errors.iter().take(LISTED_ERRORS) {
+        eprintln!("  {}: {}", error.item, describe_error(&error.error));
+    }
+    if errors.len() > LISTED_ERRORS {
+        let rest = errors.len() - LISTED_ERRORS;
+        match error_report {
+            Some(path) => eprintln!("  ...and {rest} more, all of them listed in {path:?}"),
+            None => eprintln!("  ...and {rest} more. Pass --error-report to list all of them"),
+        }
+    }
+}
+
+/// Write every error of a conversion to a file, one per line and with its category, 'failed'
+/// or 'invalid' for those left out as invalid: a JSON object with the category, item, kind of
+/// error and its message for a path ending in '.jsonl', and the category in brackets followed
+/// by the item and message otherwise.
+pub fn write_error_report<'a>(
+    path: &Path,
+    failed: impl IntoIterator<Item = &'a MessageError>,
+    invalid: &[MessageError],
+) -> anyhow::Result<()> {
+    let json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("jsonl"));
+    let categories = failed
+        .into_iter()
+        .map(|error| ("failed", error))
+        .chain(invalid.iter().map(|error| ("invalid", error)));
+    let mut report = String::new();
+    for (category, error) in categories {
+        match json {
+            true => {
+                let mut object = json!({ "category": category });
+                if let (Value::Object(fields), Value::Object(error)) =
+                    (&mut object, error_json(error))
+                {
+                    fields.extend(error);
+                }
+                report.push_str(&object.to_string());
+                report.push('\n');
+            }
+            false => report.push_str(&format!(
+                "[{category}] {}: {}\n",
+                error.item,
+                describe_error(&error.error)
+            )),
+        }
+    }
+    std::fs::write(path, report)
+        .with_context(|| format!("failed to write the error report to {path:?}"))
+}
+
+/// An error of a conversion as a JSON object.
+pub fn error_json(error: &MessageError) -> Value {
+    json!({
+        "item": error.item,
+        "error_kind": error.error.code(),
+        "error": describe_error(&error.error),
+    })
+}
+
+/// Describe how many messages or files ended up in each category other than converted, or
+/// nothing when all of them were converted.
+pub fn describe_counters(counters: &Counters, noun: &str) -> Option<String> {
+    let categories = [
+        (counters.filtered, "filtered out"),
+        (counters.existing, "skipped as already converted"),
+        (counters.invalid, "skipped as invalid"),
+        (counters.failed, "failed"),
+    ];
+    let described: Vec<String> = categories
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, category)| format!("{count} {category}"))
+        .collect();
+    if described.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "Of {} {noun}, {} converted, {}",
+        counters.total(),
+        counters.converted,
+        described.join(", ")
+    ))
+}
+
+/// Messages processed per second, counting those that failed.
+pub fn messages_per_second(summary: &ConversionSummary) -> f64 {
+    let processed = (summary.converted + summary.errors.len()) as f64;
+    match summary.elapsed.as_secs_f64() {
+        0.0 => 0.0,
+        seconds => processed / seconds,
+    }
+}
+
+/// The average size of a converted message once written.
+pub fn average_message_size(summary: &ConversionSummary) -> u64 {
+    summary
+        .bytes_written
+        .checked_div(summary.converted as u64)
+        .unwrap_or(0)
+}
+
+/// Describe how long the conversion took, how fast it went and how much it read and wrote.
+pub fn describe_throughput(summary: &ConversionSummary) -> String {
+    format!(
+        "Took {:.1}s at {:.1} emails per second. Read {} and wrote {}, {} per email on average",
+        summary.elapsed.as_secs_f64(),
+        messages_per_second(summary),
+        HumanBytes(summary.bytes_read),
+        HumanBytes(summary.bytes_written),
+        HumanBytes(average_message_size(summary))
+    )
+}
+
+/// Size and style the progress bar for the given total, using a spinner when it is unknown.
+pub fn style_progress_bar(pb: &ProgressBar, total: ProgressTotal) {
+    match total {
+        ProgressTotal::Bytes(file_size) => {
+            pb.set_length(file_size);
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template(
+                        &format!("[{{elapsed_precise}}] {} {{percent:>3}}% {{binary_bytes}}/{{binary_total_bytes}} (ETA {{eta}}) {{msg}}", output::progress_bar()),
+                    )
+                    .unwrap()
+                    .progress_chars("#>-"),
+            );
+        }
+        ProgressTotal::Messages(count) => {
+            pb.set_length(count);
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template(
+                        &format!("[{{elapsed_precise}}] {} {{human_pos:>7}}/{{human_len:7}} (ETA {{eta}}) {{msg}}", output::progress_bar()),
+                    )
+                    .unwrap()
+                    .progress_chars("#>-"),
+            );
+        }
+        ProgressTotal::Unknown => {
+            pb.unset_length();
+            pb.set_style(
+                ProgressStyle::default_spinner()
+                    .template("[{elapsed_precise}] {spinner} {msg}")
+                    .unwrap(),
+            );
+        }
+    }
+}
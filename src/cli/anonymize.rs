@@ -0,0 +1,131 @@
+use crate::cli::{
+    describe_error,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    output::{self, Stream},
+    paths, style_progress_bar, validate_header_name, validate_output_file,
+};
+use anyhow::Result;
+use clap::Parser;
+use indicatif::{HumanCount, ProgressBar, ProgressDrawTarget};
+use mailfmt::{AnonymizeOptions, Anonymized, Event, anonymize_mbox};
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+/// What is kept and replaced, and examples, shown in the long help and the man page.
+const DETAILS: &str = "\
+Addresses become pseudonyms under the reserved .example domain, the same address always
+becoming the same pseudonym, and display names become made-up names. The domains of
+Message-ID, In-Reply-To and References are replaced the same way, so threads survive.
+Content-Type, Content-Transfer-Encoding, Content-Disposition, MIME-Version and Date are kept,
+apart from file names. The words of every other header, such as Subject, are scrambled into
+letters of the same length, keeping encoded words and reply markers. Bodies become lorem ipsum
+of the same length line by line, keeping multipart boundaries and transfer encodings.
+
+Anyone who knows the seed can check whether a guessed address or word is in the original
+mbox, so only share it with those who need to produce the same output.
+
+Examples:
+  Anonymize an mbox to attach it to a bug report:
+    mailfmt anonymize archive.mbox -o anon.mbox
+
+  Produce the same output as someone else, keeping the headers of the mailer:
+    mailfmt anonymize archive.mbox -o anon.mbox --seed 42 --keep-header user-agent";
+
+/// Write a copy of a single .mbox file with its addresses, names, subjects and bodies replaced, to share a mailbox that reproduces a problem without giving away its mail.
+#[derive(Parser)]
+#[clap(after_long_help = DETAILS)]
+pub struct AnonymizeCommand {
+    input_file: PathBuf,
+
+    /// The anonymized mbox file to write.
+    #[clap(short = 'o', long = "output", value_name = "PATH", value_parser = validate_output_file)]
+    output_file: PathBuf,
+
+    /// The seed to derive pseudonyms and scrambled words from, to produce the same output from the same mbox again. A different seed is used every time when not given, and printed once the anonymization is over.
+    #[clap(long = "seed")]
+    seed: Option<u64>,
+
+    /// Leave this header as it is, such as 'user-agent' to keep the name of the mailer. Can be repeated.
+    #[clap(long = "keep-header", value_name = "NAME", value_parser = validate_header_name)]
+    keep_headers: Vec<String>,
+
+    /// Replace the output file if it already exists.
+    #[clap(long = "overwrite")]
+    overwrite: bool,
+
+    /// Read the input file even if it does not look like an mbox file.
+    #[clap(long = "force")]
+    force: bool,
+}
+
+impl AnonymizeCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        self.input_file = paths::expand(&self.input_file).map_err(anyhow::Error::msg)?;
+        self.output_file = paths::expand(&self.output_file).map_err(anyhow::Error::msg)?;
+        Ok(())
+    }
+
+    pub fn run(&self) -> Result<ExitCode> {
+        let pb = ProgressBar::hidden();
+        let mut options = AnonymizeOptions::new(&self.input_file, &self.output_file);
+        options.seed = self.seed;
+        options.keep_headers = self.keep_headers.clone();
+        options.overwrite = self.overwrite;
+        options.force = self.force;
+        options.cancel = Some(interrupt::flag());
+        options.on_event = Some(Box::new(|event| {
+            Self::handle_event(&pb, event);
+            Ok(())
+        }));
+
+        let result = anonymize_mbox(&options);
+        pb.finish_and_clear();
+        Ok(self.report(&result?))
+    }
+
+    /// Print the outcome of the anonymization and choose the exit code for it.
+    fn report(&self, anonymized: &Anonymized) -> ExitCode {
+        if anonymized.interrupted {
+            println!(
+                "Anonymization interrupted after {} emails, nothing was written",
+                anonymized.messages + anonymized.errors.len()
+            );
+            return ExitCode::from(INTERRUPTED_EXIT_CODE);
+        }
+        println!(
+            "Anonymization of {} emails completed with {} and seed {}. Output saved to {:?}",
+            anonymized.messages,
+            output::error_count(Stream::Stdout, anonymized.errors.len()),
+            anonymized.seed,
+            self.output_file
+        );
+        ExitCode::SUCCESS
+    }
+
+    fn handle_event(pb: &ProgressBar, event: Event<'_>) {
+        if output::logged(&event) {
+            return;
+        }
+        match event {
+            Event::Started { total, position } => {
+                style_progress_bar(pb, total);
+                pb.set_position(position);
+                pb.set_message("0 emails processed");
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Progress {
+                position,
+                processed,
+            } => {
+                pb.set_position(position);
+                pb.set_message(format!("{} emails processed", HumanCount(processed as u64)));
+            }
+            Event::Failed { item, error } => pb.println(output::error(format!(
+                "Error {item}: {}",
+                describe_error(error)
+            ))),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use crate::cli::{
+    describe_error,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    output::{self, Stream},
+    paths, style_progress_bar,
+};
+use anyhow::Result;
+use chrono::DateTime;
+use clap::{Parser, Subcommand};
+use indicatif::{HumanBytes, HumanCount, ProgressBar, ProgressDrawTarget};
+use mailfmt::{
+    AttachmentEntry, AttachmentList, AttachmentsOptions, ConversionSummary, Event,
+    ExtractAttachmentsOptions, MANIFEST_FILE_NAME, Pattern, extract_attachments, list_attachments,
+};
+use serde_json::{Map, Value, json};
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+/// Work with the attachments of the messages in a single .mbox file.
+#[derive(Parser)]
+pub struct AttachmentsCommand {
+    #[clap(subcommand)]
+    command: AttachmentsSubcommand,
+}
+
+#[derive(Subcommand)]
+enum AttachmentsSubcommand {
+    List(ListCommand),
+    Extract(ExtractCommand),
+}
+
+impl AttachmentsCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        match &mut self.command {
+            AttachmentsSubcommand::List(cmd) => cmd.expand_paths(),
+            AttachmentsSubcommand::Extract(cmd) => cmd.expand_paths(),
+        }
+    }
+
+    pub fn run(&self) -> Result<ExitCode> {
+        match &self.command {
+            AttachmentsSubcommand::List(cmd) => cmd.run(),
+            AttachmentsSubcommand::Extract(cmd) => cmd.run(),
+        }
+    }
+}
+
+/// Examples shown in the long help and the man page.
+const LIST_EXAMPLES: &str = "\
+Examples:
+  List the attachments of an mbox that are at least 1 MB:
+    mailfmt attachments list archive.mbox --min-size 1000000";
+
+/// List the attachments of every message, including inline parts that are files such as images.
+#[derive(Parser)]
+#[clap(after_long_help = LIST_EXAMPLES)]
+struct ListCommand {
+    input_file: PathBuf,
+
+    /// Leave out attachments smaller than this many bytes once decoded.
+    #[clap(long = "min-size", value_name = "BYTES", default_value_t = 0)]
+    min_size: u64,
+
+    /// Read the input file even if it does not look like an mbox file.
+    #[clap(long = "force")]
+    force: bool,
+
+    /// Print every attachment to stdout as a JSON object on its own line instead of as a table.
+    #[clap(long = "json")]
+    json: bool,
+}
+
+impl ListCommand {
+    fn expand_paths(&mut self) -> Result<()> {
+        self.input_file = paths::expand(&self.input_file).map_err(anyhow::Error::msg)?;
+        Ok(())
+    }
+
+    fn run(&self) -> Result<ExitCode> {
+        let pb = ProgressBar::hidden();
+        let mut options = AttachmentsOptions::new(&self.input_file);
+        options.min_size = self.min_size;
+        options.force = self.force;
+        options.cancel = Some(interrupt::flag());
+        options.on_event = Some(Box::new(|event| {
+            Self::handle_event(&pb, event);
+            Ok(())
+        }));
+
+        let result = list_attachments(&options);
+        pb.finish_and_clear();
+        let list = result?;
+
+        if self.json {
+            for entry in &list.entries {
+                println!("{}", Self::to_json(entry));
+            }
+        } else {
+            Self::print_table(&list);
+        }
+        if list.interrupted {
+            eprintln!(
+                "Listing interrupted after {} messages, the list is incomplete",
+                list.messages
+            );
+            return Ok(ExitCode::from(INTERRUPTED_EXIT_CODE));
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn handle_event(pb: &ProgressBar, event: Event<'_>) {
+        if output::logged(&event) {
+            return;
+        }
+        match event {
+            Event::Started { total, position } => {
+                style_progress_bar(pb, total);
+                pb.set_position(position);
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Progress {
+                position,
+                processed,
+            } => {
+                pb.set_position(position);
+                pb.set_message(format!("{} emails read", HumanCount(processed as u64)));
+            }
+            Event::Failed { item, error } => pb.println(output::error(format!(
+                "Error {item}: {}",
+                describe_error(error)
+            ))),
+            Event::Warning(message) => {
+                pb.suspend(|| eprintln!("{}", output::warning(format!("Warning: {message}"))))
+            }
+            _ => {}
+        }
+    }
+
+    fn print_table(list: &AttachmentList) {
+        let rows: Vec<[String; 6]> = list
+            .entries
+            .iter()
+            .map(|entry| {
+                let (size, content_type, filename) = match &entry.attachment {
+                    Ok(attachment) => (
+                        attachment
+                            .size
+                            .map_or_else(|| "?".to_string(), |size| HumanBytes(size).to_string()),
+                        attachment.content_type.clone(),
+                        attachment.filename.clone().unwrap_or_default(),
+                    ),
+                    Err(problem) => (
+                        String::new(),
+                        String::new(),
+                        format!("[unparseable structure: {problem}]"),
+                    ),
+                };
+                [
+                    entry.message.to_string(),
+                    entry.date.as_deref().map(short_date).unwrap_or_default(),
+                    entry.sender.clone().unwrap_or_default(),
+                    size,
+                    content_type,
+                    filename,
+                ]
+            })
+            .collect();
+
+        let header = ["Email", "Date", "From", "Size", "Type", "Filename"].map(str::to_string);
+        let mut widths = header.each_ref().map(|column| column.chars().count());
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        for row in std::iter::once(&header).chain(&rows) {
+            let [index, date, sender, size, content_type, filename] = row;
+            println!(
+                "{index:>w0$}  {date:w1$}  {sender:w2$}  {size:>w3$}  {content_type:w4$}  {filename}",
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3],
+                w4 = widths[4],
+            );
+        }
+        println!();
+        println!(
+            "{} attachments in {} emails, {} emails with an unparseable structure, {} errors",
+            list.entries
+                .iter()
+                .filter(|entry| entry.attachment.is_ok())
+                .count(),
+            list.messages,
+            list.entries
+                .iter()
+                .filter(|entry| entry.attachment.is_err())
+                .count(),
+            list.errors.len()
+        );
+    }
+
+    fn to_json(entry: &AttachmentEntry) -> Value {
+        let mut object = Map::new();
+        object.insert("message".into(), json!(entry.message));
+        object.insert("date".into(), json!(entry.date));
+        object.insert("sender".into(), json!(entry.sender));
+        match &entry.attachment {
+            Ok(attachment) => {
+                object.insert("filename".into(), json!(attachment.filename));
+                object.insert("content_type".into(), json!(attachment.content_type));
+                object.insert("size".into(), json!(attachment.size));
+            }
+            Err(problem) => {
+                object.insert("unparseable".into(), json!(problem));
+            }
+        }
+        Value::Object(object)
+    }
+}
+
+/// Examples shown in the long help and the man page.
+const EXTRACT_EXAMPLES: &str = "\
+Examples:
+  Extract every attachment of an mbox:
+    mailfmt attachments extract archive.mbox attachments/
+
+  Extract only PDF files and Word documents:
+    mailfmt attachments extract archive.mbox attachments/ --type application/pdf --name-glob '*.docx'";
+
+/// Decode every attachment and write it to its own file in a directory, named after the index of its email and its file name, along with a manifest.
+#[derive(Parser)]
+#[clap(after_long_help = EXTRACT_EXAMPLES)]
+struct ExtractCommand {
+    input_file: PathBuf,
+
+    output_directory: PathBuf,
+
+    /// Replace any existing files in the given directory with new ones if they overlap. Will not delete files that do not overlap.
+    #[clap(long = "overwrite")]
+    overwrite: bool,
+
+    /// Only extract attachments of this media type, such as 'application/pdf' or 'image/*'. Can be repeated.
+    #[clap(long = "type", value_name = "PATTERN", value_parser = Pattern::new)]
+    types: Vec<Pattern>,
+
+    /// Only extract attachments whose file name matches this glob pattern, such as '*.docx'. Matching is case-insensitive. Can be repeated.
+    #[clap(long = "name-glob", value_name = "PATTERN", value_parser = Pattern::new)]
+    names: Vec<Pattern>,
+
+    /// Read the input file even if it does not look like an mbox file.
+    #[clap(long = "force")]
+    force: bool,
+}
+
+impl ExtractCommand {
+    fn expand_paths(&mut self) -> Result<()> {
+        self.input_file = paths::expand(&self.input_file).map_err(anyhow::Error::msg)?;
+        self.output_directory =
+            paths::expand(&self.output_directory).map_err(anyhow::Error::msg)?;
+        Ok(())
+    }
+
+    fn run(&self) -> Result<ExitCode> {
+        let pb = ProgressBar::hidden();
+        let mut options = ExtractAttachmentsOptions::new(&self.input_file, &self.output_directory);
+        options.overwrite = self.overwrite;
+        options.types = self.types.clone();
+        options.names = self.names.clone();
+        options.force = self.force;
+        options.cancel = Some(interrupt::flag());
+        options.on_event = Some(Box::new(|event| {
+            ListCommand::handle_event(&pb, event);
+            Ok(())
+        }));
+
+        let result = extract_attachments(&options);
+        pb.finish_and_clear();
+        Ok(self.report(&result?))
+    }
+
+    /// Print the outcome of the extraction and choose the exit code for it.
+    fn report(&self, summary: &ConversionSummary) -> ExitCode {
+        let manifest = self.output_directory.join(MANIFEST_FILE_NAME);
+        if summary.interrupted {
+            println!(
+                "Extraction interrupted: {} attachments ({}) extracted with {}. Partial output saved to {:?}, listed in {:?}",
+                summary.converted,
+                HumanBytes(summary.bytes_written),
+                output::error_count(Stream::Stdout, summary.errors.len()),
+                self.output_directory,
+                manifest
+            );
+            return ExitCode::from(INTERRUPTED_EXIT_CODE);
+        }
+        println!(
+            "Extraction of {} attachments ({}) completed with {}, {} attachments did not match the filters. Output saved to {:?}, listed in {:?}",
+            summary.converted,
+            HumanBytes(summary.bytes_written),
+            output::error_count(Stream::Stdout, summary.errors.len()),
+            summary.skipped,
+            self.output_directory,
+            manifest
+        );
+        ExitCode::SUCCESS
+    }
+}
+
+/// A date as "2024-01-31 09:00", or as it was written if it cannot be parsed.
+fn short_date(date: &str) -> String {
+    DateTime::parse_from_rfc2822(date).map_or_else(
+        |_| date.to_string(),
+        |date| date.format("%Y-%m-%d %H:%M").to_string(),
+    )
+}
@@ -0,0 +1,31 @@
+use anyhow::Result;
+use clap::{Command, Parser};
+use clap_complete::Shell;
+use std::process::ExitCode;
+
+/// Examples shown in the long help and the man page.
+const EXAMPLES: &str = "\
+Examples:
+  Load completions in every new bash shell:
+    echo 'source <(mailfmt completions bash)' >> ~/.bashrc
+
+  Install completions for fish:
+    mailfmt completions fish > ~/.config/fish/completions/mailfmt.fish";
+
+/// Print a script completing the subcommands, flags and paths of mailfmt for a shell. Load it from the shell's configuration, such as with 'source <(mailfmt completions bash)' in ~/.bashrc.
+#[derive(Parser)]
+#[clap(after_long_help = EXAMPLES)]
+pub struct CompletionsCommand {
+    /// The shell to complete for.
+    #[clap(value_enum)]
+    shell: Shell,
+}
+
+impl CompletionsCommand {
+    /// Print the completion script for the given command line interface, which is generated
+    /// from its definition so that it always matches it.
+    pub fn run(&self, mut command: Command) -> Result<ExitCode> {
+        clap_complete::generate(self.shell, &mut command, "mailfmt", &mut std::io::stdout());
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -0,0 +1,568 @@
+//! Defaults for command line flags read from a configuration file, such as
+//! `~/.config/mailfmt/config.toml`:
+//!
+//! ```toml
+//! # Flags of mbox-to-eml, by their long name.
+//! [mbox-to-eml]
+//! group-by = "thread"
+//! strip-attachments = true
+//!
+//! [attachments.extract]
+//! type = ["application/pdf", "image/*"]
+//! ```
+//!
+//! Keys outside of any table are the flags shared by every command, such as `no-expand`.
+//! Flags given on the command line always win over the configuration file.
+
+use crate::cli::{output, paths};
+use anyhow::{Context, Result, bail};
+use clap::{
+    Arg, ArgAction, ArgMatches, Command, CommandFactory, Parser, Subcommand,
+    builder::BoolishValueParser, parser::ValueSource,
+};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+/// A value in the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged, expecting = "a string, integer, boolean or array")]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(s) => {
+                f.write_str("\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\t' => f.write_str("\\t")?,
+                        c if c.is_control() => write!(f, "\\u{:04X}", c as u32)?,
+                        c => write!(f, "{c}")?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Self::Integer(i) => write!(f, "{i}"),
+            Self::Boolean(b) => write!(f, "{b}"),
+            Self::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_str("]")
+            }
+        }
+    }
+}
+
+/// A key of the configuration file, which is either a flag or the table of a command.
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a string, integer, boolean, array or table")]
+enum Item {
+    Value(Value),
+    Table(BTreeMap<String, Item>),
+}
+
+/// A key set in the configuration file.
+struct Entry {
+    /// The names of the commands the key applies to, empty for the top level.
+    table: Vec<String>,
+    key: String,
+    value: Value,
+}
+
+/// A parsed configuration file.
+pub struct Config {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl Config {
+    /// The configuration file used when none is given with --config: `config.toml` in the
+    /// `mailfmt` directory of `$XDG_CONFIG_HOME` or `~/.config`, or of `%APPDATA%` on Windows.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = if cfg!(windows) {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        };
+        base.map(|base| base.join("mailfmt").join("config.toml"))
+    }
+
+    /// Read and parse a configuration file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file '{}'", path.display()))?;
+        let entries = parse(&text)
+            .with_context(|| format!("Failed to parse config file '{}'", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    /// Find the configuration file for a command line, from its --config flag or at the
+    /// default path if a file exists there.
+    fn locate(matches: &ArgMatches) -> Result<Option<Self>> {
+        if let Some(path) = matches.get_one::<PathBuf>("config") {
+            let path = match matches.get_flag("no_expand") {
+                true => path.clone(),
+                false => paths::expand(path).map_err(anyhow::Error::msg)?,
+            };
+            return Self::load(&path).map(Some);
+        }
+        match Self::default_path().filter(|path| path.is_file()) {
+            Some(path) => Self::load(&path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The entry setting a flag of a command, if any.
+    fn entry(&self, command: &[&str], arg: &Arg) -> Option<&Entry> {
+        self.entries.iter().find(|entry| {
+            Some(entry.key.as_str()) == arg.get_long()
+                && (entry.table.iter().eq(command)
+                    || (entry.table.is_empty() && arg.is_global_set()))
+        })
+    }
+
+    /// The entries that do not name a flag of an existing command.
+    fn unknown<'a>(&'a self, root: &'a Command) -> impl Iterator<Item = &'a Entry> {
+        self.entries.iter().filter(move |entry| {
+            let mut command = root;
+            for name in &entry.table {
+                match command.find_subcommand(name) {
+                    Some(subcommand) => command = subcommand,
+                    None => return true,
+                }
+            }
+            !command
+                .get_arguments()
+                .any(|arg| arg.get_long() == Some(entry.key.as_str()) && !arg.is_positional())
+        })
+    }
+
+    fn describe(&self, entry: &Entry) -> String {
+        let key = entry
+            .table
+            .iter()
+            .cloned()
+            .chain([entry.key.clone()])
+            .collect::<Vec<_>>()
+            .join(".");
+        format!("'{key}' in config file '{}'", self.path.display())
+    }
+}
+
+/// The environment variable that sets a flag, such as MAILFMT_GROUP_BY for --group-by.
+fn environment_variable(long: &str) -> String {
+    format!("MAILFMT_{}", long.to_ascii_uppercase().replace('-', "_"))
+}
+
+/// The values accepted as true for a switch set from the environment.
+const TRUE_VALUES: [&str; 6] = ["y", "yes", "t", "true", "on", "1"];
+
+/// A command line interface where every flag can also be set from an environment variable
+/// named after it, such as MAILFMT_OVERWRITE for --overwrite. Switches accept 1, true or yes
+/// and 0, false or no.
+fn with_environment(command: Command) -> Command {
+    command
+        .mut_args(|arg| {
+            let Some(variable) = arg
+                .get_long()
+                .filter(|_| configurable(&arg))
+                .map(environment_variable)
+            else {
+                return arg;
+            };
+            match arg.get_action() {
+                ArgAction::SetTrue => arg.env(variable).value_parser(BoolishValueParser::new()),
+                ArgAction::Set | ArgAction::Append | ArgAction::Count => arg.env(variable),
+                _ => arg,
+            }
+        })
+        .mut_subcommands(with_environment)
+}
+
+/// The command line interface of a parser, with every flag readable from the environment.
+pub fn command<T: CommandFactory>() -> Command {
+    with_environment(T::command())
+}
+
+/// Parse the command line, filling in flags that were not given from the environment and then
+/// from the configuration file. Warns about keys in the file that are not flags of any command.
+///
+/// Also returns the flags that were set from the environment, such as
+/// "--overwrite (MAILFMT_OVERWRITE)", so that they can be pointed out.
+pub fn parse_arguments<T: Parser>() -> Result<(T, Vec<String>)> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let mut root = command::<T>();
+    // Exits with the usual message for invalid arguments, --help and --version.
+    let matches = root.clone().get_matches_from(&args);
+    let Some(config) = Config::locate(&matches)? else {
+        return Ok((
+            T::from_arg_matches(&matches)?,
+            from_environment(&root, &matches),
+        ));
+    };
+    root.build();
+    for entry in config.unknown(&root) {
+        eprintln!(
+            "{}",
+            output::warning(format!(
+                "Warning: ignoring unknown key {}",
+                config.describe(entry)
+            ))
+        );
+    }
+
+    // Find the command that is run, with the matches of its own arguments.
+    let mut names = Vec::new();
+    let mut command = &root;
+    let mut command_matches = &matches;
+    while let Some((name, sub_matches)) = command_matches.subcommand() {
+        names.push(name);
+        command = command
+            .find_subcommand(name)
+            .expect("matched subcommand exists");
+        command_matches = sub_matches;
+    }
+
+    let mut extra = Vec::new();
+    for arg in command.get_arguments().filter(|arg| !arg.is_positional()) {
+        let Some(entry) = config.entry(&names, arg) else {
+            continue;
+        };
+        // The command line and the environment win, including over flags that cannot be used
+        // together with a flag they give.
+        let given = |arg: &Arg| {
+            matches!(
+                command_matches.value_source(arg.get_id().as_str()),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+        let conflicts = |a: &Arg, b: &Arg| {
+            command
+                .get_arg_conflicts_with(a)
+                .iter()
+                .any(|other| other.get_id() == b.get_id())
+        };
+        if given(arg)
+            || command
+                .get_arguments()
+                .any(|other| given(other) && (conflicts(arg, other) || conflicts(other, arg)))
+        {
+            continue;
+        }
+        extra.extend(
+            flag_arguments(arg, &entry.value)
+                .with_context(|| format!("Invalid value for {}", config.describe(entry)))?,
+        );
+    }
+    if extra.is_empty() {
+        return Ok((
+            T::from_arg_matches(&matches)?,
+            from_environment(&root, &matches),
+        ));
+    }
+
+    let mut args = args;
+    let end = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    args.splice(end..end, extra);
+    let matches = with_environment(T::command())
+        .try_get_matches_from(&args)
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "Invalid settings in config file '{}': {}",
+                config.path.display(),
+                e.render()
+                    .to_string()
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .trim_start_matches("error: ")
+            )
+        })?;
+    Ok((
+        T::from_arg_matches(&matches)?,
+        from_environment(&root, &matches),
+    ))
+}
+
+/// The flags of the command that is run which were set from the environment.
+fn from_environment(root: &Command, matches: &ArgMatches) -> Vec<String> {
+    let mut flags = Vec::new();
+    let mut command = root;
+    let mut matches = matches;
+    loop {
+        for arg in command.get_arguments() {
+            if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::EnvVariable)
+                && let (Some(long), Some(variable)) = (arg.get_long(), arg.get_env())
+            {
+                flags.push(format!("--{long} ({})", variable.to_string_lossy()));
+            }
+        }
+        let Some((name, sub_matches)) = matches.subcommand() else {
+            break;
+        };
+        command = command
+            .find_subcommand(name)
+            .expect("matched subcommand exists");
+        matches = sub_matches;
+    }
+    flags.sort();
+    flags.dedup();
+    flags
+}
+
+/// Point out the flags that were set from the environment, which would otherwise be easy to
+/// miss, such as an --overwrite left behind in a shell profile.
+pub fn print_environment_flags(flags: &[String]) {
+    if !flags.is_empty() {
+        eprintln!("Set from the environment: {}", flags.join(", "));
+    }
+}
+
+/// The command line arguments that give a flag the value set in the configuration file.
+fn flag_arguments(arg: &Arg, value: &Value) -> Result<Vec<OsString>> {
+    let long = arg
+        .get_long()
+        .expect("only flags with a long name are configured");
+    let flag = format!("--{long}");
+    match (arg.get_action(), value) {
+        (ArgAction::SetTrue, Value::Boolean(set)) => {
+            Ok(set.then(|| flag.into()).into_iter().collect())
+        }
+        (ArgAction::SetTrue, _) => bail!("expected true or false"),
+        (ArgAction::Count, Value::Integer(count)) => {
+            Ok((0..*count).map(|_| OsString::from(&flag)).collect())
+        }
+        (ArgAction::Count, _) => bail!("expected a number"),
+        (ArgAction::Append, Value::Array(items)) => items
+            .iter()
+            .map(|item| Ok(format!("{flag}={}", scalar(item)?).into()))
+            .collect(),
+        (_, Value::Array(_)) => bail!("expected a single value, not a list"),
+        (_, value) => Ok(vec![format!("{flag}={}", scalar(value)?).into()]),
+    }
+}
+
+/// The text of a value given to a flag.
+fn scalar(value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        Value::Array(_) => bail!("lists cannot be nested"),
+    }
+}
+
+/// Inspect the configuration file.
+#[derive(Parser)]
+pub struct ConfigCommand {
+    #[clap(subcommand)]
+    command: ConfigSubcommand,
+}
+
+#[derive(Subcommand)]
+enum ConfigSubcommand {
+    Show(ShowCommand),
+}
+
+impl ConfigCommand {
+    pub fn run(&self, command: Command, config: Option<&Path>) -> Result<ExitCode> {
+        match &self.command {
+            ConfigSubcommand::Show(cmd) => cmd.run(command, config),
+        }
+    }
+}
+
+/// Print the value every flag of a command takes when it is not given on the command line, and where that value comes from. Without a command, the flags of all commands are shown.
+#[derive(Parser)]
+struct ShowCommand {
+    /// The command to show the flags of, such as 'mbox-to-eml' or 'attachments extract'.
+    command: Vec<String>,
+}
+
+impl ShowCommand {
+    fn run(&self, mut root: Command, config: Option<&Path>) -> Result<ExitCode> {
+        let config = match config {
+            Some(path) => Some(Config::load(path)?),
+            None => match Config::default_path().filter(|path| path.is_file()) {
+                Some(path) => Some(Config::load(&path)?),
+                None => None,
+            },
+        };
+        root.build();
+
+        let mut command = &root;
+        for name in &self.command {
+            command = command
+                .find_subcommand(name)
+                .with_context(|| format!("Unknown command '{}'", self.command.join(" ")))?;
+        }
+        match &config {
+            Some(config) => println!("# Config file: {}", config.path.display()),
+            None => match Config::default_path() {
+                Some(path) => println!("# No config file, looked for {}", path.display()),
+                None => println!("# No config file"),
+            },
+        }
+        let names: Vec<&str> = self.command.iter().map(String::as_str).collect();
+        show(command, &names, config.as_ref());
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Print the flags of a command and its subcommands as a configuration file would set them.
+fn show(command: &Command, names: &[&str], config: Option<&Config>) {
+    let flags: Vec<&Arg> = command
+        .get_arguments()
+        .filter(|arg| {
+            // Shared flags are shown once, with the top-level ones.
+            !arg.is_positional()
+                && !arg.is_hide_set()
+                && (names.is_empty() || !arg.is_global_set())
+                && configurable(arg)
+        })
+        .collect();
+    if !flags.is_empty() {
+        println!();
+        if !names.is_empty() {
+            println!("[{}]", names.join("."));
+        }
+    }
+    for arg in flags {
+        let long = arg.get_long().expect("configurable flags have a long name");
+        let environment = arg.get_env().and_then(|variable| {
+            let value = std::env::var_os(variable)?.to_string_lossy().into_owned();
+            Some((variable.to_string_lossy(), value))
+        });
+        if let Some((variable, value)) = environment {
+            let value = match arg.get_action() {
+                ArgAction::SetTrue => {
+                    Value::Boolean(TRUE_VALUES.contains(&value.to_ascii_lowercase().as_str()))
+                }
+                _ => Value::String(value),
+            };
+            println!("{long} = {value}  # environment, {variable}");
+            continue;
+        }
+        match config.and_then(|config| config.entry(names, arg)) {
+            Some(entry) => println!("{long} = {}  # config file", entry.value),
+            None => match default_value(arg) {
+                Some(value) => println!("{long} = {value}  # default"),
+                None => println!("# {long} is not set"),
+            },
+        }
+    }
+    for subcommand in command.get_subcommands() {
+        if subcommand.is_hide_set() || subcommand.get_name() == "help" {
+            continue;
+        }
+        let mut names = names.to_vec();
+        names.push(subcommand.get_name());
+        show(subcommand, &names, config);
+    }
+}
+
+/// Whether a flag sets a value that a configuration file can give, unlike --help, --version
+/// and --config itself.
+fn configurable(arg: &Arg) -> bool {
+    arg.get_long().is_some()
+        && arg.get_id() != "config"
+        && !matches!(
+            arg.get_action(),
+            ArgAction::Help | ArgAction::HelpShort | ArgAction::HelpLong | ArgAction::Version
+        )
+}
+
+/// The built-in default of a flag, as it would be written in a configuration file.
+fn default_value(arg: &Arg) -> Option<Value> {
+    match arg.get_action() {
+        ArgAction::SetTrue => return Some(Value::Boolean(false)),
+        ArgAction::Count => return Some(Value::Integer(0)),
+        _ => {}
+    }
+    let values: Vec<Value> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| {
+            let value = value.to_string_lossy();
+            match value.parse() {
+                Ok(i) => Value::Integer(i),
+                Err(_) => Value::String(value.into_owned()),
+            }
+        })
+        .collect();
+    match (arg.get_action(), values.len()) {
+        (_, 0) => None,
+        (ArgAction::Append, _) => Some(Value::Array(values)),
+        _ => values.into_iter().next(),
+    }
+}
+
+/// Parse a configuration file into the keys it sets, with the keys of nested tables under
+/// the names of the tables leading to them.
+fn parse(text: &str) -> Result<Vec<Entry>> {
+    let items: BTreeMap<String, Item> = toml::from_str(text)?;
+    let mut entries = Vec::new();
+    flatten(&[], items, &mut entries)?;
+    Ok(entries)
+}
+
+/// Add the keys of a table to the entries, with '_' in their names read as '-'.
+fn flatten(
+    table: &[String],
+    items: BTreeMap<String, Item>,
+    entries: &mut Vec<Entry>,
+) -> Result<()> {
+    for (key, item) in items {
+        match item {
+            Item::Table(items) => {
+                let mut table = table.to_vec();
+                table.push(key);
+                flatten(&table, items, entries)?;
+            }
+            Item::Value(value) => {
+                let key = key.replace('_', "-");
+                if entries
+                    .iter()
+                    .any(|entry| entry.table == table && entry.key == key)
+                {
+                    bail!("'{key}' is set more than once");
+                }
+                entries.push(Entry {
+                    table: table.to_vec(),
+                    key,
+                    value,
+                });
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use crate::cli::{
+    describe_error,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    output, paths, style_progress_bar,
+};
+use anyhow::Result;
+use clap::Parser;
+use indicatif::{HumanCount, ProgressBar, ProgressDrawTarget};
+use mailfmt::{DiffEntry, DiffLocation, DiffOptions, DiffSide, Event, MailboxDiff, diff_mailboxes};
+use serde_json::{Value, json};
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+/// The exit code used when the mailboxes compared do not hold the same messages.
+pub const DIFFERENCES_EXIT_CODE: u8 = 1;
+
+/// How messages are matched, and examples, shown in the long help and the man page.
+const DETAILS: &str = "\
+Messages are matched by their Message-ID, and those without one by a hash of their content.
+Contents are compared ignoring line endings, trailing blank lines, the quoting of 'From '
+lines in mbox files and headers recording where a message is kept, such as Status,
+Content-Length and X-Folder, so that messages converted between formats compare equal. Only
+the keys and hashes of messages are kept in memory.
+
+Exits with 0 when both mailboxes hold the same messages, all of them readable, and with 1
+otherwise.
+
+Examples:
+  Check that a migration to a Maildir lost nothing:
+    mailfmt diff old.mbox new-maildir/
+
+  List every message that differs, as JSON for a script:
+    mailfmt diff old.mbox emls/ --list --json";
+
+/// Compare two mailboxes, each an .mbox file, a Maildir or a directory of .eml files, and report the messages found on only one side or with different content on both.
+#[derive(Parser)]
+#[clap(after_long_help = DETAILS)]
+pub struct DiffCommand {
+    /// The mailbox to compare against, such as the one mail was migrated from.
+    left: PathBuf,
+
+    /// The mailbox to compare, such as the one mail was migrated to.
+    right: PathBuf,
+
+    /// List the Message-IDs of the messages that differ and where they were found, instead of only counting them.
+    #[clap(long = "list")]
+    list: bool,
+
+    /// Read an input file even if it does not look like an mbox file.
+    #[clap(long = "force")]
+    force: bool,
+
+    /// Print the report to stdout as JSON instead of as text.
+    #[clap(long = "json")]
+    json: bool,
+}
+
+impl DiffCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        self.left = paths::expand(&self.left).map_err(anyhow::Error::msg)?;
+        self.right = paths::expand(&self.right).map_err(anyhow::Error::msg)?;
+        Ok(())
+    }
+
+    pub fn run(&self) -> Result<ExitCode> {
+        let pb = ProgressBar::hidden();
+        let mut options = DiffOptions::new(&self.left, &self.right);
+        options.force = self.force;
+        options.cancel = Some(interrupt::flag());
+        options.on_event = Some(Box::new(|event| {
+            Self::handle_event(&pb, event);
+            Ok(())
+        }));
+
+        let result = diff_mailboxes(&options);
+        pb.finish_and_clear();
+        let diff = result?;
+
+        if self.json {
+            println!("{:#}", self.to_json(&diff));
+        } else {
+            self.print_report(&diff);
+        }
+        if diff.interrupted {
+            eprintln!("Comparison interrupted, the report is incomplete");
+            return Ok(ExitCode::from(INTERRUPTED_EXIT_CODE));
+        }
+        Ok(match diff.is_same() {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::from(DIFFERENCES_EXIT_CODE),
+        })
+    }
+
+    fn handle_event(pb: &ProgressBar, event: Event<'_>) {
+        if output::logged(&event) {
+            return;
+        }
+        match event {
+            Event::Started { total, position } => {
+                style_progress_bar(pb, total);
+                pb.set_position(position);
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Progress {
+                position,
+                processed,
+            } => {
+                pb.set_position(position);
+                pb.set_message(format!("{} emails read", HumanCount(processed as u64)));
+            }
+            Event::Failed { item, error } => pb.println(output::error(format!(
+                "Error {item}: {}",
+                describe_error(error)
+            ))),
+            Event::Warning(message) => {
+                pb.suspend(|| eprintln!("{}", output::warning(format!("Warning: {message}"))))
+            }
+            _ => {}
+        }
+    }
+
+    fn print_report(&self, diff: &MailboxDiff) {
+        let describe = |side: &DiffSide| {
+            format!(
+                "{:?} ({}, {} emails)",
+                side.path,
+                side.kind,
+                side.messages + side.errors.len()
+            )
+        };
+        println!(
+            "Compared {} with {}",
+            describe(&diff.left),
+            describe(&diff.right)
+        );
+        for side in [&diff.left, &diff.right] {
+            if !side.errors.is_empty() {
+                println!(
+                    "{} emails of {:?} could not be read and were left out",
+                    side.errors.len(),
+                    side.path
+                );
+            }
+        }
+        println!("{} emails are the same on both sides", diff.matching);
+        let sections = [
+            (format!("only in {:?}", diff.left.path), &diff.only_left),
+            (format!("only in {:?}", diff.right.path), &diff.only_right),
+            (
+                "on both sides with different content".to_string(),
+                &diff.changed,
+            ),
+        ];
+        for (description, entries) in &sections {
+            println!("{} emails are {description}", entries.len());
+        }
+        if diff.is_same() {
+            println!("Both mailboxes hold the same emails");
+            return;
+        }
+        if !self.list {
+            if sections.iter().any(|(_, entries)| !entries.is_empty()) {
+                println!("Pass --list to list them");
+            }
+            return;
+        }
+        for (description, entries) in sections {
+            if entries.is_empty() {
+                continue;
+            }
+            println!();
+            println!("Emails {description}:");
+            for entry in entries {
+                let key = match entry.synthetic {
+                    true => format!("{} (no Message-ID)", entry.key),
+                    false => format!("<{}>", entry.key),
+                };
+                let locations: Vec<String> = entry
+                    .left
+                    .iter()
+                    .map(|location| format!("left {location}"))
+                    .chain(
+                        entry
+                            .right
+                            .iter()
+                            .map(|location| format!("right {location}")),
+                    )
+                    .collect();
+                println!("  {key}: {}", locations.join(", "));
+            }
+        }
+    }
+
+    fn to_json(&self, diff: &MailboxDiff) -> Value {
+        let side = |side: &DiffSide| {
+            json!({
+                "path": side.path.to_string_lossy(),
+                "kind": side.kind.to_string(),
+                "messages": side.messages,
+                "errors": side.errors.len(),
+            })
+        };
+        let entries = |entries: &[DiffEntry]| match self.list {
+            true => entries
+                .iter()
+                .map(|entry| {
+                    let locations = |locations: &[DiffLocation]| {
+                        locations
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                    };
+                    json!({
+                        "key": entry.key,
+                        "synthetic": entry.synthetic,
+                        "left": locations(&entry.left),
+                        "right": locations(&entry.right),
+                    })
+                })
+                .collect(),
+            false => json!(entries.len()),
+        };
+        json!({
+            "left": side(&diff.left),
+            "right": side(&diff.right),
+            "same": diff.is_same(),
+            "matching": diff.matching,
+            "only_left": entries(&diff.only_left),
+            "only_right": entries(&diff.only_right),
+            "changed": entries(&diff.changed),
+            "interrupted": diff.interrupted,
+        })
+    }
+}
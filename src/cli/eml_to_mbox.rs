@@ -0,0 +1,624 @@
+use crate::cli::{
+    describe_counters, describe_duplicates, describe_error, describe_invalid_utf8,
+    describe_msgid_index, describe_stripped, describe_throughput,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    output::{self, Stream},
+    paths,
+    porcelain::{self, Porcelain},
+    print_errors, print_path,
+    prompt::Prompter,
+    report_count, report_empty_input, validate_file_path, validate_header_name, write_error_report,
+};
+use anyhow::{Context, Result, bail};
+use clap::Parser;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use mailfmt::{
+    ConversionSummary, EmlInput, EmlToMboxOptions, Event, FromLineStyle, InvalidUtf8, MailfmtError,
+    MboxDialect, Pattern, ProgressTotal, count_eml_files, eml_to_mbox,
+};
+use std::{
+    cell::Cell,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+/// Examples shown in the long help and the man page.
+const EXAMPLES: &str = "\
+Examples:
+  Convert a directory of eml files, searched recursively:
+    mailfmt eml-to-mbox messages/ archive.mbox
+
+  Convert the eml files of one year, listed by find:
+    find messages/2023 -name '*.eml' -print0 | mailfmt eml-to-mbox --files-from - -0 archive.mbox
+
+  Append only new or changed eml files to an existing mbox:
+    mailfmt eml-to-mbox messages/ archive.mbox --append --state-file archive.state";
+
+/// Convert a directory of .eml files to a single .mbox file.
+#[derive(Parser)]
+#[clap(after_long_help = EXAMPLES)]
+pub struct ConvertToMboxCommand {
+    /// Directory to search for eml files. A glob pattern such as './export/2023-*/**/*.eml' can be given instead. Omitted when using --files-from.
+    #[arg(required_unless_present = "files_from")]
+    input_directory: Option<PathBuf>,
+
+    #[arg(value_parser = validate_file_path)]
+    output_file: Option<PathBuf>,
+
+    /// Read the list of eml files to convert from a file, or from stdin when given '-', instead of searching a directory. Files are converted in the given order.
+    #[clap(long = "files-from", value_name = "PATH")]
+    files_from: Option<PathBuf>,
+
+    /// Entries read with --files-from are separated by NUL characters instead of newlines, as produced by `find -print0`.
+    #[clap(short = '0', long = "null", requires = "files_from")]
+    null_separated: bool,
+
+    #[clap(long = "overwrite")]
+    overwrite: bool,
+
+    /// Append to the mbox file if it already exists instead of refusing to replace it. Together with --state-file, only eml files that are new or changed since the previous run are appended.
+    #[clap(long = "append", conflicts_with = "overwrite")]
+    append: bool,
+
+    /// Create the directories above the mbox file that do not exist yet, instead of failing.
+    #[clap(short = 'p', long = "create-parents")]
+    create_parents: bool,
+
+    /// Ask on the terminal before replacing an existing mbox file, instead of failing. Only asks when stdin is a terminal.
+    #[clap(long = "interactive", conflicts_with_all = ["overwrite", "append"])]
+    interactive: bool,
+
+    /// File recording which eml files were converted, by path, size, modification time and content hash. It is rewritten after every run and read back by runs using --append.
+    #[clap(long = "state-file", value_name = "PATH")]
+    state_file: Option<PathBuf>,
+
+    /// Also lock the mbox file with a traditional '<name>.lock' file while writing it, for mail programs that only honor that convention.
+    #[clap(long = "dotlock")]
+    dotlock: bool,
+
+    /// How many seconds to wait for other programs to release their lock on the mbox file before giving up.
+    #[clap(long = "lock-timeout", value_name = "SECONDS", default_value_t = 10)]
+    lock_timeout: u64,
+
+    /// The mbox dialect to write: 'mboxrd' quotes body lines starting with any number of '>' followed by 'From ' reversibly, 'mboxo' only quotes lines starting with 'From ', 'mboxcl' quotes like mboxo and adds a Content-Length header, and 'mboxcl2' only adds the Content-Length header.
+    #[clap(long = "dialect", default_value_t = MboxDialect::Mboxrd)]
+    dialect: MboxDialect,
+
+    /// How to write the separator line starting every message: 'sender' writes 'From ' followed by the sender address and the date, and 'thunderbird' writes 'From - ' followed by the date, as Thunderbird does. The date comes from the sidecar written by 'mbox-to-eml --sidecar-metadata' when there is one, so that a Thunderbird mbox keeps its separator lines through a round trip.
+    #[clap(long = "from-line-style", value_name = "STYLE", default_value_t = FromLineStyle::Sender)]
+    from_line_style: FromLineStyle,
+
+    /// Size in bytes of the buffer used when writing the mbox file.
+    #[clap(long = "buffer-size", default_value_t = 4 * 1024 * 1024)]
+    buffer_size: usize,
+
+    /// Sync the mbox file and its parent directory to disk once it has been written.
+    #[clap(long = "fsync")]
+    fsync: bool,
+
+    /// Write the mbox file even when every eml file found fails to convert. Otherwise that is an error with exit code 3.
+    #[clap(long = "allow-empty")]
+    allow_empty: bool,
+
+    /// Leave out eml files that do not start with a plausible header block, such as HTML exports or empty files, or that lack a header given with --require-headers. They are counted as skipped and listed once the conversion is over, instead of being counted as errors.
+    #[clap(long = "skip-invalid")]
+    skip_invalid: bool,
+
+    /// With --skip-invalid, the headers every eml file needs to have, with a value, to be converted, separated by commas, such as 'from,date,message-id'.
+    #[clap(long = "require-headers", value_name = "HEADERS", value_delimiter = ',', value_parser = validate_header_name, requires = "skip_invalid")]
+    require_headers: Vec<String>,
+
+    /// Do not check that the filesystem of the output file has room for the eml files and a 5% margin before converting, for filesystems that misreport their free space.
+    #[clap(long = "no-space-check")]
+    no_space_check: bool,
+
+    /// Convert even if the filesystem of the output file seems to lack the space for it, with a warning.
+    #[clap(long = "force")]
+    force: bool,
+
+    /// Follow symlinked directories when searching for eml files. Symlinked files are always included.
+    #[clap(long = "follow-symlinks")]
+    follow_symlinks: bool,
+
+    /// Glob pattern selecting which files to convert. Patterns without a '/' match file names, others match the path relative to the input directory. Matching is case-insensitive, and a pattern also matches the gzip-compressed form of the files it matches, ending in '.gz', which are decompressed before converting. Can be repeated.
+    #[clap(long = "include", default_value = "*.eml", value_parser = Pattern::new)]
+    include: Vec<Pattern>,
+
+    /// Only print the number of eml files that would be converted, found as with --include and --follow-symlinks and with --skip-invalid reading no more than their header blocks, and exit without writing anything. No output file is needed.
+    #[clap(long = "count-only", conflicts_with_all = ["watch", "append", "interactive", "porcelain", "print_paths"])]
+    count_only: bool,
+
+    /// What to do with eml files containing bytes that are not valid UTF-8: 'preserve' passes them through untouched, 'lossy' replaces them with U+FFFD, and 'strict' counts the file as an error.
+    #[clap(long = "invalid-utf8", value_name = "POLICY", default_value_t = InvalidUtf8::Preserve)]
+    invalid_utf8: InvalidUtf8,
+
+    /// Keep a UTF-8 byte-order mark at the start of eml files instead of removing it. The first header of such a file is then not recognised.
+    #[clap(long = "keep-bom")]
+    keep_bom: bool,
+
+    /// Replace the attachments of multipart messages with short text parts recording their file name, type, size and SHA-256, keeping the messages valid MIME.
+    #[clap(long = "strip-attachments")]
+    strip_attachments: bool,
+
+    /// When stripping attachments, also replace messages that consist of a single attachment, such as a bare PDF, instead of leaving them intact.
+    #[clap(long = "strip-single-part", requires = "strip_attachments")]
+    strip_single_part: bool,
+
+    /// Record the subfolder each eml file was found in, relative to the input directory, in an 'X-Folder' header such as 'X-Folder: Clients/Acme', so that 'mbox-to-eml --restore-folders' can recreate the folders. Emails directly in the input directory get no header.
+    #[clap(long = "record-folder")]
+    record_folder: bool,
+
+    /// Record the path of each eml file, relative to the input directory, in an 'X-Source-File' header, so that 'mbox-to-eml --restore-names' can name the emails after their files again. Without it, the header is removed from emails that already have one.
+    #[clap(long = "record-source")]
+    record_source: bool,
+
+    /// Report the Message-IDs found on more than one email once the conversion is over, with the numbers and files of those emails. Every email is still converted.
+    #[clap(long = "report-duplicates")]
+    report_duplicates: bool,
+
+    /// Once the mbox file is written, read the appended emails back and check that each matches the eml file it was written from, failing if any does not. Line endings, Content-Length headers and blank lines at the end of an email are not compared.
+    #[clap(long = "verify-content")]
+    verify_content: bool,
+
+    /// Append the emails of an eml file that holds a whole mailbox one by one, keeping their separator lines. Such a file holds several emails that each start with a 'From ' line followed by a header, or several header blocks that each start with a Received or Return-Path header after a blank line. Without it, such a file is appended as a single email with a warning.
+    #[clap(long = "split-embedded")]
+    split_embedded: bool,
+
+    /// Write an index of the appended emails to this file, mapping every Message-ID to the byte offset of its email in the mbox file. Emails without a Message-ID are keyed by 'sha256:' and a hash of their content, and flagged as synthetic. A file name ending in '.jsonl' gets JSON Lines, and any other tab-separated values.
+    #[clap(long = "msgid-index", value_name = "PATH")]
+    msgid_index: Option<PathBuf>,
+
+    /// Write every error of the conversion to this file once it is over, one per line, while only the first 50 are listed in the output. A file name ending in '.jsonl' gets a JSON object per line.
+    #[clap(long = "error-report", value_name = "PATH")]
+    error_report: Option<PathBuf>,
+
+    /// Print the path of every file written to stdout as soon as it is complete, in message order. All other output is written to stderr instead.
+    #[clap(long = "print-paths")]
+    print_paths: bool,
+
+    /// Terminate paths printed by --print-paths with a NUL character instead of a newline.
+    #[clap(long = "print0", requires = "print_paths")]
+    print0: bool,
+
+    #[clap(long = "porcelain", help = porcelain::HELP, conflicts_with_all = ["print_paths", "watch"])]
+    porcelain: bool,
+
+    /// With --porcelain, print a progress line every this many eml files.
+    #[clap(long = "porcelain-every", value_name = "N", default_value_t = porcelain::DEFAULT_EVERY, requires = "porcelain")]
+    porcelain_every: u64,
+
+    /// After converting, keep watching the input for new eml files and append them to the mbox until stopped with Ctrl-C.
+    #[clap(long = "watch", conflicts_with = "files_from")]
+    watch: bool,
+
+    /// How long in milliseconds a new file has to stay unchanged before it is appended in watch mode.
+    #[clap(long = "settle-ms", default_value_t = 1000, requires = "watch")]
+    settle_ms: u64,
+}
+
+impl ConvertToMboxCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        for path in [
+            &mut self.input_directory,
+            &mut self.output_file,
+            &mut self.files_from,
+            &mut self.state_file,
+            &mut self.msgid_index,
+            &mut self.error_report,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = paths::expand(path).map_err(anyhow::Error::msg)?;
+        }
+        Ok(())
+    }
+
+    /// The input directory, which is absent when the file list comes from --files-from, and the
+    /// output file. With --files-from the output file is the only positional argument given.
+    fn positionals(&self) -> Result<(Option<&Path>, &Path)> {
+        match (
+            &self.files_from,
+            self.input_directory.as_deref(),
+            self.output_file.as_deref(),
+        ) {
+            (None, Some(input_dir), Some(output_file)) => Ok((Some(input_dir), output_file)),
+            (Some(_), Some(output_file), None) => {
+                validate_file_path(&output_file.to_string_lossy()).map_err(anyhow::Error::msg)?;
+                Ok((None, output_file))
+            }
+            (Some(_), Some(_), Some(_)) => {
+                bail!("An input directory cannot be given together with --files-from")
+            }
+            _ => bail!("No output file was given"),
+        }
+    }
+
+    /// Where human-readable messages go, keeping stdout free for paths when --print-paths is used.
+    fn stream(&self) -> Stream {
+        match self.print_paths || self.porcelain {
+            true => Stream::Stderr,
+            false => Stream::Stdout,
+        }
+    }
+
+    /// Print a human-readable message to the stream for them.
+    fn info(&self, message: impl std::fmt::Display) {
+        match self.stream() {
+            Stream::Stderr => eprintln!("{message}"),
+            Stream::Stdout => println!("{message}"),
+        }
+    }
+
+    /// The eml files to convert, from the input directory or read with --files-from, and how
+    /// many of those listed do not exist.
+    fn input(&self, input_dir: Option<&Path>) -> Result<(EmlInput, usize)> {
+        match (input_dir, &self.files_from) {
+            (Some(input_dir), _) => Ok((EmlInput::Directory(input_dir.to_path_buf()), 0)),
+            (None, Some(files_from)) => {
+                let (files, missing) = self.read_files_from(files_from)?;
+                Ok((EmlInput::Files(files), missing))
+            }
+            (None, None) => {
+                unreachable!("positionals require an input directory without --files-from")
+            }
+        }
+    }
+
+    pub fn run(&self) -> Result<ExitCode> {
+        if self.count_only {
+            return self.count();
+        }
+        let (input_dir, output_file) = self.positionals()?;
+        let (input, missing) = self.input(input_dir)?;
+
+        let pb = ProgressBar::hidden();
+        let porcelain = self.porcelain.then(|| Porcelain::new(self.porcelain_every));
+        let prompter = self.interactive.then(|| Prompter::new(&pb)).flatten();
+        let watching = Cell::new(false);
+        let mut options = EmlToMboxOptions::new(input, output_file);
+        options.overwrite = self.overwrite;
+        options.create_parents = self.create_parents;
+        options.append = self.append;
+        options.state_file = self.state_file.clone();
+        options.dotlock = self.dotlock;
+        options.lock_timeout = Duration::from_secs(self.lock_timeout);
+        options.dialect = self.dialect;
+        options.from_line_style = self.from_line_style;
+        options.invalid_utf8 = self.invalid_utf8;
+        options.keep_bom = self.keep_bom;
+        options.strip_attachments = self.strip_attachments;
+        options.strip_single_part = self.strip_single_part;
+        options.record_folder = self.record_folder;
+        options.record_source = self.record_source;
+        options.report_duplicates = self.report_duplicates;
+        options.verify_content = self.verify_content;
+        options.split_embedded = self.split_embedded;
+        options.msgid_index = self.msgid_index.clone();
+        options.buffer_size = self.buffer_size;
+        options.fsync = self.fsync;
+        options.allow_empty = self.allow_empty;
+        options.skip_invalid = self.skip_invalid;
+        options.require_headers = self.require_headers.clone();
+        options.space_check = !self.no_space_check;
+        options.force = self.force;
+        options.follow_symlinks = self.follow_symlinks;
+        options.include = self.include.clone();
+        options.watch = self.watch;
+        options.settle = Duration::from_millis(self.settle_ms);
+        options.details = output::verbosity() > 0 || output::json_logs();
+        options.cancel = Some(interrupt::flag());
+        if let Some(prompter) = &prompter {
+            options.confirm_overwrite = Some(Box::new(|path| prompter.ask(path)));
+        }
+        options.on_event = Some(Box::new(|event| {
+            if porcelain.as_ref().is_some_and(|p| p.record(&event)) {
+                return Ok(());
+            }
+            Ok(self.handle_event(&pb, &watching, output_file, missing, event)?)
+        }));
+
+        let result = eml_to_mbox(&options);
+        pb.finish_and_clear();
+        let summary = match result {
+            Err(error @ MailfmtError::AllFailed { .. }) => return Ok(report_empty_input(&error)),
+            result => result?,
+        };
+        if let Some(path) = &self.error_report {
+            let watch_errors = summary.watch.iter().flat_map(|watch| &watch.errors);
+            write_error_report(
+                path,
+                summary.errors.iter().chain(watch_errors),
+                &summary.invalid,
+            )?;
+        }
+        if let Some(porcelain) = &porcelain {
+            porcelain.done(
+                summary.converted,
+                summary.skipped,
+                summary.errors.len() + missing,
+            );
+        }
+        if let Some(answers) = prompter.as_ref().and_then(Prompter::describe) {
+            self.info(answers);
+        }
+        Ok(self.report(&summary, output_file, missing))
+    }
+
+    /// Count the eml files that would be converted, for --count-only. Any output file given is
+    /// ignored.
+    fn count(&self) -> Result<ExitCode> {
+        let input_dir = match &self.files_from {
+            Some(_) => None,
+            None => self.input_directory.as_deref(),
+        };
+        let (input, _) = self.input(input_dir)?;
+        let mut options = EmlToMboxOptions::new(input, "");
+        options.skip_invalid = self.skip_invalid;
+        options.require_headers = self.require_headers.clone();
+        options.keep_bom = self.keep_bom;
+        options.follow_symlinks = self.follow_symlinks;
+        options.include = self.include.clone();
+        options.cancel = Some(interrupt::flag());
+        Ok(report_count(&count_eml_files(&options)?, "eml files"))
+    }
+
+    /// Print the outcome of the conversion, or of the watching that followed it, and choose the
+    /// exit code for it.
+    fn report(&self, summary: &ConversionSummary, output_file: &Path, missing: usize) -> ExitCode {
+        if summary.interrupted {
+            print_errors(&summary.errors, self.error_report.as_deref());
+            self.info(format!(
+                "Conversion interrupted at eml file {}: {} eml files were processed with {}, {}",
+                summary.converted + summary.errors.len(),
+                summary.converted,
+                output::error_count(self.stream(), summary.errors.len() + missing),
+                if self.append {
+                    format!("and were appended to {output_file:?}")
+                } else {
+                    format!("but no mbox file was written to {output_file:?}")
+                }
+            ));
+            return ExitCode::from(INTERRUPTED_EXIT_CODE);
+        }
+
+        match &summary.watch {
+            Some(watch) => self.info(format!(
+                "Stopped watching: appended {} eml files with {} to {:?}",
+                watch.appended,
+                output::error_count(self.stream(), watch.errors.len()),
+                output_file
+            )),
+            None => self.print_summary(summary, output_file, missing),
+        }
+
+        ExitCode::SUCCESS
+    }
+
+    fn handle_event(
+        &self,
+        pb: &ProgressBar,
+        watching: &Cell<bool>,
+        output_file: &Path,
+        missing: usize,
+        event: Event<'_>,
+    ) -> Result<()> {
+        if output::logged(&event) {
+            return Ok(());
+        }
+        match event {
+            Event::Started { total, position } => {
+                if let ProgressTotal::Messages(count) = total {
+                    pb.set_length(count);
+                }
+                pb.set_position(position);
+                pb.set_style(
+                    ProgressStyle::default_bar()
+                        .template(&format!(
+                            "[{{elapsed_precise}}] {} {{human_pos:>7}}/{{human_len:7}} {{msg}}",
+                            output::progress_bar()
+                        ))
+                        .unwrap()
+                        .progress_chars("#>-"),
+                );
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Processing(file) if !pb.is_hidden() && !pb.is_finished() => {
+                pb.set_message(output::progress_message(String::new(), file))
+            }
+            Event::Processing(_) => {}
+            Event::Progress { position, .. } => pb.set_position(position),
+            Event::Written(path) => {
+                pb.finish_and_clear();
+                if self.print_paths {
+                    print_path(path, self.print0).context("failed to print output path")?;
+                }
+            }
+            Event::Failed { item, error } if watching.get() => {
+                eprintln!(
+                    "{}",
+                    output::error(format!("Error {item}: {}", describe_error(error)))
+                )
+            }
+            // Errors are listed once the conversion is over, and only printed as they happen
+            // when verbose.
+            Event::Failed { item, error } if output::verbosity() > 0 => pb.println(output::error(
+                format!("Error {item}: {}", describe_error(error)),
+            )),
+            Event::Failed { .. } => {}
+            Event::Warning(message) => {
+                eprintln!("{}", output::warning(format!("Warning: {message}")))
+            }
+            Event::Converted(details) => output::log_details(pb, &details),
+            Event::Watching(summary) => {
+                pb.finish_and_clear();
+                watching.set(true);
+                self.print_summary(summary, output_file, missing);
+                // Watching conflicts with --files-from, so the input is always a directory.
+                if let Some(input_dir) = &self.input_directory {
+                    self.info(format!(
+                        "Watching {:?} for new eml files. Press Ctrl-C to stop.",
+                        input_dir
+                    ));
+                }
+            }
+            Event::Appended(path) => self.info(format!("Appended {:?}", path)),
+            Event::SpaceChecked {
+                required,
+                available,
+            } => output::log_space(required, available),
+            Event::Counted { .. }
+            | Event::Scanning { .. }
+            | Event::Scanned { .. }
+            | Event::Uploaded(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Print the outcome of the conversion, before any watching starts.
+    fn print_summary(&self, summary: &ConversionSummary, output_file: &Path, missing: usize) {
+        output::log_summary(summary);
+        print_errors(&summary.errors, self.error_report.as_deref());
+        if self.include.len() > 1 {
+            for (pattern, matches) in self.include.iter().zip(&summary.pattern_matches) {
+                let message = format!("Pattern '{pattern}' matched {matches} files");
+                match matches {
+                    0 => self.info(output::notice(self.stream(), message)),
+                    _ => self.info(message),
+                }
+            }
+        }
+        let unchanged = summary.skipped - summary.invalid.len();
+        if unchanged > 0 {
+            self.info(output::notice(
+                self.stream(),
+                format!("Skipped {unchanged} eml files that are unchanged since the previous run"),
+            ));
+        }
+        if !summary.invalid.is_empty() {
+            self.info(output::notice(
+                self.stream(),
+                format!(
+                    "Skipped {} eml files that failed validation:",
+                    summary.invalid.len()
+                ),
+            ));
+            for invalid in &summary.invalid {
+                self.info(format!("  {}", invalid.error));
+            }
+        }
+        if summary.compressed > 0 {
+            self.info(format!(
+                "Decompressed {} gzip-compressed eml files",
+                summary.compressed
+            ));
+        }
+        if summary.invalid_utf8 > 0 {
+            self.info(describe_invalid_utf8(
+                summary.invalid_utf8,
+                "eml files",
+                self.invalid_utf8,
+            ));
+        }
+        if self.strip_attachments {
+            self.info(describe_stripped(summary));
+        }
+        self.info(describe_throughput(summary));
+        if self.report_duplicates {
+            self.info(describe_duplicates(summary));
+        }
+        if summary.mailboxes > 0 {
+            self.info(match self.split_embedded {
+                true => format!(
+                    "{} eml files held several emails each, which were appended one by one",
+                    summary.mailboxes
+                ),
+                false => format!(
+                    "{} eml files look like mailboxes holding several emails, and were each appended as a single email. Use --split-embedded to split them",
+                    summary.mailboxes
+                ),
+            });
+        }
+        if self.verify_content && !summary.interrupted {
+            self.info(format!(
+                "{} emails were read back from the mbox file and match their eml files",
+                summary.verified
+            ));
+        }
+        if let (Some(path), Some(index)) = (&self.msgid_index, &summary.msgid_index) {
+            self.info(describe_msgid_index(path, index));
+        }
+        if summary.deleted > 0 {
+            self.info(format!(
+                "{} eml files converted by a previous run no longer exist. Their messages remain in the mbox file",
+                summary.deleted
+            ));
+        }
+        if self.append {
+            self.info(format!(
+                "The mbox file held {} messages before this run, and {} were appended to them",
+                summary.preexisting, summary.converted
+            ));
+        }
+        if let Some(counters) = describe_counters(&summary.counters(), "eml files") {
+            self.info(counters);
+        }
+        self.info(format!(
+            "{} of {} eml files completed with {}{}. Output saved to {:?}",
+            if self.append { "Append" } else { "Conversion" },
+            summary.converted,
+            output::error_count(self.stream(), summary.errors.len() + missing),
+            if self.fsync { " (fsync enabled)" } else { "" },
+            output_file
+        ));
+    }
+
+    /// Read the list of eml files given with --files-from. Entries that do not exist are
+    /// reported and counted rather than aborting the conversion.
+    fn read_files_from(&self, source: &Path) -> Result<(Vec<PathBuf>, usize)> {
+        let mut content = Vec::new();
+        if source == Path::new("-") {
+            io::stdin()
+                .lock()
+                .read_to_end(&mut content)
+                .context("failed to read the file list from stdin")?;
+        } else {
+            content = fs::read(source)
+                .with_context(|| format!("failed to read the file list at {source:?}"))?;
+        }
+
+        let separator = if self.null_separated { b'\0' } else { b'\n' };
+        let (mut files, mut missing) = (Vec::new(), 0);
+        for entry in content.split(|&b| b == separator) {
+            let entry = if self.null_separated {
+                entry
+            } else {
+                entry.strip_suffix(b"\r").unwrap_or(entry)
+            };
+            if entry.is_empty() {
+                continue;
+            }
+            let path = paths::from_bytes(entry);
+            if path.is_file() {
+                files.push(path);
+            } else {
+                eprintln!(
+                    "{}",
+                    output::error(format!(
+                        "Error: {path:?} from the file list does not exist or is not a file"
+                    ))
+                );
+                missing += 1;
+            }
+        }
+        if files.is_empty() && missing == 0 {
+            bail!("The file list given with --files-from is empty");
+        }
+        Ok((files, missing))
+    }
+}
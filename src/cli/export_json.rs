@@ -0,0 +1,137 @@
+use crate::cli::{
+    describe_error,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    output::{self, Stream},
+    paths, style_progress_bar, validate_output_file,
+};
+use anyhow::Result;
+use clap::Parser;
+use indicatif::{HumanCount, ProgressBar, ProgressDrawTarget};
+use mailfmt::{AttachmentContent, ConversionSummary, Event, ExportJsonOptions, export_json};
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+/// The schema of the exported objects and examples, shown in the long help and the man page.
+const SCHEMA: &str = "\
+Every line of the output is a JSON object describing one message, with these keys:
+
+  source       {\"index\": n, \"file\": path or null, \"offset\": n or null}
+               The position of the message in the export counting from 0, and the eml file
+               it was read from or the byte offset of its separator line in the mbox.
+  envelope     {\"sender\": string, \"date\": string}, or null for eml files
+               The mbox separator line of the message.
+  headers      {name: string or [string, ...], ...}
+               Every header by its lowercased name, with encoded words decoded. A header
+               given more than once is an array of its values in order.
+  text         string or null
+               The decoded plain text of the message, if it has any.
+  attachments  [{\"filename\": string or null, \"content_type\": string, \"size\": n}, ...]
+               The parts that are attachments, have a file name or are not text, with their
+               decoded size in bytes. With --include-attachments base64, each also has a \"content\" key
+               holding its decoded content encoded as base64.
+
+These keys and their meaning are kept stable.
+
+Examples:
+  Export an mbox, describing attachments without their content:
+    mailfmt export-json archive.mbox -o archive.jsonl
+
+  Export a directory of eml files including their attachments:
+    mailfmt export-json messages/ -o archive.jsonl --include-attachments base64";
+
+/// Export every message in a single .mbox file, or a directory of .eml files, to a JSON Lines file with one object per message.
+#[derive(Parser)]
+#[clap(after_long_help = SCHEMA)]
+pub struct ExportJsonCommand {
+    /// An mbox file, or a directory searched recursively for .eml files.
+    input: PathBuf,
+
+    /// The JSON Lines file to write.
+    #[clap(short = 'o', long = "output", value_name = "PATH", value_parser = validate_output_file)]
+    output_file: PathBuf,
+
+    /// Replace the output file if it already exists.
+    #[clap(long = "overwrite")]
+    overwrite: bool,
+
+    /// Export from the input file even if it does not look like an mbox file.
+    #[clap(long = "force")]
+    force: bool,
+
+    /// Include the content of attachments: 'none' only describes them, while 'base64' embeds their decoded content encoded as base64.
+    #[clap(long = "include-attachments", value_name = "ENCODING", default_value_t = AttachmentContent::None)]
+    include_attachments: AttachmentContent,
+}
+
+impl ExportJsonCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        self.input = paths::expand(&self.input).map_err(anyhow::Error::msg)?;
+        self.output_file = paths::expand(&self.output_file).map_err(anyhow::Error::msg)?;
+        Ok(())
+    }
+
+    pub fn run(&self) -> Result<ExitCode> {
+        let pb = ProgressBar::hidden();
+        let mut options = ExportJsonOptions::new(&self.input, &self.output_file);
+        options.overwrite = self.overwrite;
+        options.force = self.force;
+        options.attachments = self.include_attachments;
+        options.cancel = Some(interrupt::flag());
+        options.on_event = Some(Box::new(|event| {
+            Self::handle_event(&pb, event);
+            Ok(())
+        }));
+
+        let result = export_json(&options);
+        pb.finish_and_clear();
+        Ok(self.report(&result?))
+    }
+
+    /// Print the outcome of the export and choose the exit code for it.
+    fn report(&self, summary: &ConversionSummary) -> ExitCode {
+        if summary.interrupted {
+            println!(
+                "Export interrupted after {} emails, nothing was written",
+                summary.converted + summary.errors.len()
+            );
+            return ExitCode::from(INTERRUPTED_EXIT_CODE);
+        }
+        println!(
+            "Export of {} emails completed with {}. Output saved to {:?}",
+            summary.converted,
+            output::error_count(Stream::Stdout, summary.errors.len()),
+            self.output_file
+        );
+        ExitCode::SUCCESS
+    }
+
+    fn handle_event(pb: &ProgressBar, event: Event<'_>) {
+        if output::logged(&event) {
+            return;
+        }
+        match event {
+            Event::Started { total, position } => {
+                style_progress_bar(pb, total);
+                pb.set_position(position);
+                pb.set_message("0 emails processed");
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Progress {
+                position,
+                processed,
+            } => {
+                pb.set_position(position);
+                pb.set_message(format!("{} emails processed", HumanCount(processed as u64)));
+            }
+            Event::Failed { item, error } => pb.println(output::error(format!(
+                "Error {item}: {}",
+                describe_error(error)
+            ))),
+            Event::Warning(message) => {
+                pb.suspend(|| eprintln!("{}", output::warning(format!("Warning: {message}"))))
+            }
+            _ => {}
+        }
+    }
+}
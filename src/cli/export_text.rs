@@ -0,0 +1,141 @@
+use crate::cli::{
+    describe_error,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    output::{self, Stream},
+    paths, style_progress_bar,
+};
+use anyhow::Result;
+use clap::Parser;
+use indicatif::{HumanCount, ProgressBar, ProgressDrawTarget};
+use mailfmt::{ConversionSummary, Event, HtmlText, MboxToEmlOptions, mbox_to_eml};
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+/// Examples shown in the long help and the man page.
+const EXAMPLES: &str = "\
+Examples:
+  Export the text of every message of an mbox:
+    mailfmt export-text archive.mbox text/
+  Prefer the HTML of messages over their plain text:
+    mailfmt export-text archive.mbox text/ --html always";
+
+/// Export the main headers and decoded plain text of every message in a single .mbox file to a directory of .txt files.
+#[derive(Parser)]
+#[clap(after_long_help = EXAMPLES)]
+pub struct ExportTextCommand {
+    input_file: PathBuf,
+
+    output_directory: PathBuf,
+
+    /// Replace any existing files in the given directory with new ones if they overlap. Will not delete files that do not overlap.
+    #[clap(long = "overwrite")]
+    overwrite: bool,
+
+    /// Export from the input file even if it does not look like an mbox file.
+    #[clap(long = "force")]
+    force: bool,
+
+    /// Continue an interrupted export into the same output directory from the last progress recorded inside of it. The input file must be unchanged.
+    #[clap(long = "resume")]
+    resume: bool,
+
+    /// When to export the text of the HTML part of an email, with tags dropped and links followed by their target: 'fallback' when it has no plain text, 'always' even if it has, and 'never', writing a placeholder for emails without plain text.
+    #[clap(long = "html", value_name = "MODE", default_value_t = HtmlText::Fallback)]
+    html: HtmlText,
+}
+
+impl ExportTextCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        self.input_file = paths::expand(&self.input_file).map_err(anyhow::Error::msg)?;
+        self.output_directory =
+            paths::expand(&self.output_directory).map_err(anyhow::Error::msg)?;
+        Ok(())
+    }
+
+    pub fn run(&self) -> Result<ExitCode> {
+        let pb = ProgressBar::hidden();
+        let mut options = MboxToEmlOptions::new(&self.input_file, &self.output_directory);
+        options.overwrite = self.overwrite;
+        options.force = self.force;
+        options.resume = self.resume;
+        options.export_text = true;
+        options.html_text = self.html;
+        options.cancel = Some(interrupt::flag());
+        options.on_event = Some(Box::new(|event| {
+            Self::handle_event(&pb, event);
+            Ok(())
+        }));
+
+        let result = mbox_to_eml(&options);
+        pb.finish_and_clear();
+        Ok(self.report(&result?))
+    }
+
+    /// Print the outcome of the export and choose the exit code for it.
+    fn report(&self, summary: &ConversionSummary) -> ExitCode {
+        if summary.interrupted {
+            println!(
+                "Export interrupted: {} emails exported with {}. Partial output saved to {:?}. Use --resume to continue.",
+                summary.converted,
+                output::error_count(Stream::Stdout, summary.errors.len()),
+                self.output_directory
+            );
+            return ExitCode::from(INTERRUPTED_EXIT_CODE);
+        }
+        if summary.from_html > 0 {
+            println!(
+                "{} emails were exported from their HTML part",
+                summary.from_html
+            );
+        }
+        if summary.without_text > 0 {
+            println!(
+                "{}",
+                output::notice(
+                    Stream::Stdout,
+                    format!(
+                        "{} emails had no text that could be exported, so a placeholder was written for them",
+                        summary.without_text
+                    )
+                )
+            );
+        }
+        println!(
+            "Export of {} emails completed with {}. Output saved to {:?}",
+            summary.converted,
+            output::error_count(Stream::Stdout, summary.errors.len()),
+            self.output_directory
+        );
+        ExitCode::SUCCESS
+    }
+
+    fn handle_event(pb: &ProgressBar, event: Event<'_>) {
+        if output::logged(&event) {
+            return;
+        }
+        match event {
+            Event::Started { total, position } => {
+                style_progress_bar(pb, total);
+                pb.set_position(position);
+                pb.set_message("0 emails processed");
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Progress {
+                position,
+                processed,
+            } => {
+                pb.set_position(position);
+                pb.set_message(format!("{} emails processed", HumanCount(processed as u64)));
+            }
+            Event::Failed { item, error } => pb.println(output::error(format!(
+                "Error {item}: {}",
+                describe_error(error)
+            ))),
+            Event::Warning(message) => {
+                pb.suspend(|| eprintln!("{}", output::warning(format!("Warning: {message}"))))
+            }
+            _ => {}
+        }
+    }
+}
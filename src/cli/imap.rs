@@ -0,0 +1,632 @@
+use crate::cli::{
+    describe_error,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    output::{self, Stream},
+    paths, print_errors, style_progress_bar,
+};
+use anyhow::{Context, Result, bail};
+use chrono::NaiveDate;
+use clap::Parser;
+use indicatif::{HumanBytes, HumanCount, ProgressBar, ProgressDrawTarget};
+use mailfmt::{
+    ConversionSummary, Event, FetchOutput, FromLineStyle, ImapFetchOptions, ImapMailbox,
+    ImapSecurity, ImapUploadOptions, MailfmtError, MboxDialect, fetch_from_imap, upload_to_imap,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+/// The environment variable holding the password to log in with.
+const PASSWORD_VARIABLE: &str = "MAILFMT_IMAP_PASSWORD";
+
+/// Where credentials come from, shown in the long help and the man page.
+const CREDENTIALS: &str = "\
+The password is read from the first of these that has one:
+  --password-file     the first line of the file
+  MAILFMT_IMAP_PASSWORD
+  --netrc             the entry for the server's host, or the default entry, of a netrc file,
+                      ~/.netrc if not given. Its login is used when the URL names no user.
+
+imaps:// URLs connect with TLS. imap:// URLs connect without it unless --starttls upgrades the
+connection, and only log in to a server on this machine unless --insecure is given, as the
+password would be sent unencrypted.";
+
+/// Examples of uploads shown in the long help and the man page.
+const UPLOAD_EXAMPLES: &str = "\
+Examples:
+  List what would be uploaded from a directory of eml files:
+    mailfmt eml-to-imap export/ --server imaps://alice@imap.example.com/Archive --dry-run
+
+  Upload an mbox to a mailbox, reading the password from a file:
+    mailfmt mbox-to-imap archive.mbox --server imaps://alice@imap.example.com/Archive --password-file ~/.imap-password
+
+  Upload to a local bridge that only offers STARTTLS:
+    mailfmt mbox-to-imap archive.mbox --server imap://alice@127.0.0.1:1143/Archive --starttls";
+
+/// Examples of fetches shown in the long help and the man page.
+const FETCH_EXAMPLES: &str = "\
+Examples:
+  Fetch a mailbox into an mbox, and later fetch only what arrived since:
+    mailfmt imap-to-mbox imaps://alice@imap.example.com/INBOX inbox.mbox --password-file ~/.imap-password
+
+  Fetch the messages received in 2023 as eml files:
+    mailfmt imap-to-eml imaps://alice@imap.example.com/Archive archive/ --since 2023-01-01 --before 2024-01-01
+
+  Fetch from a local bridge that only offers STARTTLS:
+    mailfmt imap-to-mbox imap://alice@127.0.0.1:1143/INBOX inbox.mbox --starttls";
+
+/// The arguments of the eml-to-imap and mbox-to-imap commands.
+#[derive(Parser)]
+#[clap(after_long_help = format!("{CREDENTIALS}\n\n{UPLOAD_EXAMPLES}"))]
+pub struct UploadCommand {
+    input: PathBuf,
+
+    /// The mailbox to upload to, as an imaps:// or imap:// URL such as imaps://alice@imap.example.com/Archive. The mailbox is created if it does not exist, and is INBOX if the URL names none.
+    #[clap(long = "server", value_name = "URL")]
+    server: ImapMailbox,
+
+    /// Upgrade the plain connection of an imap:// URL to TLS with STARTTLS before logging in.
+    #[clap(long = "starttls")]
+    starttls: bool,
+
+    /// Log in over a plain imap:// connection to a server that is not on this machine, sending the password unencrypted.
+    #[clap(long = "insecure")]
+    insecure: bool,
+
+    /// Read the password from the first line of this file.
+    #[clap(long = "password-file", value_name = "PATH")]
+    password_file: Option<PathBuf>,
+
+    /// Read the login and password from this netrc file instead of ~/.netrc, when neither --password-file nor MAILFMT_IMAP_PASSWORD give a password.
+    #[clap(long = "netrc", value_name = "PATH")]
+    netrc: Option<PathBuf>,
+
+    /// How many messages to read from the input before uploading them one after another. Progress is reported once a batch is uploaded.
+    #[clap(long = "batch-size", value_name = "COUNT", default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..))]
+    batch_size: u16,
+
+    /// How many times to connect again when the connection breaks or the server is unavailable, waiting 1s, 2s, 4s and so on in between. Messages the server had not confirmed are sent again.
+    #[clap(long = "retries", value_name = "COUNT", default_value_t = 3)]
+    retries: u32,
+
+    /// How long to wait for the server to answer once connected before taking the connection as broken.
+    #[clap(long = "timeout", value_name = "SECONDS", default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: u64,
+
+    /// List the messages that would be uploaded, with their date and flags, without connecting to the server.
+    #[clap(long = "dry-run")]
+    dry_run: bool,
+
+    /// Upload from the input file even if it does not look like an mbox file.
+    #[clap(long = "force")]
+    force: bool,
+}
+
+/// What an upload reads from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum UploadInput {
+    Eml,
+    Mbox,
+}
+
+impl UploadCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        self.input = paths::expand(&self.input).map_err(anyhow::Error::msg)?;
+        if let Some(password_file) = &self.password_file {
+            self.password_file = Some(paths::expand(password_file).map_err(anyhow::Error::msg)?);
+        }
+        if let Some(netrc) = &self.netrc {
+            self.netrc = Some(paths::expand(netrc).map_err(anyhow::Error::msg)?);
+        }
+        Ok(())
+    }
+
+    pub fn run(&self, kind: UploadInput) -> Result<ExitCode> {
+        match kind {
+            UploadInput::Eml if !self.input.is_dir() => {
+                bail!("{:?} is not a directory of eml files", self.input)
+            }
+            UploadInput::Mbox if !self.input.is_file() => {
+                bail!("{:?} is not an mbox file", self.input)
+            }
+            _ => {}
+        }
+
+        let pb = ProgressBar::hidden();
+        let server = with_starttls(&self.server, self.starttls)?;
+        let mut options = ImapUploadOptions::new(&self.input, server.clone());
+        options.insecure = self.insecure;
+        options.force = self.force;
+        options.batch_size = self.batch_size.into();
+        options.retries = self.retries;
+        options.timeout = Duration::from_secs(self.timeout);
+        options.dry_run = self.dry_run;
+        if !self.dry_run {
+            (options.user, options.password) = log_in_details(
+                &server,
+                self.insecure,
+                self.password_file.as_deref(),
+                self.netrc.as_deref(),
+            )?;
+        }
+        options.cancel = Some(interrupt::flag());
+        options.on_event = Some(Box::new(|event| {
+            Self::handle_event(&pb, event);
+            Ok(())
+        }));
+
+        let result = upload_to_imap(&options);
+        pb.finish_and_clear();
+        let summary = result?;
+        output::log_summary(&summary);
+        print_errors(&summary.errors, None);
+        Ok(self.report(&summary))
+    }
+
+    /// Print the outcome of the upload and choose the exit code for it.
+    fn report(&self, summary: &ConversionSummary) -> ExitCode {
+        if self.dry_run {
+            println!(
+                "{} emails would be uploaded to {}",
+                summary.converted, self.server
+            );
+            return ExitCode::SUCCESS;
+        }
+        if summary.mailbox_created {
+            println!(
+                "{}",
+                output::notice(
+                    Stream::Stdout,
+                    format!("Created the mailbox {:?}", self.server.mailbox)
+                )
+            );
+        }
+        if summary.reconnects > 0 {
+            println!(
+                "{}",
+                output::notice(
+                    Stream::Stdout,
+                    format!(
+                        "Connected again {} times after the connection broke",
+                        summary.reconnects
+                    )
+                )
+            );
+        }
+        if summary.interrupted {
+            println!(
+                "Upload interrupted after {} emails, those uploaded stay in the mailbox",
+                summary.converted
+            );
+            return ExitCode::from(INTERRUPTED_EXIT_CODE);
+        }
+        println!(
+            "Upload of {} emails ({}) to {} completed with {}",
+            summary.converted,
+            HumanBytes(summary.bytes_written),
+            self.server,
+            output::error_count(Stream::Stdout, summary.errors.len())
+        );
+        ExitCode::SUCCESS
+    }
+
+    fn handle_event(pb: &ProgressBar, event: Event<'_>) {
+        if output::logged(&event) {
+            return;
+        }
+        match event {
+            Event::Started { total, position } => {
+                style_progress_bar(pb, total);
+                pb.set_position(position);
+                pb.set_message("0 emails processed");
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Progress {
+                position,
+                processed,
+            } => {
+                pb.set_position(position);
+                pb.set_message(format!("{} emails processed", HumanCount(processed as u64)));
+            }
+            // A dry run lists every message on stdout.
+            Event::Uploaded(details) if !details.uploaded => {
+                let source = match (&details.file, details.offset) {
+                    (Some(file), _) => format!("{file:?}"),
+                    (None, Some(offset)) => format!("email {} at byte {offset}", details.index),
+                    (None, None) => format!("email {}", details.index),
+                };
+                let line = format!(
+                    "{source}: {:?}, {}, {}, {}",
+                    details.subject.as_deref().unwrap_or_default(),
+                    details.internal_date.as_deref().unwrap_or("no date"),
+                    match details.flags.is_empty() {
+                        true => "no flags".to_string(),
+                        false => details.flags.join(" "),
+                    },
+                    HumanBytes(details.size as u64)
+                );
+                pb.suspend(|| println!("{line}"));
+            }
+            Event::Failed { item, error } => pb.println(output::error(format!(
+                "Error {item}: {}",
+                describe_error(error)
+            ))),
+            Event::Warning(message) => {
+                pb.suspend(|| eprintln!("{}", output::warning(format!("Warning: {message}"))))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The arguments of the imap-to-mbox and imap-to-eml commands.
+#[derive(Parser)]
+#[clap(after_long_help = format!("{CREDENTIALS}\n\n{FETCH_EXAMPLES}"))]
+pub struct FetchCommand {
+    /// The mailbox to fetch, as an imaps:// or imap:// URL such as imaps://alice@imap.example.com/INBOX. It is INBOX if the URL names none.
+    #[clap(value_name = "URL")]
+    server: ImapMailbox,
+
+    /// The mbox file to write, or the directory to write eml files to. A later fetch into it only fetches the messages added to the mailbox since, which also continues an interrupted fetch.
+    output: PathBuf,
+
+    /// Upgrade the plain connection of an imap:// URL to TLS with STARTTLS before logging in.
+    #[clap(long = "starttls")]
+    starttls: bool,
+
+    /// Log in over a plain imap:// connection to a server that is not on this machine, sending the password unencrypted.
+    #[clap(long = "insecure")]
+    insecure: bool,
+
+    /// Start over, replacing the mbox file or eml files of the same name in the directory and forgetting what earlier fetches fetched.
+    #[clap(long = "overwrite")]
+    overwrite: bool,
+
+    /// Only fetch messages the server received on or after this day, given as YYYY-MM-DD.
+    #[clap(long = "since", value_name = "DATE", value_parser = validate_day)]
+    since: Option<NaiveDate>,
+
+    /// Only fetch messages the server received before this day, given as YYYY-MM-DD.
+    #[clap(long = "before", value_name = "DATE", value_parser = validate_day)]
+    before: Option<NaiveDate>,
+
+    /// Read the password from the first line of this file.
+    #[clap(long = "password-file", value_name = "PATH")]
+    password_file: Option<PathBuf>,
+
+    /// Read the login and password from this netrc file instead of ~/.netrc, when neither --password-file nor MAILFMT_IMAP_PASSWORD give a password.
+    #[clap(long = "netrc", value_name = "PATH")]
+    netrc: Option<PathBuf>,
+
+    /// How many messages to fetch with every request. What was fetched is recorded after every batch.
+    #[clap(long = "batch-size", value_name = "COUNT", default_value_t = 50, value_parser = clap::value_parser!(u16).range(1..))]
+    batch_size: u16,
+
+    /// How many times to connect again when the connection breaks or the server is unavailable, waiting 1s, 2s, 4s and so on in between. The batch being fetched is fetched again.
+    #[clap(long = "retries", value_name = "COUNT", default_value_t = 3)]
+    retries: u32,
+
+    /// How long to wait for the server to answer once connected before taking the connection as broken.
+    #[clap(long = "timeout", value_name = "SECONDS", default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: u64,
+
+    /// The mbox dialect to write: 'mboxrd' quotes body lines starting with any number of '>' followed by 'From ' reversibly, 'mboxo' only quotes lines starting with 'From ', 'mboxcl' quotes like mboxo and adds a Content-Length header, and 'mboxcl2' only adds the Content-Length header.
+    #[clap(long = "dialect", default_value_t = MboxDialect::Mboxrd)]
+    dialect: MboxDialect,
+
+    /// How to write the separator line starting every message: 'sender' writes 'From ' followed by the sender address and the date, and 'thunderbird' writes 'From - ' followed by the date, as Thunderbird does.
+    #[clap(long = "from-line-style", value_name = "STYLE", default_value_t = FromLineStyle::Sender)]
+    from_line_style: FromLineStyle,
+}
+
+/// What a fetch writes to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FetchInto {
+    Mbox,
+    Eml,
+}
+
+impl FetchCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        self.output = paths::expand(&self.output).map_err(anyhow::Error::msg)?;
+        if let Some(password_file) = &self.password_file {
+            self.password_file = Some(paths::expand(password_file).map_err(anyhow::Error::msg)?);
+        }
+        if let Some(netrc) = &self.netrc {
+            self.netrc = Some(paths::expand(netrc).map_err(anyhow::Error::msg)?);
+        }
+        Ok(())
+    }
+
+    pub fn run(&self, kind: FetchInto) -> Result<ExitCode> {
+        let output = match kind {
+            FetchInto::Mbox => FetchOutput::Mbox(self.output.clone()),
+            FetchInto::Eml => FetchOutput::EmlDirectory(self.output.clone()),
+        };
+        let pb = ProgressBar::hidden();
+        let server = with_starttls(&self.server, self.starttls)?;
+        let mut options = ImapFetchOptions::new(server.clone(), output);
+        (options.user, options.password) = log_in_details(
+            &server,
+            self.insecure,
+            self.password_file.as_deref(),
+            self.netrc.as_deref(),
+        )?;
+        options.insecure = self.insecure;
+        options.overwrite = self.overwrite;
+        options.since = self.since;
+        options.before = self.before;
+        options.batch_size = self.batch_size.into();
+        options.retries = self.retries;
+        options.timeout = Duration::from_secs(self.timeout);
+        options.dialect = self.dialect;
+        options.from_line_style = self.from_line_style;
+        options.cancel = Some(interrupt::flag());
+        options.on_event = Some(Box::new(|event| {
+            Self::handle_event(&pb, event);
+            Ok(())
+        }));
+
+        let result = fetch_from_imap(&options);
+        pb.finish_and_clear();
+        let summary = match result {
+            // Only the state of the earlier fetches stands in the way of fetching anew.
+            Err(error @ MailfmtError::InvalidState { .. }) => bail!(
+                "{}. Use the --overwrite flag to fetch the mailbox again from the start.",
+                describe_error(&error)
+            ),
+            result => result?,
+        };
+        output::log_summary(&summary);
+        print_errors(&summary.errors, None);
+        Ok(self.report(&summary))
+    }
+
+    /// Print the outcome of the fetch and choose the exit code for it.
+    fn report(&self, summary: &ConversionSummary) -> ExitCode {
+        if let Some(uid) = summary.continued_after_uid {
+            println!(
+                "{}",
+                output::notice(
+                    Stream::Stdout,
+                    format!("Continued an earlier fetch after the message with UID {uid}")
+                )
+            );
+        }
+        if summary.reconnects > 0 {
+            println!(
+                "{}",
+                output::notice(
+                    Stream::Stdout,
+                    format!(
+                        "Connected again {} times after the connection broke",
+                        summary.reconnects
+                    )
+                )
+            );
+        }
+        if summary.interrupted {
+            println!(
+                "Fetch interrupted after {} emails. Fetch into {:?} again to continue.",
+                summary.converted, self.output
+            );
+            return ExitCode::from(INTERRUPTED_EXIT_CODE);
+        }
+        println!(
+            "Fetch of {} emails ({}) from {} completed with {}. Output saved to {:?}",
+            summary.converted,
+            HumanBytes(summary.bytes_written),
+            self.server,
+            output::error_count(Stream::Stdout, summary.errors.len()),
+            self.output
+        );
+        ExitCode::SUCCESS
+    }
+
+    fn handle_event(pb: &ProgressBar, event: Event<'_>) {
+        if output::logged(&event) {
+            return;
+        }
+        match event {
+            Event::Started { total, position } => {
+                style_progress_bar(pb, total);
+                pb.set_position(position);
+                pb.set_message("0 emails fetched");
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Progress {
+                position,
+                processed,
+            } => {
+                pb.set_position(position);
+                pb.set_message(format!("{} emails fetched", HumanCount(processed as u64)));
+            }
+            Event::Failed { item, error } => pb.println(output::error(format!(
+                "Error {item}: {}",
+                describe_error(error)
+            ))),
+            Event::Warning(message) => {
+                pb.suspend(|| eprintln!("{}", output::warning(format!("Warning: {message}"))))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A day given as YYYY-MM-DD.
+fn validate_day(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| format!("'{s}' is not a day given as YYYY-MM-DD"))
+}
+
+/// The user and password to log in with, from the URL, --password-file,
+/// MAILFMT_IMAP_PASSWORD or a netrc file.
+fn credentials(
+    server: &ImapMailbox,
+    password_file: Option<&Path>,
+    netrc: Option<&Path>,
+) -> Result<(String, String)> {
+    let mut user = server.user.clone();
+    let password = match password_file {
+        Some(path) => Some(
+            fs::read_to_string(path)
+                .with_context(|| format!("failed to read the password file {path:?}"))?
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        None => std::env::var(PASSWORD_VARIABLE).ok(),
+    };
+    let password = match password {
+        Some(password) => password,
+        None => {
+            let netrc = match netrc {
+                Some(path) => Some(path.to_path_buf()),
+                None => paths::expand(Path::new("~/.netrc"))
+                    .ok()
+                    .filter(|path| path.is_file()),
+            };
+            let entry = match &netrc {
+                Some(path) => {
+                    let text = fs::read_to_string(path)
+                        .with_context(|| format!("failed to read the netrc file {path:?}"))?;
+                    netrc_entry(&text, &server.host)
+                }
+                None => None,
+            };
+            match entry {
+                Some(entry) if entry.password.is_some() => {
+                    if user.is_none() {
+                        user = entry.login;
+                    }
+                    entry.password.unwrap_or_default()
+                }
+                _ => bail!(
+                    "no password for {} was found. Give it with --password-file, {PASSWORD_VARIABLE} or a netrc file",
+                    server.host
+                ),
+            }
+        }
+    };
+    match user {
+        Some(user) => Ok((user, password)),
+        None => {
+            let example = ImapMailbox {
+                user: Some("alice".to_string()),
+                ..server.clone()
+            };
+            bail!(
+                "no user to log in as was found. Name it in the URL, as in {example}, or in a netrc file"
+            )
+        }
+    }
+}
+
+/// The mailbox of `server`, with its plain connection upgraded by STARTTLS if `starttls` is
+/// given.
+fn with_starttls(server: &ImapMailbox, starttls: bool) -> Result<ImapMailbox> {
+    let mut server = server.clone();
+    match server.security {
+        _ if !starttls => {}
+        ImapSecurity::Tls => bail!(
+            "--starttls upgrades the plain connection of an imap:// URL, while {server} connects with TLS from the start"
+        ),
+        ImapSecurity::StartTls | ImapSecurity::Plain => server.security = ImapSecurity::StartTls,
+    }
+    Ok(server)
+}
+
+/// The user and password to log in to `server` with, warning that they are sent unencrypted
+/// to a server elsewhere when `insecure` allows it.
+fn log_in_details(
+    server: &ImapMailbox,
+    insecure: bool,
+    password_file: Option<&Path>,
+    netrc: Option<&Path>,
+) -> Result<(String, String)> {
+    let credentials = credentials(server, password_file, netrc)?;
+    if insecure && server.security == ImapSecurity::Plain && !server.is_loopback() {
+        eprintln!(
+            "{}",
+            output::warning(format!(
+                "Warning: the password is sent to {} unencrypted.",
+                server.host
+            ))
+        );
+    }
+    Ok(credentials)
+}
+
+/// An entry of a netrc file.
+#[derive(Default)]
+struct NetrcEntry {
+    login: Option<String>,
+    password: Option<String>,
+}
+
+/// The entry of a netrc file for `host`, or its default entry. Macro definitions are skipped.
+fn netrc_entry(text: &str, host: &str) -> Option<NetrcEntry> {
+    let (mut matched, mut default) = (None, None);
+    let mut current: Option<(bool, NetrcEntry)> = None;
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let mut tokens = line.split_whitespace();
+        while let Some(token) = tokens.next() {
+            match token {
+                "machine" | "default" => {
+                    if let Some((is_default, entry)) = current.take() {
+                        match is_default {
+                            true => default = default.or(Some(entry)),
+                            false => matched = matched.or(Some(entry)),
+                        }
+                    }
+                    current = match token {
+                        "default" => Some((true, NetrcEntry::default())),
+                        _ if tokens
+                            .next()
+                            .is_some_and(|name| name.eq_ignore_ascii_case(host)) =>
+                        {
+                            Some((false, NetrcEntry::default()))
+                        }
+                        // The login and password of another machine are passed over.
+                        _ => None,
+                    };
+                }
+                "login" | "password" => {
+                    let value = tokens.next().map(str::to_string);
+                    if let Some((_, entry)) = &mut current {
+                        match token {
+                            "login" => entry.login = value,
+                            _ => entry.password = value,
+                        }
+                    }
+                }
+                // A macro runs until the next blank line.
+                "macdef" => {
+                    for line in lines.by_ref() {
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                    }
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Some((is_default, entry)) = current {
+        match is_default {
+            true => default = default.or(Some(entry)),
+            false => matched = matched.or(Some(entry)),
+        }
+    }
+    matched.or(default)
+}
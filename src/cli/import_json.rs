@@ -0,0 +1,130 @@
+use crate::cli::{
+    describe_error,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    output::{self, Stream},
+    paths, style_progress_bar,
+};
+use anyhow::Result;
+use clap::Parser;
+use indicatif::{HumanCount, ProgressBar, ProgressDrawTarget};
+use mailfmt::{
+    ConversionSummary, Event, FromLineStyle, ImportJsonOptions, ImportOutput, MboxDialect,
+    import_json,
+};
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+/// Examples shown in the long help and the man page.
+const EXAMPLES: &str = "\
+Examples:
+  Rebuild an mbox from an export:
+    mailfmt import-json archive.jsonl -o archive.mbox
+
+  Rebuild a directory of eml files instead:
+    mailfmt import-json archive.jsonl -o messages/";
+
+/// Rebuild the messages in a JSON Lines file written by export-json as a single .mbox file or a directory of .eml files.
+#[derive(Parser)]
+#[clap(after_long_help = EXAMPLES)]
+pub struct ImportJsonCommand {
+    input_file: PathBuf,
+
+    /// The mbox file to write, or the directory to write eml files to. It is taken to be a directory if it already is one or ends with a '/'.
+    #[clap(short = 'o', long = "output", value_name = "PATH")]
+    output: PathBuf,
+
+    /// Replace the mbox file if it already exists, or any existing eml files in the directory if they overlap. Will not delete files that do not overlap.
+    #[clap(long = "overwrite")]
+    overwrite: bool,
+
+    /// The mbox dialect to write: 'mboxrd' quotes body lines starting with any number of '>' followed by 'From ' reversibly, 'mboxo' only quotes lines starting with 'From ', 'mboxcl' quotes like mboxo and adds a Content-Length header, and 'mboxcl2' only adds the Content-Length header.
+    #[clap(long = "dialect", default_value_t = MboxDialect::Mboxrd)]
+    dialect: MboxDialect,
+
+    /// How to write the separator line starting every message: 'sender' writes 'From ' followed by the sender address and the date, and 'thunderbird' writes 'From - ' followed by the date, as Thunderbird does.
+    #[clap(long = "from-line-style", value_name = "STYLE", default_value_t = FromLineStyle::Sender)]
+    from_line_style: FromLineStyle,
+}
+
+impl ImportJsonCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        self.input_file = paths::expand(&self.input_file).map_err(anyhow::Error::msg)?;
+        self.output = paths::expand(&self.output).map_err(anyhow::Error::msg)?;
+        Ok(())
+    }
+
+    pub fn run(&self) -> Result<ExitCode> {
+        let output = if self.output.is_dir() || self.output.to_string_lossy().ends_with(['/', '\\'])
+        {
+            ImportOutput::EmlDirectory(self.output.clone())
+        } else {
+            ImportOutput::Mbox(self.output.clone())
+        };
+        let pb = ProgressBar::hidden();
+        let mut options = ImportJsonOptions::new(&self.input_file, output);
+        options.overwrite = self.overwrite;
+        options.dialect = self.dialect;
+        options.from_line_style = self.from_line_style;
+        options.cancel = Some(interrupt::flag());
+        options.on_event = Some(Box::new(|event| {
+            Self::handle_event(&pb, event);
+            Ok(())
+        }));
+
+        let result = import_json(&options);
+        pb.finish_and_clear();
+        Ok(self.report(&result?))
+    }
+
+    /// Print the outcome of the import and choose the exit code for it.
+    fn report(&self, summary: &ConversionSummary) -> ExitCode {
+        if summary.interrupted {
+            println!(
+                "Import interrupted after {} emails with {}. An mbox file is not written, while eml files already written are kept.",
+                summary.converted,
+                output::error_count(Stream::Stdout, summary.errors.len())
+            );
+            return ExitCode::from(INTERRUPTED_EXIT_CODE);
+        }
+        println!(
+            "Import of {} emails completed with {}. Output saved to {:?}",
+            summary.converted,
+            output::error_count(Stream::Stdout, summary.errors.len()),
+            self.output
+        );
+        ExitCode::SUCCESS
+    }
+
+    fn handle_event(pb: &ProgressBar, event: Event<'_>) {
+        if output::logged(&event) {
+            return;
+        }
+        match event {
+            Event::Started { total, position } => {
+                style_progress_bar(pb, total);
+                pb.set_position(position);
+                pb.set_message("0 records processed");
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Progress {
+                position,
+                processed,
+            } => {
+                pb.set_position(position);
+                pb.set_message(format!(
+                    "{} records processed",
+                    HumanCount(processed as u64)
+                ));
+            }
+            Event::Failed { item, error } => pb.println(output::error(format!(
+                "Error {item}: {}",
+                describe_error(error)
+            ))),
+            Event::Warning(message) => {
+                pb.suspend(|| eprintln!("{}", output::warning(format!("Warning: {message}"))))
+            }
+            _ => {}
+        }
+    }
+}
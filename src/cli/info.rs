@@ -0,0 +1,172 @@
+use crate::cli::{
+    describe_error,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    output, paths, style_progress_bar,
+};
+use anyhow::Result;
+use clap::Parser;
+use indicatif::{HumanCount, ProgressBar, ProgressDrawTarget};
+use mailfmt::{Event, InfoOptions, MboxInfo, mbox_info};
+use serde_json::{Value, json};
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+/// Examples shown in the long help and the man page.
+const EXAMPLES: &str = "\
+Examples:
+  Show the charsets declared by the messages of an mbox:
+    mailfmt info archive.mbox
+
+  Print the report as JSON for a script:
+    mailfmt info archive.mbox --json";
+
+/// Report on what an .mbox file contains, such as the charsets declared by its messages and how many are marked as deleted.
+#[derive(Parser)]
+#[clap(after_long_help = EXAMPLES)]
+pub struct InfoCommand {
+    input_file: PathBuf,
+
+    /// Do not decode MIME parts larger than this many bytes to check whether they are valid in their declared charset.
+    #[clap(long = "max-body-size", value_name = "BYTES", default_value_t = 1024 * 1024)]
+    max_body_size: u64,
+
+    /// Inspect the input file even if it does not look like an mbox file.
+    #[clap(long = "force")]
+    force: bool,
+
+    /// Print the report to stdout as JSON instead of as a table.
+    #[clap(long = "json")]
+    json: bool,
+}
+
+impl InfoCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        self.input_file = paths::expand(&self.input_file).map_err(anyhow::Error::msg)?;
+        Ok(())
+    }
+
+    pub fn run(&self) -> Result<ExitCode> {
+        let pb = ProgressBar::hidden();
+        let mut options = InfoOptions::new(&self.input_file);
+        options.max_body_size = self.max_body_size;
+        options.force = self.force;
+        options.cancel = Some(interrupt::flag());
+        options.on_event = Some(Box::new(|event| {
+            Self::handle_event(&pb, event);
+            Ok(())
+        }));
+
+        let result = mbox_info(&options);
+        pb.finish_and_clear();
+        let info = result?;
+
+        if self.json {
+            println!("{:#}", Self::to_json(&info));
+        } else {
+            self.print_table(&info);
+        }
+        if info.interrupted {
+            eprintln!(
+                "Inspection interrupted after {} messages, the report is incomplete",
+                info.messages
+            );
+            return Ok(ExitCode::from(INTERRUPTED_EXIT_CODE));
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn handle_event(pb: &ProgressBar, event: Event<'_>) {
+        if output::logged(&event) {
+            return;
+        }
+        match event {
+            Event::Started { total, position } => {
+                style_progress_bar(pb, total);
+                pb.set_position(position);
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Progress {
+                position,
+                processed,
+            } => {
+                pb.set_position(position);
+                pb.set_message(format!("{} emails inspected", HumanCount(processed as u64)));
+            }
+            Event::Failed { item, error } => pb.println(output::error(format!(
+                "Error {item}: {}",
+                describe_error(error)
+            ))),
+            Event::Warning(message) => {
+                eprintln!("{}", output::warning(format!("Warning: {message}")))
+            }
+            _ => {}
+        }
+    }
+
+    fn print_table(&self, info: &MboxInfo) {
+        let charsets = &info.charsets;
+        println!(
+            "{} emails inspected with {} errors",
+            info.messages,
+            info.errors.len()
+        );
+        println!();
+
+        let width = charsets
+            .declared
+            .iter()
+            .map(|usage| usage.charset.len())
+            .chain(["Charset".len()])
+            .max()
+            .unwrap_or_default();
+        println!("{:width$}  {:>8}  {:>8}", "Charset", "Emails", "Parts");
+        for usage in &charsets.declared {
+            println!(
+                "{:width$}  {:>8}  {:>8}",
+                usage.charset, usage.messages, usage.parts
+            );
+        }
+        println!();
+
+        println!("Emails declaring no charset: {}", charsets.undeclared);
+        println!(
+            "Emails not valid in their declared charset: {}",
+            charsets.invalid
+        );
+        println!("Emails marked as deleted by Thunderbird: {}", info.deleted);
+        if charsets.unchecked_parts > 0 {
+            println!(
+                "Parts not checked for validity, as they are larger than {} bytes: {}",
+                self.max_body_size, charsets.unchecked_parts
+            );
+        }
+    }
+
+    fn to_json(info: &MboxInfo) -> Value {
+        let charsets = &info.charsets;
+        let declared: Vec<Value> = charsets
+            .declared
+            .iter()
+            .map(|usage| {
+                json!({
+                    "charset": usage.charset,
+                    "messages": usage.messages,
+                    "parts": usage.parts,
+                })
+            })
+            .collect();
+        json!({
+            "messages": info.messages,
+            "errors": info.errors.len(),
+            "deleted": info.deleted,
+            "interrupted": info.interrupted,
+            "charsets": {
+                "declared": declared,
+                "undeclared": charsets.undeclared,
+                "invalid": charsets.invalid,
+                "unchecked_parts": charsets.unchecked_parts,
+            },
+        })
+    }
+}
@@ -0,0 +1,33 @@
+use std::{
+    io, process,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// The exit code used when a conversion is stopped by an interrupt signal.
+pub const INTERRUPTED_EXIT_CODE: u8 = 130;
+
+static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// Install a handler for Ctrl-C (and Ctrl-Break on Windows, or SIGTERM and SIGHUP elsewhere)
+/// that requests cancellation of the running conversion. A second signal exits immediately.
+pub fn install_handler() -> io::Result<()> {
+    let interrupted = flag();
+    ctrlc::set_handler(move || {
+        if interrupted.swap(true, Ordering::Relaxed) {
+            process::exit(INTERRUPTED_EXIT_CODE.into());
+        }
+    })
+    .map_err(|error| match error {
+        ctrlc::Error::System(error) => error,
+        error => io::Error::other(error),
+    })
+}
+
+/// The flag that is set once cancellation has been requested, to be passed to conversions,
+/// which check it between messages.
+pub fn flag() -> Arc<AtomicBool> {
+    Arc::clone(INTERRUPTED.get_or_init(Arc::default))
+}
@@ -0,0 +1,65 @@
+use crate::cli::paths;
+use anyhow::{Context, Result};
+use clap::{Command, Parser};
+use clap_mangen::Man;
+use std::{fs, path::PathBuf, process::ExitCode};
+
+/// Examples shown in the long help and the man page.
+const EXAMPLES: &str = "\
+Examples:
+  Write the pages into a package's build root:
+    mailfmt man --out-dir \"$pkgdir/usr/share/man/man1\"";
+
+/// Write man pages for mailfmt and each of its subcommands, such as mailfmt.1 and mailfmt-mbox-to-eml.1, to a directory. They are generated from the same definition as the --help output.
+#[derive(Parser)]
+#[clap(after_long_help = EXAMPLES)]
+pub struct ManCommand {
+    /// The directory to write the pages to, created if it does not exist.
+    #[clap(long = "out-dir", value_name = "PATH")]
+    out_dir: PathBuf,
+}
+
+impl ManCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        self.out_dir = paths::expand(&self.out_dir).map_err(anyhow::Error::msg)?;
+        Ok(())
+    }
+
+    /// Write a page for the given command line interface and every visible subcommand of it.
+    pub fn run(&self, command: Command) -> Result<ExitCode> {
+        fs::create_dir_all(&self.out_dir)
+            .with_context(|| format!("Failed to create directory '{}'", self.out_dir.display()))?;
+        // Building applies global flags to the subcommands and names each of them after the
+        // commands leading to it, such as 'mailfmt-mbox-to-eml', which becomes the page name.
+        let mut command = command.disable_help_subcommand(true);
+        command.build();
+        let source = format!(
+            "{} {}",
+            command.get_name(),
+            command.get_version().unwrap_or_default()
+        );
+        self.write_pages(command, &source)?;
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Write the page of a command, then those of its subcommands, each naming the program and
+    /// version they come from as their source.
+    fn write_pages(&self, command: Command, source: &str) -> Result<()> {
+        let subcommands: Vec<Command> = command
+            .get_subcommands()
+            .filter(|sub| !sub.is_hide_set())
+            .cloned()
+            .collect();
+        let man = Man::new(command).source(source);
+        let path = self.out_dir.join(man.get_filename());
+        let mut page = Vec::new();
+        man.render(&mut page)?;
+        fs::write(&path, page).with_context(|| format!("Failed to write '{}'", path.display()))?;
+        println!("{}", path.display());
+        for subcommand in subcommands {
+            self.write_pages(subcommand, source)?;
+        }
+        Ok(())
+    }
+}
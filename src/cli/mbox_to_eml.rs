@@ -0,0 +1,689 @@
+use crate::cli::{
+    describe_counters, describe_duplicates, describe_error, describe_invalid_utf8,
+    describe_msgid_index, describe_stripped, describe_throughput,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    output::{self, Stream},
+    paths,
+    porcelain::{self, Porcelain},
+    print_errors, print_path,
+    prompt::Prompter,
+    report_count, report_empty_input, style_progress_bar, validate_file_path,
+    validate_transcode_charset, write_error_report,
+};
+use anyhow::{Context, Result};
+use clap::Parser;
+use indicatif::{HumanCount, ProgressBar, ProgressDrawTarget};
+use mailfmt::{
+    Compression, ConversionSummary, EmptyMessages, Event, ExistingKey, GroupBy, Headerless,
+    InputFormat, InvalidUtf8, MailfmtError, MboxDialect, MboxToEmlOptions, MessageRange, NameBy,
+    Normalization, Numbering, ProgressTotal, ReadState, UuidVersion, count_mbox, mbox_to_eml,
+};
+use std::{
+    cell::Cell,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+/// Examples shown in the long help and the man page.
+const EXAMPLES: &str = "\
+Examples:
+  Extract every message of an mbox to its own eml file:
+    mailfmt mbox-to-eml archive.mbox messages/
+
+  Extract into one subdirectory per thread, without attachments:
+    mailfmt mbox-to-eml archive.mbox messages/ --group-by thread --strip-attachments
+
+  Continue an extraction that was interrupted:
+    mailfmt mbox-to-eml archive.mbox messages/ --resume";
+
+/// Convert a single .mbox file to an extracted directory of .eml files.
+#[derive(Parser)]
+#[clap(after_long_help = EXAMPLES)]
+pub struct ConvertToEmlCommand {
+    #[arg(value_parser = validate_file_path)]
+    input_file: PathBuf,
+
+    #[arg(required_unless_present = "count_only")]
+    output_directory: Option<PathBuf>,
+
+    /// Replace any existing eml files in the given directory with new ones if they overlap. Will not delete files that do not overlap.
+    #[clap(long = "overwrite")]
+    overwrite: bool,
+
+    /// Ask on the terminal before replacing each existing file, instead of failing or replacing them all with --overwrite. Only asks when stdin is a terminal.
+    #[clap(long = "interactive", conflicts_with = "overwrite")]
+    interactive: bool,
+
+    /// Count the messages in the mbox file before converting to show an exact message count in the progress bar.
+    #[clap(long = "precount")]
+    precount: bool,
+
+    /// Only print the number of emails that would be extracted, applying --range, --list-id, --seen, --unseen, --skip-deleted, --sample, --empty-messages skip and --headerless skip, and exit without writing anything. No output directory is needed. Only the header blocks of the emails are read, and only when a filter needs them, so that even large mbox files are counted quickly.
+    #[clap(long = "count-only", conflicts_with_all = ["resume", "interactive", "porcelain", "print_paths"])]
+    count_only: bool,
+
+    /// Sync every extracted eml file and the output directory to disk before counting it as converted.
+    #[clap(long = "fsync")]
+    fsync: bool,
+
+    /// Convert the input file even if it does not look like an mbox file, and even if the output directory seems to lack the space for the extracted emails, with a warning.
+    #[clap(long = "force")]
+    force: bool,
+
+    /// Do not check that the filesystem of the output directory has room for the size of the input file and a 5% margin before converting, for filesystems that misreport their free space.
+    #[clap(long = "no-space-check")]
+    no_space_check: bool,
+
+    /// Fail when the content before the first "From " line is longer than a dummy line or looks like the headers of a message whose "From " line was lost, instead of skipping it with a warning.
+    #[clap(long = "strict")]
+    strict: bool,
+
+    /// Succeed without writing anything when the input file is empty or holds only blank lines. Otherwise such input is an error with exit code 3.
+    #[clap(long = "allow-empty")]
+    allow_empty: bool,
+
+    /// Continue an interrupted extraction into the same output directory from the last progress recorded inside of it. The input file must be unchanged.
+    #[clap(long = "resume")]
+    resume: bool,
+
+    /// What to do with messages containing bytes that are not valid UTF-8: 'preserve' passes them through untouched, 'lossy' replaces them with U+FFFD, and 'strict' counts the message as an error.
+    #[clap(long = "invalid-utf8", value_name = "POLICY", default_value_t = InvalidUtf8::Preserve)]
+    invalid_utf8: InvalidUtf8,
+
+    /// What to do with messages that have no header block, meaning no header field before their first blank line: 'keep' extracts them like any other, 'skip' leaves them out with a warning for each, and 'error' counts them as errors.
+    #[clap(long = "headerless", value_name = "POLICY", default_value_t = Headerless::Keep)]
+    headerless: Headerless,
+
+    /// What to do with empty messages, which hold no line or only blank ones, such as those between two "From " lines in a row: 'skip' leaves them out and counts them in the summary, 'keep' extracts them to empty eml files, and 'error' counts them as errors.
+    #[clap(long = "empty-messages", value_name = "POLICY", default_value_t = EmptyMessages::Skip)]
+    empty_messages: EmptyMessages,
+
+    /// The format of the input file: 'mbox', or 'babyl' for the Rmail files of older Emacs versions, whose emails keep their original headers and get their labels as Status, X-Status and X-Keywords headers.
+    #[clap(long = "input-format", value_name = "FORMAT", default_value_t = InputFormat::Mbox)]
+    input_format: InputFormat,
+
+    /// Only take a "From " line inside of an email that declares quoted-printable content, which does not require such lines to be quoted, for the start of a new email when a header field follows it within 3 lines and the line before it is not a soft line break.
+    #[clap(long = "smart-separators")]
+    smart_separators: bool,
+
+    /// The mbox dialect the input was written in: 'mboxrd' unquotes body lines starting with one or more '>' followed by 'From ', 'mboxo' and 'mboxcl' unquote lines starting with '>From ', and 'mboxcl' and 'mboxcl2' take the length of a body from its Content-Length header, so that 'From ' lines inside of it do not start a new email.
+    #[clap(long = "dialect", default_value_t = MboxDialect::Mboxrd)]
+    dialect: MboxDialect,
+
+    /// Keep a UTF-8 byte-order mark at the start of the mbox file instead of removing it. The first message is then not recognised.
+    #[clap(long = "keep-bom")]
+    keep_bom: bool,
+
+    /// Convert the text of messages to the given charset, which must be 'utf-8', from the charset declared in their Content-Type. Only single-part messages and the top-level parts of multipart messages are transcoded.
+    #[clap(long = "transcode", value_name = "CHARSET", value_parser = validate_transcode_charset)]
+    transcode: Option<String>,
+
+    /// When transcoding, treat text without a declared charset as UTF-8 if it is valid as such and as Windows-1252 otherwise, instead of leaving it as it is.
+    #[clap(long = "transcode-detect", requires = "transcode")]
+    transcode_detect: bool,
+
+    /// Replace the attachments of multipart messages with short text parts recording their file name, type, size and SHA-256, keeping the messages valid MIME.
+    #[clap(long = "strip-attachments")]
+    strip_attachments: bool,
+
+    /// When stripping attachments, also replace messages that consist of a single attachment, such as a bare PDF, instead of leaving them intact.
+    #[clap(long = "strip-single-part", requires = "strip_attachments")]
+    strip_single_part: bool,
+
+    /// Report the Message-IDs found on more than one email once the conversion is over, with the numbers and files of those emails. Every email is still converted.
+    #[clap(long = "report-duplicates")]
+    report_duplicates: bool,
+
+    /// Write an index of the extracted emails to this file, mapping every Message-ID to its eml file, relative to the output directory. Emails without a Message-ID are keyed by 'sha256:' and a hash of their content, and flagged as synthetic. A file name ending in '.jsonl' gets JSON Lines, and any other tab-separated values.
+    #[clap(long = "msgid-index", value_name = "PATH")]
+    msgid_index: Option<PathBuf>,
+
+    /// Write every error of the conversion to this file once it is over, one per line, while only the first 50 are listed in the output. A file name ending in '.jsonl' gets a JSON object per line.
+    #[clap(long = "error-report", value_name = "PATH")]
+    error_report: Option<PathBuf>,
+
+    /// Group the eml files into subdirectories: 'none' writes them all to the output directory, 'thread' gives every thread, found from the Message-ID, In-Reply-To and References headers, a subdirectory named after its subject, 'date' writes them to a 'YYYY/MM' subdirectory for the month in their Date header, or to 'undated', 'sender' and 'sender-domain' to a subdirectory named after the address or domain in their From header, or to 'unknown', and 'folder' to the subdirectory in their 'X-Folder' header, as recorded by 'eml-to-mbox --record-folder'. Only one grouping can be used at a time, so dates and senders cannot be combined. Grouping by thread reads the mbox file twice.
+    #[clap(long = "group-by", visible_alias = "subdir-by", value_name = "GROUPING", default_value_t = GroupBy::None)]
+    group_by: GroupBy,
+
+    /// Recreate the folders recorded by 'eml-to-mbox --record-folder', writing every email to the subdirectory in its 'X-Folder' header, or to the output directory itself without one. The same as '--group-by folder'.
+    #[clap(long = "restore-folders", conflicts_with = "group_by")]
+    restore_folders: bool,
+
+    /// How the eml files in subdirectories are numbered: 'global' numbers them across the whole mbox, and 'per-dir' from 0 in every subdirectory.
+    #[clap(long = "subdir-numbering", value_name = "NUMBERING", default_value_t = Numbering::Global)]
+    numbering: Numbering,
+
+    /// When grouping by sender, the fewest emails a sender needs for a subdirectory of its own. The emails of senders with fewer are written to 'other'. Any value above 1 reads the mbox file twice.
+    #[clap(long = "other-threshold", value_name = "COUNT", default_value_t = 1)]
+    other_threshold: usize,
+
+    /// The Unicode normalization applied to subjects in file names: 'nfc' composes characters such as 'é' so that names that look the same are the same, 'nfd' decomposes them as HFS+ does, and 'none' keeps subjects as they were decoded.
+    #[clap(long = "filename-normalization", value_name = "FORM", default_value_t = Normalization::Nfc)]
+    filename_normalization: Normalization,
+
+    /// Transliterate subjects to ASCII in file names: accents are dropped, letters such as 'ß' are spelled out and other characters are replaced. Emails with nothing printable left in their subject are named after their number only.
+    #[clap(long = "ascii-filenames")]
+    ascii_filenames: bool,
+
+    /// Name the eml files after the file in their 'X-Source-File' header, as recorded by 'eml-to-mbox --record-source', keeping the names they had before being converted. Emails without one, or whose name was already used, are named as usual. Without it, the header is removed from the files written.
+    #[clap(long = "restore-names")]
+    restore_names: bool,
+
+    /// What to name the eml files after: 'index' for their number followed by their subject, 'uuid' for a UUID, for systems that do not want meaningful file names such as object stores, or 'hash' for the SHA-256 of the file, so that identical messages get identical names. Files named by UUID or hash can only be mapped back to their emails with --msgid-index or --sidecar-metadata.
+    #[clap(long = "name-by", value_name = "NAMING", default_value_t = NameBy::Index, conflicts_with = "restore_names")]
+    name_by: NameBy,
+
+    /// With --name-by uuid, the version of the UUIDs: 4 for random ones, or 7 for ones starting with the time they were generated, so that the files sort in the order they were extracted.
+    #[clap(long = "uuid-version", value_name = "VERSION", default_value_t = UuidVersion::V4)]
+    uuid_version: UuidVersion,
+
+    /// With --name-by hash, keep only the first 16 hexadecimal digits of the hash. A message whose short name is taken by a file holding something else is named after its full hash instead.
+    #[clap(long = "short-hash")]
+    short_hash: bool,
+
+    /// Leave existing eml files that hold exactly what would be written in place, counting their messages as skipped. With --name-by hash and --overwrite, extracting into the same directory again writes each distinct message once.
+    #[clap(long = "skip-identical", conflicts_with = "count_only")]
+    skip_identical: bool,
+
+    /// Read the eml files already in the output directory before extracting, and leave out every email found among them by this key, whatever its file is named. Only 'message-id' is supported, and emails without a Message-ID are always extracted. The output directory may then exist, and an email whose file name is taken by another is left out with a warning unless --overwrite is given.
+    #[clap(
+        long = "skip-existing-by",
+        value_name = "KEY",
+        conflicts_with = "count_only"
+    )]
+    skip_existing_by: Option<ExistingKey>,
+
+    /// Compress every eml file written: 'none' writes them as they are, and 'gzip' compresses them and adds '.gz' to their names, as read back by eml-to-mbox. Embedded messages extracted with --explode-rfc822 are not compressed.
+    #[clap(long = "compress-output", value_name = "COMPRESSION", default_value_t = Compression::None)]
+    compress_output: Compression,
+
+    /// With --compress-output, write emails smaller than this many bytes as they are, without '.gz' in their names, as compressing them saves next to nothing.
+    #[clap(long = "compress-min-size", value_name = "BYTES", default_value_t = 0)]
+    compress_min_size: u64,
+
+    /// Remove reply and forward markers such as 'Re:', 'Fwd:' and 'AW:', and mailing list tags such as '[rust-users]', from the start of subjects in file names. Subjects made only of these are kept as they are.
+    #[clap(long = "clean-subject")]
+    clean_subject: bool,
+
+    /// Write a .meta.json file next to every eml file with the sender and date of its "From " line, the path of the mbox file and the message's byte offset in it, its Message-ID, its parsed date and the flags in its Status and X-Status headers. eml-to-mbox takes the "From " line from these files when it finds them.
+    #[clap(long = "sidecar-metadata")]
+    sidecar_metadata: bool,
+
+    /// Also write every message embedded in an email as a message/rfc822 part, such as those of a mailing list digest or an email forwarded as an attachment, to an eml file of its own, named like '0007.1_subject.eml' for the first one in email 7. Messages embedded in those are written down to 4 levels deep. The file each was found in is recorded in 'embedded.jsonl' in the output directory.
+    #[clap(long = "explode-rfc822")]
+    explode_rfc822: bool,
+
+    /// Extract a random sample of this many emails instead of all of them, chosen in a single pass among those not skipped for lacking a header block, and written in their order in the mbox. The sampled emails are kept in memory until the whole mbox has been read. The index and byte offset of every sampled email in the mbox are recorded in 'sample.jsonl' in the output directory. Cannot be resumed.
+    #[clap(long = "sample", value_name = "COUNT", conflicts_with = "resume")]
+    sample: Option<usize>,
+
+    /// The seed to draw the sample with, to draw the same sample from the same mbox again. A different seed is used every time when not given, and printed once the extraction is over.
+    #[clap(long = "seed", requires = "sample")]
+    seed: Option<u64>,
+
+    /// Only extract the emails at these positions in the mbox, counting from 0 and excluding the end, such as '1200..1300', '1200..' or '..100'. Every email counts, even those skipped for lacking a header block or that cannot be read, so the positions are those of the mbox itself. Reading stops at the end of the range. The files are numbered after their position, so email 1200 is written to '1200_subject.eml', unless --renumber is given. Cannot be resumed.
+    #[clap(long = "range", value_name = "START..END", conflicts_with = "resume")]
+    range: Option<MessageRange>,
+
+    /// Number the files of a --range from 0 instead of after their position in the mbox.
+    #[clap(long = "renumber", requires = "range")]
+    renumber: bool,
+
+    /// Only extract the emails of this mailing list, such as 'rust-users.rust-lang.org', found from the identifier between the angle brackets of their List-Id header. Matching is case-insensitive, and any part of the identifier matches unless the value is itself in angle brackets, such as '<rust-users.rust-lang.org>'. Emails without a List-Id header never match. Can be repeated.
+    #[clap(long = "list-id", value_name = "ID")]
+    list_ids: Vec<String>,
+
+    /// Only extract the emails that were read, after the R letter of their Status header or the read bit of their X-Mozilla-Status header.
+    #[clap(long = "seen", conflicts_with = "unseen")]
+    seen: bool,
+
+    /// Only extract the emails that were not read, after their Status or X-Mozilla-Status header.
+    #[clap(long = "unseen")]
+    unseen: bool,
+
+    /// Whether --seen and --unseen take emails without a Status, X-Status or X-Mozilla-Status header to be 'seen' or 'unseen'.
+    #[clap(long = "no-status-means", value_name = "STATE", default_value_t = ReadState::Unseen)]
+    no_status_means: ReadState,
+
+    /// Leave out the emails Thunderbird marked as deleted in their X-Mozilla-Status header, which it keeps in the mbox until the folder is compacted. Without it they are extracted, with a warning.
+    #[clap(long = "skip-deleted")]
+    skip_deleted: bool,
+
+    /// Print the path of every file written to stdout as soon as it is complete, in message order. All other output is written to stderr instead.
+    #[clap(long = "print-paths")]
+    print_paths: bool,
+
+    /// Terminate paths printed by --print-paths with a NUL character instead of a newline.
+    #[clap(long = "print0", requires = "print_paths")]
+    print0: bool,
+
+    #[clap(long = "porcelain", help = porcelain::HELP, conflicts_with = "print_paths")]
+    porcelain: bool,
+
+    /// With --porcelain, print a progress line every this many messages.
+    #[clap(long = "porcelain-every", value_name = "N", default_value_t = porcelain::DEFAULT_EVERY, requires = "porcelain")]
+    porcelain_every: u64,
+}
+
+impl ConvertToEmlCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        self.input_file = paths::expand(&self.input_file).map_err(anyhow::Error::msg)?;
+        for path in [
+            &mut self.output_directory,
+            &mut self.msgid_index,
+            &mut self.error_report,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = paths::expand(path).map_err(anyhow::Error::msg)?;
+        }
+        Ok(())
+    }
+
+    /// Where human-readable messages go, keeping stdout free for paths when --print-paths is used.
+    fn stream(&self) -> Stream {
+        match self.print_paths || self.porcelain {
+            true => Stream::Stderr,
+            false => Stream::Stdout,
+        }
+    }
+
+    /// Print a human-readable message to the stream for them.
+    fn info(&self, message: impl std::fmt::Display) {
+        match self.stream() {
+            Stream::Stderr => eprintln!("{message}"),
+            Stream::Stdout => println!("{message}"),
+        }
+    }
+
+    pub fn run(&self) -> Result<ExitCode> {
+        let Some(output_directory) = self
+            .output_directory
+            .as_deref()
+            .filter(|_| !self.count_only)
+        else {
+            return self.count();
+        };
+        let pb = ProgressBar::hidden();
+        let processed = Cell::new(0);
+        let porcelain = self.porcelain.then(|| Porcelain::new(self.porcelain_every));
+        let prompter = self.interactive.then(|| Prompter::new(&pb)).flatten();
+        let mut options = MboxToEmlOptions::new(&self.input_file, output_directory);
+        options.input_format = self.input_format;
+        options.overwrite = self.overwrite;
+        options.precount = self.precount;
+        options.fsync = self.fsync;
+        options.force = self.force;
+        options.space_check = !self.no_space_check;
+        options.allow_empty = self.allow_empty;
+        options.strict = self.strict;
+        options.resume = self.resume;
+        options.invalid_utf8 = self.invalid_utf8;
+        options.headerless = self.headerless;
+        options.empty_messages = self.empty_messages;
+        options.smart_separators = self.smart_separators;
+        options.dialect = self.dialect;
+        options.keep_bom = self.keep_bom;
+        options.transcode = self.transcode.is_some();
+        options.transcode_detect = self.transcode_detect;
+        options.strip_attachments = self.strip_attachments;
+        options.strip_single_part = self.strip_single_part;
+        options.report_duplicates = self.report_duplicates;
+        options.msgid_index = self.msgid_index.clone();
+        options.group_by = match self.restore_folders {
+            true => GroupBy::Folder,
+            false => self.group_by,
+        };
+        options.numbering = self.numbering;
+        options.other_threshold = self.other_threshold;
+        options.filename_normalization = self.filename_normalization;
+        options.ascii_filenames = self.ascii_filenames;
+        options.restore_names = self.restore_names;
+        options.name_by = self.name_by;
+        options.uuid_version = self.uuid_version;
+        options.short_hash = self.short_hash;
+        options.skip_identical = self.skip_identical;
+        options.skip_existing_by = self.skip_existing_by;
+        options.compress_output = self.compress_output;
+        options.compress_min_size = self.compress_min_size;
+        options.clean_subject = self.clean_subject;
+        options.sidecar_metadata = self.sidecar_metadata;
+        options.explode_rfc822 = self.explode_rfc822;
+        options.sample = self.sample;
+        options.sample_seed = self.seed;
+        options.range = self.range;
+        options.renumber = self.renumber;
+        options.list_ids = self.list_ids.clone();
+        options.read_state = self.read_state();
+        options.no_status_means = self.no_status_means;
+        options.skip_deleted = self.skip_deleted;
+        options.details = output::verbosity() > 0 || output::json_logs();
+        options.cancel = Some(interrupt::flag());
+        if let Some(prompter) = &prompter {
+            options.confirm_overwrite = Some(Box::new(|path| prompter.ask(path)));
+        }
+        options.on_event = Some(Box::new(|event| {
+            if porcelain.as_ref().is_some_and(|p| p.record(&event)) {
+                return Ok(());
+            }
+            Ok(self.handle_event(&pb, &processed, event)?)
+        }));
+
+        let result = mbox_to_eml(&options);
+        pb.finish_and_clear();
+        let summary = match result {
+            Err(error @ MailfmtError::EmptyInput { .. }) => return Ok(report_empty_input(&error)),
+            result => result?,
+        };
+        if let Some(path) = &self.error_report {
+            write_error_report(path, &summary.errors, &summary.invalid)?;
+        }
+        if let Some(porcelain) = &porcelain {
+            porcelain.done(summary.converted, summary.skipped, summary.errors.len());
+        }
+        if let Some(answers) = prompter.as_ref().and_then(Prompter::describe) {
+            self.info(answers);
+        }
+        Ok(self.report(&summary, output_directory))
+    }
+
+    /// Count the emails that would be extracted, for --count-only.
+    fn count(&self) -> Result<ExitCode> {
+        let mut options = MboxToEmlOptions::new(&self.input_file, "");
+        options.input_format = self.input_format;
+        options.force = self.force;
+        options.allow_empty = self.allow_empty;
+        options.headerless = self.headerless;
+        options.empty_messages = self.empty_messages;
+        options.smart_separators = self.smart_separators;
+        options.dialect = self.dialect;
+        options.keep_bom = self.keep_bom;
+        options.sample = self.sample;
+        options.range = self.range;
+        options.list_ids = self.list_ids.clone();
+        options.read_state = self.read_state();
+        options.no_status_means = self.no_status_means;
+        options.skip_deleted = self.skip_deleted;
+        options.cancel = Some(interrupt::flag());
+        match count_mbox(&options) {
+            Err(error @ MailfmtError::EmptyInput { .. }) => Ok(report_empty_input(&error)),
+            result => Ok(report_count(&result?, "emails")),
+        }
+    }
+
+    /// The read state asked for with --seen or --unseen.
+    fn read_state(&self) -> Option<ReadState> {
+        match (self.seen, self.unseen) {
+            (true, _) => Some(ReadState::Seen),
+            (_, true) => Some(ReadState::Unseen),
+            _ => None,
+        }
+    }
+
+    /// Print the outcome of the conversion and choose the exit code for it.
+    fn report(&self, summary: &ConversionSummary, output_dir: &Path) -> ExitCode {
+        let fsync = self.fsync;
+        output::log_summary(summary);
+        print_errors(&summary.errors, self.error_report.as_deref());
+        if summary.empty_input {
+            self.info(output::notice(
+                self.stream(),
+                "The input contained no messages, so nothing was written",
+            ));
+            return ExitCode::SUCCESS;
+        }
+        if let Some(resumed) = &summary.resumed {
+            self.info(format!(
+                "Resumed after message {}: {} emails were converted with {} errors by earlier runs",
+                resumed.converted + resumed.errors,
+                resumed.converted,
+                resumed.errors
+            ));
+        }
+
+        if summary.invalid_utf8 > 0 {
+            self.info(describe_invalid_utf8(
+                summary.invalid_utf8,
+                "emails",
+                self.invalid_utf8,
+            ));
+        }
+
+        let headerless_errors = summary
+            .errors
+            .iter()
+            .filter(|e| matches!(e.error, MailfmtError::Headerless { .. }))
+            .count();
+        if headerless_errors > 0 {
+            self.info(format!(
+                "{headerless_errors} emails without a header block were counted as errors"
+            ));
+        }
+        let empty_errors = summary
+            .errors
+            .iter()
+            .filter(|e| matches!(e.error, MailfmtError::EmptyMessage { .. }))
+            .count();
+        if empty_errors > 0 {
+            self.info(format!(
+                "{empty_errors} empty emails were counted as errors"
+            ));
+        }
+        if summary.empty > 0 {
+            self.info(format!("{} empty emails were skipped", summary.empty));
+        }
+        if summary.headerless > 0 {
+            self.info(output::notice(
+                self.stream(),
+                format!(
+                    "{} emails without a header block were skipped",
+                    summary.headerless
+                ),
+            ));
+        }
+
+        if let Some(charset) = &self.transcode {
+            self.info(format!(
+                "{} emails were transcoded to {charset}",
+                summary.transcoded
+            ));
+            if summary.not_transcoded > 0 {
+                self.info(output::notice(self.stream(), format!(
+                    "{} emails were left untouched, as their charset is unknown or not declared, or their text is nested too deeply",
+                    summary.not_transcoded
+                )));
+            }
+        }
+
+        if self.strip_attachments {
+            self.info(describe_stripped(summary));
+        }
+        if self.compress_output != Compression::None {
+            self.info(format!(
+                "Compressed {} of the eml files written with {}",
+                summary.compressed, self.compress_output
+            ));
+        }
+        if self.explode_rfc822 {
+            self.info(format!(
+                "{} embedded messages were written to eml files of their own",
+                summary.embedded
+            ));
+        }
+        if let (Some(sampled), Some(size)) = (&summary.sampled, self.sample) {
+            self.info(format!(
+                "Sampled {} of {} emails with seed {}",
+                size.min(sampled.population),
+                sampled.population,
+                sampled.seed
+            ));
+        }
+        if let Some(range) = self.range
+            && range.start > 0
+        {
+            self.info(format!(
+                "{} emails before email {} were passed over",
+                summary.out_of_range, range.start
+            ));
+        }
+        if !self.list_ids.is_empty() {
+            self.info(describe_lists(summary));
+        }
+        if let Some(state) = self.read_state() {
+            self.info(format!(
+                "{} emails were left out as they are not {state}",
+                summary.off_state
+            ));
+        }
+        if self.skip_deleted {
+            self.info(format!(
+                "{} emails were left out as they are marked as deleted",
+                summary.skipped_deleted
+            ));
+        }
+        if self.skip_existing_by.is_some() {
+            self.info(format!(
+                "{} emails were left out as already present in the output directory",
+                summary.already_present
+            ));
+        }
+        self.info(describe_throughput(summary));
+        if self.report_duplicates {
+            self.info(describe_duplicates(summary));
+        }
+        if let (Some(path), Some(index)) = (&self.msgid_index, &summary.msgid_index) {
+            self.info(describe_msgid_index(path, index));
+        }
+
+        if summary.interrupted {
+            self.info(format!(
+                "Conversion interrupted at message {}: {} emails converted with {}{}. Partial output saved to {:?}.{}",
+                summary
+                    .resumed
+                    .as_ref()
+                    .map_or(0, |resumed| resumed.converted + resumed.errors)
+                    + summary.converted
+                    + summary.errors.len(),
+                summary.converted,
+                output::error_count(self.stream(), summary.errors.len()),
+                if fsync { " (fsync enabled)" } else { "" },
+                output_dir,
+                match summary.sampled.is_some() || self.range.is_some() {
+                    true => "",
+                    false => " Use --resume to continue.",
+                }
+            ));
+            return ExitCode::from(INTERRUPTED_EXIT_CODE);
+        }
+
+        if let Some(counters) = describe_counters(&summary.counters(), "emails") {
+            self.info(counters);
+        }
+        self.info(format!(
+            "Conversion of {} emails completed with {}{}. Output saved to {:?}",
+            summary.converted,
+            output::error_count(self.stream(), summary.errors.len()),
+            if fsync { " (fsync enabled)" } else { "" },
+            output_dir
+        ));
+
+        ExitCode::SUCCESS
+    }
+
+    fn handle_event(
+        &self,
+        pb: &ProgressBar,
+        processed: &Cell<usize>,
+        event: Event<'_>,
+    ) -> Result<()> {
+        if output::logged(&event) {
+            return Ok(());
+        }
+        match event {
+            Event::Counted { messages, elapsed } => self.info(format!(
+                "Counted {} messages in {:.1}s",
+                HumanCount(messages),
+                elapsed.as_secs_f64()
+            )),
+            Event::Scanning { files } => {
+                if files == 0 {
+                    style_progress_bar(pb, ProgressTotal::Unknown);
+                    pb.set_draw_target(ProgressDrawTarget::stderr());
+                    pb.enable_steady_tick(Duration::from_millis(100));
+                }
+                pb.set_message(format!("{} existing eml files read", HumanCount(files)));
+            }
+            Event::Scanned {
+                files,
+                message_ids,
+                elapsed,
+            } => self.info(format!(
+                "Read {} existing eml files in {:.1}s, holding {} distinct Message-IDs",
+                HumanCount(files),
+                elapsed.as_secs_f64(),
+                HumanCount(message_ids as u64)
+            )),
+            Event::SpaceChecked {
+                required,
+                available,
+            } => output::log_space(required, available),
+            Event::Started { total, position } => {
+                style_progress_bar(pb, total);
+                pb.set_position(position);
+                pb.set_message("0 emails processed");
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Processing(subject) if !pb.is_hidden() => {
+                pb.set_message(output::progress_message(
+                    format!("{} emails processed", HumanCount(processed.get() as u64)),
+                    subject,
+                ));
+            }
+            Event::Processing(_) => {}
+            Event::Progress {
+                position,
+                processed: count,
+            } => {
+                processed.set(count);
+                pb.set_position(position);
+                pb.set_message(format!("{} emails processed", HumanCount(count as u64)));
+            }
+            Event::Written(path) => {
+                if self.print_paths {
+                    pb.suspend(|| print_path(path, self.print0))
+                        .context("failed to print output path")?;
+                }
+            }
+            // Errors are listed once the conversion is over, and only printed as they happen
+            // when verbose.
+            Event::Failed { item, error } if output::verbosity() > 0 => pb.println(output::error(
+                format!("Error {item}: {}", describe_error(error)),
+            )),
+            Event::Failed { .. } => {}
+            Event::Warning(message) => {
+                eprintln!("{}", output::warning(format!("Warning: {message}")))
+            }
+            Event::Converted(details) => output::log_details(pb, &details),
+            Event::Watching(_) | Event::Appended(_) | Event::Uploaded(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Describe how many emails of every mailing list were extracted, one list per line, and how
+/// many were left out as they are on none of those asked for.
+fn describe_lists(summary: &ConversionSummary) -> String {
+    let mut description = format!(
+        "{} emails were left out as they are not on the lists asked for",
+        summary.off_list
+    );
+    for (id, count) in &summary.lists {
+        description.push_str(&format!("\n  <{id}>: {count} emails"));
+    }
+    description
+}
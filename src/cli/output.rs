@@ -0,0 +1,291 @@
+//! Colors for console output, chosen once per run from --color and where the output goes, the
+//! per-message log enabled by -v, and the JSON logs written instead with --log-format json.
+
+use console::{Term, measure_text_width, truncate_str};
+use indicatif::{HumanBytes, ProgressBar};
+use mailfmt::{ConversionSummary, Event, MessageDetails};
+use std::{
+    fmt::{self, Write},
+    io::IsTerminal,
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    time::Duration,
+};
+use tracing::{Level, info};
+
+/// When to color output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color a stream only when it is a terminal and NO_COLOR is not set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::Always => "always",
+            Self::Never => "never",
+        })
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(format!(
+                "unknown color choice '{s}', expected auto, always or never"
+            )),
+        }
+    }
+}
+
+/// Where a message is printed, which decides whether it is colored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+static COLOR_STDOUT: AtomicBool = AtomicBool::new(false);
+static COLOR_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Decide which streams are colored. Until this is called, nothing is.
+pub fn init(choice: ColorChoice) {
+    let enabled = |terminal: bool| match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        }
+    };
+    COLOR_STDOUT.store(enabled(std::io::stdout().is_terminal()), Ordering::Relaxed);
+    COLOR_STDERR.store(enabled(std::io::stderr().is_terminal()), Ordering::Relaxed);
+}
+
+fn enabled(stream: Stream) -> bool {
+    match stream {
+        Stream::Stdout => COLOR_STDOUT.load(Ordering::Relaxed),
+        Stream::Stderr => COLOR_STDERR.load(Ordering::Relaxed),
+    }
+}
+
+/// Wrap text in an SGR escape sequence if the stream is colored.
+fn paint(stream: Stream, code: &str, text: impl fmt::Display) -> String {
+    match enabled(stream) {
+        true => format!("\x1b[{code}m{text}\x1b[0m"),
+        false => text.to_string(),
+    }
+}
+
+/// An error about a single message or file, printed to stderr in red.
+pub fn error(message: impl fmt::Display) -> String {
+    paint(Stream::Stderr, "31", message)
+}
+
+/// A warning, printed to stderr in yellow.
+pub fn warning(message: impl fmt::Display) -> String {
+    paint(Stream::Stderr, "33", message)
+}
+
+/// A notice that items were skipped or filtered out, in yellow.
+pub fn notice(stream: Stream, message: impl fmt::Display) -> String {
+    paint(stream, "33", message)
+}
+
+/// An error count for a summary, such as "3 errors", in bold red when it is not zero.
+pub fn error_count(stream: Stream, count: usize) -> String {
+    match count {
+        0 => format!("{count} errors"),
+        _ => paint(stream, "1;31", format!("{count} errors")),
+    }
+}
+
+/// The bar of a progress bar template, colored unless stderr is not.
+pub fn progress_bar() -> &'static str {
+    match enabled(Stream::Stderr) {
+        true => "{bar:40.cyan/blue}",
+        false => "{bar:40}",
+    }
+}
+
+/// The columns a progress bar line takes up at most besides its message.
+const PROGRESS_LINE_WIDTH: usize = 90;
+
+/// The message of a progress bar: its `status`, if any, followed by what it is working on when
+/// the terminal has room for it, truncated to the columns left so that wide characters cannot
+/// make the bar wrap.
+pub fn progress_message(status: String, current: &str) -> String {
+    let columns = Term::stderr()
+        .size_checked()
+        .map_or(0, |(_, columns)| usize::from(columns));
+    let room = columns.saturating_sub(PROGRESS_LINE_WIDTH + measure_text_width(&status) + 2);
+    let current: String = current
+        .trim()
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    if room < 8 || current.is_empty() {
+        return status;
+    }
+    let current = truncate_str(&current, room, "…");
+    match status.is_empty() {
+        true => current.into_owned(),
+        false => format!("{status}: {current}"),
+    }
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+
+/// Set how much is logged about every converted message: the number of times -v was given.
+pub fn set_verbosity(level: u8) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+/// How much is logged about every converted message, 0 for nothing.
+pub fn verbosity() -> u8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+/// Log the space the output is estimated to need and the space available for it to stderr,
+/// when verbose.
+pub fn log_space(required: u64, available: u64) {
+    if verbosity() > 0 {
+        eprintln!(
+            "Estimated the output to need {}, with {} available",
+            HumanBytes(required),
+            HumanBytes(available)
+        );
+    }
+}
+
+/// Log how a message was converted to stderr, above the progress bar: its sender, date and
+/// file, and at -vv also how long it took and the decisions made for it.
+pub fn log_details(pb: &ProgressBar, details: &MessageDetails<'_>) {
+    let mut line = format!(
+        "#{} from {}, dated {} ({}) -> {}",
+        details.index,
+        details.sender.as_deref().unwrap_or("unknown sender"),
+        details.date.as_deref().unwrap_or("unknown"),
+        details.date_source,
+        details.file.display()
+    );
+    if verbosity() > 1 {
+        let _ = write!(line, " in {:.1?}", details.elapsed);
+        for note in &details.notes {
+            let _ = write!(line, "\n    {note}");
+        }
+    }
+    match pb.is_hidden() {
+        true => eprintln!("{line}"),
+        false => pb.println(line),
+    }
+}
+
+/// How errors, warnings and the per-message log are written to stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Lines for people, with a progress bar on a terminal.
+    #[default]
+    Human,
+    /// A JSON object per line, for log aggregation, without a progress bar.
+    Json,
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Human => "human",
+            Self::Json => "json",
+        })
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown log format '{s}', expected human or json")),
+        }
+    }
+}
+
+static JSON_LOGS: AtomicBool = AtomicBool::new(false);
+
+/// Choose how events are logged. JSON logs are written by a `tracing` subscriber, which
+/// receives the events of the library as well as those of the CLI.
+pub fn set_log_format(format: LogFormat) {
+    JSON_LOGS.store(format == LogFormat::Json, Ordering::Relaxed);
+    if format == LogFormat::Json {
+        tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .with_target(false)
+            .with_max_level(Level::INFO)
+            .with_writer(std::io::stderr)
+            .init();
+    }
+}
+
+/// Whether events are logged to stderr as JSON.
+pub fn json_logs() -> bool {
+    JSON_LOGS.load(Ordering::Relaxed)
+}
+
+/// A duration in milliseconds for a JSON log, to the microsecond.
+pub fn milliseconds(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1_000_000.0).round() / 1000.0
+}
+
+/// With JSON logs, log the summary of a conversion: its counts, those of every category of
+/// [`Counters`](mailfmt::Counters) included, duration and throughput.
+pub fn log_summary(summary: &ConversionSummary) {
+    let counters = summary.counters();
+    info!(
+        event = "summary",
+        converted = summary.converted,
+        preexisting = summary.preexisting,
+        verified = summary.verified,
+        mailboxes = summary.mailboxes,
+        already_present = summary.already_present,
+        skipped = summary.skipped,
+        headerless = summary.headerless,
+        errors = summary.errors.len(),
+        filtered = counters.filtered,
+        existing = counters.existing,
+        invalid = counters.invalid,
+        failed = counters.failed,
+        compressed = summary.compressed,
+        interrupted = summary.interrupted,
+        duration_ms = milliseconds(summary.elapsed),
+        messages_per_second = (super::messages_per_second(summary) * 10.0).round() / 10.0,
+        bytes_read = summary.bytes_read,
+        bytes_written = summary.bytes_written,
+        duplicate_groups = summary.duplicates.len(),
+        average_message_size = super::average_message_size(summary),
+    );
+}
+
+/// Whether JSON logs replace the human output of an event of a conversion: the progress bar,
+/// errors, warnings and the per-message log. The library logs every event itself. Other
+/// events are still handled as usual, as they also decide what is printed to stdout.
+pub fn logged(event: &Event<'_>) -> bool {
+    json_logs()
+        && !matches!(
+            event,
+            Event::Written(_) | Event::Watching(_) | Event::Appended(_)
+        )
+}
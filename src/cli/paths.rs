@@ -0,0 +1,120 @@
+//! Paths given on the command line, expanded as a shell would have done.
+
+use std::path::{Path, PathBuf};
+
+/// Expand a leading `~` or `~user` and any `$VAR`/`${VAR}` (plus `%VAR%` on Windows) sequences
+/// in a path, for arguments that did not go through a shell.
+pub fn expand(path: &Path) -> Result<PathBuf, String> {
+    let Some(path) = path.to_str() else {
+        return Ok(path.to_path_buf());
+    };
+    let expanded = expand_variables(&expand_home(path)?)?;
+    Ok(PathBuf::from(expanded))
+}
+
+fn expand_home(path: &str) -> Result<String, String> {
+    let Some(rest) = path.strip_prefix('~') else {
+        return Ok(path.to_string());
+    };
+    let (user, rest) = rest.split_at(rest.find(['/', '\\']).unwrap_or(rest.len()));
+    let home = if user.is_empty() {
+        std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .map(PathBuf::from)
+            .ok_or("cannot expand '~': the home directory is not known")?
+    } else {
+        home_of_user(user).ok_or_else(|| format!("cannot expand '~{user}': no such user"))?
+    };
+    Ok(format!("{}{rest}", home.display()))
+}
+
+#[cfg(unix)]
+fn home_of_user(user: &str) -> Option<PathBuf> {
+    use std::{
+        ffi::{CStr, CString, OsStr},
+        os::unix::ffi::OsStrExt,
+    };
+
+    let name = CString::new(user).ok()?;
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: `passwd` is plain old data that `getpwnam_r` fills in.
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the duration of the call and the buffer length is accurate.
+    let status = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() || passwd.pw_dir.is_null() {
+        return None;
+    }
+    // SAFETY: `pw_dir` points into `buffer`, which is still alive, and is nul-terminated.
+    let dir = unsafe { CStr::from_ptr(passwd.pw_dir) };
+    Some(PathBuf::from(OsStr::from_bytes(dir.to_bytes())))
+}
+
+#[cfg(not(unix))]
+fn home_of_user(_user: &str) -> Option<PathBuf> {
+    None
+}
+
+fn expand_variables(path: &str) -> Result<String, String> {
+    let lookup = |name: &str| {
+        std::env::var(name)
+            .map_err(|_| format!("cannot expand path: environment variable '{name}' is not set. Use the --no-expand flag to use the path as-is."))
+    };
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(start) = rest.find(['$', '%']) {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if rest[start..].starts_with('$') {
+            if let Some(braced) = after.strip_prefix('{')
+                && let Some(end) = braced.find('}')
+            {
+                expanded.push_str(&lookup(&braced[..end])?);
+                rest = &braced[end + 1..];
+                continue;
+            }
+            let end = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+            if end > 0 && !after.starts_with(|c: char| c.is_ascii_digit()) {
+                expanded.push_str(&lookup(&after[..end])?);
+                rest = &after[end..];
+                continue;
+            }
+        } else if cfg!(windows)
+            && let Some(end) = after.find('%')
+            && end > 0
+            && after[..end].chars().all(is_name_char)
+        {
+            expanded.push_str(&lookup(&after[..end])?);
+            rest = &after[end + 1..];
+            continue;
+        }
+        // Not a variable reference, so keep the character as-is.
+        expanded.push_str(&rest[start..start + 1]);
+        rest = after;
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Build a path from raw bytes, such as an entry of a file list.
+pub fn from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        PathBuf::from(OsStr::from_bytes(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
@@ -0,0 +1,128 @@
+use crate::cli::{
+    describe_error,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    output, paths, style_progress_bar,
+};
+use anyhow::{Context, Result};
+use clap::Parser;
+use indicatif::{HumanCount, ProgressBar, ProgressDrawTarget};
+use mailfmt::{Event, PeekEnd, PeekOptions, peek_mbox};
+use std::{io, path::PathBuf, process::ExitCode, time::Duration};
+
+/// Examples shown in the long help and the man page.
+const EXAMPLES: &str = "\
+Examples:
+  Print the first five messages of an mbox:
+    mailfmt head -n 5 archive.mbox
+
+  Print the header blocks of its last ten messages:
+    mailfmt tail archive.mbox --headers-only
+
+  Write its last three messages to eml files:
+    mailfmt tail -n 3 archive.mbox -o peek/";
+
+/// The arguments of the head and tail commands.
+#[derive(Parser)]
+#[clap(after_long_help = EXAMPLES)]
+pub struct PeekCommand {
+    input_file: PathBuf,
+
+    /// How many messages to show.
+    #[clap(
+        short = 'n',
+        long = "count",
+        value_name = "COUNT",
+        default_value_t = 10
+    )]
+    count: usize,
+
+    /// Only show the separator line and header block of every message.
+    #[clap(long = "headers-only")]
+    headers_only: bool,
+
+    /// Write the messages to eml files in this directory instead of printing them, named after their index in the mbox and their subject. The directory is created if needed, and files of the same name in it are replaced.
+    #[clap(short = 'o', long = "output", value_name = "DIR")]
+    output_directory: Option<PathBuf>,
+
+    /// Read the input file even if it does not look like an mbox file.
+    #[clap(long = "force")]
+    force: bool,
+}
+
+impl PeekCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        self.input_file = paths::expand(&self.input_file).map_err(anyhow::Error::msg)?;
+        if let Some(output_directory) = &self.output_directory {
+            self.output_directory =
+                Some(paths::expand(output_directory).map_err(anyhow::Error::msg)?);
+        }
+        Ok(())
+    }
+
+    pub fn run(&self, end: PeekEnd) -> Result<ExitCode> {
+        let pb = ProgressBar::hidden();
+        let mut options = PeekOptions::new(&self.input_file, self.count, end);
+        options.force = self.force;
+        options.cancel = Some(interrupt::flag());
+        options.on_event = Some(Box::new(|event| {
+            Self::handle_event(&pb, event);
+            Ok(())
+        }));
+
+        let result = peek_mbox(&options);
+        pb.finish_and_clear();
+        let peeked = result?;
+
+        if let Some(output_directory) = &self.output_directory {
+            let written = peeked.write_eml_files(output_directory, self.headers_only)?;
+            eprintln!(
+                "Wrote {} eml files to {:?}",
+                written.len(),
+                output_directory
+            );
+        } else {
+            match peeked.write_mbox(&mut io::stdout().lock(), self.headers_only) {
+                // The reader, such as a pager, has seen enough.
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+                result => result.context("failed to print messages")?,
+            }
+        }
+        if peeked.interrupted {
+            eprintln!(
+                "Reading interrupted, so these are the last messages read rather than the last ones in the mbox"
+            );
+            return Ok(ExitCode::from(INTERRUPTED_EXIT_CODE));
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn handle_event(pb: &ProgressBar, event: Event<'_>) {
+        if output::logged(&event) {
+            return;
+        }
+        match event {
+            Event::Started { total, position } => {
+                style_progress_bar(pb, total);
+                pb.set_position(position);
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Progress {
+                position,
+                processed,
+            } => {
+                pb.set_position(position);
+                pb.set_message(format!("{} emails read", HumanCount(processed as u64)));
+            }
+            Event::Failed { item, error } => {
+                let line = output::error(format!("Error {item}: {}", describe_error(error)));
+                match pb.is_hidden() {
+                    true => eprintln!("{line}"),
+                    false => pb.println(line),
+                }
+            }
+            _ => {}
+        }
+    }
+}
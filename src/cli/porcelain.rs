@@ -0,0 +1,104 @@
+//! Progress for scripts and GUI wrappers, printed to stdout as lines in a stable format instead
+//! of a progress bar.
+//!
+//! ```text
+//! progress <done> <total> <bytes-done> <bytes-total>
+//! error <index> <message>
+//! done <converted> <skipped> <errors>
+//! ```
+//!
+//! `progress` counts the messages processed by this run out of those it has to process, and the
+//! bytes of the input read out of its size. Any of these that is not known is `?`. `error`
+//! gives the index among the processed messages of one that failed, with the error on the rest
+//! of the line. `done` is always the last line.
+
+use crate::cli::describe_error;
+use mailfmt::{Event, ProgressTotal};
+use std::cell::Cell;
+
+/// How often a progress line is printed by default, in messages.
+pub const DEFAULT_EVERY: u64 = 100;
+
+/// The help of --porcelain, documenting the format.
+pub const HELP: &str = "Print progress to stdout as lines for scripts instead of showing a progress bar, in a format that stays stable across releases: 'progress <done> <total> <bytes-done> <bytes-total>' every --porcelain-every messages, 'error <index> <message>' for every message that failed, and 'done <converted> <skipped> <errors>' at the end. Values that are not known are '?'. All other output is written to stderr.";
+
+pub struct Porcelain {
+    every: u64,
+    total: Cell<Option<u64>>,
+    bytes_done: Cell<Option<u64>>,
+    bytes_total: Cell<Option<u64>>,
+    processed: Cell<usize>,
+}
+
+impl Porcelain {
+    pub fn new(every: u64) -> Self {
+        Self {
+            every: every.max(1),
+            total: Cell::new(None),
+            bytes_done: Cell::new(None),
+            bytes_total: Cell::new(None),
+            processed: Cell::new(0),
+        }
+    }
+
+    /// Print the lines for an event, returning whether it is fully handled: the events that
+    /// would otherwise show the progress bar or print an error.
+    pub fn record(&self, event: &Event<'_>) -> bool {
+        match event {
+            Event::Started { total, position } => {
+                match *total {
+                    ProgressTotal::Bytes(size) => {
+                        self.bytes_total.set(Some(size));
+                        self.bytes_done.set(Some(*position));
+                    }
+                    ProgressTotal::Messages(count) => {
+                        self.total.set(Some(count.saturating_sub(*position)))
+                    }
+                    ProgressTotal::Unknown => {}
+                }
+                self.progress();
+            }
+            Event::Progress {
+                position,
+                processed,
+            } => {
+                if self.bytes_total.get().is_some() {
+                    self.bytes_done.set(Some(*position));
+                }
+                self.processed.set(*processed);
+                if (*processed as u64).is_multiple_of(self.every) {
+                    self.progress();
+                }
+            }
+            Event::Failed { error, .. } => {
+                let message = describe_error(*error);
+                println!(
+                    "error {} {}",
+                    self.processed.get(),
+                    message.split_whitespace().collect::<Vec<_>>().join(" ")
+                );
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Print the last progress line, unless it was just printed, and the final counts.
+    pub fn done(&self, converted: usize, skipped: usize, errors: usize) {
+        if !(self.processed.get() as u64).is_multiple_of(self.every) {
+            self.progress();
+        }
+        println!("done {converted} {skipped} {errors}");
+    }
+
+    fn progress(&self) {
+        let known = |value: Option<u64>| value.map_or_else(|| "?".to_string(), |v| v.to_string());
+        println!(
+            "progress {} {} {} {}",
+            self.processed.get(),
+            known(self.total.get()),
+            known(self.bytes_done.get()),
+            known(self.bytes_total.get())
+        );
+    }
+}
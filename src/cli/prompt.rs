@@ -0,0 +1,123 @@
+//! Questions asked on the terminal with --interactive before an existing file is replaced.
+
+use crate::cli::interrupt;
+use indicatif::ProgressBar;
+use mailfmt::Answer;
+use std::{
+    cell::Cell,
+    io::{self, IsTerminal},
+    path::Path,
+    sync::{
+        atomic::Ordering,
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::Duration,
+};
+
+/// Asks whether to replace existing files, remembering "all" and "quit" for the rest of the run
+/// and counting the answers for the summary.
+pub struct Prompter<'a> {
+    pb: &'a ProgressBar,
+    all: Cell<bool>,
+    quit: Cell<bool>,
+    answered: Cell<usize>,
+    replaced: Cell<usize>,
+    kept: Cell<usize>,
+}
+
+impl<'a> Prompter<'a> {
+    /// A prompter pausing the given progress bar while asking, or `None` when stdin is not a
+    /// terminal, so that existing files are an error as they are without --interactive.
+    pub fn new(pb: &'a ProgressBar) -> Option<Self> {
+        io::stdin().is_terminal().then(|| Self {
+            pb,
+            all: Cell::new(false),
+            quit: Cell::new(false),
+            answered: Cell::new(0),
+            replaced: Cell::new(0),
+            kept: Cell::new(0),
+        })
+    }
+
+    /// Ask whether to replace an existing file, unless an earlier answer covers it.
+    pub fn ask(&self, path: &Path) -> Answer {
+        if self.quit.get() {
+            return Answer::Quit;
+        }
+        if !self.all.get() {
+            let name = path
+                .file_name()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy();
+            let answer = self.pb.suspend(|| self.read_answer(&name));
+            self.answered.set(self.answered.get() + 1);
+            match answer {
+                Answer::No => {
+                    self.kept.set(self.kept.get() + 1);
+                    return Answer::No;
+                }
+                Answer::Quit => {
+                    self.quit.set(true);
+                    return Answer::Quit;
+                }
+                Answer::Yes => {}
+            }
+        }
+        self.replaced.set(self.replaced.get() + 1);
+        Answer::Yes
+    }
+
+    /// The answers given, for the summary, if any question was asked.
+    pub fn describe(&self) -> Option<String> {
+        (self.answered.get() > 0).then(|| {
+            format!(
+                "Answered {} prompts: {} files replaced and {} kept{}",
+                self.answered.get(),
+                self.replaced.get(),
+                self.kept.get(),
+                if self.quit.get() { ", then quit" } else { "" }
+            )
+        })
+    }
+
+    /// Prompt until a valid answer is given. The end of input quits, and so does an interrupt
+    /// signal, so that the conversion is cancelled and cleans up as it does between messages.
+    fn read_answer(&self, name: &str) -> Answer {
+        let interrupted = interrupt::flag();
+        loop {
+            eprint!("overwrite {name}? [y/N/a(ll)/q(uit)] ");
+            // The line is read on its own thread, as a read from the terminal cannot be woken up
+            // by a signal handler.
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                let mut line = String::new();
+                let _ = sender.send(io::stdin().read_line(&mut line).map(|read| (read, line)));
+            });
+            let line = loop {
+                if interrupted.load(Ordering::Relaxed) {
+                    eprintln!();
+                    return Answer::Quit;
+                }
+                match receiver.recv_timeout(Duration::from_millis(100)) {
+                    Ok(Ok((0, _)) | Err(_)) | Err(RecvTimeoutError::Disconnected) => {
+                        eprintln!();
+                        return Answer::Quit;
+                    }
+                    Ok(Ok((_, line))) => break line,
+                    Err(RecvTimeoutError::Timeout) => {}
+                }
+            };
+            match line.trim().to_ascii_lowercase().as_str() {
+                "y" | "yes" => return Answer::Yes,
+                "" | "n" | "no" => return Answer::No,
+                "q" | "quit" => return Answer::Quit,
+                "a" | "all" => {
+                    self.all.set(true);
+                    return Answer::Yes;
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use crate::cli::{
+    describe_error,
+    interrupt::{self, INTERRUPTED_EXIT_CODE},
+    output, paths, style_progress_bar,
+};
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use indicatif::{HumanBytes, HumanCount, ProgressBar, ProgressDrawTarget};
+use mailfmt::{Event, SizeBucket, SizeReport, SizeReportOptions, size_report};
+use serde_json::{Value, json};
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+/// Report on the messages in an .mbox file or a directory of .eml files.
+#[derive(Parser)]
+pub struct ReportCommand {
+    #[clap(subcommand)]
+    command: ReportSubcommand,
+}
+
+#[derive(Subcommand)]
+enum ReportSubcommand {
+    Sizes(SizesCommand),
+}
+
+impl ReportCommand {
+    /// Expand '~' and environment variables in the paths given as arguments.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        match &mut self.command {
+            ReportSubcommand::Sizes(cmd) => cmd.expand_paths(),
+        }
+    }
+
+    pub fn run(&self) -> Result<ExitCode> {
+        match &self.command {
+            ReportSubcommand::Sizes(cmd) => cmd.run(),
+        }
+    }
+}
+
+/// Examples shown in the long help and the man page.
+const EXAMPLES: &str = "\
+Examples:
+  List the 25 largest messages of an mbox:
+    mailfmt report sizes archive.mbox --top 25
+
+  Report on a directory of eml files as JSON:
+    mailfmt report sizes messages/ --json";
+
+/// List the largest messages, the total size of the messages of every sender and how message sizes are spread. Only the headers of messages are read into memory.
+#[derive(Parser)]
+#[clap(after_long_help = EXAMPLES)]
+struct SizesCommand {
+    /// An mbox file, or a directory searched recursively for .eml files.
+    input: PathBuf,
+
+    /// How many of the largest messages to list.
+    #[clap(long = "top", value_name = "N", default_value_t = 10)]
+    top: usize,
+
+    /// Read the input file even if it does not look like an mbox file.
+    #[clap(long = "force")]
+    force: bool,
+
+    /// Print the report to stdout as JSON instead of as tables.
+    #[clap(long = "json")]
+    json: bool,
+}
+
+impl SizesCommand {
+    fn expand_paths(&mut self) -> Result<()> {
+        self.input = paths::expand(&self.input).map_err(anyhow::Error::msg)?;
+        Ok(())
+    }
+
+    fn run(&self) -> Result<ExitCode> {
+        let pb = ProgressBar::hidden();
+        let mut options = SizeReportOptions::new(&self.input);
+        options.top = self.top;
+        options.force = self.force;
+        options.cancel = Some(interrupt::flag());
+        options.on_event = Some(Box::new(|event| {
+            Self::handle_event(&pb, event);
+            Ok(())
+        }));
+
+        let result = size_report(&options);
+        pb.finish_and_clear();
+        let report = result?;
+
+        if self.json {
+            println!("{:#}", Self::to_json(&report));
+        } else {
+            self.print_tables(&report);
+        }
+        if report.interrupted {
+            eprintln!(
+                "Report interrupted after {} messages, it is incomplete",
+                report.messages
+            );
+            return Ok(ExitCode::from(INTERRUPTED_EXIT_CODE));
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn handle_event(pb: &ProgressBar, event: Event<'_>) {
+        if output::logged(&event) {
+            return;
+        }
+        match event {
+            Event::Started { total, position } => {
+                style_progress_bar(pb, total);
+                pb.set_position(position);
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+            Event::Progress {
+                position,
+                processed,
+            } => {
+                pb.set_position(position);
+                pb.set_message(format!("{} emails read", HumanCount(processed as u64)));
+            }
+            Event::Failed { item, error } => pb.println(output::error(format!(
+                "Error {item}: {}",
+                describe_error(error)
+            ))),
+            Event::Warning(message) => {
+                pb.suspend(|| eprintln!("{}", output::warning(format!("Warning: {message}"))))
+            }
+            _ => {}
+        }
+    }
+
+    fn print_tables(&self, report: &SizeReport) {
+        println!(
+            "{} emails, {} in total",
+            report.messages,
+            HumanBytes(report.total_bytes)
+        );
+        if !report.errors.is_empty() {
+            println!("{} files could not be read", report.errors.len());
+        }
+
+        println!();
+        println!("Largest emails:");
+        print_table(
+            ["Email", "Size", "Date", "From", "Subject"],
+            report.largest.iter().map(|message| {
+                [
+                    message.file.as_ref().map_or_else(
+                        || message.index.to_string(),
+                        |file| {
+                            file.strip_prefix(&self.input)
+                                .unwrap_or(file)
+                                .display()
+                                .to_string()
+                        },
+                    ),
+                    HumanBytes(message.size).to_string(),
+                    message.date.clone().unwrap_or_default(),
+                    message.sender.clone().unwrap_or_default(),
+                    message.subject.clone().unwrap_or_default(),
+                ]
+            }),
+        );
+
+        println!();
+        println!("Senders:");
+        print_table(
+            ["Size", "Emails", "Sender"],
+            report.senders.iter().map(|sender| {
+                [
+                    HumanBytes(sender.bytes).to_string(),
+                    sender.messages.to_string(),
+                    sender.sender.clone(),
+                ]
+            }),
+        );
+
+        println!();
+        println!("Sizes:");
+        print_table(
+            ["Range", "Emails", "Size"],
+            report.histogram.iter().map(|bucket| {
+                [
+                    describe_bucket(bucket),
+                    bucket.messages.to_string(),
+                    HumanBytes(bucket.bytes).to_string(),
+                ]
+            }),
+        );
+    }
+
+    fn to_json(report: &SizeReport) -> Value {
+        let largest: Vec<Value> = report
+            .largest
+            .iter()
+            .map(|message| {
+                json!({
+                    "index": message.index,
+                    "file": message.file.as_ref().map(|file| file.display().to_string()),
+                    "date": message.date,
+                    "sender": message.sender,
+                    "subject": message.subject,
+                    "size": message.size,
+                })
+            })
+            .collect();
+        let senders: Vec<Value> = report
+            .senders
+            .iter()
+            .map(|sender| {
+                json!({
+                    "sender": sender.sender,
+                    "messages": sender.messages,
+                    "bytes": sender.bytes,
+                })
+            })
+            .collect();
+        let histogram: Vec<Value> = report
+            .histogram
+            .iter()
+            .map(|bucket| {
+                json!({
+                    "min": bucket.min,
+                    "max": bucket.max,
+                    "messages": bucket.messages,
+                    "bytes": bucket.bytes,
+                })
+            })
+            .collect();
+        json!({
+            "messages": report.messages,
+            "total_bytes": report.total_bytes,
+            "largest": largest,
+            "senders": senders,
+            "histogram": histogram,
+            "errors": report.errors.len(),
+            "interrupted": report.interrupted,
+        })
+    }
+}
+
+/// Print rows under a header, with every column but the last padded to the same width.
+fn print_table<const N: usize>(header: [&str; N], rows: impl Iterator<Item = [String; N]>) {
+    let rows: Vec<[String; N]> = std::iter::once(header.map(str::to_string))
+        .chain(rows)
+        .collect();
+    let mut widths = [0; N];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in &rows {
+        let mut line = String::new();
+        for (column, (cell, width)) in row.iter().zip(widths).enumerate() {
+            if column + 1 == N {
+                line.push_str(cell);
+            } else {
+                line.push_str(&format!("{cell:width$}  "));
+            }
+        }
+        println!("{}", line.trim_end());
+    }
+}
+
+/// A bucket's range, such as "10 KiB - 100 KiB".
+fn describe_bucket(bucket: &SizeBucket) -> String {
+    match (bucket.min, bucket.max) {
+        (0, Some(max)) => format!("< {}", HumanBytes(max)),
+        (min, Some(max)) => format!("{} - {}", HumanBytes(min), HumanBytes(max)),
+        (min, None) => format!(">= {}", HumanBytes(min)),
+    }
+}
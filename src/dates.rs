@@ -0,0 +1,124 @@
+//! The date of a message, from wherever the best record of it is. A Date header is missing
+//! from plenty of spam and malformed mail, while the separator line of an mbox carries the
+//! date the delivering mail server wrote, in the asctime format of `Mon Jan  1 00:00:00 2024`.
+
+use crate::{DateSource, writer::parse_date};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, TimeZone, Utc};
+use std::time::SystemTime;
+
+/// Where the date of a message can be taken from. Any of them can be missing.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct DateSources<'a> {
+    /// The value of its Date header.
+    pub header: Option<&'a str>,
+    /// Its separator line after "From ", the envelope sender and date.
+    pub envelope: Option<&'a str>,
+    /// The unfolded value of its first Received header, the one added last.
+    pub received: Option<&'a str>,
+    /// When the file holding it was last modified.
+    pub modified: Option<SystemTime>,
+}
+
+/// The date of a message and where it was taken from, trying in order its Date header, the
+/// date on its separator line, the date its last mail server received it at, and the time
+/// its file was last modified. A source that is missing or cannot be parsed is passed over,
+/// and `None` is returned when none is left, for the caller to use its own default.
+pub(crate) fn message_date(
+    sources: DateSources<'_>,
+) -> Option<(DateTime<FixedOffset>, DateSource)> {
+    let DateSources {
+        header,
+        envelope,
+        received,
+        modified,
+    } = sources;
+    header
+        .and_then(parse_date)
+        .map(|date| (date, DateSource::Header))
+        .or_else(|| envelope_date(envelope?).map(|date| (date, DateSource::Separator)))
+        .or_else(|| received_date(received?).map(|date| (date, DateSource::Received)))
+        .or_else(|| {
+            let modified = DateTime::<Utc>::from(modified?).fixed_offset();
+            Some((modified, DateSource::FileTime))
+        })
+}
+
+/// The date on a separator line after "From ", such as `sender Mon Jan  1 00:00:00 2024`.
+/// The day may have one or two digits, the seconds may be missing, and a time zone may come
+/// before or after the year, either as an offset such as `+0100` or as a common name such as
+/// `EST`. Dates without one are taken to be in UTC. Anything after the year, such as the
+/// `remote from` of UUCP, is ignored.
+pub(crate) fn envelope_date(envelope: &str) -> Option<DateTime<FixedOffset>> {
+    let words: Vec<&str> = envelope.split_whitespace().collect();
+    // The sender comes first, and may hold spaces when quoted, so look for the month after it.
+    let start = (1..words.len()).find(|&i| {
+        month(words[i]).is_some()
+            && words
+                .get(i + 1)
+                .is_some_and(|day| day.parse::<u32>().is_ok())
+    })?;
+    let month = month(words[start])?;
+    let day = words[start + 1].parse().ok()?;
+    let time = words.get(start + 2)?;
+    let time = NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .ok()?;
+    let (mut year, mut offset) = (None, None);
+    for word in words.iter().skip(start + 3).take(2) {
+        match word.parse::<i32>() {
+            Ok(number) if word.len() == 4 => year = Some(number),
+            _ => match zone_offset(word) {
+                Some(zone) if offset.is_none() => offset = Some(zone),
+                _ => break,
+            },
+        }
+    }
+    let offset = FixedOffset::east_opt(offset.unwrap_or(0))?;
+    let date = NaiveDate::from_ymd_opt(year?, month, day)?.and_time(time);
+    offset.from_local_datetime(&date).single()
+}
+
+/// The date at the end of a Received header, after its last ';'.
+fn received_date(received: &str) -> Option<DateTime<FixedOffset>> {
+    parse_date(received.rsplit_once(';')?.1.trim())
+}
+
+/// The number of a month from its English abbreviation.
+fn month(name: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let position = MONTHS
+        .iter()
+        .position(|month| name.eq_ignore_ascii_case(month))?;
+    Some(position as u32 + 1)
+}
+
+/// The offset from UTC in seconds of a time zone such as `+0100` or `EST`.
+fn zone_offset(zone: &str) -> Option<i32> {
+    if let Some(digits) = zone.strip_prefix(['+', '-'])
+        && digits.len() == 4
+        && digits.bytes().all(|b| b.is_ascii_digit())
+    {
+        let hours: i32 = digits[..2].parse().ok()?;
+        let minutes: i32 = digits[2..].parse().ok()?;
+        let seconds = hours * 3600 + minutes * 60;
+        return Some(if zone.starts_with('-') {
+            -seconds
+        } else {
+            seconds
+        });
+    }
+    let hours = match zone.to_ascii_uppercase().as_str() {
+        "UT" | "UTC" | "GMT" | "Z" => 0,
+        "EDT" => -4,
+        "EST" | "CDT" => -5,
+        "CST" | "MDT" => -6,
+        "MST" | "PDT" => -7,
+        "PST" => -8,
+        "BST" | "CET" => 1,
+        "CEST" | "EET" => 2,
+        _ => return None,
+    };
+    Some(hours * 3600)
+}
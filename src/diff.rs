@@ -0,0 +1,465 @@
+//! Comparing two mailboxes by the Message-IDs of their messages, to check that nothing was lost
+//! or changed when moving mail from one to the other.
+
+use crate::{
+    Event, EventHandler, MailfmtError, MessageError, ProgressTotal,
+    counting::CountingReader,
+    duplicates::normalize_message_id,
+    error::{IoContext, Result},
+    export::{find_eml_files, read_eml_file},
+    gzip,
+    headers::{FOLDER_HEADER, SOURCE_FILE_HEADER, get_header_value_from_lines},
+    mbox::{MboxParser, check_mbox_input},
+    msgid_index::content_key,
+};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tracing::info_span;
+
+/// Options for [`diff_mailboxes`]. Create them with [`DiffOptions::new`] and adjust the fields
+/// as needed.
+pub struct DiffOptions<'a> {
+    /// The mailbox to compare against, such as the one mail was migrated from: an mbox file, a
+    /// Maildir, or a directory searched recursively for .eml files.
+    pub left: PathBuf,
+    /// The mailbox to compare, such as the one mail was migrated to, of any of the same kinds.
+    pub right: PathBuf,
+    /// Read an input file even if it does not look like an mbox file.
+    pub force: bool,
+    /// Set to stop after the current message. The comparison then only covers the messages
+    /// read so far, so it is bound to report differences.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called with progress and unreadable messages as both sides are read, one after the
+    /// other.
+    pub on_event: Option<EventHandler<'a>>,
+}
+
+impl DiffOptions<'_> {
+    pub fn new(left: impl Into<PathBuf>, right: impl Into<PathBuf>) -> Self {
+        Self {
+            left: left.into(),
+            right: right.into(),
+            force: false,
+            cancel: None,
+            on_event: None,
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// The kinds of mailboxes that can be compared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MailboxKind {
+    Mbox,
+    /// A directory with `cur` or `new` subdirectories, whose files are the messages. Its
+    /// subfolders are not read.
+    Maildir,
+    /// A directory searched recursively for .eml files.
+    EmlDirectory,
+}
+
+impl MailboxKind {
+    /// The kind of mailbox at a path: a directory is a Maildir if it has a `cur` or `new`
+    /// subdirectory, and any other path is an mbox file.
+    pub fn detect(path: &Path) -> Self {
+        if !path.is_dir() {
+            Self::Mbox
+        } else if path.join("cur").is_dir() || path.join("new").is_dir() {
+            Self::Maildir
+        } else {
+            Self::EmlDirectory
+        }
+    }
+}
+
+impl fmt::Display for MailboxKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Mbox => "mbox",
+            Self::Maildir => "maildir",
+            Self::EmlDirectory => "eml directory",
+        })
+    }
+}
+
+/// What [`diff_mailboxes`] found when comparing two mailboxes.
+#[derive(Debug)]
+pub struct MailboxDiff {
+    pub left: DiffSide,
+    pub right: DiffSide,
+    /// The messages only found on the left, by key.
+    pub only_left: Vec<DiffEntry>,
+    /// The messages only found on the right, by key.
+    pub only_right: Vec<DiffEntry>,
+    /// The keys found on both sides but with different content, or on a different number of
+    /// messages.
+    pub changed: Vec<DiffEntry>,
+    /// How many keys were found on both sides with the same content.
+    pub matching: usize,
+    /// Whether the comparison was cancelled before both sides were read.
+    pub interrupted: bool,
+    pub elapsed: Duration,
+}
+
+impl MailboxDiff {
+    /// Whether both mailboxes hold the same messages, all of them readable.
+    pub fn is_same(&self) -> bool {
+        self.only_left.is_empty()
+            && self.only_right.is_empty()
+            && self.changed.is_empty()
+            && self.left.errors.is_empty()
+            && self.right.errors.is_empty()
+            && !self.interrupted
+    }
+}
+
+/// One of the mailboxes compared by [`diff_mailboxes`].
+#[derive(Debug)]
+pub struct DiffSide {
+    pub path: PathBuf,
+    pub kind: MailboxKind,
+    /// How many messages were read.
+    pub messages: usize,
+    /// The messages or files that could not be read, and are left out of the comparison.
+    pub errors: Vec<MessageError>,
+}
+
+/// A key found on only one side, or with different content on both.
+#[derive(Debug)]
+pub struct DiffEntry {
+    /// The Message-ID, as given by [`normalize_message_id`], or for a message without one the
+    /// `sha256:` hash of its content.
+    pub key: String,
+    /// Whether the key is a content hash rather than a Message-ID.
+    pub synthetic: bool,
+    /// The messages with the key on the left.
+    pub left: Vec<DiffLocation>,
+    /// The messages with the key on the right.
+    pub right: Vec<DiffLocation>,
+}
+
+/// Where a compared message was found.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DiffLocation {
+    /// The byte offset of its separator line in an mbox file.
+    Offset(u64),
+    /// Its file in a Maildir or a directory of eml files.
+    File(PathBuf),
+}
+
+impl fmt::Display for DiffLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Offset(offset) => write!(f, "offset {offset}"),
+            Self::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Headers left out of the content hashes, as they record where a message is kept or were
+/// added when converting rather than being part of the message: the status flags of mail
+/// clients, Content-Length and the headers recording eml files.
+const IGNORED_HEADERS: &[&str] = &[
+    "content-length",
+    "status",
+    "x-status",
+    "x-keywords",
+    "x-uid",
+    FOLDER_HEADER,
+    SOURCE_FILE_HEADER,
+];
+
+/// The key of a message and whether it is synthetic, a content hash rather than a Message-ID.
+type Key = (String, bool);
+
+/// The messages of one side by key, with the content hash and location of each.
+type Keys = HashMap<Key, Vec<(String, DiffLocation)>>;
+
+/// Compare two mailboxes, each an mbox file, a Maildir or a directory of eml files, by the
+/// Message-IDs of their messages. Messages without a Message-ID are keyed by a hash of their
+/// content. Both sides are read one message at a time, keeping only the keys, content hashes
+/// and locations in memory.
+///
+/// Content hashes ignore line endings, trailing blank lines, the quoting of "From " lines in
+/// mbox files and the headers that converting or filing a message adds, so that a message
+/// moved between formats compares equal.
+///
+/// ```
+/// use mailfmt::{DiffOptions, diff_mailboxes};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-diff-{}", std::process::id()));
+/// fs::create_dir_all(dir.join("emls"))?;
+/// fs::write(
+///     dir.join("in.mbox"),
+///     "From a Mon Jan 01 00:00:00 2024\n\
+///      Message-ID: <one@example.com>\n\
+///      \n\
+///      >From here\n\
+///      \n\
+///      From b Mon Jan 01 00:00:00 2024\n\
+///      Message-ID: <two@example.com>\n\
+///      \n\
+///      Lost\n",
+/// )?;
+/// fs::write(dir.join("emls/one.eml"), "Message-ID: <one@example.com>\r\n\r\nFrom here\r\n")?;
+///
+/// let diff = diff_mailboxes(&DiffOptions::new(dir.join("in.mbox"), dir.join("emls")))?;
+/// assert_eq!((diff.left.messages, diff.right.messages, diff.matching), (2, 1, 1));
+/// assert_eq!(diff.only_left[0].key, "two@example.com");
+/// assert!(diff.only_right.is_empty() && diff.changed.is_empty());
+/// assert!(!diff.is_same());
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn diff_mailboxes(options: &DiffOptions<'_>) -> Result<MailboxDiff> {
+    let _span = info_span!("diff_mailboxes", left = %options.left.display(), right = %options.right.display()).entered();
+    let start = Instant::now();
+    let (left, left_keys, left_interrupted) = read_side(&options.left, options)?;
+    let (right, mut right_keys, right_interrupted) = match left_interrupted {
+        true => (empty_side(&options.right), Keys::new(), true),
+        false => read_side(&options.right, options)?,
+    };
+
+    let mut diff = MailboxDiff {
+        left,
+        right,
+        only_left: Vec::new(),
+        only_right: Vec::new(),
+        changed: Vec::new(),
+        matching: 0,
+        interrupted: left_interrupted || right_interrupted,
+        elapsed: Duration::ZERO,
+    };
+    for (key, left) in left_keys {
+        match right_keys.remove(&key) {
+            None => diff.only_left.push(entry(key, left, Vec::new())),
+            Some(right) if sorted_hashes(&left) == sorted_hashes(&right) => diff.matching += 1,
+            Some(right) => diff.changed.push(entry(key, left, right)),
+        }
+    }
+    for (key, right) in right_keys {
+        diff.only_right.push(entry(key, Vec::new(), right));
+    }
+    for entries in [&mut diff.only_left, &mut diff.only_right, &mut diff.changed] {
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+    }
+    diff.elapsed = start.elapsed();
+    Ok(diff)
+}
+
+fn empty_side(path: &Path) -> DiffSide {
+    DiffSide {
+        path: path.to_path_buf(),
+        kind: MailboxKind::detect(path),
+        messages: 0,
+        errors: Vec::new(),
+    }
+}
+
+fn entry(
+    (key, synthetic): Key,
+    left: Vec<(String, DiffLocation)>,
+    right: Vec<(String, DiffLocation)>,
+) -> DiffEntry {
+    DiffEntry {
+        key,
+        synthetic,
+        left: locations(left),
+        right: locations(right),
+    }
+}
+
+/// The content hashes of the messages with a key, sorted so that they can be compared.
+fn sorted_hashes(messages: &[(String, DiffLocation)]) -> Vec<&str> {
+    let mut hashes: Vec<_> = messages.iter().map(|(hash, _)| hash.as_str()).collect();
+    hashes.sort_unstable();
+    hashes
+}
+
+/// The locations of messages, in the order they were read.
+fn locations(messages: Vec<(String, DiffLocation)>) -> Vec<DiffLocation> {
+    let mut locations: Vec<_> = messages.into_iter().map(|(_, location)| location).collect();
+    locations.sort();
+    locations
+}
+
+/// Read every message of a mailbox, returning it with the keys of its messages and whether
+/// reading it was cancelled.
+fn read_side(path: &Path, options: &DiffOptions<'_>) -> Result<(DiffSide, Keys, bool)> {
+    let mut side = empty_side(path);
+    let mut keys = Keys::new();
+    let mut add = |lines: &[Vec<u8>], location: DiffLocation| {
+        let hash = content_hash(lines);
+        let key =
+            match get_header_value_from_lines(lines, "message-id").and_then(normalize_message_id) {
+                Some(message_id) => (message_id, false),
+                None => (hash.clone(), true),
+            };
+        keys.entry(key).or_default().push((hash, location));
+    };
+    let mut interrupted = false;
+
+    if side.kind == MailboxKind::Mbox {
+        check_mbox_input(path, options.force, false)?;
+        let input_size = gzip::content_size(path).io_context("read metadata of mbox file", path)?;
+        let reader = CountingReader::new(gzip::open(path, 0).io_context("open mbox file", path)?);
+        let bytes_read = reader.counter();
+        options.emit(Event::Started {
+            total: match input_size {
+                0 => ProgressTotal::Unknown,
+                size => ProgressTotal::Bytes(size),
+            },
+            position: 0,
+        })?;
+        for message in MboxParser::from_reader(BufReader::new(reader)) {
+            if options.is_cancelled() {
+                interrupted = true;
+                break;
+            }
+            match message {
+                Ok(message) => {
+                    side.messages += 1;
+                    add(&message.lines, DiffLocation::Offset(message.offset));
+                }
+                Err(error) => {
+                    let item = format!(
+                        "reading email {} of {path:?}",
+                        side.messages + side.errors.len()
+                    );
+                    options.emit(Event::Failed {
+                        item: item.clone(),
+                        error: &error,
+                    })?;
+                    side.errors.push(MessageError { item, error });
+                }
+            }
+            options.emit(Event::Progress {
+                position: bytes_read.load(Ordering::Relaxed),
+                processed: side.messages + side.errors.len(),
+            })?;
+        }
+        return Ok((side, keys, interrupted));
+    }
+
+    let files = match side.kind {
+        MailboxKind::Maildir => find_maildir_files(path)?,
+        _ => match find_eml_files(path) {
+            // An empty directory is a mailbox without messages.
+            Err(MailfmtError::NoInputFiles { .. }) => Vec::new(),
+            files => files?,
+        },
+    };
+    options.emit(Event::Started {
+        total: ProgressTotal::Messages(files.len() as u64),
+        position: 0,
+    })?;
+    for (index, file) in files.into_iter().enumerate() {
+        if options.is_cancelled() {
+            interrupted = true;
+            break;
+        }
+        match read_eml_file(&file) {
+            Ok(lines) => {
+                side.messages += 1;
+                add(&lines, DiffLocation::File(file));
+            }
+            Err(error) => {
+                let item = format!("reading {file:?}");
+                options.emit(Event::Failed {
+                    item: item.clone(),
+                    error: &error,
+                })?;
+                side.errors.push(MessageError { item, error });
+            }
+        }
+        options.emit(Event::Progress {
+            position: index as u64 + 1,
+            processed: index + 1,
+        })?;
+    }
+    Ok((side, keys, interrupted))
+}
+
+/// The sorted message files in the `cur` and `new` subdirectories of a Maildir, leaving out
+/// hidden files.
+fn find_maildir_files(maildir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for subdirectory in ["cur", "new"] {
+        let dir = maildir.join(subdirectory);
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&dir).io_context("read directory", &dir)? {
+            let path = entry.io_context("read directory", &dir)?.path();
+            let hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if !hidden && path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The `sha256:` hash of a message's content, leaving out [`IGNORED_HEADERS`], trailing blank
+/// lines and the quoting of ">From " lines, with CRLF line endings read as LF.
+fn content_hash(lines: &[Vec<u8>]) -> String {
+    let mut kept: Vec<&[u8]> = Vec::with_capacity(lines.len());
+    let (mut in_header, mut ignoring) = (true, false);
+    for line in lines {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if in_header {
+            if line.is_empty() {
+                in_header = false;
+            } else if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+                ignoring =
+                    String::from_utf8_lossy(line)
+                        .split_once(':')
+                        .is_some_and(|(name, _)| {
+                            IGNORED_HEADERS
+                                .iter()
+                                .any(|ignored| name.trim().eq_ignore_ascii_case(ignored))
+                        });
+            }
+            if in_header && ignoring {
+                continue;
+            }
+        }
+        // Lines are quoted again by every conversion that does not unquote them first, so
+        // every level of quoting is left out.
+        let unquoted = &line[line.iter().take_while(|&&b| b == b'>').count()..];
+        kept.push(match unquoted.starts_with(b"From ") {
+            true => unquoted,
+            false => line,
+        });
+    }
+    while kept.last().is_some_and(|line| line.is_empty()) {
+        kept.pop();
+    }
+    content_key(kept)
+}
@@ -0,0 +1,78 @@
+//! Messages that share a Message-ID, found while converting.
+
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, path::PathBuf};
+
+/// A Message-ID found on more than one message of a conversion.
+#[derive(Debug)]
+pub struct DuplicateGroup {
+    /// The Message-ID, as given by [`normalize_message_id`] for its first message.
+    pub message_id: String,
+    /// The messages with the Message-ID, in the order they were converted.
+    pub messages: Vec<MessageLocation>,
+}
+
+/// Where a converted message came from or went to.
+#[derive(Debug)]
+pub struct MessageLocation {
+    /// The number of the message among those converted, or among those already in an mbox
+    /// that eml files were appended to.
+    pub index: usize,
+    /// For mbox-to-eml the eml file written, and for eml-to-mbox the eml file read, or the
+    /// mbox file appended to for the messages it already held.
+    pub file: PathBuf,
+}
+
+/// The Message-ID in a Message-ID header value, without the whitespace and angle brackets
+/// around it, so that IDs written differently compare equal. Returns `None` for an empty one.
+///
+/// ```
+/// use mailfmt::normalize_message_id;
+///
+/// assert_eq!(normalize_message_id(" <abc@example.com> ").as_deref(), Some("abc@example.com"));
+/// assert_eq!(normalize_message_id("abc@example.com").as_deref(), Some("abc@example.com"));
+/// assert_eq!(normalize_message_id("< >"), None);
+/// ```
+pub fn normalize_message_id(value: &str) -> Option<String> {
+    let id = value.trim();
+    let id = id.strip_prefix('<').unwrap_or(id);
+    let id = id.strip_suffix('>').unwrap_or(id).trim();
+    (!id.is_empty()).then(|| id.to_string())
+}
+
+/// Tracks the Message-IDs of converted messages by a hash of each, keeping an ID itself only
+/// once it has been seen twice.
+#[derive(Default)]
+pub(crate) struct Duplicates {
+    first: HashMap<[u8; 16], MessageLocation>,
+    groups: HashMap<[u8; 16], usize>,
+    found: Vec<DuplicateGroup>,
+}
+
+impl Duplicates {
+    /// Record a converted message with the given Message-ID header value, if it has one.
+    pub(crate) fn record(&mut self, message_id: Option<&str>, location: MessageLocation) {
+        let Some(message_id) = message_id.and_then(normalize_message_id) else {
+            return;
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(message_id.as_bytes());
+        let hash: [u8; 16] = hasher.finalize()[..16].try_into().unwrap();
+        if let Some(&group) = self.groups.get(&hash) {
+            self.found[group].messages.push(location);
+        } else if let Some(first) = self.first.remove(&hash) {
+            self.groups.insert(hash, self.found.len());
+            self.found.push(DuplicateGroup {
+                message_id,
+                messages: vec![first, location],
+            });
+        } else {
+            self.first.insert(hash, location);
+        }
+    }
+
+    /// The Message-IDs seen more than once, in the order their second message was converted.
+    pub(crate) fn into_groups(self) -> Vec<DuplicateGroup> {
+        self.found
+    }
+}
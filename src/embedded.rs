@@ -0,0 +1,136 @@
+//! Messages embedded in others as message/rfc822 parts, such as the messages of a mailing list
+//! digest or one forwarded as an attachment, for extracting them to eml files of their own.
+
+use crate::{
+    atomic::AtomicFile,
+    error::{IoContext, Result},
+    mime::for_each_embedded,
+};
+use serde_json::json;
+use std::{
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// The name of the manifest [`MboxToEmlOptions::explode_rfc822`](crate::MboxToEmlOptions::explode_rfc822)
+/// writes to the output directory.
+pub const EMBEDDED_MANIFEST_FILE_NAME: &str = "embedded.jsonl";
+
+/// How deeply embedded messages are looked for inside of other embedded messages, so that a
+/// message nesting copies of itself cannot produce an unbounded number of files.
+pub const MAX_EMBEDDED_DEPTH: usize = 4;
+
+/// A message found inside of another.
+pub struct Embedded {
+    /// Its position among the messages embedded in the outermost message, such as `2` for the
+    /// second message embedded in it and `2.1` for the first message embedded in that one.
+    pub number: String,
+    /// Its lines, decoded from the transfer encoding of its part, or the transfer encoding if
+    /// it cannot be decoded.
+    pub lines: std::result::Result<Vec<Vec<u8>>, String>,
+}
+
+impl Embedded {
+    /// The number of the message this one is embedded in, or `None` for the outermost one.
+    pub fn parent_number(&self) -> Option<&str> {
+        self.number.rsplit_once('.').map(|(parent, _)| parent)
+    }
+}
+
+/// Every message embedded in a message, parents before their children, down to
+/// [`MAX_EMBEDDED_DEPTH`] levels. Also returns whether messages nested deeper were left inside
+/// of their parents.
+pub fn embedded_messages(lines: &[Vec<u8>]) -> (Vec<Embedded>, bool) {
+    fn walk(lines: &[Vec<u8>], prefix: &str, depth: usize, found: &mut Vec<Embedded>) -> bool {
+        let mut parts = Vec::new();
+        for_each_embedded(lines, &mut |part| {
+            parts.push(
+                part.decode_body()
+                    .map(split_lines)
+                    .ok_or_else(|| part.transfer_encoding()),
+            );
+        });
+        if parts.is_empty() {
+            return false;
+        }
+        if depth >= MAX_EMBEDDED_DEPTH {
+            return true;
+        }
+        let mut truncated = false;
+        for (i, part) in parts.into_iter().enumerate() {
+            let number = match prefix {
+                "" => (i + 1).to_string(),
+                prefix => format!("{prefix}.{}", i + 1),
+            };
+            let mut children = Vec::new();
+            if let Ok(lines) = &part {
+                truncated |= walk(lines, &number, depth + 1, &mut children);
+            }
+            found.push(Embedded {
+                number,
+                lines: part,
+            });
+            found.append(&mut children);
+        }
+        truncated
+    }
+
+    let mut found = Vec::new();
+    let truncated = walk(lines, "", 0, &mut found);
+    (found, truncated)
+}
+
+/// Split decoded content into lines without their line endings, as an mbox message is.
+fn split_lines(content: Vec<u8>) -> Vec<Vec<u8>> {
+    let content = content.strip_suffix(b"\n").unwrap_or(&content);
+    content
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+        .collect()
+}
+
+/// Writes the manifest of embedded messages under a temporary name as they are extracted,
+/// moving it into place once the extraction is over. Every line holds a JSON object with the
+/// `file` of an embedded message and the `parent` file it was found in, both relative to the
+/// output directory, and its `number` among the messages embedded in the outermost one.
+pub struct EmbeddedManifest {
+    file: BufWriter<AtomicFile>,
+    path: PathBuf,
+    output_dir: PathBuf,
+}
+
+impl EmbeddedManifest {
+    pub fn create(output_dir: &Path) -> Result<Self> {
+        let path = output_dir.join(EMBEDDED_MANIFEST_FILE_NAME);
+        let file = BufWriter::new(
+            AtomicFile::create(&path).io_context("create embedded message manifest", &path)?,
+        );
+        Ok(Self {
+            file,
+            path,
+            output_dir: output_dir.to_path_buf(),
+        })
+    }
+
+    pub fn add(&mut self, file: &Path, parent: &Path, number: &str) -> Result<()> {
+        let relative = |path: &Path| {
+            let path = path.strip_prefix(&self.output_dir).unwrap_or(path);
+            path.to_string_lossy().into_owned()
+        };
+        let entry = json!({
+            "file": relative(file),
+            "parent": relative(parent),
+            "number": number,
+        });
+        writeln!(self.file, "{entry}").io_context("write embedded message manifest", &self.path)
+    }
+
+    pub fn finish(self) -> Result<()> {
+        self.file
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .io_context("write embedded message manifest", &self.path)?
+            .commit()
+            .io_context("move embedded message manifest into place", &self.path)
+    }
+}
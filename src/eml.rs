@@ -1,144 +1,1773 @@
-use crate::validate_output_file;
-use anyhow::{Context, Result, bail};
-use chrono::DateTime;
-use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
+use crate::{
+    Answer, ConfirmHandler, ConversionSummary, DateSource, Event, EventHandler, MailfmtError,
+    MessageCount, MessageDetails, MessageError, ProgressTotal, WatchSummary,
+    atomic::AtomicFile,
+    counting::CountingReader,
+    dates::{DateSources, message_date},
+    duplicates::{Duplicates, MessageLocation},
+    encoding::{InvalidUtf8, cr_line_endings, strip_bom},
+    error::{IoContext, Result},
+    gzip,
+    headers::{
+        FOLDER_HEADER, SOURCE_FILE_HEADER, encode_header, get_header_value,
+        get_header_value_from_lines, is_header_line,
+    },
+    lock::MboxLock,
+    mbox::{check_output_directory, create_output_directory, is_separator, scan_header_blocks},
+    mime::Entity,
+    msgid_index::{IndexLocation, IndexWriter, content_key},
+    paths::{self, GLOB_OPTIONS, sync_directory},
+    sidecar,
+    space::{self, SEPARATOR_OVERHEAD},
+    state::{FileRecord, IncrementalState},
+    strip::{Stripped, strip_attachments},
+    validate::validate_eml,
+    verify,
+    writer::{
+        FromLineStyle, MboxDialect, MboxWriter, PLACEHOLDER_DATE, PLACEHOLDER_SENDER, asctime,
+        envelope_parts,
+    },
+};
+use chrono::{DateTime, FixedOffset};
+use glob::Pattern;
+use notify::{EventKind, RecursiveMode, Watcher, event::ModifyKind};
 use std::{
-    fs::{self, File},
-    io::Write,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
-    time::Duration,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
+use tracing::info_span;
+
+/// Where the eml files to convert come from.
+pub enum EmlInput {
+    /// A directory that is searched recursively for files matching the include patterns, or a
+    /// glob pattern such as `./export/2023-*/**/*.eml` matching files and directories.
+    Directory(PathBuf),
+    /// An explicit list of files, converted in the given order.
+    Files(Vec<PathBuf>),
+}
 
-/// Convert a directory of .eml files to a single .mbox file.
-#[derive(Parser)]
-pub struct ConvertToMboxCommand {
-    input_directory: PathBuf,
+/// Options for [`eml_to_mbox`]. Create them with [`EmlToMboxOptions::new`] and adjust the
+/// fields as needed.
+pub struct EmlToMboxOptions<'a> {
+    pub input: EmlInput,
+    pub output_file: PathBuf,
+    /// Replace the output file if it already exists.
+    pub overwrite: bool,
+    /// Create the directories above the output file that do not exist yet, instead of failing
+    /// with [`MailfmtError::OutputParentMissing`].
+    pub create_parents: bool,
+    /// Append to the output file if it already exists. Together with `state_file`, only eml
+    /// files that are new or changed since the previous run are appended.
+    pub append: bool,
+    /// File recording which eml files were converted, by path, size, modification time and
+    /// content hash. It is rewritten after every run and read back by runs using `append`.
+    pub state_file: Option<PathBuf>,
+    /// Also lock the output with a traditional `<name>.lock` file while writing it.
+    pub dotlock: bool,
+    /// How long to wait for other programs to release their lock on the output.
+    pub lock_timeout: Duration,
+    /// The mbox dialect to write, deciding how body lines starting with "From " are quoted.
+    pub dialect: MboxDialect,
+    /// How separator lines are written. With [`FromLineStyle::Thunderbird`], the sender is
+    /// replaced by a dash and only the date is kept.
+    pub from_line_style: FromLineStyle,
+    /// What to do with eml files containing bytes that are not valid UTF-8.
+    pub invalid_utf8: InvalidUtf8,
+    /// Keep a byte-order mark at the start of an eml file instead of removing it. A kept mark
+    /// ends up in front of the first header's name, hiding that header.
+    pub keep_bom: bool,
+    /// Replace the attachments of multipart messages with short text parts recording their
+    /// file name, media type, decoded size and SHA-256, keeping the messages valid MIME.
+    pub strip_attachments: bool,
+    /// When stripping attachments, also replace messages that are a single attachment, such
+    /// as a bare PDF, instead of leaving them as they are.
+    pub strip_single_part: bool,
+    /// Record the folder each eml file was found in, relative to the input directory, in an
+    /// [`X-Folder`](crate::headers::FOLDER_HEADER) header added to the top of its message,
+    /// replacing any it already had. Files directly in the input directory get none. Extracting
+    /// with [`GroupBy::Folder`](crate::GroupBy::Folder) recreates the folders.
+    pub record_folder: bool,
+    /// Record the path of each eml file, relative to the input directory, or its file name
+    /// when given on its own, in an [`X-Source-File`](crate::headers::SOURCE_FILE_HEADER)
+    /// header added to the top of its message. Without it, any such header the eml files
+    /// already have is removed. Extracting with [`MboxToEmlOptions::restore_names`] names the
+    /// eml files after it again.
+    pub record_source: bool,
+    /// Size in bytes of the buffer used when writing the mbox file.
+    pub buffer_size: usize,
+    /// Sync the mbox file and its parent directory to disk once it has been written.
+    pub fsync: bool,
+    /// Write the mbox even when every eml file found fails to convert, instead of returning
+    /// [`MailfmtError::AllFailed`].
+    pub allow_empty: bool,
+    /// Leave out eml files that do not start with a plausible header block, made only of
+    /// header fields up to the first blank line, or that lack one of `require_headers`. They
+    /// are counted as skipped and listed in [`ConversionSummary::invalid`] rather than as
+    /// errors. Without it, only files whose first line is not a header field are rejected,
+    /// as errors.
+    pub skip_invalid: bool,
+    /// With `skip_invalid`, the names of the headers every eml file needs, with a value, to be
+    /// converted, such as `from` and `message-id`.
+    pub require_headers: Vec<String>,
+    /// Before converting, check that the filesystem of the output file has room for the eml
+    /// files to convert, their separator lines and a 5% margin, failing with
+    /// [`MailfmtError::InsufficientSpace`] otherwise. Both figures are reported through
+    /// [`Event::SpaceChecked`].
+    pub space_check: bool,
+    /// Convert even if the output file seems to lack the space for it, warning about it
+    /// instead.
+    pub force: bool,
+    /// Follow symlinked directories when searching for eml files.
+    pub follow_symlinks: bool,
+    /// Patterns selecting which files in an input directory to convert. Patterns without a
+    /// `/` match file names, others match the path relative to the input directory. Each also
+    /// matches the gzip-compressed form of the files it matches, ending in `.gz`.
+    pub include: Vec<Pattern>,
+    /// After converting, keep watching the input directory for new eml files and append them
+    /// until cancelled. Only possible with [`EmlInput::Directory`].
+    pub watch: bool,
+    /// How long a new file has to stay unchanged before it is appended while watching.
+    pub settle: Duration,
+    /// Report every appended message through [`Event::Converted`], with its sender, date and
+    /// file and the decisions made for it.
+    pub details: bool,
+    /// Track the Message-IDs of appended messages and report those found on more than one in
+    /// [`ConversionSummary::duplicates`]. With `append`, the messages already in the mbox are
+    /// included. Every message is still appended.
+    pub report_duplicates: bool,
+    /// Once the mbox is written, read the messages appended by this run back and check that
+    /// each matches the content it was written from, after attachments were stripped and
+    /// headers recorded, failing with [`MailfmtError::VerificationFailed`] if any does not.
+    /// Line endings, a Content-Length header added by the dialect and blank lines at the end
+    /// of a message are not compared, as the mbox does not keep them apart.
+    pub verify_content: bool,
+    /// Append the messages of an eml file that holds a whole mailbox one by one, each with
+    /// the separator line it had, if any. Such a file holds several messages that each start
+    /// with a "From " line followed by a header, or several header blocks that each start
+    /// with a Received or Return-Path header after a blank line. Without it, the file is
+    /// appended as a single message with a warning. Either way, such files are counted in
+    /// [`ConversionSummary::mailboxes`].
+    pub split_embedded: bool,
+    /// Write an index of the messages appended by this run to this file, mapping their
+    /// Message-IDs to the byte offsets of their separator lines in the mbox. It is moved into
+    /// place along with the mbox. See [`MboxToEmlOptions::msgid_index`] for its format, and
+    /// [`ConversionSummary::msgid_index`] for how many entries it holds.
+    pub msgid_index: Option<PathBuf>,
+    /// Set to stop the conversion, or the watching, as soon as possible.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Asked before replacing an existing mbox file when neither `overwrite` nor `append` is
+    /// set. Any answer but [`Answer::Yes`] fails with [`MailfmtError::OutputExists`].
+    pub confirm_overwrite: Option<ConfirmHandler<'a>>,
+    /// Called with progress and per-file results as the conversion goes on.
+    pub on_event: Option<EventHandler<'a>>,
+}
 
-    #[arg(value_parser = validate_output_file)]
-    output_file: PathBuf,
+impl EmlToMboxOptions<'_> {
+    pub fn new(input: EmlInput, output_file: impl Into<PathBuf>) -> Self {
+        Self {
+            input,
+            output_file: output_file.into(),
+            overwrite: false,
+            create_parents: false,
+            append: false,
+            state_file: None,
+            dotlock: false,
+            lock_timeout: Duration::from_secs(10),
+            dialect: MboxDialect::default(),
+            from_line_style: FromLineStyle::default(),
+            invalid_utf8: InvalidUtf8::default(),
+            keep_bom: false,
+            strip_attachments: false,
+            strip_single_part: false,
+            record_folder: false,
+            record_source: false,
+            buffer_size: 4 * 1024 * 1024,
+            fsync: false,
+            allow_empty: false,
+            skip_invalid: false,
+            require_headers: Vec::new(),
+            space_check: true,
+            force: false,
+            follow_symlinks: false,
+            include: vec![Pattern::new("*.eml").expect("default pattern is valid")],
+            watch: false,
+            settle: Duration::from_secs(1),
+            details: false,
+            report_duplicates: false,
+            verify_content: false,
+            split_embedded: false,
+            msgid_index: None,
+            cancel: None,
+            confirm_overwrite: None,
+            on_event: None,
+        }
+    }
 
-    #[clap(long = "overwrite")]
-    overwrite: bool,
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
+
+    /// Report a file that could not be converted, returning it for the summary.
+    fn failed(&self, item: String, error: MailfmtError) -> Result<MessageError> {
+        self.emit(Event::Failed {
+            item: item.clone(),
+            error: &error,
+        })?;
+        Ok(MessageError { item, error })
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
 }
 
-impl ConvertToMboxCommand {
-    pub fn run(&self) -> Result<()> {
-        Self::eml_to_mbox(&self.input_directory, &self.output_file, self.overwrite)
+/// How often the changes reported by the file watcher are picked up in watch mode, and new files
+/// are checked for having settled.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Convert eml files into a single mbox file.
+///
+/// Files that cannot be converted are reported through [`Event::Failed`] and counted in the
+/// summary without stopping the conversion. A cancelled conversion leaves no output behind
+/// unless it was appending.
+///
+/// Separator lines are made from the From and Date headers, unless an eml file has a
+/// `.meta.json` sidecar as written with [`MboxToEmlOptions::sidecar_metadata`](crate::MboxToEmlOptions::sidecar_metadata), which gives the
+/// separator line the message had in its original mbox.
+///
+/// A UTF-8 byte-order mark at the start of an eml file is removed unless
+/// [`EmlToMboxOptions::keep_bom`] is set, so that the header it would be glued to is found:
+///
+/// ```
+/// use mailfmt::{EmlInput, EmlToMboxOptions, eml_to_mbox};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-bom-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// fs::write(dir.join("bom.eml"), "\u{FEFF}From: Alice <alice@example.com>\nSubject: Hi\n\nHello\n")?;
+///
+/// let input = EmlInput::Files(vec![dir.join("bom.eml")]);
+/// eml_to_mbox(&EmlToMboxOptions::new(input, dir.join("out.mbox")))?;
+/// let mbox = fs::read_to_string(dir.join("out.mbox"))?;
+/// assert!(mbox.starts_with("From alice@example.com "));
+/// assert!(mbox.contains("\nFrom: Alice <alice@example.com>\n"));
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn eml_to_mbox(options: &EmlToMboxOptions<'_>) -> Result<ConversionSummary> {
+    let _span = info_span!("eml_to_mbox", output = %options.output_file.display()).entered();
+    Conversion {
+        options,
+        warned_broken_symlinks: RefCell::new(HashSet::new()),
     }
+    .run()
+}
 
-    fn get_header_value<'a>(content: &'a str, header_name: &str) -> Option<&'a str> {
-        let prefix = format!("{}:", header_name.to_lowercase());
-        content
-            .lines()
-            .find(|line| line.to_lowercase().starts_with(&prefix))
-            .map(|line| line[prefix.len()..].trim())
+/// Count the eml files that [`eml_to_mbox`] would convert with the same options, without
+/// converting them or needing an output file. Files are found the same way, and with
+/// [`EmlToMboxOptions::skip_invalid`] only those passing validation are counted, reading no
+/// more than their header blocks. Files unchanged since a previous run are counted too.
+///
+/// ```
+/// use mailfmt::{EmlInput, EmlToMboxOptions, count_eml_files};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-doc-count-eml-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// fs::write(dir.join("one.eml"), "Subject: One\n\nHello\n")?;
+/// fs::write(dir.join("page.eml"), "<html></html>\n")?;
+///
+/// // No mbox file is written, so any will do.
+/// let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.clone()), "");
+/// options.skip_invalid = true;
+/// let count = count_eml_files(&options)?;
+/// assert_eq!((count.found, count.matching), (2, 1));
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn count_eml_files(options: &EmlToMboxOptions<'_>) -> Result<MessageCount> {
+    let start = Instant::now();
+    let conversion = Conversion {
+        options,
+        warned_broken_symlinks: RefCell::new(HashSet::new()),
+    };
+    let eml_files = match &options.input {
+        EmlInput::Directory(input_dir) => {
+            let mut pattern_matches = vec![0; options.include.len()];
+            conversion.discover_eml_files(input_dir, None, &mut pattern_matches)?
+        }
+        EmlInput::Files(files) => files.clone(),
+    };
+    let mut count = MessageCount::default();
+    for eml_file in &eml_files {
+        if options.is_cancelled() {
+            count.interrupted = true;
+            break;
+        }
+        count.found += 1;
+        // Files that cannot be read fail to convert, and are counted like them.
+        let valid = match options.skip_invalid {
+            true => read_header_block(eml_file, options.keep_bom).is_none_or(|header_block| {
+                validate_eml(eml_file, &header_block, &options.require_headers).is_ok()
+            }),
+            false => true,
+        };
+        if valid {
+            count.matching += 1;
+        }
+    }
+    count.elapsed = start.elapsed();
+    Ok(count)
+}
+
+/// Read the lines of an eml file up to its first blank line, decoded lossily, or `None` if it
+/// cannot be read.
+pub(crate) fn read_header_block(eml_file: &Path, keep_bom: bool) -> Option<String> {
+    let mut reader = BufReader::new(File::open(eml_file).ok()?);
+    // The header block of a compressed file is only known once the file is decompressed.
+    if gzip::has_extension(eml_file) || gzip::is_gzip(reader.fill_buf().ok()?) {
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).ok()?;
+        let content = gzip::decompress(&content).ok()?;
+        return header_block(&mut content.as_slice(), keep_bom);
+    }
+    header_block(&mut reader, keep_bom)
+}
+
+/// Read lines up to the end of the header block, ignoring a byte-order mark unless keeping it.
+/// Lines ending with CR alone are returned with LF.
+fn header_block(reader: &mut impl BufRead, keep_bom: bool) -> Option<String> {
+    let (mut header_block, mut line) = (Vec::new(), Vec::new());
+    let mut first = !keep_bom;
+    while reader.read_until(b'\n', &mut line).ok()? > 0 {
+        let content = if first { strip_bom(&line) } else { &line };
+        first = false;
+        if matches!(content, b"\n" | b"\r\n") {
+            break;
+        }
+        // A file whose lines end with CR alone is read as a single line.
+        if header_block.is_empty()
+            && let Some(content) = cr_line_endings(content)
+        {
+            let end = content
+                .windows(2)
+                .position(|pair| pair == b"\n\n")
+                .map_or(content.len(), |blank| blank + 1);
+            header_block.extend_from_slice(&content[..end]);
+            break;
+        }
+        header_block.extend_from_slice(content);
+        line.clear();
     }
+    Some(String::from_utf8_lossy(&header_block).into_owned())
+}
+
+/// The state of a single [`eml_to_mbox`] run.
+struct Conversion<'a> {
+    options: &'a EmlToMboxOptions<'a>,
+    warned_broken_symlinks: RefCell<HashSet<PathBuf>>,
+}
+
+impl Conversion<'_> {
+    fn run(&self) -> Result<ConversionSummary> {
+        let start = Instant::now();
+        let options = self.options;
+        let output_file = options.output_file.as_path();
+        if output_file.exists()
+            && !options.overwrite
+            && !options.append
+            && options
+                .confirm_overwrite
+                .as_ref()
+                .is_none_or(|confirm| confirm(output_file) != Answer::Yes)
+        {
+            return Err(MailfmtError::OutputExists {
+                path: output_file.to_path_buf(),
+            });
+        }
+        if let Some(parent) = output_file.parent() {
+            check_output_directory(parent)?;
+        }
+        let missing_parent = missing_parent(output_file);
+        if let Some(missing) = missing_parent
+            && !options.create_parents
+        {
+            return Err(MailfmtError::OutputParentMissing {
+                path: output_file.to_path_buf(),
+                missing: missing.to_path_buf(),
+            });
+        }
 
-    fn eml_to_mbox(input_dir: &Path, output_file: &Path, overwrite: bool) -> Result<()> {
-        if output_file.exists() && !overwrite {
-            bail!(
-                "File already exists at {:?}. Use the --overwrite flag to replace it.",
-                output_file
-            );
+        let mut pattern_matches = vec![0; options.include.len()];
+        let (input_dir, eml_files) = match &options.input {
+            EmlInput::Directory(input_dir) => (
+                Some(input_dir.as_path()),
+                self.discover_eml_files(input_dir, Some(output_file), &mut pattern_matches)?,
+            ),
+            EmlInput::Files(files) => (None, files.clone()),
+        };
+        if options.watch && input_dir.is_none() {
+            return Err(MailfmtError::WatchRequiresDirectory);
         }
 
-        let eml_files = {
-            let mut eml_files = Vec::new();
-            Self::find_eml_files(input_dir, &mut eml_files)?;
-            if eml_files.is_empty() {
-                bail!("Did not find any .eml files inside of {:?}", input_dir);
+        if let Some(input_file) = eml_files
+            .iter()
+            .find(|eml_file| paths::same_file(eml_file, output_file))
+        {
+            return Err(MailfmtError::OutputIsInput {
+                output: output_file.to_path_buf(),
+                input: input_file.clone(),
+            });
+        }
+
+        // Created before locking, as the lock and the temporary mbox go next to the output file.
+        if missing_parent.is_some()
+            && let Some(parent) = output_file.parent()
+        {
+            create_output_directory(parent, "create output directory")?;
+        }
+
+        // Held until the conversion, including any watching, is over. A new mbox moved into
+        // place is locked again, as the lock is held on the file replaced, if any.
+        let mut lock = MboxLock::acquire(
+            output_file,
+            options.dotlock,
+            options.append,
+            options.lock_timeout,
+        )?;
+
+        let previous = match &options.state_file {
+            Some(state_file) if options.append && state_file.exists() => {
+                IncrementalState::load(state_file)?
             }
-            eml_files.sort();
-            eml_files
+            _ => IncrementalState::default(),
         };
+        let mut state = IncrementalState::default();
+        let mut pending = Vec::new();
+        for eml_file in &eml_files {
+            let key = IncrementalState::key(eml_file);
+            // Files that cannot be checked are converted again, which reports the actual error.
+            if let Some(record) = previous.files.get(&key)
+                && let Ok(Some(current)) = record.unchanged(eml_file)
+            {
+                state.files.insert(key, current);
+            } else {
+                pending.push(eml_file);
+            }
+        }
+        let skipped = state.files.len();
+        let deleted = {
+            let discovered: HashSet<String> =
+                eml_files.iter().map(|f| IncrementalState::key(f)).collect();
+            previous
+                .files
+                .keys()
+                .filter(|key| !discovered.contains(*key))
+                .count()
+        };
+        if options.space_check {
+            // Files that cannot be read are left out, as converting them fails anyway.
+            let required = pending
+                .iter()
+                .filter_map(|eml_file| match gzip::has_extension(eml_file) {
+                    true => gzip::decompressed_size(eml_file).ok(),
+                    false => fs::metadata(eml_file).ok().map(|metadata| metadata.len()),
+                })
+                .map(|size| size + SEPARATOR_OVERHEAD)
+                .sum();
+            space::check(output_file, required, options.force, |event| {
+                options.emit(event)
+            })?;
+        }
 
-        let (converted, errors) = {
-            let (mut converted, mut errors) = (0, 0);
-            let mut output = File::create(output_file)?;
-            let pb = ProgressBar::new(eml_files.len() as u64);
-            pb.enable_steady_tick(Duration::from_millis(100));
-            pb.set_style(
-                ProgressStyle::default_bar()
-                    .template(
-                        "[{elapsed_precise}] {bar:40.cyan/blue} {human_pos:>7}/{human_len:7} {msg}",
-                    )
-                    .unwrap()
-                    .progress_chars("#>-"),
-            );
-            for eml_file in &eml_files {
-                match Self::process_eml_file(eml_file, &mut output) {
-                    Ok(()) => converted += 1,
-                    Err(e) => {
-                        pb.println(format!("Error processing {:?}: {}", eml_file, e));
-                        errors += 1;
+        let (mut converted, mut errors, mut interrupted) = (0, Vec::new(), false);
+        let mut invalid = Vec::new();
+        let (mut invalid_utf8, mut compressed) = (0, 0);
+        let mut stripped = Stripped::default();
+        let mut duplicates = options.report_duplicates.then(Duplicates::default);
+        // With content verified, the eml files appended and the hashes of what was written.
+        let mut written = Vec::new();
+        // The eml files processed, which with mailboxes split can be fewer than the messages.
+        let (mut position, mut mailboxes) = (0, 0);
+        let mut msgid_index = options
+            .msgid_index
+            .as_deref()
+            .map(|path| IndexWriter::create(path, true))
+            .transpose()?;
+        // How many messages the mbox already holds, and the blank line it may lack at its end.
+        let (preexisting, padding) = match options.append {
+            true => (
+                existing_messages(output_file, duplicates.as_mut())
+                    .io_context("read mbox file", output_file)?,
+                missing_blank_line(output_file).io_context("read mbox file", output_file)?,
+            ),
+            false => (0, &b""[..]),
+        };
+        // Where the first appended message starts in the mbox.
+        let start_offset = match options.append {
+            true => {
+                fs::metadata(output_file).map_or(0, |metadata| metadata.len())
+                    + padding.len() as u64
+            }
+            false => 0,
+        };
+        let bytes_read = Arc::new(AtomicU64::new(0));
+        let output = if options.append {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(output_file)
+                .io_context("open mbox file", output_file)?;
+            if !padding.is_empty() {
+                file.write_all(padding)
+                    .io_context("write mbox file", output_file)?;
+                options.emit(Event::Warning(format!(
+                    "The mbox file at {output_file:?} did not end with a blank line, which was added before the appended messages"
+                )))?;
+            }
+            MboxOutput::Append(file)
+        } else {
+            MboxOutput::Replace(
+                AtomicFile::create(output_file).io_context("create mbox file", output_file)?,
+            )
+        };
+        let mut output = MboxWriter::new(
+            BufWriter::with_capacity(options.buffer_size, output),
+            options.dialect,
+        )
+        .with_from_line_style(options.from_line_style);
+        options.emit(Event::Started {
+            total: ProgressTotal::Messages(pending.len() as u64),
+            position: 0,
+        })?;
+        for eml_file in pending {
+            if options.is_cancelled() {
+                interrupted = true;
+                break;
+            }
+            let name = input_dir
+                .and_then(|root| eml_file.strip_prefix(root).ok())
+                .unwrap_or(eml_file)
+                .to_string_lossy();
+            let _span = info_span!("message", file = %name).entered();
+            options.emit(Event::Processing(&name))?;
+            let file_start = Instant::now();
+            match process_eml_file(
+                eml_file,
+                input_dir,
+                output_file,
+                &mut output,
+                &bytes_read,
+                options,
+            ) {
+                Ok(file) => {
+                    let pattern = input_dir
+                        .filter(|_| options.details)
+                        .and_then(|root| self.matching_pattern(root, eml_file));
+                    for mut appended in file.messages {
+                        if options.details {
+                            if let Some(pattern) = pattern {
+                                appended.notes.insert(
+                                    0,
+                                    format!(
+                                        "included by the pattern '{}'",
+                                        options.include[pattern]
+                                    ),
+                                );
+                            }
+                            options.emit(Event::Converted(MessageDetails {
+                                index: converted,
+                                sender: appended.sender,
+                                date: appended.date,
+                                date_source: appended.date_source,
+                                file: eml_file,
+                                elapsed: file_start.elapsed(),
+                                notes: appended.notes,
+                            }))?;
+                        }
+                        if let Some(msgid_index) = &mut msgid_index {
+                            msgid_index.add(
+                                appended.message_id.as_deref(),
+                                || appended.content_key.clone().unwrap_or_default(),
+                                IndexLocation::Offset(start_offset + appended.offset),
+                            )?;
+                        }
+                        if let Some(duplicates) = &mut duplicates {
+                            duplicates.record(
+                                appended.message_id.as_deref(),
+                                MessageLocation {
+                                    index: converted,
+                                    file: eml_file.clone(),
+                                },
+                            );
+                        }
+                        if let Some(hash) = appended.content_hash {
+                            written.push((eml_file.clone(), hash));
+                        }
+                        converted += 1;
+                        stripped.attachments += appended.stripped.attachments;
+                        stripped.bytes += appended.stripped.bytes;
                     }
+                    invalid_utf8 += usize::from(file.invalid_utf8);
+                    compressed += usize::from(file.compressed);
+                    mailboxes += usize::from(file.mailbox.is_some());
+                    self.record_converted(&mut state, eml_file)?;
+                }
+                Err(error) if options.skip_invalid && is_invalid(&error) => {
+                    invalid.push(MessageError {
+                        item: format!("validating {eml_file:?}"),
+                        error,
+                    });
                 }
-                pb.inc(1);
+                Err(error) => {
+                    if let MailfmtError::InvalidUtf8File { .. } = error {
+                        invalid_utf8 += 1;
+                    }
+                    errors.push(options.failed(format!("processing {eml_file:?}"), error)?);
+                }
+            }
+            position += 1;
+            options.emit(Event::Progress {
+                position,
+                processed: converted + invalid.len() + errors.len(),
+            })?;
+        }
+        // Files left out on purpose, as unchanged or invalid, are not failures.
+        if converted == 0
+            && skipped == 0
+            && invalid.is_empty()
+            && !errors.is_empty()
+            && !interrupted
+            && !options.allow_empty
+        {
+            // Dropping the output removes its temporary file.
+            return Err(MailfmtError::AllFailed {
+                count: errors.len(),
+            });
+        }
+        let bytes_written = output.bytes_written() + padding.len() as u64;
+        let output = output
+            .finish()
+            .and_then(|output| output.into_inner().map_err(|e| e.into_error()))
+            .io_context("flush mbox output file", output_file)?;
+        // An interrupted conversion is never moved into place; dropping the output removes its
+        // temporary file. Appended messages are already in place and are kept.
+        let mut msgid_index_written = None;
+        if !interrupted || options.append {
+            finish_output(output, output_file, options.fsync)?;
+            if !options.append {
+                lock.relock(output_file, options.lock_timeout)?;
+            }
+            self.save_state(&state)?;
+            msgid_index_written = msgid_index.map(IndexWriter::finish).transpose()?;
+            if !interrupted {
+                options.emit(Event::Written(output_file))?;
             }
-            pb.finish_and_clear();
-            (converted, errors)
+        }
+        if options.verify_content && !interrupted {
+            verify::verify_mbox(
+                output_file,
+                start_offset,
+                &written,
+                options.dialect,
+                |event| options.emit(event),
+            )?;
+        }
+
+        let mut summary = ConversionSummary {
+            converted,
+            skipped: skipped + invalid.len(),
+            invalid,
+            errors,
+            invalid_utf8,
+            interrupted,
+            bytes_written: if !interrupted || options.append {
+                bytes_written
+            } else {
+                0
+            },
+            bytes_read: bytes_read.load(Ordering::Relaxed),
+            elapsed: start.elapsed(),
+            pattern_matches,
+            compressed,
+            deleted,
+            preexisting,
+            verified: written.len(),
+            mailboxes,
+            stripped: stripped.attachments,
+            stripped_bytes: stripped.bytes,
+            duplicates: duplicates.map(Duplicates::into_groups).unwrap_or_default(),
+            msgid_index: msgid_index_written,
+            ..ConversionSummary::default()
         };
+        if options.watch
+            && !interrupted
+            && let Some(input_dir) = input_dir
+        {
+            options.emit(Event::Watching(&summary))?;
+            summary.watch = Some(self.watch_for_new_files(
+                input_dir,
+                eml_files.into_iter().collect(),
+                state,
+            )?);
+        }
+        Ok(summary)
+    }
 
-        println!(
-            "Conversion of {converted} eml files completed with {errors} errors. Output saved to {:?}",
-            output_file
-        );
+    /// Record a converted eml file in the state, if a state file is being kept.
+    fn record_converted(&self, state: &mut IncrementalState, eml_file: &Path) -> Result<()> {
+        if self.options.state_file.is_none() {
+            return Ok(());
+        }
+        match FileRecord::read(eml_file) {
+            Ok(record) => {
+                state.files.insert(IncrementalState::key(eml_file), record);
+                Ok(())
+            }
+            // The file is converted again by the next run, which is the safe outcome.
+            Err(e) => self.options.emit(Event::Warning(format!(
+                "failed to record {eml_file:?} in the state file: {e}"
+            ))),
+        }
+    }
 
-        Ok(())
+    fn save_state(&self, state: &IncrementalState) -> Result<()> {
+        match &self.options.state_file {
+            Some(state_file) => state.save(state_file),
+            None => Ok(()),
+        }
     }
 
-    fn find_eml_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
-        for entry in
-            fs::read_dir(dir).with_context(|| format!("failed to read directory at {dir:?}"))?
+    /// Find the eml files to convert inside of an input directory or matching an input pattern.
+    fn discover_eml_files(
+        &self,
+        input_dir: &Path,
+        output_file: Option<&Path>,
+        pattern_matches: &mut [usize],
+    ) -> Result<Vec<PathBuf>> {
+        let input_pattern = InputPattern::parse(input_dir)?;
+        if let Some(output_file) = output_file
+            && input_pattern.is_none()
         {
-            let path = entry?.path();
-            if path.is_dir() {
-                Self::find_eml_files(&path, files)?;
-            } else if path.extension().and_then(|s| s.to_str()) == Some("eml") {
+            let resolved_input =
+                paths::resolve(input_dir).io_context("resolve input directory", input_dir)?;
+            let resolved_output =
+                paths::resolve(output_file).io_context("resolve output file", output_file)?;
+            if resolved_output.starts_with(&resolved_input) {
+                return Err(MailfmtError::OutputInsideInput {
+                    output: output_file.to_path_buf(),
+                    input: input_dir.to_path_buf(),
+                });
+            }
+        }
+
+        let eml_files =
+            self.collect_eml_files(input_dir, input_pattern.as_ref(), pattern_matches)?;
+        // Watching an empty directory is fine, as files may arrive later.
+        if eml_files.is_empty() && !self.options.watch {
+            return Err(MailfmtError::NoInputFiles {
+                input: input_dir.to_path_buf(),
+                include: match input_pattern {
+                    Some(_) => Vec::new(),
+                    None => self
+                        .options
+                        .include
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect(),
+                },
+            });
+        }
+        Ok(eml_files)
+    }
+
+    /// Collect the sorted list of eml files inside of an input directory or matching an input pattern.
+    fn collect_eml_files(
+        &self,
+        input_dir: &Path,
+        input_pattern: Option<&InputPattern>,
+        pattern_matches: &mut [usize],
+    ) -> Result<Vec<PathBuf>> {
+        let mut eml_files = Vec::new();
+        match input_pattern {
+            Some(pattern) => {
+                let mut visited = HashSet::new();
+                self.find_glob_matches(pattern, &mut visited, &mut eml_files, pattern_matches)?;
+            }
+            None => {
+                let resolved_input =
+                    paths::resolve(input_dir).io_context("resolve input directory", input_dir)?;
+                let mut visited = HashSet::from([resolved_input]);
+                self.find_eml_files(
+                    input_dir,
+                    input_dir,
+                    &mut visited,
+                    &mut eml_files,
+                    pattern_matches,
+                )?;
+            }
+        }
+        eml_files.sort();
+        // A file can match both a pattern and its compressed form.
+        eml_files.dedup();
+        Ok(eml_files)
+    }
+
+    /// Append eml files that appear in the input after the initial conversion to the mbox until
+    /// cancelled, as reported by a file watcher. A new file is only appended once its size and
+    /// modification time have stayed the same for the settle time, so that files still being
+    /// written are not picked up early.
+    fn watch_for_new_files(
+        &self,
+        input_dir: &Path,
+        mut known: HashSet<PathBuf>,
+        mut state: IncrementalState,
+    ) -> Result<WatchSummary> {
+        let options = self.options;
+        let output_file = options.output_file.as_path();
+        let input_pattern = InputPattern::parse(input_dir)?;
+        let mut output = MboxWriter::new(
+            OpenOptions::new()
+                .append(true)
+                .open(output_file)
+                .io_context("open mbox file", output_file)?,
+            options.dialect,
+        )
+        .with_from_line_style(options.from_line_style);
+        let (sender, changes) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)
+            .map_err(watch_error)
+            .io_context("watch input directory", input_dir)?;
+        let watched = input_pattern
+            .as_ref()
+            .map_or(input_dir, |pattern| pattern.base.as_path());
+        watcher
+            .watch(watched, RecursiveMode::Recursive)
+            .map_err(watch_error)
+            .io_context("watch input directory", watched)?;
+        let mut pending: HashMap<PathBuf, (u64, Option<SystemTime>, Instant)> = HashMap::new();
+        let mut summary = WatchSummary::default();
+        let bytes_read = Arc::new(AtomicU64::new(0));
+        // Files may have arrived between the initial conversion and the watcher starting.
+        let mut rescan = true;
+
+        while !options.is_cancelled() {
+            thread::sleep(WATCH_INTERVAL);
+
+            // The input is only searched again once the watcher reports entries coming or going,
+            // while changes to the content of files waiting to settle are seen by their metadata.
+            rescan |= changes.try_iter().any(|change| lists_changed(&change));
+            if rescan {
+                rescan = false;
+                let mut pattern_matches = vec![0; options.include.len()];
+                for path in
+                    self.collect_eml_files(input_dir, input_pattern.as_ref(), &mut pattern_matches)?
+                {
+                    if known.contains(&path) || pending.contains_key(&path) {
+                        continue;
+                    }
+                    if let Ok(metadata) = fs::metadata(&path) {
+                        let modified = metadata.modified().ok();
+                        pending.insert(path, (metadata.len(), modified, Instant::now()));
+                    }
+                }
+            }
+
+            let mut ready = Vec::new();
+            pending.retain(|path, (len, modified, since)| {
+                // Files can disappear again before they settle, in which case they are forgotten.
+                let Ok(metadata) = fs::metadata(path) else {
+                    return false;
+                };
+                let current = (metadata.len(), metadata.modified().ok());
+                if (*len, *modified) != current {
+                    (*len, *modified, *since) = (current.0, current.1, Instant::now());
+                } else if since.elapsed() >= options.settle {
+                    ready.push(path.clone());
+                }
+                true
+            });
+            ready.sort();
+
+            for path in ready {
+                pending.remove(&path);
+                match process_eml_file(
+                    &path,
+                    Some(input_dir),
+                    output_file,
+                    &mut output,
+                    &bytes_read,
+                    options,
+                ) {
+                    Ok(file) => {
+                        summary.appended += file.messages.len();
+                        self.record_converted(&mut state, &path)?;
+                        options.emit(Event::Appended(&path))?;
+                    }
+                    Err(error) if options.skip_invalid && is_invalid(&error) => {
+                        options.emit(Event::Warning(format!(
+                            "Skipped {path:?}, as it failed validation: {error}"
+                        )))?;
+                    }
+                    Err(error) => {
+                        summary
+                            .errors
+                            .push(options.failed(format!("processing {path:?}"), error)?);
+                    }
+                }
+                output.flush().io_context("flush mbox file", output_file)?;
+                if options.fsync {
+                    output
+                        .get_ref()
+                        .sync_data()
+                        .io_context("sync mbox file", output_file)?;
+                }
+                known.insert(path);
+            }
+        }
+
+        self.save_state(&state)?;
+        Ok(summary)
+    }
+
+    /// Index of the first include pattern matching a discovered file, if any.
+    fn matching_pattern(&self, root: &Path, path: &Path) -> Option<usize> {
+        let relative = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let file_name = path.file_name()?.to_string_lossy();
+        self.options.include.iter().position(|pattern| {
+            // Patterns without a '/' only apply to the file name.
+            let candidate: &str = match pattern.as_str().contains('/') {
+                false => &file_name,
+                true => &relative,
+            };
+            let matches = |text| pattern.matches_with(text, GLOB_OPTIONS);
+            // A compressed file matches like the file it was compressed from.
+            matches(candidate) || gzip::strip_extension(candidate).is_some_and(matches)
+        })
+    }
+
+    /// List the entries of a directory as `(path, is_dir)` pairs, applying the symlink policy:
+    /// broken symlinks are skipped with a warning, symlinked directories are skipped unless
+    /// following them, and directories that were already visited are skipped to break cycles.
+    fn read_dir_entries(
+        &self,
+        dir: &Path,
+        visited: &mut HashSet<PathBuf>,
+    ) -> Result<Vec<(PathBuf, bool)>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(dir).io_context("read directory", dir)? {
+            let entry = entry.io_context("read directory", dir)?;
+            let path = entry.path();
+            let is_symlink = entry
+                .file_type()
+                .io_context("read metadata", &path)?
+                .is_symlink();
+            if let Some(is_dir) = self.classify(&path, is_symlink, visited)? {
+                entries.push((path, is_dir));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Whether a path found while searching the input is a directory, or `None` if it is
+    /// skipped by the symlink policy of [`Self::read_dir_entries`].
+    fn classify(
+        &self,
+        path: &Path,
+        is_symlink: bool,
+        visited: &mut HashSet<PathBuf>,
+    ) -> Result<Option<bool>> {
+        let follow_symlinks = self.options.follow_symlinks;
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if is_symlink && e.kind() == io::ErrorKind::NotFound => {
+                if self
+                    .warned_broken_symlinks
+                    .borrow_mut()
+                    .insert(path.to_path_buf())
+                {
+                    self.options.emit(Event::Warning(format!(
+                        "skipping broken symlink at {path:?}"
+                    )))?;
+                }
+                return Ok(None);
+            }
+            Err(e) => return Err(e).io_context("read metadata", path),
+        };
+
+        if metadata.is_dir() {
+            if is_symlink && !follow_symlinks {
+                return Ok(None);
+            }
+            // Only directories reachable through a symlink can be seen twice, so the cycle
+            // check is only needed when symlinks are followed.
+            if follow_symlinks {
+                let canonical = path.canonicalize().io_context("resolve directory", path)?;
+                if !visited.insert(canonical) {
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(metadata.is_dir()))
+    }
+
+    fn find_eml_files(
+        &self,
+        root: &Path,
+        dir: &Path,
+        visited: &mut HashSet<PathBuf>,
+        files: &mut Vec<PathBuf>,
+        pattern_matches: &mut [usize],
+    ) -> Result<()> {
+        for (path, is_dir) in self.read_dir_entries(dir, visited)? {
+            if is_dir {
+                self.find_eml_files(root, &path, visited, files, pattern_matches)?;
+            } else if let Some(pattern) = self.matching_pattern(root, &path) {
+                pattern_matches[pattern] += 1;
                 files.push(path);
             }
         }
         Ok(())
     }
 
-    fn process_eml_file(eml_file: &Path, output: &mut File) -> Result<()> {
-        let content = fs::read_to_string(eml_file)
-            .with_context(|| format!("failed to read eml file at {eml_file:?}"))?;
+    /// Find the files and directories matching an input glob pattern. Matched files are always
+    /// included, while matched directories are searched like a regular input directory. The
+    /// pattern also matches the gzip-compressed form of the files it matches, ending in `.gz`.
+    fn find_glob_matches(
+        &self,
+        pattern: &InputPattern,
+        visited: &mut HashSet<PathBuf>,
+        files: &mut Vec<PathBuf>,
+        pattern_matches: &mut [usize],
+    ) -> Result<()> {
+        let compressed = format!("{}.gz", pattern.pattern);
+        for (glob, compressed) in [(pattern.pattern.as_str(), false), (&compressed, true)] {
+            let paths = glob::glob_with(glob, GLOB_OPTIONS).map_err(|error| {
+                MailfmtError::InvalidPattern {
+                    pattern: pattern.pattern.clone(),
+                    reason: error.msg.to_string(),
+                }
+            })?;
+            for path in paths {
+                let path = path
+                    .map_err(io::Error::from)
+                    .io_context("read directory", Path::new(&pattern.pattern))?;
+                if !self.options.follow_symlinks && pattern.through_symlink(&path) {
+                    continue;
+                }
+                let is_symlink = fs::symlink_metadata(&path)
+                    .io_context("read metadata", &path)?
+                    .is_symlink();
+                match self.classify(&path, is_symlink, visited)? {
+                    Some(true) if !compressed => {
+                        self.find_eml_files(&path, &path, visited, files, pattern_matches)?
+                    }
+                    Some(false) => files.push(path),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a change reported by the file watcher may have added or removed input files, so that
+/// the input has to be searched again. Errors, such as missed events, are treated as such.
+fn lists_changed(change: &notify::Result<notify::Event>) -> bool {
+    match change {
+        Ok(event) => {
+            event.need_rescan()
+                || !matches!(
+                    event.kind,
+                    EventKind::Access(_)
+                        | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Metadata(_))
+                )
+        }
+        Err(_) => true,
+    }
+}
+
+/// The error of a file watcher as an I/O error.
+fn watch_error(error: notify::Error) -> io::Error {
+    match error.kind {
+        notify::ErrorKind::Io(error) => error,
+        _ => io::Error::other(error),
+    }
+}
+
+/// Count the messages of an mbox that is appended to, recording their Message-IDs with
+/// `duplicates` so that appended messages repeating them are reported. A missing file holds
+/// none.
+fn existing_messages(
+    mbox_file: &Path,
+    mut duplicates: Option<&mut Duplicates>,
+) -> io::Result<usize> {
+    let file = match File::open(mbox_file) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        file => file?,
+    };
+    let mut count = 0;
+    scan_header_blocks(
+        BufReader::new(file),
+        false,
+        duplicates.is_some(),
+        |header, _| {
+            if let Some(duplicates) = &mut duplicates {
+                duplicates.record(
+                    get_header_value_from_lines(&header, "message-id"),
+                    MessageLocation {
+                        index: count,
+                        file: mbox_file.to_path_buf(),
+                    },
+                );
+            }
+            count += 1;
+            true
+        },
+    )?;
+    Ok(count)
+}
+
+/// What has to be written to the end of an mbox before appending to it, so that it ends with a
+/// blank line. One that was cut short would otherwise run into the first appended message.
+fn missing_blank_line(mbox_file: &Path) -> io::Result<&'static [u8]> {
+    let mut file = match File::open(mbox_file) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(b""),
+        file => file?,
+    };
+    let length = file.metadata()?.len();
+    if length == 0 {
+        return Ok(b"");
+    }
+    let mut end = Vec::new();
+    file.seek(SeekFrom::Start(length.saturating_sub(4)))?;
+    file.read_to_end(&mut end)?;
+    Ok(match end.as_slice() {
+        end if end.ends_with(b"\n\n") || end.ends_with(b"\r\n\r\n") => b"",
+        end if end.ends_with(b"\n") => b"\n",
+        _ => b"\n\n",
+    })
+}
+
+/// The topmost of the directories above `output_file` that do not exist yet, if any.
+fn missing_parent(output_file: &Path) -> Option<&Path> {
+    output_file
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        .last()
+}
+
+/// Sync the finished mbox file if requested and move it into its final place.
+fn finish_output(output: MboxOutput, output_file: &Path, fsync: bool) -> Result<()> {
+    if fsync {
+        output
+            .file()
+            .sync_all()
+            .io_context("sync mbox output file", output_file)?;
+    }
+    if let MboxOutput::Replace(output) = output {
+        output
+            .commit()
+            .io_context("move mbox file into place", output_file)?;
+    }
+    if fsync && let Some(parent) = output_file.parent() {
+        sync_directory(parent).io_context("sync output directory", parent)?;
+    }
+    Ok(())
+}
+
+/// What appending an eml file to the mbox did.
+struct AppendedFile {
+    /// The messages appended, more than one for a mailbox split with
+    /// [`EmlToMboxOptions::split_embedded`].
+    messages: Vec<Appended>,
+    invalid_utf8: bool,
+    /// Whether the file was gzip-compressed.
+    compressed: bool,
+    /// How many messages the file held, if it looked like a mailbox.
+    mailbox: Option<usize>,
+}
+
+/// What appending a message to the mbox did.
+struct Appended {
+    /// Byte offset of its separator line among the bytes written by this run.
+    offset: u64,
+    stripped: Stripped,
+    sender: Option<String>,
+    date: Option<String>,
+    date_source: DateSource,
+    message_id: Option<String>,
+    /// With a Message-ID index written, the key of a message without a Message-ID.
+    content_key: Option<String>,
+    /// With content verified, the hash of the content appended, as it is compared.
+    content_hash: Option<String>,
+    /// Decisions made for the message, only kept when details are reported.
+    notes: Vec<String>,
+}
 
-        let from_addr = Self::get_header_value(&content, "from")
-            .and_then(|value| {
-                if let Some(start) = value.find('<') {
-                    value.find('>').map(|end| &value[start + 1..end])
-                } else {
-                    Some(value)
+/// A message of an eml file that holds a whole mailbox.
+struct MailboxPart<'a> {
+    /// Its separator line after "From ", if it had one.
+    envelope: Option<String>,
+    content: &'a [u8],
+}
+
+/// Append an eml file to the mbox, adding the bytes read from it to `bytes_read`. `input_dir`
+/// is the directory it was found in, if any, which recorded folders are relative to.
+fn process_eml_file(
+    eml_file: &Path,
+    input_dir: Option<&Path>,
+    output_file: &Path,
+    output: &mut MboxWriter<impl Write>,
+    bytes_read: &Arc<AtomicU64>,
+    options: &EmlToMboxOptions<'_>,
+) -> Result<AppendedFile> {
+    let mut notes = Vec::new();
+    let policy = options.invalid_utf8;
+    let mut content = Vec::new();
+    CountingReader::with_counter(
+        File::open(eml_file).io_context("open eml file", eml_file)?,
+        Arc::clone(bytes_read),
+    )
+    .read_to_end(&mut content)
+    .io_context("read eml file", eml_file)?;
+    let compressed = gzip::has_extension(eml_file) || gzip::is_gzip(&content);
+    if compressed {
+        content = gzip::decompress(&content).map_err(|reason| MailfmtError::CorruptGzip {
+            path: eml_file.to_path_buf(),
+            reason,
+        })?;
+        notes.push("decompressed from gzip".to_string());
+    }
+    let bom = if options.keep_bom {
+        0
+    } else {
+        content.len() - strip_bom(&content).len()
+    };
+    if bom > 0 {
+        notes.push("byte-order mark removed".to_string());
+    }
+    let lf = cr_line_endings(&content[bom..]);
+    if lf.is_some() {
+        notes.push("CR line endings converted to LF".to_string());
+    }
+    let (content, invalid) = policy.apply(lf.as_deref().unwrap_or(&content[bom..]));
+    if let Some(offset) = invalid
+        && policy == InvalidUtf8::Strict
+    {
+        return Err(MailfmtError::InvalidUtf8File {
+            path: eml_file.to_path_buf(),
+            offset: (bom + offset) as u64,
+        });
+    }
+    if let Some(offset) = invalid {
+        notes.push(format!(
+            "bytes that are not valid UTF-8 from offset {} {}",
+            bom + offset,
+            match policy {
+                InvalidUtf8::Lossy => "replaced with U+FFFD",
+                _ => "kept as they are",
+            }
+        ));
+    }
+
+    let mut parts = mailbox_parts(&content).unwrap_or_default();
+    let mailbox = (!parts.is_empty()).then_some(parts.len());
+    if let Some(count) = mailbox
+        && !options.split_embedded
+    {
+        options.emit(Event::Warning(format!(
+            "{eml_file:?} looks like a mailbox holding {count} messages, and was appended as a single message"
+        )))?;
+        parts.clear();
+    }
+    // Every message is checked before any is written, so that a file is appended whole or not
+    // at all.
+    for part in &parts {
+        check_eml(eml_file, part.content, options)?;
+    }
+    let messages = match parts.len() {
+        0 => {
+            check_eml(eml_file, &content, options)?;
+            // A sidecar written when the eml file was extracted keeps its original separator
+            // line.
+            let sidecar = match sidecar::read_envelope(eml_file) {
+                Ok(sidecar) => sidecar,
+                Err(e) => {
+                    options.emit(Event::Warning(format!(
+                        "Ignored the sidecar of {eml_file:?}, as it cannot be used: {e}"
+                    )))?;
+                    None
                 }
-            })
-            .unwrap_or("unknown@example.com");
+            };
+            let separator = sidecar.as_deref().map(|envelope| (envelope, "its sidecar"));
+            let mut appended = append_eml_message(
+                eml_file,
+                &content,
+                separator,
+                input_dir,
+                output_file,
+                output,
+                options,
+            )?;
+            appended.notes.splice(0..0, notes);
+            vec![appended]
+        }
+        count => {
+            let mut messages = Vec::with_capacity(count);
+            for (index, part) in parts.into_iter().enumerate() {
+                let separator = part
+                    .envelope
+                    .as_deref()
+                    .map(|envelope| (envelope, "the file"));
+                let mut appended = append_eml_message(
+                    eml_file,
+                    part.content,
+                    separator,
+                    input_dir,
+                    output_file,
+                    output,
+                    options,
+                )?;
+                let mut file_notes = notes.clone();
+                file_notes.push(format!("message {} of the {count} in the file", index + 1));
+                appended.notes.splice(0..0, file_notes);
+                messages.push(appended);
+            }
+            messages
+        }
+    };
+    Ok(AppendedFile {
+        messages,
+        invalid_utf8: invalid.is_some(),
+        compressed,
+        mailbox,
+    })
+}
 
-        let date_str = Self::get_header_value(&content, "date")
-            .and_then(|value| {
-                DateTime::parse_from_rfc2822(value)
-                    .or_else(|_| DateTime::parse_from_rfc3339(value))
-                    .ok()
-                    .map(|dt| dt.format("%a %b %d %H:%M:%S %Y").to_string())
-            })
-            .unwrap_or_else(|| "Mon Jan 01 00:00:00 2024".to_string());
+/// Check that the content of a message from an eml file is one, and with
+/// [`EmlToMboxOptions::skip_invalid`] that it passes validation.
+fn check_eml(eml_file: &Path, content: &[u8], options: &EmlToMboxOptions<'_>) -> Result<()> {
+    let text = String::from_utf8_lossy(content);
+    if options.skip_invalid {
+        validate_eml(eml_file, &text, &options.require_headers)?;
+    } else if !text.lines().next().is_some_and(is_header_line) {
+        return Err(MailfmtError::InvalidEml {
+            path: eml_file.to_path_buf(),
+        });
+    }
+    Ok(())
+}
 
-        writeln!(output, "From {} {}", from_addr, date_str)
-            .context("failed to write from line to mbox output file")?;
-        write!(output, "{}", content).context("failed to write content to mbox output file")?;
+/// Append a message of an eml file to the mbox, with the separator line it was given along
+/// with where it came from, if any, or one made from its headers.
+fn append_eml_message(
+    eml_file: &Path,
+    content: &[u8],
+    separator: Option<(&str, &str)>,
+    input_dir: Option<&Path>,
+    output_file: &Path,
+    output: &mut MboxWriter<impl Write>,
+    options: &EmlToMboxOptions<'_>,
+) -> Result<Appended> {
+    let mut notes = Vec::new();
+    // Headers are read from a decoded copy, while the bytes written follow the policy.
+    let text = String::from_utf8_lossy(content);
+    let (mut sender, _) = envelope_parts(&text);
+    let (date, date_source) =
+        match separator.and_then(|(envelope, origin)| Some((envelope.split_once(' ')?, origin))) {
+            Some(((separator_sender, separator_date), origin)) => {
+                notes.push(format!("separator line taken from {origin}"));
+                sender = Some(separator_sender);
+                (Some(separator_date.to_string()), DateSource::Separator)
+            }
+            None => match eml_date(eml_file, &text) {
+                Some((date, source)) => (Some(asctime(&date)), source),
+                None => (None, DateSource::Placeholder),
+            },
+        };
+    // Thunderbird separator lines have no sender to fall back on.
+    if sender.is_none() && options.from_line_style == FromLineStyle::Sender {
+        notes.push(format!(
+            "no usable From address, so the separator line uses {PLACEHOLDER_SENDER}"
+        ));
+    }
+    match date_source {
+        DateSource::Header | DateSource::Separator => {}
+        DateSource::Placeholder => notes.push(format!(
+            "no usable Date header, so the separator line uses {PLACEHOLDER_DATE}"
+        )),
+        source => notes.push(format!(
+            "no usable Date header, so the separator line is dated from its {source}"
+        )),
+    }
+    let envelope = format!(
+        "{} {}",
+        sender.unwrap_or(PLACEHOLDER_SENDER),
+        date.as_deref().unwrap_or(PLACEHOLDER_DATE)
+    );
+    let stripped = options
+        .strip_attachments
+        .then(|| strip_eml(content, options.strip_single_part))
+        .flatten();
+    let (content, stripped) = match &stripped {
+        Some((content, stripped)) => (content.as_slice(), *stripped),
+        None => (content, Stripped::default()),
+    };
+    let folder = options
+        .record_folder
+        .then(|| input_dir.and_then(|root| relative_folder(root, eml_file)))
+        .flatten();
+    let mut recorded = folder.as_deref().and_then(|folder| {
+        notes.push(format!("folder {folder:?} recorded in {FOLDER_HEADER}"));
+        replace_header(content, FOLDER_HEADER, Some(folder))
+    });
+    // A recorded source file is only kept when recording it again, so that it does not leak.
+    let source = options
+        .record_source
+        .then(|| source_path(input_dir, eml_file));
+    if let Some(replaced) = replace_header(
+        recorded.as_deref().unwrap_or(content),
+        SOURCE_FILE_HEADER,
+        source.as_deref(),
+    ) {
+        notes.push(match &source {
+            Some(source) => format!("source file {source:?} recorded in {SOURCE_FILE_HEADER}"),
+            None => format!("{SOURCE_FILE_HEADER} header removed"),
+        });
+        recorded = Some(replaced);
+    }
+    let content = recorded.as_deref().unwrap_or(content);
+    let offset = output.bytes_written();
+    output
+        .append_raw(content, &envelope)
+        .io_context("write message to mbox output file", output_file)?;
+    if stripped.attachments > 0 {
+        notes.push(format!(
+            "{} attachments stripped, saving {} bytes",
+            stripped.attachments, stripped.bytes
+        ));
+    }
+    let message_id = get_header_value(&text, "message-id").map(str::to_string);
+    let content_key = (options.msgid_index.is_some() && message_id.is_none()).then(|| {
+        let content = content.strip_suffix(b"\n").unwrap_or(content);
+        content_key(content.split(|&b| b == b'\n'))
+    });
+    Ok(Appended {
+        offset,
+        stripped,
+        sender: sender.map(str::to_string),
+        date: date.or_else(|| Some(PLACEHOLDER_DATE.to_string())),
+        date_source,
+        message_id,
+        content_key,
+        content_hash: options
+            .verify_content
+            .then(|| verify::content_hash(content, options.dialect)),
+        notes,
+    })
+}
+
+/// The messages of eml content that holds a whole mailbox rather than a single message, as
+/// described for [`EmlToMboxOptions::split_embedded`], or `None` if it holds one message. The
+/// blank line before each message that follows another is left out of the message.
+fn mailbox_parts(content: &[u8]) -> Option<Vec<MailboxPart<'_>>> {
+    // The byte offset of every line and its content without its line ending.
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        let bare = line.strip_suffix(b"\n").unwrap_or(line);
+        lines.push((offset, bare.strip_suffix(b"\r").unwrap_or(bare)));
+        offset += line.len();
+    }
+    let after_blank = |index: usize| index == 0 || lines[index - 1].1.is_empty();
+    let followed_by_header = |index: usize| {
+        lines.get(index + 1).is_some_and(|(_, line)| {
+            is_header_line(&String::from_utf8_lossy(line))
+                || line.first().is_some_and(|&b| b == b' ' || b == b'\t')
+        })
+    };
 
-        match content.as_bytes() {
-            b if b.ends_with(b"\n\n") => {}
-            b if b.ends_with(b"\n") => writeln!(output)?,
-            _ => {
-                writeln!(output)?;
-                writeln!(output)?;
+    let mut starts: Vec<usize> = (0..lines.len())
+        .filter(|&index| {
+            after_blank(index) && is_separator(lines[index].1) && followed_by_header(index)
+        })
+        .collect();
+    let separated = starts.len() > 1;
+    if separated {
+        // Content before the first separator line is a message of its own.
+        if !content[..lines[starts[0]].0].trim_ascii().is_empty() {
+            starts.insert(0, 0);
+        }
+    } else {
+        starts = vec![0];
+        let mut in_header = true;
+        for (index, (_, line)) in lines.iter().enumerate() {
+            if line.is_empty() {
+                in_header = false;
+            } else if !in_header
+                && after_blank(index)
+                && starts_trace_header(line)
+                && followed_by_header(index)
+            {
+                starts.push(index);
+                in_header = true;
             }
         }
+        if starts.len() < 2 {
+            return None;
+        }
+    }
 
-        output.flush()?;
-        Ok(())
+    let parts = starts.iter().enumerate().map(|(position, &first)| {
+        let envelope = (separated && is_separator(lines[first].1)).then(|| {
+            String::from_utf8_lossy(&lines[first].1[5..])
+                .trim()
+                .to_string()
+        });
+        let start = match envelope {
+            Some(_) => lines.get(first + 1).map_or(content.len(), |line| line.0),
+            None => lines[first].0,
+        };
+        let end = match starts.get(position + 1) {
+            Some(&next) if lines[next - 1].1.is_empty() && next - 1 > first => lines[next - 1].0,
+            Some(&next) => lines[next].0,
+            None => content.len(),
+        };
+        MailboxPart {
+            envelope,
+            content: &content[start..end],
+        }
+    });
+    Some(parts.collect())
+}
+
+/// Whether a line is a Received or Return-Path header, one of which starts the header block
+/// of a message delivered to a mailbox.
+fn starts_trace_header(line: &[u8]) -> bool {
+    [&b"received:"[..], b"return-path:"].iter().any(|name| {
+        line.get(..name.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(name))
+    })
+}
+
+/// The date of an eml file for its separator line, from its Date header, its Received header
+/// or the time the file was last modified, in that order.
+fn eml_date(eml_file: &Path, text: &str) -> Option<(DateTime<FixedOffset>, DateSource)> {
+    let header: Vec<Vec<u8>> = text
+        .lines()
+        .take_while(|line| !line.trim_end_matches('\r').is_empty())
+        .map(|line| line.as_bytes().to_vec())
+        .collect();
+    let entity = Entity::parse(&header);
+    let field = |name| {
+        entity
+            .fields
+            .iter()
+            .find(|field| field.is(name))
+            .map(|field| field.value.trim())
+    };
+    message_date(DateSources {
+        header: field("date"),
+        envelope: None,
+        received: field("received"),
+        modified: fs::metadata(eml_file)
+            .and_then(|metadata| metadata.modified())
+            .ok(),
+    })
+}
+
+/// The folder of an eml file relative to the input directory, with `/` between its
+/// components, or `None` for a file directly in the input directory.
+fn relative_folder(input_dir: &Path, eml_file: &Path) -> Option<String> {
+    let folder = eml_file.strip_prefix(input_dir).ok()?.parent()?;
+    let components: Vec<_> = folder
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    (!components.is_empty()).then(|| components.join("/"))
+}
+
+/// The path of an eml file relative to the input directory, with `/` between its components,
+/// or its file name when it was not found in one.
+fn source_path(input_dir: Option<&Path>, eml_file: &Path) -> String {
+    match input_dir.and_then(|root| eml_file.strip_prefix(root).ok()) {
+        Some(relative) => relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        None => eml_file
+            .file_name()
+            .unwrap_or(eml_file.as_os_str())
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+/// Drop the fields with the given name from a message's header block and, given a value, add
+/// a field with it to the top, encoded and folded by [`encode_header`] and keeping the
+/// message's line endings. Returns `None` if there was nothing to drop nor add.
+fn replace_header(content: &[u8], name: &str, value: Option<&str>) -> Option<Vec<u8>> {
+    let ending: &[u8] = match content.split(|&b| b == b'\n').next() {
+        Some(line) if line.ends_with(b"\r") => b"\r\n",
+        _ => b"\n",
+    };
+    let mut output = Vec::with_capacity(content.len());
+    if let Some(value) = value {
+        for line in encode_header(name, value).split('\n') {
+            output.extend_from_slice(line.as_bytes());
+            output.extend_from_slice(ending);
+        }
+    }
+    let (mut in_header, mut dropping, mut dropped) = (true, false, false);
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        if in_header {
+            if line == b"\n" || line == b"\r\n" {
+                in_header = false;
+            } else if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+                dropping = String::from_utf8_lossy(line)
+                    .split_once(':')
+                    .is_some_and(|(field, _)| field.eq_ignore_ascii_case(name));
+            }
+            if in_header && dropping {
+                dropped = true;
+                continue;
+            }
+        }
+        output.extend_from_slice(line);
+    }
+    (value.is_some() || dropped).then_some(output)
+}
+
+/// Whether an error is one of those that make [`EmlToMboxOptions::skip_invalid`] leave a file
+/// out rather than count it as an error.
+fn is_invalid(error: &MailfmtError) -> bool {
+    matches!(
+        error,
+        MailfmtError::InvalidEml { .. } | MailfmtError::MissingHeaders { .. }
+    )
+}
+
+/// Strip the attachments of an eml file's content, keeping its line endings. Returns `None` if
+/// it has none to strip.
+fn strip_eml(content: &[u8], single_part: bool) -> Option<(Vec<u8>, Stripped)> {
+    let crlf = content
+        .split(|&b| b == b'\n')
+        .next()
+        .is_some_and(|line| line.ends_with(b"\r"));
+    let mut lines: Vec<Vec<u8>> = content
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+        .collect();
+    let stripped = strip_attachments(&mut lines, single_part);
+    if stripped.attachments == 0 {
+        return None;
+    }
+    let ending: &[u8] = if crlf { b"\r\n" } else { b"\n" };
+    let lines = lines.join(ending);
+    let bytes = (content.len() as u64).saturating_sub(lines.len() as u64);
+    Some((lines, Stripped { bytes, ..stripped }))
+}
+
+/// The mbox file being written: either a new file that is only moved into place once complete,
+/// or an existing file that messages are appended to directly.
+enum MboxOutput {
+    Replace(AtomicFile),
+    Append(File),
+}
+
+impl MboxOutput {
+    fn file(&self) -> &File {
+        match self {
+            Self::Replace(output) => output.file(),
+            Self::Append(file) => file,
+        }
+    }
+}
+
+impl Write for MboxOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Replace(output) => output.write(buf),
+            Self::Append(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Replace(output) => output.flush(),
+            Self::Append(file) => file.flush(),
+        }
+    }
+}
+
+/// Whether a string contains any characters with a special meaning in glob patterns.
+fn has_meta(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// An input argument that is a glob pattern rather than a literal path.
+struct InputPattern {
+    pattern: String,
+    /// The directory made up of the pattern's leading components without glob characters,
+    /// which is watched for new files.
+    base: PathBuf,
+}
+
+impl InputPattern {
+    /// Treat the input as a pattern if it contains glob characters and does not exist as a literal path.
+    fn parse(input: &Path) -> Result<Option<Self>> {
+        let Some(input) = input.to_str() else {
+            return Ok(None);
+        };
+        if !has_meta(input) || Path::new(input).exists() {
+            return Ok(None);
+        }
+        Pattern::new(input).map_err(|error| MailfmtError::InvalidPattern {
+            pattern: input.to_string(),
+            reason: error.msg.to_string(),
+        })?;
+        let literal: PathBuf = Path::new(input)
+            .components()
+            .take_while(|component| !has_meta(&component.as_os_str().to_string_lossy()))
+            .collect();
+        Ok(Some(Self {
+            pattern: input.to_string(),
+            base: match literal.as_os_str().is_empty() {
+                true => PathBuf::from("."),
+                false => literal,
+            },
+        }))
+    }
+
+    /// Whether a path matching the pattern was reached through a symlinked directory below
+    /// the base directory.
+    fn through_symlink(&self, path: &Path) -> bool {
+        path.ancestors()
+            .skip(1)
+            .take_while(|ancestor| *ancestor != self.base && !ancestor.as_os_str().is_empty())
+            .any(Path::is_symlink)
     }
 }
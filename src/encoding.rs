@@ -0,0 +1,111 @@
+use std::{borrow::Cow, fmt, str::FromStr};
+
+/// The UTF-8 byte-order mark that tools on Windows tend to put at the start of text files.
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Remove a byte-order mark from the start of some bytes, which must be the start of a file.
+pub(crate) fn strip_bom(bytes: &[u8]) -> &[u8] {
+    bytes.strip_prefix(BOM).unwrap_or(bytes)
+}
+
+/// Convert the line endings of content written by classic Mac OS, which ends lines with a bare
+/// CR, to LF. Content with any LF is left alone, so that a stray CR inside an LF or CRLF file
+/// is kept. Returns `None` if the content does not end its lines with CR alone.
+pub(crate) fn cr_line_endings(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.contains(&b'\n') || !bytes.contains(&b'\r') {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .map(|&b| if b == b'\r' { b'\n' } else { b })
+            .collect(),
+    )
+}
+
+/// What to do with byte sequences in a message that are not valid UTF-8.
+///
+/// ```
+/// use mailfmt::{InvalidUtf8, MailfmtError, MboxParser};
+///
+/// let mbox = b"From a Mon Jan 01 00:00:00 2024\n\
+///              Subject: \xff subject\n\
+///              X-Note: \xff value\n\
+///              \n\
+///              \xff body\n";
+/// let parse = |policy| {
+///     MboxParser::from_reader(&mbox[..])
+///         .with_invalid_utf8(policy)
+///         .next()
+///         .unwrap()
+/// };
+///
+/// let preserved = parse(InvalidUtf8::Preserve)?;
+/// assert_eq!(preserved.lines[0], b"Subject: \xff subject");
+/// assert_eq!(preserved.lines[1], b"X-Note: \xff value");
+/// assert_eq!(preserved.lines[3], b"\xff body");
+/// assert_eq!(preserved.invalid_utf8, Some(41));
+///
+/// let replaced = parse(InvalidUtf8::Lossy)?;
+/// assert_eq!(replaced.header("subject"), Some("\u{FFFD} subject"));
+/// assert_eq!(replaced.header("x-note"), Some("\u{FFFD} value"));
+/// assert_eq!(replaced.lines[3], "\u{FFFD} body".as_bytes());
+/// assert_eq!(replaced.invalid_utf8, Some(41));
+///
+/// assert!(matches!(
+///     parse(InvalidUtf8::Strict),
+///     Err(MailfmtError::InvalidUtf8 { index: 0, offset: 41 })
+/// ));
+/// # Ok::<(), MailfmtError>(())
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidUtf8 {
+    /// Pass the bytes through untouched.
+    #[default]
+    Preserve,
+    /// Replace each invalid sequence with U+FFFD, for destinations that require UTF-8.
+    Lossy,
+    /// Count the message as an error, reporting where the first invalid sequence is.
+    Strict,
+}
+
+impl InvalidUtf8 {
+    /// Apply the policy to some bytes, returning them together with the position of their first
+    /// invalid sequence, if any. Strict has to be handled by the caller, which knows what to
+    /// report; the bytes are passed through for it.
+    pub(crate) fn apply(self, bytes: &[u8]) -> (Cow<'_, [u8]>, Option<usize>) {
+        match std::str::from_utf8(bytes) {
+            Ok(_) => (Cow::Borrowed(bytes), None),
+            Err(e) if self == Self::Lossy => (
+                Cow::Owned(String::from_utf8_lossy(bytes).into_owned().into_bytes()),
+                Some(e.valid_up_to()),
+            ),
+            Err(e) => (Cow::Borrowed(bytes), Some(e.valid_up_to())),
+        }
+    }
+}
+
+impl fmt::Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Preserve => "preserve",
+            Self::Lossy => "lossy",
+            Self::Strict => "strict",
+        })
+    }
+}
+
+impl FromStr for InvalidUtf8 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "preserve" => Ok(Self::Preserve),
+            "lossy" => Ok(Self::Lossy),
+            "strict" => Ok(Self::Strict),
+            _ => Err(format!(
+                "unknown invalid UTF-8 policy '{s}', expected one of preserve, lossy or strict"
+            )),
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    error::Error,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub(crate) type Result<T, E = MailfmtError> = std::result::Result<T, E>;
+
+/// An error returned by a conversion or reported through [`Event::Failed`](crate::Event::Failed).
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum MailfmtError {
+    /// The output already exists and neither replacing nor appending to it was requested.
+    #[error("File already exists at {path:?}")]
+    OutputExists { path: PathBuf },
+    /// The output directory already exists and replacing files inside of it was not requested.
+    #[error("Directory already exists at {path:?}")]
+    OutputDirectoryExists { path: PathBuf },
+    /// The output directory, or one of the directories to be created inside of it, exists as a
+    /// file.
+    #[error(
+        "Output path {path:?} exists and is a file, not a directory. Choose a different output path or remove the file."
+    )]
+    OutputNotADirectory { path: PathBuf },
+    /// The directory the output file goes in does not exist, and creating it was not
+    /// requested. `missing` is the topmost of the directories above it that do not exist.
+    #[error("Cannot write {path:?}, as the directory {missing:?} does not exist")]
+    OutputParentMissing { path: PathBuf, missing: PathBuf },
+    /// The input mbox file does not exist.
+    #[error("Mbox file at {path:?} does not exist")]
+    InputNotFound { path: PathBuf },
+    /// The input does not look like an mbox file.
+    #[error(
+        "File at {path:?} does not look like an mbox file: no \"From \" line followed by headers was found near its start"
+    )]
+    NotAnMbox { path: PathBuf },
+    /// The input does not look like a Babyl file.
+    #[error(
+        "File at {path:?} does not look like a Babyl file: it does not start with \"BABYL OPTIONS:\""
+    )]
+    NotBabyl { path: PathBuf },
+    /// Writing an output file would overwrite one of the input files.
+    #[error(
+        "Output file {output:?} would overwrite the input file {input:?}. Choose a different output path."
+    )]
+    OutputIsInput { output: PathBuf, input: PathBuf },
+    /// The output file is inside of the input directory being converted.
+    #[error(
+        "Output file {output:?} is inside of the input directory {input:?}. Choose an output path outside of the directory being converted."
+    )]
+    OutputInsideInput { output: PathBuf, input: PathBuf },
+    /// No files to convert were found in the input. `include` holds the include patterns that
+    /// were searched for, and is empty when the input itself is a pattern.
+    #[error("{}", no_input_files(input, include))]
+    NoInputFiles {
+        input: PathBuf,
+        include: Vec<String>,
+    },
+    /// The input mbox file is empty or holds only blank lines.
+    #[error("Mbox file at {path:?} contains no messages, so nothing was written")]
+    EmptyInput { path: PathBuf },
+    /// Every one of the `count` eml files found failed to convert, so no mbox was written.
+    #[error("All {count} eml files failed to convert, so no mbox file was written")]
+    AllFailed { count: usize },
+    /// Only an input directory can be watched for new files.
+    #[error("Only an input directory can be watched for new files")]
+    WatchRequiresDirectory,
+    /// A pattern is not a valid glob.
+    #[error("Invalid pattern '{pattern}': {reason}")]
+    InvalidPattern { pattern: String, reason: String },
+    /// Message `index` of an mbox could not be read.
+    #[error("failed to read message {index}")]
+    MessageParse { index: usize, source: io::Error },
+    /// An mbox starts with more content before its first separator than strict mode accepts.
+    #[error(
+        "The mbox file has {bytes} bytes in {lines} lines before its first \"From \" line{}. The first message may have lost its separator.",
+        match header_lines {
+            0 => String::new(),
+            count => format!(", {count} of which look like headers"),
+        }
+    )]
+    LeadingContent {
+        bytes: u64,
+        lines: usize,
+        header_lines: usize,
+    },
+    /// Message `index` of an mbox, at byte `offset`, has no header block.
+    #[error("message {index} at byte {offset} of the mbox file has no header block")]
+    Headerless { index: usize, offset: u64 },
+    /// Message `index` of an mbox, at byte `offset`, holds no line or only blank ones.
+    #[error("message {index} at byte {offset} of the mbox file is empty")]
+    EmptyMessage { index: usize, offset: u64 },
+    /// Message `index` of an mbox is not valid UTF-8, from byte `offset` of the mbox onwards.
+    #[error("message {index} is not valid UTF-8 from byte {offset} of the mbox file")]
+    InvalidUtf8 { index: usize, offset: u64 },
+    /// An eml file is not valid UTF-8, from byte `offset` of the file onwards.
+    #[error("eml file at {path:?} is not valid UTF-8 from byte {offset}")]
+    InvalidUtf8File { path: PathBuf, offset: u64 },
+    /// An eml file has no header block at its start.
+    #[error("no header block found at the start of the eml file at {path:?}")]
+    InvalidEml { path: PathBuf },
+    /// A gzip-compressed eml file cannot be decompressed, as it is truncated or corrupt.
+    #[error("eml file at {path:?} cannot be decompressed: {reason}")]
+    CorruptGzip { path: PathBuf, reason: &'static str },
+    /// An eml file lacks headers that were required, or has them without a value.
+    #[error("eml file at {path:?} lacks the required headers {}", missing.join(", "))]
+    MissingHeaders { path: PathBuf, missing: Vec<String> },
+    /// The JSON record on line `line` of a file being imported cannot be turned into a message.
+    #[error("record on line {line} cannot be imported: {reason}")]
+    InvalidRecord { line: usize, reason: String },
+    /// Another process did not release its lock on the output in time.
+    #[error(
+        "Timed out after {} seconds waiting for another process to release its lock on {path:?}",
+        timeout.as_secs()
+    )]
+    LockTimeout { path: PathBuf, timeout: Duration },
+    /// Another process did not remove its `.lock` file in time. `holder` is its process id,
+    /// if the lock file names one.
+    #[error(
+        "Timed out after {} seconds waiting for the lock file {path:?}{}",
+        timeout.as_secs(),
+        match holder {
+            Some(pid) => format!(" held by process {pid}. Remove it if that process is no longer running."),
+            None => ". Remove it if no other program is using the mbox file.".to_string(),
+        }
+    )]
+    DotlockTimeout {
+        path: PathBuf,
+        timeout: Duration,
+        holder: Option<u32>,
+    },
+    /// A state or resume file cannot be used.
+    #[error("State file at {path:?} cannot be used: {reason}")]
+    InvalidState { path: PathBuf, reason: String },
+    /// Resuming was requested, but the output directory holds no interrupted extraction.
+    #[error("No interrupted extraction to resume was found in {output:?}")]
+    NothingToResume { output: PathBuf },
+    /// The input was changed since the extraction being resumed was started.
+    #[error(
+        "Mbox file at {input:?} is not the file the extraction in {output:?} was started from. Only an unchanged input can be resumed."
+    )]
+    InputChanged { input: PathBuf, output: PathBuf },
+    /// No message starts where the extraction being resumed stopped.
+    #[error("No message starts at byte {offset} of {input:?}, where the extraction stopped")]
+    ResumeMisaligned { input: PathBuf, offset: u64 },
+    /// Resuming was requested for an extraction of a sample, whose messages are only chosen
+    /// once the whole input has been read.
+    #[error(
+        "An extraction of a sample cannot be resumed, as its messages are only chosen once the whole input has been read"
+    )]
+    SampleNotResumable,
+    /// Resuming was requested for an extraction of a range, whose files are numbered after
+    /// their position in the mbox rather than after those already extracted.
+    #[error(
+        "An extraction of a range cannot be resumed. Extract the rest of it with a range starting where it stopped instead."
+    )]
+    RangeNotResumable,
+    /// The filesystem holding `path` has fewer bytes `available` than the output is estimated
+    /// to need, with a 5% margin on top of the `required` estimate.
+    #[error(
+        "The output is estimated to need {required} bytes and a 5% margin, but only {available} bytes are available at {path:?}"
+    )]
+    InsufficientSpace {
+        path: PathBuf,
+        required: u64,
+        available: u64,
+    },
+    /// The IMAP server at `server` could not be talked to after `attempts` attempts.
+    #[error("Could not talk to the IMAP server at {server} after {attempts} attempts: {reason}")]
+    ImapConnection {
+        server: String,
+        attempts: u32,
+        reason: String,
+    },
+    /// The IMAP server refused to log `user` in.
+    #[error("The IMAP server at {server} refused to log in {user:?}: {response}")]
+    ImapLogin {
+        server: String,
+        user: String,
+        response: String,
+    },
+    /// Logging in to the IMAP server at `server` would send the password unencrypted to
+    /// another machine.
+    #[error("Logging in to the IMAP server at {server} would send the password unencrypted")]
+    ImapInsecure { server: String },
+    /// The IMAP mailbox to upload to could not be created, or the one to fetch from could not
+    /// be opened.
+    #[error("The IMAP mailbox {mailbox:?} cannot be used: {response}")]
+    ImapMailbox { mailbox: String, response: String },
+    /// The IMAP server refused a command, such as storing a message or fetching messages.
+    #[error("The IMAP server refused the request: {response}")]
+    ImapRejected { response: String },
+    /// An I/O operation on a file failed. `action` describes what was being done.
+    #[error("failed to {action} at {path:?}")]
+    Io {
+        action: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// Messages written to the mbox did not read back as the eml files they were written from,
+    /// or were not found at all. Each was reported with an [`Event::Warning`](crate::Event::Warning).
+    #[error(
+        "{mismatched} of the {written} messages written to the mbox file did not read back as the eml files they were written from. The mbox file was kept for inspection."
+    )]
+    VerificationFailed { mismatched: usize, written: usize },
+    /// The `on_event` callback returned an error, which stopped the conversion.
+    #[error(transparent)]
+    Aborted(Box<dyn Error + Send + Sync>),
+}
+
+impl MailfmtError {
+    /// A stable identifier for the kind of error, for use in machine-readable reports.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::OutputExists { .. } | Self::OutputDirectoryExists { .. } => "output-exists",
+            Self::OutputNotADirectory { .. } => "output-not-a-directory",
+            Self::OutputParentMissing { .. } => "output-parent-missing",
+            Self::InputNotFound { .. } => "input-not-found",
+            Self::NotAnMbox { .. } => "not-an-mbox",
+            Self::NotBabyl { .. } => "not-babyl",
+            Self::OutputIsInput { .. } => "output-is-input",
+            Self::OutputInsideInput { .. } => "output-inside-input",
+            Self::NoInputFiles { .. } => "no-input-files",
+            Self::EmptyInput { .. } => "empty-input",
+            Self::AllFailed { .. } => "all-failed",
+            Self::WatchRequiresDirectory => "watch-requires-directory",
+            Self::InvalidPattern { .. } => "invalid-pattern",
+            Self::MessageParse { .. } => "message-parse",
+            Self::LeadingContent { .. } => "leading-content",
+            Self::Headerless { .. } => "headerless",
+            Self::EmptyMessage { .. } => "empty-message",
+            Self::InvalidUtf8 { .. } | Self::InvalidUtf8File { .. } => "invalid-utf8",
+            Self::InvalidEml { .. } => "invalid-eml",
+            Self::CorruptGzip { .. } => "corrupt-gzip",
+            Self::MissingHeaders { .. } => "missing-headers",
+            Self::InvalidRecord { .. } => "invalid-record",
+            Self::LockTimeout { .. } | Self::DotlockTimeout { .. } => "lock-timeout",
+            Self::InvalidState { .. } => "invalid-state",
+            Self::NothingToResume { .. } => "nothing-to-resume",
+            Self::InputChanged { .. } => "input-changed",
+            Self::ResumeMisaligned { .. } => "resume-misaligned",
+            Self::SampleNotResumable => "sample-not-resumable",
+            Self::RangeNotResumable => "range-not-resumable",
+            Self::InsufficientSpace { .. } => "insufficient-space",
+            Self::ImapConnection { .. } => "imap-connection",
+            Self::ImapLogin { .. } => "imap-login",
+            Self::ImapInsecure { .. } => "imap-insecure",
+            Self::ImapMailbox { .. } => "imap-mailbox",
+            Self::ImapRejected { .. } => "imap-rejected",
+            Self::Io { source, .. } if source.kind() == io::ErrorKind::StorageFull => "disk-full",
+            Self::Io { .. } => "io",
+            Self::VerificationFailed { .. } => "verification-failed",
+            Self::Aborted(_) => "aborted",
+        }
+    }
+}
+
+/// The message of [`MailfmtError::NoInputFiles`], which names the pattern or the include
+/// patterns that matched nothing.
+fn no_input_files(input: &Path, include: &[String]) -> String {
+    match include.is_empty() {
+        true => format!(
+            "Did not find any eml files matching the pattern '{}'",
+            input.display()
+        ),
+        false => format!(
+            "Did not find any files matching {} inside of {input:?}",
+            include
+                .iter()
+                .map(|pattern| format!("'{pattern}'"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// A message or file that could not be converted, as collected in a [`ConversionSummary`](crate::ConversionSummary).
+#[derive(Debug)]
+pub struct MessageError {
+    /// What was being converted, such as "saving email 12".
+    pub item: String,
+    pub error: MailfmtError,
+}
+
+/// Attach the file and the action being performed to an I/O error.
+pub(crate) trait IoContext<T> {
+    fn io_context(self, action: &'static str, path: &Path) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn io_context(self, action: &'static str, path: &Path) -> Result<T> {
+        self.map_err(|source| MailfmtError::Io {
+            action,
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
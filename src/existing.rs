@@ -0,0 +1,100 @@
+//! The messages already extracted to an output directory, found by the Message-IDs in the
+//! header blocks of its eml files.
+
+use crate::{
+    Event,
+    duplicates::normalize_message_id,
+    eml::read_header_block,
+    error::{IoContext, Result},
+    headers::get_header_value,
+};
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, fs, path::Path, time::Instant};
+
+/// How many eml files are read between two [`Event::Scanning`] events.
+const SCAN_PROGRESS_INTERVAL: u64 = 500;
+
+/// The Message-IDs of the eml files in an output directory and its subdirectories, kept as
+/// hashes of their normalized form.
+#[derive(Default)]
+pub(crate) struct ExistingMessages {
+    message_ids: HashSet<[u8; 16]>,
+}
+
+impl ExistingMessages {
+    /// Read the header block of every eml file in `output_dir`, compressed or not, reporting
+    /// progress through `emit`. Files that cannot be read are reported and passed over.
+    pub(crate) fn scan(
+        output_dir: &Path,
+        keep_bom: bool,
+        emit: impl Fn(Event<'_>) -> Result<()>,
+    ) -> Result<Self> {
+        let start = Instant::now();
+        let mut existing = Self::default();
+        let mut files = 0;
+        emit(Event::Scanning { files })?;
+        let mut directories = vec![output_dir.to_path_buf()];
+        while let Some(directory) = directories.pop() {
+            for entry in fs::read_dir(&directory).io_context("read output directory", &directory)? {
+                let entry = entry.io_context("read output directory", &directory)?;
+                let path = entry.path();
+                // Symlinks are not followed, so that nothing outside the directory is read.
+                let file_type = entry
+                    .file_type()
+                    .io_context("read output directory", &path)?;
+                if file_type.is_dir() {
+                    directories.push(path);
+                } else if file_type.is_file() && is_eml_file(&path) {
+                    match read_header_block(&path, keep_bom) {
+                        Some(header_block) => {
+                            if let Some(id) = get_header_value(&header_block, "message-id") {
+                                existing.insert(id);
+                            }
+                        }
+                        None => emit(Event::Warning(format!(
+                            "Could not read {path:?} to find its Message-ID, so a message it holds may be extracted again"
+                        )))?,
+                    }
+                    files += 1;
+                    if files % SCAN_PROGRESS_INTERVAL == 0 {
+                        emit(Event::Scanning { files })?;
+                    }
+                }
+            }
+        }
+        emit(Event::Scanned {
+            files,
+            message_ids: existing.message_ids.len(),
+            elapsed: start.elapsed(),
+        })?;
+        Ok(existing)
+    }
+
+    fn insert(&mut self, message_id: &str) {
+        if let Some(hash) = hash(message_id) {
+            self.message_ids.insert(hash);
+        }
+    }
+
+    /// Whether a message with the given Message-ID header value is present.
+    pub(crate) fn contains(&self, message_id: Option<&str>) -> bool {
+        message_id
+            .and_then(hash)
+            .is_some_and(|hash| self.message_ids.contains(&hash))
+    }
+}
+
+/// The hash a Message-ID is kept as, or `None` for an empty one.
+fn hash(message_id: &str) -> Option<[u8; 16]> {
+    let message_id = normalize_message_id(message_id)?;
+    let mut hasher = Sha256::new();
+    hasher.update(message_id.as_bytes());
+    Some(hasher.finalize()[..16].try_into().unwrap())
+}
+
+/// Whether a file is named like the eml files extracted, compressed or not.
+fn is_eml_file(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|name| name.ends_with(".eml") || name.ends_with(".eml.gz"))
+}
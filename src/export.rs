@@ -0,0 +1,424 @@
+//! Export of messages as JSON Lines, one object per message, for loading mail into scripts
+//! and databases.
+
+use crate::{
+    ConversionSummary, Event, EventHandler, MailfmtError, MessageError, ProgressTotal,
+    atomic::AtomicFile,
+    counting::CountingReader,
+    encoding::{cr_line_endings, strip_bom},
+    error::{IoContext, Result},
+    gzip,
+    headers::{decode_encoded_words, is_header_line},
+    mbox::{MboxParser, check_mbox_input},
+    mime::{Entity, decode_text, encode_base64, for_each_part},
+    paths,
+};
+use serde_json::{Map, Value, json};
+use std::{
+    fmt, fs,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
+use tracing::info_span;
+
+/// What to do with the content of attachments when exporting messages as JSON.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AttachmentContent {
+    /// Describe attachments without their content.
+    #[default]
+    None,
+    /// Include the decoded content of attachments, encoded as base64.
+    Base64,
+}
+
+impl fmt::Display for AttachmentContent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Base64 => "base64",
+        })
+    }
+}
+
+impl FromStr for AttachmentContent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "base64" => Ok(Self::Base64),
+            _ => Err(format!(
+                "unknown attachment content '{s}', expected none or base64"
+            )),
+        }
+    }
+}
+
+/// Options for [`export_json`]. Create them with [`ExportJsonOptions::new`] and adjust the
+/// fields as needed.
+pub struct ExportJsonOptions<'a> {
+    /// An mbox file, or a directory searched recursively for .eml files.
+    pub input: PathBuf,
+    pub output_file: PathBuf,
+    /// Replace the output file if it already exists.
+    pub overwrite: bool,
+    /// Export from an input file even if it does not look like an mbox file.
+    pub force: bool,
+    pub attachments: AttachmentContent,
+    /// Set to stop the export after the current message. Nothing is written then.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called with progress and per-message results as the export goes on.
+    pub on_event: Option<EventHandler<'a>>,
+}
+
+impl ExportJsonOptions<'_> {
+    pub fn new(input: impl Into<PathBuf>, output_file: impl Into<PathBuf>) -> Self {
+        Self {
+            input: input.into(),
+            output_file: output_file.into(),
+            overwrite: false,
+            force: false,
+            attachments: AttachmentContent::default(),
+            cancel: None,
+            on_event: None,
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
+
+    /// Report a message that could not be exported, returning it for the summary.
+    fn failed(&self, item: String, error: MailfmtError) -> Result<MessageError> {
+        self.emit(Event::Failed {
+            item: item.clone(),
+            error: &error,
+        })?;
+        Ok(MessageError { item, error })
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// Where an exported message came from.
+enum Source<'a> {
+    Mbox { offset: u64, envelope: &'a str },
+    Eml(&'a Path),
+}
+
+/// Export every message of an mbox file, or of the eml files in a directory, to a JSON Lines
+/// file holding one object per message, read and written one message at a time. Each object
+/// has these keys, which are kept stable:
+///
+/// - `source`: `index`, the position of the message in the export counting from 0, and
+///   `file`, the eml file it was read from, or `offset`, the byte offset of its separator
+///   line in the mbox. The other one is null.
+/// - `envelope`: the `sender` and `date` of the mbox separator line, or null for eml files.
+/// - `headers`: every header by its lowercased name, with encoded words decoded. A header
+///   given more than once is an array of its values in order, any other a string.
+/// - `text`: the decoded plain text, as given by [`Message::text`](crate::Message::text), or
+///   null if there is none.
+/// - `attachments`: the parts that are attachments, have a file name or are not text, each
+///   with its `filename` (or null), `content_type`, decoded `size` in bytes and, with
+///   [`AttachmentContent::Base64`], its `content`.
+///
+/// Messages that cannot be read are reported through [`Event::Failed`] and left out.
+///
+/// ```
+/// use mailfmt::{ExportJsonOptions, export_json};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-export-json-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// fs::write(
+///     dir.join("in.mbox"),
+///     "From alice@example.com Mon Jan 01 00:00:00 2024\n\
+///      Received: from a\n\
+///      Received: from b\n\
+///      Subject: =?utf-8?q?Caf=C3=A9?=\n\
+///      Content-Type: multipart/mixed; boundary=b\n\
+///      \n\
+///      --b\n\
+///      \n\
+///      Hello\n\
+///      --b\n\
+///      Content-Type: application/pdf; name=menu.pdf\n\
+///      Content-Transfer-Encoding: base64\n\
+///      \n\
+///      JVBERi0=\n\
+///      --b--\n",
+/// )?;
+///
+/// export_json(&ExportJsonOptions::new(dir.join("in.mbox"), dir.join("out.jsonl")))?;
+/// let output = fs::read_to_string(dir.join("out.jsonl"))?;
+/// let message: serde_json::Value = serde_json::from_str(output.lines().next().unwrap())?;
+/// let headers = message.get("headers").unwrap();
+/// assert_eq!(headers.get("subject").unwrap().as_str(), Some("Café"));
+/// assert_eq!(headers.get("received").unwrap().as_array().unwrap().len(), 2);
+/// assert_eq!(message.get("text").unwrap().as_str(), Some("Hello"));
+/// let attachment = &message.get("attachments").unwrap().as_array().unwrap()[0];
+/// assert_eq!(attachment.get("filename").unwrap().as_str(), Some("menu.pdf"));
+/// assert_eq!(attachment.get("size").unwrap().as_u64(), Some(5));
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn export_json(options: &ExportJsonOptions<'_>) -> Result<ConversionSummary> {
+    let _span = info_span!("export_json", input = %options.input.display()).entered();
+    let start = Instant::now();
+    let (input, output_file) = (options.input.as_path(), options.output_file.as_path());
+    if output_file.exists() && !options.overwrite {
+        return Err(MailfmtError::OutputExists {
+            path: output_file.to_path_buf(),
+        });
+    }
+    let eml_files = match input.is_dir() {
+        true => Some(find_eml_files(input)?),
+        false => {
+            check_mbox_input(input, options.force, false)?;
+            None
+        }
+    };
+    if let Some(input) = eml_files
+        .iter()
+        .flatten()
+        .map(PathBuf::as_path)
+        .chain(eml_files.is_none().then_some(input))
+        .find(|input| paths::same_file(input, output_file))
+    {
+        return Err(MailfmtError::OutputIsInput {
+            output: output_file.to_path_buf(),
+            input: input.to_path_buf(),
+        });
+    }
+
+    let mut output = BufWriter::new(
+        AtomicFile::create(output_file).io_context("create output file", output_file)?,
+    );
+    let mut summary = ConversionSummary::default();
+    let mut write =
+        |summary: &mut ConversionSummary, lines: &[Vec<u8>], source: Source<'_>| -> Result<()> {
+            let index = summary.converted;
+            let (json, warning) = message_json(lines, index, source, options.attachments);
+            if let Some(warning) = warning {
+                options.emit(Event::Warning(format!(
+                    "the plain text of email {index} was not fully decoded: {warning}"
+                )))?;
+            }
+            let line = format!("{json}\n");
+            output
+                .write_all(line.as_bytes())
+                .io_context("write output file", output_file)?;
+            summary.converted += 1;
+            summary.bytes_written += line.len() as u64;
+            Ok(())
+        };
+
+    match &eml_files {
+        Some(eml_files) => {
+            options.emit(Event::Started {
+                total: ProgressTotal::Messages(eml_files.len() as u64),
+                position: 0,
+            })?;
+            for eml_file in eml_files {
+                if options.is_cancelled() {
+                    summary.interrupted = true;
+                    break;
+                }
+                match read_eml_file(eml_file) {
+                    Ok(lines) => write(&mut summary, &lines, Source::Eml(eml_file))?,
+                    Err(error) => summary
+                        .errors
+                        .push(options.failed(format!("processing {eml_file:?}"), error)?),
+                }
+                let processed = summary.converted + summary.errors.len();
+                options.emit(Event::Progress {
+                    position: processed as u64,
+                    processed,
+                })?;
+            }
+        }
+        None => {
+            let input_size =
+                gzip::content_size(input).io_context("read metadata of mbox file", input)?;
+            let reader =
+                CountingReader::new(gzip::open(input, 0).io_context("open mbox file", input)?);
+            let bytes_read = reader.counter();
+            options.emit(Event::Started {
+                total: match input_size {
+                    0 => ProgressTotal::Unknown,
+                    size => ProgressTotal::Bytes(size),
+                },
+                position: 0,
+            })?;
+            for message in MboxParser::from_reader(BufReader::new(reader)) {
+                if options.is_cancelled() {
+                    summary.interrupted = true;
+                    break;
+                }
+                match message {
+                    Ok(message) => {
+                        let source = Source::Mbox {
+                            offset: message.offset,
+                            envelope: &message.envelope,
+                        };
+                        write(&mut summary, &message.lines, source)?;
+                    }
+                    Err(error) => {
+                        let item = format!("reading email {}", summary.converted);
+                        summary.errors.push(options.failed(item, error)?);
+                    }
+                }
+                options.emit(Event::Progress {
+                    position: bytes_read.load(Ordering::Relaxed),
+                    processed: summary.converted + summary.errors.len(),
+                })?;
+            }
+        }
+    }
+
+    // An interrupted export is never moved into place; dropping the output removes its
+    // temporary file.
+    let output = output
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .io_context("flush output file", output_file)?;
+    if summary.interrupted {
+        summary.bytes_written = 0;
+    } else {
+        output
+            .commit()
+            .io_context("move output file into place", output_file)?;
+        options.emit(Event::Written(output_file))?;
+    }
+    summary.elapsed = start.elapsed();
+    Ok(summary)
+}
+
+/// The sorted eml files inside of a directory and its subdirectories. Symlinked directories
+/// are not followed.
+pub(crate) fn find_eml_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let (mut files, mut pending) = (Vec::new(), vec![dir.to_path_buf()]);
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).io_context("read directory", &dir)? {
+            let path = entry.io_context("read directory", &dir)?.path();
+            let metadata = fs::symlink_metadata(&path).io_context("read metadata", &path)?;
+            if metadata.is_dir() {
+                pending.push(path);
+            } else if path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("eml"))
+            {
+                files.push(path);
+            }
+        }
+    }
+    if files.is_empty() {
+        return Err(MailfmtError::NoInputFiles {
+            input: dir.to_path_buf(),
+            include: vec!["*.eml".to_string()],
+        });
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Read the lines of an eml file, without a leading byte-order mark or line endings, which may
+/// be CR alone.
+pub(crate) fn read_eml_file(eml_file: &Path) -> Result<Vec<Vec<u8>>> {
+    let content = fs::read(eml_file).io_context("read eml file", eml_file)?;
+    let content = strip_bom(&content);
+    let lf = cr_line_endings(content);
+    let content = lf.as_deref().unwrap_or(content);
+    if !content
+        .split(|&b| b == b'\n')
+        .next()
+        .is_some_and(|line| is_header_line(&String::from_utf8_lossy(line)))
+    {
+        return Err(MailfmtError::InvalidEml {
+            path: eml_file.to_path_buf(),
+        });
+    }
+    let content = content.strip_suffix(b"\n").unwrap_or(content);
+    Ok(content
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+        .collect())
+}
+
+/// The JSON object for a message, with a warning if its plain text could not be fully decoded.
+fn message_json(
+    lines: &[Vec<u8>],
+    index: usize,
+    source: Source<'_>,
+    attachments: AttachmentContent,
+) -> (Value, Option<String>) {
+    let entity = Entity::parse(lines);
+    let mut headers = Map::new();
+    for field in entity.fields.iter().filter(|field| !field.name.is_empty()) {
+        let name = field.name.to_ascii_lowercase();
+        let value = Value::from(decode_encoded_words(field.value.trim()));
+        match headers.get_mut(&name) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => *existing = json!([existing.take(), value]),
+            None => {
+                headers.insert(name, value);
+            }
+        }
+    }
+
+    let mut parts = Vec::new();
+    for_each_part(lines, &mut |part| {
+        if !part.is_file() {
+            return;
+        }
+        let content = part.decode_body().unwrap_or_else(|| part.body.join(&b'\n'));
+        let mut attachment = json!({
+            "filename": part.filename(),
+            "content_type": part.content_type.media_type.to_ascii_lowercase(),
+            "size": content.len(),
+        });
+        if attachments == AttachmentContent::Base64 {
+            attachment["content"] = json!(encode_base64(&content));
+        }
+        parts.push(attachment);
+    });
+
+    let text = decode_text(lines);
+    let (file, offset, envelope) = match source {
+        Source::Mbox { offset, envelope } => {
+            let (sender, date) = envelope.split_once(' ').unwrap_or((envelope, ""));
+            let envelope = json!({
+                "sender": sender,
+                "date": date.trim(),
+            });
+            (Value::Null, json!(offset), envelope)
+        }
+        Source::Eml(path) => (json!(path.to_string_lossy()), Value::Null, Value::Null),
+    };
+    let json = json!({
+        "source": {
+            "index": index,
+            "file": file,
+            "offset": offset,
+        },
+        "envelope": envelope,
+        "headers": headers,
+        "text": text.as_ref().map(|text| text.text.as_str()),
+        "attachments": parts,
+    });
+    (json, text.and_then(|text| text.warning))
+}
@@ -0,0 +1,279 @@
+//! The subdirectories of the output directory that extracted messages are grouped into.
+
+use crate::{
+    encoding::InvalidUtf8,
+    error::{IoContext, Result},
+    gzip,
+    headers::decode_encoded_words,
+    mbox::MboxToEmlOptions,
+    writer::address_of,
+};
+use chrono::{DateTime, FixedOffset};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::BufReader,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use uuid::Uuid;
+
+/// How extracted messages are grouped into subdirectories of the output directory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GroupBy {
+    /// All messages are written to the output directory itself.
+    #[default]
+    None,
+    /// Every thread gets a subdirectory named after the subject of its first message.
+    Thread,
+    /// Messages are written to a `YYYY/MM` subdirectory for the month in their Date header, or
+    /// to `undated` if it is missing or cannot be parsed.
+    Date,
+    /// Messages are written to a subdirectory named after the address in their From header,
+    /// lowercased.
+    Sender,
+    /// Messages are written to a subdirectory named after the domain of the address in their
+    /// From header, lowercased.
+    SenderDomain,
+    /// Messages are written to the subdirectory recorded in their X-Folder header, as written
+    /// by [`EmlToMboxOptions::record_folder`], or to the output directory itself without one.
+    ///
+    /// [`EmlToMboxOptions::record_folder`]: crate::EmlToMboxOptions::record_folder
+    Folder,
+}
+
+impl fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Thread => "thread",
+            Self::Date => "date",
+            Self::Sender => "sender",
+            Self::SenderDomain => "sender-domain",
+            Self::Folder => "folder",
+        })
+    }
+}
+
+impl FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "thread" => Ok(Self::Thread),
+            "date" => Ok(Self::Date),
+            "sender" => Ok(Self::Sender),
+            "sender-domain" => Ok(Self::SenderDomain),
+            "folder" => Ok(Self::Folder),
+            _ => Err(format!(
+                "unknown grouping '{s}', expected none, thread, date, sender, sender-domain or folder"
+            )),
+        }
+    }
+}
+
+/// How the messages grouped into subdirectories are numbered in their file names.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Numbering {
+    /// Messages are numbered across the whole mbox, so that numbers are unique.
+    #[default]
+    Global,
+    /// Messages are numbered from 0 in every subdirectory.
+    PerDirectory,
+}
+
+impl fmt::Display for Numbering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Global => "global",
+            Self::PerDirectory => "per-dir",
+        })
+    }
+}
+
+impl FromStr for Numbering {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "global" => Ok(Self::Global),
+            "per-dir" => Ok(Self::PerDirectory),
+            _ => Err(format!(
+                "unknown numbering '{s}', expected global or per-dir"
+            )),
+        }
+    }
+}
+
+/// What the eml files of extracted messages are named after.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NameBy {
+    /// Their number, followed by their subject when they have one.
+    #[default]
+    Index,
+    /// A UUID of [`MboxToEmlOptions::uuid_version`](crate::MboxToEmlOptions::uuid_version),
+    /// for systems that do not want meaningful file names. Only a Message-ID index or sidecar
+    /// metadata map the files back to their messages.
+    Uuid,
+    /// The SHA-256 of the bytes written, in hexadecimal, so that identical messages are given
+    /// identical names. Only a Message-ID index or sidecar metadata map the files back to their
+    /// messages.
+    Hash,
+}
+
+impl fmt::Display for NameBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Index => "index",
+            Self::Uuid => "uuid",
+            Self::Hash => "hash",
+        })
+    }
+}
+
+impl FromStr for NameBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "index" => Ok(Self::Index),
+            "uuid" => Ok(Self::Uuid),
+            "hash" => Ok(Self::Hash),
+            _ => Err(format!(
+                "unknown naming '{s}', expected index, uuid or hash"
+            )),
+        }
+    }
+}
+
+/// The version of the UUIDs that name extracted messages with [`NameBy::Uuid`](crate::NameBy::Uuid).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UuidVersion {
+    /// Random UUIDs.
+    #[default]
+    V4,
+    /// UUIDs starting with the time they were generated, in milliseconds, so that they sort
+    /// in the order they were generated.
+    V7,
+}
+
+impl fmt::Display for UuidVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::V4 => "4",
+            Self::V7 => "7",
+        })
+    }
+}
+
+impl FromStr for UuidVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().trim_start_matches('v') {
+            "4" => Ok(Self::V4),
+            "7" => Ok(Self::V7),
+            _ => Err(format!("unknown UUID version '{s}', expected 4 or 7")),
+        }
+    }
+}
+
+impl UuidVersion {
+    /// A new UUID of this version, formatted in lowercase with hyphens. Those of version 7 are
+    /// strictly increasing within the process, even when several are generated within a
+    /// millisecond.
+    pub(crate) fn generate(self) -> String {
+        match self {
+            Self::V4 => Uuid::new_v4(),
+            Self::V7 => Uuid::now_v7(),
+        }
+        .to_string()
+    }
+}
+
+/// The directory for messages not grouped by date, as nothing gives their date.
+pub(crate) const UNDATED_DIRECTORY: &str = "undated";
+
+/// The `YYYY/MM` subdirectory for a message with the given date, in the time zone it was
+/// written in, or [`UNDATED_DIRECTORY`].
+pub(crate) fn date_directory(date: Option<DateTime<FixedOffset>>) -> PathBuf {
+    match date {
+        Some(date) => [date.format("%Y").to_string(), date.format("%m").to_string()]
+            .iter()
+            .collect(),
+        None => PathBuf::from(UNDATED_DIRECTORY),
+    }
+}
+
+/// The subdirectory for a message with the given X-Folder header, decoding its encoded words
+/// and sanitizing every component of its path. Empty, `.` and `..` components are dropped, so
+/// the directory never leaves the output directory, and `None` stands for the output directory
+/// itself.
+pub(crate) fn folder_directory(folder: &str) -> Option<PathBuf> {
+    let directory: PathBuf = decode_encoded_words(folder)
+        .split(['/', '\\'])
+        .map(str::trim)
+        .filter(|component| !matches!(*component, "" | "." | ".."))
+        .map(sanitize_filename::sanitize)
+        .filter(|component| !component.is_empty())
+        .collect();
+    (directory != Path::new("")).then_some(directory)
+}
+
+/// The directory for messages without a usable From address when grouping by sender.
+pub(crate) const UNKNOWN_SENDER_DIRECTORY: &str = "unknown";
+
+/// The directory shared by the messages of senders with fewer messages than the threshold.
+pub(crate) const OTHER_DIRECTORY: &str = "other";
+
+/// The lowercased address in a From header, or only its domain, that messages are grouped
+/// by when grouping by sender.
+pub(crate) fn sender_key(from: Option<&str>, domain: bool) -> Option<String> {
+    let address = from.and_then(address_of)?;
+    let key = match domain {
+        true => address.rsplit_once('@')?.1,
+        false => address,
+    };
+    Some(key.to_lowercase()).filter(|key| !key.is_empty())
+}
+
+/// Read every message of an mbox and count the messages of every sender, by [`sender_key`].
+pub(crate) fn sender_counts(
+    options: &MboxToEmlOptions<'_>,
+    domain: bool,
+) -> Result<HashMap<String, usize>> {
+    let input_file = options.input_file.as_path();
+    let file = gzip::open(input_file, 0).io_context("open mbox file", input_file)?;
+    let mut counts = HashMap::new();
+    for message in options
+        .parser(BufReader::new(file), 0)
+        .with_invalid_utf8(InvalidUtf8::Preserve)
+    {
+        // A message that cannot be read is not extracted, so it is not counted.
+        let Ok(message) = message else {
+            continue;
+        };
+        if let Some(key) = sender_key(message.header("from"), domain) {
+            *counts.entry(key).or_insert(0) += 1;
+        }
+    }
+    Ok(counts)
+}
+
+/// The subdirectory for a message from a sender, given by [`sender_key`]: one named after
+/// the sender, [`OTHER_DIRECTORY`] if the sender has fewer messages than counted, or
+/// [`UNKNOWN_SENDER_DIRECTORY`].
+pub(crate) fn sender_directory(
+    key: Option<String>,
+    counts: Option<&HashMap<String, usize>>,
+    threshold: usize,
+) -> PathBuf {
+    let Some(key) = key else {
+        return PathBuf::from(UNKNOWN_SENDER_DIRECTORY);
+    };
+    match counts.and_then(|counts| counts.get(&key)) {
+        Some(&count) if count < threshold => PathBuf::from(OTHER_DIRECTORY),
+        _ => PathBuf::from(sanitize_filename::sanitize(key)),
+    }
+}
@@ -0,0 +1,149 @@
+//! Compression and decompression of gzip files, for eml files that are compressed one by one
+//! and for mbox files that are compressed as a whole.
+
+use flate2::{
+    bufread::{GzDecoder, MultiGzDecoder},
+    write::GzEncoder,
+};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::Path,
+    str::FromStr,
+};
+
+/// How extracted eml files are compressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// They are written as they are.
+    #[default]
+    None,
+    /// They are compressed with gzip, and `.gz` is added to their names.
+    Gzip,
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Gzip => "gzip",
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "gzip" | "gz" => Ok(Self::Gzip),
+            _ => Err(format!("unknown compression '{s}', expected none or gzip")),
+        }
+    }
+}
+
+/// The bytes every gzip file starts with.
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+const TRUNCATED: &str = "the compressed data ends early, so the file is truncated";
+
+/// Whether a path has the `.gz` extension of gzip-compressed files.
+pub(crate) fn has_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gz"))
+}
+
+/// A file name or path without its `.gz` extension, if it has one.
+pub(crate) fn strip_extension(name: &str) -> Option<&str> {
+    let split = name.len().checked_sub(3)?;
+    (name.is_char_boundary(split) && name[split..].eq_ignore_ascii_case(".gz"))
+        .then(|| &name[..split])
+}
+
+/// The size a gzip file decompresses to, as recorded at its end. Sizes of 4 GiB or more wrap
+/// around, and only the last member counts for files of several.
+pub(crate) fn decompressed_size(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::End(-4))?;
+    let mut size = [0; 4];
+    file.read_exact(&mut size)?;
+    Ok(u32::from_le_bytes(size) as u64)
+}
+
+/// Whether data starts like a gzip file.
+pub(crate) fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Whether a file starts like a gzip file.
+fn is_gzip_file(file: &mut File) -> io::Result<bool> {
+    let mut start = Vec::with_capacity(MAGIC.len());
+    (&mut *file)
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut start)?;
+    file.rewind()?;
+    Ok(is_gzip(&start))
+}
+
+/// Open a file for reading from `offset` on, decompressing it as it is read when it is
+/// gzip-compressed, in which case `offset` is one in the decompressed content.
+pub(crate) fn open(path: &Path, offset: u64) -> io::Result<Box<dyn Read>> {
+    let mut file = File::open(path)?;
+    if !is_gzip_file(&mut file)? {
+        file.seek(SeekFrom::Start(offset))?;
+        return Ok(Box::new(file));
+    }
+    let mut decoder = MultiGzDecoder::new(BufReader::new(file));
+    io::copy(&mut (&mut decoder).take(offset), &mut io::sink())?;
+    Ok(Box::new(decoder))
+}
+
+/// The size of a file's content, which for a gzip-compressed file is the size it decompresses
+/// to, with the same limits as [`decompressed_size`].
+pub(crate) fn content_size(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    match is_gzip_file(&mut file)? {
+        true => decompressed_size(path),
+        false => Ok(file.metadata()?.len()),
+    }
+}
+
+/// Compress data into a gzip file of a single member.
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    let output = Vec::with_capacity(data.len() / 2 + 32);
+    let mut encoder = GzEncoder::new(output, flate2::Compression::default());
+    encoder
+        .write_all(data)
+        .expect("writing to memory cannot fail");
+    encoder.finish().expect("writing to memory cannot fail")
+}
+
+/// Decompress a gzip file, including files of several members one after another. Fails with
+/// what is wrong with the data when it is truncated or corrupt.
+pub(crate) fn decompress(mut data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if !is_gzip(data) {
+        return Err("the file is not gzip-compressed");
+    }
+    let mut output = Vec::new();
+    loop {
+        let mut member = GzDecoder::new(data);
+        member.read_to_end(&mut output).map_err(|error| match error.kind() {
+            io::ErrorKind::UnexpectedEof => TRUNCATED,
+            // flate2 tells a wrong checksum apart from invalid data by its message only.
+            _ if error.to_string().contains("checksum") => {
+                "the checksum of the decompressed content does not match, so the file is corrupt"
+            }
+            _ => "the compressed data is invalid, so the file is corrupt",
+        })?;
+        data = member.into_inner();
+        // Some tools pad the file with zeros after its last member.
+        if data.iter().all(|&byte| byte == 0) {
+            return Ok(output);
+        }
+        if !is_gzip(data) {
+            return Err("the file has data after its compressed content");
+        }
+    }
+}
@@ -0,0 +1,373 @@
+//! Utilities for the header block at the start of a message.
+
+use crate::{charset::Charset, mime::decode_base64};
+
+/// The header recording the folder an eml file was found in, relative to the input directory,
+/// with `/` between its components, such as `X-Folder: Clients/Acme`. Written by
+/// [`EmlToMboxOptions::record_folder`] and read back by [`GroupBy::Folder`].
+///
+/// [`EmlToMboxOptions::record_folder`]: crate::EmlToMboxOptions::record_folder
+/// [`GroupBy::Folder`]: crate::GroupBy::Folder
+pub const FOLDER_HEADER: &str = "X-Folder";
+
+/// The header recording the path of the eml file a message was read from, relative to the
+/// input directory, such as `X-Source-File: Clients/Acme/invoice.eml`. Written by
+/// [`EmlToMboxOptions::record_source`] and read back by [`MboxToEmlOptions::restore_names`].
+///
+/// [`EmlToMboxOptions::record_source`]: crate::EmlToMboxOptions::record_source
+/// [`MboxToEmlOptions::restore_names`]: crate::MboxToEmlOptions::restore_names
+pub const SOURCE_FILE_HEADER: &str = "X-Source-File";
+
+/// Whether a line looks like an RFC 5322 header field, i.e. a printable field name followed by a colon.
+///
+/// ```
+/// use mailfmt::headers::is_header_line;
+///
+/// assert!(is_header_line("Subject: Hello"));
+/// assert!(is_header_line("X-Custom:"));
+/// assert!(!is_header_line("Hello there: not a field"));
+/// assert!(!is_header_line("no colon"));
+/// ```
+pub fn is_header_line(line: &str) -> bool {
+    line.split_once(':').is_some_and(|(name, _)| {
+        !name.is_empty()
+            && name
+                .bytes()
+                .all(|b| (b'!'..=b'~').contains(&b) && b != b':')
+    })
+}
+
+/// The trimmed value of the first line of a message starting with the given header name,
+/// which is matched case-insensitively.
+///
+/// ```
+/// use mailfmt::headers::get_header_value;
+///
+/// let message = "Subject: Hello\nX-\u{212A}: look-alike\n\nBody";
+/// assert_eq!(get_header_value(message, "SUBJECT"), Some("Hello"));
+/// // Only ASCII letters are folded, so characters that lowercase to ASCII never match.
+/// assert_eq!(get_header_value(message, "x-k"), None);
+/// ```
+pub fn get_header_value<'a>(content: &'a str, header_name: &str) -> Option<&'a str> {
+    content
+        .lines()
+        .find_map(|line| strip_header_name(line, header_name))
+}
+
+/// Like [`get_header_value`], for a message that has already been split into lines, such as
+/// a [`Message`](crate::Message).
+/// Lines that are not valid UTF-8 are skipped.
+pub fn get_header_value_from_lines<'a>(lines: &'a [Vec<u8>], header_name: &str) -> Option<&'a str> {
+    lines
+        .iter()
+        .filter_map(|line| std::str::from_utf8(line).ok())
+        .find_map(|line| strip_header_name(line, header_name))
+}
+
+/// The trimmed value of a header line if it has the given name.
+fn strip_header_name<'a>(line: &'a str, header_name: &str) -> Option<&'a str> {
+    let (name, value) = line.split_once(':')?;
+    name.eq_ignore_ascii_case(header_name).then(|| value.trim())
+}
+
+/// Decode the RFC 2047 encoded words in a header value, such as `=?utf-8?Q?caf=C3=A9?=`, into
+/// the text they stand for. Whitespace between adjacent encoded words is dropped. Words that
+/// cannot be decoded are kept as they are, and unsupported charsets are read as UTF-8.
+///
+/// ```
+/// use mailfmt::headers::decode_encoded_words;
+///
+/// assert_eq!(decode_encoded_words("=?ISO-8859-1?Q?caf=E9_cr=E8me?="), "café crème");
+/// assert_eq!(decode_encoded_words("Re: =?utf-8?B?0J/RgNC40LI=?= =?utf-8?B?0LXRgg==?="), "Re: Привет");
+/// assert_eq!(decode_encoded_words("=?utf-8?X?nope?= plain"), "=?utf-8?X?nope?= plain");
+/// ```
+pub fn decode_encoded_words(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let (mut rest, mut after_word) = (value, false);
+    while let Some(start) = rest.find("=?") {
+        let (before, candidate) = rest.split_at(start);
+        match decode_encoded_word(candidate) {
+            Some((text, length)) => {
+                // Whitespace only separates adjacent encoded words, and is not part of the text.
+                if !(after_word && before.trim().is_empty()) {
+                    decoded.push_str(before);
+                }
+                decoded.push_str(&text);
+                rest = &candidate[length..];
+                after_word = true;
+            }
+            None => {
+                decoded.push_str(before);
+                decoded.push_str("=?");
+                rest = &candidate[2..];
+                after_word = false;
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Decode the encoded word at the start of some text, returning it with the length it had.
+pub(crate) fn decode_encoded_word(text: &str) -> Option<(String, usize)> {
+    let inner = text.strip_prefix("=?")?;
+    let (charset, inner) = inner.split_once('?')?;
+    let (encoding, inner) = inner.split_once('?')?;
+    let end = inner.find("?=")?;
+    let encoded = &inner[..end];
+    if encoded.contains(char::is_whitespace) {
+        return None;
+    }
+    let bytes = match encoding {
+        "B" | "b" => decode_base64(encoded.as_bytes())?,
+        "Q" | "q" => decode_q(encoded.as_bytes())?,
+        _ => return None,
+    };
+    // A language may follow the charset, as in "utf-8*en".
+    let label = charset.split('*').next().unwrap_or_default();
+    let text = match Charset::for_label(label) {
+        Some(charset) => charset.decode(&bytes)?,
+        None => String::from_utf8_lossy(&bytes).into_owned(),
+    };
+    let length = "=?".len() + charset.len() + encoding.len() + 2 + end + "?=".len();
+    Some((text, length))
+}
+
+/// Decode the "Q" encoding of an encoded word, a variant of quoted-printable.
+fn decode_q(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.iter();
+    while let Some(&b) = bytes.next() {
+        decoded.push(match b {
+            b'_' => b' ',
+            b'=' => {
+                let hex = [*bytes.next()?, *bytes.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            b => b,
+        });
+    }
+    Some(decoded)
+}
+
+/// The reply and forward markers removed by [`clean_subject`], compared case-insensitively, in
+/// the languages whose mail clients localize them.
+pub(crate) const SUBJECT_MARKERS: &[&str] = &[
+    "re", "fw", "fwd", // English
+    "aw", "wg", // German
+    "sv", "vs", "vb", // Scandinavian and Finnish
+    "antw", "doorst", // Dutch
+    "tr", "ref", // French
+    "r", "rif", "i", // Italian
+    "res", "enc", // Portuguese
+    "rv",  // Spanish
+    "odp", "pd", // Polish
+    "ynt", "ilt", // Turkish
+    "回复", "回覆", "答复", "转发", "轉寄", // Chinese
+    "返信", "転送", // Japanese
+];
+
+/// A subject without the reply and forward markers and the bracketed mailing list tags at its
+/// start, such as "Re: [rust-users] Fwd: ", in any order and repeated any number of times. A
+/// count after a marker, as in "Re[2]:", is removed with it. The whole subject is kept, trimmed,
+/// if nothing would be left.
+///
+/// ```
+/// use mailfmt::headers::clean_subject;
+///
+/// assert_eq!(clean_subject("Re: Re: Re: Important thing"), "Important thing");
+/// assert_eq!(clean_subject("RE: FW: Quarterly numbers"), "Quarterly numbers");
+/// assert_eq!(clean_subject("Fwd: Re: Lunch?"), "Lunch?");
+/// assert_eq!(clean_subject("Re: [rust-users] Re: Lifetimes in closures"), "Lifetimes in closures");
+/// assert_eq!(clean_subject("[Python-Dev] [RELEASE] Python 3.13.0"), "Python 3.13.0");
+/// assert_eq!(clean_subject("Re[2]: Server is down"), "Server is down");
+/// assert_eq!(clean_subject("AW: WG: Termin am Montag"), "Termin am Montag");
+/// assert_eq!(clean_subject("SV: VS: Kokous"), "Kokous");
+/// assert_eq!(clean_subject("TR: RE : Réunion"), "Réunion");
+/// assert_eq!(clean_subject("R: I: Riunione"), "Riunione");
+/// assert_eq!(clean_subject("Odp: PD: Spotkanie"), "Spotkanie");
+/// assert_eq!(clean_subject("回复：会议"), "会议");
+/// // Only markers at the start are removed, and never the whole subject.
+/// assert_eq!(clean_subject("Ideas: Re: the plan"), "Ideas: Re: the plan");
+/// assert_eq!(clean_subject("Re: [announce]"), "Re: [announce]");
+/// assert_eq!(clean_subject(" Re: "), "Re:");
+/// ```
+pub fn clean_subject(subject: &str) -> &str {
+    let mut rest = subject.trim();
+    loop {
+        rest = rest.trim_start();
+        if let Some(tag) = rest.strip_prefix('[')
+            && let Some((name, after)) = tag.split_once(']')
+            && !name.trim().is_empty()
+        {
+            rest = after;
+            continue;
+        }
+        let Some(colon) = rest.find([':', '：']) else {
+            break;
+        };
+        let (prefix, after) = rest.split_at(colon);
+        let marker = prefix.trim_end();
+        // Some clients count replies, as in "Re[2]:" or "Re(2):".
+        let marker = match marker.strip_suffix([']', ')']) {
+            Some(counted) => counted
+                .trim_end_matches(|c: char| c.is_ascii_digit())
+                .strip_suffix(['[', '('])
+                .unwrap_or(marker),
+            None => marker,
+        };
+        let is_marker = SUBJECT_MARKERS
+            .iter()
+            .any(|known| marker.eq_ignore_ascii_case(known));
+        if !is_marker {
+            break;
+        }
+        rest = &after[after.chars().next().map_or(0, char::len_utf8)..];
+    }
+    match rest.trim() {
+        "" => subject.trim(),
+        cleaned => cleaned,
+    }
+}
+
+/// The list identifier in an unfolded List-Id header value (RFC 2919): the part between the
+/// angle brackets after any display name, or the whole trimmed value when it has none, as
+/// some lists leave them out. Returns `None` when there is no identifier.
+///
+/// ```
+/// use mailfmt::headers::list_id;
+///
+/// assert_eq!(list_id("Rust users <rust-users.rust-lang.org>"), Some("rust-users.rust-lang.org"));
+/// assert_eq!(list_id("\"Announce <list>\"  < announce.example.com > "), Some("announce.example.com"));
+/// assert_eq!(list_id("  bare.example.com "), Some("bare.example.com"));
+/// assert_eq!(list_id("No id <>"), None);
+/// ```
+pub fn list_id(value: &str) -> Option<&str> {
+    // A quoted display name may hold angle brackets of its own, so take the last pair.
+    let id = match value.rfind('<') {
+        Some(start) => {
+            let rest = &value[start + 1..];
+            &rest[..rest.find('>').unwrap_or(rest.len())]
+        }
+        None => value,
+    };
+    Some(id.trim()).filter(|id| !id.is_empty())
+}
+
+/// How long header lines are kept where possible when folding them.
+const FOLD_WIDTH: usize = 78;
+
+/// Write a header field, encoding the parts of its value that are not plain ASCII as RFC 2047
+/// encoded words and folding it at whitespace into lines of up to 78 characters where
+/// possible. The lines are joined with '\n'. Decoding and unfolding gives back the value.
+///
+/// ```
+/// use mailfmt::headers::{decode_encoded_words, encode_header};
+///
+/// assert_eq!(encode_header("From", "René <r@example.com>"), "From: =?utf-8?q?Ren=C3=A9?= <r@example.com>");
+///
+/// let subject = "A rather long subject about the café crème served every morning in the office";
+/// let field = encode_header("Subject", subject);
+/// assert!(field.lines().all(|line| line.len() <= 78));
+/// let unfolded = field.strip_prefix("Subject:").unwrap().replace('\n', "");
+/// assert_eq!(decode_encoded_words(unfolded.trim()), subject);
+/// ```
+pub fn encode_header(name: &str, value: &str) -> String {
+    // Each piece is the whitespace before an unbreakable word and the word itself.
+    let mut pieces: Vec<(&str, String)> = Vec::new();
+    let tokens = split_whitespace_runs(value);
+    let (mut i, mut space) = (0, "");
+    while i < tokens.len() {
+        let word = tokens[i];
+        i += 1;
+        if word.trim().is_empty() {
+            space = word;
+        } else if !needs_encoding(word) {
+            pieces.push((std::mem::take(&mut space), word.to_string()));
+        } else {
+            // Adjacent words needing encoding are encoded together, as the whitespace between
+            // encoded words is dropped when decoding them.
+            let mut text = word.to_string();
+            while i + 1 < tokens.len() && needs_encoding(tokens[i + 1]) {
+                text.push_str(tokens[i]);
+                text.push_str(tokens[i + 1]);
+                i += 2;
+            }
+            for (n, encoded) in encode_words(&text).into_iter().enumerate() {
+                pieces.push((
+                    if n == 0 {
+                        std::mem::take(&mut space)
+                    } else {
+                        " "
+                    },
+                    encoded,
+                ));
+            }
+        }
+    }
+
+    let mut field = format!("{name}:");
+    let mut line_start = 0;
+    for (i, (space, word)) in pieces.into_iter().enumerate() {
+        let space = if i == 0 { " " } else { space };
+        if i > 0 && field.len() - line_start + space.len() + word.len() > FOLD_WIDTH {
+            field.push('\n');
+            line_start = field.len();
+        }
+        field.push_str(space);
+        field.push_str(&word);
+    }
+    field
+}
+
+/// Split text into alternating runs of whitespace and other characters.
+fn split_whitespace_runs(text: &str) -> Vec<&str> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for (i, c) in text.char_indices().skip(1) {
+        let previous = text[..i]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_whitespace);
+        if c.is_whitespace() != previous {
+            runs.push(&text[start..i]);
+            start = i;
+        }
+    }
+    if start < text.len() {
+        runs.push(&text[start..]);
+    }
+    runs
+}
+
+/// Whether a word has to be written as an encoded word: it is not plain printable ASCII, or
+/// it would otherwise be mistaken for one.
+fn needs_encoding(word: &str) -> bool {
+    word.contains("=?") || !word.bytes().all(|b| (b' '..=b'~').contains(&b))
+}
+
+/// Encode text as UTF-8 "Q" encoded words of at most 75 characters each.
+fn encode_words(text: &str) -> Vec<String> {
+    const PREFIX: &str = "=?utf-8?q?";
+    const MAX_ENCODED: usize = 75 - PREFIX.len() - "?=".len();
+    let (mut words, mut encoded) = (Vec::new(), String::new());
+    for c in text.chars() {
+        let mut piece = String::new();
+        match c {
+            ' ' => piece.push('_'),
+            c if c.is_ascii_alphanumeric() || "!*+-/".contains(c) => piece.push(c),
+            c => {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    piece.push_str(&format!("={b:02X}"));
+                }
+            }
+        }
+        if encoded.len() + piece.len() > MAX_ENCODED {
+            words.push(format!("{PREFIX}{}?=", std::mem::take(&mut encoded)));
+        }
+        encoded.push_str(&piece);
+    }
+    words.push(format!("{PREFIX}{encoded}?="));
+    words
+}
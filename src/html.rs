@@ -0,0 +1,512 @@
+//! Just enough HTML to turn the HTML part of a message into readable plain text, for messages
+//! that have no plain text of their own. Malformed markup is read as forgivingly as browsers do:
+//! unclosed tags end the text, unknown tags are dropped and a `<` that starts no tag is text.
+
+use crate::charset::Charset;
+use std::{fmt, str::FromStr};
+
+/// When a text export takes the text of a message from its HTML part.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HtmlText {
+    /// Only when it has no plain text part.
+    #[default]
+    Fallback,
+    /// Whenever it has an HTML part, even if it also has plain text.
+    Always,
+    /// Never, so that messages without plain text get a placeholder.
+    Never,
+}
+
+impl fmt::Display for HtmlText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Fallback => "fallback",
+            Self::Always => "always",
+            Self::Never => "never",
+        })
+    }
+}
+
+impl FromStr for HtmlText {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fallback" => Ok(Self::Fallback),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(format!(
+                "unknown HTML handling '{s}', expected fallback, always or never"
+            )),
+        }
+    }
+}
+
+/// Elements whose content is not text to read.
+const SKIPPED_ELEMENTS: [&str; 6] = ["head", "script", "style", "template", "title", "noscript"];
+
+/// Elements that start and end a paragraph.
+const PARAGRAPH_ELEMENTS: [&str; 20] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "center",
+    "dl",
+    "div",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "ol",
+    "p",
+    "section",
+    "table",
+];
+
+/// Elements that start and end a line.
+const LINE_ELEMENTS: [&str; 6] = ["dd", "dt", "hr", "li", "tr", "ul"];
+
+/// Named character references, by name. Those that may be used without a semicolon come first.
+const ENTITIES: [(&str, char); 105] = [
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("quot", '"'),
+    ("nbsp", '\u{a0}'),
+    ("apos", '\''),
+    ("copy", '©'),
+    ("reg", '®'),
+    ("trade", '™'),
+    ("hellip", '…'),
+    ("mdash", '—'),
+    ("ndash", '–'),
+    ("lsquo", '‘'),
+    ("rsquo", '’'),
+    ("sbquo", '‚'),
+    ("ldquo", '“'),
+    ("rdquo", '”'),
+    ("bdquo", '„'),
+    ("laquo", '«'),
+    ("raquo", '»'),
+    ("lsaquo", '‹'),
+    ("rsaquo", '›'),
+    ("bull", '•'),
+    ("middot", '·'),
+    ("dagger", '†'),
+    ("euro", '€'),
+    ("pound", '£'),
+    ("yen", '¥'),
+    ("cent", '¢'),
+    ("curren", '¤'),
+    ("sect", '§'),
+    ("para", '¶'),
+    ("deg", '°'),
+    ("plusmn", '±'),
+    ("times", '×'),
+    ("divide", '÷'),
+    ("micro", 'µ'),
+    ("frac12", '½'),
+    ("frac14", '¼'),
+    ("frac34", '¾'),
+    ("sup1", '¹'),
+    ("sup2", '²'),
+    ("sup3", '³'),
+    ("iexcl", '¡'),
+    ("iquest", '¿'),
+    ("ordf", 'ª'),
+    ("ordm", 'º'),
+    ("larr", '←'),
+    ("rarr", '→'),
+    ("uarr", '↑'),
+    ("darr", '↓'),
+    ("check", '✓'),
+    ("shy", '\u{ad}'),
+    ("zwnj", '\u{200c}'),
+    ("zwj", '\u{200d}'),
+    ("lrm", '\u{200e}'),
+    ("rlm", '\u{200f}'),
+    ("ensp", '\u{2002}'),
+    ("emsp", '\u{2003}'),
+    ("thinsp", '\u{2009}'),
+    ("Agrave", 'À'),
+    ("Aacute", 'Á'),
+    ("Acirc", 'Â'),
+    ("Atilde", 'Ã'),
+    ("Auml", 'Ä'),
+    ("Aring", 'Å'),
+    ("AElig", 'Æ'),
+    ("Ccedil", 'Ç'),
+    ("Egrave", 'È'),
+    ("Eacute", 'É'),
+    ("Ecirc", 'Ê'),
+    ("Euml", 'Ë'),
+    ("Iacute", 'Í'),
+    ("Iuml", 'Ï'),
+    ("Ntilde", 'Ñ'),
+    ("Ograve", 'Ò'),
+    ("Oacute", 'Ó'),
+    ("Ocirc", 'Ô'),
+    ("Ouml", 'Ö'),
+    ("Oslash", 'Ø'),
+    ("Ugrave", 'Ù'),
+    ("Uacute", 'Ú'),
+    ("Uuml", 'Ü'),
+    ("szlig", 'ß'),
+    ("agrave", 'à'),
+    ("aacute", 'á'),
+    ("acirc", 'â'),
+    ("atilde", 'ã'),
+    ("auml", 'ä'),
+    ("aring", 'å'),
+    ("aelig", 'æ'),
+    ("ccedil", 'ç'),
+    ("egrave", 'è'),
+    ("eacute", 'é'),
+    ("ecirc", 'ê'),
+    ("euml", 'ë'),
+    ("iacute", 'í'),
+    ("iuml", 'ï'),
+    ("ntilde", 'ñ'),
+    ("ograve", 'ò'),
+    ("oacute", 'ó'),
+    ("ocirc", 'ô'),
+    ("ouml", 'ö'),
+    ("oslash", 'ø'),
+    ("uuml", 'ü'),
+];
+
+/// How many of [`ENTITIES`] may be used without a semicolon, as in `&amp` or `&nbsp`.
+const LEGACY_ENTITIES: usize = 5;
+
+/// Turn HTML into plain text: tags are dropped, character references decoded and whitespace
+/// collapsed, with paragraphs separated by blank lines, list items starting with `- ` and
+/// links followed by where they lead, as `text (url)`.
+pub(crate) fn html_to_text(html: &str) -> String {
+    let mut text = Renderer::default();
+    // The target and start in the text of the link being read.
+    let mut link: Option<(String, usize)> = None;
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '<' => {
+                let Some((tag, after)) = parse_tag(rest) else {
+                    text.push('<');
+                    rest = &rest[1..];
+                    continue;
+                };
+                rest = after;
+                let Tag::Element {
+                    name,
+                    closing,
+                    attributes,
+                } = tag
+                else {
+                    continue;
+                };
+                let name = name.as_str();
+                if !closing && SKIPPED_ELEMENTS.contains(&name) {
+                    rest = match skip_element(rest, name) {
+                        Some(after) => after,
+                        // A head that is never closed is taken to end where the body starts.
+                        None if name == "head" => rest,
+                        None => "",
+                    };
+                } else if name == "br" {
+                    text.line_break(1);
+                } else if name == "pre" {
+                    text.line_break(2);
+                    text.preformatted = !closing;
+                } else if PARAGRAPH_ELEMENTS.contains(&name) {
+                    text.line_break(2);
+                } else if LINE_ELEMENTS.contains(&name) {
+                    text.line_break(1);
+                    if name == "li" && !closing {
+                        text.push_str("- ");
+                    }
+                } else if matches!(name, "td" | "th") && !closing {
+                    text.space();
+                } else if name == "a" && !closing {
+                    link = attribute(&attributes, "href")
+                        .map(|href| (decode_references(href.trim()), text.len()));
+                } else if name == "a"
+                    && let Some((href, start)) = link.take()
+                {
+                    text.link(&href, start);
+                }
+            }
+            '&' => {
+                let (decoded, length) = character_reference(rest);
+                text.push_str(&decoded);
+                rest = &rest[length..];
+            }
+            _ => {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    text.finish()
+}
+
+/// What starts with a `<`.
+enum Tag {
+    Element {
+        /// The name of the element, lowercased.
+        name: String,
+        closing: bool,
+        attributes: Vec<(String, String)>,
+    },
+    /// A comment, doctype or processing instruction.
+    Other,
+}
+
+/// Parse the tag at the start of `html`, returning it and what follows it, or `None` if the
+/// `<` does not start a tag. A tag without its `>` runs to the end.
+fn parse_tag(html: &str) -> Option<(Tag, &str)> {
+    let after = &html[1..];
+    if let Some(comment) = after.strip_prefix("!--") {
+        let end = comment.find("-->").map_or(comment.len(), |end| end + 3);
+        return Some((Tag::Other, &comment[end..]));
+    }
+    if after.starts_with(['!', '?']) {
+        let end = after.find('>').map_or(after.len(), |end| end + 1);
+        return Some((Tag::Other, &after[end..]));
+    }
+    let (closing, after) = match after.strip_prefix('/') {
+        Some(after) => (true, after),
+        None => (false, after),
+    };
+    if !after.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let name_end = after
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != ':')
+        .unwrap_or(after.len());
+    let name = after[..name_end].to_ascii_lowercase();
+    let mut rest = &after[name_end..];
+    let mut attributes = Vec::new();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            rest = after;
+            break;
+        }
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(rest.len())
+            .max(1);
+        let attribute = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (raw, remaining) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let quoted = &after[1..];
+                    let end = quoted.find(quote).unwrap_or(quoted.len());
+                    (&quoted[..end], quoted.get(end + 1..).unwrap_or_default())
+                }
+                _ => {
+                    let end = after
+                        .find(|c: char| c.is_whitespace() || c == '>')
+                        .unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = raw.to_string();
+            rest = remaining;
+        }
+        attributes.push((attribute, value));
+    }
+    Some((
+        Tag::Element {
+            name,
+            closing,
+            attributes,
+        },
+        rest,
+    ))
+}
+
+fn attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(attribute, _)| attribute == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Skip the content of an element up to and including its end tag, or `None` without one.
+fn skip_element<'a>(html: &'a str, name: &str) -> Option<&'a str> {
+    let mut position = 0;
+    while let Some(found) = html[position..].find("</") {
+        let start = position + found;
+        let candidate = &html[start + 2..];
+        if candidate
+            .get(..name.len())
+            .is_some_and(|candidate| candidate.eq_ignore_ascii_case(name))
+            && !candidate[name.len()..].starts_with(|c: char| c.is_ascii_alphanumeric())
+        {
+            let end = candidate.find('>').map_or(candidate.len(), |end| end + 1);
+            return Some(&candidate[end..]);
+        }
+        position = start + 2;
+    }
+    None
+}
+
+/// Decode the character references in an attribute value.
+fn decode_references(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(position) = rest.find('&') {
+        decoded.push_str(&rest[..position]);
+        let (text, length) = character_reference(&rest[position..]);
+        decoded.push_str(&text);
+        rest = &rest[position + length..];
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Decode the character reference at the start of `html`, which starts with `&`, returning its
+/// text and length. Anything that is not a known reference is just the `&`.
+fn character_reference(html: &str) -> (String, usize) {
+    let after = &html[1..];
+    if let Some(number) = after.strip_prefix('#') {
+        let (digits, radix, prefix) = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => (hex, 16, 2),
+            None => (number, 10, 1),
+        };
+        let end = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        if end > 0 {
+            let value =
+                u32::from_str_radix(&digits[..end].chars().take(8).collect::<String>(), radix)
+                    .unwrap_or(u32::MAX);
+            let semicolon = usize::from(digits[end..].starts_with(';'));
+            return (
+                numeric_reference(value).to_string(),
+                1 + prefix + end + semicolon,
+            );
+        }
+        return ("&".to_string(), 1);
+    }
+    let name_end = after
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(after.len());
+    let name = &after[..name_end];
+    let terminated = after[name_end..].starts_with(';');
+    ENTITIES
+        .iter()
+        .enumerate()
+        .find(|(index, (entity, _))| *entity == name && (terminated || *index < LEGACY_ENTITIES))
+        .map_or(("&".to_string(), 1), |(_, (_, c))| {
+            (c.to_string(), 1 + name_end + usize::from(terminated))
+        })
+}
+
+/// The character of a numeric reference. Like browsers, the C1 control codes are read as the
+/// Windows-1252 characters they stand for in pages that were mislabelled, and anything that is
+/// not a character is U+FFFD.
+fn numeric_reference(value: u32) -> char {
+    match value {
+        0x80..=0x9F => Charset::fallback()
+            .decode(&[value as u8])
+            .and_then(|decoded| decoded.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER),
+        0 => char::REPLACEMENT_CHARACTER,
+        _ => char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER),
+    }
+}
+
+/// Builds the text of an HTML document, collapsing whitespace outside of `<pre>` elements.
+#[derive(Default)]
+struct Renderer {
+    text: String,
+    /// Whether whitespace was read since the last character written.
+    space: bool,
+    /// How many line breaks are due before the next character written.
+    breaks: usize,
+    preformatted: bool,
+}
+
+impl Renderer {
+    fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    fn push(&mut self, c: char) {
+        match c {
+            // Invisible characters that only pad previews and break up words.
+            '\u{ad}' | '\u{34f}' | '\u{200b}'..='\u{200f}' | '\u{feff}' | '\r' => {}
+            _ if c.is_whitespace() && !self.preformatted => self.space = true,
+            _ => {
+                if !self.text.is_empty() {
+                    if self.breaks > 0 {
+                        self.trim_trailing_spaces();
+                        self.text.extend(std::iter::repeat_n('\n', self.breaks));
+                    } else if self.space && !self.text.ends_with('\n') {
+                        self.text.push(' ');
+                    }
+                }
+                self.text.push(c);
+                (self.space, self.breaks) = (false, 0);
+            }
+        }
+    }
+
+    fn push_str(&mut self, text: &str) {
+        text.chars().for_each(|c| self.push(c));
+    }
+
+    /// Separate what follows with a space, as between table cells.
+    fn space(&mut self) {
+        self.space = true;
+    }
+
+    /// End the line, with `count` line breaks before what follows: 1 for a new line and 2 for a
+    /// new paragraph.
+    fn line_break(&mut self, count: usize) {
+        self.breaks = self.breaks.max(count);
+        self.space = false;
+    }
+
+    /// Follow the text of a link from `start` with its target, unless that is what the text
+    /// says already or leads nowhere outside of the page.
+    fn link(&mut self, href: &str, start: usize) {
+        let text = self.text[start..].trim();
+        let target = href.strip_prefix("mailto:").unwrap_or(href);
+        if text.is_empty()
+            || target.is_empty()
+            || href.starts_with('#')
+            || href.to_ascii_lowercase().starts_with("javascript:")
+            || text.trim_end_matches('/') == target.trim_end_matches('/')
+        {
+            return;
+        }
+        self.space = true;
+        self.push_str(&format!("({target})"));
+    }
+
+    fn trim_trailing_spaces(&mut self) {
+        let trimmed = self.text.trim_end_matches([' ', '\t']).len();
+        self.text.truncate(trimmed);
+    }
+
+    fn finish(mut self) -> String {
+        self.trim_trailing_spaces();
+        let trimmed = self.text.trim_end().len();
+        self.text.truncate(trimmed);
+        self.text
+    }
+}
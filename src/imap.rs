@@ -0,0 +1,1365 @@
+//! Upload of messages to a mailbox on an IMAP server with the APPEND command, so that an
+//! archive ends up in the account its owner reads mail in, and fetching of the messages of a
+//! mailbox into an mbox file or eml files.
+//!
+//! The protocol is spoken by the `imap` crate. Connections use TLS unless the mailbox is
+//! given as a plain `imap://` URL, and the password is only sent over a plain connection to a
+//! server on this machine unless the options allow it.
+
+use crate::{
+    ConversionSummary, Event, EventHandler, MailfmtError, MessageError, ProgressTotal,
+    counting::CountingReader,
+    error::{IoContext, Result},
+    export::{find_eml_files, read_eml_file},
+    gzip,
+    headers::decode_encoded_words,
+    mbox::{
+        MboxParser, check_mbox_input, check_output_directory, create_output_directory,
+        eml_file_name, save_eml_file,
+    },
+    mime::{Entity, decode_percent, encode_base64},
+    state::FetchState,
+    status,
+    writer::{
+        FromLineStyle, MboxDialect, MboxWriter, PLACEHOLDER_DATE, PLACEHOLDER_SENDER,
+        envelope_parts, parse_date,
+    },
+};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
+use imap::{ClientBuilder, ConnectionMode, extensions::idle::SetReadTimeout, types::Flag};
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::info_span;
+
+/// The port IMAP servers listen on for plain connections, which STARTTLS may upgrade to TLS.
+pub const IMAP_PORT: u16 = 143;
+
+/// The port IMAP servers listen on for TLS connections.
+pub const IMAPS_PORT: u16 = 993;
+
+/// How the connection to an IMAP server is made.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImapSecurity {
+    /// TLS from the start, as `imaps://` URLs ask for.
+    #[default]
+    Tls,
+    /// A plain connection upgraded to TLS with STARTTLS before logging in.
+    StartTls,
+    /// A plain connection, as `imap://` URLs ask for, which sends the password unencrypted.
+    Plain,
+}
+
+/// A mailbox on an IMAP server, given as an `imaps://` or `imap://` URL as described by
+/// RFC 5092, such as `imaps://alice@imap.example.com/Archive`. The user and the port are
+/// optional, and the mailbox defaults to INBOX. `imaps://` URLs connect with TLS, and `imap://`
+/// URLs with a plain connection.
+///
+/// ```
+/// use mailfmt::{ImapMailbox, ImapSecurity};
+///
+/// let mailbox: ImapMailbox = "imaps://alice@imap.example.com/Archive%202024".parse()?;
+/// assert_eq!(mailbox.user.as_deref(), Some("alice"));
+/// assert_eq!((mailbox.host.as_str(), mailbox.port), ("imap.example.com", 993));
+/// assert_eq!(mailbox.security, ImapSecurity::Tls);
+/// assert_eq!(mailbox.mailbox, "Archive 2024");
+///
+/// let inbox: ImapMailbox = "imap://[::1]".parse()?;
+/// assert_eq!((inbox.server(), inbox.mailbox.as_str()), ("[::1]:143".to_string(), "INBOX"));
+/// assert_eq!(inbox.security, ImapSecurity::Plain);
+/// assert!(inbox.is_loopback());
+/// # Ok::<(), String>(())
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImapMailbox {
+    pub host: String,
+    pub port: u16,
+    pub security: ImapSecurity,
+    /// The user to log in as, if the URL names one.
+    pub user: Option<String>,
+    /// The name of the mailbox, decoded.
+    pub mailbox: String,
+}
+
+impl ImapMailbox {
+    /// The host and port of the server, as in `localhost:143`.
+    pub fn server(&self) -> String {
+        match self.host.contains(':') {
+            true => format!("[{}]:{}", self.host, self.port),
+            false => format!("{}:{}", self.host, self.port),
+        }
+    }
+
+    /// Whether the server is this machine, so that what is sent to it stays on it.
+    pub fn is_loopback(&self) -> bool {
+        self.host.eq_ignore_ascii_case("localhost")
+            || self
+                .host
+                .parse::<IpAddr>()
+                .is_ok_and(|address| address.is_loopback())
+    }
+}
+
+impl fmt::Display for ImapMailbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.security {
+            ImapSecurity::Tls => "imaps://",
+            ImapSecurity::StartTls | ImapSecurity::Plain => "imap://",
+        })?;
+        if let Some(user) = &self.user {
+            write!(f, "{user}@")?;
+        }
+        write!(f, "{}/{}", self.server(), self.mailbox)
+    }
+}
+
+impl FromStr for ImapMailbox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let not_imap = || {
+            format!(
+                "'{s}' is not an imaps:// or imap:// URL, such as imaps://alice@imap.example.com/Archive"
+            )
+        };
+        let (scheme, rest) = s.split_once("://").ok_or_else(not_imap)?;
+        let (security, default_port) = match scheme.to_ascii_lowercase().as_str() {
+            "imaps" => (ImapSecurity::Tls, IMAPS_PORT),
+            "imap" => (ImapSecurity::Plain, IMAP_PORT),
+            _ => return Err(not_imap()),
+        };
+        let rest = rest.split(['?', '#']).next().unwrap_or_default();
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (user, address) = match authority.rsplit_once('@') {
+            // A user may be followed by the authentication mechanism to use, which is ignored.
+            Some((user, address)) => (
+                Some(decode(user.split(';').next().unwrap_or(user))?),
+                address,
+            ),
+            None => (None, authority),
+        };
+        let (host, port) = match address.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, rest) = bracketed
+                    .split_once(']')
+                    .ok_or_else(|| format!("'{s}' has an unclosed '[' in its host"))?;
+                match rest {
+                    "" => (host, None),
+                    rest => (host, Some(rest.strip_prefix(':').ok_or_else(not_imap)?)),
+                }
+            }
+            None => match address.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (address, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(format!("'{s}' has no host"));
+        }
+        let port = match port {
+            None | Some("") => default_port,
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("'{port}' is not a valid port"))?,
+        };
+        // Parameters such as ";UIDVALIDITY=" only matter for reading the mailbox.
+        let mailbox = decode(
+            path.split(';')
+                .next()
+                .unwrap_or_default()
+                .trim_end_matches('/'),
+        )?;
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            security,
+            user: user.filter(|user| !user.is_empty()),
+            mailbox: match mailbox.is_empty() {
+                true => "INBOX".to_string(),
+                false => mailbox,
+            },
+        })
+    }
+}
+
+/// Decode the %XX escapes of a part of a URL.
+fn decode(encoded: &str) -> Result<String, String> {
+    String::from_utf8(decode_percent(encoded.as_bytes()))
+        .map_err(|_| format!("'{encoded}' is not valid UTF-8 once decoded"))
+}
+
+/// Options for [`upload_to_imap`]. Create them with [`ImapUploadOptions::new`] and adjust the
+/// fields as needed.
+pub struct ImapUploadOptions<'a> {
+    /// An mbox file, or a directory searched recursively for .eml files.
+    pub input: PathBuf,
+    pub mailbox: ImapMailbox,
+    /// The user to log in as, the one named by `mailbox` by default.
+    pub user: String,
+    pub password: String,
+    /// Log in over a plain connection to a server that is not on this machine, sending the
+    /// password unencrypted. Without it, such an upload fails with
+    /// [`MailfmtError::ImapInsecure`] before connecting.
+    pub insecure: bool,
+    /// Upload from an input file even if it does not look like an mbox file.
+    pub force: bool,
+    /// How many messages are read from the input before they are uploaded one after another,
+    /// at least 1. Progress is reported once a batch is uploaded.
+    pub batch_size: usize,
+    /// How many times to connect again when the connection breaks, or the server reports it
+    /// is unavailable, before failing with [`MailfmtError::ImapConnection`]. Messages the
+    /// server had not confirmed are sent again, so one it stored without confirming it may be
+    /// uploaded twice.
+    pub retries: u32,
+    /// How long to wait before the first retry, doubling with every retry after it.
+    pub retry_delay: Duration,
+    /// How long to wait for the server to answer once connected before taking the connection
+    /// as broken.
+    pub timeout: Duration,
+    /// Report every message through [`Event::Uploaded`] without connecting to the server.
+    pub dry_run: bool,
+    /// Set to stop the upload before the next batch. Messages already uploaded stay on the
+    /// server.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called with progress and per-message results as the upload goes on.
+    pub on_event: Option<EventHandler<'a>>,
+}
+
+impl ImapUploadOptions<'_> {
+    pub fn new(input: impl Into<PathBuf>, mailbox: ImapMailbox) -> Self {
+        Self {
+            input: input.into(),
+            user: mailbox.user.clone().unwrap_or_default(),
+            mailbox,
+            password: String::new(),
+            insecure: false,
+            force: false,
+            batch_size: 10,
+            retries: 3,
+            retry_delay: Duration::from_secs(1),
+            timeout: Duration::from_secs(30),
+            dry_run: false,
+            cancel: None,
+            on_event: None,
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
+
+    /// Report a message that could not be uploaded, returning it for the summary.
+    fn failed(&self, item: String, error: MailfmtError) -> Result<MessageError> {
+        self.emit(Event::Failed {
+            item: item.clone(),
+            error: &error,
+        })?;
+        Ok(MessageError { item, error })
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// A message of an upload to an IMAP server, reported through [`Event::Uploaded`].
+#[derive(Debug)]
+pub struct UploadDetails {
+    /// The position of the message in the input, counting from 0.
+    pub index: usize,
+    /// The eml file the message was read from, or `None` for a message of an mbox.
+    pub file: Option<PathBuf>,
+    /// The byte offset of the message's separator line in the mbox, or `None` for an eml file.
+    pub offset: Option<u64>,
+    /// The Subject header, decoded, if the message has one.
+    pub subject: Option<String>,
+    /// The internal date the message gets on the server, as IMAP writes it, taken from its
+    /// Date header or else the date on its separator line. Without one, the server uses the
+    /// time of the upload.
+    pub internal_date: Option<String>,
+    /// The flags the message gets on the server, such as `\Seen`, from its Status and
+    /// X-Status headers. Messages marked as deleted do not get `\Deleted`, so that uploading
+    /// them does not get them expunged.
+    pub flags: Vec<&'static str>,
+    /// The size of the message as uploaded, with CRLF line endings, in bytes.
+    pub size: usize,
+    /// Whether the message was uploaded, rather than only listed by a dry run.
+    pub uploaded: bool,
+}
+
+/// The IMAP flag for each of the flags read by [`status::flags`] that is kept on upload.
+const IMAP_FLAGS: [(&str, &str); 4] = [
+    ("seen", "\\Seen"),
+    ("answered", "\\Answered"),
+    ("flagged", "\\Flagged"),
+    ("draft", "\\Draft"),
+];
+
+/// Where a message to upload comes from.
+enum Source<'a> {
+    Mbox { offset: u64, envelope: &'a str },
+    Eml(&'a Path),
+}
+
+/// A message read from the input and waiting to be uploaded.
+struct Pending {
+    item: String,
+    details: UploadDetails,
+    /// The internal date of the message, as written in `details`.
+    date: Option<DateTime<FixedOffset>>,
+    /// The message with CRLF line endings, as IMAP wants it.
+    data: Vec<u8>,
+}
+
+impl Pending {
+    fn new(index: usize, lines: &[Vec<u8>], source: Source<'_>) -> Self {
+        let (lines, item, envelope_date) = match source {
+            Source::Mbox { envelope, .. } => (
+                // The blank line before the next separator is not part of the message.
+                match lines.split_last() {
+                    Some((last, rest)) if last.is_empty() => rest,
+                    _ => lines,
+                },
+                format!("uploading email {index}"),
+                envelope.split_once(' ').map(|(_, date)| date.trim()),
+            ),
+            Source::Eml(file) => (lines, format!("uploading {file:?}"), None),
+        };
+        let entity = Entity::parse(lines);
+        let header = |name: &str| {
+            entity
+                .fields
+                .iter()
+                .find(|field| field.is(name))
+                .map(|field| field.value.trim())
+        };
+        let date = header("date").and_then(parse_date).or_else(|| {
+            NaiveDateTime::parse_from_str(envelope_date?, "%a %b %e %H:%M:%S %Y")
+                .ok()
+                .map(|date| date.and_utc().fixed_offset())
+        });
+        let flags = status::flags(header)
+            .into_iter()
+            .filter_map(|flag| {
+                IMAP_FLAGS
+                    .iter()
+                    .find(|(name, _)| *name == flag)
+                    .map(|&(_, imap)| imap)
+            })
+            .collect();
+        let mut data = Vec::with_capacity(lines.iter().map(|line| line.len() + 2).sum());
+        for line in lines {
+            data.extend_from_slice(line);
+            data.extend_from_slice(b"\r\n");
+        }
+        let details = UploadDetails {
+            index,
+            file: match source {
+                Source::Eml(file) => Some(file.to_path_buf()),
+                Source::Mbox { .. } => None,
+            },
+            offset: match source {
+                Source::Mbox { offset, .. } => Some(offset),
+                Source::Eml(_) => None,
+            },
+            subject: header("subject").map(decode_encoded_words),
+            internal_date: date.map(|date| date.format("%d-%b-%Y %H:%M:%S %z").to_string()),
+            flags,
+            size: data.len(),
+            uploaded: false,
+        };
+        Self {
+            item,
+            details,
+            date,
+            data,
+        }
+    }
+}
+
+/// Upload every message of an mbox file, or of the eml files in a directory, to a mailbox on
+/// an IMAP server, creating the mailbox if it does not exist. Every message gets the internal
+/// date and flags described by [`UploadDetails`], and its line endings are changed to CRLF.
+///
+/// Messages the server rejects are reported through [`Event::Failed`] and counted as errors,
+/// while a refused login or a mailbox that cannot be created fail the whole upload, as does a
+/// plain connection to another machine unless `insecure` allows it.
+///
+/// ```
+/// use mailfmt::{Event, ImapUploadOptions, upload_to_imap};
+/// use std::{cell::RefCell, fs};
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-upload-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// fs::write(
+///     dir.join("in.mbox"),
+///     "From alice@example.com Mon Jan 01 00:00:00 2024\n\
+///      Subject: Read\n\
+///      Date: Tue, 02 Jan 2024 10:30:00 +0100\n\
+///      Status: RO\n\
+///      \n\
+///      Hello\n",
+/// )?;
+///
+/// let listed = RefCell::new(Vec::new());
+/// let mut options = ImapUploadOptions::new(dir.join("in.mbox"), "imap://alice@localhost/Archive".parse()?);
+/// options.dry_run = true;
+/// options.on_event = Some(Box::new(|event| {
+///     if let Event::Uploaded(details) = event {
+///         listed.borrow_mut().push((details.internal_date.clone(), details.flags.clone()));
+///     }
+///     Ok(())
+/// }));
+/// let summary = upload_to_imap(&options)?;
+/// assert_eq!(summary.converted, 1);
+/// assert_eq!(
+///     listed.take(),
+///     [(Some("02-Jan-2024 10:30:00 +0100".to_string()), vec!["\\Seen"])]
+/// );
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn upload_to_imap(options: &ImapUploadOptions<'_>) -> Result<ConversionSummary> {
+    let _span = info_span!("upload_to_imap", input = %options.input.display()).entered();
+    let start = Instant::now();
+    if !options.dry_run {
+        check_security(&options.mailbox, options.insecure)?;
+    }
+    let input = options.input.as_path();
+    let eml_files = match input.is_dir() {
+        true => Some(find_eml_files(input)?),
+        false => {
+            check_mbox_input(input, options.force, false)?;
+            None
+        }
+    };
+
+    let mut uploader = Uploader::new(options);
+    match &eml_files {
+        Some(eml_files) => {
+            options.emit(Event::Started {
+                total: ProgressTotal::Messages(eml_files.len() as u64),
+                position: 0,
+            })?;
+            for (index, eml_file) in eml_files.iter().enumerate() {
+                if options.is_cancelled() {
+                    uploader.summary.interrupted = true;
+                    break;
+                }
+                match read_eml_file(eml_file) {
+                    Ok(lines) => {
+                        uploader.add(Pending::new(index, &lines, Source::Eml(eml_file)))?
+                    }
+                    Err(error) => uploader
+                        .summary
+                        .errors
+                        .push(options.failed(format!("processing {eml_file:?}"), error)?),
+                }
+                uploader.progress(index as u64 + 1)?;
+            }
+        }
+        None => {
+            let input_size =
+                gzip::content_size(input).io_context("read metadata of mbox file", input)?;
+            let reader =
+                CountingReader::new(gzip::open(input, 0).io_context("open mbox file", input)?);
+            let bytes_read = reader.counter();
+            options.emit(Event::Started {
+                total: match input_size {
+                    0 => ProgressTotal::Unknown,
+                    size => ProgressTotal::Bytes(size),
+                },
+                position: 0,
+            })?;
+            for (index, message) in MboxParser::from_reader(BufReader::new(reader)).enumerate() {
+                if options.is_cancelled() {
+                    uploader.summary.interrupted = true;
+                    break;
+                }
+                match message {
+                    Ok(message) => {
+                        let source = Source::Mbox {
+                            offset: message.offset,
+                            envelope: &message.envelope,
+                        };
+                        uploader.add(Pending::new(index, &message.lines, source))?;
+                    }
+                    Err(error) => {
+                        let item = format!("reading email {index}");
+                        uploader.summary.errors.push(options.failed(item, error)?);
+                    }
+                }
+                uploader.progress(bytes_read.load(Ordering::Relaxed))?;
+            }
+            uploader.summary.bytes_read = bytes_read.load(Ordering::Relaxed);
+        }
+    }
+
+    let mut summary = uploader.finish()?;
+    summary.elapsed = start.elapsed();
+    Ok(summary)
+}
+
+/// Why talking to the server failed.
+enum Failure {
+    /// The connection broke, or the server is unavailable for now, which retrying may fix.
+    Transient(String),
+    /// Something retrying cannot fix, such as a refused login.
+    Fatal(MailfmtError),
+}
+
+impl From<imap::Error> for Failure {
+    fn from(error: imap::Error) -> Self {
+        failure(error, |response| MailfmtError::ImapRejected { response })
+    }
+}
+
+impl From<MailfmtError> for Failure {
+    fn from(error: MailfmtError) -> Self {
+        Self::Fatal(error)
+    }
+}
+
+/// The failure of a command that ended with `error`. A command the server refused is fatal,
+/// with `refused` making the error from its response, unless the server refused it with
+/// UNAVAILABLE or INUSE as it is busy for now.
+fn failure(error: imap::Error, refused: impl FnOnce(String) -> MailfmtError) -> Failure {
+    let (status, information) = match error {
+        imap::Error::No(no) => ("NO", no.information),
+        imap::Error::Bad(bad) => ("BAD", bad.information),
+        imap::Error::Validate(error) => return Failure::Fatal(refused(error.to_string())),
+        imap::Error::ConnectionLost => {
+            return Failure::Transient("the server closed the connection".to_string());
+        }
+        imap::Error::Bye(bye) => {
+            return Failure::Transient(format!("the server closed the connection: {bye}"));
+        }
+        error => return Failure::Transient(error.to_string()),
+    };
+    let response = format!("{status} {information}");
+    // The `imap` crate leaves response codes it does not know in the text.
+    let code = information
+        .strip_prefix('[')
+        .and_then(|text| text.split([']', ' ']).next());
+    match code.is_some_and(|code| {
+        code.eq_ignore_ascii_case("UNAVAILABLE") || code.eq_ignore_ascii_case("INUSE")
+    }) {
+        true => Failure::Transient(format!("the server is unavailable: {response}")),
+        false => Failure::Fatal(refused(response)),
+    }
+}
+
+/// A session with an IMAP server, once logged in.
+type Session = imap::Session<imap::Connection>;
+
+/// An upload in progress: the session with the server and the messages waiting to be sent.
+struct Uploader<'o, 'a> {
+    options: &'o ImapUploadOptions<'a>,
+    session: Option<Session>,
+    /// The mailbox name as sent to the server.
+    mailbox: String,
+    /// Whether the mailbox is known to exist.
+    mailbox_ready: bool,
+    pending: Vec<Pending>,
+    summary: ConversionSummary,
+}
+
+impl<'o, 'a> Uploader<'o, 'a> {
+    fn new(options: &'o ImapUploadOptions<'a>) -> Self {
+        Self {
+            options,
+            session: None,
+            mailbox: encode_mailbox_name(&options.mailbox.mailbox),
+            mailbox_ready: false,
+            pending: Vec::new(),
+            summary: ConversionSummary::default(),
+        }
+    }
+
+    /// Queue a message, uploading the queue once it holds a batch. A dry run only reports it.
+    fn add(&mut self, mut pending: Pending) -> Result<()> {
+        if self.options.dry_run {
+            let _span = info_span!("message", index = pending.details.index).entered();
+            self.summary.converted += 1;
+            pending.details.uploaded = false;
+            return self.options.emit(Event::Uploaded(&pending.details));
+        }
+        self.pending.push(pending);
+        if self.pending.len() >= self.options.batch_size.max(1) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Report progress at `position`, once every queued message has been handled.
+    fn progress(&self, position: u64) -> Result<()> {
+        if !self.pending.is_empty() {
+            return Ok(());
+        }
+        self.options.emit(Event::Progress {
+            position,
+            processed: self.summary.converted + self.summary.errors.len(),
+        })
+    }
+
+    /// Upload the queued messages, connecting again after transient failures.
+    fn flush(&mut self) -> Result<()> {
+        let options = self.options;
+        let mut backoff = Backoff::new(
+            &options.mailbox,
+            options.retries,
+            options.retry_delay,
+            options.cancel.as_deref(),
+        );
+        while !self.pending.is_empty() {
+            let reason = match self.send_pending() {
+                Ok(()) => continue,
+                Err(Failure::Fatal(error)) => return Err(error),
+                Err(Failure::Transient(reason)) => reason,
+            };
+            self.session = None;
+            if !backoff.pause(reason, |event| options.emit(event))? {
+                self.summary.interrupted = true;
+                return Ok(());
+            }
+            self.summary.reconnects += 1;
+        }
+        Ok(())
+    }
+
+    /// Send the queued messages one after another. Those the server stored or rejected leave
+    /// the queue, even when a later one fails.
+    fn send_pending(&mut self) -> Result<(), Failure> {
+        self.connect()?;
+        let options = self.options;
+        let session = self.session.as_mut().expect("connected above");
+        // APPEND puts the name in quotes as it is given.
+        let mailbox = escape(&self.mailbox);
+        let mut pending = std::mem::take(&mut self.pending).into_iter();
+        while let Some(mut message) = pending.next() {
+            let _span = info_span!("message", index = message.details.index).entered();
+            let appended = {
+                let mut append = session.append(&mailbox, &message.data);
+                append.flags(message.details.flags.iter().map(|&flag| Flag::from(flag)));
+                if let Some(date) = message.date {
+                    append.internal_date(date);
+                }
+                append.finish()
+            };
+            let refused = |response| MailfmtError::ImapRejected { response };
+            let result = match appended {
+                Ok(_) => {
+                    message.details.uploaded = true;
+                    self.summary.converted += 1;
+                    self.summary.bytes_written += message.data.len() as u64;
+                    options.emit(Event::Uploaded(&message.details))
+                }
+                // The server answered the command before the message was sent, which the
+                // `imap` crate does not keep the response of.
+                Err(imap::Error::Append) => {
+                    let error = refused("the server refused the message".to_string());
+                    options
+                        .failed(message.item.clone(), error)
+                        .map(|error| self.summary.errors.push(error))
+                }
+                Err(error) => match failure(error, refused) {
+                    Failure::Fatal(error) => options
+                        .failed(message.item.clone(), error)
+                        .map(|error| self.summary.errors.push(error)),
+                    Failure::Transient(reason) => {
+                        self.pending = std::iter::once(message).chain(pending).collect();
+                        return Err(Failure::Transient(reason));
+                    }
+                },
+            };
+            if let Err(error) = result {
+                self.pending = pending.collect();
+                return Err(error.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Connect and log in unless already connected, and create the mailbox the first time
+    /// if it does not exist.
+    fn connect(&mut self) -> Result<(), Failure> {
+        if self.session.is_some() {
+            return Ok(());
+        }
+        let options = self.options;
+        let mut session = log_in(
+            &options.mailbox,
+            &options.user,
+            &options.password,
+            options.timeout,
+        )?;
+        if !self.mailbox_ready {
+            let pattern = format!("\"{}\"", escape(&self.mailbox));
+            if session.list(Some(""), Some(&pattern))?.is_empty() {
+                session.create(&self.mailbox).map_err(|error| {
+                    failure(error, |response| MailfmtError::ImapMailbox {
+                        mailbox: options.mailbox.mailbox.clone(),
+                        response,
+                    })
+                })?;
+                self.summary.mailbox_created = true;
+            }
+            self.mailbox_ready = true;
+        }
+        self.session = Some(session);
+        Ok(())
+    }
+
+    /// Upload what is left in the queue, unless the upload was cancelled, and log out.
+    fn finish(mut self) -> Result<ConversionSummary> {
+        if !self.summary.interrupted {
+            self.flush()?;
+        }
+        if let Some(mut session) = self.session.take() {
+            // The server closes the connection either way.
+            let _ = session.logout();
+        }
+        Ok(self.summary)
+    }
+}
+
+/// Where fetched messages are written.
+pub enum FetchOutput {
+    /// A single mbox file, which later fetches append to.
+    Mbox(PathBuf),
+    /// A directory that every message is saved to as its own eml file.
+    EmlDirectory(PathBuf),
+}
+
+/// Options for [`fetch_from_imap`]. Create them with [`ImapFetchOptions::new`] and adjust the
+/// fields as needed.
+pub struct ImapFetchOptions<'a> {
+    pub mailbox: ImapMailbox,
+    /// The user to log in as, the one named by `mailbox` by default.
+    pub user: String,
+    pub password: String,
+    /// Log in over a plain connection to a server that is not on this machine, sending the
+    /// password unencrypted. Without it, such a fetch fails with
+    /// [`MailfmtError::ImapInsecure`] before connecting.
+    pub insecure: bool,
+    pub output: FetchOutput,
+    /// Start over, replacing the output and forgetting what earlier fetches fetched, rather
+    /// than continuing after them.
+    pub overwrite: bool,
+    /// Only fetch messages the server received on or after this day.
+    pub since: Option<NaiveDate>,
+    /// Only fetch messages the server received before this day.
+    pub before: Option<NaiveDate>,
+    /// How many messages are fetched with every command, at least 1.
+    pub batch_size: usize,
+    /// How many times to connect again when the connection breaks, or the server reports it
+    /// is unavailable, before failing with [`MailfmtError::ImapConnection`]. The batch being
+    /// fetched is fetched again in full.
+    pub retries: u32,
+    /// How long to wait before the first retry, doubling with every retry after it.
+    pub retry_delay: Duration,
+    /// How long to wait for the server to answer once connected before taking the connection
+    /// as broken.
+    pub timeout: Duration,
+    /// The mbox dialect to write when the output is an mbox file.
+    pub dialect: MboxDialect,
+    /// How separator lines are written when the output is an mbox file.
+    pub from_line_style: FromLineStyle,
+    /// Set to stop the fetch after the current batch. What was fetched is kept, and the next
+    /// fetch continues after it.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called with progress and per-message results as the fetch goes on.
+    pub on_event: Option<EventHandler<'a>>,
+}
+
+impl ImapFetchOptions<'_> {
+    pub fn new(mailbox: ImapMailbox, output: FetchOutput) -> Self {
+        Self {
+            user: mailbox.user.clone().unwrap_or_default(),
+            mailbox,
+            password: String::new(),
+            insecure: false,
+            output,
+            overwrite: false,
+            since: None,
+            before: None,
+            batch_size: 50,
+            retries: 3,
+            retry_delay: Duration::from_secs(1),
+            timeout: Duration::from_secs(30),
+            dialect: MboxDialect::default(),
+            from_line_style: FromLineStyle::default(),
+            cancel: None,
+            on_event: None,
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
+
+    /// Report a message that could not be written, returning it for the summary.
+    fn failed(&self, item: String, error: MailfmtError) -> Result<MessageError> {
+        self.emit(Event::Failed {
+            item: item.clone(),
+            error: &error,
+        })?;
+        Ok(MessageError { item, error })
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// The IMAP flags kept as Status and X-Status headers when fetching, with the names
+/// [`status::status_headers`] takes for them.
+const FETCHED_FLAGS: [(&str, &[&str]); 5] = [
+    ("\\Seen", &["seen", "old"]),
+    ("\\Answered", &["answered"]),
+    ("\\Flagged", &["flagged"]),
+    ("\\Draft", &["draft"]),
+    ("\\Deleted", &["deleted"]),
+];
+
+/// Fetch the messages of a mailbox on an IMAP server into an mbox file or a directory of eml
+/// files, oldest UID first, without marking them as read. Their flags are written as Status
+/// and X-Status headers, replacing any the messages had, and their line endings are changed
+/// to LF.
+///
+/// What was fetched is recorded next to the output after every batch, in a file named by
+/// [`FetchOutput`]'s path followed by `.mailfmt-imap.json`, or `.mailfmt-imap.json` inside
+/// of an output directory. A later fetch into the same output only fetches the messages
+/// added to the mailbox since, which also continues an interrupted fetch. It fails with
+/// [`MailfmtError::InvalidState`] if the mailbox was replaced in the meantime, as its UIDs
+/// then no longer match, unless `overwrite` starts over.
+pub fn fetch_from_imap(options: &ImapFetchOptions<'_>) -> Result<ConversionSummary> {
+    let _span = info_span!("fetch_from_imap", server = %options.mailbox.server(), mailbox = %options.mailbox.mailbox).entered();
+    let start = Instant::now();
+    check_security(&options.mailbox, options.insecure)?;
+    let (output_path, directory) = match &options.output {
+        FetchOutput::Mbox(path) => (path.as_path(), false),
+        FetchOutput::EmlDirectory(path) => (path.as_path(), true),
+    };
+    if directory {
+        check_output_directory(output_path)?;
+    }
+    let state_path = FetchState::path_for(output_path, directory);
+    let mailbox = format!("{}/{}", options.mailbox.server(), options.mailbox.mailbox);
+    let previous = match options.overwrite {
+        true => None,
+        false => FetchState::load(&state_path)?,
+    };
+    match &previous {
+        Some(state) if state.mailbox != mailbox => {
+            return Err(MailfmtError::InvalidState {
+                path: state_path,
+                reason: format!("it records a fetch from {}, not {mailbox}", state.mailbox),
+            });
+        }
+        Some(_) => {}
+        None if output_path.exists() && !options.overwrite => {
+            let path = output_path.to_path_buf();
+            return Err(match directory {
+                true => MailfmtError::OutputDirectoryExists { path },
+                false => MailfmtError::OutputExists { path },
+            });
+        }
+        None => {}
+    }
+
+    let mut output = match &options.output {
+        FetchOutput::Mbox(output_file) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(previous.is_some())
+                .write(true)
+                .truncate(previous.is_none())
+                .open(output_file)
+                .io_context("open mbox file", output_file)?;
+            Output::Mbox(
+                MboxWriter::new(BufWriter::new(file), options.dialect)
+                    .with_from_line_style(options.from_line_style),
+            )
+        }
+        FetchOutput::EmlDirectory(output_dir) => {
+            create_output_directory(output_dir, "create output directory")?;
+            Output::EmlDirectory
+        }
+    };
+
+    let mut fetcher = Fetcher {
+        options,
+        session: None,
+        mailbox: encode_mailbox_name(&options.mailbox.mailbox),
+        uid_validity: previous.as_ref().map(|state| state.uid_validity),
+        state_path: &state_path,
+        summary: ConversionSummary::default(),
+    };
+    fetcher.summary.continued_after_uid = previous
+        .as_ref()
+        .map(|state| state.last_uid)
+        .filter(|&uid| uid > 0);
+    let result = fetcher.fetch_all(&mut output, output_path, previous);
+    if let Some(mut session) = fetcher.session.take() {
+        // The server closes the connection either way.
+        let _ = session.logout();
+    }
+    result?;
+    let mut summary = fetcher.summary;
+    summary.elapsed = start.elapsed();
+    Ok(summary)
+}
+
+/// The messages being written, to an mbox file or as eml files.
+enum Output {
+    Mbox(MboxWriter<BufWriter<File>>),
+    EmlDirectory,
+}
+
+/// A message as fetched from the server.
+struct Fetched {
+    uid: u64,
+    flags: Vec<String>,
+    internal_date: Option<DateTime<FixedOffset>>,
+    content: Vec<u8>,
+}
+
+/// A fetch in progress: the session with the server and where it stands.
+struct Fetcher<'o, 'a> {
+    options: &'o ImapFetchOptions<'a>,
+    session: Option<Session>,
+    /// The mailbox name as sent to the server.
+    mailbox: String,
+    /// The UIDVALIDITY the mailbox has to keep, once known.
+    uid_validity: Option<u64>,
+    state_path: &'o Path,
+    summary: ConversionSummary,
+}
+
+impl Fetcher<'_, '_> {
+    /// Search for the messages to fetch and fetch them batch by batch, recording the state
+    /// after every batch.
+    fn fetch_all(
+        &mut self,
+        output: &mut Output,
+        output_path: &Path,
+        previous: Option<FetchState>,
+    ) -> Result<()> {
+        let options = self.options;
+        let mut state = previous.unwrap_or(FetchState {
+            mailbox: format!("{}/{}", options.mailbox.server(), options.mailbox.mailbox),
+            uid_validity: 0,
+            last_uid: 0,
+            next_index: 0,
+        });
+        let Some(uids) = self.retrying(|fetcher| fetcher.search(state.last_uid))? else {
+            self.summary.interrupted = true;
+            return Ok(());
+        };
+        state.uid_validity = self.uid_validity.unwrap_or_default();
+        options.emit(Event::Started {
+            total: ProgressTotal::Messages(uids.len() as u64),
+            position: 0,
+        })?;
+
+        for (batch, uids) in uids.chunks(options.batch_size.max(1)).enumerate() {
+            if options.is_cancelled() {
+                self.summary.interrupted = true;
+                break;
+            }
+            let Some(messages) = self.retrying(|fetcher| fetcher.fetch(uids))? else {
+                self.summary.interrupted = true;
+                break;
+            };
+            for uid in uids {
+                let _span = info_span!("message", uid).entered();
+                let Some(message) = messages.iter().find(|message| message.uid == *uid) else {
+                    options.emit(Event::Warning(format!(
+                        "the message with UID {uid} was deleted from the mailbox before it could be fetched"
+                    )))?;
+                    continue;
+                };
+                self.summary.bytes_read += message.content.len() as u64;
+                let item = format!("fetching the message with UID {uid}");
+                match write_fetched(output, output_path, message, state.next_index) {
+                    Ok((written, path)) => {
+                        self.summary.converted += 1;
+                        self.summary.bytes_written += written;
+                        state.next_index += 1;
+                        if let Some(path) = path {
+                            options.emit(Event::Written(&path))?;
+                        }
+                    }
+                    Err(error) => self.summary.errors.push(options.failed(item, error)?),
+                }
+            }
+            if let Output::Mbox(writer) = output {
+                writer.flush().io_context("write mbox file", output_path)?;
+            }
+            state.last_uid = state.last_uid.max(uids.last().copied().unwrap_or_default());
+            state.save(self.state_path)?;
+            options.emit(Event::Progress {
+                position: ((batch * options.batch_size.max(1)) + uids.len()) as u64,
+                processed: self.summary.converted + self.summary.errors.len(),
+            })?;
+        }
+        // The state also records a fetch of an empty mailbox, so that the next one continues.
+        if state.last_uid == 0 && !self.summary.interrupted {
+            state.save(self.state_path)?;
+        }
+        if let Output::Mbox(writer) = output {
+            writer.flush().io_context("write mbox file", output_path)?;
+            options.emit(Event::Written(output_path))?;
+        }
+        Ok(())
+    }
+
+    /// Run `attempt` until it succeeds, connecting again after transient failures. Returns
+    /// `None` if cancelled while waiting to try again.
+    fn retrying<T>(
+        &mut self,
+        mut attempt: impl FnMut(&mut Self) -> Result<T, Failure>,
+    ) -> Result<Option<T>> {
+        let options = self.options;
+        let mut backoff = Backoff::new(
+            &options.mailbox,
+            options.retries,
+            options.retry_delay,
+            options.cancel.as_deref(),
+        );
+        loop {
+            let reason = match attempt(self) {
+                Ok(value) => return Ok(Some(value)),
+                Err(Failure::Fatal(error)) => return Err(error),
+                Err(Failure::Transient(reason)) => reason,
+            };
+            self.session = None;
+            if !backoff.pause(reason, |event| options.emit(event))? {
+                return Ok(None);
+            }
+            self.summary.reconnects += 1;
+        }
+    }
+
+    /// Connect, log in and open the mailbox read-only unless already connected.
+    fn connect(&mut self) -> Result<&mut Session, Failure> {
+        if self.session.is_none() {
+            let options = self.options;
+            let mut session = log_in(
+                &options.mailbox,
+                &options.user,
+                &options.password,
+                options.timeout,
+            )?;
+            let opened = session.examine(&self.mailbox).map_err(|error| {
+                failure(error, |response| MailfmtError::ImapMailbox {
+                    mailbox: options.mailbox.mailbox.clone(),
+                    response,
+                })
+            })?;
+            let uid_validity = opened.uid_validity.map(u64::from).unwrap_or_default();
+            match self.uid_validity {
+                Some(expected) if expected != uid_validity => {
+                    return Err(Failure::Fatal(MailfmtError::InvalidState {
+                        path: self.state_path.to_path_buf(),
+                        reason: "the mailbox was replaced on the server since, so its messages are no longer those recorded".to_string(),
+                    }));
+                }
+                _ => self.uid_validity = Some(uid_validity),
+            }
+            self.session = Some(session);
+        }
+        Ok(self.session.as_mut().expect("connected above"))
+    }
+
+    /// The UIDs of the messages to fetch, in ascending order.
+    fn search(&mut self, last_uid: u64) -> Result<Vec<u64>, Failure> {
+        let options = self.options;
+        let mut query = format!("UID {}:*", last_uid + 1);
+        for (key, date) in [("SINCE", options.since), ("BEFORE", options.before)] {
+            if let Some(date) = date {
+                query.push_str(&format!(" {key} {}", date.format("%d-%b-%Y")));
+            }
+        }
+        let mut uids: Vec<u64> = self
+            .connect()?
+            .uid_search(query)?
+            .into_iter()
+            .map(u64::from)
+            // "n:*" also matches the highest UID when it is below n.
+            .filter(|&uid| uid > last_uid)
+            .collect();
+        uids.sort_unstable();
+        Ok(uids)
+    }
+
+    /// Fetch the messages with the given UIDs, without marking them as read.
+    fn fetch(&mut self, uids: &[u64]) -> Result<Vec<Fetched>, Failure> {
+        let set = uids
+            .iter()
+            .map(u64::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        let fetches = self
+            .connect()?
+            .uid_fetch(set, "(UID FLAGS INTERNALDATE BODY.PEEK[])")?;
+        Ok(fetches
+            .iter()
+            .filter_map(|fetch| {
+                Some(Fetched {
+                    uid: fetch.uid?.into(),
+                    flags: fetch.flags().iter().map(Flag::to_string).collect(),
+                    internal_date: fetch.internal_date(),
+                    content: fetch.body()?.to_vec(),
+                })
+            })
+            .collect())
+    }
+}
+
+/// Write a fetched message with its flags as Status and X-Status headers, returning how
+/// many bytes were written and the path of its eml file.
+fn write_fetched(
+    output: &mut Output,
+    output_path: &Path,
+    message: &Fetched,
+    index: usize,
+) -> Result<(u64, Option<PathBuf>)> {
+    let mut lines: Vec<Vec<u8>> = message
+        .content
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+        .collect();
+    if lines.last().is_some_and(Vec::is_empty) {
+        lines.pop();
+    }
+    let names: Vec<&str> = FETCHED_FLAGS
+        .iter()
+        .filter(|(flag, _)| {
+            message
+                .flags
+                .iter()
+                .any(|fetched| fetched.eq_ignore_ascii_case(flag))
+        })
+        .flat_map(|(_, names)| names.iter().copied())
+        .collect();
+    let lines = status::with_status_headers(lines, &status::status_headers(&names));
+
+    match output {
+        Output::Mbox(writer) => {
+            let mut content = lines.join(&b'\n');
+            content.push(b'\n');
+            let text = String::from_utf8_lossy(&content);
+            let (sender, date) = envelope_parts(&text);
+            let date = date.or_else(|| {
+                let internal_date = message.internal_date?;
+                Some(internal_date.format("%a %b %d %H:%M:%S %Y").to_string())
+            });
+            let envelope = format!(
+                "{} {}",
+                sender.unwrap_or(PLACEHOLDER_SENDER),
+                date.as_deref().unwrap_or(PLACEHOLDER_DATE)
+            );
+            let before = writer.bytes_written();
+            writer
+                .append_raw(&content, &envelope)
+                .io_context("write message to mbox file", output_path)?;
+            Ok((writer.bytes_written() - before, None))
+        }
+        Output::EmlDirectory => {
+            let entity = Entity::parse(&lines);
+            let subject = entity
+                .fields
+                .iter()
+                .find(|field| field.is("subject"))
+                .map(|field| decode_encoded_words(field.value.trim()))
+                .filter(|subject| !subject.is_empty())
+                .map(|subject| sanitize_filename::sanitize(&subject));
+            let path = output_path.join(eml_file_name(index, subject, "eml"));
+            let written = save_eml_file(&path, &lines, true, false)?;
+            Ok((written, Some(path)))
+        }
+    }
+}
+
+/// Fail unless logging in to the server of `mailbox` keeps the password from being sent
+/// unencrypted to another machine, or `insecure` allows it.
+fn check_security(mailbox: &ImapMailbox, insecure: bool) -> Result<()> {
+    match mailbox.security == ImapSecurity::Plain && !mailbox.is_loopback() && !insecure {
+        true => Err(MailfmtError::ImapInsecure {
+            server: mailbox.server(),
+        }),
+        false => Ok(()),
+    }
+}
+
+/// Connect to the server of `mailbox` as it asks for, and log in as `user`.
+fn log_in(
+    mailbox: &ImapMailbox,
+    user: &str,
+    password: &str,
+    timeout: Duration,
+) -> Result<Session, Failure> {
+    let refused = |response: String| {
+        Failure::Fatal(MailfmtError::ImapLogin {
+            server: mailbox.server(),
+            user: user.to_string(),
+            response,
+        })
+    };
+    let mode = match mailbox.security {
+        ImapSecurity::Tls => ConnectionMode::Tls,
+        ImapSecurity::StartTls => ConnectionMode::StartTls,
+        ImapSecurity::Plain => ConnectionMode::Plaintext,
+    };
+    let client = match ClientBuilder::new(mailbox.host.as_str(), mailbox.port)
+        .mode(mode)
+        .connect()
+    {
+        Ok(client) => client,
+        Err(imap::Error::StartTlsNotAvailable) => {
+            return Err(refused("the server does not offer STARTTLS".to_string()));
+        }
+        Err(error) => return Err(error.into()),
+    };
+    // The builder waits for the connection and the greeting as long as the system lets it, so
+    // the timeout applies to every answer after them.
+    let mut stream = client.into_inner()?;
+    stream.set_read_timeout(Some(timeout))?;
+    let mut client = imap::Client::new(stream);
+    if mailbox.security == ImapSecurity::Plain && client.capabilities()?.has_str("LOGINDISABLED") {
+        return Err(refused(
+            "the server only accepts logins over TLS".to_string(),
+        ));
+    }
+    client.login(user, password).map_err(|(error, _)| {
+        failure(error, |response| MailfmtError::ImapLogin {
+            server: mailbox.server(),
+            user: user.to_string(),
+            response,
+        })
+    })
+}
+
+/// The waits between attempts to talk to a server after transient failures, doubling every
+/// time.
+struct Backoff<'a> {
+    server: String,
+    retries: u32,
+    delay: Duration,
+    cancel: Option<&'a AtomicBool>,
+    attempts: u32,
+}
+
+impl<'a> Backoff<'a> {
+    fn new(
+        mailbox: &ImapMailbox,
+        retries: u32,
+        delay: Duration,
+        cancel: Option<&'a AtomicBool>,
+    ) -> Self {
+        Self {
+            server: mailbox.server(),
+            retries,
+            delay,
+            cancel,
+            attempts: 0,
+        }
+    }
+
+    /// Wait before the next attempt after a transient failure, warning about it through
+    /// `emit`. Returns false if cancelled while waiting, and fails once every retry is used.
+    fn pause(&mut self, reason: String, emit: impl Fn(Event<'_>) -> Result<()>) -> Result<bool> {
+        self.attempts += 1;
+        if self.attempts > self.retries {
+            return Err(MailfmtError::ImapConnection {
+                server: self.server.clone(),
+                attempts: self.attempts,
+                reason,
+            });
+        }
+        let delay = self.delay.saturating_mul(1 << (self.attempts - 1).min(16));
+        emit(Event::Warning(format!(
+            "Could not talk to the IMAP server at {}: {reason}. Trying again in {:.1}s",
+            self.server,
+            delay.as_secs_f64()
+        )))?;
+        let cancelled = || {
+            self.cancel
+                .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+        };
+        let end = Instant::now() + delay;
+        while let Some(left) = end.checked_duration_since(Instant::now()) {
+            if cancelled() {
+                return Ok(false);
+            }
+            thread::sleep(left.min(Duration::from_millis(100)));
+        }
+        Ok(!cancelled())
+    }
+}
+
+/// Encode a mailbox name in the modified UTF-7 that IMAP uses for names that are not plain
+/// ASCII, as described by RFC 3501.
+fn encode_mailbox_name(name: &str) -> String {
+    fn flush(encoded: &mut String, units: &mut Vec<u16>) {
+        if units.is_empty() {
+            return;
+        }
+        let bytes: Vec<u8> = units.iter().flat_map(|unit| unit.to_be_bytes()).collect();
+        encoded.push('&');
+        encoded.push_str(
+            &encode_base64(&bytes)
+                .trim_end_matches('=')
+                .replace('/', ","),
+        );
+        encoded.push('-');
+        units.clear();
+    }
+
+    let (mut encoded, mut units) = (String::with_capacity(name.len()), Vec::new());
+    for c in name.chars() {
+        match c {
+            ' '..='~' => {
+                flush(&mut encoded, &mut units);
+                encoded.push(c);
+                if c == '&' {
+                    encoded.push('-');
+                }
+            }
+            _ => units.extend_from_slice(c.encode_utf16(&mut [0; 2])),
+        }
+    }
+    flush(&mut encoded, &mut units);
+    encoded
+}
+
+/// Escape the backslashes and quotes of a string for the commands that the `imap` crate puts
+/// it in quotes for as it is given, such as APPEND.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
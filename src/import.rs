@@ -0,0 +1,543 @@
+//! Import of messages from the JSON Lines written by [`export_json`](crate::export_json),
+//! rebuilding them as RFC 822 messages.
+
+use crate::{
+    ConversionSummary, Event, EventHandler, MailfmtError, MessageError, ProgressTotal,
+    atomic::AtomicFile,
+    counting::CountingReader,
+    error::{IoContext, Result},
+    headers::{encode_header, is_header_line},
+    mbox::{check_output_directory, create_output_directory, eml_file_name, save_eml_file},
+    mime::{decode_base64, encode_base64, encode_quoted_printable},
+    paths,
+    writer::{FromLineStyle, MboxDialect, MboxWriter, envelope_for},
+};
+use serde_json::Value;
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
+use tracing::info_span;
+
+/// Where imported messages are written.
+pub enum ImportOutput {
+    /// A single mbox file.
+    Mbox(PathBuf),
+    /// A directory that every message is saved to as its own eml file.
+    EmlDirectory(PathBuf),
+}
+
+/// Options for [`import_json`]. Create them with [`ImportJsonOptions::new`] and adjust the
+/// fields as needed.
+pub struct ImportJsonOptions<'a> {
+    pub input_file: PathBuf,
+    pub output: ImportOutput,
+    /// Replace the output mbox file if it already exists, or eml files in the output
+    /// directory if they overlap.
+    pub overwrite: bool,
+    /// The mbox dialect to write when the output is an mbox file.
+    pub dialect: MboxDialect,
+    /// How separator lines are written when the output is an mbox file.
+    pub from_line_style: FromLineStyle,
+    /// Set to stop the import after the current record. An mbox file is then not written,
+    /// while eml files already saved are kept.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called with progress and per-record results as the import goes on.
+    pub on_event: Option<EventHandler<'a>>,
+}
+
+impl ImportJsonOptions<'_> {
+    pub fn new(input_file: impl Into<PathBuf>, output: ImportOutput) -> Self {
+        Self {
+            input_file: input_file.into(),
+            output,
+            overwrite: false,
+            dialect: MboxDialect::default(),
+            from_line_style: FromLineStyle::default(),
+            cancel: None,
+            on_event: None,
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
+
+    /// Report a record that could not be imported, returning it for the summary.
+    fn failed(&self, item: String, error: MailfmtError) -> Result<MessageError> {
+        self.emit(Event::Failed {
+            item: item.clone(),
+            error: &error,
+        })?;
+        Ok(MessageError { item, error })
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// The messages being written, to an mbox file or as eml files.
+enum Output {
+    Mbox(MboxWriter<BufWriter<AtomicFile>>, PathBuf),
+    EmlDirectory(PathBuf),
+}
+
+/// Rebuild the messages in a JSON Lines file written by [`export_json`](crate::export_json)
+/// and write them to an mbox file or a directory of eml files.
+///
+/// Every record needs its `headers` and `text`. The headers are written with the common ones
+/// first, encoded and folded as needed, and the text as UTF-8, quoted-printable unless it is
+/// plain ASCII. The MIME structure is rebuilt, so the Content-Type, Content-Transfer-Encoding,
+/// Content-Disposition, Content-Length and MIME-Version headers of the record are replaced.
+/// Attachments are only kept if their content was exported. Records that cannot be imported
+/// are reported through [`Event::Failed`] and left out.
+///
+/// ```
+/// use mailfmt::{ExportJsonOptions, ImportJsonOptions, ImportOutput, export_json, import_json};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-import-json-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// fs::write(
+///     dir.join("in.jsonl"),
+///     r#"{"headers": {"from": "René <r@example.com>", "subject": "Café"}, "text": "Bonjour à tous\n"}
+/// {"headers": {"subject": "No text"}}
+/// "#,
+/// )?;
+///
+/// let options = ImportJsonOptions::new(dir.join("in.jsonl"), ImportOutput::Mbox(dir.join("out.mbox")));
+/// let summary = import_json(&options)?;
+/// assert_eq!((summary.converted, summary.errors.len()), (1, 1));
+/// assert_eq!(summary.errors[0].error.code(), "invalid-record");
+///
+/// export_json(&ExportJsonOptions::new(dir.join("out.mbox"), dir.join("out.jsonl")))?;
+/// let exported = fs::read_to_string(dir.join("out.jsonl"))?;
+/// assert!(exported.contains(r#""from":"René <r@example.com>","subject":"Café""#));
+/// assert!(exported.contains(r#""text":"Bonjour à tous\n""#));
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn import_json(options: &ImportJsonOptions<'_>) -> Result<ConversionSummary> {
+    let _span = info_span!("import_json", input = %options.input_file.display()).entered();
+    let start = Instant::now();
+    let input_file = options.input_file.as_path();
+    let input_size = fs::metadata(input_file)
+        .io_context("read metadata of input file", input_file)?
+        .len();
+    let reader =
+        CountingReader::new(File::open(input_file).io_context("open input file", input_file)?);
+    let bytes_read = reader.counter();
+    let mut reader = BufReader::new(reader);
+    let mut output = match &options.output {
+        ImportOutput::Mbox(output_file) => {
+            if output_file.exists() && !options.overwrite {
+                return Err(MailfmtError::OutputExists {
+                    path: output_file.clone(),
+                });
+            }
+            if paths::same_file(input_file, output_file) {
+                return Err(MailfmtError::OutputIsInput {
+                    output: output_file.clone(),
+                    input: input_file.to_path_buf(),
+                });
+            }
+            let file =
+                AtomicFile::create(output_file).io_context("create mbox file", output_file)?;
+            Output::Mbox(
+                MboxWriter::new(BufWriter::new(file), options.dialect)
+                    .with_from_line_style(options.from_line_style),
+                output_file.clone(),
+            )
+        }
+        ImportOutput::EmlDirectory(output_dir) => {
+            check_output_directory(output_dir)?;
+            if output_dir.exists() && !options.overwrite {
+                return Err(MailfmtError::OutputDirectoryExists {
+                    path: output_dir.clone(),
+                });
+            }
+            create_output_directory(output_dir, "create output directory")?;
+            Output::EmlDirectory(output_dir.clone())
+        }
+    };
+
+    options.emit(Event::Started {
+        total: match input_size {
+            0 => ProgressTotal::Unknown,
+            size => ProgressTotal::Bytes(size),
+        },
+        position: 0,
+    })?;
+
+    let mut summary = ConversionSummary::default();
+    let mut line = Vec::new();
+    for number in 1.. {
+        if options.is_cancelled() {
+            summary.interrupted = true;
+            break;
+        }
+        line.clear();
+        if reader
+            .read_until(b'\n', &mut line)
+            .io_context("read input file", input_file)?
+            == 0
+        {
+            break;
+        }
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+
+        let item = format!("importing the record on line {number}");
+        match build_message(&line) {
+            Ok(message) => {
+                if message.dropped_attachments > 0 {
+                    options.emit(Event::Warning(format!(
+                        "the record on line {number} describes {} attachments without their content, so they were left out",
+                        message.dropped_attachments
+                    )))?;
+                }
+                match write_message(&mut output, &message, summary.converted) {
+                    Ok((written, path)) => {
+                        summary.converted += 1;
+                        summary.bytes_written += written;
+                        if let Some(path) = path {
+                            options.emit(Event::Written(&path))?;
+                        }
+                    }
+                    Err(error) => summary.errors.push(options.failed(item, error)?),
+                }
+            }
+            Err(reason) => {
+                let error = MailfmtError::InvalidRecord {
+                    line: number,
+                    reason,
+                };
+                summary.errors.push(options.failed(item, error)?);
+            }
+        }
+        options.emit(Event::Progress {
+            position: bytes_read.load(Ordering::Relaxed),
+            processed: summary.converted + summary.errors.len(),
+        })?;
+    }
+
+    // An interrupted mbox is never moved into place; dropping it removes its temporary file.
+    if let Output::Mbox(writer, output_file) = output {
+        let file = writer
+            .finish()
+            .and_then(|output| output.into_inner().map_err(|e| e.into_error()))
+            .io_context("flush mbox file", &output_file)?;
+        if summary.interrupted {
+            summary.bytes_written = 0;
+        } else {
+            file.commit()
+                .io_context("move mbox file into place", &output_file)?;
+            options.emit(Event::Written(&output_file))?;
+        }
+    }
+    summary.elapsed = start.elapsed();
+    Ok(summary)
+}
+
+/// A message rebuilt from a record.
+struct Rebuilt {
+    lines: Vec<Vec<u8>>,
+    /// The envelope of the record, if it has one.
+    envelope: Option<String>,
+    subject: Option<String>,
+    /// How many attachments were described without their content.
+    dropped_attachments: usize,
+}
+
+/// Write a message, returning how many bytes were written and the path of its eml file.
+fn write_message(
+    output: &mut Output,
+    message: &Rebuilt,
+    index: usize,
+) -> Result<(u64, Option<PathBuf>)> {
+    match output {
+        Output::Mbox(writer, output_file) => {
+            let mut content = message.lines.join(&b'\n');
+            content.push(b'\n');
+            let envelope = match &message.envelope {
+                Some(envelope) => envelope.clone(),
+                None => envelope_for(&String::from_utf8_lossy(&content)),
+            };
+            let before = writer.bytes_written();
+            writer
+                .append_raw(&content, &envelope)
+                .io_context("write message to mbox file", output_file)?;
+            Ok((writer.bytes_written() - before, None))
+        }
+        Output::EmlDirectory(output_dir) => {
+            let subject = message
+                .subject
+                .as_deref()
+                .filter(|subject| !subject.is_empty())
+                .map(sanitize_filename::sanitize);
+            let path = output_dir.join(eml_file_name(index, subject, "eml"));
+            let written = save_eml_file(&path, &message.lines, true, false)?;
+            Ok((written, Some(path)))
+        }
+    }
+}
+
+/// Headers written first, in this order, when a record has them. The others follow in the
+/// order of the record.
+const HEADER_ORDER: [&str; 13] = [
+    "return-path",
+    "received",
+    "date",
+    "from",
+    "sender",
+    "reply-to",
+    "to",
+    "cc",
+    "bcc",
+    "subject",
+    "message-id",
+    "in-reply-to",
+    "references",
+];
+
+/// Headers describing the MIME structure, which is rebuilt rather than taken from the record.
+const STRUCTURE_HEADERS: [&str; 5] = [
+    "content-type",
+    "content-transfer-encoding",
+    "content-disposition",
+    "content-length",
+    "mime-version",
+];
+
+/// Rebuild the message described by a record, or say why it cannot be.
+fn build_message(record: &[u8]) -> std::result::Result<Rebuilt, String> {
+    let record = std::str::from_utf8(record).map_err(|_| "it is not valid UTF-8".to_string())?;
+    let record: Value =
+        serde_json::from_str(record.trim()).map_err(|e| format!("it is not valid JSON: {e}"))?;
+    let Some(Value::Object(headers)) = record.get("headers") else {
+        return Err("it has no \"headers\" object".to_string());
+    };
+    let text = match record.get("text") {
+        Some(Value::Null) => None,
+        Some(Value::String(text)) => Some(text.as_str()),
+        _ => return Err("it has no \"text\" string or null".to_string()),
+    };
+
+    let mut fields = Vec::new();
+    for (name, value) in headers {
+        if !is_header_line(&format!("{name}:")) {
+            return Err(format!("'{name}' is not a valid header name"));
+        }
+        let values = match value {
+            Value::String(value) => vec![value.as_str()],
+            Value::Array(values) => values
+                .iter()
+                .map(|value| value.as_str())
+                .collect::<Option<_>>()
+                .ok_or_else(|| format!("the values of header '{name}' are not all strings"))?,
+            _ => return Err(format!("header '{name}' is neither a string nor an array")),
+        };
+        let name = name.to_ascii_lowercase();
+        if !STRUCTURE_HEADERS.contains(&name.as_str()) {
+            fields.extend(values.into_iter().map(|value| (name.clone(), value)));
+        }
+    }
+    // A stable sort keeps the record's order within each group.
+    fields.sort_by_key(|(name, _)| {
+        HEADER_ORDER
+            .iter()
+            .position(|ordered| ordered == name)
+            .unwrap_or(HEADER_ORDER.len())
+    });
+
+    let mut attachments = Vec::new();
+    let mut dropped_attachments = 0;
+    for (i, attachment) in record
+        .get("attachments")
+        .and_then(Value::as_array)
+        .map_or(&[][..], Vec::as_slice)
+        .iter()
+        .enumerate()
+    {
+        let Some(content) = attachment.get("content").and_then(Value::as_str) else {
+            dropped_attachments += 1;
+            continue;
+        };
+        let content = decode_base64(content.as_bytes())
+            .ok_or_else(|| format!("the content of attachment {i} is not valid base64"))?;
+        let content_type = attachment
+            .get("content_type")
+            .and_then(Value::as_str)
+            .filter(|content_type| is_media_type(content_type))
+            .ok_or_else(|| format!("attachment {i} has no valid \"content_type\""))?;
+        let filename = attachment.get("filename").and_then(Value::as_str);
+        attachments.push(attachment_part(content_type, filename, &content));
+    }
+
+    let mut lines: Vec<Vec<u8>> = fields
+        .iter()
+        .flat_map(|(name, value)| {
+            encode_header(&canonical_name(name), value)
+                .split('\n')
+                .map(|line| line.as_bytes().to_vec())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    lines.push(b"MIME-Version: 1.0".to_vec());
+    match (text, attachments.is_empty()) {
+        (Some(text), true) => lines.extend(text_part(text)),
+        // Without text, an empty multipart body keeps the message from having any.
+        (text, _) => {
+            let mut parts = attachments;
+            if let Some(text) = text {
+                parts.insert(0, text_part(text));
+            }
+            let boundary = (0..)
+                .map(|n| format!("mailfmt-boundary-{n}"))
+                .find(|boundary| {
+                    let delimiter = format!("--{boundary}");
+                    !parts
+                        .iter()
+                        .flatten()
+                        .any(|line| line.starts_with(delimiter.as_bytes()))
+                })
+                .expect("some boundary is unused");
+            lines.push(
+                format!("Content-Type: multipart/mixed; boundary=\"{boundary}\"").into_bytes(),
+            );
+            lines.push(Vec::new());
+            for part in parts {
+                lines.push(format!("--{boundary}").into_bytes());
+                lines.extend(part);
+            }
+            lines.push(format!("--{boundary}--").into_bytes());
+        }
+    }
+
+    let envelope = record.get("envelope").and_then(|envelope| {
+        let sender = envelope.get("sender")?.as_str()?;
+        let date = envelope.get("date")?.as_str()?;
+        (!sender.is_empty() && !sender.contains(char::is_whitespace) && !date.is_empty())
+            .then(|| format!("{sender} {date}"))
+    });
+    let subject = fields
+        .iter()
+        .find(|(name, _)| name == "subject")
+        .map(|(_, subject)| subject.to_string());
+    Ok(Rebuilt {
+        lines,
+        envelope,
+        subject,
+        dropped_attachments,
+    })
+}
+
+/// The header fields and body of a plain text part, or of a single-part message.
+fn text_part(text: &str) -> Vec<Vec<u8>> {
+    // Plain ASCII is written as it is if decoding gives back exactly the text, which needs
+    // the text to end with a line break as a blank line may follow it.
+    let plain = text.is_ascii()
+        && !text.contains('\r')
+        && (text.is_empty() || text.ends_with('\n'))
+        && text.split('\n').all(|line| line.len() <= 998);
+    let mut lines = vec![
+        b"Content-Type: text/plain; charset=utf-8".to_vec(),
+        match plain {
+            true => b"Content-Transfer-Encoding: 7bit".to_vec(),
+            false => b"Content-Transfer-Encoding: quoted-printable".to_vec(),
+        },
+        Vec::new(),
+    ];
+    match plain {
+        true if text.is_empty() => {}
+        true => lines.extend(text.split('\n').map(|line| line.as_bytes().to_vec())),
+        false => lines.extend(encode_quoted_printable(text)),
+    }
+    lines
+}
+
+/// The header fields and base64 body of an attachment.
+fn attachment_part(content_type: &str, filename: Option<&str>, content: &[u8]) -> Vec<Vec<u8>> {
+    let (mut content_type, mut disposition) = (content_type.to_string(), "attachment".to_string());
+    if let Some(filename) = filename {
+        content_type.push_str(&format!("; {}", encode_param("name", filename)));
+        disposition.push_str(&format!("; {}", encode_param("filename", filename)));
+    }
+    let mut lines = vec![
+        format!("Content-Type: {content_type}").into_bytes(),
+        format!("Content-Disposition: {disposition}").into_bytes(),
+        b"Content-Transfer-Encoding: base64".to_vec(),
+        Vec::new(),
+    ];
+    lines.extend(
+        encode_base64(content)
+            .as_bytes()
+            .chunks(76)
+            .map(<[u8]>::to_vec),
+    );
+    lines
+}
+
+/// A parameter as a quoted string, or in the extended form of RFC 2231 if it is not plain
+/// ASCII or could be mistaken for an encoded word.
+fn encode_param(name: &str, value: &str) -> String {
+    if value.bytes().all(|b| (b' '..=b'~').contains(&b)) && !value.contains("=?") {
+        return format!(
+            "{name}=\"{}\"",
+            value.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+    }
+    let mut encoded = format!("{name}*=utf-8''");
+    for b in value.bytes() {
+        match b {
+            b if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) => {
+                encoded.push(char::from(b))
+            }
+            b => encoded.push_str(&format!("%{b:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Whether a content type from a record is a plain type/subtype pair that is safe to write.
+fn is_media_type(content_type: &str) -> bool {
+    content_type.split_once('/').is_some_and(|(kind, subtype)| {
+        [kind, subtype].iter().all(|part| {
+            !part.is_empty()
+                && part
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b))
+        })
+    })
+}
+
+/// The usual capitalisation of a lowercased header name, such as "Message-ID".
+fn canonical_name(name: &str) -> String {
+    name.split('-')
+        .map(|word| match word {
+            "id" => "ID".to_string(),
+            "mime" => "MIME".to_string(),
+            word => {
+                let mut chars = word.chars();
+                chars.next().map_or_else(String::new, |first| {
+                    first.to_ascii_uppercase().to_string() + chars.as_str()
+                })
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
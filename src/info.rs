@@ -0,0 +1,229 @@
+use crate::{
+    Event, EventHandler, MailfmtError, MessageError, ProgressTotal,
+    charset::Charset,
+    counting::CountingReader,
+    error::{IoContext, Result},
+    gzip,
+    mbox::{MboxParser, check_mbox_input},
+    mime::for_each_part,
+};
+use std::{
+    collections::HashMap,
+    io::BufReader,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tracing::info_span;
+
+/// Options for [`mbox_info`]. Create them with [`InfoOptions::new`] and adjust the fields as
+/// needed.
+pub struct InfoOptions<'a> {
+    pub input_file: PathBuf,
+    /// MIME parts with a body larger than this many bytes are not decoded to check whether
+    /// they are valid in their declared charset, which keeps large attachments from slowing
+    /// the report down. They are still counted.
+    pub max_body_size: u64,
+    /// Inspect the input file even if it does not look like an mbox file.
+    pub force: bool,
+    /// Set to stop inspecting after the current message. The report then covers the messages
+    /// inspected so far.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called with progress and unreadable messages as the inspection goes on.
+    pub on_event: Option<EventHandler<'a>>,
+}
+
+impl InfoOptions<'_> {
+    pub fn new(input_file: impl Into<PathBuf>) -> Self {
+        Self {
+            input_file: input_file.into(),
+            max_body_size: 1024 * 1024,
+            force: false,
+            cancel: None,
+            on_event: None,
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// What [`mbox_info`] found in an mbox.
+#[derive(Debug, Default)]
+pub struct MboxInfo {
+    /// How many messages were inspected.
+    pub messages: usize,
+    /// How many of them Thunderbird marked as deleted, which it keeps in the mbox until the
+    /// folder is compacted.
+    pub deleted: usize,
+    /// The messages that could not be read.
+    pub errors: Vec<MessageError>,
+    pub charsets: CharsetReport,
+    /// Whether the inspection was cancelled before reaching the end of the mbox.
+    pub interrupted: bool,
+    pub elapsed: Duration,
+}
+
+/// The charsets declared by the Content-Type headers of the messages in an mbox and their
+/// MIME parts.
+#[derive(Debug, Default)]
+pub struct CharsetReport {
+    /// Every declared charset, lowercased, ordered from the most to the least messages
+    /// declaring it.
+    pub declared: Vec<CharsetUsage>,
+    /// How many messages declare no charset at all.
+    pub undeclared: usize,
+    /// How many messages have text that is not valid in its declared charset, which usually
+    /// means they were damaged or mislabelled. Only charsets that can be transcoded are
+    /// checked.
+    pub invalid: usize,
+    /// How many MIME parts were not checked for validity, as their body is larger than
+    /// [`InfoOptions::max_body_size`].
+    pub unchecked_parts: usize,
+}
+
+/// How often a charset is declared.
+#[derive(Debug, Default)]
+pub struct CharsetUsage {
+    pub charset: String,
+    /// How many messages declare it for themselves or at least one of their parts.
+    pub messages: usize,
+    /// How many messages and parts declare it.
+    pub parts: usize,
+}
+
+/// Inspect every message of an mbox file and report on what it contains, reading it once
+/// from start to end.
+///
+/// ```
+/// use mailfmt::{InfoOptions, mbox_info};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-info-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// fs::write(
+///     dir.join("in.mbox"),
+///     b"From a Mon Jan 01 00:00:00 2024\n\
+///       Content-Type: multipart/alternative; boundary=b\n\
+///       \n\
+///       --b\n\
+///       Content-Type: text/plain; charset=UTF-8\n\
+///       \n\
+///       caf\xc3\xa9\n\
+///       --b\n\
+///       Content-Type: text/html; charset=utf-8\n\
+///       \n\
+///       caf\xe9\n\
+///       --b--\n\
+///       From b Mon Jan 01 00:00:00 2024\n\
+///       Subject: Plain\n\
+///       \n\
+///       Hello\n",
+/// )?;
+///
+/// let info = mbox_info(&InfoOptions::new(dir.join("in.mbox")))?;
+/// assert_eq!(info.messages, 2);
+/// assert_eq!(info.charsets.declared[0].charset, "utf-8");
+/// assert_eq!((info.charsets.declared[0].messages, info.charsets.declared[0].parts), (1, 2));
+/// assert_eq!(info.charsets.undeclared, 1);
+/// assert_eq!(info.charsets.invalid, 1);
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn mbox_info(options: &InfoOptions<'_>) -> Result<MboxInfo> {
+    let _span = info_span!("mbox_info", input = %options.input_file.display()).entered();
+    let start = Instant::now();
+    let input_file = options.input_file.as_path();
+    check_mbox_input(input_file, options.force, false)?;
+
+    let input_size =
+        gzip::content_size(input_file).io_context("read metadata of mbox file", input_file)?;
+    let reader =
+        CountingReader::new(gzip::open(input_file, 0).io_context("open mbox file", input_file)?);
+    let bytes_read = reader.counter();
+    options.emit(Event::Started {
+        total: match input_size {
+            0 => ProgressTotal::Unknown,
+            size => ProgressTotal::Bytes(size),
+        },
+        position: 0,
+    })?;
+
+    let mut info = MboxInfo::default();
+    let mut declared: HashMap<String, CharsetUsage> = HashMap::new();
+    for message in MboxParser::from_reader(BufReader::new(reader)) {
+        if options.is_cancelled() {
+            info.interrupted = true;
+            break;
+        }
+        match message {
+            Ok(message) => {
+                info.messages += 1;
+                info.deleted += usize::from(message.is_deleted());
+                let (mut charsets, mut invalid) = (Vec::new(), false);
+                for_each_part(&message.lines, &mut |part| {
+                    let Some(label) = part.content_type.param("charset") else {
+                        return;
+                    };
+                    let label = label.trim().to_ascii_lowercase();
+                    let usage = declared.entry(label.clone()).or_default();
+                    usage.parts += 1;
+                    if !charsets.contains(&label) {
+                        usage.messages += 1;
+                        charsets.push(label.clone());
+                    }
+
+                    let Some(charset) = Charset::for_label(&label).filter(|_| !invalid) else {
+                        return;
+                    };
+                    let size: usize = part.body.iter().map(|line| line.len() + 1).sum();
+                    if size as u64 > options.max_body_size {
+                        info.charsets.unchecked_parts += 1;
+                    } else if let Some(body) = part.decode_body() {
+                        invalid = !charset.is_valid(&body);
+                    }
+                });
+                info.charsets.undeclared += usize::from(charsets.is_empty());
+                info.charsets.invalid += usize::from(invalid);
+            }
+            Err(error) => {
+                let item = format!("reading email {}", info.messages + info.errors.len());
+                options.emit(Event::Failed {
+                    item: item.clone(),
+                    error: &error,
+                })?;
+                info.errors.push(MessageError { item, error });
+            }
+        }
+        options.emit(Event::Progress {
+            position: bytes_read.load(Ordering::Relaxed),
+            processed: info.messages + info.errors.len(),
+        })?;
+    }
+
+    info.charsets.declared = declared
+        .into_iter()
+        .map(|(charset, usage)| CharsetUsage { charset, ..usage })
+        .collect();
+    info.charsets.declared.sort_by(|a, b| {
+        (b.messages, b.parts)
+            .cmp(&(a.messages, a.parts))
+            .then_with(|| a.charset.cmp(&b.charset))
+    });
+    info.elapsed = start.elapsed();
+    Ok(info)
+}
@@ -0,0 +1,472 @@
+//! A simple and quick bidirectional converter between mbox and eml formats.
+//!
+//! [`mbox_to_eml`] and [`eml_to_mbox`] perform a whole conversion like the `mailfmt` binary
+//! does, reporting progress through an optional callback and returning a [`ConversionSummary`].
+//! Nothing is printed, but every event is also emitted through `tracing`, inside a span for the
+//! conversion and one for each message, for applications that collect logs. [`MboxParser`],
+//! [`MboxWriter`] and the [`headers`] utilities can also be used on their own to read and write
+//! mbox data from any source.
+//!
+//! ```no_run
+//! use mailfmt::{Event, MboxToEmlOptions, mbox_to_eml};
+//!
+//! let mut options = MboxToEmlOptions::new("archive.mbox", "archive");
+//! options.on_event = Some(Box::new(|event| {
+//!     if let Event::Failed { item, error } = event {
+//!         eprintln!("Error {item}: {error}");
+//!     }
+//!     Ok(())
+//! }));
+//! let summary = mbox_to_eml(&options)?;
+//! println!("{} emails converted", summary.converted);
+//! # Ok::<(), mailfmt::MailfmtError>(())
+//! ```
+
+mod anonymize;
+mod atomic;
+mod attachments;
+mod babyl;
+mod charset;
+mod counting;
+mod dates;
+mod diff;
+mod duplicates;
+mod embedded;
+mod eml;
+mod encoding;
+mod error;
+mod existing;
+mod export;
+mod grouping;
+mod gzip;
+pub mod headers;
+mod html;
+mod imap;
+mod import;
+mod info;
+mod lock;
+mod mbox;
+mod mime;
+mod msgid_index;
+mod normalize;
+mod paths;
+mod peek;
+mod sample;
+mod sidecar;
+mod sizes;
+mod space;
+mod state;
+mod status;
+mod strip;
+mod threading;
+mod trace;
+mod transcode;
+mod validate;
+mod verify;
+mod writer;
+
+pub use anonymize::{AnonymizeOptions, Anonymized, anonymize_mbox};
+pub use attachments::{
+    Attachment, AttachmentEntry, AttachmentList, AttachmentsOptions, ExtractAttachmentsOptions,
+    MANIFEST_FILE_NAME, extract_attachments, list_attachments,
+};
+pub use babyl::InputFormat;
+pub use diff::{
+    DiffEntry, DiffLocation, DiffOptions, DiffSide, MailboxDiff, MailboxKind, diff_mailboxes,
+};
+pub use duplicates::{DuplicateGroup, MessageLocation, normalize_message_id};
+pub use embedded::{EMBEDDED_MANIFEST_FILE_NAME, MAX_EMBEDDED_DEPTH};
+pub use eml::{EmlInput, EmlToMboxOptions, count_eml_files, eml_to_mbox};
+pub use encoding::InvalidUtf8;
+pub use error::{MailfmtError, MessageError};
+pub use export::{AttachmentContent, ExportJsonOptions, export_json};
+pub use glob::Pattern;
+pub use grouping::{GroupBy, NameBy, Numbering, UuidVersion};
+pub use gzip::Compression;
+pub use html::HtmlText;
+pub use imap::{
+    FetchOutput, IMAP_PORT, IMAPS_PORT, ImapFetchOptions, ImapMailbox, ImapSecurity,
+    ImapUploadOptions, UploadDetails, fetch_from_imap, upload_to_imap,
+};
+pub use import::{ImportJsonOptions, ImportOutput, import_json};
+pub use info::{CharsetReport, CharsetUsage, InfoOptions, MboxInfo, mbox_info};
+pub use mbox::{
+    EmptyMessages, ExistingKey, Headerless, LeadingContent, MboxParser, MboxToEmlOptions, Message,
+    MessageRange, OffsetLine, OffsetLines, SMART_SEPARATOR_LOOKAHEAD, count_mbox, mbox_to_eml,
+};
+pub use mime::DecodedText;
+pub use normalize::{Normalization, normalize, transliterate};
+pub use peek::{PeekEnd, PeekOptions, Peeked, peek_mbox};
+pub use sample::SAMPLE_MANIFEST_FILE_NAME;
+pub use sizes::{
+    MessageSize, SIZE_BUCKETS, SenderTotal, SizeBucket, SizeReport, SizeReportOptions, size_report,
+};
+pub use status::ReadState;
+pub use writer::{FromLineStyle, MboxDialect, MboxWriter, envelope_for};
+
+use std::{collections::BTreeMap, error::Error, fmt, path::Path, time::Duration};
+
+/// The outcome of a conversion.
+///
+/// ```
+/// use mailfmt::{EmlInput, EmlToMboxOptions, MboxToEmlOptions, eml_to_mbox, mbox_to_eml};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-summary-{}", std::process::id()));
+/// fs::create_dir_all(dir.join("in"))?;
+/// fs::write(dir.join("in/one.eml"), "Subject: One\n\nHello\n")?;
+/// fs::write(dir.join("in/two.eml"), "not an email\n")?;
+///
+/// let input = EmlInput::Directory(dir.join("in"));
+/// let summary = eml_to_mbox(&EmlToMboxOptions::new(input, dir.join("all.mbox")))?;
+/// assert_eq!(summary.converted, 1);
+/// assert_eq!(summary.errors.len(), 1);
+/// assert_eq!(summary.errors[0].error.code(), "invalid-eml");
+/// assert_eq!(summary.bytes_written, fs::metadata(dir.join("all.mbox"))?.len());
+///
+/// let summary = mbox_to_eml(&MboxToEmlOptions::new(dir.join("all.mbox"), dir.join("out")))?;
+/// assert_eq!(summary.converted, 1);
+/// assert!(summary.errors.is_empty());
+/// assert_eq!(summary.bytes_written, "Subject: One\n\nHello\n\n".len() as u64);
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct ConversionSummary {
+    /// How many messages were converted by this run.
+    pub converted: usize,
+    /// How many messages or files were deliberately not converted. For eml-to-mbox, these are
+    /// the files that are unchanged since the previous run and those in `invalid`, and for
+    /// mbox-to-eml the messages whose existing eml file was not replaced when asked.
+    pub skipped: usize,
+    /// The messages that failed to convert, in order. Each was also reported with [`Event::Failed`].
+    pub errors: Vec<MessageError>,
+    /// How many messages or files contained bytes that are not valid UTF-8, whatever the
+    /// [`InvalidUtf8`] policy did with them.
+    pub invalid_utf8: usize,
+    /// For mbox-to-eml with [`MboxToEmlOptions::transcode`], how many messages had text
+    /// converted to UTF-8.
+    pub transcoded: usize,
+    /// For mbox-to-eml with [`MboxToEmlOptions::transcode`], how many messages had text left
+    /// as it was, because its charset was unknown or not declared, or it was nested in a
+    /// multipart part.
+    pub not_transcoded: usize,
+    /// Whether the conversion was cancelled before it completed.
+    pub interrupted: bool,
+    /// How many bytes of output this run wrote, not counting any watching.
+    pub bytes_written: u64,
+    /// For mbox-to-eml and eml-to-mbox, how many bytes of input this run read, not counting
+    /// any precounting or watching.
+    pub bytes_read: u64,
+    /// How long the conversion took, not counting any watching.
+    pub elapsed: Duration,
+    /// For eml-to-mbox, how many files each include pattern matched, in pattern order.
+    pub pattern_matches: Vec<usize>,
+    /// For eml-to-mbox, how many of the converted files were gzip-compressed, not counting any
+    /// watching, and for mbox-to-eml, how many eml files were written compressed.
+    pub compressed: usize,
+    /// For text exports, how many messages had their text taken from their HTML part.
+    pub from_html: usize,
+    /// For text exports, how many messages had no text that could be exported, and got a
+    /// placeholder listing the types of their parts instead.
+    pub without_text: usize,
+    /// For eml-to-mbox with [`EmlToMboxOptions::skip_invalid`], the files that were left out
+    /// as they failed validation, in order, not counting any watching.
+    pub invalid: Vec<MessageError>,
+    /// For eml-to-mbox, how many files converted by the previous run no longer exist.
+    pub deleted: usize,
+    /// For eml-to-mbox with [`EmlToMboxOptions::append`], how many messages the mbox file
+    /// already held before this run appended to it.
+    pub preexisting: usize,
+    /// For eml-to-mbox with [`EmlToMboxOptions::verify_content`], how many messages were read
+    /// back from the mbox file and matched what they were written from.
+    pub verified: usize,
+    /// For eml-to-mbox, how many eml files looked like mailboxes holding several messages.
+    /// With [`EmlToMboxOptions::split_embedded`], their messages were appended one by one and
+    /// are counted in `converted`.
+    pub mailboxes: usize,
+    /// For mbox-to-eml with [`MboxToEmlOptions::skip_existing_by`], how many messages were
+    /// left out as already present in the output directory. They are counted in `skipped`
+    /// as well.
+    pub already_present: usize,
+    /// With attachments stripped, how many attachments were replaced, not counting any
+    /// watching.
+    pub stripped: usize,
+    /// With attachments stripped, how many bytes smaller the messages became.
+    pub stripped_bytes: u64,
+    /// For mbox-to-eml, what earlier runs did when the extraction was resumed.
+    pub resumed: Option<Resumed>,
+    /// For mbox-to-eml with [`MboxToEmlOptions::allow_empty`], whether the input was empty or
+    /// held only blank lines, so that nothing was written.
+    pub empty_input: bool,
+    /// For mbox-to-eml with [`MboxToEmlOptions::explode_rfc822`], how many embedded messages
+    /// were written to eml files of their own. They are not counted in `converted`.
+    pub embedded: usize,
+    /// For mbox-to-eml, how many messages without a header block were left out.
+    pub headerless: usize,
+    /// For mbox-to-eml, how many empty messages were left out, as
+    /// [`MboxToEmlOptions::empty_messages`] skips them by default.
+    pub empty: usize,
+    /// For mbox-to-eml with [`MboxToEmlOptions::range`], how many messages before the range
+    /// were passed over.
+    pub out_of_range: usize,
+    /// For mbox-to-eml with [`MboxToEmlOptions::list_ids`], how many messages were left out as
+    /// they are not on any of the lists.
+    pub off_list: usize,
+    /// For mbox-to-eml with [`MboxToEmlOptions::read_state`], how many messages were left out
+    /// as they are in the other read state.
+    pub off_state: usize,
+    /// For mbox-to-eml with [`MboxToEmlOptions::skip_deleted`], how many messages were left out
+    /// as Thunderbird marked them as deleted.
+    pub skipped_deleted: usize,
+    /// For mbox-to-eml with [`MboxToEmlOptions::list_ids`], how many messages of every list
+    /// were extracted, by lowercased list identifier.
+    pub lists: BTreeMap<String, usize>,
+    /// For mbox-to-eml with [`MboxToEmlOptions::sample`], how the sample was drawn.
+    pub sampled: Option<Sampled>,
+    /// For eml-to-mbox, what happened while watching for new files.
+    pub watch: Option<WatchSummary>,
+    /// With duplicates reported, the Message-IDs found on more than one message converted by
+    /// this run, not counting any watching.
+    pub duplicates: Vec<DuplicateGroup>,
+    /// With a Message-ID index written, how many entries it holds.
+    pub msgid_index: Option<MessageIdIndex>,
+    /// For uploads to and fetches from IMAP, how many times the connection was opened again
+    /// after breaking.
+    pub reconnects: usize,
+    /// For uploads to IMAP, whether the mailbox did not exist and was created.
+    pub mailbox_created: bool,
+    /// For fetches from IMAP, the highest UID fetched by earlier fetches into the same output,
+    /// after which this one continued.
+    pub continued_after_uid: Option<u64>,
+}
+
+impl ConversionSummary {
+    /// How many messages or files of an mbox-to-eml or eml-to-mbox conversion ended up in each
+    /// category, not counting any watching.
+    ///
+    /// ```
+    /// use mailfmt::{Counters, EmlInput, EmlToMboxOptions, eml_to_mbox};
+    /// use std::fs;
+    ///
+    /// let dir = std::env::temp_dir().join(format!("mailfmt-doc-counters-{}", std::process::id()));
+    /// fs::create_dir_all(dir.join("in"))?;
+    /// fs::write(dir.join("in/one.eml"), "Subject: One\n\nHello\n")?;
+    /// fs::write(dir.join("in/page.eml"), "<html></html>\n")?;
+    ///
+    /// let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), dir.join("all.mbox"));
+    /// options.skip_invalid = true;
+    /// let summary = eml_to_mbox(&options)?;
+    /// assert_eq!(
+    ///     summary.counters(),
+    ///     Counters { converted: 1, invalid: 1, ..Counters::default() }
+    /// );
+    /// # fs::remove_dir_all(&dir)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn counters(&self) -> Counters {
+        Counters {
+            converted: self.converted,
+            filtered: self.out_of_range + self.off_list + self.off_state + self.skipped_deleted,
+            existing: self.skipped - self.invalid.len(),
+            invalid: self.headerless + self.empty + self.invalid.len(),
+            failed: self.errors.len(),
+        }
+    }
+}
+
+/// The outcome of every message or file of a conversion, so that those left out on purpose
+/// can be told apart from those that failed. Each is counted in exactly one category.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub converted: usize,
+    /// Left out by a filter: before [`MboxToEmlOptions::range`], not on any of
+    /// [`MboxToEmlOptions::list_ids`], not in [`MboxToEmlOptions::read_state`], or marked as
+    /// deleted with [`MboxToEmlOptions::skip_deleted`].
+    pub filtered: usize,
+    /// Left out as their output already exists: eml files that were not replaced for
+    /// mbox-to-eml, and eml files unchanged since the previous run for eml-to-mbox.
+    pub existing: usize,
+    /// Left out as they are not valid: empty messages and those without a header block for
+    /// mbox-to-eml, and eml files failing [`EmlToMboxOptions::skip_invalid`] for eml-to-mbox.
+    pub invalid: usize,
+    /// Failed to convert, as listed in [`ConversionSummary::errors`].
+    pub failed: usize,
+}
+
+impl Counters {
+    /// How many messages or files were handled in all.
+    pub fn total(&self) -> usize {
+        self.converted + self.filtered + self.existing + self.invalid + self.failed
+    }
+}
+
+/// The messages counted by [`count_mbox`], or the eml files counted by [`count_eml_files`].
+#[derive(Debug, Default)]
+pub struct MessageCount {
+    /// How many were found, not counting those after the end of any range.
+    pub found: u64,
+    /// How many of those the conversion with the same options would take, as far as can be
+    /// told without converting them: messages or files that fail to convert are counted.
+    pub matching: u64,
+    /// Whether counting was cancelled before it completed, so that both counts are too low.
+    pub interrupted: bool,
+    pub elapsed: Duration,
+}
+
+/// The entries of a Message-ID index written with a conversion.
+#[derive(Debug)]
+pub struct MessageIdIndex {
+    pub entries: usize,
+    /// How many messages had no Message-ID and are keyed by a hash of their content instead.
+    pub synthetic: usize,
+}
+
+/// The messages handled by the earlier runs of a resumed extraction.
+#[derive(Debug)]
+pub struct Resumed {
+    pub converted: usize,
+    pub errors: usize,
+}
+
+/// How the sample of an extraction with [`MboxToEmlOptions::sample`] was drawn.
+#[derive(Debug)]
+pub struct Sampled {
+    /// The seed it was drawn with, which draws the same sample from the same input again.
+    pub seed: u64,
+    /// How many messages it was drawn from, not counting those left out as empty, headerless or for
+    /// their mailing list, or that could not be read.
+    pub population: usize,
+}
+
+/// The files appended while watching the input of an eml-to-mbox conversion.
+#[derive(Debug, Default)]
+pub struct WatchSummary {
+    pub appended: usize,
+    pub errors: Vec<MessageError>,
+}
+
+/// What progress positions are measured against.
+#[derive(Clone, Copy, Debug)]
+pub enum ProgressTotal {
+    /// The size of the input in bytes.
+    Bytes(u64),
+    /// The number of messages or files to convert.
+    Messages(u64),
+    /// Nothing, as the size of the input is not known.
+    Unknown,
+}
+
+/// A callback receiving the events of a conversion. Returning an error from it stops the
+/// conversion with [`MailfmtError::Aborted`].
+pub type EventHandler<'a> = Box<dyn Fn(Event<'_>) -> Result<(), Box<dyn Error + Send + Sync>> + 'a>;
+
+/// Called with an existing output file before it is replaced, when replacing files was not
+/// asked for up front, to decide what to do with it.
+pub type ConfirmHandler<'a> = Box<dyn Fn(&Path) -> Answer + 'a>;
+
+/// What to do with an existing output file, as answered by a [`ConfirmHandler`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Answer {
+    /// Replace it.
+    Yes,
+    /// Leave it as it is.
+    No,
+    /// Leave it as it is and stop, as if the conversion was cancelled.
+    Quit,
+}
+
+/// Something that happened during a conversion, passed to the `on_event` callback of its options.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// The messages in the input were counted before converting.
+    Counted { messages: u64, elapsed: Duration },
+    /// The eml files already in the output directory are being read for their Message-IDs
+    /// before extracting, with `files` read so far.
+    Scanning { files: u64 },
+    /// The eml files already in the output directory were read, finding `message_ids`
+    /// distinct Message-IDs among them.
+    Scanned {
+        files: u64,
+        message_ids: usize,
+        elapsed: Duration,
+    },
+    /// The space the output is estimated to need was compared with the space available where
+    /// it is written, in bytes. The estimate does not include the 5% margin required on top.
+    SpaceChecked { required: u64, available: u64 },
+    /// Conversion is starting, with progress measured against `total` from `position` onwards.
+    Started { total: ProgressTotal, position: u64 },
+    /// A message is about to be processed, named by its subject when it comes from an mbox
+    /// file, or by the path of its eml file relative to the input directory.
+    Processing(&'a str),
+    /// A message was processed, successfully or not. `processed` counts the messages
+    /// processed by this run.
+    Progress { position: u64, processed: usize },
+    /// An output file was written completely.
+    Written(&'a Path),
+    /// A message could not be converted. The conversion continues with the next one.
+    Failed {
+        item: String,
+        error: &'a MailfmtError,
+    },
+    /// Something was skipped or could not be done, without affecting the converted output.
+    Warning(String),
+    /// The conversion is complete and the input is now being watched for new files.
+    Watching(&'a ConversionSummary),
+    /// A new file was appended while watching.
+    Appended(&'a Path),
+    /// A message was converted, with the details of how. Only reported when the options ask
+    /// for details.
+    Converted(MessageDetails<'a>),
+    /// A message was uploaded to an IMAP server, or listed by a dry run of an upload.
+    Uploaded(&'a UploadDetails),
+}
+
+/// How a single message was converted, reported through [`Event::Converted`].
+#[derive(Debug)]
+pub struct MessageDetails<'a> {
+    /// The number of the message among those converted, counting from 0, as used in the
+    /// names of extracted eml files.
+    pub index: usize,
+    /// The address the message is from, if it has one.
+    pub sender: Option<String>,
+    /// The date of the message, as it was written where it was taken from.
+    pub date: Option<String>,
+    /// Where `date` was taken from.
+    pub date_source: DateSource,
+    /// The eml file the message was written to or read from.
+    pub file: &'a Path,
+    /// How long converting the message took.
+    pub elapsed: Duration,
+    /// Decisions made while converting the message, such as fallbacks used or filters
+    /// applied, in the order they were made.
+    pub notes: Vec<String>,
+}
+
+/// Where the date of a converted message was taken from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DateSource {
+    /// Its Date header.
+    Header,
+    /// The date on its mbox separator line, as it has no usable Date header, or the separator
+    /// line kept in the sidecar of its eml file.
+    Separator,
+    /// The date its last mail server received it at, at the end of its first Received header.
+    Received,
+    /// When its eml file was last modified, as nothing in the message itself gives its date.
+    FileTime,
+    /// Nowhere, as it has no usable date. A placeholder date was written instead.
+    Placeholder,
+}
+
+impl fmt::Display for DateSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Header => "Date header",
+            Self::Separator => "separator line",
+            Self::Received => "Received header",
+            Self::FileTime => "file modification time",
+            Self::Placeholder => "placeholder",
+        })
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{
+    MailfmtError,
+    error::{IoContext, Result},
+};
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long to wait between attempts to acquire a lock that is held by someone else.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An exclusive advisory lock on an mbox file, optionally paired with a traditional
+/// `<name>.lock` dotlock file. Both are released when the lock is dropped.
+pub struct MboxLock {
+    /// Kept open for as long as the lock is held, as closing it releases the lock.
+    _file: Option<File>,
+    dotlock: Option<PathBuf>,
+}
+
+impl MboxLock {
+    /// Lock the mbox file at `path`, waiting up to `timeout` for other holders to release it.
+    ///
+    /// The dotlock is taken first, as is conventional for mail tools using both. A file that
+    /// does not exist yet is only created for locking when `create` is set; otherwise only
+    /// the dotlock (if any) protects it until it is created and locked with
+    /// [`MboxLock::relock`].
+    pub fn acquire(path: &Path, dotlock: bool, create: bool, timeout: Duration) -> Result<Self> {
+        let deadline = Instant::now() + timeout;
+        let mut lock = Self {
+            _file: None,
+            dotlock: None,
+        };
+        if dotlock {
+            let dotlock_path = Self::dotlock_path_for(path);
+            Self::acquire_dotlock(&dotlock_path, deadline, timeout)?;
+            lock.dotlock = Some(dotlock_path);
+        }
+
+        lock._file = Self::lock_file(path, create, deadline, timeout)?;
+        Ok(lock)
+    }
+
+    /// Lock the file at `path` again once it was replaced by another file, such as a new mbox
+    /// moved into place, so that the lock is held on the file others open from now on. The
+    /// lock on the replaced file is released.
+    pub fn relock(&mut self, path: &Path, timeout: Duration) -> Result<()> {
+        self._file = Self::lock_file(path, false, Instant::now() + timeout, timeout)?;
+        Ok(())
+    }
+
+    /// Open and lock the file at `path`, which is `None` when it does not exist and is not
+    /// created.
+    fn lock_file(
+        path: &Path,
+        create: bool,
+        deadline: Instant,
+        timeout: Duration,
+    ) -> Result<Option<File>> {
+        let file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(create)
+            .truncate(false)
+            .open(path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).io_context("open mbox file", path),
+        };
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(Some(file)),
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    thread::sleep(RETRY_INTERVAL)
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(MailfmtError::LockTimeout {
+                        path: path.to_path_buf(),
+                        timeout,
+                    });
+                }
+                Err(TryLockError::Error(e)) => return Err(e).io_context("lock mbox file", path),
+            }
+        }
+    }
+
+    /// The traditional dotlock path for an mbox file, which is its full name with `.lock` appended.
+    pub fn dotlock_path_for(path: &Path) -> PathBuf {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(".lock");
+        PathBuf::from(name)
+    }
+
+    fn acquire_dotlock(dotlock_path: &Path, deadline: Instant, timeout: Duration) -> Result<()> {
+        loop {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(dotlock_path)
+            {
+                Ok(mut file) => {
+                    // The holder's process id lets others report who has the lock.
+                    writeln!(file, "{}", std::process::id())
+                        .io_context("write lock file", dotlock_path)?;
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if Instant::now() < deadline {
+                        thread::sleep(RETRY_INTERVAL);
+                        continue;
+                    }
+                    let holder = fs::read_to_string(dotlock_path)
+                        .ok()
+                        .and_then(|content| content.trim().parse::<u32>().ok());
+                    return Err(MailfmtError::DotlockTimeout {
+                        path: dotlock_path.to_path_buf(),
+                        timeout,
+                        holder,
+                    });
+                }
+                Err(e) => return Err(e).io_context("create lock file", dotlock_path),
+            }
+        }
+    }
+}
+
+impl Drop for MboxLock {
+    fn drop(&mut self) {
+        if let Some(dotlock) = &self.dotlock {
+            let _ = fs::remove_file(dotlock);
+        }
+    }
+}
@@ -1,9 +1,28 @@
-mod eml;
-mod mbox;
+mod cli;
 
-use crate::{eml::ConvertToMboxCommand, mbox::ConvertToEmlCommand};
+use crate::cli::{
+    anonymize::AnonymizeCommand,
+    attachments::AttachmentsCommand,
+    completions::CompletionsCommand,
+    config::{self, ConfigCommand},
+    diff::DiffCommand,
+    eml_to_mbox::ConvertToMboxCommand,
+    export_json::ExportJsonCommand,
+    export_text::ExportTextCommand,
+    imap::{FetchCommand, FetchInto, UploadCommand, UploadInput},
+    import_json::ImportJsonCommand,
+    info::InfoCommand,
+    interrupt,
+    man::ManCommand,
+    mbox_to_eml::ConvertToEmlCommand,
+    output::{self, ColorChoice, LogFormat},
+    paths,
+    peek::PeekCommand,
+    report::ReportCommand,
+};
 use clap::Parser;
-use std::path::PathBuf;
+use mailfmt::PeekEnd;
+use std::{path::PathBuf, process::ExitCode, time::Instant};
 
 /// A simple and quick bidirectional converter between mbox and eml formats.
 #[derive(Parser)]
@@ -11,24 +30,148 @@ use std::path::PathBuf;
 struct Arguments {
     #[clap(subcommand)]
     command: Commands,
+
+    /// Do not expand a leading '~' or environment variables such as '$HOME' in paths given as arguments.
+    #[clap(long = "no-expand", global = true)]
+    no_expand: bool,
+
+    /// When to color output: 'auto' colors it only on a terminal and when NO_COLOR is not set, 'always' or 'never'.
+    #[clap(long = "color", value_name = "WHEN", global = true, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Log every converted message to stderr with its sender, date, date source and file. Give it twice to also log how long each message took and the decisions made for it, such as fallbacks used and filters applied.
+    #[clap(short = 'v', long = "verbose", global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// How to write errors, warnings and the per-message log to stderr: 'human', or 'json' for a JSON object per line with a timestamp, level and event name, such as one per converted message with its index and duration, for log aggregation. JSON logs replace the progress bar.
+    #[clap(long = "log-format", value_name = "FORMAT", global = true, default_value_t = LogFormat::Human)]
+    log_format: LogFormat,
+
+    /// Read default values for flags from this file instead of ~/.config/mailfmt/config.toml. Flags given on the command line always win.
+    #[clap(long = "config", value_name = "PATH", global = true)]
+    config: Option<PathBuf>,
 }
 
 #[derive(Parser)]
 enum Commands {
     EmlToMbox(ConvertToMboxCommand),
     MboxToEml(ConvertToEmlCommand),
+    Info(InfoCommand),
+    /// Print the first messages of an .mbox file as they are, or write them to eml files.
+    Head(PeekCommand),
+    /// Print the last messages of an .mbox file as they are, or write them to eml files. The whole file is read to find them.
+    Tail(PeekCommand),
+    ExportText(ExportTextCommand),
+    ExportJson(ExportJsonCommand),
+    ImportJson(ImportJsonCommand),
+    /// Upload every message of a directory of .eml files to a mailbox on an IMAP server, with its date and read status.
+    EmlToImap(UploadCommand),
+    /// Upload every message of an .mbox file to a mailbox on an IMAP server, with its date and read status.
+    MboxToImap(UploadCommand),
+    /// Fetch the messages of a mailbox on an IMAP server into an .mbox file, with their read status. Later fetches only fetch new messages.
+    ImapToMbox(FetchCommand),
+    /// Fetch the messages of a mailbox on an IMAP server as .eml files, with their read status. Later fetches only fetch new messages.
+    ImapToEml(FetchCommand),
+    Anonymize(AnonymizeCommand),
+    Diff(DiffCommand),
+    Attachments(AttachmentsCommand),
+    Report(ReportCommand),
+    Completions(CompletionsCommand),
+    Man(ManCommand),
+    Config(ConfigCommand),
 }
 
-fn validate_output_file(s: &str) -> Result<PathBuf, String> {
-    if s.ends_with('/') || s.ends_with('\\') {
-        return Err(format!("'{}' appears to be a directory, not a file", s));
+impl Commands {
+    /// Expand '~' and environment variables in the paths given as arguments to the command.
+    fn expand_paths(&mut self) -> anyhow::Result<()> {
+        match self {
+            Self::EmlToMbox(cmd) => cmd.expand_paths(),
+            Self::MboxToEml(cmd) => cmd.expand_paths(),
+            Self::Info(cmd) => cmd.expand_paths(),
+            Self::Head(cmd) | Self::Tail(cmd) => cmd.expand_paths(),
+            Self::ExportText(cmd) => cmd.expand_paths(),
+            Self::ExportJson(cmd) => cmd.expand_paths(),
+            Self::ImportJson(cmd) => cmd.expand_paths(),
+            Self::EmlToImap(cmd) | Self::MboxToImap(cmd) => cmd.expand_paths(),
+            Self::ImapToMbox(cmd) | Self::ImapToEml(cmd) => cmd.expand_paths(),
+            Self::Anonymize(cmd) => cmd.expand_paths(),
+            Self::Diff(cmd) => cmd.expand_paths(),
+            Self::Attachments(cmd) => cmd.expand_paths(),
+            Self::Report(cmd) => cmd.expand_paths(),
+            Self::Man(cmd) => cmd.expand_paths(),
+            Self::Completions(_) | Self::Config(_) => Ok(()),
+        }
     }
-    Ok(PathBuf::from(s))
 }
 
-fn main() -> anyhow::Result<()> {
-    match Arguments::parse().command {
+fn main() -> anyhow::Result<ExitCode> {
+    interrupt::install_handler()?;
+    let (args, from_environment) = config::parse_arguments::<Arguments>()?;
+    output::init(args.color);
+    output::set_verbosity(args.verbose);
+    output::set_log_format(args.log_format);
+    if !output::json_logs() {
+        let result = run(args);
+        config::print_environment_flags(&from_environment);
+        return Ok(result.unwrap_or_else(|error| {
+            let message = format!("Error: {}", cli::describe_error(&*error));
+            eprintln!("{}", output::error(message));
+            ExitCode::FAILURE
+        }));
+    }
+
+    // Every event of the run is logged within this span, which tells runs apart.
+    let _span = tracing::info_span!("run", pid = std::process::id()).entered();
+    let start = Instant::now();
+    tracing::info!(
+        event = "run_started",
+        arguments = ?std::env::args().skip(1).collect::<Vec<_>>(),
+        environment = ?from_environment
+    );
+    let result = run(args);
+    let duration_ms = output::milliseconds(start.elapsed());
+    match result {
+        Ok(code) => {
+            tracing::info!(event = "run_finished", duration_ms);
+            Ok(code)
+        }
+        Err(error) => {
+            tracing::error!(
+                event = "run_failed",
+                duration_ms,
+                error = cli::describe_error(&*error)
+            );
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+fn run(mut args: Arguments) -> anyhow::Result<ExitCode> {
+    if !args.no_expand {
+        args.command.expand_paths()?;
+        if let Some(config) = &args.config {
+            args.config = Some(paths::expand(config).map_err(anyhow::Error::msg)?);
+        }
+    }
+    match args.command {
         Commands::EmlToMbox(cmd) => cmd.run(),
         Commands::MboxToEml(cmd) => cmd.run(),
+        Commands::Info(cmd) => cmd.run(),
+        Commands::Head(cmd) => cmd.run(PeekEnd::Head),
+        Commands::Tail(cmd) => cmd.run(PeekEnd::Tail),
+        Commands::ExportText(cmd) => cmd.run(),
+        Commands::ExportJson(cmd) => cmd.run(),
+        Commands::ImportJson(cmd) => cmd.run(),
+        Commands::EmlToImap(cmd) => cmd.run(UploadInput::Eml),
+        Commands::MboxToImap(cmd) => cmd.run(UploadInput::Mbox),
+        Commands::ImapToMbox(cmd) => cmd.run(FetchInto::Mbox),
+        Commands::ImapToEml(cmd) => cmd.run(FetchInto::Eml),
+        Commands::Anonymize(cmd) => cmd.run(),
+        Commands::Diff(cmd) => cmd.run(),
+        Commands::Attachments(cmd) => cmd.run(),
+        Commands::Report(cmd) => cmd.run(),
+        Commands::Completions(cmd) => cmd.run(config::command::<Arguments>()),
+        Commands::Man(cmd) => cmd.run(config::command::<Arguments>()),
+        Commands::Config(cmd) => cmd.run(config::command::<Arguments>(), args.config.as_deref()),
     }
 }
@@ -1,187 +1,3212 @@
-use crate::validate_output_file;
-use anyhow::{Context, Result, bail};
-use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
+use crate::{
+    Answer, ConfirmHandler, ConversionSummary, DateSource, Event, EventHandler, MailfmtError,
+    MessageCount, MessageDetails, MessageError, ProgressTotal, Resumed, Sampled,
+    atomic::AtomicFile,
+    babyl::{self, InputFormat, input_lines},
+    counting::CountingReader,
+    dates::{DateSources, message_date},
+    duplicates::{Duplicates, MessageLocation},
+    embedded::{EmbeddedManifest, MAX_EMBEDDED_DEPTH, embedded_messages},
+    encoding::{InvalidUtf8, strip_bom},
+    error::{IoContext, Result},
+    existing::ExistingMessages,
+    grouping::{
+        GroupBy, NameBy, Numbering, UuidVersion, date_directory, folder_directory, sender_counts,
+        sender_directory, sender_key,
+    },
+    gzip::{self, Compression},
+    headers::{
+        FOLDER_HEADER, SOURCE_FILE_HEADER, clean_subject, decode_encoded_words,
+        get_header_value_from_lines, is_header_line, list_id,
+    },
+    html::{HtmlText, html_to_text},
+    mime::{DecodedText, Entity, decode_html, decode_text, for_each_part},
+    msgid_index::{IndexLocation, IndexWriter, content_key},
+    normalize::{Normalization, normalize, transliterate},
+    paths::{self, sync_directory},
+    sample::{Reservoir, SampleManifest, random_seed},
+    sidecar, space,
+    state::ResumeState,
+    status::{self, ReadState},
+    strip::{Stripped, strip_attachments},
+    threading::thread_directories,
+    transcode::{Transcoded, transcode_message},
+    writer::{MboxDialect, address_of},
+};
+use chrono::{DateTime, FixedOffset};
+use sha2::{Digest, Sha256};
 use std::{
-    fs::{self, File},
-    io::{self, BufRead, BufReader, BufWriter, Write},
-    iter::Peekable,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt, fs,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    iter::{FusedIterator, Peekable},
     path::{Path, PathBuf},
-    time::Duration,
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
 };
+use tracing::info_span;
+
+/// What [`mbox_to_eml`] does with messages that have no header block: no header field before
+/// the first blank line, such as a separator line followed directly by text, or the
+/// pseudo-messages some old clients insert.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Headerless {
+    /// Extract them like any other message.
+    #[default]
+    Keep,
+    /// Leave them out, reporting each with [`Event::Warning`] and counting them in
+    /// [`ConversionSummary::headerless`].
+    Skip,
+    /// Count them as errors.
+    Error,
+}
+
+impl fmt::Display for Headerless {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Keep => "keep",
+            Self::Skip => "skip",
+            Self::Error => "error",
+        })
+    }
+}
+
+impl FromStr for Headerless {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "keep" => Ok(Self::Keep),
+            "skip" => Ok(Self::Skip),
+            "error" => Ok(Self::Error),
+            _ => Err(format!(
+                "unknown policy for messages without headers '{s}', expected one of keep, skip or error"
+            )),
+        }
+    }
+}
+
+/// What [`mbox_to_eml`] does with empty messages, which hold no line or only blank ones, such
+/// as those between two separator lines in a row.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmptyMessages {
+    /// Leave them out, counting them in [`ConversionSummary::empty`].
+    #[default]
+    Skip,
+    /// Extract them like any other message, to empty or blank eml files.
+    Keep,
+    /// Count them as errors.
+    Error,
+}
+
+impl fmt::Display for EmptyMessages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Skip => "skip",
+            Self::Keep => "keep",
+            Self::Error => "error",
+        })
+    }
+}
+
+impl FromStr for EmptyMessages {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(Self::Skip),
+            "keep" => Ok(Self::Keep),
+            "error" => Ok(Self::Error),
+            _ => Err(format!(
+                "unknown policy for empty messages '{s}', expected one of skip, keep or error"
+            )),
+        }
+    }
+}
+
+/// What [`mbox_to_eml`] recognizes the messages already in its output directory by, with
+/// [`MboxToEmlOptions::skip_existing_by`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExistingKey {
+    /// Their Message-ID, compared without the whitespace and angle brackets around it.
+    /// Messages without one are always extracted.
+    MessageId,
+}
+
+impl fmt::Display for ExistingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::MessageId => "message-id",
+        })
+    }
+}
+
+impl FromStr for ExistingKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "message-id" => Ok(Self::MessageId),
+            _ => Err(format!(
+                "unknown key for existing messages '{s}', expected message-id"
+            )),
+        }
+    }
+}
+
+/// The positions of the messages to extract from an mbox, counting every message from 0
+/// whether it can be read or not. The end is excluded, and either end can be left open.
+///
+/// ```
+/// use mailfmt::MessageRange;
+///
+/// let range: MessageRange = "1200..1300".parse()?;
+/// assert!(range.contains(1200) && !range.contains(1300));
+/// assert_eq!("..500".parse::<MessageRange>()?, MessageRange { start: 0, end: Some(500) });
+/// assert_eq!("40000..".parse::<MessageRange>()?, MessageRange { start: 40000, end: None });
+/// assert!("1300..1200".parse::<MessageRange>().is_err());
+/// # Ok::<(), String>(())
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MessageRange {
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+impl MessageRange {
+    pub fn contains(&self, index: usize) -> bool {
+        index >= self.start && self.end.is_none_or(|end| index < end)
+    }
+}
+
+impl fmt::Display for MessageRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..", self.start)?;
+        match self.end {
+            Some(end) => write!(f, "{end}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for MessageRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((start, end)) = s.trim().split_once("..") else {
+            return Err(format!(
+                "'{s}' is not a range, expected START..END, START.. or ..END, such as 1200..1300"
+            ));
+        };
+        if end.starts_with('=') {
+            return Err(format!(
+                "'{s}' is an inclusive range, but the end is always excluded, so use START..END"
+            ));
+        }
+        let index = |part: &str| match part.trim() {
+            "" => Ok(None),
+            part => part
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("'{part}' in '{s}' is not a message position")),
+        };
+        let (start, end) = (index(start)?.unwrap_or(0), index(end)?);
+        if end.is_some_and(|end| end <= start) {
+            return Err(format!(
+                "'{s}' selects no messages, as its end is not after its start"
+            ));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+/// Options for [`mbox_to_eml`]. Create them with [`MboxToEmlOptions::new`] and adjust the
+/// fields as needed.
+pub struct MboxToEmlOptions<'a> {
+    pub input_file: PathBuf,
+    pub output_directory: PathBuf,
+    /// The format of the input file, an mbox by default.
+    pub input_format: InputFormat,
+    /// Replace existing eml files in the output directory if they overlap. Files that do not
+    /// overlap are never deleted.
+    pub overwrite: bool,
+    /// Count the messages before converting, so that progress is reported as
+    /// [`ProgressTotal::Messages`] rather than in bytes.
+    pub precount: bool,
+    /// Sync every extracted eml file and the output directory to disk before counting it as
+    /// converted.
+    pub fsync: bool,
+    /// Convert the input file even if it does not look like an mbox file, and even if the
+    /// output directory seems to lack the space for the extracted messages.
+    pub force: bool,
+    /// Before extracting, check that the filesystem of the output directory has room for
+    /// about as many bytes as remain to be read of the input, and a 5% margin, failing with
+    /// [`MailfmtError::InsufficientSpace`] otherwise. Both figures are reported through
+    /// [`Event::SpaceChecked`].
+    pub space_check: bool,
+    /// Succeed without writing anything, not even the output directory, when the input is
+    /// empty or holds only blank lines, instead of returning [`MailfmtError::EmptyInput`].
+    pub allow_empty: bool,
+    /// What to do with messages that have no header block.
+    pub headerless: Headerless,
+    /// What to do with messages that hold no line or only blank ones. They are dealt with
+    /// before `headerless` is applied, as they have no header block either.
+    pub empty_messages: EmptyMessages,
+    /// Only take "From " lines in quoted-printable messages for separators when they are
+    /// followed by a header field. See [`MboxParser::with_smart_separators`]. Counting the
+    /// messages with `precount` then parses them too, so that the count matches.
+    pub smart_separators: bool,
+    /// The mbox dialect the input was written in, deciding how body lines are unquoted and
+    /// whether Content-Length headers are honoured. See [`MboxParser::with_dialect`]. Babyl
+    /// files are always read as [`MboxDialect::Mboxrd`], as their lines are quoted that way.
+    pub dialect: MboxDialect,
+    /// Fail with [`MailfmtError::LeadingContent`] when the content before the first separator
+    /// is longer than a dummy line or looks like part of a message, instead of warning about it.
+    pub strict: bool,
+    /// Continue an interrupted extraction into the same output directory from the last
+    /// progress recorded inside of it. The input file must be unchanged.
+    pub resume: bool,
+    /// What to do with messages containing bytes that are not valid UTF-8.
+    pub invalid_utf8: InvalidUtf8,
+    /// Keep a byte-order mark at the start of the input instead of removing it. A kept mark
+    /// hides the first message's separator line, so the message is skipped.
+    pub keep_bom: bool,
+    /// Convert the text of single-part messages, and of the top-level parts of multipart
+    /// messages, from its declared charset to UTF-8. The invalid UTF-8 policy is applied to,
+    /// and [`ConversionSummary::invalid_utf8`] counts, what remains invalid afterwards.
+    pub transcode: bool,
+    /// When transcoding, take text without a declared charset to be UTF-8 if it is valid as
+    /// such, and Windows-1252 otherwise, instead of leaving it as it is.
+    pub transcode_detect: bool,
+    /// Replace the attachments of multipart messages with short text parts recording their
+    /// file name, media type, decoded size and SHA-256, keeping the messages valid MIME.
+    pub strip_attachments: bool,
+    /// When stripping attachments, also replace messages that are a single attachment, such
+    /// as a bare PDF, instead of leaving them as they are.
+    pub strip_single_part: bool,
+    /// How to group the eml files into subdirectories of the output directory. Grouping by
+    /// thread reads the whole mbox once before extracting, to find which messages reference
+    /// each other.
+    pub group_by: GroupBy,
+    /// How the eml files in subdirectories are numbered. Resuming with per-directory numbering
+    /// continues from the number of files already in each subdirectory.
+    pub numbering: Numbering,
+    /// When grouping by sender, the fewest messages a sender needs for a subdirectory of its
+    /// own. The messages of senders with fewer share a subdirectory named "other", so that
+    /// one-off senders do not each get one. Any value above 1 reads the whole mbox once before
+    /// extracting, to count the messages of every sender.
+    pub other_threshold: usize,
+    /// The Unicode normalization form applied to subjects before they are used in file names,
+    /// so that subjects that look the same give the same name.
+    pub filename_normalization: Normalization,
+    /// Transliterate subjects to ASCII before they are used in file names, naming messages
+    /// after their number only when nothing printable is left. See [`transliterate`].
+    pub ascii_filenames: bool,
+    /// Name the eml files after the file recorded in their
+    /// [`X-Source-File`](crate::headers::SOURCE_FILE_HEADER) header, sanitized and with the
+    /// extension being written, as recorded by [`EmlToMboxOptions::record_source`]. Messages
+    /// without one, or whose name was already given to another message, are named as usual.
+    /// Without it, the header is removed from the files written.
+    ///
+    /// [`EmlToMboxOptions::record_source`]: crate::EmlToMboxOptions::record_source
+    pub restore_names: bool,
+    /// What to name the eml files after. Naming them by UUID or hash replaces the numbers and
+    /// subjects of their names, and `restore_names` is not used.
+    pub name_by: NameBy,
+    /// The version of the UUIDs the eml files are named after with [`NameBy::Uuid`].
+    pub uuid_version: UuidVersion,
+    /// With [`NameBy::Hash`], keep only the first 16 hexadecimal digits of the hash. A file of
+    /// that name holding something else is a collision, and the message is named after its
+    /// full hash instead.
+    pub short_hash: bool,
+    /// Leave existing eml files that hold exactly what would be written in place, counting
+    /// their messages as skipped, rather than replacing them or asking. With
+    /// [`NameBy::Hash`], this writes each distinct message once, however many times it is
+    /// extracted into the same directory.
+    pub skip_identical: bool,
+    /// Read the eml files already in the output directory and its subdirectories before
+    /// extracting, and leave out every message found among them by this key, whatever its
+    /// file is named, counting it in [`ConversionSummary::already_present`]. This also allows
+    /// the output directory to exist. Only the header blocks of the files are read, reported
+    /// through [`Event::Scanning`] and [`Event::Scanned`]. A message that is not present but
+    /// whose file name is taken is left out with a warning unless `overwrite` is set.
+    pub skip_existing_by: Option<ExistingKey>,
+    /// How to compress the eml files written. Compressed files get `.gz` added to their names,
+    /// while embedded messages extracted with `explode_rfc822` are written as they are.
+    pub compress_output: Compression,
+    /// With `compress_output`, write messages smaller than this many bytes as they are, as
+    /// compressing them saves next to nothing.
+    pub compress_min_size: u64,
+    /// Remove reply and forward markers and mailing list tags from the start of subjects before
+    /// they are used in file names. See [`clean_subject`].
+    pub clean_subject: bool,
+    /// Write each message to a .txt file instead, holding its Date, From, To and Subject
+    /// headers, decoded, followed by its plain text as given by [`Message::text`], or the text
+    /// of its HTML part as `html_text` decides. Messages without either get a note listing the
+    /// types of their parts instead.
+    pub export_text: bool,
+    /// With `export_text`, when to take the text of a message from its HTML part, with tags
+    /// dropped, character references decoded, whitespace collapsed and links followed by
+    /// their target in parentheses.
+    pub html_text: HtmlText,
+    /// Write a `.meta.json` sidecar next to every eml file, right after it, holding the
+    /// envelope sender and date of its separator line, the path of the mbox file and the byte
+    /// offset of the message in it, its Message-ID, its Date header in RFC 3339 format and the
+    /// flags in its Status and X-Status headers. [`eml_to_mbox`](crate::eml_to_mbox) takes the
+    /// separator line from a sidecar when it finds one.
+    pub sidecar_metadata: bool,
+    /// Also write every message embedded in an extracted one as a message/rfc822 part, such
+    /// as those of a mailing list digest, to an eml file of its own next to it, decoded from
+    /// the transfer encoding of its part. The first message embedded in `0007_Subject.eml` is
+    /// written to `0007.1_Subject.eml`, and one embedded in that to `0007.1.1_Subject.eml`,
+    /// down to [`MAX_EMBEDDED_DEPTH`] levels. Each is recorded with the file it was found in,
+    /// in a manifest named [`EMBEDDED_MANIFEST_FILE_NAME`](crate::EMBEDDED_MANIFEST_FILE_NAME) in the output directory. Not used
+    /// with `export_text`.
+    pub explode_rfc822: bool,
+    /// Extract a uniformly random sample of this many messages instead of all of them, chosen
+    /// by reservoir sampling among those that are not left out as headerless, in a single pass
+    /// over the input. The chosen messages are kept in memory until the input has been read,
+    /// and are then extracted in their order in the mbox. Each file is recorded with the index
+    /// and byte offset of its message in a manifest named
+    /// [`SAMPLE_MANIFEST_FILE_NAME`](crate::SAMPLE_MANIFEST_FILE_NAME) in the output
+    /// directory. Messages that cannot be read are still reported. Cannot be resumed, and
+    /// `precount` is not used.
+    pub sample: Option<usize>,
+    /// The seed the sample is drawn with, which draws the same sample from the same input
+    /// again. A different one is used for every run when not set, and reported in
+    /// [`ConversionSummary::sampled`].
+    pub sample_seed: Option<u64>,
+    /// Only extract the messages at these positions in the mbox, counting every message
+    /// whether it can be read or not, before anything else leaves any out, such as
+    /// `headerless` or `sample`. Messages before the range are passed over without being
+    /// reported, and counted in [`ConversionSummary::out_of_range`], and reading stops at its
+    /// end. Their files are numbered after their position unless `renumber` is set. Cannot be
+    /// resumed.
+    pub range: Option<MessageRange>,
+    /// Only extract the messages of these mailing lists, matched case-insensitively against
+    /// the identifier in their List-Id header, the part between its angle brackets. An entry
+    /// in angle brackets itself, such as `<rust-users.rust-lang.org>`, has to match the whole
+    /// identifier, and any other entry only a part of it. Messages without a List-Id header
+    /// never match. The others are counted in [`ConversionSummary::off_list`], after any
+    /// `range` has been applied and before any `sample` is drawn. Empty to extract every
+    /// message.
+    pub list_ids: Vec<String>,
+    /// Only extract the messages in this read state, after their Status, X-Status and
+    /// X-Mozilla-Status headers. The others are counted in [`ConversionSummary::off_state`],
+    /// after any `list_ids` have been applied and before any `sample` is drawn. `None` to
+    /// extract every message.
+    pub read_state: Option<ReadState>,
+    /// The read state of messages that have none of the headers recording it.
+    pub no_status_means: ReadState,
+    /// Leave out the messages Thunderbird marked as deleted in their X-Mozilla-Status header,
+    /// which it keeps in the mbox until the folder is compacted. They are counted in
+    /// [`ConversionSummary::skipped_deleted`], after any `read_state` has been applied and before any
+    /// `sample` is drawn. Without it, they are extracted like any other, with an
+    /// [`Event::Warning`] telling how many there were after the last message.
+    pub skip_deleted: bool,
+    /// Number the files of a `range` from 0, or from where the numbering of the output
+    /// directory is when resuming, instead of after their position in the mbox.
+    pub renumber: bool,
+    /// Report every extracted message through [`Event::Converted`], with its sender, date and
+    /// file and the decisions made for it.
+    pub details: bool,
+    /// Track the Message-IDs of extracted messages and report those found on more than one in
+    /// [`ConversionSummary::duplicates`]. Every message is still extracted.
+    pub report_duplicates: bool,
+    /// Write an index of the messages extracted by this run to this file, mapping their
+    /// Message-IDs to their eml files, relative to the output directory. It is moved into
+    /// place once the extraction is over, including when it is cancelled. See
+    /// [`ConversionSummary::msgid_index`] for how many entries it holds.
+    ///
+    /// Every line holds a Message-ID without the whitespace and angle brackets around it, the
+    /// file and whether the ID is synthetic: messages without a Message-ID are keyed by
+    /// `sha256:` and the SHA-256 of their content, with lines ending in LF. A file name ending
+    /// in `.jsonl` gets a JSON object on every line with `message_id`, `file` and `synthetic`,
+    /// and any other tab-separated values under a header line.
+    pub msgid_index: Option<PathBuf>,
+    /// Set to stop the conversion after the message being extracted.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Asked before replacing every existing eml file when `overwrite` is not set, which also
+    /// allows the output directory to exist. Messages whose file is not replaced are counted as
+    /// skipped.
+    pub confirm_overwrite: Option<ConfirmHandler<'a>>,
+    /// Called with progress and per-message results as the conversion goes on.
+    pub on_event: Option<EventHandler<'a>>,
+}
+
+impl MboxToEmlOptions<'_> {
+    pub fn new(input_file: impl Into<PathBuf>, output_directory: impl Into<PathBuf>) -> Self {
+        Self {
+            input_file: input_file.into(),
+            output_directory: output_directory.into(),
+            input_format: InputFormat::default(),
+            overwrite: false,
+            precount: false,
+            fsync: false,
+            force: false,
+            space_check: true,
+            allow_empty: false,
+            headerless: Headerless::default(),
+            empty_messages: EmptyMessages::default(),
+            smart_separators: false,
+            dialect: MboxDialect::default(),
+            strict: false,
+            resume: false,
+            invalid_utf8: InvalidUtf8::default(),
+            keep_bom: false,
+            transcode: false,
+            transcode_detect: false,
+            strip_attachments: false,
+            strip_single_part: false,
+            group_by: GroupBy::default(),
+            numbering: Numbering::default(),
+            other_threshold: 1,
+            filename_normalization: Normalization::default(),
+            ascii_filenames: false,
+            restore_names: false,
+            name_by: NameBy::default(),
+            uuid_version: UuidVersion::default(),
+            short_hash: false,
+            skip_identical: false,
+            skip_existing_by: None,
+            compress_output: Compression::None,
+            compress_min_size: 0,
+            clean_subject: false,
+            export_text: false,
+            html_text: HtmlText::default(),
+            sidecar_metadata: false,
+            explode_rfc822: false,
+            sample: None,
+            sample_seed: None,
+            range: None,
+            renumber: false,
+            list_ids: Vec::new(),
+            read_state: None,
+            no_status_means: ReadState::Unseen,
+            skip_deleted: false,
+            details: false,
+            report_duplicates: false,
+            msgid_index: None,
+            cancel: None,
+            confirm_overwrite: None,
+            on_event: None,
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
 
-/// Convert a single .mbox file to an extracted directory of .eml files.
-#[derive(Parser)]
-pub struct ConvertToEmlCommand {
-    #[arg(value_parser = validate_output_file)]
-    input_file: PathBuf,
+    /// Report a message that could not be converted, returning it for the summary.
+    fn failed(&self, item: String, error: MailfmtError) -> Result<MessageError> {
+        self.emit(Event::Failed {
+            item: item.clone(),
+            error: &error,
+        })?;
+        Ok(MessageError { item, error })
+    }
+
+    /// Whether an existing eml file may be replaced, asking if replacing was not set up front.
+    fn confirm_overwrite(&self, path: &Path) -> Answer {
+        match (&self.confirm_overwrite, self.overwrite || self.resume) {
+            (_, true) | (None, false) => Answer::Yes,
+            (Some(confirm), false) => confirm(path),
+        }
+    }
 
-    output_directory: PathBuf,
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
 
-    /// Replace any existing eml files in the given directory with new ones if they overlap. Will not delete files that do not overlap.
-    #[clap(long = "overwrite")]
-    overwrite: bool,
+    /// A parser for the input, from a reader positioned at `offset` in it, finding messages
+    /// and unquoting their lines as these options say.
+    pub(crate) fn parser<'r, R: BufRead + 'r>(
+        &self,
+        reader: R,
+        offset: u64,
+    ) -> MboxParser<Box<dyn Iterator<Item = io::Result<OffsetLine>> + 'r>> {
+        let dialect = match self.input_format {
+            InputFormat::Mbox => self.dialect,
+            InputFormat::Babyl => MboxDialect::Mboxrd,
+        };
+        MboxParser::new(input_lines(
+            reader,
+            offset,
+            self.keep_bom,
+            self.input_format,
+        ))
+        .with_smart_separators(self.smart_separators)
+        .with_dialect(dialect)
+    }
 }
 
-impl ConvertToEmlCommand {
-    pub fn run(&self) -> Result<()> {
-        Self::mbox_to_eml(&self.input_file, &self.output_directory, self.overwrite)
+/// Extract every message of an mbox file into its own eml file.
+///
+/// Files are named after the message number and subject. Messages that cannot be read or
+/// saved are reported through [`Event::Failed`] and counted in the summary without stopping
+/// the conversion. A cancelled conversion records its progress in the output directory so that
+/// it can be continued with [`MboxToEmlOptions::resume`].
+///
+/// With [`MboxToEmlOptions::transcode`], text is converted to UTF-8 from its declared charset:
+///
+/// ```
+/// use mailfmt::{MboxToEmlOptions, mbox_to_eml};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-transcode-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// fs::write(
+///     dir.join("in.mbox"),
+///     b"From a Mon Jan 01 00:00:00 2024\n\
+///       Content-Type: text/plain; charset=iso-8859-1\n\
+///       Content-Transfer-Encoding: quoted-printable\n\
+///       \n\
+///       caf=E9 cr\xe8me\n\
+///       \n\
+///       From b Mon Jan 01 00:00:00 2024\n\
+///       Content-Type: text/plain; charset=x-unknown\n\
+///       \n\
+///       \xff\n",
+/// )?;
+///
+/// let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), dir.join("out"));
+/// options.transcode = true;
+/// let summary = mbox_to_eml(&options)?;
+/// assert_eq!((summary.transcoded, summary.not_transcoded, summary.invalid_utf8), (1, 1, 1));
+/// assert_eq!(
+///     fs::read_to_string(dir.join("out/0000.eml"))?,
+///     "Content-Type: text/plain; charset=utf-8\n\
+///      Content-Transfer-Encoding: 8bit\n\
+///      \n\
+///      café crème\n\
+///      \n"
+/// );
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn mbox_to_eml(options: &MboxToEmlOptions<'_>) -> Result<ConversionSummary> {
+    let _span = info_span!("mbox_to_eml", input = %options.input_file.display()).entered();
+    let start = Instant::now();
+    let (input_file, output_dir) = (
+        options.input_file.as_path(),
+        options.output_directory.as_path(),
+    );
+    if input_file.exists()
+        && is_blank(input_file, options.keep_bom).io_context("read mbox file", input_file)?
+    {
+        if !options.allow_empty {
+            return Err(MailfmtError::EmptyInput {
+                path: input_file.to_path_buf(),
+            });
+        }
+        return Ok(ConversionSummary {
+            empty_input: true,
+            elapsed: start.elapsed(),
+            ..ConversionSummary::default()
+        });
+    }
+    check_input(options)?;
+    check_output_directory(output_dir)?;
+    if output_dir.exists()
+        && !options.overwrite
+        && !options.resume
+        && options.skip_existing_by.is_none()
+        && options.confirm_overwrite.is_none()
+    {
+        return Err(MailfmtError::OutputDirectoryExists {
+            path: output_dir.to_path_buf(),
+        });
+    }
+
+    let input_size =
+        gzip::content_size(input_file).io_context("read metadata of mbox file", input_file)?;
+    let resumed = resume_state(options, input_size)?;
+    let (start_offset, start_index) = resumed
+        .as_ref()
+        .map_or((0, 0), |state| (state.offset, state.next_index));
+    if options.space_check {
+        space::check(
+            output_dir,
+            input_size.saturating_sub(start_offset),
+            options.force,
+            |event| options.emit(event),
+        )?;
     }
 
-    fn get_header_value_from_lines<'a>(lines: &'a [String], header_name: &str) -> Option<&'a str> {
-        let prefix = format!("{}:", header_name.to_lowercase());
-        lines
+    let mut extraction = Extraction::new(options, start_index, resumed.is_some())?;
+    let file = gzip::open(input_file, start_offset).io_context("open mbox file", input_file)?;
+    let reader = CountingReader::new(file);
+    let bytes_read = reader.counter();
+    let mut reader = BufReader::new(reader);
+    let starts_message = match options.input_format {
+        InputFormat::Mbox => is_separator,
+        InputFormat::Babyl => babyl::starts_message,
+    };
+    if resumed.is_some()
+        && !starts_message(reader.fill_buf().io_context("read mbox file", input_file)?)
+    {
+        return Err(MailfmtError::ResumeMisaligned {
+            input: input_file.to_path_buf(),
+            offset: start_offset,
+        });
+    }
+
+    let earlier = resumed
+        .as_ref()
+        .map_or(0, |state| state.converted + state.errors) as u64;
+    extraction.earlier = earlier as usize;
+    let mut parser = options
+        .parser(reader, start_offset)
+        // Transcoding needs the bytes as they were, so the policy is applied after it.
+        .with_invalid_utf8(match options.transcode {
+            true => InvalidUtf8::Preserve,
+            false => options.invalid_utf8,
+        });
+    let sample_seed = options.sample_seed.unwrap_or_else(random_seed);
+    let range_end = options.range.and_then(|range| range.end);
+    let sample = options.sample.map(|size| {
+        let start = Instant::now();
+        let sample = draw_sample(&mut parser, size, sample_seed, options);
+        (sample, start.elapsed())
+    });
+    let (total, start_position) = match (&sample, options.precount, input_size) {
+        (Some((sample, elapsed)), _, _) => {
+            options.emit(Event::Counted {
+                messages: sample.read as u64,
+                elapsed: *elapsed,
+            })?;
+            (ProgressTotal::Messages(sample.messages.len() as u64), 0)
+        }
+        (None, true, _) => {
+            let start = Instant::now();
+            let reader =
+                BufReader::new(gzip::open(input_file, 0).io_context("open mbox file", input_file)?);
+            let count = match options.input_format {
+                // Only the parser can tell which "From " lines start a message.
+                InputFormat::Mbox
+                    if options.smart_separators || options.dialect.content_length() =>
+                {
+                    options.parser(reader, 0).count() as u64
+                }
+                InputFormat::Mbox => count_messages(reader, options.keep_bom)
+                    .io_context("count messages in mbox file", input_file)?,
+                InputFormat::Babyl => babyl::count_messages(reader, options.keep_bom)
+                    .io_context("count messages in mbox file", input_file)?,
+            };
+            options.emit(Event::Counted {
+                messages: count,
+                elapsed: start.elapsed(),
+            })?;
+            let count = match range_end {
+                Some(end) => count.min(end as u64),
+                None => count,
+            };
+            (ProgressTotal::Messages(count), earlier)
+        }
+        (None, false, 0) => (ProgressTotal::Unknown, 0),
+        (None, false, file_size) => (ProgressTotal::Bytes(file_size), start_offset),
+    };
+    options.emit(Event::Started {
+        total,
+        position: start_position,
+    })?;
+    if options.name_by != NameBy::Index
+        && options.msgid_index.is_none()
+        && !options.sidecar_metadata
+    {
+        options.emit(Event::Warning(format!(
+            "The eml files are named by {}, but neither a Message-ID index nor sidecar metadata is written to map them back to their emails",
+            match options.name_by {
+                NameBy::Uuid => "UUID",
+                _ => "hash",
+            }
+        )))?;
+    }
+    let population = sample.as_ref().map(|(sample, _)| sample.population);
+    // Those passed over while drawing the sample are not part of its progress.
+    let sampled_out_of_range = sample.as_ref().map_or(0, |(sample, _)| sample.before_range);
+    let sampled_not_on_list = sample.as_ref().map_or(0, |(sample, _)| sample.off_list);
+    let sampled_not_in_state = sample.as_ref().map_or(0, |(sample, _)| sample.off_state);
+    let sampled_deleted = sample.as_ref().map_or(0, |(sample, _)| sample.deleted);
+    let mut sample = sample.map(|(sample, _)| sample.messages.into_iter());
+    if sample.is_some() {
+        extraction.sample_manifest = Some(SampleManifest::create(output_dir)?);
+    }
+    // The position in the mbox of the next message read, or drawn for the sample.
+    let mut next_position = earlier as usize;
+    // Only the start of the file can have content before a separator.
+    let mut leading_checked = start_offset != 0;
+    let mut interrupted = false;
+    // Where to resume from when stopped by an answer, as the message asked about is unwritten.
+    let mut quit_at = None;
+    let checkpoint = |offset: Option<u64>, counts: &Counts| {
+        save_checkpoint(
+            options,
+            offset,
+            input_size,
+            start_index + counts.converted + counts.skipped,
+            resumed.as_ref(),
+            (counts.converted, counts.errors.len()),
+        )
+    };
+
+    loop {
+        if options.is_cancelled() {
+            interrupted = true;
+            break;
+        }
+        if sample.is_none() && range_end.is_some_and(|end| next_position >= end) {
+            break;
+        }
+        let message_start = Instant::now();
+        let email_result = match &mut sample {
+            Some(messages) => messages.next().map(|(position, result)| {
+                next_position = position;
+                result
+            }),
+            None => parser.next(),
+        };
+        if !leading_checked {
+            leading_checked = true;
+            check_leading_content(options, parser.leading())?;
+        }
+        let Some(email_result) = email_result else {
+            break;
+        };
+        let position_in_mbox = next_position;
+        next_position += 1;
+        if let Some(offset) = extraction.extract(email_result, position_in_mbox, message_start)? {
+            quit_at = Some(offset);
+            interrupted = true;
+            break;
+        }
+
+        let counts = &extraction.counts;
+        let processed = counts.processed();
+        let position = match total {
+            ProgressTotal::Bytes(_) => start_offset + bytes_read.load(Ordering::Relaxed),
+            ProgressTotal::Messages(_) => earlier + processed as u64,
+            ProgressTotal::Unknown => 0,
+        };
+        options.emit(Event::Progress {
+            position,
+            processed,
+        })?;
+        if processed % RESUME_CHECKPOINT_INTERVAL == 0 {
+            checkpoint(parser.position(), counts)?;
+        }
+    }
+
+    let Extraction {
+        counts,
+        subdirectories,
+        duplicates,
+        msgid_index,
+        sample_manifest,
+        embedded_manifest,
+        ..
+    } = extraction;
+    if counts.deleted_kept > 0 {
+        options.emit(Event::Warning(format!(
+            "{} of the messages were marked as deleted by Thunderbird and were extracted anyway",
+            counts.deleted_kept
+        )))?;
+    }
+    if interrupted {
+        let offset = quit_at.or_else(|| parser.position());
+        checkpoint(offset, &counts)?;
+    } else {
+        ResumeState::remove(output_dir)?;
+    }
+    // The manifest is kept when interrupted, as it describes the files that were written.
+    if let Some(manifest) = embedded_manifest {
+        manifest.finish()?;
+    }
+    if let Some(manifest) = sample_manifest {
+        manifest.finish()?;
+    }
+    if options.fsync {
+        for directory in subdirectories
             .iter()
-            .find(|line| line.to_lowercase().starts_with(&prefix))
-            .map(|line| line[prefix.len()..].trim())
+            .map(PathBuf::as_path)
+            .chain([output_dir])
+        {
+            sync_directory(directory).io_context("sync output directory", directory)?;
+        }
+    }
+
+    Ok(ConversionSummary {
+        converted: counts.converted,
+        skipped: counts.skipped,
+        errors: counts.errors,
+        invalid_utf8: counts.invalid_utf8,
+        transcoded: counts.transcoded,
+        not_transcoded: counts.not_transcoded,
+        interrupted,
+        bytes_written: counts.bytes_written,
+        bytes_read: bytes_read.load(Ordering::Relaxed),
+        elapsed: start.elapsed(),
+        stripped: counts.stripped.attachments,
+        stripped_bytes: counts.stripped.bytes,
+        headerless: counts.headerless,
+        empty: counts.empty,
+        embedded: counts.embedded,
+        compressed: counts.compressed,
+        already_present: counts.already_present,
+        from_html: counts.from_html,
+        without_text: counts.without_text,
+        duplicates: duplicates.map(Duplicates::into_groups).unwrap_or_default(),
+        msgid_index: msgid_index.map(IndexWriter::finish).transpose()?,
+        out_of_range: counts.out_of_range + sampled_out_of_range,
+        off_list: counts.off_list + sampled_not_on_list,
+        off_state: counts.off_state + sampled_not_in_state,
+        skipped_deleted: counts.deleted + sampled_deleted,
+        lists: counts.lists,
+        sampled: population.map(|population| Sampled {
+            seed: sample_seed,
+            population,
+        }),
+        resumed: resumed.map(|state| Resumed {
+            converted: state.converted,
+            errors: state.errors,
+        }),
+        ..ConversionSummary::default()
+    })
+}
+
+/// The state to continue an earlier extraction from with [`MboxToEmlOptions::resume`], checked
+/// against the input it was recorded for.
+fn resume_state(options: &MboxToEmlOptions<'_>, input_size: u64) -> Result<Option<ResumeState>> {
+    let (input_file, output_dir) = (
+        options.input_file.as_path(),
+        options.output_directory.as_path(),
+    );
+    if !options.resume {
+        return Ok(None);
+    }
+    if options.sample.is_some() {
+        return Err(MailfmtError::SampleNotResumable);
+    }
+    if options.range.is_some() {
+        return Err(MailfmtError::RangeNotResumable);
+    }
+    let Some(state) = ResumeState::load(output_dir)? else {
+        return Err(MailfmtError::NothingToResume {
+            output: output_dir.to_path_buf(),
+        });
+    };
+    let sample = ResumeState::sample_hash(input_file, state.offset)
+        .io_context("read mbox file", input_file)?;
+    if state.input_size != input_size || state.sample_sha256 != sample {
+        return Err(MailfmtError::InputChanged {
+            input: input_file.to_path_buf(),
+            output: output_dir.to_path_buf(),
+        });
     }
+    Ok(Some(state))
+}
+
+/// What an extraction has counted so far.
+#[derive(Default)]
+struct Counts {
+    converted: usize,
+    errors: Vec<MessageError>,
+    bytes_written: u64,
+    invalid_utf8: usize,
+    transcoded: usize,
+    not_transcoded: usize,
+    stripped: Stripped,
+    /// Messages whose existing file was not replaced still use up their index, as do those
+    /// already present in the output directory.
+    skipped: usize,
+    already_present: usize,
+    /// Messages without headers that were left out do not, nor do empty ones.
+    headerless: usize,
+    empty: usize,
+    /// Messages embedded in the extracted ones, written to files of their own.
+    embedded: usize,
+    compressed: usize,
+    from_html: usize,
+    without_text: usize,
+    /// Messages before the range, which were passed over.
+    out_of_range: usize,
+    /// Messages of other mailing lists, and the extracted ones of every list.
+    off_list: usize,
+    lists: BTreeMap<String, usize>,
+    /// Messages in the other read state than the one asked for.
+    off_state: usize,
+    /// Messages marked as deleted, which were left out or, when they are not skipped, extracted.
+    deleted: usize,
+    deleted_kept: usize,
+}
+
+impl Counts {
+    /// How many messages were read, whatever became of them.
+    fn processed(&self) -> usize {
+        self.converted
+            + self.skipped
+            + self.headerless
+            + self.empty
+            + self.errors.len()
+            + self.out_of_range
+            + self.off_list
+            + self.off_state
+            + self.deleted
+    }
+}
+
+/// An extraction in progress, carrying what the messages read so far have left behind. Each
+/// message goes through the stages of [`Extraction::extract`] in turn.
+struct Extraction<'a, 'o> {
+    options: &'a MboxToEmlOptions<'o>,
+    /// The index of the first file written by this run, and whether it continues another.
+    start_index: usize,
+    resuming: bool,
+    /// The messages read by the runs this one continues.
+    earlier: usize,
+    threads: Option<HashMap<u64, String>>,
+    senders: Option<HashMap<String, usize>>,
+    existing: Option<ExistingMessages>,
+    /// Sidecars name the mbox file by its absolute path, so that it can be found from anywhere.
+    sidecar_source: Option<PathBuf>,
+    /// Every subdirectory created, including the year directories of a date layout.
+    subdirectories: HashSet<PathBuf>,
+    /// The number of files in each subdirectory holding messages, for per-directory numbering.
+    directory_files: HashMap<PathBuf, usize>,
+    /// The files named after their X-Source-File header, so that no two messages get the same.
+    restored_names: HashSet<PathBuf>,
+    /// The version of the UUIDs files are named after, if they are.
+    uuids: Option<UuidVersion>,
+    duplicates: Option<Duplicates>,
+    msgid_index: Option<IndexWriter>,
+    sample_manifest: Option<SampleManifest>,
+    embedded_manifest: Option<EmbeddedManifest>,
+    counts: Counts,
+}
+
+/// Where and what the name stage decided to write for a message.
+struct Target<'m> {
+    directory: PathBuf,
+    file_index: usize,
+    filepath: PathBuf,
+    content: Cow<'m, [Vec<u8>]>,
+    final_newline: bool,
+    compressed: bool,
+}
 
-    fn mbox_to_eml(input_file: &Path, output_dir: &Path, overwrite: bool) -> Result<()> {
-        if !input_file.exists() {
-            bail!("Mbox file at {:?} does not exist", input_file);
+impl<'a, 'o> Extraction<'a, 'o> {
+    /// Read what grouping and skipping need from the input and output up front, and create the
+    /// output directory.
+    fn new(options: &'a MboxToEmlOptions<'o>, start_index: usize, resuming: bool) -> Result<Self> {
+        let (input_file, output_dir) = (
+            options.input_file.as_path(),
+            options.output_directory.as_path(),
+        );
+        let threads = match options.group_by {
+            GroupBy::Thread => Some(thread_directories(options)?),
+            _ => None,
+        };
+        let senders = match options.group_by {
+            GroupBy::Sender | GroupBy::SenderDomain if options.other_threshold > 1 => Some(
+                sender_counts(options, options.group_by == GroupBy::SenderDomain)?,
+            ),
+            _ => None,
+        };
+        let existing = match options.skip_existing_by {
+            Some(ExistingKey::MessageId) if output_dir.exists() => Some(ExistingMessages::scan(
+                output_dir,
+                options.keep_bom,
+                |event| options.emit(event),
+            )?),
+            _ => None,
+        };
+        let sidecar_source = options
+            .sidecar_metadata
+            .then(|| paths::resolve(input_file).unwrap_or_else(|_| input_file.to_path_buf()));
+        create_output_directory(output_dir, "create output directory")?;
+        let msgid_index = options
+            .msgid_index
+            .as_deref()
+            .map(|path| IndexWriter::create(path, false))
+            .transpose()?;
+        let embedded_manifest = (options.explode_rfc822 && !options.export_text)
+            .then(|| EmbeddedManifest::create(output_dir))
+            .transpose()?;
+        Ok(Self {
+            options,
+            start_index,
+            resuming,
+            earlier: 0,
+            threads,
+            senders,
+            existing,
+            sidecar_source,
+            subdirectories: HashSet::new(),
+            directory_files: HashMap::new(),
+            restored_names: HashSet::new(),
+            uuids: (options.name_by == NameBy::Uuid).then_some(options.uuid_version),
+            duplicates: options.report_duplicates.then(Duplicates::default),
+            msgid_index,
+            sample_manifest: None,
+            embedded_manifest,
+            counts: Counts::default(),
+        })
+    }
+
+    /// The index of the next file written, counting from the first of the runs continued.
+    fn next_index(&self) -> usize {
+        self.start_index + self.counts.converted + self.counts.skipped
+    }
+
+    /// Take a message read from the mbox, at `position` in it, through the filter, prepare,
+    /// name and write stages. Returns the offset to resume from when asked to stop.
+    fn extract(
+        &mut self,
+        read: Result<Message>,
+        position: usize,
+        message_start: Instant,
+    ) -> Result<Option<u64>> {
+        let _span = info_span!("message", position).entered();
+        let Some(email) = self.filter(read, position)? else {
+            return Ok(None);
+        };
+        // Decisions made for the message, only kept when details are reported.
+        let mut notes = Vec::new();
+        let Some(email) = self.prepare(email, &mut notes)? else {
+            return Ok(None);
+        };
+        let target = self.name(&email, position, &mut notes)?;
+        self.write(&email, target, position, message_start, notes)
+    }
+
+    /// The filter stage: count the messages that are left out or could not be read, passing on
+    /// the others.
+    fn filter(&mut self, read: Result<Message>, position: usize) -> Result<Option<Message>> {
+        let options = self.options;
+        let counts = &mut self.counts;
+        if position < options.range.unwrap_or_default().start {
+            counts.out_of_range += 1;
+            return Ok(None);
         }
-        if output_dir.exists() && !overwrite {
-            bail!(
-                "Directory already exists at {:?}. Use the --overwrite flag to replace overlapping files inside of it.",
-                output_dir
-            );
+        let email = match read {
+            Ok(email) => email,
+            Err(error) => {
+                self.read_failed(error)?;
+                return Ok(None);
+            }
+        };
+        if !email.on_list(&options.list_ids) {
+            counts.off_list += 1;
+            return Ok(None);
+        }
+        if !email.in_read_state(options) {
+            counts.off_state += 1;
+            return Ok(None);
+        }
+        if email.is_deleted() {
+            match options.skip_deleted {
+                true => {
+                    counts.deleted += 1;
+                    return Ok(None);
+                }
+                false => counts.deleted_kept += 1,
+            }
         }
+        let subject = email.header("subject").map(decode_encoded_words);
+        options.emit(Event::Processing(subject.as_deref().unwrap_or_default()))?;
 
-        fs::create_dir_all(output_dir)
-            .with_context(|| format!("failed to create output directory at {output_dir:?}"))?;
+        if self
+            .existing
+            .as_ref()
+            .is_some_and(|existing| existing.contains(email.header("message-id")))
+        {
+            counts.already_present += 1;
+            counts.skipped += 1;
+            return Ok(None);
+        }
+        if options.empty_messages != EmptyMessages::Keep && email.is_empty() {
+            match options.empty_messages {
+                EmptyMessages::Skip => counts.empty += 1,
+                _ => counts.errors.push(options.failed(
+                    format!("reading email {}", counts.converted),
+                    MailfmtError::EmptyMessage {
+                        index: position,
+                        offset: email.offset,
+                    },
+                )?),
+            }
+            return Ok(None);
+        }
+        if options.headerless != Headerless::Keep && !email.has_headers() {
+            let offset = email.offset;
+            match options.headerless {
+                Headerless::Skip => {
+                    counts.headerless += 1;
+                    options.emit(Event::Warning(format!(
+                        "Skipped message {position} at byte {offset} of the mbox file, as it has no header block"
+                    )))?;
+                }
+                _ => counts.errors.push(options.failed(
+                    format!("reading email {}", counts.converted),
+                    MailfmtError::Headerless {
+                        index: position,
+                        offset,
+                    },
+                )?),
+            }
+            return Ok(None);
+        }
+        Ok(Some(email))
+    }
 
-        let (converted, errors) = {
-            let reader = BufReader::new(
-                File::open(input_file)
-                    .with_context(|| format!("failed to open mbox file at {input_file:?}"))?,
-            );
+    /// Count a message that could not be read.
+    fn read_failed(&mut self, error: MailfmtError) -> Result<()> {
+        // The parser counts messages from where this run started reading.
+        let earlier = self.earlier;
+        let error = match error {
+            MailfmtError::MessageParse { index, source } => MailfmtError::MessageParse {
+                index: earlier + index,
+                source,
+            },
+            MailfmtError::InvalidUtf8 { index, offset } => {
+                self.counts.invalid_utf8 += 1;
+                MailfmtError::InvalidUtf8 {
+                    index: earlier + index,
+                    offset,
+                }
+            }
+            error => error,
+        };
+        let item = format!("reading email {}", self.counts.converted);
+        self.counts.errors.push(self.options.failed(item, error)?);
+        Ok(())
+    }
 
-            let pb = ProgressBar::new_spinner();
-            pb.set_style(
-                ProgressStyle::default_spinner()
-                    .template("[{elapsed_precise}] {spinner} {human_pos} emails processed {msg}")
-                    .unwrap(),
-            );
-            pb.enable_steady_tick(Duration::from_millis(100));
-
-            let mut parser = MboxParser::new(reader.lines());
-            let (mut converted, mut errors) = (0, 0);
-
-            while let Some(email_result) = parser.next_message() {
-                match email_result {
-                    Ok(email) => {
-                        let subject = Self::get_header_value_from_lines(&email, "subject")
-                            .filter(|s| !s.is_empty())
-                            .map(sanitize_filename::sanitize);
-                        match Self::save_eml_file(output_dir, converted, subject, &email) {
-                            Ok(()) => converted += 1,
-                            Err(e) => {
-                                pb.println(format!("Error saving email {}: {}", converted, e));
-                                errors += 1;
-                            }
-                        }
+    /// The prepare stage: transcode the text of a message and strip its attachments, as asked.
+    fn prepare(&mut self, mut email: Message, notes: &mut Vec<String>) -> Result<Option<Message>> {
+        let options = self.options;
+        if options.transcode {
+            match transcode_message(&mut email.lines, options.transcode_detect) {
+                Transcoded::Converted => {
+                    self.counts.transcoded += 1;
+                    notes.push("text transcoded to UTF-8".to_string());
+                }
+                Transcoded::Untouched => {
+                    self.counts.not_transcoded += 1;
+                    notes.push(
+                        "text left as it is, as its charset is unknown or not declared".to_string(),
+                    );
+                }
+                Transcoded::Unchanged => {}
+            }
+            let index = self.counts.converted + self.counts.errors.len();
+            if let Err(error) = apply_invalid_utf8(&mut email, options.invalid_utf8, index) {
+                self.read_failed(error)?;
+                return Ok(None);
+            }
+        }
+        if !email.has_headers() {
+            notes.push("no header block, extracted as it is".to_string());
+        }
+        if let Some(offset) = email.invalid_utf8 {
+            self.counts.invalid_utf8 += 1;
+            notes.push(format!(
+                "bytes that are not valid UTF-8 from offset {offset} {}",
+                match options.invalid_utf8 {
+                    InvalidUtf8::Lossy => "replaced with U+FFFD",
+                    _ => "kept as they are",
+                }
+            ));
+        }
+        if options.strip_attachments {
+            let stripped = strip_attachments(&mut email.lines, options.strip_single_part);
+            self.counts.stripped.attachments += stripped.attachments;
+            self.counts.stripped.bytes += stripped.bytes;
+            if stripped.attachments > 0 {
+                notes.push(format!(
+                    "{} attachments stripped, saving {} bytes",
+                    stripped.attachments, stripped.bytes
+                ));
+            }
+        }
+        Ok(Some(email))
+    }
+
+    /// The name stage: decide which directory and file a message goes to, and what is written.
+    fn name<'m>(
+        &mut self,
+        email: &'m Message,
+        position: usize,
+        notes: &mut Vec<String>,
+    ) -> Result<Target<'m>> {
+        let options = self.options;
+        let decoded_subject = email.header("subject").filter(|s| !s.is_empty());
+        let subject = file_subject(decoded_subject, options);
+        match (decoded_subject, &subject) {
+            (None, _) => notes.push("no subject, so the file is named after its number only".to_string()),
+            (Some(_), None) => notes.push(
+                "nothing of the subject is left in ASCII, so the file is named after its number only"
+                    .to_string(),
+            ),
+            (Some(_), Some(_)) => {}
+        }
+        let directory = self.directory(email, notes)?;
+        let file_index = match (options.numbering, self.directory_files.get(&directory)) {
+            (Numbering::PerDirectory, Some(&files)) => files,
+            _ if options.range.is_some() && !options.renumber => position,
+            _ => self.next_index(),
+        };
+
+        let extension = if options.export_text { "txt" } else { "eml" };
+        let content = match options.export_text {
+            true => Cow::Owned(self.exported_text(email, position, notes)?),
+            false => Cow::Borrowed(email.lines.as_slice()),
+        };
+        // Text exports are written as a whole, ending in a line break of their own.
+        let final_newline = email.final_newline || options.export_text;
+        // A recorded source file is only kept when naming files after it.
+        let content = match options.restore_names {
+            false => match without_header(&content, SOURCE_FILE_HEADER) {
+                Some(lines) => {
+                    notes.push(format!("{SOURCE_FILE_HEADER} header removed"));
+                    Cow::Owned(lines)
+                }
+                None => content,
+            },
+            true => content,
+        };
+        let compressed = options.compress_output == Compression::Gzip
+            && written_size(&content, final_newline) >= options.compress_min_size;
+        let extension: Cow<str> = match compressed {
+            true => {
+                notes.push("compressed with gzip".to_string());
+                format!("{extension}.gz").into()
+            }
+            false => extension.into(),
+        };
+        let restored = (options.restore_names && options.name_by == NameBy::Index)
+            .then(|| source_file_name(email, &extension))
+            .flatten()
+            .map(|name| directory.join(name));
+        let filepath = match (self.uuids, restored) {
+            (Some(version), _) => {
+                notes.push("named by UUID".to_string());
+                directory.join(format!("{}.{extension}", version.generate()))
+            }
+            (None, _) if options.name_by == NameBy::Hash => {
+                let hash = written_hash(&content, final_newline);
+                let short = directory.join(format!("{}.{extension}", &hash[..16]));
+                match options.short_hash {
+                    true if !short.exists() || holds_content(&short, &content, final_newline) => {
+                        notes.push("named by hash".to_string());
+                        short
                     }
-                    Err(e) => {
-                        pb.println(format!("Error reading email {}: {}", converted, e));
-                        errors += 1;
+                    true => {
+                        notes.push(format!(
+                            "{} holds another message, so it is named by its full hash",
+                            short.display()
+                        ));
+                        directory.join(format!("{hash}.{extension}"))
                     }
+                    false => {
+                        notes.push("named by hash".to_string());
+                        directory.join(format!("{hash}.{extension}"))
+                    }
+                }
+            }
+            (None, Some(filepath)) if self.restored_names.insert(filepath.clone()) => {
+                notes.push("named after its X-Source-File header".to_string());
+                filepath
+            }
+            (None, restored) => {
+                if restored.is_some() {
+                    notes.push(
+                        "the name in its X-Source-File header was already used, so it is named as usual"
+                            .to_string(),
+                    );
                 }
-                pb.inc(1);
+                directory.join(eml_file_name(file_index, subject, &extension))
             }
+        };
+        Ok(Target {
+            directory,
+            file_index,
+            filepath,
+            content,
+            final_newline,
+            compressed,
+        })
+    }
 
-            pb.finish_and_clear();
-            (converted, errors)
+    /// The directory a message is grouped into, created the first time it is used.
+    fn directory(&mut self, email: &Message, notes: &mut Vec<String>) -> Result<PathBuf> {
+        let options = self.options;
+        let output_dir = options.output_directory.as_path();
+        let subdirectory = match options.group_by {
+            GroupBy::None => None,
+            GroupBy::Thread => self
+                .threads
+                .as_ref()
+                .and_then(|threads| threads.get(&email.offset))
+                .map(|thread| {
+                    notes.push(format!("grouped with its thread in {thread:?}"));
+                    PathBuf::from(thread)
+                }),
+            GroupBy::Date => {
+                let directory = date_directory(email.date().map(|(date, _)| date));
+                notes.push(format!("grouped by date in {:?}", directory.display()));
+                Some(directory)
+            }
+            GroupBy::Sender | GroupBy::SenderDomain => {
+                let directory = sender_directory(
+                    sender_key(
+                        email.header("from"),
+                        options.group_by == GroupBy::SenderDomain,
+                    ),
+                    self.senders.as_ref(),
+                    options.other_threshold,
+                );
+                notes.push(format!("grouped by sender in {:?}", directory.display()));
+                Some(directory)
+            }
+            GroupBy::Folder => {
+                let entity = Entity::parse(&email.lines);
+                let directory = entity
+                    .fields
+                    .iter()
+                    .find(|field| field.is(FOLDER_HEADER))
+                    .and_then(|field| folder_directory(&field.value));
+                if let Some(directory) = &directory {
+                    notes.push(format!("restored to its folder {:?}", directory.display()));
+                }
+                directory
+            }
+        };
+        let Some(subdirectory) = subdirectory else {
+            return Ok(output_dir.to_path_buf());
         };
+        let directory = output_dir.join(subdirectory);
+        if !self.directory_files.contains_key(&directory) {
+            create_output_directory(&directory, "create subdirectory")?;
+            self.subdirectories.extend(
+                directory
+                    .ancestors()
+                    .take_while(|parent| *parent != output_dir)
+                    .map(Path::to_path_buf),
+            );
+            let files = match (options.numbering, self.resuming) {
+                (Numbering::PerDirectory, true) => {
+                    count_numbered_files(&directory).io_context("read subdirectory", &directory)?
+                }
+                _ => 0,
+            };
+            self.directory_files.insert(directory.clone(), files);
+        }
+        Ok(directory)
+    }
+
+    /// The text of a message exported with [`MboxToEmlOptions::export_text`].
+    fn exported_text(
+        &mut self,
+        email: &Message,
+        position: usize,
+        notes: &mut Vec<String>,
+    ) -> Result<Vec<Vec<u8>>> {
+        let exported = export_text(email, position, self.options.html_text);
+        if let Some(warning) = exported.warning {
+            self.options.emit(Event::Warning(warning))?;
+        }
+        match exported.source {
+            TextSource::Plain => {}
+            TextSource::Html => {
+                notes.push("text taken from its HTML part".to_string());
+                self.counts.from_html += 1;
+            }
+            TextSource::Placeholder => {
+                notes.push("no text to export, so a placeholder is written".to_string());
+                self.counts.without_text += 1;
+            }
+        }
+        Ok(exported.lines)
+    }
 
-        println!(
-            "Conversion of {} emails completed with {} errors. Output saved to {:?}",
-            converted, errors, output_dir
+    /// The write stage: save a message to the file decided on, unless an existing file is to
+    /// be kept, and record it. Returns the offset to resume from when asked to stop.
+    fn write(
+        &mut self,
+        email: &Message,
+        target: Target<'_>,
+        position: usize,
+        message_start: Instant,
+        mut notes: Vec<String>,
+    ) -> Result<Option<u64>> {
+        let options = self.options;
+        let (input_file, output_dir) = (
+            options.input_file.as_path(),
+            options.output_directory.as_path(),
         );
+        let Target {
+            directory,
+            file_index,
+            filepath,
+            content,
+            final_newline,
+            compressed,
+        } = target;
+        if paths::same_file(&filepath, input_file) {
+            return Err(MailfmtError::OutputIsInput {
+                output: filepath,
+                input: input_file.to_path_buf(),
+            });
+        }
+        let exists = paths::extended_length(&filepath).exists();
+        let answer = match exists {
+            true if options.skip_identical && holds_content(&filepath, &content, final_newline) => {
+                Answer::No
+            }
+            true if options.skip_existing_by.is_some()
+                && !options.overwrite
+                && !options.resume
+                && options.confirm_overwrite.is_none() =>
+            {
+                options.emit(Event::Warning(format!(
+                    "Left out message {position}, as {filepath:?} already exists and holds another message"
+                )))?;
+                Answer::No
+            }
+            true => options.confirm_overwrite(&filepath),
+            false => Answer::Yes,
+        };
+        match answer {
+            Answer::Yes => {}
+            Answer::No => {
+                self.counts.skipped += 1;
+                if let Some(files) = self.directory_files.get_mut(&directory) {
+                    *files += 1;
+                }
+                return Ok(None);
+            }
+            Answer::Quit => return Ok(Some(email.offset)),
+        }
+        if exists {
+            notes.push("replaced an existing file".to_string());
+        }
 
-        Ok(())
+        let saved = match compressed {
+            true => save_compressed_eml_file(&filepath, &content, final_newline, options.fsync),
+            false => save_eml_file(&filepath, &content, final_newline, options.fsync),
+        };
+        let written = match saved.and_then(|written| match &self.sidecar_source {
+            Some(source) => {
+                let sidecar = sidecar::describe(email, source);
+                Ok(written + sidecar::write(&filepath, &sidecar, options.fsync)?)
+            }
+            None => Ok(written),
+        }) {
+            Ok(written) => written,
+            Err(error) => {
+                let item = format!("saving email {}", self.counts.converted);
+                self.counts.errors.push(options.failed(item, error)?);
+                return Ok(None);
+            }
+        };
+        let index = self.next_index();
+        if options.details {
+            options.emit(Event::Converted(message_details(
+                email,
+                index,
+                &filepath,
+                message_start.elapsed(),
+                notes,
+            )))?;
+        }
+        if let Some(duplicates) = &mut self.duplicates {
+            duplicates.record(
+                email.header("message-id"),
+                MessageLocation {
+                    index,
+                    file: filepath.clone(),
+                },
+            );
+        }
+        if let Some(msgid_index) = &mut self.msgid_index {
+            msgid_index.add(
+                email.header("message-id"),
+                || content_key(content.iter().map(Vec::as_slice)),
+                IndexLocation::File(filepath.strip_prefix(output_dir).unwrap_or(&filepath)),
+            )?;
+        }
+        let counts = &mut self.counts;
+        counts.converted += 1;
+        counts.compressed += usize::from(compressed);
+        counts.bytes_written += written;
+        if !options.list_ids.is_empty()
+            && let Some(id) = email.list_id()
+        {
+            *counts.lists.entry(id.to_ascii_lowercase()).or_insert(0) += 1;
+        }
+        if let Some(files) = self.directory_files.get_mut(&directory) {
+            *files += 1;
+        }
+        options.emit(Event::Written(&filepath))?;
+        if let Some(manifest) = &mut self.sample_manifest {
+            manifest.add(&filepath, position, email.offset)?;
+        }
+        if let Some(manifest) = &mut self.embedded_manifest {
+            let (count, size) = save_embedded(email, &filepath, file_index, manifest, options)?;
+            self.counts.embedded += count;
+            self.counts.bytes_written += size;
+        }
+        Ok(None)
     }
+}
+
+/// Record how far the extraction has got in the output directory, so that it can be
+/// continued with `resume` if it does not complete.
+fn save_checkpoint(
+    options: &MboxToEmlOptions<'_>,
+    offset: Option<u64>,
+    input_size: u64,
+    next_index: usize,
+    resumed: Option<&ResumeState>,
+    (converted, errors): (usize, usize),
+) -> Result<()> {
+    // Nothing is left to resume once the input has been read to its end, and neither a sample
+    // nor a range can be resumed at all.
+    let Some(offset) = offset.filter(|_| options.sample.is_none() && options.range.is_none())
+    else {
+        return Ok(());
+    };
+    let output_dir = options.output_directory.as_path();
+    // Messages must be durable before the state claims they are done.
+    if options.fsync {
+        sync_directory(output_dir).io_context("sync output directory", output_dir)?;
+    }
+    let (earlier_converted, earlier_errors) =
+        resumed.map_or((0, 0), |state| (state.converted, state.errors));
+    ResumeState {
+        input_size,
+        sample_sha256: ResumeState::sample_hash(&options.input_file, offset)
+            .io_context("read mbox file", &options.input_file)?,
+        offset,
+        next_index,
+        converted: earlier_converted + converted,
+        errors: earlier_errors + errors,
+    }
+    .save(output_dir)
+}
+
+/// The headers at the top of a message exported as text, in order.
+const EXPORT_HEADERS: [&str; 4] = ["Date", "From", "To", "Subject"];
+
+/// The lines of a message exported as text: its main headers, decoded, and its decoded plain
+/// text, with a warning if the text could not be fully decoded. A message without plain text
+/// gets a note listing the types of its parts instead.
+/// The text exported for a message.
+struct ExportedText {
+    lines: Vec<Vec<u8>>,
+    warning: Option<String>,
+    source: TextSource,
+}
+
+/// Where the text exported for a message was taken from.
+enum TextSource {
+    Plain,
+    Html,
+    /// Neither, so a placeholder was written instead.
+    Placeholder,
+}
+
+fn export_text(message: &Message, index: usize, html: HtmlText) -> ExportedText {
+    let entity = Entity::parse(&message.lines);
+    let mut lines: Vec<Vec<u8>> = EXPORT_HEADERS
+        .iter()
+        .filter_map(|name| {
+            let field = entity.fields.iter().find(|field| field.is(name))?;
+            Some(format!("{name}: {}", decode_encoded_words(field.value.trim())).into_bytes())
+        })
+        .collect();
+    lines.push(Vec::new());
 
-    fn save_eml_file(
-        output_dir: &Path,
-        index: usize,
-        subject: Option<String>,
-        content: &[String],
-    ) -> Result<()> {
-        let filename = if let Some(subject) = subject {
-            format!("{:04}_{}.eml", index, subject)
-        } else {
-            format!("{:04}.eml", index)
+    let plain = || message.text().map(|text| (text, TextSource::Plain));
+    let html_part = || {
+        decode_html(&message.lines)
+            .map(|decoded| DecodedText {
+                text: html_to_text(&decoded.text),
+                warning: decoded.warning,
+            })
+            .filter(|decoded| !decoded.text.is_empty())
+            .map(|text| (text, TextSource::Html))
+    };
+    let text = match html {
+        HtmlText::Fallback => plain().or_else(html_part),
+        HtmlText::Always => html_part().or_else(plain),
+        HtmlText::Never => plain(),
+    };
+    let Some((decoded, source)) = text else {
+        let mut types = Vec::new();
+        for_each_part(&message.lines, &mut |part| {
+            if !part.content_type.is("multipart") {
+                types.push(part.content_type.media_type.to_ascii_lowercase());
+            }
+        });
+        lines.push(match types.is_empty() {
+            true => b"[This email has no plain text]".to_vec(),
+            false => format!(
+                "[This email has no plain text. Its parts are: {}]",
+                types.join(", ")
+            )
+            .into_bytes(),
+        });
+        return ExportedText {
+            lines,
+            warning: None,
+            source: TextSource::Placeholder,
+        };
+    };
+    lines.extend(
+        decoded
+            .text
+            .trim_end_matches('\n')
+            .split('\n')
+            .map(|line| line.as_bytes().to_vec()),
+    );
+    let warning = decoded.warning.map(|warning| {
+        let part = match source {
+            TextSource::Html => "HTML",
+            _ => "plain text",
         };
-        let filepath = output_dir.join(filename);
+        format!("the {part} of email {index} was not fully decoded: {warning}")
+    });
+    ExportedText {
+        lines,
+        warning,
+        source,
+    }
+}
 
-        let mut file = BufWriter::new(
-            File::create(&filepath)
-                .with_context(|| format!("failed to create eml file at {filepath:?}"))?,
-        );
+/// Apply an invalid UTF-8 policy to a message that was parsed with its bytes preserved and
+/// then transcoded. A message that transcoding made valid no longer counts as invalid.
+fn apply_invalid_utf8(message: &mut Message, policy: InvalidUtf8, index: usize) -> Result<()> {
+    let Some(offset) = message.invalid_utf8 else {
+        return Ok(());
+    };
+    if message
+        .lines
+        .iter()
+        .all(|line| std::str::from_utf8(line).is_ok())
+    {
+        message.invalid_utf8 = None;
+        return Ok(());
+    }
+    if policy == InvalidUtf8::Preserve {
+        return Ok(());
+    }
+    for line in &mut message.lines {
+        if let (replaced, Some(_)) = policy.apply(line) {
+            if policy == InvalidUtf8::Strict {
+                // The first invalid sequence may have been transcoded, but this is where the
+                // message stopped being UTF-8.
+                return Err(MailfmtError::InvalidUtf8 { index, offset });
+            }
+            *line = replaced.into_owned();
+        }
+    }
+    Ok(())
+}
 
-        for line in content {
-            writeln!(file, "{}", line)?;
+/// The details of an extracted message for [`Event::Converted`].
+fn message_details<'a>(
+    email: &Message,
+    index: usize,
+    file: &'a Path,
+    elapsed: Duration,
+    mut notes: Vec<String>,
+) -> MessageDetails<'a> {
+    let (envelope_sender, envelope_date) = email
+        .envelope
+        .split_once(' ')
+        .map_or((email.envelope.as_str(), ""), |(sender, date)| {
+            (sender, date.trim())
+        });
+    let sender = match email.header("from").and_then(address_of) {
+        Some(sender) => sender,
+        // Thunderbird writes a dash in place of the sender.
+        None if envelope_sender == "-" => "",
+        None => {
+            notes.push(
+                "no From address, so the sender is taken from the separator line".to_string(),
+            );
+            envelope_sender
         }
+    };
+    let (date, date_source) = match email.date() {
+        Some((_, DateSource::Separator)) => (envelope_date.to_string(), DateSource::Separator),
+        Some((date, DateSource::Received)) => (date.to_rfc2822(), DateSource::Received),
+        // A Date header that cannot be parsed is still reported as it was written.
+        _ => match email.header("date").filter(|date| !date.is_empty()) {
+            Some(date) => (date.to_string(), DateSource::Header),
+            None => (envelope_date.to_string(), DateSource::Separator),
+        },
+    };
+    MessageDetails {
+        index,
+        sender: Some(sender.to_string()).filter(|sender| !sender.is_empty()),
+        date: Some(date).filter(|date| !date.is_empty()),
+        date_source,
+        file,
+        elapsed,
+        notes,
+    }
+}
 
-        file.flush()?;
-        Ok(())
+/// The number of files in a directory named after the number of a message, which per-directory
+/// numbering continues from when resuming.
+fn count_numbered_files(directory: &Path) -> io::Result<usize> {
+    let mut count = 0;
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file()
+            && entry
+                .file_name()
+                .as_encoded_bytes()
+                .first()
+                .is_some_and(u8::is_ascii_digit)
+        {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+pub(crate) fn eml_file_name(index: usize, subject: Option<String>, extension: &str) -> String {
+    if let Some(subject) = subject {
+        format!("{:04}_{}.{}", index, subject, extension)
+    } else {
+        format!("{:04}.{}", index, extension)
+    }
+}
+
+/// The subject of a message as used in its file name, or `None` if it has none or nothing of
+/// it is left in ASCII.
+fn file_subject(subject: Option<&str>, options: &MboxToEmlOptions<'_>) -> Option<String> {
+    subject
+        .filter(|subject| !subject.is_empty())
+        .map(|subject| match options.clean_subject {
+            true => clean_subject(subject),
+            false => subject,
+        })
+        .and_then(|subject| match options.ascii_filenames {
+            true => transliterate(subject).map(Cow::Owned),
+            false => Some(normalize(subject, options.filename_normalization)),
+        })
+        .map(sanitize_filename::sanitize)
+}
+
+/// The file name recorded in a message's X-Source-File header, sanitized and with the given
+/// extension, or `None` if it has none that is usable.
+fn source_file_name(message: &Message, extension: &str) -> Option<String> {
+    let entity = Entity::parse(&message.lines);
+    let field = entity
+        .fields
+        .iter()
+        .find(|field| field.is(SOURCE_FILE_HEADER))?;
+    let path = decode_encoded_words(&field.value);
+    let name = path.rsplit(['/', '\\']).next()?.trim();
+    let name = gzip::strip_extension(name).unwrap_or(name);
+    let stem = sanitize_filename::sanitize(Path::new(name).file_stem()?.to_string_lossy());
+    (!stem.is_empty()).then(|| format!("{stem}.{extension}"))
+}
+
+/// The lines of a message without the fields of its header block with the given name,
+/// including their continuation lines, or `None` if it has none.
+fn without_header(lines: &[Vec<u8>], name: &str) -> Option<Vec<Vec<u8>>> {
+    let mut kept = Vec::with_capacity(lines.len());
+    let (mut in_header, mut dropping, mut dropped) = (true, false, false);
+    for line in lines {
+        if in_header {
+            if line.is_empty() || line == b"\r" {
+                in_header = false;
+            } else if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+                dropping = String::from_utf8_lossy(line)
+                    .split_once(':')
+                    .is_some_and(|(field, _)| field.eq_ignore_ascii_case(name));
+            }
+            if in_header && dropping {
+                dropped = true;
+                continue;
+            }
+        }
+        kept.push(line.clone());
+    }
+    dropped.then_some(kept)
+}
+
+/// The messages chosen for a sample and those that are still reported, in their order in the
+/// mbox, with their index in it.
+struct Sample {
+    messages: Vec<(usize, Result<Message>)>,
+    /// How many messages the sample was drawn from.
+    population: usize,
+    /// How many messages were read.
+    read: usize,
+    /// How many of them were before the range, and passed over.
+    before_range: usize,
+    /// How many of those in the range were not on any of the lists asked for.
+    off_list: usize,
+    /// How many of those on the lists were not in the read state asked for.
+    off_state: usize,
+    /// How many of those in the read state were marked as deleted, and skipped.
+    deleted: usize,
+}
+
+/// Read every message of the mbox, or of the range, keeping a random sample of `size` of those that are not
+/// left out as headerless, for their mailing list, for their read state or as deleted, along with those that are and those that cannot be
+/// read, as they are still reported.
+fn draw_sample<I: Iterator<Item = io::Result<OffsetLine>>>(
+    parser: &mut MboxParser<I>,
+    size: usize,
+    seed: u64,
+    options: &MboxToEmlOptions<'_>,
+) -> Sample {
+    let mut reservoir = Reservoir::new(size, seed);
+    let mut messages = Vec::new();
+    let mut read = 0;
+    let range = options.range.unwrap_or_default();
+    let (mut off_list, mut off_state, mut deleted) = (0, 0, 0);
+    while range.end.is_none_or(|end| read < end) {
+        let Some(result) = parser.next() else {
+            break;
+        };
+        let index = read;
+        read += 1;
+        if index < range.start {
+            continue;
+        }
+        if result
+            .as_ref()
+            .is_ok_and(|email| !email.on_list(&options.list_ids))
+        {
+            off_list += 1;
+            continue;
+        }
+        if result
+            .as_ref()
+            .is_ok_and(|email| !email.in_read_state(options))
+        {
+            off_state += 1;
+            continue;
+        }
+        if options.skip_deleted && result.as_ref().is_ok_and(Message::is_deleted) {
+            deleted += 1;
+            continue;
+        }
+        match result {
+            Ok(email)
+                if (options.empty_messages == EmptyMessages::Keep || !email.is_empty())
+                    && (options.headerless == Headerless::Keep || email.has_headers()) =>
+            {
+                reservoir.offer((index, Ok(email)))
+            }
+            result => messages.push((index, result)),
+        }
+    }
+    let population = reservoir.offered();
+    messages.extend(reservoir.into_items());
+    messages.sort_by_key(|(index, _)| *index);
+    Sample {
+        messages,
+        population,
+        read,
+        before_range: range.start.min(read),
+        off_list,
+        off_state,
+        deleted,
+    }
+}
+
+/// Write the messages embedded in an extracted message to eml files of their own next to its
+/// file, such as `0007.1_Subject.eml` for the first one in `0007_Subject.eml`, recording them
+/// in the manifest. Returns how many were written and their size. Embedded messages that
+/// cannot be decoded or saved are warned about, as the email itself was extracted.
+fn save_embedded(
+    email: &Message,
+    filepath: &Path,
+    file_index: usize,
+    manifest: &mut EmbeddedManifest,
+    options: &MboxToEmlOptions<'_>,
+) -> Result<(usize, u64)> {
+    let (embedded, truncated) = embedded_messages(&email.lines);
+    if truncated {
+        options.emit(Event::Warning(format!(
+            "Email {file_index} has messages embedded more than {MAX_EMBEDDED_DEPTH} deep, which are left inside of their parents"
+        )))?;
+    }
+    let directory = filepath.parent().unwrap_or(Path::new(""));
+    // The files written for this email, by the number of their message.
+    let mut files = HashMap::new();
+    let (mut written, mut bytes) = (0, 0);
+    for message in &embedded {
+        let lines = match &message.lines {
+            Ok(lines) => lines,
+            Err(encoding) => {
+                options.emit(Event::Warning(format!(
+                    "Message {} embedded in email {file_index} is not valid {encoding} and is not extracted",
+                    message.number
+                )))?;
+                continue;
+            }
+        };
+        let subject = file_subject(get_header_value_from_lines(lines, "subject"), options);
+        let prefix = format!("{file_index:04}.{}", message.number);
+        let path = directory.join(match subject {
+            Some(subject) => format!("{prefix}_{subject}.eml"),
+            None => format!("{prefix}.eml"),
+        });
+        match save_eml_file(&path, lines, true, options.fsync) {
+            Ok(size) => {
+                let parent = message
+                    .parent_number()
+                    .and_then(|number| files.get(number))
+                    .map_or(filepath, PathBuf::as_path);
+                manifest.add(&path, parent, &message.number)?;
+                options.emit(Event::Written(&path))?;
+                files.insert(message.number.as_str(), path);
+                written += 1;
+                bytes += size;
+            }
+            Err(error) => options.emit(Event::Warning(format!(
+                "Message {} embedded in email {file_index} could not be saved: {error}",
+                message.number
+            )))?,
+        }
+    }
+    Ok((written, bytes))
+}
+
+/// The lines of `content` as they are written to an eml file, each with the line break that
+/// follows it. The last goes without one when `final_newline` is not set.
+fn written_lines(
+    content: &[Vec<u8>],
+    final_newline: bool,
+) -> impl Iterator<Item = (&[u8], &'static [u8])> {
+    let last = content.len().saturating_sub(1);
+    content.iter().enumerate().map(move |(index, line)| {
+        let ending: &[u8] = match index == last && !final_newline {
+            true => b"",
+            false => b"\n",
+        };
+        (line.as_slice(), ending)
+    })
+}
+
+/// The SHA-256 in hexadecimal of the bytes [`save_eml_file`] writes for `content`.
+fn written_hash(content: &[Vec<u8>], final_newline: bool) -> String {
+    let mut hasher = Sha256::new();
+    for (line, ending) in written_lines(content, final_newline) {
+        hasher.update(line);
+        hasher.update(ending);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// The number of bytes [`save_eml_file`] writes for `content`.
+fn written_size(content: &[Vec<u8>], final_newline: bool) -> u64 {
+    written_lines(content, final_newline)
+        .map(|(line, ending)| (line.len() + ending.len()) as u64)
+        .sum()
+}
+
+/// Whether the file at `path` holds exactly the bytes [`save_eml_file`] writes for `content`,
+/// once decompressed if it is named like a gzip file. Files that cannot be read hold nothing.
+fn holds_content(path: &Path, content: &[Vec<u8>], final_newline: bool) -> bool {
+    let size = written_size(content, final_newline);
+    let compressed = gzip::has_extension(path);
+    if !compressed && fs::metadata(path).map_or(true, |metadata| metadata.len() != size) {
+        return false;
+    }
+    let bytes = match fs::read(path) {
+        Ok(bytes) if compressed => match gzip::decompress(&bytes) {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        },
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+    let mut rest = bytes.as_slice();
+    bytes.len() as u64 == size
+        && written_lines(content, final_newline).all(|(line, ending)| {
+            let matches = rest.starts_with(line) && rest[line.len()..].starts_with(ending);
+            rest = rest.get(line.len() + ending.len()..).unwrap_or_default();
+            matches
+        })
+}
+
+/// Write an eml file compressed with gzip, returning its compressed size.
+fn save_compressed_eml_file(
+    filepath: &Path,
+    content: &[Vec<u8>],
+    final_newline: bool,
+    fsync: bool,
+) -> Result<u64> {
+    let mut bytes = Vec::with_capacity(written_size(content, final_newline) as usize);
+    for (line, ending) in written_lines(content, final_newline) {
+        bytes.extend_from_slice(line);
+        bytes.extend_from_slice(ending);
+    }
+    let compressed = gzip::compress(&bytes);
+    let mut file = AtomicFile::create(filepath).io_context("create eml file", filepath)?;
+    file.write_all(&compressed)
+        .io_context("write eml file", filepath)?;
+    if fsync {
+        file.file()
+            .sync_all()
+            .io_context("sync eml file", filepath)?;
+    }
+    file.commit()
+        .io_context("move eml file into place", filepath)?;
+    Ok(compressed.len() as u64)
+}
+
+/// Write an eml file, returning its size. Its last line is only followed by a line break with
+/// `final_newline`, so that a message that had none in the mbox keeps its bytes.
+pub(crate) fn save_eml_file(
+    filepath: &Path,
+    content: &[Vec<u8>],
+    final_newline: bool,
+    fsync: bool,
+) -> Result<u64> {
+    let mut file =
+        BufWriter::new(AtomicFile::create(filepath).io_context("create eml file", filepath)?);
+
+    let mut written = 0;
+    for (line, ending) in written_lines(content, final_newline) {
+        file.write_all(line)
+            .and_then(|()| file.write_all(ending))
+            .io_context("write eml file", filepath)?;
+        written += (line.len() + ending.len()) as u64;
+    }
+
+    let file = file
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .io_context("write eml file", filepath)?;
+    if fsync {
+        file.file()
+            .sync_all()
+            .io_context("sync eml file", filepath)?;
+    }
+    file.commit()
+        .io_context("move eml file into place", filepath)?;
+    Ok(written)
+}
+
+/// The most bytes of content before the first separator that [`MboxToEmlOptions::strict`]
+/// accepts, enough for the dummy first line some tools write.
+const LEADING_CONTENT_LIMIT: u64 = 512;
+
+/// Warn about content skipped before the first separator, or fail on it in strict mode when it
+/// could be what is left of a message whose separator was lost.
+fn check_leading_content(options: &MboxToEmlOptions, leading: LeadingContent) -> Result<()> {
+    if leading.lines == 0 {
+        return Ok(());
+    }
+    if options.strict && (leading.bytes > LEADING_CONTENT_LIMIT || leading.header_lines > 0) {
+        return Err(MailfmtError::LeadingContent {
+            bytes: leading.bytes,
+            lines: leading.lines,
+            header_lines: leading.header_lines,
+        });
+    }
+    options.emit(Event::Warning(format!(
+        "Skipped {} bytes in {} lines before the first message{}",
+        leading.bytes,
+        leading.lines,
+        match leading.header_lines {
+            0 => String::new(),
+            count => format!(
+                ", {count} of which look like headers of a message whose \"From \" line is missing"
+            ),
+        }
+    )))
+}
+
+/// How many messages are extracted between updates of the resume state.
+const RESUME_CHECKPOINT_INTERVAL: usize = 1000;
+
+/// Check that an input file exists and, unless `force` is set, that it looks like an mbox.
+pub(crate) fn check_mbox_input(input_file: &Path, force: bool, keep_bom: bool) -> Result<()> {
+    if !input_file.exists() {
+        return Err(MailfmtError::InputNotFound {
+            path: input_file.to_path_buf(),
+        });
+    }
+    if force {
+        return Ok(());
+    }
+    match sniff_mbox(input_file, keep_bom).io_context("read mbox file", input_file)? {
+        true => Ok(()),
+        false => Err(MailfmtError::NotAnMbox {
+            path: input_file.to_path_buf(),
+        }),
+    }
+}
+
+/// Fail when `dir` or one of its ancestors exists as something other than a directory, as
+/// creating it would, with an error that does not say which path is in the way.
+pub(crate) fn check_output_directory(dir: &Path) -> Result<()> {
+    match dir.ancestors().find(|path| {
+        fs::metadata(paths::extended_length(path)).is_ok_and(|metadata| !metadata.is_dir())
+    }) {
+        Some(path) => Err(MailfmtError::OutputNotADirectory {
+            path: path.to_path_buf(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Create a directory to write output to, along with any missing parents.
+pub(crate) fn create_output_directory(dir: &Path, action: &'static str) -> Result<()> {
+    check_output_directory(dir)?;
+    fs::create_dir_all(paths::extended_length(dir)).io_context(action, dir)
+}
+
+/// Check the input file of [`mbox_to_eml`] or [`count_mbox`] in the format it is given in.
+fn check_input(options: &MboxToEmlOptions<'_>) -> Result<()> {
+    let (input_file, force, keep_bom) = (&options.input_file, options.force, options.keep_bom);
+    match options.input_format {
+        InputFormat::Mbox => check_mbox_input(input_file, force, keep_bom),
+        InputFormat::Babyl => babyl::check_input(input_file, force, keep_bom),
+    }
+}
+
+/// How many bytes at the start of a file are inspected when checking whether it is an mbox.
+const SNIFF_LENGTH: u64 = 8 * 1024;
+
+/// Inspect the start of a file, once decompressed if it is gzip-compressed, to determine whether
+/// it is an mbox, by looking for a separator line that is followed by a header.
+fn sniff_mbox(path: &Path, keep_bom: bool) -> io::Result<bool> {
+    let mut start = Vec::new();
+    gzip::open(path, 0)?
+        .take(SNIFF_LENGTH)
+        .read_to_end(&mut start)?;
+    let start = if keep_bom { &start } else { strip_bom(&start) };
+    let mut lines = start
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    while let Some(line) = lines.next() {
+        if is_separator(line)
+            && let Some(next) = lines.next()
+            && is_header_line(&String::from_utf8_lossy(next))
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Count the messages of an mbox file that [`mbox_to_eml`] would extract with the same options,
+/// without extracting them or needing an output directory. The range, mailing lists, sample
+/// size and headerless policy of the options are applied, and the input is checked the same
+/// way.
+///
+/// Messages are found with the same scan as [`MboxToEmlOptions::precount`], which only reads
+/// their header blocks when a filter needs them. With
+/// [`MboxToEmlOptions::smart_separators`], every message is parsed instead, which is slower.
+///
+/// ```
+/// use mailfmt::{MboxToEmlOptions, MessageRange, count_mbox};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-doc-count-mbox-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// let mbox = dir.join("in.mbox");
+/// fs::write(&mbox, "From a Mon Jan 01 00:00:00 2024\nSubject: One\n\nHello\n\n\
+///                   From b Mon Jan 01 00:00:00 2024\nSubject: Two\n\nHello\n\n\
+///                   From c Mon Jan 01 00:00:00 2024\nSubject: Three\n\nHello\n")?;
+///
+/// // No output directory is created, so any will do.
+/// let mut options = MboxToEmlOptions::new(&mbox, "");
+/// assert_eq!(count_mbox(&options)?.matching, 3);
+/// options.range = Some("1..".parse()?);
+/// let count = count_mbox(&options)?;
+/// assert_eq!((count.found, count.matching), (3, 2));
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn count_mbox(options: &MboxToEmlOptions<'_>) -> Result<MessageCount> {
+    let start = Instant::now();
+    let input_file = options.input_file.as_path();
+    if input_file.exists()
+        && is_blank(input_file, options.keep_bom).io_context("read mbox file", input_file)?
+    {
+        if !options.allow_empty {
+            return Err(MailfmtError::EmptyInput {
+                path: input_file.to_path_buf(),
+            });
+        }
+        return Ok(MessageCount {
+            elapsed: start.elapsed(),
+            ..MessageCount::default()
+        });
+    }
+    check_input(options)?;
+
+    let range = options.range.unwrap_or_default();
+    let mut count = MessageCount::default();
+    // Whether to keep reading, given the lines of the next message, or at least its header block,
+    // and whether it is empty.
+    let mut visit = |lines: Vec<Vec<u8>>, empty: bool| {
+        if options.is_cancelled() {
+            count.interrupted = true;
+            return false;
+        }
+        let position = count.found as usize;
+        if range.end.is_some_and(|end| position >= end) {
+            return false;
+        }
+        count.found += 1;
+        let message = Message {
+            offset: 0,
+            envelope: String::new(),
+            lines,
+            final_newline: true,
+            invalid_utf8: None,
+        };
+        if position >= range.start
+            && (options.empty_messages != EmptyMessages::Skip || !empty)
+            && message.on_list(&options.list_ids)
+            && message.in_read_state(options)
+            && !(options.skip_deleted && message.is_deleted())
+            && (options.headerless != Headerless::Skip || message.has_headers())
+        {
+            count.matching += 1;
+        }
+        true
+    };
+    let reader =
+        BufReader::new(gzip::open(input_file, 0).io_context("open mbox file", input_file)?);
+    // Babyl files have no header blocks to scan for, only messages to parse, and where a
+    // body with a Content-Length ends only shows when parsing.
+    if options.smart_separators
+        || options.input_format == InputFormat::Babyl
+        || options.dialect.content_length()
+    {
+        let mut parser = options
+            .parser(reader, 0)
+            .with_invalid_utf8(InvalidUtf8::Preserve);
+        for message in parser.by_ref() {
+            let message = message?;
+            let empty = message.is_empty();
+            if !visit(message.lines, empty) {
+                break;
+            }
+        }
+    } else {
+        let headers = !options.list_ids.is_empty()
+            || options.read_state.is_some()
+            || options.skip_deleted
+            || options.headerless == Headerless::Skip;
+        scan_header_blocks(reader, options.keep_bom, headers, visit)
+            .io_context("count messages in mbox file", input_file)?;
+    }
+    if let Some(size) = options.sample {
+        count.matching = count.matching.min(size as u64);
+    }
+    count.elapsed = start.elapsed();
+    Ok(count)
+}
+
+/// Whether a file is empty or holds nothing but whitespace, such as blank lines.
+fn is_blank(path: &Path, keep_bom: bool) -> io::Result<bool> {
+    let mut reader = BufReader::new(gzip::open(path, 0)?);
+    let mut first = !keep_bom;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(true);
+        }
+        let content = if first { strip_bom(buffer) } else { buffer };
+        if !content.iter().all(u8::is_ascii_whitespace) {
+            return Ok(false);
+        }
+        first = false;
+        let length = buffer.len();
+        reader.consume(length);
+    }
+}
+
+/// Whether a line is a "From " separator that starts a new message.
+pub(crate) fn is_separator(line: &[u8]) -> bool {
+    line.starts_with(b"From ")
+}
+
+/// Count the messages in an mbox without parsing them, following the same boundary rules as [`MboxParser`].
+fn count_messages<R: BufRead>(mut reader: R, keep_bom: bool) -> io::Result<u64> {
+    let mut line = Vec::new();
+    let (mut separators, mut trailing_content) = (0, false);
+    let mut first = !keep_bom;
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        let content = if first { strip_bom(&line) } else { &line };
+        first = false;
+        if is_separator(content) {
+            separators += 1;
+            trailing_content = false;
+        } else if separators > 0 && !content.trim_ascii().is_empty() {
+            trailing_content = true;
+        }
+        line.clear();
+    }
+    // A separator at the very end of the file with nothing but blank lines after it does not
+    // produce a message.
+    if separators > 0 && !trailing_content {
+        separators -= 1;
+    }
+    Ok(separators)
+}
+
+/// Call `visit` with the header block of every message in an mbox, without their line endings,
+/// and whether the message is empty, finding the messages as [`count_messages`] does. Header
+/// blocks are only kept when `headers` is set, and are empty otherwise. Reading stops once
+/// `visit` returns false.
+pub(crate) fn scan_header_blocks<R: BufRead>(
+    mut reader: R,
+    keep_bom: bool,
+    headers: bool,
+    mut visit: impl FnMut(Vec<Vec<u8>>, bool) -> bool,
+) -> io::Result<()> {
+    let mut line = Vec::new();
+    let mut first = !keep_bom;
+    // The header block of the message being read, whether it is still being read, and whether
+    // the message has any line that is not blank after its separator.
+    let (mut block, mut in_headers, mut has_lines) = (None, false, false);
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        let content = if first { strip_bom(&line) } else { &line };
+        first = false;
+        if is_separator(content) {
+            if let Some(block) = block.take()
+                && !visit(block, !has_lines)
+            {
+                return Ok(());
+            }
+            (block, in_headers, has_lines) = (Some(Vec::new()), headers, false);
+        } else if let Some(block) = &mut block {
+            has_lines |= !content.trim_ascii().is_empty();
+            if in_headers {
+                let content = content.strip_suffix(b"\n").unwrap_or(content);
+                let content = content.strip_suffix(b"\r").unwrap_or(content);
+                match content.is_empty() {
+                    true => in_headers = false,
+                    false => block.push(content.to_vec()),
+                }
+            }
+        }
+        line.clear();
+    }
+    // A separator at the very end of the file with nothing but blank lines after it does not
+    // produce a message.
+    if let Some(block) = block
+        && has_lines
+    {
+        visit(block, false);
+    }
+    Ok(())
+}
+
+/// A line as [`MboxParser`] reads it: the byte offset it starts at, its content without its
+/// line ending, and whether it was followed by a line break, which only the last line of the
+/// data can lack.
+pub type OffsetLine = (u64, Vec<u8>, bool);
+
+/// The lines of a reader, without their line endings, together with the byte offset each
+/// starts at. This is the input [`MboxParser`] reads from.
+///
+/// A UTF-8 byte-order mark at the very start of the data is removed, as it would otherwise
+/// hide the first separator line:
+///
+/// ```
+/// use mailfmt::{MboxParser, OffsetLines};
+///
+/// let mbox = b"\xEF\xBB\xBFFrom a Mon Jan 01 00:00:00 2024\nSubject: First\n";
+/// let message = MboxParser::from_reader(&mbox[..]).next().unwrap()?;
+/// assert_eq!(message.offset, 3);
+/// assert_eq!(message.header("subject"), Some("First"));
+///
+/// let lines = OffsetLines::new(&mbox[..], 0).keep_bom(true);
+/// assert!(MboxParser::new(lines).next().is_none());
+/// # Ok::<(), mailfmt::MailfmtError>(())
+/// ```
+pub struct OffsetLines<R> {
+    reader: R,
+    offset: u64,
+    strip_bom: bool,
+}
+
+impl<R: BufRead> OffsetLines<R> {
+    /// Read lines from a reader that is positioned at `offset` in its underlying data, which is
+    /// 0 unless reading starts part of the way in.
+    pub fn new(reader: R, offset: u64) -> Self {
+        Self {
+            reader,
+            offset,
+            strip_bom: offset == 0,
+        }
     }
+
+    /// Keep a byte-order mark at the start of the data as part of the first line.
+    pub fn keep_bom(mut self, keep: bool) -> Self {
+        self.strip_bom &= !keep;
+        self
+    }
+}
+
+impl<R: BufRead> Iterator for OffsetLines<R> {
+    type Item = io::Result<OffsetLine>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = Vec::new();
+        let read = match self.reader.read_until(b'\n', &mut line) {
+            Ok(0) => return None,
+            Ok(read) => read,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut start = self.offset;
+        self.offset += read as u64;
+        if self.strip_bom {
+            self.strip_bom = false;
+            let bom = line.len() - strip_bom(&line).len();
+            line.drain(..bom);
+            start += bom as u64;
+        }
+        let newline = line.ends_with(b"\n");
+        if newline {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        Some(Ok((start, line, newline)))
+    }
+}
+
+/// A single message read from an mbox.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    /// Byte offset of the message's "From " separator line in the mbox.
+    pub offset: u64,
+    /// The separator line after "From ", usually the envelope sender and a date.
+    pub envelope: String,
+    /// The lines of the message, without line endings or the separator line. They are not
+    /// necessarily valid UTF-8, depending on the parser's [`InvalidUtf8`] policy.
+    pub lines: Vec<Vec<u8>>,
+    /// Whether the last line of the message ended with a line break, which only that of the
+    /// last message of an mbox can lack. Extracting the message keeps it that way.
+    pub final_newline: bool,
+    /// Byte offset in the mbox of the first sequence in the message that is not valid UTF-8,
+    /// if it has one. This is set regardless of the policy, even when the sequences have been
+    /// replaced.
+    pub invalid_utf8: Option<u64>,
+}
+
+impl Message {
+    /// The trimmed value of the first header with the given name, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        get_header_value_from_lines(&self.lines, name)
+    }
+
+    /// The identifier of the mailing list the message came through, from its List-Id header,
+    /// unfolded, as returned by [`list_id`].
+    ///
+    /// ```
+    /// use mailfmt::MboxParser;
+    ///
+    /// let mbox = "From a Mon Jan 01 00:00:00 2024\n\
+    ///             List-Id: Discussion of Rust\n \
+    ///             <rust-users.rust-lang.org>\n\
+    ///             \n\
+    ///             Body\n";
+    /// let message = MboxParser::from_reader(mbox.as_bytes()).next().unwrap()?;
+    /// assert_eq!(message.list_id().as_deref(), Some("rust-users.rust-lang.org"));
+    /// # Ok::<(), mailfmt::MailfmtError>(())
+    /// ```
+    ///
+    /// [`list_id`]: crate::headers::list_id
+    pub fn list_id(&self) -> Option<String> {
+        let entity = Entity::parse(&self.lines);
+        let field = entity.fields.iter().find(|field| field.is("list-id"))?;
+        list_id(&field.value).map(str::to_string)
+    }
+
+    /// Whether the message is on one of `list_ids`, as [`MboxToEmlOptions::list_ids`] matches
+    /// them. Every message is when there are none.
+    fn on_list(&self, list_ids: &[String]) -> bool {
+        if list_ids.is_empty() {
+            return true;
+        }
+        let Some(id) = self.list_id() else {
+            return false;
+        };
+        let id = id.to_ascii_lowercase();
+        list_ids.iter().any(|wanted| {
+            let wanted = wanted.trim().to_ascii_lowercase();
+            match wanted.strip_prefix('<').and_then(|w| w.strip_suffix('>')) {
+                Some(exact) => id == exact.trim(),
+                None => id.contains(&wanted),
+            }
+        })
+    }
+
+    /// Whether the message has been read, after its Status, X-Status or X-Mozilla-Status
+    /// header, or `None` if it has none of them. Thunderbird records the flags of a message
+    /// as a hexadecimal bitmask in its X-Mozilla-Status header, in which 1 stands for read.
+    ///
+    /// ```
+    /// use mailfmt::{MboxParser, ReadState};
+    ///
+    /// let mbox = "From a Mon Jan 01 00:00:00 2024\nStatus: RO\n\nRead\n\
+    ///             From b Mon Jan 01 00:00:00 2024\nStatus: O\nX-Status: A\n\nOld\n\
+    ///             From c Mon Jan 01 00:00:00 2024\nX-Mozilla-Status: 0003\n\nReplied\n\
+    ///             From d Mon Jan 01 00:00:00 2024\nX-Mozilla-Status: 0002\n\nUnread\n\
+    ///             From e Mon Jan 01 00:00:00 2024\nSubject: New\n\nNew\n";
+    /// let states: Vec<_> = MboxParser::from_reader(mbox.as_bytes())
+    ///     .map(|message| message.map(|message| message.read_state()))
+    ///     .collect::<Result<_, _>>()?;
+    /// assert_eq!(
+    ///     states,
+    ///     [
+    ///         Some(ReadState::Seen),
+    ///         Some(ReadState::Unseen),
+    ///         Some(ReadState::Seen),
+    ///         Some(ReadState::Unseen),
+    ///         None,
+    ///     ]
+    /// );
+    /// # Ok::<(), mailfmt::MailfmtError>(())
+    /// ```
+    pub fn read_state(&self) -> Option<ReadState> {
+        status::read_state(|name| self.header(name))
+    }
+
+    /// The date of the message and where it was taken from: its Date header, or when that is
+    /// missing or cannot be parsed, the date on its separator line, or else the date at the
+    /// end of its first Received header. Separator dates without a time zone are taken to be
+    /// in UTC.
+    ///
+    /// ```
+    /// use mailfmt::{DateSource, MboxParser};
+    ///
+    /// let mbox = "From a Mon Jan 01 00:00:00 2024\nDate: Tue, 02 Jan 2024 10:30:00 +0100\n\nDated\n\
+    ///             From b Wed Jan  3 08:15:00 2024\nDate: yesterday\n\nUnusable\n\
+    ///             From c Thu Jan 4 09:00 +0200 2024\n\nZoned\n\
+    ///             From d\nReceived: from mx.example.com\n by mail.example.com;\n Fri, 5 Jan 2024 07:00:00 +0000\n\nReceived\n";
+    /// let dates: Vec<_> = MboxParser::from_reader(mbox.as_bytes())
+    ///     .map(|message| {
+    ///         message.map(|message| {
+    ///             message.date().map(|(date, source)| (date.to_rfc3339(), source))
+    ///         })
+    ///     })
+    ///     .collect::<Result<_, _>>()?;
+    /// assert_eq!(
+    ///     dates,
+    ///     [
+    ///         Some(("2024-01-02T10:30:00+01:00".to_string(), DateSource::Header)),
+    ///         Some(("2024-01-03T08:15:00+00:00".to_string(), DateSource::Separator)),
+    ///         Some(("2024-01-04T09:00:00+02:00".to_string(), DateSource::Separator)),
+    ///         Some(("2024-01-05T07:00:00+00:00".to_string(), DateSource::Received)),
+    ///     ]
+    /// );
+    /// # Ok::<(), mailfmt::MailfmtError>(())
+    /// ```
+    pub fn date(&self) -> Option<(DateTime<FixedOffset>, DateSource)> {
+        let entity = Entity::parse(&self.lines);
+        let field = |name| {
+            entity
+                .fields
+                .iter()
+                .find(|field| field.is(name))
+                .map(|field| field.value.trim())
+        };
+        message_date(DateSources {
+            header: field("date"),
+            envelope: Some(&self.envelope),
+            received: field("received"),
+            modified: None,
+        })
+    }
+
+    /// Whether Thunderbird marked the message as deleted, with the 0x0008 bit of its
+    /// X-Mozilla-Status header. Thunderbird keeps deleted messages in the mbox until the folder
+    /// is compacted. A value that is not hexadecimal marks nothing.
+    ///
+    /// ```
+    /// use mailfmt::MboxParser;
+    ///
+    /// let mbox = "From a Mon Jan 01 00:00:00 2024\nX-Mozilla-Status: 0009\n\nDeleted\n\
+    ///             From b Mon Jan 01 00:00:00 2024\nX-Mozilla-Status: 0001\n\nKept\n\
+    ///             From c Mon Jan 01 00:00:00 2024\nX-Mozilla-Status: zz08\n\nMalformed\n";
+    /// let deleted: Vec<_> = MboxParser::from_reader(mbox.as_bytes())
+    ///     .map(|message| message.map(|message| message.is_deleted()))
+    ///     .collect::<Result<_, _>>()?;
+    /// assert_eq!(deleted, [true, false, false]);
+    /// # Ok::<(), mailfmt::MailfmtError>(())
+    /// ```
+    pub fn is_deleted(&self) -> bool {
+        status::mozilla_deleted(|name| self.header(name))
+    }
+
+    /// Whether the message is in the read state [`MboxToEmlOptions::read_state`] asks for.
+    /// Every message is when it asks for none.
+    fn in_read_state(&self, options: &MboxToEmlOptions<'_>) -> bool {
+        options
+            .read_state
+            .is_none_or(|wanted| self.read_state().unwrap_or(options.no_status_means) == wanted)
+    }
+
+    /// Whether the message is empty: it holds no line, or only blank ones, as when two
+    /// separator lines follow each other.
+    ///
+    /// ```
+    /// use mailfmt::MboxParser;
+    ///
+    /// let mbox = "From a Mon Jan 01 00:00:00 2024\n\
+    ///             From b Mon Jan 01 00:00:00 2024\n\
+    ///             \n\
+    ///             \r\n\
+    ///             From c Mon Jan 01 00:00:00 2024\n\
+    ///             Subject: Hello\n";
+    /// let empty: Vec<_> = MboxParser::from_reader(mbox.as_bytes())
+    ///     .map(|message| message.map(|message| message.is_empty()))
+    ///     .collect::<Result<_, _>>()?;
+    /// assert_eq!(empty, [true, true, false]);
+    /// # Ok::<(), mailfmt::MailfmtError>(())
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.lines.iter().all(|line| line.trim_ascii().is_empty())
+    }
+
+    /// Whether the message has a header block: a header field before its first blank line.
+    ///
+    /// ```
+    /// use mailfmt::MboxParser;
+    ///
+    /// let mbox = "From a Mon Jan 01 00:00:00 2024\n\
+    ///             Subject: Hello\n\
+    ///             \n\
+    ///             Body\n\
+    ///             From b Mon Jan 01 00:00:00 2024\n\
+    ///             Just some text: with a colon\n";
+    /// let mut messages = MboxParser::from_reader(mbox.as_bytes());
+    /// assert!(messages.next().unwrap()?.has_headers());
+    /// assert!(!messages.next().unwrap()?.has_headers());
+    /// # Ok::<(), mailfmt::MailfmtError>(())
+    /// ```
+    pub fn has_headers(&self) -> bool {
+        self.lines
+            .iter()
+            .take_while(|line| !line.is_empty() && line.as_slice() != b"\r")
+            .any(|line| is_header_line(&String::from_utf8_lossy(line)))
+    }
+
+    /// The lines of the message up to its first blank line, which are its header block if it
+    /// has one.
+    pub fn header_lines(&self) -> &[Vec<u8>] {
+        let end = (self.lines.iter())
+            .position(|line| line.is_empty() || line.as_slice() == b"\r")
+            .unwrap_or(self.lines.len());
+        &self.lines[..end]
+    }
+
+    /// The plain text of the message: its first text/plain part that is not an attachment,
+    /// or its body if it is not a MIME message, decoded from its transfer encoding and charset.
+    /// `None` if it has no plain text.
+    ///
+    /// ```
+    /// use mailfmt::MboxParser;
+    ///
+    /// let mbox = "From a Mon Jan 01 00:00:00 2024\n\
+    ///             Content-Type: multipart/mixed; boundary=b\n\
+    ///             \n\
+    ///             --b\n\
+    ///             Content-Type: text/plain; charset=iso-8859-1\n\
+    ///             Content-Transfer-Encoding: quoted-printable\n\
+    ///             \n\
+    ///             It=92s a caf=E9 with a very long line that was wrapped by a soft line =\n\
+    ///             break.\n\
+    ///             --b\n\
+    ///             Content-Type: text/plain\n\
+    ///             Content-Disposition: attachment; filename=notes.txt\n\
+    ///             \n\
+    ///             Not the message text\n\
+    ///             --b--\n";
+    /// let message = MboxParser::from_reader(mbox.as_bytes()).next().unwrap()?;
+    /// let text = message.text().unwrap();
+    /// assert_eq!(text.text, "It’s a café with a very long line that was wrapped by a soft line break.");
+    /// assert_eq!(text.warning, None);
+    /// # Ok::<(), mailfmt::MailfmtError>(())
+    /// ```
+    ///
+    /// Text that cannot be decoded is given as it was, with a warning saying why:
+    ///
+    /// ```
+    /// use mailfmt::MboxParser;
+    ///
+    /// let mbox = "From a Mon Jan 01 00:00:00 2024\n\
+    ///             Content-Transfer-Encoding: base64\n\
+    ///             \n\
+    ///             SGVsbG8=\n\
+    ///             \n\
+    ///             From b Mon Jan 01 00:00:00 2024\n\
+    ///             Content-Transfer-Encoding: base64\n\
+    ///             \n\
+    ///             SGVsbG8h2\n";
+    /// let mut parser = MboxParser::from_reader(mbox.as_bytes());
+    /// assert_eq!(parser.next().unwrap()?.text().unwrap().text, "Hello");
+    /// let broken = parser.next().unwrap()?.text().unwrap();
+    /// assert_eq!(broken.text, "SGVsbG8h2");
+    /// assert!(broken.warning.is_some());
+    /// # Ok::<(), mailfmt::MailfmtError>(())
+    /// ```
+    pub fn text(&self) -> Option<DecodedText> {
+        decode_text(&self.lines)
+    }
+}
+
+/// Content before the first "From " separator of an mbox, which [`MboxParser`] skips.
+///
+/// Blank lines are not counted, so that content consisting only of them is empty.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LeadingContent {
+    /// The number of bytes skipped, blank lines included.
+    pub bytes: u64,
+    /// The number of lines skipped that are not blank.
+    pub lines: usize,
+    /// How many of those look like header fields, as those of a message whose separator was lost.
+    pub header_lines: usize,
 }
 
-struct MboxParser<I: Iterator<Item = io::Result<String>>> {
+/// A streaming parser splitting mbox data into [`Message`]s at their "From " separator lines.
+///
+/// Anything before the first separator is skipped, and described by [`MboxParser::leading`]. Reading stops at the first line that cannot
+/// be read: the part of the message before it is yielded, followed by the error. The parser is fused: once it has returned `None` it keeps
+/// doing so.
+///
+/// ```
+/// use mailfmt::MboxParser;
+///
+/// let mbox = "From a@example.com Mon Jan 01 00:00:00 2024\n\
+///             Subject: First\n\
+///             \n\
+///             Hello\n\
+///             From b@example.com Tue Jan 02 00:00:00 2024\n\
+///             Subject: Second\n";
+/// let mut parser = MboxParser::from_reader(mbox.as_bytes());
+///
+/// let first = parser.next().unwrap()?;
+/// assert_eq!(first.envelope, "a@example.com Mon Jan 01 00:00:00 2024");
+/// assert_eq!(first.lines, [&b"Subject: First"[..], b"", b"Hello"]);
+/// let second = parser.next().unwrap()?;
+/// assert_eq!(second.header("subject"), Some("Second"));
+/// assert!(parser.next().is_none());
+/// assert!(parser.next().is_none());
+/// # Ok::<(), mailfmt::MailfmtError>(())
+/// ```
+///
+/// As an iterator, it works with the usual adapters:
+///
+/// ```
+/// use mailfmt::MboxParser;
+///
+/// let mbox = "From a Mon Jan 01 00:00:00 2024\nSubject: One\n\n\
+///             From b Mon Jan 01 00:00:00 2024\nSubject: Two\n\n\
+///             From c Mon Jan 01 00:00:00 2024\nSubject: Three\n";
+/// let subjects: Vec<String> = MboxParser::from_reader(mbox.as_bytes())
+///     .skip(1)
+///     .filter_map(Result::ok)
+///     .filter_map(|message| message.header("subject").map(str::to_string))
+///     .collect();
+/// assert_eq!(subjects, ["Two", "Three"]);
+/// ```
+///
+/// Bytes that are not valid UTF-8 are kept as they are, unless another [`InvalidUtf8`] policy
+/// is chosen:
+///
+/// ```
+/// use mailfmt::{InvalidUtf8, MailfmtError, MboxParser};
+///
+/// let mbox = b"From a Mon Jan 01 00:00:00 2024\nSubject: caf\xe9\n";
+/// let message = MboxParser::from_reader(&mbox[..]).next().unwrap()?;
+/// assert_eq!(message.lines, [b"Subject: caf\xe9"]);
+/// assert_eq!(message.invalid_utf8, Some(44));
+///
+/// let mut parser = MboxParser::from_reader(&mbox[..]).with_invalid_utf8(InvalidUtf8::Lossy);
+/// assert_eq!(parser.next().unwrap()?.lines, ["Subject: caf\u{FFFD}".as_bytes()]);
+///
+/// let mut parser = MboxParser::from_reader(&mbox[..]).with_invalid_utf8(InvalidUtf8::Strict);
+/// assert!(matches!(
+///     parser.next(),
+///     Some(Err(MailfmtError::InvalidUtf8 { index: 0, offset: 44 }))
+/// ));
+/// # Ok::<(), MailfmtError>(())
+/// ```
+pub struct MboxParser<I: Iterator<Item = io::Result<OffsetLine>>> {
     lines: Peekable<I>,
+    /// Lines read ahead to decide whether a "From " line is a separator, returned before those
+    /// of `lines`.
+    lookahead: VecDeque<io::Result<OffsetLine>>,
+    invalid_utf8: InvalidUtf8,
+    smart_separators: bool,
+    dialect: Option<MboxDialect>,
+    leading: LeadingContent,
+    /// How many messages have been returned so far, including those returned as errors.
+    returned: usize,
+    /// Whether the last message returned was cut short by an error that is returned next.
+    truncated: bool,
     finished: bool,
 }
 
-impl<I: Iterator<Item = io::Result<String>>> MboxParser<I> {
-    fn new(lines: I) -> Self {
+impl<R: BufRead> MboxParser<OffsetLines<R>> {
+    /// Parse mbox data from the start of a reader.
+    pub fn from_reader(reader: R) -> Self {
+        Self::new(OffsetLines::new(reader, 0))
+    }
+}
+
+impl<I: Iterator<Item = io::Result<OffsetLine>>> MboxParser<I> {
+    pub fn new(lines: I) -> Self {
         Self {
             lines: lines.peekable(),
+            lookahead: VecDeque::new(),
+            invalid_utf8: InvalidUtf8::default(),
+            smart_separators: false,
+            dialect: None,
+            leading: LeadingContent::default(),
+            returned: 0,
+            truncated: false,
             finished: false,
         }
     }
 
-    fn next_message(&mut self) -> Option<Result<Vec<String>>> {
+    /// Set what to do with messages containing bytes that are not valid UTF-8. They are
+    /// preserved by default.
+    pub fn with_invalid_utf8(mut self, policy: InvalidUtf8) -> Self {
+        self.invalid_utf8 = policy;
+        self
+    }
+
+    /// Be more careful about "From " lines in messages declaring quoted-printable content,
+    /// which does not require them to be quoted, so that writers often leave them as they are.
+    /// Once a message, or one of its parts, has declared `Content-Transfer-Encoding:
+    /// quoted-printable`, a "From " line only starts a new message if one of the next
+    /// [`SMART_SEPARATOR_LOOKAHEAD`] lines, before any blank line, is a header field, and the
+    /// line before it is not a soft line break ending in `=`.
+    ///
+    /// ```
+    /// use mailfmt::MboxParser;
+    ///
+    /// let mbox = "From a Mon Jan 01 00:00:00 2024\n\
+    ///             Subject: Quoted\n\
+    ///             Content-Transfer-Encoding: quoted-printable\n\
+    ///             \n\
+    ///             As my colleague wrote:\n\
+    ///             \n\
+    ///             From someone@example.com Mon Jan 01 00:00:00 2024\n\
+    ///             was in the log.\n\
+    ///             \n\
+    ///             From b Mon Jan 01 00:00:00 2024\n\
+    ///             Subject: Next\n";
+    /// assert_eq!(MboxParser::from_reader(mbox.as_bytes()).count(), 3);
+    /// let parser = MboxParser::from_reader(mbox.as_bytes()).with_smart_separators(true);
+    /// assert_eq!(parser.count(), 2);
+    /// ```
+    pub fn with_smart_separators(mut self, smart: bool) -> Self {
+        self.smart_separators = smart;
+        self
+    }
+
+    /// Read the messages as `dialect` wrote them: body lines are unquoted as it quoted them,
+    /// and with [`MboxDialect::Mboxcl`] and [`MboxDialect::Mboxcl2`] the body of a message
+    /// with a Content-Length header is taken to be that long, so that "From " lines in it do
+    /// not start a new message. Without a dialect, lines are returned as they are.
+    ///
+    /// ```
+    /// use mailfmt::{MboxDialect, MboxParser};
+    ///
+    /// let mbox = "From a Mon Jan 01 00:00:00 2024\n\
+    ///             Subject: Hi\n\
+    ///             Content-Length: 27\n\
+    ///             \n\
+    ///             From here on\n\
+    ///             >From there on\n\
+    ///             \n\
+    ///             From b Mon Jan 01 00:00:00 2024\n\
+    ///             Subject: Next\n";
+    /// assert_eq!(MboxParser::from_reader(mbox.as_bytes()).count(), 3);
+    /// let mut parser =
+    ///     MboxParser::from_reader(mbox.as_bytes()).with_dialect(MboxDialect::Mboxcl2);
+    /// let message = parser.next().unwrap()?;
+    /// assert_eq!(message.lines[4], b">From there on");
+    /// assert_eq!(parser.count(), 1);
+    ///
+    /// let mbox = "From a Mon Jan 01 00:00:00 2024\n\n>From here\n>>From there\n";
+    /// let message = MboxParser::from_reader(mbox.as_bytes())
+    ///     .with_dialect(MboxDialect::Mboxrd)
+    ///     .next()
+    ///     .unwrap()?;
+    /// assert_eq!(message.lines, [&b""[..], b"From here", b">From there"]);
+    /// # Ok::<(), mailfmt::MailfmtError>(())
+    /// ```
+    pub fn with_dialect(mut self, dialect: MboxDialect) -> Self {
+        self.dialect = Some(dialect);
+        self
+    }
+
+    /// The content skipped before the first separator, known once the first message has been
+    /// returned or the input turned out to hold none.
+    ///
+    /// ```
+    /// use mailfmt::{LeadingContent, MboxParser};
+    ///
+    /// let mbox = "\n\
+    ///             Subject: Lost\n\
+    ///             \n\
+    ///             From a Mon Jan 01 00:00:00 2024\n\
+    ///             Subject: Kept\n";
+    /// let mut parser = MboxParser::from_reader(mbox.as_bytes());
+    /// assert_eq!(parser.next().unwrap()?.header("subject"), Some("Kept"));
+    /// assert_eq!(
+    ///     parser.leading(),
+    ///     LeadingContent { bytes: 16, lines: 1, header_lines: 1 }
+    /// );
+    /// # Ok::<(), mailfmt::MailfmtError>(())
+    /// ```
+    pub fn leading(&self) -> LeadingContent {
+        self.leading
+    }
+
+    /// Byte offset of the next line to be parsed, which is where the next message starts once
+    /// a message has been returned. `None` once the input is exhausted.
+    pub fn position(&mut self) -> Option<u64> {
         if self.finished {
             return None;
         }
+        match self.peek_line() {
+            Some(Ok((offset, ..))) => Some(*offset),
+            _ => None,
+        }
+    }
 
-        // Skip to next "From " line
-        while let Some(Ok(line)) = self.lines.peek() {
-            if line.starts_with("From ") {
-                self.lines.next();
+    fn peek_line(&mut self) -> Option<&io::Result<OffsetLine>> {
+        if !self.lookahead.is_empty() {
+            return self.lookahead.front();
+        }
+        self.lines.peek()
+    }
+
+    fn next_line(&mut self) -> Option<io::Result<OffsetLine>> {
+        self.lookahead.pop_front().or_else(|| self.lines.next())
+    }
+
+    /// The next line, if it was read successfully and `accept` accepts it and its offset.
+    fn next_line_if(&mut self, accept: impl Fn(u64, &[u8]) -> bool) -> Option<OffsetLine> {
+        match self.peek_line() {
+            Some(Ok((offset, line, _))) if accept(*offset, line) => self.next_line()?.ok(),
+            _ => None,
+        }
+    }
+
+    /// Whether the "From " line about to be read starts a new message, rather than being part
+    /// of a quoted-printable body after `previous`, by looking at the lines after it.
+    fn starts_message(&mut self, previous: Option<&Vec<u8>>) -> bool {
+        if previous.is_some_and(|line| line.trim_ascii_end().ends_with(b"=")) {
+            return false;
+        }
+        let mut read = Vec::new();
+        if let Some(Ok(separator)) = self.next_line() {
+            read.push(Ok(separator));
+        }
+        let mut has_header = false;
+        while read.len() <= SMART_SEPARATOR_LOOKAHEAD {
+            let Some(line) = self.next_line() else {
+                break;
+            };
+            let header = match &line {
+                Ok((_, line, _)) if !line.trim_ascii().is_empty() => {
+                    is_header_line(&String::from_utf8_lossy(line))
+                }
+                _ => {
+                    read.push(line);
+                    break;
+                }
+            };
+            read.push(line);
+            if header {
+                has_header = true;
                 break;
             }
-            self.lines.next();
         }
+        for line in read.into_iter().rev() {
+            self.lookahead.push_front(line);
+        }
+        has_header
+    }
+}
+
+/// The value of the Content-Length header in the header block of a message, if it has a valid
+/// one.
+fn content_length(header: &[Vec<u8>]) -> Option<u64> {
+    header.iter().find_map(|line| {
+        let (name, value) = line.split_at_checked(15)?;
+        match name.eq_ignore_ascii_case(b"content-length:") {
+            true => std::str::from_utf8(value).ok()?.trim().parse().ok(),
+            false => None,
+        }
+    })
+}
+
+/// How many lines after a "From " line in a quoted-printable message are looked at for a
+/// header field by [`MboxParser::with_smart_separators`].
+pub const SMART_SEPARATOR_LOOKAHEAD: usize = 3;
+
+/// Whether a line of a message declares that it, or one of its parts, is quoted-printable.
+fn declares_quoted_printable(line: &[u8]) -> bool {
+    let line = String::from_utf8_lossy(line);
+    line.split_once(':').is_some_and(|(name, value)| {
+        name.eq_ignore_ascii_case("content-transfer-encoding")
+            && value.trim().eq_ignore_ascii_case("quoted-printable")
+    })
+}
+
+impl<I: Iterator<Item = io::Result<OffsetLine>>> Iterator for MboxParser<I> {
+    type Item = Result<Message>;
 
-        let mut email_data = Vec::new();
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        // Skip to next "From " line
+        let mut start = None;
+        let mut skipped = None;
+        while let Some((offset, line, _)) = self.next_line_if(|_, _| true) {
+            if is_separator(&line) {
+                start = Some((offset, line));
+                break;
+            }
+            let first = *skipped.get_or_insert(offset);
+            // Without a following line, the line ending can only be guessed.
+            self.leading.bytes = offset + line.len() as u64 + 1 - first;
+            if !line.trim_ascii().is_empty() {
+                self.leading.lines += 1;
+                self.leading.header_lines +=
+                    usize::from(is_header_line(&String::from_utf8_lossy(&line)));
+            }
+        }
+        if let (Some(first), Some((offset, _))) = (skipped, &start) {
+            self.leading.bytes = offset - first;
+        }
+        if self.leading.lines == 0 {
+            self.leading.bytes = 0;
+        }
 
-        while let Some(line_result) = self.lines.peek() {
-            match line_result {
-                Ok(line) if line.starts_with("From ") => {
-                    return Some(Ok(email_data));
+        let (mut lines, mut invalid_utf8) = (Vec::new(), None);
+        let mut quoted_printable = false;
+        let mut final_newline = true;
+        // Whether the header block is still being read, and where a body whose length is given
+        // by its Content-Length header ends.
+        let (mut in_header, mut body_end) = (true, None);
+        loop {
+            let accept = |offset, line: &[u8]| {
+                !is_separator(line) || body_end.is_some_and(|end| offset < end)
+            };
+            let (offset, line, newline) = match self.next_line_if(accept) {
+                Some(line) => line,
+                None if quoted_printable
+                    && matches!(self.peek_line(), Some(Ok(_)))
+                    && !self.starts_message(lines.last()) =>
+                {
+                    match self.next_line() {
+                        Some(Ok(line)) => line,
+                        _ => break,
+                    }
                 }
-                Ok(_) => {
-                    if let Some(Ok(line)) = self.lines.next() {
-                        email_data.push(line);
+                None => break,
+            };
+            quoted_printable |= self.smart_separators && declares_quoted_printable(&line);
+            let (line, invalid) = self.invalid_utf8.apply(&line);
+            if let Some(position) = invalid {
+                invalid_utf8.get_or_insert(offset + position as u64);
+            }
+            let mut line = line.into_owned();
+            match self.dialect {
+                Some(dialect) if !in_header && dialect.unquote(&line).len() < line.len() => {
+                    line.remove(0);
+                }
+                Some(dialect) if in_header && line.is_empty() => {
+                    in_header = false;
+                    if dialect.content_length()
+                        && let Some(length) = content_length(&lines)
+                        && let Some(Ok((start, ..))) = self.peek_line()
+                    {
+                        body_end = Some(start + length);
                     }
                 }
-                Err(_) => {
-                    self.finished = true;
-                    return self
-                        .lines
-                        .next()
-                        .map(|r| r.map(|_| email_data).map_err(Into::into));
+                _ => {}
+            }
+            lines.push(line);
+            final_newline = newline;
+        }
+
+        match self.peek_line() {
+            // The next message's separator.
+            Some(Ok(_)) => {}
+            // The lines read so far are returned first, and the error on the next call.
+            Some(Err(_)) if start.is_some() => self.truncated = true,
+            Some(Err(_)) => {
+                self.finished = true;
+                let index = self.returned - usize::from(self.truncated);
+                return self
+                    .next_line()?
+                    .err()
+                    .map(|source| Err(MailfmtError::MessageParse { index, source }));
+            }
+            None => {
+                self.finished = true;
+                // A separator at the very end of the input, followed by nothing but blank lines,
+                // does not start a message.
+                if lines.iter().all(|line| line.trim_ascii().is_empty()) {
+                    return None;
                 }
             }
         }
 
-        self.finished = true;
-        if email_data.is_empty() {
-            None
-        } else {
-            Some(Ok(email_data))
+        let (offset, separator) = start?;
+        let index = self.returned;
+        self.returned += 1;
+        if self.invalid_utf8 == InvalidUtf8::Strict
+            && let Some(offset) = invalid_utf8
+        {
+            return Some(Err(MailfmtError::InvalidUtf8 { index, offset }));
         }
+        Some(Ok(Message {
+            offset,
+            envelope: String::from_utf8_lossy(separator.strip_prefix(b"From ").unwrap_or_default())
+                .into_owned(),
+            lines,
+            final_newline,
+            invalid_utf8,
+        }))
     }
 }
+
+impl<I: Iterator<Item = io::Result<OffsetLine>>> FusedIterator for MboxParser<I> {}
@@ -0,0 +1,607 @@
+//! Just enough MIME to find the parts of a message, what they declare about themselves, and
+//! their decoded content.
+
+use crate::{charset::Charset, headers::decode_encoded_words};
+use std::ops::Range;
+
+/// A message or one of its parts: a header block and the body after it.
+pub(crate) struct Entity<'a> {
+    pub header: &'a [Vec<u8>],
+    pub body: &'a [Vec<u8>],
+    pub fields: Vec<Field>,
+    pub content_type: ContentType,
+}
+
+impl<'a> Entity<'a> {
+    /// Split the lines of a message or part at the blank line ending its header block. Without
+    /// one, it is all header.
+    pub fn parse(lines: &'a [Vec<u8>]) -> Self {
+        let header_end = lines
+            .iter()
+            .position(|line| line.is_empty())
+            .unwrap_or(lines.len());
+        let header = &lines[..header_end];
+        let fields = fields(header);
+        let content_type = fields
+            .iter()
+            .find(|field| field.is("content-type"))
+            .map(|field| ContentType::parse(&field.value))
+            .unwrap_or_default();
+        Self {
+            header,
+            body: &lines[(header_end + 1).min(lines.len())..],
+            fields,
+            content_type,
+        }
+    }
+
+    /// The lowercased Content-Transfer-Encoding, 7bit when none is declared.
+    pub fn transfer_encoding(&self) -> String {
+        self.fields
+            .iter()
+            .find(|field| field.is("content-transfer-encoding"))
+            .map_or_else(
+                || "7bit".to_string(),
+                |field| field.value.trim().to_ascii_lowercase(),
+            )
+    }
+
+    /// The body decoded from its transfer encoding, or `None` if the encoding is unknown or
+    /// the body is not valid in it. Lines are joined with '\n'.
+    pub fn decode_body(&self) -> Option<Vec<u8>> {
+        match self.transfer_encoding().as_str() {
+            "7bit" | "8bit" | "binary" => Some(self.body.join(&b'\n')),
+            "quoted-printable" => Some(decode_quoted_printable(self.body)),
+            "base64" => decode_base64(self.body.iter().flatten()),
+            _ => None,
+        }
+    }
+
+    /// Whether the entity declares itself to be an attachment rather than part of the message
+    /// text.
+    pub fn is_attachment(&self) -> bool {
+        self.fields
+            .iter()
+            .find(|field| field.is("content-disposition"))
+            .is_some_and(|field| {
+                field
+                    .value
+                    .trim()
+                    .to_ascii_lowercase()
+                    .starts_with("attachment")
+            })
+    }
+
+    /// Whether the entity is a file rather than message text: an attachment, a part with a file
+    /// name, or a part that is neither text nor multipart.
+    pub fn is_file(&self) -> bool {
+        !self.content_type.is("multipart")
+            && (self.is_attachment() || !self.content_type.is("text") || self.filename().is_some())
+    }
+
+    /// The name of the file the entity holds, from the filename parameter of its
+    /// Content-Disposition or else the name parameter of its Content-Type, decoded.
+    pub fn filename(&self) -> Option<String> {
+        let disposition = self
+            .fields
+            .iter()
+            .find(|field| field.is("content-disposition"))
+            .map(|field| ContentType::parse(&field.value));
+        let name = disposition
+            .and_then(|disposition| param_value(&disposition, "filename"))
+            .or_else(|| param_value(&self.content_type, "name"))?;
+        Some(decode_encoded_words(&name)).filter(|name| !name.is_empty())
+    }
+
+    /// The parts of a multipart entity, or `None` if it is not multipart or its boundary is
+    /// missing or never used.
+    pub fn multipart(&self) -> Option<Multipart> {
+        if !self.content_type.is("multipart") {
+            return None;
+        }
+        Multipart::split(self.body, &self.content_type.param("boundary")?)
+    }
+}
+
+/// The decoded plain text of a message, as returned by [`Message::text`](crate::Message::text).
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedText {
+    pub text: String,
+    /// Why the text could not be fully decoded, if it could not. The text is then given as it
+    /// was where decoding failed.
+    pub warning: Option<String>,
+}
+
+/// Find the first plain text part of a message that is not an attachment, or its body if it
+/// is not a MIME message, and decode it. See [`Message::text`](crate::Message::text).
+pub(crate) fn decode_text(lines: &[Vec<u8>]) -> Option<DecodedText> {
+    let mut text = None;
+    for_each_part(lines, &mut |part| {
+        if text.is_none()
+            && part
+                .content_type
+                .media_type
+                .eq_ignore_ascii_case("text/plain")
+            && !part.is_attachment()
+        {
+            text = Some(decode_text_part(part));
+        }
+    });
+    text
+}
+
+fn decode_text_part(part: &Entity<'_>) -> DecodedText {
+    let mut warning = None;
+    let bytes = part.decode_body().unwrap_or_else(|| {
+        warning = Some(format!(
+            "its {} transfer encoding could not be decoded, so it is given as it was",
+            part.transfer_encoding()
+        ));
+        part.body.join(&b'\n')
+    });
+    let text = decode_charset(bytes, part.content_type.param("charset"), &mut warning);
+    DecodedText {
+        text: text.replace("\r\n", "\n"),
+        warning,
+    }
+}
+
+/// Find the first HTML part of a message that is not an attachment and decode it, taking its
+/// charset from a `<meta>` element when its Content-Type declares none. `None` if it has no
+/// such part, or its transfer encoding cannot be decoded.
+pub(crate) fn decode_html(lines: &[Vec<u8>]) -> Option<DecodedText> {
+    let mut html = None;
+    for_each_part(lines, &mut |part| {
+        if html.is_none()
+            && part
+                .content_type
+                .media_type
+                .eq_ignore_ascii_case("text/html")
+            && !part.is_attachment()
+        {
+            html = Some(part.decode_body().map(|bytes| {
+                let mut warning = None;
+                let label = part
+                    .content_type
+                    .param("charset")
+                    .or_else(|| meta_charset(&bytes));
+                let text = decode_charset(bytes, label, &mut warning);
+                DecodedText { text, warning }
+            }));
+        }
+    });
+    html.flatten()
+}
+
+/// The charset declared by a `<meta charset>` or `<meta http-equiv="Content-Type">` element
+/// near the start of an HTML document.
+fn meta_charset(html: &[u8]) -> Option<String> {
+    let start = String::from_utf8_lossy(&html[..html.len().min(4096)]).to_ascii_lowercase();
+    start.match_indices("<meta").find_map(|(position, _)| {
+        let tag = &start[position..];
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        let value = tag[tag.find("charset")? + "charset".len()..]
+            .trim_start()
+            .strip_prefix('=')?
+            .trim_start_matches([' ', '"', '\'']);
+        let end = value
+            .find(|c: char| !c.is_ascii_alphanumeric() && !matches!(c, '-' | '_' | '.' | ':'))
+            .unwrap_or(value.len());
+        (end > 0).then(|| value[..end].to_string())
+    })
+}
+
+/// Decode text in the charset with the given label, noting in `warning` when it cannot be.
+fn decode_charset(bytes: Vec<u8>, label: Option<String>, warning: &mut Option<String>) -> String {
+    match label {
+        Some(label) => match Charset::for_label(&label) {
+            Some(charset) => charset.decode(&bytes).unwrap_or_else(|| {
+                warning.get_or_insert_with(|| {
+                    format!("its text is not valid in its declared charset '{label}'")
+                });
+                String::from_utf8_lossy(&bytes).into_owned()
+            }),
+            None => {
+                warning.get_or_insert_with(|| {
+                    format!("its charset '{label}' is not supported, so it was decoded as UTF-8")
+                });
+                String::from_utf8_lossy(&bytes).into_owned()
+            }
+        },
+        // Without a declared charset, anything that is not UTF-8 is most likely Windows-1252.
+        None => String::from_utf8(bytes).unwrap_or_else(|e| {
+            Charset::fallback()
+                .decode(e.as_bytes())
+                .expect("Windows-1252 decodes anything")
+        }),
+    }
+}
+
+/// How deeply multipart entities are descended into, so that a malformed or hostile message
+/// cannot exhaust the stack.
+pub(crate) const MAX_DEPTH: usize = 32;
+
+/// Call `visit` with a message and each of its parts, parents before their children.
+pub(crate) fn for_each_part(lines: &[Vec<u8>], visit: &mut impl FnMut(&Entity<'_>)) {
+    fn walk(lines: &[Vec<u8>], depth: usize, visit: &mut impl FnMut(&Entity<'_>)) {
+        let entity = Entity::parse(lines);
+        visit(&entity);
+        if depth < MAX_DEPTH
+            && let Some(multipart) = entity.multipart()
+        {
+            for part in multipart.parts() {
+                walk(&entity.body[part], depth + 1, visit);
+            }
+        }
+    }
+    walk(lines, 0, visit);
+}
+
+/// Call `visit` with every message/rfc822 part of a message, in order, including the parts of
+/// a multipart/digest that declare no Content-Type, as those default to message/rfc822. The
+/// messages inside of them are not descended into.
+pub(crate) fn for_each_embedded(lines: &[Vec<u8>], visit: &mut impl FnMut(&Entity<'_>)) {
+    fn walk(lines: &[Vec<u8>], depth: usize, digest: bool, visit: &mut impl FnMut(&Entity<'_>)) {
+        let entity = Entity::parse(lines);
+        let declared = entity.fields.iter().any(|field| field.is("content-type"));
+        if (depth > 0 && digest && !declared)
+            || entity
+                .content_type
+                .media_type
+                .trim()
+                .eq_ignore_ascii_case("message/rfc822")
+        {
+            visit(&entity);
+            return;
+        }
+        if depth < MAX_DEPTH
+            && let Some(multipart) = entity.multipart()
+        {
+            let digest = entity
+                .content_type
+                .media_type
+                .trim()
+                .eq_ignore_ascii_case("multipart/digest");
+            for part in multipart.parts() {
+                walk(&entity.body[part], depth + 1, digest, visit);
+            }
+        }
+    }
+    walk(lines, 0, false, visit);
+}
+
+/// Why the parts of a message cannot all be found, if they cannot: a multipart entity has no
+/// boundary or never uses it, or multipart entities are nested too deeply.
+pub(crate) fn structure_problem(lines: &[Vec<u8>]) -> Option<String> {
+    fn walk(lines: &[Vec<u8>], depth: usize) -> Option<String> {
+        let entity = Entity::parse(lines);
+        if !entity.content_type.is("multipart") {
+            return None;
+        }
+        if depth >= MAX_DEPTH {
+            return Some(format!(
+                "multipart parts are nested more than {MAX_DEPTH} deep"
+            ));
+        }
+        let Some(boundary) = entity.content_type.param("boundary") else {
+            return Some(format!(
+                "a {} part has no boundary",
+                entity.content_type.media_type
+            ));
+        };
+        let Some(multipart) = Multipart::split(entity.body, &boundary) else {
+            return Some(format!("the boundary '{boundary}' is never used"));
+        };
+        multipart
+            .parts()
+            .find_map(|part| walk(&entity.body[part], depth + 1))
+    }
+    walk(lines, 0)
+}
+
+/// A header field, spanning one line and any continuation lines after it.
+pub(crate) struct Field {
+    /// The lines of the header block the field spans.
+    pub lines: Range<usize>,
+    pub name: String,
+    /// The value with its continuation lines unfolded.
+    pub value: String,
+}
+
+impl Field {
+    /// Whether the field has the given name, matched case-insensitively.
+    pub fn is(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+}
+
+/// Split a header block into its fields. Every line belongs to a field, a stray continuation
+/// line at the start forming one without a name.
+fn fields(header: &[Vec<u8>]) -> Vec<Field> {
+    let mut fields: Vec<Field> = Vec::new();
+    for (index, line) in header.iter().enumerate() {
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with([' ', '\t'])
+            && let Some(field) = fields.last_mut()
+        {
+            field.lines.end = index + 1;
+            field.value.push_str(&line);
+            continue;
+        }
+        let (name, value) = line.split_once(':').unwrap_or_default();
+        fields.push(Field {
+            lines: index..index + 1,
+            name: name.trim().to_string(),
+            value: value.to_string(),
+        });
+    }
+    fields
+}
+
+/// A parsed Content-Type value, text/plain when none was given.
+pub(crate) struct ContentType {
+    pub media_type: String,
+    /// The parameters as written, each with its lowercased name.
+    pub params: Vec<(String, String)>,
+}
+
+impl Default for ContentType {
+    fn default() -> Self {
+        Self {
+            media_type: "text/plain".to_string(),
+            params: Vec::new(),
+        }
+    }
+}
+
+impl ContentType {
+    fn parse(value: &str) -> Self {
+        let mut parts = split_params(value).into_iter();
+        let media_type = parts.next().unwrap_or_default();
+        Self {
+            media_type: media_type.trim().to_string(),
+            params: parts
+                .filter_map(|param| {
+                    let (name, _) = param.split_once('=')?;
+                    Some((name.trim().to_ascii_lowercase(), param.trim().to_string()))
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether the media type is of the given top-level type, such as "text".
+    pub fn is(&self, kind: &str) -> bool {
+        self.media_type
+            .split_once('/')
+            .is_some_and(|(top, _)| top.trim().eq_ignore_ascii_case(kind))
+    }
+
+    /// The unquoted value of a parameter.
+    pub fn param(&self, name: &str) -> Option<String> {
+        let (_, param) = self.params.iter().find(|(n, _)| n == name)?;
+        let (_, value) = param.split_once('=')?;
+        let value = value.trim();
+        Some(
+            match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+                None => value.to_string(),
+            },
+        )
+    }
+}
+
+/// A parameter that may be given in the extended form of RFC 2231, such as
+/// `filename*=utf-8''caf%C3%A9`, which is preferred over the plain one. Continuations of the
+/// extended form are not supported.
+fn param_value(params: &ContentType, name: &str) -> Option<String> {
+    if let Some(extended) = params.param(&format!("{name}*"))
+        && let Some((label, rest)) = extended.split_once('\'')
+        && let Some((_language, encoded)) = rest.split_once('\'')
+    {
+        let bytes = decode_percent(encoded.as_bytes());
+        let charset = Charset::for_label(label).unwrap_or(Charset::Utf8);
+        if let Some(value) = charset.decode(&bytes) {
+            return Some(value);
+        }
+    }
+    params.param(name)
+}
+
+/// Decode %XX escapes, keeping malformed ones as they are.
+pub(crate) fn decode_percent(encoded: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] == b'%'
+            && let Some(byte) = encoded
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(encoded[i]);
+            i += 1;
+        }
+    }
+    decoded
+}
+
+/// Split a header value at the semicolons separating its parameters, ignoring those inside
+/// quoted strings.
+fn split_params(value: &str) -> Vec<String> {
+    let (mut parts, mut current) = (Vec::new(), String::new());
+    let (mut quoted, mut escaped) = (false, false);
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                parts.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    parts.push(current);
+    parts
+}
+
+/// Where the parts of a multipart body are, found from the lines delimiting them.
+pub(crate) struct Multipart {
+    /// The indices of the delimiter lines in the body, followed by the closing delimiter or,
+    /// for an unterminated body, the end of the body.
+    pub delimiters: Vec<usize>,
+    /// Whether the body has a closing delimiter, which may be followed by an epilogue.
+    pub closed: bool,
+}
+
+impl Multipart {
+    fn split(body: &[Vec<u8>], boundary: &str) -> Option<Self> {
+        let delimiter = format!("--{boundary}");
+        let (mut delimiters, mut closed) = (Vec::new(), false);
+        for (index, line) in body.iter().enumerate() {
+            match line.trim_ascii_end().strip_prefix(delimiter.as_bytes()) {
+                Some(b"") => delimiters.push(index),
+                Some(b"--") => {
+                    delimiters.push(index);
+                    closed = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        if delimiters.is_empty() {
+            return None;
+        }
+        // An unterminated body ends with its last part.
+        if !closed {
+            delimiters.push(body.len());
+        }
+        Some(Self { delimiters, closed })
+    }
+
+    /// The lines of the body holding each part, between the delimiters.
+    pub fn parts(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.delimiters
+            .windows(2)
+            .map(|delimiters| delimiters[0] + 1..delimiters[1])
+    }
+}
+
+/// Decode quoted-printable lines. A malformed escape is kept as it is, as most readers do.
+fn decode_quoted_printable(lines: &[Vec<u8>]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let line = line.trim_ascii_end();
+        let (line, soft_break) = match line.strip_suffix(b"=") {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        let mut i = 0;
+        while i < line.len() {
+            if line[i] == b'='
+                && let Some(byte) = line
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                decoded.push(byte);
+                i += 3;
+            } else {
+                decoded.push(line[i]);
+                i += 1;
+            }
+        }
+        if !soft_break && index + 1 < lines.len() {
+            decoded.push(b'\n');
+        }
+    }
+    decoded
+}
+
+/// Decode base64, ignoring whitespace and anything after the padding. Missing padding
+/// is tolerated, but not a lone character left over at the end.
+pub(crate) fn decode_base64<'a>(encoded: impl IntoIterator<Item = &'a u8>) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &b in encoded {
+        let value = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            b if b.is_ascii_whitespace() => continue,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    // Four characters decode to three bytes, so a single one left over cannot be decoded.
+    (bits != 6).then_some(decoded)
+}
+
+/// Encode bytes as base64 with padding, on a single line.
+pub(crate) fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let buffer = chunk.iter().enumerate().fold(0u32, |buffer, (i, &b)| {
+            buffer | u32::from(b) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            encoded.push(match i <= chunk.len() {
+                true => char::from(ALPHABET[(buffer >> (18 - 6 * i) & 63) as usize]),
+                false => '=',
+            });
+        }
+    }
+    encoded
+}
+
+/// Encode text as quoted-printable lines of at most 76 characters. Text that does not end with
+/// a line break ends with a soft line break, so that decoding gives back exactly the text even
+/// when a blank line follows.
+pub(crate) fn encode_quoted_printable(text: &str) -> Vec<Vec<u8>> {
+    const MAX_LINE: usize = 76;
+    let mut lines = Vec::new();
+    for line in text.split('\n') {
+        let mut encoded = Vec::new();
+        for (i, &b) in line.as_bytes().iter().enumerate() {
+            let last = i + 1 == line.len();
+            let literal = match b {
+                b' ' | b'\t' => !last,
+                b'=' => false,
+                b => (b'!'..=b'~').contains(&b),
+            };
+            let piece = match literal {
+                true => vec![b],
+                false => format!("={b:02X}").into_bytes(),
+            };
+            // Room is kept for the '=' of a soft line break.
+            if encoded.len() + piece.len() > MAX_LINE - 1 {
+                encoded.push(b'=');
+                lines.push(std::mem::take(&mut encoded));
+            }
+            encoded.extend(piece);
+        }
+        lines.push(encoded);
+    }
+    if !text.is_empty()
+        && !text.ends_with('\n')
+        && let Some(last) = lines.last_mut()
+    {
+        last.push(b'=');
+    }
+    lines
+}
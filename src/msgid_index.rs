@@ -0,0 +1,132 @@
+//! The index of Message-IDs written alongside a conversion, mapping every message to where it
+//! was written.
+
+use crate::{
+    MessageIdIndex,
+    atomic::AtomicFile,
+    duplicates::normalize_message_id,
+    error::{IoContext, Result},
+};
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Where a message was written: an eml file, relative to the output directory, or the byte
+/// offset of its separator line in the output mbox.
+pub(crate) enum IndexLocation<'a> {
+    File(&'a Path),
+    Offset(u64),
+}
+
+/// The synthetic key of a message without a Message-ID: `sha256:` and the SHA-256 of its
+/// lines, given without line endings, each ended with LF.
+pub(crate) fn content_key<'l>(lines: impl IntoIterator<Item = &'l [u8]>) -> String {
+    let mut hasher = Sha256::new();
+    for line in lines {
+        hasher.update(line.strip_suffix(b"\r").unwrap_or(line));
+        hasher.update(b"\n");
+    }
+    format!("sha256:{:x}", hasher.finalize())
+}
+
+/// Writes a Message-ID index under a temporary name as messages are converted, moving it into
+/// place once the conversion is over.
+///
+/// Every line holds a message's Message-ID, without the whitespace and angle brackets around
+/// it, its location, and whether the ID is synthetic: a message without a Message-ID is keyed
+/// by its [`content_key`] instead. A path ending in `.jsonl` gets a JSON object on every line
+/// with `message_id`, `file` or `offset` and `synthetic`. Any other path gets tab-separated
+/// values under a header line.
+pub(crate) struct IndexWriter {
+    file: BufWriter<AtomicFile>,
+    path: PathBuf,
+    json: bool,
+    entries: usize,
+    synthetic: usize,
+}
+
+impl IndexWriter {
+    /// Start an index for messages written to eml files, or to an mbox if `offsets` is set.
+    pub(crate) fn create(path: &Path, offsets: bool) -> Result<Self> {
+        let mut file =
+            BufWriter::new(AtomicFile::create(path).io_context("create Message-ID index", path)?);
+        let json = path
+            .extension()
+            .is_some_and(|extension| extension == "jsonl");
+        if !json {
+            let location = if offsets { "offset" } else { "file" };
+            writeln!(file, "message_id\t{location}\tsynthetic")
+                .io_context("write Message-ID index", path)?;
+        }
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            json,
+            entries: 0,
+            synthetic: 0,
+        })
+    }
+
+    /// Add a message with the given Message-ID header value, keyed by its [`content_key`]
+    /// instead if it has none.
+    pub(crate) fn add(
+        &mut self,
+        message_id: Option<&str>,
+        content_key: impl FnOnce() -> String,
+        location: IndexLocation<'_>,
+    ) -> Result<()> {
+        let (message_id, synthetic) = match message_id.and_then(normalize_message_id) {
+            Some(message_id) => (message_id, false),
+            None => (content_key(), true),
+        };
+        let result = if self.json {
+            let location = match location {
+                IndexLocation::File(file) => ("file", json!(file.to_string_lossy())),
+                IndexLocation::Offset(offset) => ("offset", json!(offset)),
+            };
+            let entry: Map<String, Value> = [
+                ("message_id", json!(message_id)),
+                location,
+                ("synthetic", json!(synthetic)),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+            let entry = Value::Object(entry);
+            writeln!(self.file, "{entry}")
+        } else {
+            let location = match location {
+                IndexLocation::File(file) => file.to_string_lossy().into_owned(),
+                IndexLocation::Offset(offset) => offset.to_string(),
+            };
+            writeln!(
+                self.file,
+                "{}\t{}\t{synthetic}",
+                message_id.replace(['\t', '\n', '\r'], " "),
+                location.replace(['\t', '\n', '\r'], " ")
+            )
+        };
+        result.io_context("write Message-ID index", &self.path)?;
+        self.entries += 1;
+        self.synthetic += usize::from(synthetic);
+        Ok(())
+    }
+
+    /// Move the index into place, returning how many entries it holds.
+    pub(crate) fn finish(self) -> Result<MessageIdIndex> {
+        let file = self
+            .file
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .io_context("write Message-ID index", &self.path)?;
+        file.commit()
+            .io_context("move Message-ID index into place", &self.path)?;
+        Ok(MessageIdIndex {
+            entries: self.entries,
+            synthetic: self.synthetic,
+        })
+    }
+}
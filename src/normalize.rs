@@ -0,0 +1,343 @@
+//! Unicode normalization and ASCII transliteration of the subjects used in file names, so that
+//! names which look the same are the same.
+
+use std::{borrow::Cow, fmt, str::FromStr};
+use unicode_normalization::{
+    UnicodeNormalization,
+    char::{canonical_combining_class, decompose_canonical},
+};
+
+/// The Unicode normalization form applied to the subject part of eml file names.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    /// Composed characters, such as a single "é", as most systems expect.
+    #[default]
+    Nfc,
+    /// Decomposed characters, such as "e" followed by a combining acute accent, as HFS+ stores
+    /// file names.
+    Nfd,
+    /// Subjects are used as they were decoded.
+    None,
+}
+
+impl fmt::Display for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Nfc => "nfc",
+            Self::Nfd => "nfd",
+            Self::None => "none",
+        })
+    }
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nfc" => Ok(Self::Nfc),
+            "nfd" => Ok(Self::Nfd),
+            "none" => Ok(Self::None),
+            _ => Err(format!(
+                "unknown normalization '{s}', expected nfc, nfd or none"
+            )),
+        }
+    }
+}
+
+/// Normalize text to the given form, borrowing it when nothing has to change.
+///
+/// ```
+/// use mailfmt::{Normalization, normalize};
+///
+/// let precomposed = "Caf\u{e9}";
+/// let decomposed = "Cafe\u{301}";
+/// assert_ne!(precomposed, decomposed);
+/// assert_eq!(normalize(decomposed, Normalization::Nfc), precomposed);
+/// assert_eq!(normalize(precomposed, Normalization::Nfd), decomposed);
+/// assert_eq!(
+///     normalize(precomposed, Normalization::Nfc),
+///     normalize(decomposed, Normalization::Nfc)
+/// );
+/// assert_ne!(
+///     normalize(precomposed, Normalization::None),
+///     normalize(decomposed, Normalization::None)
+/// );
+/// ```
+pub fn normalize(text: &str, form: Normalization) -> Cow<'_, str> {
+    if text.is_ascii() || form == Normalization::None {
+        return Cow::Borrowed(text);
+    }
+    let normalized: String = match form {
+        Normalization::Nfc => text.nfc().collect(),
+        _ => text.nfd().collect(),
+    };
+    match normalized == text {
+        true => Cow::Borrowed(text),
+        false => Cow::Owned(normalized),
+    }
+}
+
+/// What replaces characters that have no ASCII spelling in [`transliterate`].
+const REPLACEMENT: char = '_';
+
+/// Transliterate text to ASCII for file names: accents are dropped, letters such as "ß" and
+/// Cyrillic and Greek ones are spelled out, and every run of other characters, such as CJK
+/// ones, becomes a single '_'. Returns `None` if no letter or digit is left.
+///
+/// ```
+/// use mailfmt::transliterate;
+///
+/// assert_eq!(transliterate("\u{dc}berweisung").as_deref(), Some("Uberweisung"));
+/// assert_eq!(
+///     transliterate("Stra\u{df}e \u{2013} \u{41f}\u{440}\u{438}\u{432}\u{435}\u{442}").as_deref(),
+///     Some("Strasse - Privet")
+/// );
+/// assert_eq!(transliterate("\u{4f1a}\u{8b70} 2024").as_deref(), Some("2024"));
+/// assert_eq!(transliterate("\u{4f1a}\u{8b70}"), None);
+/// ```
+pub fn transliterate(text: &str) -> Option<String> {
+    let mut ascii = String::with_capacity(text.len());
+    let mut decomposed = Vec::new();
+    for c in text.chars() {
+        if c.is_ascii() {
+            ascii.push(if c.is_ascii_control() { REPLACEMENT } else { c });
+            continue;
+        }
+        if let Some(spelling) = spell_out(c) {
+            ascii.push_str(spelling);
+            continue;
+        }
+        decomposed.clear();
+        decompose_canonical(c, |part| decomposed.push(part));
+        for &part in &decomposed {
+            if part.is_ascii() {
+                ascii.push(part);
+            } else if let Some(spelling) = spell_out(part) {
+                ascii.push_str(spelling);
+            } else if canonical_combining_class(part) == 0 {
+                ascii.push(REPLACEMENT);
+            }
+        }
+    }
+
+    let mut collapsed = String::with_capacity(ascii.len());
+    for c in ascii.chars() {
+        if !(c == REPLACEMENT && collapsed.ends_with(REPLACEMENT)) {
+            collapsed.push(c);
+        }
+    }
+    let trimmed = collapsed.trim_matches(|c: char| c == REPLACEMENT || c.is_whitespace());
+    trimmed
+        .chars()
+        .any(|c| c.is_ascii_alphanumeric())
+        .then(|| trimmed.to_string())
+}
+
+/// The ASCII spelling of a character that is not a letter with accents.
+fn spell_out(c: char) -> Option<&'static str> {
+    SPELLINGS
+        .binary_search_by_key(&c, |&(character, _)| character)
+        .ok()
+        .map(|found| SPELLINGS[found].1)
+}
+
+/// ASCII spellings of letters and punctuation that do not decompose to ASCII, sorted by
+/// character.
+static SPELLINGS: &[(char, &str)] = &[
+    ('\u{A0}', " "),
+    ('\u{A1}', "!"),
+    ('\u{A3}', "GBP"),
+    ('\u{A5}', "JPY"),
+    ('\u{A9}', "(c)"),
+    ('\u{AB}', "\""),
+    ('\u{AE}', "(R)"),
+    ('\u{B0}', "deg"),
+    ('\u{B7}', "."),
+    ('\u{BB}', "\""),
+    ('\u{BC}', "1/4"),
+    ('\u{BD}', "1/2"),
+    ('\u{BE}', "3/4"),
+    ('\u{BF}', "?"),
+    ('Æ', "AE"),
+    ('Ð', "D"),
+    ('\u{D7}', "x"),
+    ('Ø', "O"),
+    ('Þ', "Th"),
+    ('ß', "ss"),
+    ('æ', "ae"),
+    ('ð', "d"),
+    ('\u{F7}', "/"),
+    ('ø', "o"),
+    ('þ', "th"),
+    ('Đ', "D"),
+    ('đ', "d"),
+    ('Ħ', "H"),
+    ('ħ', "h"),
+    ('ı', "i"),
+    ('Ĳ', "IJ"),
+    ('ĳ', "ij"),
+    ('ĸ', "k"),
+    ('Ŀ', "L"),
+    ('ŀ', "l"),
+    ('Ł', "L"),
+    ('ł', "l"),
+    ('ŉ', "'n"),
+    ('Ŋ', "N"),
+    ('ŋ', "n"),
+    ('Œ', "OE"),
+    ('œ', "oe"),
+    ('Ŧ', "T"),
+    ('ŧ', "t"),
+    ('ſ', "s"),
+    ('ƒ', "f"),
+    ('Α', "A"),
+    ('Β', "V"),
+    ('Γ', "G"),
+    ('Δ', "D"),
+    ('Ε', "E"),
+    ('Ζ', "Z"),
+    ('Η', "I"),
+    ('Θ', "Th"),
+    ('Ι', "I"),
+    ('Κ', "K"),
+    ('Λ', "L"),
+    ('Μ', "M"),
+    ('Ν', "N"),
+    ('Ξ', "X"),
+    ('Ο', "O"),
+    ('Π', "P"),
+    ('Ρ', "R"),
+    ('Σ', "S"),
+    ('Τ', "T"),
+    ('Υ', "Y"),
+    ('Φ', "F"),
+    ('Χ', "Ch"),
+    ('Ψ', "Ps"),
+    ('Ω', "O"),
+    ('α', "a"),
+    ('β', "v"),
+    ('γ', "g"),
+    ('δ', "d"),
+    ('ε', "e"),
+    ('ζ', "z"),
+    ('η', "i"),
+    ('θ', "th"),
+    ('ι', "i"),
+    ('κ', "k"),
+    ('λ', "l"),
+    ('μ', "m"),
+    ('ν', "n"),
+    ('ξ', "x"),
+    ('ο', "o"),
+    ('π', "p"),
+    ('ρ', "r"),
+    ('ς', "s"),
+    ('σ', "s"),
+    ('τ', "t"),
+    ('υ', "y"),
+    ('φ', "f"),
+    ('χ', "ch"),
+    ('ψ', "ps"),
+    ('ω', "o"),
+    ('Ё', "Yo"),
+    ('Є', "Ye"),
+    ('І', "I"),
+    ('Ї', "Yi"),
+    ('Ў', "U"),
+    ('А', "A"),
+    ('Б', "B"),
+    ('В', "V"),
+    ('Г', "G"),
+    ('Д', "D"),
+    ('Е', "E"),
+    ('Ж', "Zh"),
+    ('З', "Z"),
+    ('И', "I"),
+    ('Й', "Y"),
+    ('К', "K"),
+    ('Л', "L"),
+    ('М', "M"),
+    ('Н', "N"),
+    ('О', "O"),
+    ('П', "P"),
+    ('Р', "R"),
+    ('С', "S"),
+    ('Т', "T"),
+    ('У', "U"),
+    ('Ф', "F"),
+    ('Х', "Kh"),
+    ('Ц', "Ts"),
+    ('Ч', "Ch"),
+    ('Ш', "Sh"),
+    ('Щ', "Shch"),
+    ('Ъ', ""),
+    ('Ы', "Y"),
+    ('Ь', ""),
+    ('Э', "E"),
+    ('Ю', "Yu"),
+    ('Я', "Ya"),
+    ('а', "a"),
+    ('б', "b"),
+    ('в', "v"),
+    ('г', "g"),
+    ('д', "d"),
+    ('е', "e"),
+    ('ж', "zh"),
+    ('з', "z"),
+    ('и', "i"),
+    ('й', "y"),
+    ('к', "k"),
+    ('л', "l"),
+    ('м', "m"),
+    ('н', "n"),
+    ('о', "o"),
+    ('п', "p"),
+    ('р', "r"),
+    ('с', "s"),
+    ('т', "t"),
+    ('у', "u"),
+    ('ф', "f"),
+    ('х', "kh"),
+    ('ц', "ts"),
+    ('ч', "ch"),
+    ('ш', "sh"),
+    ('щ', "shch"),
+    ('ъ', ""),
+    ('ы', "y"),
+    ('ь', ""),
+    ('э', "e"),
+    ('ю', "yu"),
+    ('я', "ya"),
+    ('ё', "yo"),
+    ('є', "ye"),
+    ('і', "i"),
+    ('ї', "yi"),
+    ('ў', "u"),
+    ('Ґ', "G"),
+    ('ґ', "g"),
+    ('ẞ', "SS"),
+    ('\u{2002}', " "),
+    ('\u{2003}', " "),
+    ('\u{2009}', " "),
+    ('\u{2010}', "-"),
+    ('\u{2011}', "-"),
+    ('\u{2012}', "-"),
+    ('\u{2013}', "-"),
+    ('\u{2014}', "-"),
+    ('\u{2015}', "-"),
+    ('\u{2018}', "'"),
+    ('\u{2019}', "'"),
+    ('\u{201A}', "'"),
+    ('\u{201B}', "'"),
+    ('\u{201C}', "\""),
+    ('\u{201D}', "\""),
+    ('\u{201E}', "\""),
+    ('\u{2022}', "*"),
+    ('\u{2026}', "..."),
+    ('\u{2039}', "'"),
+    ('\u{203A}', "'"),
+    ('\u{20AC}', "EUR"),
+    ('\u{2122}', "TM"),
+    ('\u{3000}', " "),
+];
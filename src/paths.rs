@@ -0,0 +1,138 @@
+use glob::MatchOptions;
+use std::{
+    borrow::Cow,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// How glob patterns are matched: case-insensitively, with `*` and `?` not matching a `/`.
+pub const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Resolve a path to an absolute form with symlinks and `..` components removed, even when
+/// the path itself does not exist yet. The deepest existing ancestor is canonicalized and the
+/// remaining components are appended to it.
+pub fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+
+    let mut existing = absolute.as_path();
+    let mut missing = Vec::new();
+    loop {
+        match existing.canonicalize() {
+            Ok(canonical) => {
+                let mut resolved = canonical;
+                for component in missing.iter().rev() {
+                    if *component == ".." {
+                        resolved.pop();
+                    } else if *component != "." {
+                        resolved.push(component);
+                    }
+                }
+                return Ok(resolved);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let (Some(parent), Some(last)) =
+                    (existing.parent(), existing.components().next_back())
+                else {
+                    return Err(e);
+                };
+                missing.push(last.as_os_str().to_os_string());
+                existing = parent;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether two paths refer to the same existing file, even when they are spelled differently
+/// (symlinks, hard links, or case-insensitive filesystems).
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (std::fs::metadata(a), std::fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        match (a.canonicalize(), b.canonicalize()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// The form of a path that is not limited to the 260 characters of `MAX_PATH` on Windows, such
+/// as `\\?\C:\archive\2024\01\0001_Subject.eml`, or `\\?\UNC\server\share\...` for a network
+/// share, to create directories and files with. Paths shown to users and written to manifests
+/// keep their normal form. Other platforms have no such limit and get the path back as it is.
+pub fn extended_length(path: &Path) -> Cow<'_, Path> {
+    #[cfg(windows)]
+    {
+        use std::{
+            ffi::OsString,
+            path::{Component, Prefix},
+        };
+
+        // Extended-length paths are taken as they are, so they have to be absolute and
+        // normalized, without '/' or '..'.
+        let Ok(absolute) = std::path::absolute(path) else {
+            return Cow::Borrowed(path);
+        };
+        let mut components = absolute.components();
+        let mut extended = match components.next() {
+            Some(Component::Prefix(prefix)) => match prefix.kind() {
+                Prefix::Disk(letter) => OsString::from(format!(r"\\?\{}:", letter as char)),
+                Prefix::UNC(server, share) => {
+                    let mut extended = OsString::from(r"\\?\UNC\");
+                    extended.push(server);
+                    extended.push(r"\");
+                    extended.push(share);
+                    extended
+                }
+                // Already extended-length, or a device.
+                _ => return Cow::Owned(absolute),
+            },
+            _ => return Cow::Owned(absolute),
+        };
+        for component in components {
+            if let Component::Normal(name) = component {
+                extended.push(r"\");
+                extended.push(name);
+            }
+        }
+        Cow::Owned(PathBuf::from(extended))
+    }
+    #[cfg(not(windows))]
+    {
+        Cow::Borrowed(path)
+    }
+}
+
+/// Flush a directory's entries to disk so that files created or renamed inside of it are durable.
+pub fn sync_directory(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        std::fs::File::open(dir)?.sync_all()
+    }
+    #[cfg(not(unix))]
+    {
+        // Directories cannot be opened as files for syncing on this platform.
+        let _ = dir;
+        Ok(())
+    }
+}
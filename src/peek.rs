@@ -0,0 +1,228 @@
+use crate::{
+    Event, EventHandler, MailfmtError, MessageError, ProgressTotal,
+    counting::CountingReader,
+    error::{IoContext, Result},
+    gzip,
+    mbox::{
+        MboxParser, Message, check_mbox_input, create_output_directory, eml_file_name,
+        save_eml_file,
+    },
+    normalize::{Normalization, normalize},
+};
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use tracing::info_span;
+
+/// Which end of an mbox [`peek_mbox`] takes its messages from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeekEnd {
+    /// The first messages, reading no further than them.
+    Head,
+    /// The last messages, reading the whole mbox while keeping only as many messages as asked
+    /// for in memory.
+    Tail,
+}
+
+/// Options for [`peek_mbox`]. Create them with [`PeekOptions::new`] and adjust the fields as
+/// needed.
+pub struct PeekOptions<'a> {
+    pub input_file: PathBuf,
+    /// How many messages to take.
+    pub count: usize,
+    pub end: PeekEnd,
+    /// Read the input file even if it does not look like an mbox file.
+    pub force: bool,
+    /// Set to stop reading after the current message. The last messages are then those read
+    /// so far.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called with progress and unreadable messages while the whole mbox is read for its last
+    /// messages.
+    pub on_event: Option<EventHandler<'a>>,
+}
+
+impl PeekOptions<'_> {
+    pub fn new(input_file: impl Into<PathBuf>, count: usize, end: PeekEnd) -> Self {
+        Self {
+            input_file: input_file.into(),
+            count,
+            end,
+            force: false,
+            cancel: None,
+            on_event: None,
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// The messages [`peek_mbox`] took from an mbox.
+#[derive(Debug, Default)]
+pub struct Peeked {
+    /// The messages in their order in the mbox, with their index in it counting from 0.
+    pub messages: Vec<(usize, Message)>,
+    /// The messages that could not be read among those looked at.
+    pub errors: Vec<MessageError>,
+    /// Whether reading was cancelled before reaching the end of the mbox, for its last
+    /// messages.
+    pub interrupted: bool,
+}
+
+impl Peeked {
+    /// Write the messages as an mbox, each after its separator line, or only their separator
+    /// lines and header blocks with `headers_only`. The lines are written as they were read,
+    /// including any quoted "From " lines.
+    pub fn write_mbox(&self, out: &mut impl Write, headers_only: bool) -> io::Result<()> {
+        for (_, message) in &self.messages {
+            writeln!(out, "From {}", message.envelope)?;
+            let lines = match headers_only {
+                true => message.header_lines(),
+                false => &message.lines,
+            };
+            for line in lines {
+                out.write_all(line)?;
+                out.write_all(b"\n")?;
+            }
+            // Messages usually keep the blank line that ended them in the mbox.
+            if lines.last().is_none_or(|line| !line.is_empty()) {
+                out.write_all(b"\n")?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Write every message to an eml file of its own in `output_dir`, creating it if needed,
+    /// named after its index in the mbox and its subject as mbox-to-eml names them. Files of
+    /// the same name are replaced. With `headers_only`, only their header blocks are written.
+    /// Returns the paths written.
+    pub fn write_eml_files(&self, output_dir: &Path, headers_only: bool) -> Result<Vec<PathBuf>> {
+        create_output_directory(output_dir, "create output directory")?;
+        let mut written = Vec::new();
+        for (index, message) in &self.messages {
+            let subject = message
+                .header("subject")
+                .filter(|subject| !subject.is_empty())
+                .map(|subject| sanitize_filename::sanitize(normalize(subject, Normalization::Nfc)));
+            let path = output_dir.join(eml_file_name(*index, subject, "eml"));
+            match headers_only {
+                true => {
+                    let mut lines = message.header_lines().to_vec();
+                    lines.push(Vec::new());
+                    save_eml_file(&path, &lines, true, false)?
+                }
+                false => save_eml_file(&path, &message.lines, message.final_newline, false)?,
+            };
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+/// Take the first or last messages of an mbox file, for a quick look at what it holds without
+/// converting it. Messages that cannot be read are reported and left out.
+///
+/// ```
+/// use mailfmt::{PeekEnd, PeekOptions, peek_mbox};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-peek-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// let mbox = dir.join("in.mbox");
+/// fs::write(
+///     &mbox,
+///     "From a Mon Jan 01 00:00:00 2024\nSubject: One\n\nBody\n\n\
+///      From b Mon Jan 01 00:00:00 2024\nSubject: Two\n\nBody\n\n\
+///      From c Mon Jan 01 00:00:00 2024\nSubject: Three\n\nBody\n",
+/// )?;
+///
+/// let tail = peek_mbox(&PeekOptions::new(&mbox, 2, PeekEnd::Tail))?;
+/// let indices: Vec<_> = tail.messages.iter().map(|(index, _)| *index).collect();
+/// assert_eq!(indices, [1, 2]);
+///
+/// let head = peek_mbox(&PeekOptions::new(&mbox, 1, PeekEnd::Head))?;
+/// let mut out = Vec::new();
+/// head.write_mbox(&mut out, true)?;
+/// assert_eq!(out, b"From a Mon Jan 01 00:00:00 2024\nSubject: One\n\n");
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn peek_mbox(options: &PeekOptions<'_>) -> Result<Peeked> {
+    let _span = info_span!("peek_mbox", input = %options.input_file.display()).entered();
+    let input_file = options.input_file.as_path();
+    check_mbox_input(input_file, options.force, false)?;
+
+    let reader =
+        CountingReader::new(gzip::open(input_file, 0).io_context("open mbox file", input_file)?);
+    let bytes_read = reader.counter();
+    let tail = options.end == PeekEnd::Tail;
+    if tail {
+        let input_size =
+            gzip::content_size(input_file).io_context("read metadata of mbox file", input_file)?;
+        options.emit(Event::Started {
+            total: match input_size {
+                0 => ProgressTotal::Unknown,
+                size => ProgressTotal::Bytes(size),
+            },
+            position: 0,
+        })?;
+    }
+
+    let mut peeked = Peeked::default();
+    if options.count == 0 && !tail {
+        return Ok(peeked);
+    }
+    let mut messages = VecDeque::with_capacity(options.count.min(1024));
+    for (index, message) in MboxParser::from_reader(BufReader::new(reader)).enumerate() {
+        if tail && options.is_cancelled() {
+            peeked.interrupted = true;
+            break;
+        }
+        match message {
+            Ok(message) => {
+                if messages.len() == options.count {
+                    messages.pop_front();
+                }
+                if options.count > 0 {
+                    messages.push_back((index, message));
+                }
+            }
+            Err(error) => {
+                let item = format!("reading email {index}");
+                options.emit(Event::Failed {
+                    item: item.clone(),
+                    error: &error,
+                })?;
+                peeked.errors.push(MessageError { item, error });
+            }
+        }
+        match tail {
+            true => options.emit(Event::Progress {
+                position: bytes_read.load(Ordering::Relaxed),
+                processed: index + 1,
+            })?,
+            // The rest of the mbox is not even read.
+            false if messages.len() == options.count => break,
+            false => {}
+        }
+    }
+    peeked.messages = messages.into();
+    Ok(peeked)
+}
@@ -0,0 +1,136 @@
+//! Drawing a random sample of the messages in an mbox in a single pass, for building a small
+//! set of test messages out of a large archive.
+
+use crate::{
+    atomic::AtomicFile,
+    error::{IoContext, Result},
+};
+use serde_json::json;
+use std::{
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The name of the manifest [`MboxToEmlOptions::sample`](crate::MboxToEmlOptions::sample)
+/// writes to the output directory.
+pub const SAMPLE_MANIFEST_FILE_NAME: &str = "sample.jsonl";
+
+/// A seed for when none was given, different from run to run.
+pub fn random_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+    SplitMix64(nanos ^ u64::from(std::process::id()).rotate_left(32)).next()
+}
+
+/// The SplitMix64 generator, which is small and good enough to pick a sample with, and gives
+/// the same numbers for the same seed on every platform.
+pub(crate) struct SplitMix64(pub u64);
+
+impl SplitMix64 {
+    pub fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `bound`, without the bias of taking the remainder.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+}
+
+/// Keeps a uniformly random sample of up to `size` of the items offered to it, without knowing
+/// how many there will be, by replacing a random kept item with a decreasing probability.
+pub struct Reservoir<T> {
+    size: usize,
+    offered: usize,
+    kept: Vec<(usize, T)>,
+    rng: SplitMix64,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(size: usize, seed: u64) -> Self {
+        Self {
+            size,
+            offered: 0,
+            kept: Vec::with_capacity(size.min(1024)),
+            rng: SplitMix64(seed),
+        }
+    }
+
+    pub fn offer(&mut self, item: T) {
+        let position = self.offered;
+        self.offered += 1;
+        if self.kept.len() < self.size {
+            self.kept.push((position, item));
+            return;
+        }
+        let slot = self.rng.below(self.offered as u64) as usize;
+        if slot < self.size {
+            self.kept[slot] = (position, item);
+        }
+    }
+
+    /// How many items were offered, kept or not.
+    pub fn offered(&self) -> usize {
+        self.offered
+    }
+
+    /// The kept items, in the order they were offered.
+    pub fn into_items(mut self) -> Vec<T> {
+        self.kept.sort_unstable_by_key(|(position, _)| *position);
+        self.kept.into_iter().map(|(_, item)| item).collect()
+    }
+}
+
+/// Writes the manifest of a sample under a temporary name as it is extracted, moving it into
+/// place once the extraction is over. Every line holds a JSON object with the `file` a message
+/// was written to, relative to the output directory, and the `index` and byte `offset` of the
+/// message in the mbox file.
+pub struct SampleManifest {
+    file: BufWriter<AtomicFile>,
+    path: PathBuf,
+    output_dir: PathBuf,
+}
+
+impl SampleManifest {
+    pub fn create(output_dir: &Path) -> Result<Self> {
+        let path = output_dir.join(SAMPLE_MANIFEST_FILE_NAME);
+        let file =
+            BufWriter::new(AtomicFile::create(&path).io_context("create sample manifest", &path)?);
+        Ok(Self {
+            file,
+            path,
+            output_dir: output_dir.to_path_buf(),
+        })
+    }
+
+    pub fn add(&mut self, file: &Path, index: usize, offset: u64) -> Result<()> {
+        let file = file.strip_prefix(&self.output_dir).unwrap_or(file);
+        let entry = json!({
+            "file": file.to_string_lossy(),
+            "index": index,
+            "offset": offset,
+        });
+        writeln!(self.file, "{entry}").io_context("write sample manifest", &self.path)
+    }
+
+    pub fn finish(self) -> Result<()> {
+        self.file
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .io_context("write sample manifest", &self.path)?
+            .commit()
+            .io_context("move sample manifest into place", &self.path)
+    }
+}
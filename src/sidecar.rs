@@ -0,0 +1,85 @@
+//! Metadata files written next to extracted eml files, holding what a re-import or audit needs
+//! without parsing the message again, and read back when converting to mbox.
+
+use crate::{
+    atomic::AtomicFile,
+    error::{IoContext, Result},
+    mbox::Message,
+    status::flags,
+    writer::parse_date,
+};
+use serde_json::{Value, json};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// What is appended to the name of an eml file, in place of its extension, to name its sidecar.
+pub const SIDECAR_EXTENSION: &str = "meta.json";
+
+/// The sidecar of an eml file: `0001_Hello.eml` has `0001_Hello.meta.json`.
+pub fn sidecar_path(eml_file: &Path) -> PathBuf {
+    eml_file.with_extension(SIDECAR_EXTENSION)
+}
+
+/// Describe a message extracted from the mbox at `source`: its envelope, where it was found,
+/// its Message-ID, its Date header in RFC 3339 format if it can be parsed, and its flags.
+pub fn describe(message: &Message, source: &Path) -> Value {
+    let (sender, date) = message
+        .envelope
+        .split_once(' ')
+        .map_or((message.envelope.as_str(), ""), |(sender, date)| {
+            (sender, date.trim())
+        });
+    let flags = flags(|name| message.header(name));
+    json!({
+        "envelope_sender": sender,
+        "envelope_date": date,
+        "source": source.to_string_lossy(),
+        "offset": message.offset,
+        "message_id": message.header("message-id"),
+        "date": message
+            .header("date")
+            .and_then(parse_date)
+            .map(|date| date.to_rfc3339()),
+        "flags": flags,
+    })
+}
+
+/// Write the sidecar of an eml file, returning its size.
+pub fn write(eml_file: &Path, sidecar: &Value, fsync: bool) -> Result<u64> {
+    let path = sidecar_path(eml_file);
+    let content = format!("{sidecar:#}\n");
+    let mut file = AtomicFile::create(&path).io_context("create sidecar", &path)?;
+    file.write_all(content.as_bytes())
+        .io_context("write sidecar", &path)?;
+    if fsync {
+        file.file().sync_all().io_context("sync sidecar", &path)?;
+    }
+    file.commit().io_context("move sidecar into place", &path)?;
+    Ok(content.len() as u64)
+}
+
+/// The envelope recorded in the sidecar of an eml file, as used on a separator line, or `None`
+/// if the file has no sidecar.
+pub fn read_envelope(eml_file: &Path) -> io::Result<Option<String>> {
+    let content = match fs::read_to_string(sidecar_path(eml_file)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let sidecar: Value = serde_json::from_str(&content)?;
+    let field = |name| sidecar.get(name).and_then(Value::as_str).unwrap_or("");
+    match (field("envelope_sender"), field("envelope_date")) {
+        ("", _) | (_, "") => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "it has no envelope sender and date",
+        )),
+        (sender, _) if sender.contains(char::is_whitespace) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "its envelope sender contains whitespace",
+        )),
+        (sender, date) => Ok(Some(format!("{sender} {date}"))),
+    }
+}
@@ -0,0 +1,390 @@
+//! Reporting on the sizes of the messages in an mbox or a directory of eml files, reading
+//! only their headers.
+
+use crate::{
+    Event, EventHandler, MailfmtError, MessageError, OffsetLines, ProgressTotal,
+    counting::CountingReader,
+    encoding::strip_bom,
+    error::{IoContext, Result},
+    export::find_eml_files,
+    gzip,
+    headers::{decode_encoded_words, get_header_value_from_lines},
+    mbox::{check_mbox_input, is_separator},
+    writer::address_of,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tracing::info_span;
+
+/// Options for [`size_report`]. Create them with [`SizeReportOptions::new`] and adjust the
+/// fields as needed.
+pub struct SizeReportOptions<'a> {
+    /// An mbox file, or a directory searched recursively for .eml files.
+    pub input: PathBuf,
+    /// How many of the largest messages to report.
+    pub top: usize,
+    /// Read an input file even if it does not look like an mbox file.
+    pub force: bool,
+    /// Set to stop after the current message. The report then covers the messages read so far.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called with progress and unreadable files as the input is read.
+    pub on_event: Option<EventHandler<'a>>,
+}
+
+impl SizeReportOptions<'_> {
+    pub fn new(input: impl Into<PathBuf>) -> Self {
+        Self {
+            input: input.into(),
+            top: 10,
+            force: false,
+            cancel: None,
+            on_event: None,
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) -> Result<()> {
+        event.trace();
+        match &self.on_event {
+            Some(on_event) => on_event(event).map_err(MailfmtError::Aborted),
+            None => Ok(()),
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// The sizes [`size_report`] found.
+#[derive(Debug, Default)]
+pub struct SizeReport {
+    /// How many messages were read.
+    pub messages: usize,
+    /// The size of all messages together, in bytes.
+    pub total_bytes: u64,
+    /// The largest messages, largest first. Messages of equal size are in input order.
+    pub largest: Vec<MessageSize>,
+    /// The total size of the messages of every sender, largest first.
+    pub senders: Vec<SenderTotal>,
+    /// How the messages are spread over [`SIZE_BUCKETS`], smallest first.
+    pub histogram: Vec<SizeBucket>,
+    /// The eml files that could not be read.
+    pub errors: Vec<MessageError>,
+    /// Whether reading was cancelled before reaching the end of the input.
+    pub interrupted: bool,
+    pub elapsed: Duration,
+}
+
+/// A message and its size.
+#[derive(Debug)]
+pub struct MessageSize {
+    /// The index of the message in the mbox or in the sorted eml files.
+    pub index: usize,
+    /// The eml file holding the message, when reading a directory.
+    pub file: Option<PathBuf>,
+    /// The Date header of the message, as it was written.
+    pub date: Option<String>,
+    /// The From header of the message, decoded.
+    pub sender: Option<String>,
+    /// The Subject header of the message, decoded.
+    pub subject: Option<String>,
+    /// The size in bytes of the message, without its mbox separator line.
+    pub size: u64,
+}
+
+/// The messages of a sender.
+#[derive(Debug)]
+pub struct SenderTotal {
+    /// The lowercased address of the sender, or the From header as it was written if it has
+    /// no usable address, or "(unknown)" if the message has none.
+    pub sender: String,
+    pub messages: usize,
+    pub bytes: u64,
+}
+
+/// The messages with a size in a range.
+#[derive(Debug)]
+pub struct SizeBucket {
+    /// The smallest size in the bucket, in bytes.
+    pub min: u64,
+    /// The size the bucket stops before, or `None` for the last bucket.
+    pub max: Option<u64>,
+    pub messages: usize,
+    pub bytes: u64,
+}
+
+/// The sizes in bytes at which the buckets of [`SizeReport::histogram`] start, after the first
+/// one starting at 0: 10 KiB, 100 KiB, 1 MiB and 10 MiB.
+pub const SIZE_BUCKETS: [u64; 4] = [10 << 10, 100 << 10, 1 << 20, 10 << 20];
+
+/// The most bytes of a message's header block that are kept to read its headers from. A
+/// message without a blank line after its header would otherwise be held in memory whole.
+const MAX_HEADER_BYTES: usize = 256 * 1024;
+
+/// Report on the sizes of the messages in an mbox file or a directory of eml files: the
+/// largest messages, the total size per sender and a histogram. Only the headers of each
+/// message are kept in memory while reading it.
+///
+/// ```
+/// use mailfmt::{SizeReportOptions, size_report};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("mailfmt-sizes-{}", std::process::id()));
+/// fs::create_dir_all(&dir)?;
+/// let big = "x".repeat(20_000);
+/// fs::write(
+///     dir.join("in.mbox"),
+///     format!(
+///         "From a Mon Jan 01 00:00:00 2024\n\
+///          From: Ann <ANN@example.com>\n\
+///          Subject: =?utf-8?q?Caf=C3=A9?=\n\
+///          \n\
+///          Hello\n\
+///          From b Mon Jan 01 00:00:00 2024\n\
+///          From: ann@example.com\n\
+///          Subject: Big\n\
+///          \n\
+///          {big}\n"
+///     ),
+/// )?;
+///
+/// let mut options = SizeReportOptions::new(dir.join("in.mbox"));
+/// options.top = 1;
+/// let report = size_report(&options)?;
+/// assert_eq!(report.messages, 2);
+/// assert_eq!(report.largest.len(), 1);
+/// assert_eq!((report.largest[0].index, report.largest[0].size), (1, 20_037));
+/// assert_eq!(report.senders[0].sender, "ann@example.com");
+/// assert_eq!(report.senders[0].bytes, report.total_bytes);
+/// let counts: Vec<_> = report.histogram.iter().map(|bucket| bucket.messages).collect();
+/// assert_eq!(counts, [1, 1, 0, 0, 0]);
+/// # fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn size_report(options: &SizeReportOptions<'_>) -> Result<SizeReport> {
+    let _span = info_span!("size_report", input = %options.input.display()).entered();
+    let start = Instant::now();
+    let input = options.input.as_path();
+    let mut report = Report::new(options.top);
+    if input.is_dir() {
+        let eml_files = find_eml_files(input)?;
+        options.emit(Event::Started {
+            total: ProgressTotal::Messages(eml_files.len() as u64),
+            position: 0,
+        })?;
+        for (index, eml_file) in eml_files.into_iter().enumerate() {
+            if options.is_cancelled() {
+                report.sizes.interrupted = true;
+                break;
+            }
+            match read_eml_header(&eml_file) {
+                Ok((header, size)) => report.add(index, Some(eml_file), &header, size),
+                Err(error) => {
+                    let item = format!("reading {eml_file:?}");
+                    options.emit(Event::Failed {
+                        item: item.clone(),
+                        error: &error,
+                    })?;
+                    report.sizes.errors.push(MessageError { item, error });
+                }
+            }
+            options.emit(Event::Progress {
+                position: index as u64 + 1,
+                processed: index + 1,
+            })?;
+        }
+    } else {
+        check_mbox_input(input, options.force, false)?;
+        let input_size =
+            gzip::content_size(input).io_context("read metadata of mbox file", input)?;
+        let reader = CountingReader::new(gzip::open(input, 0).io_context("open mbox file", input)?);
+        let bytes_read = reader.counter();
+        options.emit(Event::Started {
+            total: match input_size {
+                0 => ProgressTotal::Unknown,
+                size => ProgressTotal::Bytes(size),
+            },
+            position: 0,
+        })?;
+
+        // The message being read: its index, where its content starts once known, and the
+        // lines of its header block so far.
+        let mut current: Option<(usize, Option<u64>, Vec<Vec<u8>>)> = None;
+        let mut in_header = false;
+        let mut header_bytes = 0;
+        for line in OffsetLines::new(BufReader::new(reader), 0) {
+            let (offset, line, _) = line.io_context("read mbox file", input)?;
+            if is_separator(&line) {
+                if let Some((index, content_start, header)) = current.take() {
+                    let size = offset - content_start.unwrap_or(offset);
+                    report.add(index, None, &header, size);
+                    options.emit(Event::Progress {
+                        position: bytes_read.load(Ordering::Relaxed),
+                        processed: report.sizes.messages,
+                    })?;
+                }
+                if options.is_cancelled() {
+                    report.sizes.interrupted = true;
+                    break;
+                }
+                current = Some((report.sizes.messages, None, Vec::new()));
+                (in_header, header_bytes) = (true, 0);
+                continue;
+            }
+            let Some((_, content_start, header)) = &mut current else {
+                continue;
+            };
+            content_start.get_or_insert(offset);
+            if line.is_empty() {
+                in_header = false;
+            } else if in_header && header_bytes < MAX_HEADER_BYTES {
+                header_bytes += line.len();
+                header.push(line);
+            }
+        }
+        // A separator at the very end with nothing after it does not start a message.
+        if let Some((index, Some(content_start), header)) = current
+            && !report.sizes.interrupted
+        {
+            let size = bytes_read.load(Ordering::Relaxed) - content_start;
+            report.add(index, None, &header, size);
+            options.emit(Event::Progress {
+                position: bytes_read.load(Ordering::Relaxed),
+                processed: report.sizes.messages,
+            })?;
+        }
+    }
+
+    let mut sizes = report.finish();
+    sizes.elapsed = start.elapsed();
+    Ok(sizes)
+}
+
+/// The report as it is gathered.
+struct Report {
+    sizes: SizeReport,
+    top: usize,
+    senders: HashMap<String, (usize, u64)>,
+}
+
+impl Report {
+    fn new(top: usize) -> Self {
+        let mut sizes = SizeReport::default();
+        let mut min = 0;
+        for max in SIZE_BUCKETS.map(Some).into_iter().chain([None]) {
+            sizes.histogram.push(SizeBucket {
+                min,
+                max,
+                messages: 0,
+                bytes: 0,
+            });
+            min = max.unwrap_or_default();
+        }
+        Self {
+            sizes,
+            top,
+            senders: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, index: usize, file: Option<PathBuf>, header: &[Vec<u8>], size: u64) {
+        self.sizes.messages += 1;
+        self.sizes.total_bytes += size;
+        let bucket = SIZE_BUCKETS.iter().take_while(|&&min| size >= min).count();
+        self.sizes.histogram[bucket].messages += 1;
+        self.sizes.histogram[bucket].bytes += size;
+
+        let from = get_header_value_from_lines(header, "from");
+        let key = match from {
+            Some(from) => address_of(from).unwrap_or(from).to_lowercase(),
+            None => "(unknown)".to_string(),
+        };
+        let total = self.senders.entry(key).or_default();
+        *total = (total.0 + 1, total.1 + size);
+
+        if self.top == 0 {
+            return;
+        }
+        let largest = &mut self.sizes.largest;
+        if largest.len() == self.top && largest.last().is_some_and(|last| last.size >= size) {
+            return;
+        }
+        let header = |name| get_header_value_from_lines(header, name);
+        let message = MessageSize {
+            index,
+            file,
+            date: header("date").map(str::to_string),
+            sender: from.map(decode_encoded_words),
+            subject: header("subject").map(decode_encoded_words),
+            size,
+        };
+        let position = largest.partition_point(|other| other.size >= size);
+        largest.insert(position, message);
+        largest.truncate(self.top);
+    }
+
+    fn finish(self) -> SizeReport {
+        let mut sizes = self.sizes;
+        sizes.senders = self
+            .senders
+            .into_iter()
+            .map(|(sender, (messages, bytes))| SenderTotal {
+                sender,
+                messages,
+                bytes,
+            })
+            .collect();
+        sizes.senders.sort_by(|a, b| {
+            (b.bytes, b.messages)
+                .cmp(&(a.bytes, a.messages))
+                .then_with(|| a.sender.cmp(&b.sender))
+        });
+        sizes
+    }
+}
+
+/// Read the header block of an eml file, without a leading byte-order mark or line endings,
+/// along with the size of the file.
+fn read_eml_header(eml_file: &Path) -> Result<(Vec<Vec<u8>>, u64)> {
+    let file = File::open(eml_file).io_context("open eml file", eml_file)?;
+    let size = file
+        .metadata()
+        .io_context("read metadata of eml file", eml_file)?
+        .len();
+    let mut reader = BufReader::new(file);
+    let (mut header, mut header_bytes, mut line) = (Vec::new(), 0, Vec::new());
+    while header_bytes < MAX_HEADER_BYTES {
+        line.clear();
+        if reader
+            .read_until(b'\n', &mut line)
+            .io_context("read eml file", eml_file)?
+            == 0
+        {
+            break;
+        }
+        let content = if header.is_empty() {
+            strip_bom(&line)
+        } else {
+            &line
+        };
+        let content = content.strip_suffix(b"\n").unwrap_or(content);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        if content.is_empty() {
+            break;
+        }
+        header_bytes += content.len();
+        header.push(content.to_vec());
+    }
+    Ok((header, size))
+}
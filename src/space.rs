@@ -0,0 +1,50 @@
+use crate::{Event, MailfmtError, error::Result};
+use std::path::Path;
+
+/// The bytes an eml file is estimated to grow by in an mbox: its separator line, the blank
+/// line ending it and some quoted "From " lines.
+pub const SEPARATOR_OVERHEAD: u64 = 64;
+
+/// Check that the filesystem the output is written to has room for an estimated `required`
+/// bytes and a 5% margin on top, reporting both figures through [`Event::SpaceChecked`].
+/// `destination` need not exist yet, as its deepest existing ancestor is checked instead.
+///
+/// Too little space fails with [`MailfmtError::InsufficientSpace`], or is only warned about
+/// with `force`. Space that cannot be queried is warned about and the conversion goes ahead.
+pub fn check(
+    destination: &Path,
+    required: u64,
+    force: bool,
+    emit: impl Fn(Event<'_>) -> Result<()>,
+) -> Result<()> {
+    let existing = destination
+        .ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .unwrap_or(Path::new("."));
+    let available = match fs4::available_space(existing) {
+        Ok(available) => available,
+        Err(e) => {
+            return emit(Event::Warning(format!(
+                "Could not check the free space at {existing:?}, so the conversion was started without knowing it: {e}"
+            )));
+        }
+    };
+    emit(Event::SpaceChecked {
+        required,
+        available,
+    })?;
+
+    if required.saturating_add(required / 20) <= available {
+        return Ok(());
+    }
+    match force {
+        true => emit(Event::Warning(format!(
+            "The output is estimated to need {required} bytes and a 5% margin, but only {available} bytes are available at {existing:?}. Converting anyway, as forced."
+        ))),
+        false => Err(MailfmtError::InsufficientSpace {
+            path: existing.to_path_buf(),
+            required,
+            available,
+        }),
+    }
+}
@@ -0,0 +1,364 @@
+use crate::{
+    MailfmtError,
+    atomic::AtomicFile,
+    error::{IoContext, Result},
+};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/// The version written to new state files. Files with a newer version are rejected, while
+/// unknown fields in files of a known version are ignored so that fields can be added freely.
+const STATE_VERSION: i64 = 1;
+
+/// What is known about an eml file that was converted by a previous run.
+#[derive(Clone, PartialEq)]
+pub struct FileRecord {
+    pub size: u64,
+    /// Modification time as seconds and nanoseconds since the Unix epoch, if available.
+    pub modified: Option<(i64, u32)>,
+    pub sha256: String,
+}
+
+impl FileRecord {
+    /// Describe a file as it currently is on disk, hashing its full content.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let (size, modified) = Self::stat(&file)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(Self {
+            size,
+            modified,
+            sha256: format!("{:x}", hasher.finalize()),
+        })
+    }
+
+    fn stat(file: &File) -> io::Result<(u64, Option<(i64, u32)>)> {
+        let metadata = file.metadata()?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since| (since.as_secs() as i64, since.subsec_nanos()));
+        Ok((metadata.len(), modified))
+    }
+
+    /// Whether a file is unchanged since this record was taken. Size and modification time are
+    /// compared first, and the content hash only when they differ, so that files that were
+    /// merely touched are still recognised.
+    pub fn unchanged(&self, path: &Path) -> io::Result<Option<Self>> {
+        let (size, modified) = Self::stat(&File::open(path)?)?;
+        if size == self.size && modified == self.modified {
+            return Ok(Some(self.clone()));
+        }
+        if size != self.size {
+            return Ok(None);
+        }
+        let current = Self::read(path)?;
+        Ok((current.sha256 == self.sha256).then_some(current))
+    }
+
+    /// The record as the entry of a state file, for the file at `path`.
+    fn to_json(&self, path: &str) -> Value {
+        json!({
+            "path": path,
+            "size": self.size,
+            "modified_secs": self.modified.map(|(secs, _)| secs),
+            "modified_nanos": self.modified.map(|(_, nanos)| nanos),
+            "sha256": self.sha256,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let modified = match (
+            value.get("modified_secs").and_then(Value::as_i64),
+            value.get("modified_nanos").and_then(Value::as_i64),
+        ) {
+            (Some(secs), Some(nanos)) => Some((secs, u32::try_from(nanos).ok()?)),
+            _ => None,
+        };
+        Some(Self {
+            size: value.get("size")?.as_u64()?,
+            modified,
+            sha256: value.get("sha256")?.as_str()?.to_string(),
+        })
+    }
+}
+
+/// The record of which eml files have already been converted into an mbox, used to only
+/// append new or changed files on later runs.
+#[derive(Default)]
+pub struct IncrementalState {
+    pub files: BTreeMap<String, FileRecord>,
+}
+
+impl IncrementalState {
+    /// The key a file is recorded under: its absolute path, so that runs from different
+    /// working directories agree.
+    pub fn key(path: &Path) -> String {
+        crate::paths::resolve(path)
+            .unwrap_or_else(|_| path.to_path_buf())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).io_context("read state file", path)?;
+        let json: Value =
+            serde_json::from_str(&content).map_err(|e| invalid(path, e.to_string()))?;
+        match json.get("version").and_then(Value::as_i64) {
+            Some(version) if version > STATE_VERSION => {
+                return Err(invalid(
+                    path,
+                    format!(
+                        "it was written by a newer version of mailfmt (state version {version})"
+                    ),
+                ));
+            }
+            Some(_) => {}
+            None => return Err(invalid(path, "it has no version")),
+        }
+
+        let mut files = BTreeMap::new();
+        for entry in json
+            .get("files")
+            .and_then(Value::as_array)
+            .map_or(&[][..], Vec::as_slice)
+        {
+            let (Some(file_path), Some(record)) = (
+                entry.get("path").and_then(Value::as_str),
+                FileRecord::from_json(entry),
+            ) else {
+                return Err(invalid(path, "it contains an invalid file entry"));
+            };
+            files.insert(file_path.to_string(), record);
+        }
+        Ok(Self { files })
+    }
+
+    /// Write the state file, replacing any previous one only once it has been written completely.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let files: Vec<Value> = self
+            .files
+            .iter()
+            .map(|(file_path, record)| record.to_json(file_path))
+            .collect();
+        let json = json!({
+            "version": STATE_VERSION,
+            "files": files,
+        });
+
+        let mut file = AtomicFile::create(path).io_context("create state file", path)?;
+        writeln!(file, "{json:#}").io_context("write state file", path)?;
+        file.commit().io_context("move state file into place", path)
+    }
+}
+
+/// How much of the input at the start and before the resume offset is hashed to recognise it.
+const RESUME_SAMPLE_LENGTH: u64 = 64 * 1024;
+
+/// Progress of an mbox-to-eml extraction, kept in the output directory so that an interrupted
+/// extraction can be continued with --resume.
+pub struct ResumeState {
+    pub input_size: u64,
+    /// Hash of the start of the input and of the data just before `offset`. Hashing all of a
+    /// large mailbox on every checkpoint would be too slow, and these samples are enough to tell
+    /// a different or rewritten file apart.
+    pub sample_sha256: String,
+    /// Byte offset of the "From " line starting the next message to extract.
+    pub offset: u64,
+    /// The number the next extracted eml file is given.
+    pub next_index: usize,
+    /// How many messages earlier runs converted and failed to convert.
+    pub converted: usize,
+    pub errors: usize,
+}
+
+impl ResumeState {
+    /// The resume state file inside of an output directory.
+    pub fn path_in(output_dir: &Path) -> PathBuf {
+        output_dir.join(".mailfmt-resume.json")
+    }
+
+    /// Hash the samples of an input file that identify it for resuming at `offset`.
+    pub fn sample_hash(input: &Path, offset: u64) -> io::Result<String> {
+        use std::io::{Seek, SeekFrom};
+
+        let mut file = File::open(input)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = Vec::new();
+        (&mut file)
+            .take(RESUME_SAMPLE_LENGTH)
+            .read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+        buffer.clear();
+        file.seek(SeekFrom::Start(offset.saturating_sub(RESUME_SAMPLE_LENGTH)))?;
+        file.take(offset.min(RESUME_SAMPLE_LENGTH))
+            .read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Load the resume state of an output directory, if there is one.
+    pub fn load(output_dir: &Path) -> Result<Option<Self>> {
+        let path = Self::path_in(output_dir);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).io_context("read resume state", &path),
+        };
+        let json: Value =
+            serde_json::from_str(&content).map_err(|e| invalid(&path, e.to_string()))?;
+        if json
+            .get("version")
+            .and_then(Value::as_i64)
+            .is_none_or(|version| version > STATE_VERSION)
+        {
+            return Err(invalid(
+                &path,
+                "it was written by a different version of mailfmt",
+            ));
+        }
+
+        let field = |name: &str| json.get(name).and_then(Value::as_u64);
+        let state = (|| {
+            Some(Self {
+                input_size: field("input_size")?,
+                sample_sha256: json.get("sample_sha256")?.as_str()?.to_string(),
+                offset: field("offset")?,
+                next_index: usize::try_from(field("next_index")?).ok()?,
+                converted: usize::try_from(field("converted")?).ok()?,
+                errors: usize::try_from(field("errors")?).ok()?,
+            })
+        })();
+        match state {
+            Some(state) => Ok(Some(state)),
+            None => Err(invalid(&path, "it is incomplete")),
+        }
+    }
+
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        let path = Self::path_in(output_dir);
+        let json = json!({
+            "version": STATE_VERSION,
+            "input_size": self.input_size,
+            "sample_sha256": self.sample_sha256,
+            "offset": self.offset,
+            "next_index": self.next_index,
+            "converted": self.converted,
+            "errors": self.errors,
+        });
+        let mut file = AtomicFile::create(&path).io_context("create resume state", &path)?;
+        writeln!(file, "{json:#}").io_context("write resume state", &path)?;
+        file.commit()
+            .io_context("move resume state into place", &path)
+    }
+
+    /// Remove the resume state once an extraction has completed.
+    pub fn remove(output_dir: &Path) -> Result<()> {
+        let path = Self::path_in(output_dir);
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).io_context("remove resume state", &path),
+        }
+    }
+}
+
+/// Progress of a fetch from an IMAP mailbox, kept next to its output so that the next fetch
+/// continues after the last message fetched.
+pub struct FetchState {
+    /// The mailbox fetched from, as in `localhost:143/INBOX`.
+    pub mailbox: String,
+    /// The UIDVALIDITY of the mailbox, which changes when its UIDs are no longer those
+    /// recorded.
+    pub uid_validity: u64,
+    /// The highest UID fetched so far.
+    pub last_uid: u64,
+    /// The number the next eml file is given.
+    pub next_index: usize,
+}
+
+impl FetchState {
+    /// The state file of a fetch: inside of an output directory, or next to an mbox file.
+    pub fn path_for(output: &Path, directory: bool) -> PathBuf {
+        match directory {
+            true => output.join(".mailfmt-imap.json"),
+            false => {
+                let mut name = output.file_name().unwrap_or_default().to_os_string();
+                name.push(".mailfmt-imap.json");
+                output.with_file_name(name)
+            }
+        }
+    }
+
+    /// Load the state of a fetch, if there is one.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).io_context("read fetch state", path),
+        };
+        let json: Value =
+            serde_json::from_str(&content).map_err(|e| invalid(path, e.to_string()))?;
+        if json
+            .get("version")
+            .and_then(Value::as_i64)
+            .is_none_or(|version| version > STATE_VERSION)
+        {
+            return Err(invalid(
+                path,
+                "it was written by a different version of mailfmt",
+            ));
+        }
+
+        let field = |name: &str| json.get(name).and_then(Value::as_u64);
+        let state = (|| {
+            Some(Self {
+                mailbox: json.get("mailbox")?.as_str()?.to_string(),
+                uid_validity: field("uid_validity")?,
+                last_uid: field("last_uid")?,
+                next_index: usize::try_from(field("next_index")?).ok()?,
+            })
+        })();
+        match state {
+            Some(state) => Ok(Some(state)),
+            None => Err(invalid(path, "it is incomplete")),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = json!({
+            "version": STATE_VERSION,
+            "mailbox": self.mailbox,
+            "uid_validity": self.uid_validity,
+            "last_uid": self.last_uid,
+            "next_index": self.next_index,
+        });
+        let mut file = AtomicFile::create(path).io_context("create fetch state", path)?;
+        writeln!(file, "{json:#}").io_context("write fetch state", path)?;
+        file.commit()
+            .io_context("move fetch state into place", path)
+    }
+}
+
+fn invalid(path: &Path, reason: impl Into<String>) -> MailfmtError {
+    MailfmtError::InvalidState {
+        path: path.to_path_buf(),
+        reason: reason.into(),
+    }
+}
@@ -0,0 +1,140 @@
+//! The read, answered and other flags of a message, as mail clients record them in its
+//! Status and X-Status headers, or in the hexadecimal bitmask of Thunderbird's
+//! X-Mozilla-Status header. Sidecars, IMAP uploads and fetches, Babyl labels and the filters
+//! of mbox-to-eml all name the flags the same way, such as `seen` and `answered`.
+
+use std::{fmt, str::FromStr};
+
+/// The flags of a message that a Status or X-Status header can hold, with the letter for each
+/// and the name it gets in a sidecar.
+const FLAGS: [(&str, char, &str); 6] = [
+    ("status", 'R', "seen"),
+    ("status", 'O', "old"),
+    ("x-status", 'A', "answered"),
+    ("x-status", 'F', "flagged"),
+    ("x-status", 'T', "draft"),
+    ("x-status", 'D', "deleted"),
+];
+
+/// The bits of an X-Mozilla-Status header, with the name of the flag each stands for.
+const MOZILLA_FLAGS: [(u32, &str); 4] = [
+    (0x0001, "seen"),
+    (0x0002, "answered"),
+    (0x0004, "flagged"),
+    (0x0008, "deleted"),
+];
+
+/// Whether a message has been read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadState {
+    Seen,
+    Unseen,
+}
+
+impl fmt::Display for ReadState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Seen => "seen",
+            Self::Unseen => "unseen",
+        })
+    }
+}
+
+impl FromStr for ReadState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "seen" | "read" => Ok(Self::Seen),
+            "unseen" | "unread" => Ok(Self::Unseen),
+            _ => Err(format!("unknown read state '{s}', expected seen or unseen")),
+        }
+    }
+}
+
+/// The flags of a message by the names they get in a sidecar, given the value of each of its
+/// headers by lowercased name.
+pub(crate) fn flags<'a>(header: impl Fn(&str) -> Option<&'a str>) -> Vec<&'static str> {
+    let mozilla = mozilla_status(&header);
+    FLAGS
+        .iter()
+        .filter(|(name, letter, flag)| {
+            header(name).is_some_and(|value| value.contains(*letter))
+                || MOZILLA_FLAGS
+                    .iter()
+                    .any(|(bit, name)| name == flag && mozilla & bit != 0)
+        })
+        .map(|&(_, _, flag)| flag)
+        .collect()
+}
+
+/// Whether Thunderbird marked a message as deleted, given the value of each of its headers by
+/// lowercased name. It keeps deleted messages in the mbox until the folder is compacted, with
+/// the deleted bit set in their X-Mozilla-Status header.
+pub(crate) fn mozilla_deleted<'a>(header: impl Fn(&str) -> Option<&'a str>) -> bool {
+    mozilla_status(&header) & 0x0008 != 0
+}
+
+/// The bitmask of an X-Mozilla-Status header, or no bits set if it is missing or malformed.
+fn mozilla_status<'a>(header: &impl Fn(&str) -> Option<&'a str>) -> u32 {
+    header("x-mozilla-status")
+        .and_then(|value| u32::from_str_radix(value.trim(), 16).ok())
+        .unwrap_or_default()
+}
+
+/// Whether a message has been read, given the value of each of its headers by lowercased
+/// name, or `None` if it has neither a Status, an X-Status nor an X-Mozilla-Status header.
+pub(crate) fn read_state<'a>(header: impl Fn(&str) -> Option<&'a str>) -> Option<ReadState> {
+    ["status", "x-status", "x-mozilla-status"]
+        .into_iter()
+        .any(|name| header(name).is_some())
+        .then(|| match flags(header).contains(&"seen") {
+            true => ReadState::Seen,
+            false => ReadState::Unseen,
+        })
+}
+
+/// The Status and X-Status headers holding the flags named as in a sidecar, the reverse of
+/// [`flags`]. Headers that would be empty are left out.
+pub(crate) fn status_headers(names: &[&str]) -> Vec<(&'static str, String)> {
+    ["Status", "X-Status"]
+        .into_iter()
+        .filter_map(|header| {
+            let letters: String = FLAGS
+                .iter()
+                .filter(|(name, _, flag)| name.eq_ignore_ascii_case(header) && names.contains(flag))
+                .map(|&(_, letter, _)| letter)
+                .collect();
+            (!letters.is_empty()).then_some((header, letters))
+        })
+        .collect()
+}
+
+/// Replace the Status and X-Status headers of a message, and any others named in `headers`,
+/// with `headers`, added at the end of its header block.
+pub(crate) fn with_status_headers(lines: Vec<Vec<u8>>, headers: &[(&str, String)]) -> Vec<Vec<u8>> {
+    let end = lines
+        .iter()
+        .position(|line| line.is_empty())
+        .unwrap_or(lines.len());
+    let mut result = Vec::with_capacity(lines.len() + headers.len());
+    let mut skipping = false;
+    for line in &lines[..end] {
+        let continued = line.first().is_some_and(|&b| b == b' ' || b == b'\t');
+        if !continued {
+            let name = line.split(|&b| b == b':').next().unwrap_or_default();
+            skipping = ["status", "x-status"]
+                .iter()
+                .chain(headers.iter().map(|(name, _)| name))
+                .any(|replaced| name.eq_ignore_ascii_case(replaced.as_bytes()));
+        }
+        if !skipping {
+            result.push(line.clone());
+        }
+    }
+    for (name, value) in headers {
+        result.push(format!("{name}: {value}").into_bytes());
+    }
+    result.extend_from_slice(&lines[end..]);
+    result
+}
@@ -0,0 +1,144 @@
+//! Stripping of attachments from a message, leaving a short text part describing each in its
+//! place.
+
+use crate::mime::{Entity, MAX_DEPTH, Multipart};
+use sha2::{Digest, Sha256};
+
+/// What stripping removed from a message.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Stripped {
+    /// How many attachments were replaced.
+    pub attachments: usize,
+    /// How many bytes smaller the message became.
+    pub bytes: u64,
+}
+
+/// Replace the attachments of a message in place with text/plain parts recording their file
+/// name, media type, decoded size and SHA-256. Parts of multipart messages are replaced at any
+/// depth, keeping the multipart structure, preamble, delimiters and epilogue as they are.
+///
+/// A message that is a single attachment, such as a bare PDF, is only replaced with
+/// `single_part`, keeping its header fields other than the Content ones.
+pub(crate) fn strip_attachments(lines: &mut Vec<Vec<u8>>, single_part: bool) -> Stripped {
+    let entity = Entity::parse(lines);
+    let mut attachments = 0;
+    let stripped = if entity.content_type.is("multipart") {
+        strip_entity(lines, 0, &mut attachments)
+    } else if single_part && entity.is_file() {
+        attachments = 1;
+        let mut stripped = content_free_header(&entity);
+        stripped.extend(stub(&entity));
+        Some(stripped)
+    } else {
+        None
+    };
+    let Some(mut stripped) = stripped else {
+        return Stripped::default();
+    };
+    // The length of the message has changed, so a Content-Length field would now be wrong.
+    let header = Entity::parse(&stripped);
+    if let Some(field) = header
+        .fields
+        .iter()
+        .find(|field| field.is("content-length"))
+    {
+        stripped.drain(field.lines.clone());
+    }
+
+    let size = |lines: &[Vec<u8>]| lines.iter().map(|line| line.len() as u64 + 1).sum::<u64>();
+    let bytes = size(lines).saturating_sub(size(&stripped));
+    *lines = stripped;
+    Stripped { attachments, bytes }
+}
+
+/// Strip the attachments of a multipart entity, returning its new lines if any were replaced.
+fn strip_entity(lines: &[Vec<u8>], depth: usize, attachments: &mut usize) -> Option<Vec<Vec<u8>>> {
+    let entity = Entity::parse(lines);
+    if entity.content_type.is("multipart") {
+        let multipart = entity.multipart().filter(|_| depth < MAX_DEPTH)?;
+        return strip_parts(lines, &entity, &multipart, depth, attachments);
+    }
+    if !entity.is_file() {
+        return None;
+    }
+    *attachments += 1;
+    Some(stub(&entity))
+}
+
+/// Strip the attachments in the parts of a multipart entity, returning its new lines if any
+/// were replaced.
+fn strip_parts(
+    lines: &[Vec<u8>],
+    entity: &Entity<'_>,
+    multipart: &Multipart,
+    depth: usize,
+    attachments: &mut usize,
+) -> Option<Vec<Vec<u8>>> {
+    let body = entity.body;
+    let mut changed = false;
+    let mut stripped = lines[..lines.len() - body.len()].to_vec();
+    stripped.extend_from_slice(&body[..multipart.delimiters[0]]);
+    for part in multipart.parts() {
+        stripped.push(body[part.start - 1].clone());
+        match strip_entity(&body[part.clone()], depth + 1, attachments) {
+            Some(lines) => {
+                changed = true;
+                stripped.extend(lines);
+            }
+            None => stripped.extend_from_slice(&body[part]),
+        }
+    }
+    if multipart.closed {
+        let close = *multipart.delimiters.last().expect("not empty");
+        stripped.extend_from_slice(&body[close..]);
+    }
+    changed.then_some(stripped)
+}
+
+/// The header block of an entity without its Content fields, which describe the content that
+/// is being replaced.
+fn content_free_header(entity: &Entity<'_>) -> Vec<Vec<u8>> {
+    entity
+        .fields
+        .iter()
+        .filter(|field| !field.name.to_ascii_lowercase().starts_with("content-"))
+        .flat_map(|field| entity.header[field.lines.clone()].iter().cloned())
+        .collect()
+}
+
+/// The text/plain part put in place of an attachment.
+fn stub(entity: &Entity<'_>) -> Vec<Vec<u8>> {
+    let content = entity
+        .decode_body()
+        .unwrap_or_else(|| entity.body.join(&b'\n'));
+    let mut hasher = Sha256::new();
+    hasher.update(&content);
+    // A decoded file name may hold anything, but the stub must stay one field per line.
+    let filename = entity.filename().map_or_else(
+        || "(none)".to_string(),
+        |name| name.replace(char::is_control, " "),
+    );
+
+    let text = [
+        "This attachment was removed by mailfmt.".to_string(),
+        String::new(),
+        format!("Filename: {filename}"),
+        format!(
+            "Content-Type: {}",
+            entity.content_type.media_type.to_ascii_lowercase()
+        ),
+        format!("Size: {} bytes", content.len()),
+        format!("SHA-256: {:x}", hasher.finalize()),
+    ];
+    let encoding = if filename.is_ascii() { "7bit" } else { "8bit" };
+    [
+        "Content-Type: text/plain; charset=utf-8".to_string(),
+        "Content-Disposition: inline".to_string(),
+        format!("Content-Transfer-Encoding: {encoding}"),
+        String::new(),
+    ]
+    .into_iter()
+    .chain(text)
+    .map(String::into_bytes)
+    .collect()
+}
@@ -0,0 +1,168 @@
+//! Grouping of the messages in an mbox into threads, following their Message-ID, In-Reply-To
+//! and References headers.
+
+use crate::{
+    encoding::InvalidUtf8,
+    error::{IoContext, Result},
+    headers::decode_encoded_words,
+    mbox::MboxToEmlOptions,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+};
+
+/// What threading needs to know about a message.
+struct ThreadMessage {
+    offset: u64,
+    id: Option<String>,
+    /// The IDs in its In-Reply-To and References headers.
+    parents: Vec<String>,
+    subject: Option<String>,
+}
+
+/// The longest subject used in a thread directory name, in characters.
+const MAX_SUBJECT_CHARS: usize = 60;
+
+/// Read every message of an mbox and group them into threads, returning the name of the
+/// directory for each message's thread by the byte offset of its separator line.
+///
+/// Messages are joined into a thread when one references another's Message-ID, or when they
+/// reference the same Message-ID, so that replies to a message missing from the mbox still
+/// end up together. A thread's directory is named after the subject of its first message
+/// that references no other message in the mbox, followed by a short hash telling apart
+/// threads with the same subject.
+pub(crate) fn thread_directories(options: &MboxToEmlOptions<'_>) -> Result<HashMap<u64, String>> {
+    let input_file = options.input_file.as_path();
+    let file = File::open(input_file).io_context("open mbox file", input_file)?;
+    let mut messages = Vec::new();
+    for message in options
+        .parser(BufReader::new(file), 0)
+        .with_invalid_utf8(InvalidUtf8::Preserve)
+    {
+        // A message that cannot be read is not extracted, so it needs no thread.
+        let Ok(message) = message else {
+            continue;
+        };
+        let header = |name| message.header(name).unwrap_or_default();
+        let mut parents = message_ids(header("in-reply-to"));
+        parents.extend(message_ids(header("references")));
+        messages.push(ThreadMessage {
+            offset: message.offset,
+            id: message_ids(header("message-id")).into_iter().next(),
+            parents,
+            subject: message.header("subject").map(decode_encoded_words),
+        });
+    }
+
+    // Union-find over the messages, numbered first, and the IDs they mention.
+    let mut nodes: Vec<usize> = (0..messages.len()).collect();
+    let mut id_nodes: HashMap<&str, usize> = HashMap::new();
+    for (index, message) in messages.iter().enumerate() {
+        for id in message.id.iter().chain(&message.parents) {
+            let node = *id_nodes.entry(id).or_insert_with(|| {
+                nodes.push(nodes.len());
+                nodes.len() - 1
+            });
+            union(&mut nodes, index, node);
+        }
+    }
+
+    let present: HashSet<&str> = messages.iter().filter_map(|m| m.id.as_deref()).collect();
+    let has_parent = |message: &ThreadMessage| {
+        message
+            .parents
+            .iter()
+            .any(|parent| present.contains(parent.as_str()) && Some(parent) != message.id.as_ref())
+    };
+    let mut roots: HashMap<usize, usize> = HashMap::new();
+    for (index, message) in messages.iter().enumerate() {
+        if !has_parent(message) {
+            roots.entry(find(&mut nodes, index)).or_insert(index);
+        }
+    }
+    // A thread whose messages all reference each other starts with its first message.
+    for index in 0..messages.len() {
+        roots.entry(find(&mut nodes, index)).or_insert(index);
+    }
+
+    let names: HashMap<usize, String> = roots
+        .into_iter()
+        .map(|(thread, root)| (thread, directory_name(&messages[root])))
+        .collect();
+    Ok(messages
+        .iter()
+        .enumerate()
+        .map(|(index, message)| (message.offset, names[&find(&mut nodes, index)].clone()))
+        .collect())
+}
+
+/// The directory name for a thread starting with the given message.
+fn directory_name(root: &ThreadMessage) -> String {
+    let subject = sanitize_filename::sanitize(strip_reply_prefixes(
+        root.subject.as_deref().unwrap_or_default(),
+    ));
+    let subject: String = subject.trim().chars().take(MAX_SUBJECT_CHARS).collect();
+    let subject = match subject.trim_end() {
+        "" => "no subject",
+        subject => subject,
+    };
+    let mut hasher = Sha256::new();
+    match &root.id {
+        Some(id) => hasher.update(id.as_bytes()),
+        None => hasher.update(root.offset.to_string().as_bytes()),
+    }
+    let hash = format!("{:x}", hasher.finalize());
+    format!("{subject} {}", &hash[..8])
+}
+
+/// A subject without the reply and forward markers at its start, such as "Re: Fwd: ".
+fn strip_reply_prefixes(mut subject: &str) -> &str {
+    loop {
+        subject = subject.trim_start();
+        let Some((prefix, rest)) = subject.split_once(':') else {
+            return subject;
+        };
+        if !["re", "fw", "fwd"]
+            .iter()
+            .any(|marker| prefix.trim().eq_ignore_ascii_case(marker))
+        {
+            return subject;
+        }
+        subject = rest;
+    }
+}
+
+/// The message IDs in a Message-ID, In-Reply-To or References header value, in order.
+fn message_ids(value: &str) -> Vec<String> {
+    let mut ids = Vec::new();
+    let mut rest = value;
+    while let Some((_, after)) = rest.split_once('<')
+        && let Some((id, after)) = after.split_once('>')
+    {
+        if !id.trim().is_empty() {
+            ids.push(id.trim().to_string());
+        }
+        rest = after;
+    }
+    // Some senders leave out the angle brackets around a lone ID.
+    if ids.is_empty() && value.contains('@') && !value.trim().contains(char::is_whitespace) {
+        ids.push(value.trim().to_string());
+    }
+    ids
+}
+
+fn find(nodes: &mut [usize], mut node: usize) -> usize {
+    while nodes[node] != node {
+        nodes[node] = nodes[nodes[node]];
+        node = nodes[node];
+    }
+    node
+}
+
+fn union(nodes: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(nodes, a), find(nodes, b));
+    nodes[a.max(b)] = a.min(b);
+}
@@ -0,0 +1,101 @@
+//! The events of conversions as `tracing` events, for applications that collect logs rather
+//! than handle every [`Event`] themselves. Each event is named by its `event` field, and is
+//! emitted inside the span of its conversion and, for a message, the `message` span with its
+//! position, file or UID.
+
+use crate::{Event, ProgressTotal};
+use std::{error::Error, time::Duration};
+use tracing::{error, info, warn};
+
+/// A duration in milliseconds, to the microsecond.
+fn milliseconds(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1_000_000.0).round() / 1000.0
+}
+
+/// An error followed by those that caused it.
+fn with_causes(error: &dyn Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message = format!("{message}: {cause}");
+        source = cause.source();
+    }
+    message
+}
+
+impl Event<'_> {
+    /// Emit the event through `tracing`. Those only driving a progress bar are left out.
+    pub(crate) fn trace(&self) {
+        match self {
+            Self::Counted { messages, elapsed } => info!(
+                event = "counted",
+                messages,
+                duration_ms = milliseconds(*elapsed)
+            ),
+            Self::Scanned {
+                files,
+                message_ids,
+                elapsed,
+            } => info!(
+                event = "scanned",
+                files,
+                message_ids,
+                duration_ms = milliseconds(*elapsed)
+            ),
+            Self::SpaceChecked {
+                required,
+                available,
+            } => info!(
+                event = "space_checked",
+                required_bytes = required,
+                available_bytes = available
+            ),
+            Self::Started { total, position } => match total {
+                ProgressTotal::Bytes(bytes) => {
+                    info!(event = "started", total_bytes = bytes, position)
+                }
+                ProgressTotal::Messages(messages) => {
+                    info!(event = "started", total_messages = messages, position)
+                }
+                ProgressTotal::Unknown => info!(event = "started", position),
+            },
+            Self::Scanning { .. } | Self::Processing(_) | Self::Progress { .. } => {}
+            Self::Written(path) => info!(event = "written", path = %path.display()),
+            Self::Failed { item, error } => error!(
+                event = "failed",
+                item,
+                error_kind = error.code(),
+                error = with_causes(*error)
+            ),
+            Self::Warning(message) => warn!(event = "warning", "{message}"),
+            Self::Watching(summary) => info!(
+                event = "watching",
+                converted = summary.converted,
+                errors = summary.errors.len()
+            ),
+            Self::Appended(path) => info!(event = "appended", path = %path.display()),
+            Self::Converted(details) => info!(
+                event = "message",
+                message_index = details.index,
+                action = "converted",
+                sender = details.sender.as_deref(),
+                date = details.date.as_deref(),
+                date_source = %details.date_source,
+                file = %details.file.display(),
+                duration_ms = milliseconds(details.elapsed),
+                notes = ?details.notes
+            ),
+            Self::Uploaded(details) => info!(
+                event = "message",
+                message_index = details.index,
+                action = if details.uploaded { "uploaded" } else { "listed" },
+                file = details.file.as_ref().map(|file| file.display().to_string()),
+                offset = details.offset,
+                subject = details.subject.as_deref(),
+                internal_date = details.internal_date.as_deref(),
+                flags = ?details.flags,
+                size_bytes = details.size
+            ),
+        }
+    }
+}
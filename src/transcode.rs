@@ -0,0 +1,167 @@
+//! Transcoding of the text in a message to UTF-8, following the charset declared by each part.
+
+use crate::{
+    charset::Charset,
+    mime::{ContentType, Entity, Field, Multipart},
+};
+
+/// What transcoding did to a message, ordered from least to most noteworthy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Transcoded {
+    /// The message has no text, or its text already is UTF-8.
+    Unchanged,
+    /// Text in the message was converted to UTF-8.
+    Converted,
+    /// Text in the message was left as it was, because its charset or transfer encoding is
+    /// unknown, no charset was declared for it, or it is nested too deeply.
+    Untouched,
+}
+
+/// Transcode the text of a message to UTF-8 in place, rewriting the charset and transfer
+/// encoding declared for it. A single-part message and the top-level parts of a multipart
+/// message are transcoded; anything nested deeper is left as it is.
+///
+/// With `detect`, text that declares no charset is taken to be UTF-8 if it is valid as such,
+/// and Windows-1252 otherwise.
+pub(crate) fn transcode_message(lines: &mut Vec<Vec<u8>>, detect: bool) -> Transcoded {
+    let (outcome, transcoded) = transcode_entity(lines, detect, true);
+    if let Some(transcoded) = transcoded {
+        *lines = transcoded;
+    }
+    outcome
+}
+
+/// Transcode a message or one of its parts, returning its new lines if any were changed.
+fn transcode_entity(
+    lines: &[Vec<u8>],
+    detect: bool,
+    top_level: bool,
+) -> (Transcoded, Option<Vec<Vec<u8>>>) {
+    let entity = Entity::parse(lines);
+    if entity.content_type.is("multipart") {
+        return match entity.multipart().filter(|_| top_level) {
+            Some(multipart) => transcode_parts(lines, &entity, &multipart, detect),
+            None => (Transcoded::Untouched, None),
+        };
+    }
+    if !entity.content_type.is("text") {
+        return (Transcoded::Unchanged, None);
+    }
+
+    let Some(decoded) = entity.decode_body() else {
+        return (Transcoded::Untouched, None);
+    };
+    let declared = match entity.content_type.param("charset") {
+        Some(label) => match Charset::for_label(&label) {
+            Some(charset) => Some(charset),
+            None => return (Transcoded::Untouched, None),
+        },
+        None => None,
+    };
+    // ISO-2022-JP is written in ASCII bytes, but not as ASCII text.
+    if decoded.is_ascii() && declared.is_none_or(Charset::is_ascii_compatible) {
+        return (Transcoded::Unchanged, None);
+    }
+    let charset = match declared {
+        // Already UTF-8, unless it is mislabelled, which is left for the reader to sort out.
+        Some(Charset::Ascii | Charset::Utf8) => {
+            return match std::str::from_utf8(&decoded) {
+                Ok(_) => (Transcoded::Unchanged, None),
+                Err(_) => (Transcoded::Untouched, None),
+            };
+        }
+        Some(charset) => charset,
+        None if !detect => return (Transcoded::Untouched, None),
+        None if std::str::from_utf8(&decoded).is_ok() => Charset::Utf8,
+        None => Charset::fallback(),
+    };
+    let Some(text) = charset.decode(&decoded) else {
+        return (Transcoded::Untouched, None);
+    };
+
+    let mut lines = rewrite_header(
+        entity.header,
+        &entity.fields,
+        &with_utf8_charset(&entity.content_type),
+    );
+    lines.push(Vec::new());
+    lines.extend(
+        text.split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line).as_bytes().to_vec()),
+    );
+    (Transcoded::Converted, Some(lines))
+}
+
+/// Transcode the parts of a multipart message, returning its new lines if any part was
+/// changed. The preamble, delimiters and epilogue are kept as they are.
+fn transcode_parts(
+    lines: &[Vec<u8>],
+    entity: &Entity<'_>,
+    multipart: &Multipart,
+    detect: bool,
+) -> (Transcoded, Option<Vec<Vec<u8>>>) {
+    let body = entity.body;
+    let (mut outcome, mut changed) = (Transcoded::Unchanged, false);
+    let mut transcoded = lines[..lines.len() - body.len()].to_vec();
+    transcoded.extend_from_slice(&body[..multipart.delimiters[0]]);
+    for part in multipart.parts() {
+        transcoded.push(body[part.start - 1].clone());
+        let (part_outcome, lines) = transcode_entity(&body[part.clone()], detect, false);
+        outcome = outcome.max(part_outcome);
+        match lines {
+            Some(lines) => {
+                changed = true;
+                transcoded.extend(lines);
+            }
+            None => transcoded.extend_from_slice(&body[part]),
+        }
+    }
+    if multipart.closed {
+        let close = *multipart.delimiters.last().expect("not empty");
+        transcoded.extend_from_slice(&body[close..]);
+    }
+    (outcome, changed.then_some(transcoded))
+}
+
+/// Copy a header block, replacing its Content-Type and Content-Transfer-Encoding fields with
+/// ones for 8-bit UTF-8 text. Fields that are missing are added at the end.
+fn rewrite_header(header: &[Vec<u8>], fields: &[Field], content_type: &str) -> Vec<Vec<u8>> {
+    let replacements = [
+        ("content-type", format!("Content-Type: {content_type}")),
+        (
+            "content-transfer-encoding",
+            "Content-Transfer-Encoding: 8bit".to_string(),
+        ),
+    ];
+    let mut lines = Vec::with_capacity(header.len() + replacements.len());
+    let mut replaced = [false; 2];
+    for field in fields {
+        match replacements.iter().position(|(name, _)| field.is(name)) {
+            Some(index) if !replaced[index] => {
+                replaced[index] = true;
+                lines.push(replacements[index].1.as_bytes().to_vec());
+            }
+            // A duplicate would contradict the replacement.
+            Some(_) => {}
+            None => lines.extend_from_slice(&header[field.lines.clone()]),
+        }
+    }
+    for (index, (_, line)) in replacements.iter().enumerate() {
+        if !replaced[index] {
+            lines.push(line.as_bytes().to_vec());
+        }
+    }
+    lines
+}
+
+/// A Content-Type value with its charset parameter set to UTF-8, keeping the other parameters.
+fn with_utf8_charset(content_type: &ContentType) -> String {
+    let mut value = format!("{}; charset=utf-8", content_type.media_type);
+    for (name, param) in &content_type.params {
+        if name != "charset" {
+            value.push_str("; ");
+            value.push_str(param);
+        }
+    }
+    value
+}
@@ -0,0 +1,47 @@
+//! The rules an eml file has to meet to be taken for an email, rather than an HTML export or
+//! an empty file that happens to have the right extension.
+
+use crate::{MailfmtError, error::Result, headers::is_header_line};
+use std::path::Path;
+
+/// Check that an eml file's content starts with a plausible header block, made only of header
+/// fields and their continuation lines up to the first blank line, and that it holds every
+/// one of the `required` headers with a value. Header names are matched case-insensitively.
+///
+/// Fails with [`MailfmtError::InvalidEml`] or [`MailfmtError::MissingHeaders`].
+pub fn validate_eml(path: &Path, content: &str, required: &[String]) -> Result<()> {
+    let header_block: Vec<&str> = content
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .collect();
+    let plausible = header_block
+        .first()
+        .is_some_and(|line| is_header_line(line))
+        && header_block
+            .iter()
+            .all(|line| is_header_line(line) || line.starts_with([' ', '\t']));
+    if !plausible {
+        return Err(MailfmtError::InvalidEml {
+            path: path.to_path_buf(),
+        });
+    }
+
+    let missing: Vec<String> = required
+        .iter()
+        .filter(|name| {
+            !header_block.iter().any(|line| {
+                line.split_once(':').is_some_and(|(field, value)| {
+                    field.eq_ignore_ascii_case(name) && !value.trim().is_empty()
+                })
+            })
+        })
+        .cloned()
+        .collect();
+    match missing.is_empty() {
+        true => Ok(()),
+        false => Err(MailfmtError::MissingHeaders {
+            path: path.to_path_buf(),
+            missing,
+        }),
+    }
+}
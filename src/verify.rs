@@ -0,0 +1,113 @@
+//! Reading back the messages written to an mbox and comparing them with the content they were
+//! written from.
+
+use crate::{
+    Event, MailfmtError,
+    encoding::InvalidUtf8,
+    error::{IoContext, Result},
+    mbox::{MboxParser, OffsetLines},
+    writer::MboxDialect,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// The SHA-256 of a message given as its full content, in the form that is compared.
+pub(crate) fn content_hash(content: &[u8], dialect: MboxDialect) -> String {
+    let lines = content
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    comparable_hash(lines, dialect)
+}
+
+/// The SHA-256 of the lines of a message in the form that is compared: with LF line endings,
+/// without the Content-Length header a Content-Length dialect writes its own of, and without
+/// blank lines at its end, as the blank line that ends every message in an mbox does not tell
+/// how many it had.
+fn comparable_hash<'a>(lines: impl Iterator<Item = &'a [u8]>, dialect: MboxDialect) -> String {
+    let mut hasher = Sha256::new();
+    let (mut in_header, mut blank_lines) = (true, 0);
+    for line in lines {
+        if line.is_empty() {
+            in_header = false;
+            blank_lines += 1;
+            continue;
+        }
+        if in_header && dialect.content_length() && is_content_length(line) {
+            continue;
+        }
+        for _ in 0..blank_lines {
+            hasher.update(b"\n");
+        }
+        blank_lines = 0;
+        hasher.update(line);
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn is_content_length(line: &[u8]) -> bool {
+    line.get(..15)
+        .is_some_and(|name| name.eq_ignore_ascii_case(b"content-length:"))
+}
+
+/// Read the messages of `mbox_file` from byte `start` on and compare them in order with the
+/// eml files they were written from and the [`content_hash`] of what was written for each.
+/// Every difference is reported as a warning before failing with
+/// [`MailfmtError::VerificationFailed`].
+pub(crate) fn verify_mbox(
+    mbox_file: &Path,
+    start: u64,
+    written: &[(PathBuf, String)],
+    dialect: MboxDialect,
+    emit: impl Fn(Event<'_>) -> Result<()>,
+) -> Result<()> {
+    let mut file = File::open(mbox_file).io_context("open mbox file", mbox_file)?;
+    file.seek(SeekFrom::Start(start))
+        .io_context("seek in mbox file", mbox_file)?;
+    // Read back as the dialect wrote it, so that body lines come back unquoted.
+    let parser = MboxParser::new(OffsetLines::new(BufReader::new(file), start))
+        .with_invalid_utf8(InvalidUtf8::Preserve)
+        .with_dialect(dialect);
+
+    let (mut read, mut mismatched) = (0, 0);
+    for message in parser {
+        let message = message?;
+        let lines = message.lines.iter().map(Vec::as_slice);
+        match written.get(read) {
+            Some((eml_file, hash)) if *hash != comparable_hash(lines, dialect) => {
+                mismatched += 1;
+                emit(Event::Warning(format!(
+                    "Message {read} written by this run, at byte {} of the mbox file, does not read back as {eml_file:?}, which it was written from",
+                    message.offset
+                )))?;
+            }
+            Some(_) => {}
+            None => {
+                mismatched += 1;
+                emit(Event::Warning(format!(
+                    "Message {read} at byte {} of the mbox file was not written by this run, which wrote {} messages",
+                    message.offset,
+                    written.len()
+                )))?;
+            }
+        }
+        read += 1;
+    }
+    for (index, (eml_file, _)) in written.iter().enumerate().skip(read) {
+        mismatched += 1;
+        emit(Event::Warning(format!(
+            "Message {index} written by this run from {eml_file:?} was not found when reading the mbox file back"
+        )))?;
+    }
+    match mismatched {
+        0 => Ok(()),
+        mismatched => Err(MailfmtError::VerificationFailed {
+            mismatched,
+            written: written.len(),
+        }),
+    }
+}
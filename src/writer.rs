@@ -0,0 +1,479 @@
+use crate::{Message, headers::get_header_value};
+use chrono::{DateTime, FixedOffset};
+use std::{
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+};
+
+/// The variant of the mbox format to write, which decides how lines in a message body that
+/// look like a "From " separator are protected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MboxDialect {
+    /// Body lines starting with "From " are quoted as ">From ". Readers cannot tell these
+    /// apart from lines that started with ">From " to begin with.
+    Mboxo,
+    /// Body lines starting with any number of '>' followed by "From " gain another '>', which
+    /// readers can reverse exactly.
+    #[default]
+    Mboxrd,
+    /// Quoted like [`MboxDialect::Mboxo`], with a Content-Length header added to every message.
+    Mboxcl,
+    /// Not quoted at all, relying on a Content-Length header added to every message instead.
+    Mboxcl2,
+}
+
+impl MboxDialect {
+    fn quote(self, line: &[u8]) -> bool {
+        match self {
+            Self::Mboxo | Self::Mboxcl => line.starts_with(b"From "),
+            Self::Mboxrd => line
+                .iter()
+                .position(|&b| b != b'>')
+                .is_some_and(|start| line[start..].starts_with(b"From ")),
+            Self::Mboxcl2 => false,
+        }
+    }
+
+    /// A body line as it was before it was quoted. Lines that started with ">From " to begin
+    /// with are unquoted as well in the dialects that cannot tell them apart.
+    pub(crate) fn unquote(self, line: &[u8]) -> &[u8] {
+        let quoted = match self {
+            Self::Mboxo | Self::Mboxcl => line.starts_with(b">From "),
+            Self::Mboxrd => line.starts_with(b">") && self.quote(&line[1..]),
+            Self::Mboxcl2 => false,
+        };
+        match quoted {
+            true => &line[1..],
+            false => line,
+        }
+    }
+
+    pub(crate) fn content_length(self) -> bool {
+        matches!(self, Self::Mboxcl | Self::Mboxcl2)
+    }
+}
+
+impl fmt::Display for MboxDialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Mboxo => "mboxo",
+            Self::Mboxrd => "mboxrd",
+            Self::Mboxcl => "mboxcl",
+            Self::Mboxcl2 => "mboxcl2",
+        })
+    }
+}
+
+impl FromStr for MboxDialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mboxo" => Ok(Self::Mboxo),
+            "mboxrd" => Ok(Self::Mboxrd),
+            "mboxcl" => Ok(Self::Mboxcl),
+            "mboxcl2" => Ok(Self::Mboxcl2),
+            _ => Err(format!(
+                "unknown mbox dialect '{s}', expected one of mboxo, mboxrd, mboxcl or mboxcl2"
+            )),
+        }
+    }
+}
+
+/// How the separator line starting every message in an mbox is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FromLineStyle {
+    /// "From " followed by the envelope sender and the date, as most tools write it.
+    #[default]
+    Sender,
+    /// "From - " followed by the date, with a dash in place of the sender, as Thunderbird
+    /// writes it.
+    Thunderbird,
+}
+
+impl fmt::Display for FromLineStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sender => "sender",
+            Self::Thunderbird => "thunderbird",
+        })
+    }
+}
+
+impl FromStr for FromLineStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sender" => Ok(Self::Sender),
+            "thunderbird" => Ok(Self::Thunderbird),
+            _ => Err(format!(
+                "unknown From line style '{s}', expected sender or thunderbird"
+            )),
+        }
+    }
+}
+
+/// Writes messages to an mbox, taking care of the separator lines, quoting and the blank line
+/// that has to end every message.
+///
+/// ```
+/// use mailfmt::{MboxDialect, MboxWriter};
+///
+/// let mut writer = MboxWriter::new(Vec::new(), MboxDialect::Mboxrd);
+/// writer.append_raw(b"Subject: Hi\n\nFrom here on\n>From there", "a@example.com Mon Jan 01 00:00:00 2024")?;
+/// writer.append_raw(b"Subject: Empty\n", "b@example.com Mon Jan 01 00:00:00 2024")?;
+/// let mbox = writer.finish()?;
+///
+/// assert_eq!(
+///     String::from_utf8(mbox).unwrap(),
+///     "From a@example.com Mon Jan 01 00:00:00 2024\n\
+///      Subject: Hi\n\
+///      \n\
+///      >From here on\n\
+///      >>From there\n\
+///      \n\
+///      From b@example.com Mon Jan 01 00:00:00 2024\n\
+///      Subject: Empty\n\
+///      \n"
+/// );
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// With a Content-Length dialect the length of the body is added to the header:
+///
+/// ```
+/// use mailfmt::{MboxDialect, MboxWriter};
+///
+/// let mut writer = MboxWriter::new(Vec::new(), MboxDialect::Mboxcl2);
+/// writer.append_raw(b"Subject: Hi\nContent-Length: 99\n\nFrom here on\n", "a@example.com Mon Jan 01 00:00:00 2024")?;
+///
+/// assert_eq!(
+///     String::from_utf8(writer.finish()?).unwrap(),
+///     "From a@example.com Mon Jan 01 00:00:00 2024\n\
+///      Subject: Hi\n\
+///      Content-Length: 13\n\
+///      \n\
+///      From here on\n\
+///      \n"
+/// );
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct MboxWriter<W: Write> {
+    inner: W,
+    dialect: MboxDialect,
+    from_line_style: FromLineStyle,
+    bytes_written: u64,
+}
+
+impl<W: Write> MboxWriter<W> {
+    pub fn new(inner: W, dialect: MboxDialect) -> Self {
+        Self {
+            inner,
+            dialect,
+            from_line_style: FromLineStyle::default(),
+            bytes_written: 0,
+        }
+    }
+
+    /// Set how separator lines are written. With [`FromLineStyle::Thunderbird`], the sender
+    /// of every envelope is replaced by a dash, keeping its date.
+    ///
+    /// ```
+    /// use mailfmt::{FromLineStyle, MboxDialect, MboxWriter};
+    ///
+    /// let mut writer = MboxWriter::new(Vec::new(), MboxDialect::Mboxrd)
+    ///     .with_from_line_style(FromLineStyle::Thunderbird);
+    /// writer.append_raw(b"Subject: Hi\n\nHello\n", "a@example.com Mon Jan 01 00:00:00 2024")?;
+    ///
+    /// assert_eq!(
+    ///     String::from_utf8(writer.finish()?).unwrap(),
+    ///     "From - Mon Jan 01 00:00:00 2024\n\
+    ///      Subject: Hi\n\
+    ///      \n\
+    ///      Hello\n\
+    ///      \n"
+    /// );
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn with_from_line_style(mut self, style: FromLineStyle) -> Self {
+        self.from_line_style = style;
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// How many bytes have been written so far, including separator lines and blank lines.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Append a message read from an mbox, keeping its original envelope. Its lines are taken
+    /// as they are, so a message from an mbox that quoted them should be read with
+    /// [`MboxParser::with_dialect`](crate::MboxParser::with_dialect) to unquote them first.
+    pub fn append_message(&mut self, message: &Message) -> io::Result<()> {
+        let mut content = message.lines.join(&b'\n');
+        content.push(b'\n');
+        self.append_raw(&content, &message.envelope)
+    }
+
+    /// Append a message given as its full content, such as the content of an eml file, with
+    /// the given envelope after "From " on its separator line. See [`envelope_for`] for
+    /// creating one from the message's headers.
+    pub fn append_raw(&mut self, content: &[u8], envelope: &str) -> io::Result<()> {
+        let (header, body) = split_header(content);
+        let body = self.quote_body(body);
+
+        let envelope = envelope.trim_end();
+        let separator = match self.from_line_style {
+            FromLineStyle::Sender => format!("From {envelope}\n"),
+            FromLineStyle::Thunderbird => match envelope.split_once(' ') {
+                Some((_, date)) => format!("From - {}\n", date.trim_start()),
+                None => "From -\n".to_string(),
+            },
+        };
+        self.write(separator.as_bytes())?;
+        if self.dialect.content_length() {
+            self.write_header_with_length(header, body.len())?;
+        } else {
+            self.write(header)?;
+        }
+        self.write(&body)?;
+
+        // Every message ends with a blank line before the next separator.
+        let written: &[u8] = if !body.is_empty() {
+            &body
+        } else if self.dialect.content_length() {
+            // The header was always written with its blank line.
+            b"\n\n"
+        } else {
+            header
+        };
+        match written {
+            b if b.ends_with(b"\n\n") || b.ends_with(b"\r\n\r\n") => {}
+            b if b.ends_with(b"\n") => self.write(b"\n")?,
+            _ => self.write(b"\n\n")?,
+        }
+        Ok(())
+    }
+
+    /// Write a header block with a Content-Length field for a body of the given length added
+    /// before its blank line. Any existing Content-Length would be wrong after quoting, so it
+    /// is replaced.
+    fn write_header_with_length(&mut self, header: &[u8], length: usize) -> io::Result<()> {
+        let crlf = header.ends_with(b"\r\n");
+        let line_ending: &[u8] = if crlf { b"\r\n" } else { b"\n" };
+        let mut fields = header;
+        if let Some(stripped) = fields.strip_suffix(line_ending)
+            && (stripped.is_empty() || stripped.ends_with(b"\n"))
+        {
+            fields = stripped;
+        }
+        for line in fields.split_inclusive(|&b| b == b'\n') {
+            if !line.to_ascii_lowercase().starts_with(b"content-length:") {
+                self.write(line)?;
+            }
+        }
+        if !fields.is_empty() && !fields.ends_with(b"\n") {
+            self.write(line_ending)?;
+        }
+        self.write(format!("Content-Length: {length}").as_bytes())?;
+        self.write(line_ending)?;
+        self.write(line_ending)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.inner.write_all(bytes)?;
+        self.bytes_written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Flush buffered output to the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Flush and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn quote_body(&self, body: &[u8]) -> Vec<u8> {
+        let mut quoted = Vec::with_capacity(body.len());
+        for line in body.split_inclusive(|&b| b == b'\n') {
+            if self.dialect.quote(line) {
+                quoted.push(b'>');
+            }
+            quoted.extend_from_slice(line);
+        }
+        quoted
+    }
+}
+
+/// Split a message into its header block, including the blank line ending it, and its body.
+/// A message without a blank line is all header.
+fn split_header(content: &[u8]) -> (&[u8], &[u8]) {
+    let mut end = 0;
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        end += line.len();
+        if line == b"\n" || line == b"\r\n" {
+            return content.split_at(end);
+        }
+    }
+    (content, &[])
+}
+
+/// The address in an address header value such as `René <r@example.com>`, if it has a usable
+/// one.
+pub(crate) fn address_of(value: &str) -> Option<&str> {
+    match value.rsplit_once('<') {
+        // The address follows the last '<', as the name before it may contain one.
+        Some((_, address)) => address.split_once('>').map(|(address, _)| address),
+        // A bare address, possibly followed by a comment with the sender's name.
+        None => value.split_whitespace().find(|part| part.contains('@')),
+    }
+    .filter(|addr| !addr.is_empty() && !addr.contains(char::is_whitespace))
+}
+
+/// The sender used in a separator line when a message has no usable From header.
+pub(crate) const PLACEHOLDER_SENDER: &str = "unknown@example.com";
+
+/// The date used in a separator line when a message has no usable Date header.
+pub(crate) const PLACEHOLDER_DATE: &str = "Mon Jan 01 00:00:00 2024";
+
+/// Create the envelope for a message's separator line from its From and Date headers: the
+/// sender address and the date in asctime format. Fallbacks are used for missing or unusable
+/// headers, so that the separator line is always valid.
+pub fn envelope_for(content: &str) -> String {
+    let (from_addr, date_str) = envelope_parts(content);
+    format!(
+        "{} {}",
+        from_addr.unwrap_or(PLACEHOLDER_SENDER),
+        date_str.as_deref().unwrap_or(PLACEHOLDER_DATE)
+    )
+}
+
+/// The sender address and the date in asctime format for a message's separator line, or
+/// `None` for a From or Date header that is missing or unusable.
+pub(crate) fn envelope_parts(content: &str) -> (Option<&str>, Option<String>) {
+    let from_addr = get_header_value(content, "from").and_then(address_of);
+    let date_str = get_header_value(content, "date")
+        .and_then(parse_date)
+        .map(|dt| asctime(&dt));
+    (from_addr, date_str)
+}
+
+/// A date in the asctime format of separator lines, such as `Mon Jan 01 00:00:00 2024`, in
+/// its own time zone.
+pub(crate) fn asctime(date: &DateTime<FixedOffset>) -> String {
+    date.format("%a %b %d %H:%M:%S %Y").to_string()
+}
+
+/// Parse the value of a Date header, which should be in RFC 2822 format, though some tools
+/// write RFC 3339 dates.
+pub(crate) fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc2822(value)
+        .or_else(|_| DateTime::parse_from_rfc3339(value))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENVELOPE: &str = "a@example.com Mon Jan 01 00:00:00 2024";
+
+    fn written(dialect: MboxDialect, messages: &[&[u8]]) -> String {
+        let mut writer = MboxWriter::new(Vec::new(), dialect);
+        for message in messages {
+            writer.append_raw(message, ENVELOPE).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn every_message_starts_with_a_separator_and_ends_with_a_blank_line() {
+        assert_eq!(
+            written(
+                MboxDialect::Mboxrd,
+                &[b"Subject: A\n\nOne\n", b"Subject: B\n\nTwo\n\n"]
+            ),
+            "From a@example.com Mon Jan 01 00:00:00 2024\nSubject: A\n\nOne\n\n\
+             From a@example.com Mon Jan 01 00:00:00 2024\nSubject: B\n\nTwo\n\n"
+        );
+        let mut writer = MboxWriter::new(Vec::new(), MboxDialect::Mboxrd)
+            .with_from_line_style(FromLineStyle::Thunderbird);
+        writer.append_raw(b"Subject: A\n\nOne\n", "nodate").unwrap();
+        assert_eq!(
+            String::from_utf8(writer.finish().unwrap()).unwrap(),
+            "From -\nSubject: A\n\nOne\n\n"
+        );
+    }
+
+    #[test]
+    fn body_lines_are_quoted_as_the_dialect_says() {
+        let message = b"From: a@example.com\n\nFrom here\n>From there\nFromage\n";
+        for (dialect, body) in [
+            (MboxDialect::Mboxo, ">From here\n>From there\nFromage\n"),
+            (MboxDialect::Mboxrd, ">From here\n>>From there\nFromage\n"),
+            (MboxDialect::Mboxcl, ">From here\n>From there\nFromage\n"),
+            (MboxDialect::Mboxcl2, "From here\n>From there\nFromage\n"),
+        ] {
+            let mbox = written(dialect, &[message]);
+            assert!(
+                mbox.contains(&format!("\n\n{body}\n")),
+                "{dialect}: {mbox:?}"
+            );
+            // Header lines are never quoted.
+            assert!(
+                mbox.contains("\nFrom: a@example.com\n"),
+                "{dialect}: {mbox:?}"
+            );
+            // The dialects that tell quoted lines apart give them back as they were.
+            if matches!(dialect, MboxDialect::Mboxrd | MboxDialect::Mboxcl2) {
+                let unquoted: Vec<_> = body
+                    .lines()
+                    .map(|line| dialect.unquote(line.as_bytes()))
+                    .collect();
+                assert_eq!(unquoted, [&b"From here"[..], b">From there", b"Fromage"]);
+            }
+        }
+    }
+
+    #[test]
+    fn an_empty_body_still_ends_with_a_blank_line() {
+        for dialect in [MboxDialect::Mboxrd, MboxDialect::Mboxcl2] {
+            let length = match dialect.content_length() {
+                true => "Content-Length: 0\n",
+                false => "",
+            };
+            for message in [&b"Subject: A\n"[..], b"Subject: A\n\n", b"Subject: A"] {
+                assert_eq!(
+                    written(dialect, &[message]),
+                    format!("From {ENVELOPE}\nSubject: A\n{length}\n"),
+                    "{dialect}: {message:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn a_missing_final_newline_is_added_before_the_blank_line() {
+        assert_eq!(
+            written(MboxDialect::Mboxrd, &[b"Subject: A\n\nNo newline"]),
+            format!("From {ENVELOPE}\nSubject: A\n\nNo newline\n\n")
+        );
+        assert_eq!(
+            written(MboxDialect::Mboxcl2, &[b"Subject: A\n\nNo newline"]),
+            format!("From {ENVELOPE}\nSubject: A\nContent-Length: 10\n\nNo newline\n\n")
+        );
+        assert_eq!(
+            written(MboxDialect::Mboxrd, &[b"Subject: A\r\n\r\nCRLF\r\n"]),
+            format!("From {ENVELOPE}\nSubject: A\r\n\r\nCRLF\r\n\n")
+        );
+    }
+}
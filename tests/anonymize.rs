@@ -0,0 +1,190 @@
+//! Anonymizes an mbox and checks that its content is gone while its structure is kept.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{AnonymizeOptions, MboxParser, Message, anonymize_mbox, headers};
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::Path,
+};
+
+/// A multipart message with an encoded subject and an attachment, and a reply to it.
+const MBOX: &str = "\
+From alice@corp.com Mon Jan 01 00:00:00 2024
+From: \"Alice Liddell\" <alice@corp.com>
+To: bob@other.org (Bob), Carol <carol@corp.com>
+Subject: =?utf-8?B?Q2Fmw6kgbWVldGluZw==?= tomorrow
+Message-ID: <abc123@mail.corp.com>
+Date: Mon, 1 Jan 2024 10:00:00 +0000
+Received: from mail.corp.com (10.0.0.1)
+\tby mx.other.org
+MIME-Version: 1.0
+Content-Type: multipart/mixed; boundary=\"XYZ\"
+User-Agent: Mutt 2.0
+
+Preamble text
+--XYZ
+Content-Type: text/plain; charset=utf-8
+Content-Transfer-Encoding: quoted-printable
+
+Caf=C3=A9 is open tomorrow, see you there with a long line that goes o=
+n and on
+> quoted reply
+--XYZ
+Content-Type: application/pdf; name=\"secret plan.pdf\"
+Content-Disposition: attachment; filename*=utf-8''pl%C3%A1n.pdf
+Content-Transfer-Encoding: base64
+
+SGVsbG8gd29ybGQsIHRoaXMgaXMgYSBzZWNyZXQgZG9jdW1lbnQuCkFuZCBhIHNlY29uZCBs
+aW5lLgo=
+--XYZ--
+
+From bob@other.org Mon Jan 01 01:00:00 2024
+From: Bob <bob@other.org>
+To: alice@corp.com
+Subject: Re: =?utf-8?B?Q2Fmw6kgbWVldGluZw==?= tomorrow
+In-Reply-To: <abc123@mail.corp.com>
+References: <abc123@mail.corp.com>
+
+Sure thing.
+";
+
+/// Anonymize the fixture with `seed` to `name`, returning its content.
+fn anonymize(dir: &Path, name: &str, seed: u64, keep_headers: &[&str]) -> String {
+    let mut options = AnonymizeOptions::new(dir.join("in.mbox"), dir.join(name));
+    options.seed = Some(seed);
+    options.keep_headers = keep_headers.iter().map(|name| name.to_string()).collect();
+    let anonymized = anonymize_mbox(&options).unwrap();
+    assert_eq!((anonymized.messages, anonymized.seed), (2, seed));
+    fs::read_to_string(dir.join(name)).unwrap()
+}
+
+fn messages(path: &Path) -> Vec<Message> {
+    MboxParser::from_reader(BufReader::new(File::open(path).unwrap()))
+        .map(Result::unwrap)
+        .collect()
+}
+
+/// The lines of a message from the one starting with `start` up to the next blank line.
+fn lines_from<'a>(message: &'a Message, start: &str) -> Vec<&'a [u8]> {
+    message
+        .lines
+        .iter()
+        .skip_while(|line| !line.starts_with(start.as_bytes()))
+        .take_while(|line| !line.is_empty())
+        .map(Vec::as_slice)
+        .collect()
+}
+
+#[test]
+fn the_same_seed_gives_the_same_output() {
+    let dir = temp_dir("anonymize-seed");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    let first = anonymize(&dir, "a.mbox", 42, &[]);
+    assert_eq!(anonymize(&dir, "b.mbox", 42, &[]), first);
+    assert_ne!(anonymize(&dir, "c.mbox", 7, &[]), first);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn content_is_replaced_and_structure_kept() {
+    let dir = temp_dir("anonymize");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    let output = anonymize(&dir, "out.mbox", 42, &[]);
+    for secret in [
+        "alice",
+        "Alice",
+        "Liddell",
+        "bob",
+        "carol",
+        "corp.com",
+        "other.org",
+        "10.0.0.1",
+        "Q2Fmw6k",
+        "tomorrow",
+        "Preamble",
+        "Caf",
+        "secret",
+        "pl%C3",
+        "SGVsbG8",
+        "Mutt",
+        "Sure",
+    ] {
+        assert!(!output.contains(secret), "{secret} in {output}");
+    }
+
+    let [message, reply] = &messages(&dir.join("out.mbox"))[..] else {
+        panic!("expected two messages");
+    };
+    // Addresses and Message-ID domains are replaced consistently, so threads survive.
+    let sender = message.header("from").unwrap();
+    assert!(sender.ends_with(".example>"), "{sender}");
+    let sender_address = &sender[sender.find('<').unwrap() + 1..sender.len() - 1];
+    assert_eq!(reply.header("to"), Some(sender_address));
+    assert!(message.envelope.starts_with(sender_address));
+    let message_id = message.header("message-id").unwrap();
+    assert!(message_id.starts_with("<abc123@"));
+    assert_eq!(reply.header("in-reply-to"), Some(message_id));
+    assert_eq!(reply.header("references"), Some(message_id));
+
+    // Subjects keep their encoded words, their length and reply markers.
+    let subject = message.header("subject").unwrap();
+    assert!(subject.starts_with("=?utf-8?B?"), "{subject}");
+    let decoded = headers::decode_encoded_words(subject);
+    assert_eq!(
+        decoded.chars().count(),
+        "Café meeting tomorrow".chars().count()
+    );
+    assert_eq!(reply.header("subject").unwrap(), format!("Re: {subject}"));
+
+    // Structural headers, delimiters and transfer encodings are kept.
+    assert_eq!(
+        message.header("date"),
+        Some("Mon, 1 Jan 2024 10:00:00 +0000")
+    );
+    assert_eq!(
+        message.header("content-type"),
+        Some("multipart/mixed; boundary=\"XYZ\"")
+    );
+    let original = &messages(&dir.join("in.mbox"))[0];
+    let delimiters = |message: &Message| {
+        message
+            .lines
+            .iter()
+            .filter(|line| line.starts_with(b"--XYZ") || line.starts_with(b"Content-Transfer"))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(delimiters(message), delimiters(original));
+
+    // Every line of the body keeps its length, including the headers of parts, the
+    // quoted-printable lines with their soft line breaks and the base64 ones.
+    let body = |message: &Message| {
+        let start = message.lines.iter().position(Vec::is_empty).unwrap();
+        message.lines[start..].to_vec()
+    };
+    let (anonymized_body, original_body) = (body(message), body(original));
+    assert_eq!(anonymized_body.len(), original_body.len());
+    for (anonymized, original) in anonymized_body.iter().zip(&original_body) {
+        assert_eq!(anonymized.len(), original.len());
+        assert_eq!(anonymized.ends_with(b"="), original.ends_with(b"="));
+    }
+
+    // File names are scrambled, keeping their extension's length and RFC 2231 labels.
+    let attachment = lines_from(message, "Content-Type: application/pdf");
+    assert!(attachment[1].starts_with(b"Content-Disposition: attachment; filename*=utf-8''"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn kept_headers_are_left_as_they_are() {
+    let dir = temp_dir("anonymize-keep");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    let output = anonymize(&dir, "out.mbox", 1, &["user-agent", "Received"]);
+    assert!(output.contains("\nUser-Agent: Mutt 2.0\n"));
+    assert!(output.contains("\nReceived: from mail.corp.com (10.0.0.1)\n\tby mx.other.org\n"));
+    assert!(!output.contains("alice"));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,130 @@
+//! Appends eml files to existing mbox files, including one that was cut short before its
+//! closing blank line, and checks what the summary tells about them.
+
+mod common;
+
+use common::fixture_dir;
+use mailfmt::{EmlInput, EmlToMboxOptions, Event, MboxParser, eml_to_mbox};
+use std::{cell::RefCell, fs, process::Command};
+
+const EXISTING: &str = "\
+From a@example.com Mon Jan 01 00:00:00 2024
+Message-ID: <old@example.com>
+Subject: Old
+
+Kept
+
+";
+
+/// The eml files converted in each test.
+const EML_FILES: &[(&str, &str)] = &[
+    (
+        "new.eml",
+        "Message-ID: <new@example.com>
+Subject: New
+
+Added
+",
+    ),
+    (
+        "again.eml",
+        "Message-ID: <old@example.com>
+Subject: Again
+
+Repeated
+",
+    ),
+];
+
+fn subjects(mbox: &str) -> Vec<String> {
+    MboxParser::from_reader(mbox.as_bytes())
+        .map(|message| message.unwrap().header("subject").unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn a_missing_blank_line_is_added_before_appending() {
+    for existing in [EXISTING, EXISTING.trim_end(), "\n"] {
+        let dir = fixture_dir("append-blank-line", EML_FILES);
+        let output = dir.join("all.mbox");
+        fs::write(&output, existing).unwrap();
+
+        let warnings = RefCell::new(Vec::new());
+        let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), &output);
+        options.append = true;
+        options.on_event = Some(Box::new(|event| {
+            if let Event::Warning(warning) = event {
+                warnings.borrow_mut().push(warning);
+            }
+            Ok(())
+        }));
+        let summary = eml_to_mbox(&options).unwrap();
+        drop(options);
+        assert_eq!(summary.converted, 2);
+
+        let mbox = fs::read_to_string(&output).unwrap();
+        let old = existing.trim().len();
+        assert_eq!(&mbox[..old], existing.trim());
+        assert!(mbox[old..].starts_with("\n\nFrom "), "{mbox:?}");
+        let mut expected = vec!["Again", "New"];
+        if old > 0 {
+            expected.insert(0, "Old");
+        }
+        assert_eq!(subjects(&mbox), expected);
+        assert_eq!(summary.preexisting, expected.len() - 2);
+        assert_eq!(
+            warnings.into_inner().len(),
+            usize::from(existing != EXISTING),
+            "{existing:?}"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn duplicates_of_messages_already_in_the_mbox_are_reported() {
+    let dir = fixture_dir("append-duplicates", EML_FILES);
+    let output = dir.join("all.mbox");
+    fs::write(&output, EXISTING).unwrap();
+
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), &output);
+    options.append = true;
+    options.report_duplicates = true;
+    let summary = eml_to_mbox(&options).unwrap();
+    assert_eq!((summary.preexisting, summary.converted), (1, 2));
+    assert_eq!(summary.duplicates.len(), 1);
+    let group = &summary.duplicates[0];
+    assert_eq!(group.message_id, "old@example.com");
+    let files: Vec<_> = group.messages.iter().map(|m| m.file.clone()).collect();
+    assert_eq!(files, [output.clone(), dir.join("in/again.eml")]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_reports_the_messages_already_held() {
+    let dir = fixture_dir("append-cli", EML_FILES);
+    let output = dir.join("all.mbox");
+    fs::write(&output, EXISTING).unwrap();
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+            .env("NO_COLOR", "1")
+            .arg("eml-to-mbox")
+            .arg(dir.join("in"))
+            .arg(&output)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let appended = run(&["--append"]);
+    assert!(appended.status.success(), "{appended:?}");
+    let stdout = String::from_utf8(appended.stdout).unwrap();
+    assert!(
+        stdout.contains("held 1 messages before this run, and 2 were appended"),
+        "{stdout}"
+    );
+
+    let refused = run(&["--append", "--overwrite"]);
+    assert!(!refused.status.success(), "{refused:?}");
+    fs::remove_dir_all(&dir).unwrap();
+}
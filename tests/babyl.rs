@@ -0,0 +1,115 @@
+//! Extracts the messages of an Emacs Rmail file in Babyl format, and checks that they keep their
+//! original headers and get their labels as headers.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{InputFormat, MboxToEmlOptions, count_mbox, mbox_to_eml};
+use std::{fs, process::Command};
+
+/// Two messages: the first reformatted by Rmail, with its original header before the EOOH
+/// line, answered and labelled, and the second unseen and never reformatted.
+const BABYL: &str = "BABYL OPTIONS: -*- rmail -*-\n\
+Version: 5\n\
+Labels: work,family\n\
+Note:   This is the header of an rmail file.\n\
+\x1f\x0c\n\
+1, answered,, work, family,\n\
+Summary-line:  2-Jan     alice@example.com  [4] #Lunch\n\
+Return-Path: <alice@example.com>\n\
+From: Alice <alice@example.com>\n\
+Subject: Lunch\n\
+Date: Tue, 02 Jan 2024 10:30:00 +0100\n\
+Received: by mail.example.com\n\
+X-Keywords: old\n\
+\n\
+*** EOOH ***\n\
+From: Alice <alice@example.com>\n\
+Subject: Lunch\n\
+\n\
+Noon?\n\
+From the kitchen, with love.\n\
+\x1f\x0c\n\
+0, unseen,,\n\
+*** EOOH ***\n\
+From: bob@example.com\n\
+Subject: Status report\n\
+Status: RO\n\
+\n\
+All good.\n\
+\x1f";
+
+#[test]
+fn messages_keep_their_original_headers_and_get_their_labels() {
+    let dir = temp_dir("babyl-extract");
+    fs::write(dir.join("RMAIL"), BABYL).unwrap();
+    let output = dir.join("out");
+
+    let mut options = MboxToEmlOptions::new(dir.join("RMAIL"), &output);
+    options.input_format = InputFormat::Babyl;
+    options.sidecar_metadata = true;
+    assert_eq!(count_mbox(&options).unwrap().matching, 2);
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.errors.len()), (2, 0));
+
+    assert_eq!(
+        fs::read_to_string(output.join("0000_Lunch.eml")).unwrap(),
+        "Return-Path: <alice@example.com>\n\
+         From: Alice <alice@example.com>\n\
+         Subject: Lunch\n\
+         Date: Tue, 02 Jan 2024 10:30:00 +0100\n\
+         Received: by mail.example.com\n\
+         Status: RO\n\
+         X-Status: A\n\
+         X-Keywords: work, family\n\
+         \n\
+         Noon?\n\
+         From the kitchen, with love.\n"
+    );
+    assert_eq!(
+        fs::read_to_string(output.join("0001_Status report.eml")).unwrap(),
+        "From: bob@example.com\nSubject: Status report\n\nAll good.\n"
+    );
+    let sidecar = fs::read_to_string(output.join("0000_Lunch.meta.json")).unwrap();
+    assert!(
+        sidecar.contains("\"envelope_sender\": \"alice@example.com\""),
+        "{sidecar}"
+    );
+    assert!(sidecar.contains("\"envelope_date\": \"Tue Jan 02 10:30:00 2024\""));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_refuses_an_mbox_given_as_babyl() {
+    let dir = temp_dir("babyl-cli");
+    fs::write(
+        dir.join("in.mbox"),
+        "From a Mon Jan 01 00:00:00 2024\nSubject: A\n\nA\n",
+    )
+    .unwrap();
+    fs::write(dir.join("RMAIL"), BABYL).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .args(["mbox-to-eml", "--input-format", "babyl"])
+        .arg(dir.join("in.mbox"))
+        .arg(dir.join("out"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("does not look like a Babyl file"),
+        "{stderr}"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .args(["mbox-to-eml", "--input-format", "rmail", "--count-only"])
+        .arg(dir.join("RMAIL"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "2");
+    fs::remove_dir_all(&dir).unwrap();
+}
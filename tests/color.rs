@@ -0,0 +1,61 @@
+//! Runs conversions that report errors and warnings with `--color never` and checks that no
+//! escape codes reach redirected output, even where colors would otherwise be forced.
+
+mod common;
+
+use common::temp_dir;
+use std::{fs, path::Path, process::Command};
+
+/// A message, and one without a header block that is reported as an error or a warning.
+const MBOX: &str = "\
+From a@example.com Mon Jan 01 00:00:00 2024
+Subject: One
+
+First
+
+From b@example.com Mon Jan 01 00:00:00 2024
+just text
+";
+
+/// Run the binary with colors forced through the environment, returning its stdout and stderr.
+fn run(color: &str, input: &Path, output: &Path, headerless: &str) -> (String, String) {
+    let result = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env_remove("NO_COLOR")
+        .env("CLICOLOR_FORCE", "1")
+        .arg("mbox-to-eml")
+        .arg(input)
+        .arg(output)
+        .args(["--headerless", headerless, "--color", color])
+        .output()
+        .unwrap();
+    (
+        String::from_utf8(result.stdout).unwrap(),
+        String::from_utf8(result.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn color_never_writes_no_escape_codes() {
+    let dir = temp_dir("color-never");
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, MBOX).unwrap();
+
+    let (stdout, stderr) = run("always", &mbox, &dir.join("always"), "error");
+    assert!(
+        stdout.contains("\x1b[") || stderr.contains("\x1b["),
+        "{stdout}{stderr}"
+    );
+
+    let runs = [
+        (mbox.clone(), dir.join("errors"), "error"),
+        (mbox.clone(), dir.join("warnings"), "skip"),
+        (dir.join("missing.mbox"), dir.join("missing"), "keep"),
+    ];
+    for (input, output, headerless) in runs {
+        let (stdout, stderr) = run("never", &input, &output, headerless);
+        assert!(!stdout.is_empty() || !stderr.is_empty());
+        assert!(!stdout.contains("\x1b["), "{stdout}");
+        assert!(!stderr.contains("\x1b["), "{stderr}");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
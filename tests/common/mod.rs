@@ -0,0 +1,23 @@
+//! Helpers shared by the integration tests.
+
+use std::{fs, path::PathBuf};
+
+/// An empty directory for the test called `name`, under the system temporary directory and
+/// unique to this test run. Whatever an earlier run left there is removed first.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mailfmt-test-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A [`temp_dir`] with an `in` directory holding the given eml files, by name and contents.
+#[allow(dead_code)]
+pub fn fixture_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::create_dir(dir.join("in")).unwrap();
+    for (file, contents) in files {
+        fs::write(dir.join("in").join(file), contents).unwrap();
+    }
+    dir
+}
@@ -0,0 +1,44 @@
+//! Prints the completion script of every supported shell and checks that it covers each
+//! subcommand.
+
+use std::process::Command;
+
+/// The subcommands listed by `mailfmt --help`.
+fn subcommands() -> Vec<String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .arg("--help")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .skip_while(|line| *line != "Commands:")
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|name| *name != "help")
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn every_shell_completes_every_subcommand() {
+    let subcommands = subcommands();
+    assert!(subcommands.contains(&"mbox-to-eml".to_string()));
+
+    for shell in ["bash", "zsh", "fish", "elvish", "powershell"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+            .env("NO_COLOR", "1")
+            .args(["completions", shell])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{shell}");
+        let script = String::from_utf8(output.stdout).unwrap();
+        assert!(!script.trim().is_empty(), "{shell}");
+        for name in &subcommands {
+            assert!(script.contains(name.as_str()), "{shell} misses {name}");
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Counts the messages a conversion would take without converting them, and checks that the
+//! count agrees with the conversion.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{
+    EmlInput, EmlToMboxOptions, Headerless, MboxToEmlOptions, count_eml_files, count_mbox,
+    mbox_to_eml,
+};
+use std::{fs, process::Command};
+
+/// Messages of two mailing lists, one without headers and one ending the file with a
+/// separator that has nothing after it.
+const MBOX: &str = "\
+From a@example.com Mon Jan 01 00:00:00 2024
+List-Id: Rust <rust.example.com>
+Subject: One
+
+First
+
+From b@example.com Mon Jan 01 00:00:00 2024
+Subject: Two
+
+Second
+
+From c@example.com Mon Jan 01 00:00:00 2024
+Just text: without headers
+
+From d@example.com Mon Jan 01 00:00:00 2024
+List-Id: Go
+ <go.example.com>
+Subject: Four
+
+Fourth
+
+From e@example.com Mon Jan 01 00:00:00 2024
+";
+
+#[test]
+fn mbox_counts_match_the_extraction() {
+    let dir = temp_dir("count-mbox");
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, MBOX).unwrap();
+
+    let configure: [fn(&mut MboxToEmlOptions); 5] = [
+        |_| {},
+        |options| options.headerless = Headerless::Skip,
+        |options| options.list_ids = vec!["example.com".to_string()],
+        |options| options.range = Some("1..3".parse().unwrap()),
+        |options| options.smart_separators = true,
+    ];
+    for (index, configure) in configure.iter().enumerate() {
+        let output = dir.join(format!("out-{index}"));
+        let mut options = MboxToEmlOptions::new(&mbox, &output);
+        configure(&mut options);
+        let count = count_mbox(&options).unwrap();
+        assert!(!output.exists());
+        let summary = mbox_to_eml(&options).unwrap();
+        assert_eq!(
+            count.matching as usize,
+            summary.converted + summary.errors.len(),
+            "options {index}: {count:?}"
+        );
+        assert!(count.found >= count.matching);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn eml_counts_leave_out_invalid_files() {
+    let dir = temp_dir("count-eml");
+    let input = dir.join("in");
+    fs::create_dir_all(input.join("nested")).unwrap();
+    fs::write(input.join("one.eml"), "Subject: One\r\n\r\nHello\r\n").unwrap();
+    fs::write(
+        input.join("nested/two.eml"),
+        "\u{feff}From: a@example.com\nSubject: Two\n\nHello\n",
+    )
+    .unwrap();
+    // Extensions are matched whatever their case.
+    fs::write(
+        input.join("nested/THREE.EML"),
+        "From: b@example.com\nSubject: Three\n\nHello\n",
+    )
+    .unwrap();
+    fs::write(input.join("page.eml"), "<html></html>\n").unwrap();
+    fs::write(input.join("notes.txt"), "Subject: Not an eml file\n").unwrap();
+    fs::write(
+        input.join("archive.Mbox"),
+        "From a@example.com Mon Jan 01 00:00:00 2024\nSubject: Not an eml file\n\n",
+    )
+    .unwrap();
+
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(input.clone()), "");
+    let count = count_eml_files(&options).unwrap();
+    assert_eq!((count.found, count.matching), (4, 4));
+    options.skip_invalid = true;
+    assert_eq!(count_eml_files(&options).unwrap().matching, 3);
+    options.require_headers = vec!["from".to_string()];
+    assert_eq!(count_eml_files(&options).unwrap().matching, 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn counts_are_printed_without_an_output() {
+    let dir = temp_dir("count-cli");
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, MBOX).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .args(["mbox-to-eml", mbox.to_str().unwrap(), "--count-only"])
+        .args(["--list-id", "<go.example.com>"])
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,59 @@
+//! Converts eml files whose lines end with a bare CR, as classic Mac OS mail clients wrote them,
+//! and checks that their headers are found and their lines written with LF, while a stray CR in
+//! an LF file is kept.
+
+mod common;
+
+use common::fixture_dir;
+use mailfmt::{EmlInput, EmlToMboxOptions, MboxParser, count_eml_files, eml_to_mbox};
+use std::fs;
+
+const CLASSIC: &str = "Received: from mac.example.com\r\
+From: Alice <alice@example.com>\r\
+Date: Mon, 01 Jan 2024 10:00:00 +0000\r\
+Subject: Classic\r\
+\r\
+Hello\r\
+From the past\r";
+
+#[test]
+fn cr_line_endings_are_converted_to_lf() {
+    let dir = fixture_dir("cr-line-endings", &[("classic.eml", CLASSIC)]);
+    let output = dir.join("out.mbox");
+
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), &output);
+    options.skip_invalid = true;
+    options.require_headers = vec!["Subject".to_string()];
+    assert_eq!(count_eml_files(&options).unwrap().matching, 1);
+    assert_eq!(eml_to_mbox(&options).unwrap().converted, 1);
+
+    let mbox = fs::read_to_string(&output).unwrap();
+    assert!(!mbox.contains('\r'), "{mbox:?}");
+    assert!(
+        mbox.starts_with("From alice@example.com Mon Jan 01 10:00:00 2024\n"),
+        "{mbox:?}"
+    );
+    let messages: Vec<_> = MboxParser::from_reader(mbox.as_bytes())
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].header("subject"), Some("Classic"));
+    assert_eq!(messages[0].header("received"), Some("from mac.example.com"));
+    assert!(mbox.contains("\n\nHello\n>From the past\n"), "{mbox:?}");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_stray_cr_in_an_lf_file_is_kept() {
+    let dir = fixture_dir(
+        "cr-line-endings-stray",
+        &[("stray.eml", "Subject: Stray\n\nA\rB\n")],
+    );
+    let output = dir.join("out.mbox");
+
+    let options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), &output);
+    eml_to_mbox(&options).unwrap();
+    let mbox = fs::read_to_string(&output).unwrap();
+    assert!(mbox.contains("Subject: Stray\n\nA\rB\n"), "{mbox:?}");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,91 @@
+//! Converts eml files into an mbox file in directories that do not exist yet, which are only
+//! created when asked to.
+
+mod common;
+
+use common::fixture_dir;
+use mailfmt::{EmlInput, EmlToMboxOptions, MailfmtError, eml_to_mbox};
+use std::{fs, process::Command};
+
+/// The eml files converted in each test.
+const EML_FILES: &[(&str, &str)] = &[(
+    "one.eml",
+    "Subject: One
+
+Hello
+",
+)];
+
+#[test]
+fn missing_parents_are_named_or_created() {
+    let dir = fixture_dir("create-parents", EML_FILES);
+    let output = dir.join("backups/2024/june/all.mbox");
+
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), &output);
+    match eml_to_mbox(&options) {
+        Err(MailfmtError::OutputParentMissing { path, missing }) => {
+            assert_eq!((path, missing), (output.clone(), dir.join("backups")))
+        }
+        result => panic!("unexpected result {result:?}"),
+    }
+    assert!(!dir.join("backups").exists());
+
+    options.create_parents = true;
+    assert_eq!(eml_to_mbox(&options).unwrap().converted, 1);
+    assert!(
+        fs::read_to_string(&output)
+            .unwrap()
+            .contains("Subject: One")
+    );
+    // Nothing but the mbox is left next to it.
+    let names: Vec<_> = fs::read_dir(output.parent().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["all.mbox"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_file_in_place_of_a_parent_is_not_replaced() {
+    let dir = fixture_dir("create-parents-file", EML_FILES);
+    fs::write(dir.join("backups"), "Not a directory").unwrap();
+
+    let mut options = EmlToMboxOptions::new(
+        EmlInput::Directory(dir.join("in")),
+        dir.join("backups/2024/all.mbox"),
+    );
+    options.create_parents = true;
+    match eml_to_mbox(&options) {
+        Err(MailfmtError::OutputNotADirectory { path }) => assert_eq!(path, dir.join("backups")),
+        result => panic!("unexpected result {result:?}"),
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_suggests_create_parents() {
+    let dir = fixture_dir("create-parents-cli", EML_FILES);
+    let output = dir.join("backups/2024/all.mbox");
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+            .env("NO_COLOR", "1")
+            .arg("eml-to-mbox")
+            .arg(dir.join("in"))
+            .arg(&output)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let refused = run(&[]);
+    assert!(!refused.status.success(), "{refused:?}");
+    let stderr = String::from_utf8(refused.stderr).unwrap();
+    assert!(stderr.contains("backups\" does not exist"), "{stderr}");
+    assert!(stderr.contains("--create-parents"), "{stderr}");
+
+    let created = run(&["-p"]);
+    assert!(created.status.success(), "{created:?}");
+    assert!(output.is_file());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,245 @@
+//! Takes the date of messages from their Date header, their separator line, their Received
+//! header or the modification time of their file, in that order, and checks the variants of
+//! separator dates that are understood.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{
+    DateSource, EmlInput, EmlToMboxOptions, Event, GroupBy, MboxParser, MboxToEmlOptions,
+    eml_to_mbox, mbox_to_eml,
+};
+use std::{
+    cell::RefCell,
+    fs::{self, File},
+    time::{Duration, SystemTime},
+};
+
+/// The date of a single message, in RFC 3339, and where it was taken from.
+fn date_of(message: &str) -> Option<(String, DateSource)> {
+    let message = MboxParser::from_reader(message.as_bytes())
+        .next()
+        .unwrap()
+        .unwrap();
+    message
+        .date()
+        .map(|(date, source)| (date.to_rfc3339(), source))
+}
+
+/// The date of a message without any header, from its separator line alone.
+fn separator_date(separator: &str) -> Option<String> {
+    let (date, source) = date_of(&format!("{separator}\nSubject: Undated\n\nBody\n"))?;
+    assert_eq!(source, DateSource::Separator);
+    Some(date)
+}
+
+#[test]
+fn separator_dates_in_their_variants() {
+    let cases = [
+        (
+            "From a@example.com Mon Jan 01 00:00:00 2024",
+            "2024-01-01T00:00:00+00:00",
+        ),
+        (
+            "From a@example.com Mon Jan  1 00:00:00 2024",
+            "2024-01-01T00:00:00+00:00",
+        ),
+        (
+            "From a@example.com Mon Jan 1 00:00:00 2024",
+            "2024-01-01T00:00:00+00:00",
+        ),
+        (
+            "From a@example.com Tue Feb 27 13:45 2024",
+            "2024-02-27T13:45:00+00:00",
+        ),
+        (
+            "From a@example.com Wed Mar  6 09:10:11 +0100 2024",
+            "2024-03-06T09:10:11+01:00",
+        ),
+        (
+            "From a@example.com Wed Mar  6 09:10:11 2024 -0500",
+            "2024-03-06T09:10:11-05:00",
+        ),
+        (
+            "From a@example.com Sat Jan  3 01:05:34 EST 1996",
+            "1996-01-03T01:05:34-05:00",
+        ),
+        (
+            "From a@example.com Sat Jan  3 01:05:34 1996 GMT",
+            "1996-01-03T01:05:34+00:00",
+        ),
+        (
+            "From - Thu Jul 04 18:00:00 2024",
+            "2024-07-04T18:00:00+00:00",
+        ),
+        (
+            "From \"John Doe\"@example.com Fri Aug 30 23:59:59 2024",
+            "2024-08-30T23:59:59+00:00",
+        ),
+        (
+            "From uucp Sun Sep 1 12:00:00 2024 remote from host",
+            "2024-09-01T12:00:00+00:00",
+        ),
+        (
+            "From a@example.com mon jan 01 00:00:00 2024",
+            "2024-01-01T00:00:00+00:00",
+        ),
+    ];
+    for (separator, expected) in cases {
+        assert_eq!(
+            separator_date(separator).as_deref(),
+            Some(expected),
+            "{separator}"
+        );
+    }
+}
+
+#[test]
+fn unusable_separator_dates() {
+    for separator in [
+        "From a@example.com",
+        "From a@example.com yesterday",
+        "From a@example.com Mon Jan 01 00:00:00",
+        "From a@example.com Mon Feb 30 00:00:00 2024",
+        "From a@example.com Mon Jan 01 25:00:00 2024",
+        "From a@example.com Mon Jan 01 00:00:00 24",
+        "From Jan 1 00:00:00 2024",
+    ] {
+        assert_eq!(
+            date_of(&format!("{separator}\n\nBody\n")),
+            None,
+            "{separator}"
+        );
+    }
+}
+
+#[test]
+fn date_sources_in_order() {
+    let received =
+        "Received: from mx.example.com\n by mail.example.com;\n Fri, 5 Jan 2024 07:00:00 +0000\n";
+    let cases = [
+        (
+            "From a Mon Jan 01 00:00:00 2024\nDate: Tue, 02 Jan 2024 10:30:00 +0100\n".to_string()
+                + received,
+            Some(("2024-01-02T10:30:00+01:00", DateSource::Header)),
+        ),
+        (
+            "From a Mon Jan 01 00:00:00 2024\nDate: 2024-01-02T10:30:00+01:00\n".to_string(),
+            Some(("2024-01-02T10:30:00+01:00", DateSource::Header)),
+        ),
+        (
+            "From a Mon Jan 01 00:00:00 2024\nDate: not a date\n".to_string() + received,
+            Some(("2024-01-01T00:00:00+00:00", DateSource::Separator)),
+        ),
+        (
+            "From a Mon Jan 01 00:00:00 2024\n".to_string() + received,
+            Some(("2024-01-01T00:00:00+00:00", DateSource::Separator)),
+        ),
+        (
+            "From a\nDate: \n".to_string() + received,
+            Some(("2024-01-05T07:00:00+00:00", DateSource::Received)),
+        ),
+        (
+            "From a\nReceived: by mail.example.com; sometime\n".to_string(),
+            None,
+        ),
+        ("From a\nSubject: Nothing\n".to_string(), None),
+    ];
+    for (header, expected) in cases {
+        let message = format!("{header}\nBody\n");
+        assert_eq!(
+            date_of(&message),
+            expected.map(|(date, source)| (date.to_string(), source)),
+            "{message}"
+        );
+    }
+}
+
+#[test]
+fn messages_without_a_date_header_are_grouped_by_their_separator() {
+    let dir = temp_dir("dates-grouping");
+    fs::write(
+        dir.join("in.mbox"),
+        "From a Sat Mar  2 10:00:00 2024\nSubject: Spam\n\nBuy\n\n\
+         From b\nSubject: Nothing\n\nUndated\n",
+    )
+    .unwrap();
+    let output = dir.join("out");
+    let sources = RefCell::new(Vec::new());
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), &output);
+    options.group_by = GroupBy::Date;
+    options.details = true;
+    options.on_event = Some(Box::new(|event| {
+        if let Event::Converted(details) = event {
+            sources
+                .borrow_mut()
+                .push((details.date, details.date_source));
+        }
+        Ok(())
+    }));
+
+    assert_eq!(mbox_to_eml(&options).unwrap().converted, 2);
+    drop(options);
+    assert!(output.join("2024/03/0000_Spam.eml").exists());
+    assert!(output.join("undated/0001_Nothing.eml").exists());
+    assert_eq!(
+        sources.into_inner(),
+        [
+            (
+                Some("Sat Mar  2 10:00:00 2024".to_string()),
+                DateSource::Separator
+            ),
+            (None, DateSource::Separator),
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn eml_files_without_a_date_header_use_received_and_then_their_file_time() {
+    let dir = temp_dir("dates-eml");
+    fs::create_dir_all(dir.join("in")).unwrap();
+    fs::write(
+        dir.join("in/a.eml"),
+        "Received: from mx.example.com\n by mail.example.com;\n Fri, 5 Jan 2024 07:00:00 +0000\nSubject: Received\n\nBody\n",
+    )
+    .unwrap();
+    fs::write(dir.join("in/b.eml"), "Subject: Saved\n\nBody\n").unwrap();
+    File::options()
+        .write(true)
+        .open(dir.join("in/b.eml"))
+        .unwrap()
+        .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        .unwrap();
+
+    let sources = RefCell::new(Vec::new());
+    let mut options =
+        EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), dir.join("out.mbox"));
+    options.details = true;
+    options.on_event = Some(Box::new(|event| {
+        if let Event::Converted(details) = event {
+            sources.borrow_mut().push(details.date_source);
+        }
+        Ok(())
+    }));
+    assert_eq!(eml_to_mbox(&options).unwrap().converted, 2);
+    drop(options);
+    assert_eq!(
+        sources.into_inner(),
+        [DateSource::Received, DateSource::FileTime]
+    );
+
+    let mbox = fs::read_to_string(dir.join("out.mbox")).unwrap();
+    let separators: Vec<_> = mbox
+        .lines()
+        .filter(|line| line.starts_with("From "))
+        .collect();
+    assert_eq!(
+        separators,
+        [
+            "From unknown@example.com Fri Jan 05 07:00:00 2024",
+            "From unknown@example.com Tue Nov 14 22:13:20 2023",
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
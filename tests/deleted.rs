@@ -0,0 +1,126 @@
+//! Leaves out the messages Thunderbird marked as deleted in their X-Mozilla-Status header, and
+//! checks that they are warned about when extracted and reported by info.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{Event, InfoOptions, MboxToEmlOptions, count_mbox, mbox_info, mbox_to_eml};
+use std::{cell::RefCell, fs, path::PathBuf, process::Command};
+
+/// Deleted, read, deleted and read, with a malformed bitmask, and without any status header.
+const MBOX: &str = "\
+From a Mon Jan 01 00:00:00 2024
+Subject: Deleted
+X-Mozilla-Status: 0008
+
+Body
+
+From b Mon Jan 01 00:00:00 2024
+Subject: Read
+X-Mozilla-Status: 0001
+
+Body
+
+From c Mon Jan 01 00:00:00 2024
+Subject: Read and deleted
+X-Mozilla-Status: 0009
+
+Body
+
+From d Mon Jan 01 00:00:00 2024
+Subject: Malformed
+X-Mozilla-Status: 00x8
+
+Body
+
+From e Mon Jan 01 00:00:00 2024
+Subject: New
+
+Body
+";
+
+fn file_names(dir: &PathBuf) -> Vec<String> {
+    let mut names: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn deleted_messages_are_skipped() {
+    let dir = temp_dir("deleted-skip");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    let output = dir.join("out");
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), &output);
+    options.skip_deleted = true;
+
+    assert_eq!(count_mbox(&options).unwrap().matching, 3);
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.skipped_deleted), (3, 2));
+    assert_eq!(summary.counters().filtered, 2);
+    assert_eq!(
+        file_names(&output),
+        ["0000_Read.eml", "0001_Malformed.eml", "0002_New.eml"]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn deleted_messages_are_warned_about_when_extracted() {
+    let dir = temp_dir("deleted-warn");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    let warnings = RefCell::new(Vec::new());
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), dir.join("out"));
+    options.on_event = Some(Box::new(|event| {
+        if let Event::Warning(message) = event {
+            warnings.borrow_mut().push(message);
+        }
+        Ok(())
+    }));
+
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.skipped_deleted), (5, 0));
+    drop(options);
+    assert_eq!(
+        warnings.into_inner(),
+        ["2 of the messages were marked as deleted by Thunderbird and were extracted anyway"]
+    );
+
+    let info = mbox_info(&InfoOptions::new(dir.join("in.mbox"))).unwrap();
+    assert_eq!((info.messages, info.deleted), (5, 2));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_reports_deleted_messages() {
+    let dir = temp_dir("deleted-cli");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .args(["info", "--json"])
+        .arg(dir.join("in.mbox"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\"deleted\": 2"), "{stdout}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .arg("mbox-to-eml")
+        .arg(dir.join("in.mbox"))
+        .arg(dir.join("out"))
+        .arg("--skip-deleted")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("2 emails were left out as they are marked as deleted"),
+        "{stdout}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
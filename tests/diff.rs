@@ -0,0 +1,143 @@
+//! Compares mailboxes after converting them between formats, and after losing and changing
+//! some of their messages.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{
+    DiffLocation, DiffOptions, EmlInput, EmlToMboxOptions, MailboxKind, MboxToEmlOptions,
+    diff_mailboxes, eml_to_mbox, mbox_to_eml,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Messages with and without a Message-ID, one of them with a line that needs quoting.
+const MBOX: &str = "\
+From a@example.com Mon Jan 01 00:00:00 2024
+Message-ID: <one@example.com>
+Subject: One
+Status: RO
+
+>From the start
+>>From quoted
+
+From b@example.com Mon Jan 01 00:00:00 2024
+Message-ID: <two@example.com>
+Subject: Two
+
+Second
+
+From c@example.com Mon Jan 01 00:00:00 2024
+Subject: No ID
+
+Third
+
+";
+
+/// The names of the eml files in a directory, sorted.
+fn eml_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "eml"))
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn converted_mailboxes_are_the_same() {
+    let dir = temp_dir("diff-same");
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, MBOX).unwrap();
+    let emls = dir.join("emls");
+    mbox_to_eml(&MboxToEmlOptions::new(&mbox, &emls)).unwrap();
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(emls.clone()), dir.join("back"));
+    options.record_folder = true;
+    options.record_source = true;
+    eml_to_mbox(&options).unwrap();
+
+    for (left, right) in [(&mbox, &emls), (&mbox, &dir.join("back")), (&emls, &mbox)] {
+        let diff = diff_mailboxes(&DiffOptions::new(left, right)).unwrap();
+        assert!(diff.is_same(), "{left:?} and {right:?}: {diff:?}");
+        assert_eq!(
+            (diff.left.messages, diff.right.messages, diff.matching),
+            (3, 3, 3)
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn lost_added_and_changed_messages_are_reported() {
+    let dir = temp_dir("diff-changes");
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, MBOX).unwrap();
+    let emls = dir.join("emls");
+    mbox_to_eml(&MboxToEmlOptions::new(&mbox, &emls)).unwrap();
+    let files = eml_files(&emls);
+    fs::write(
+        &files[1],
+        "Message-ID: <two@example.com>\nSubject: Two\n\nEdited\n",
+    )
+    .unwrap();
+    fs::remove_file(&files[2]).unwrap();
+    fs::write(
+        emls.join("new.eml"),
+        "Message-ID: <three@example.com>\n\nNew\n",
+    )
+    .unwrap();
+
+    let diff = diff_mailboxes(&DiffOptions::new(&mbox, &emls)).unwrap();
+    assert!(!diff.is_same());
+    assert_eq!(diff.right.kind, MailboxKind::EmlDirectory);
+    assert_eq!(diff.matching, 1);
+    let [lost] = &diff.only_left[..] else {
+        panic!("expected one message only on the left: {diff:?}");
+    };
+    assert!(lost.synthetic && lost.key.starts_with("sha256:"));
+    assert_eq!(
+        diff.only_right
+            .iter()
+            .map(|entry| entry.key.as_str())
+            .collect::<Vec<_>>(),
+        ["three@example.com"]
+    );
+    let [changed] = &diff.changed[..] else {
+        panic!("expected one changed message: {diff:?}");
+    };
+    assert_eq!(changed.key, "two@example.com");
+    assert!(matches!(changed.left[..], [DiffLocation::Offset(_)]));
+    assert_eq!(changed.right, [DiffLocation::File(files[1].clone())]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn maildirs_are_read_from_cur_and_new() {
+    let dir = temp_dir("diff-maildir");
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, MBOX).unwrap();
+    let maildir = dir.join("Maildir");
+    for subdirectory in ["cur", "new", "tmp"] {
+        fs::create_dir_all(maildir.join(subdirectory)).unwrap();
+    }
+    let emls = dir.join("emls");
+    mbox_to_eml(&MboxToEmlOptions::new(&mbox, &emls)).unwrap();
+    for (index, file) in eml_files(&emls).iter().enumerate() {
+        let name = match index {
+            0 => "cur/1700000000.1.host:2,S",
+            1 => "new/1700000000.2.host",
+            // Messages still being delivered are not read.
+            _ => "tmp/1700000000.3.host",
+        };
+        fs::copy(file, maildir.join(name)).unwrap();
+    }
+
+    let diff = diff_mailboxes(&DiffOptions::new(&mbox, &maildir)).unwrap();
+    assert_eq!(diff.right.kind, MailboxKind::Maildir);
+    assert_eq!((diff.right.messages, diff.matching), (2, 2));
+    assert_eq!(diff.only_left.len(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
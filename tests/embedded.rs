@@ -0,0 +1,112 @@
+//! Extracts the messages embedded in a digest and a forwarded email and checks the files and
+//! manifest they give.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{MAX_EMBEDDED_DEPTH, MboxToEmlOptions, mbox_to_eml};
+use serde_json::Value;
+use std::fs;
+
+/// A digest whose parts default to message/rfc822, followed by an email forwarding another as
+/// a base64-encoded attachment, which itself forwards a third.
+const MBOX: &str = "\
+From list@example.com Mon Jan 01 00:00:00 2024
+From: list@example.com
+Subject: Digest
+Content-Type: multipart/digest; boundary=d
+
+--d
+
+From: alice@example.com
+Subject: First
+
+One
+--d
+
+From: bob@example.com
+Subject: Second
+
+Two
+--d--
+
+From alice@example.com Tue Jan 02 00:00:00 2024
+From: alice@example.com
+Subject: Fwd
+Content-Type: multipart/mixed; boundary=m
+
+--m
+
+See attached
+--m
+Content-Type: message/rfc822
+Content-Transfer-Encoding: base64
+
+RnJvbTogYm9iQGV4YW1wbGUuY29tClN1YmplY3Q6IElubmVyCkNvbnRlbnQtVHlwZTogbWVzc2Fn
+ZS9yZmM4MjIKCkZyb206IGNhcm9sQGV4YW1wbGUuY29tClN1YmplY3Q6IERlZXBlc3QKCkhlbGxv
+Cg==
+--m--
+";
+
+#[test]
+fn digests_and_forwards_are_exploded() {
+    let dir = temp_dir("embedded");
+    let (mbox, out) = (dir.join("in.mbox"), dir.join("out"));
+    fs::write(&mbox, MBOX).unwrap();
+    let mut options = MboxToEmlOptions::new(&mbox, &out);
+    options.explode_rfc822 = true;
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.embedded), (2, 4));
+
+    let mut files: Vec<_> = fs::read_dir(&out)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "0000.1_First.eml",
+            "0000.2_Second.eml",
+            "0000_Digest.eml",
+            "0001.1.1_Deepest.eml",
+            "0001.1_Inner.eml",
+            "0001_Fwd.eml",
+            "embedded.jsonl",
+        ]
+    );
+    assert_eq!(
+        fs::read_to_string(out.join("0001.1.1_Deepest.eml")).unwrap(),
+        "From: carol@example.com\nSubject: Deepest\n\nHello\n"
+    );
+
+    let parents: Vec<_> = fs::read_to_string(out.join("embedded.jsonl"))
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let entry = serde_json::from_str::<Value>(line).unwrap();
+            let field = |name| entry.get(name).unwrap().as_str().unwrap().to_string();
+            (field("file"), field("parent"))
+        })
+        .collect();
+    assert_eq!(parents[3].0, "0001.1.1_Deepest.eml");
+    assert_eq!(parents[3].1, "0001.1_Inner.eml");
+    assert_eq!(parents[0].1, "0000_Digest.eml");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn nesting_is_capped() {
+    let dir = temp_dir("embedded-depth");
+    let (mbox, out) = (dir.join("in.mbox"), dir.join("out"));
+    let mut message = "Subject: Bottom\n\nHello\n".to_string();
+    for _ in 0..MAX_EMBEDDED_DEPTH + 2 {
+        message = format!("Subject: Wrapper\nContent-Type: message/rfc822\n\n{message}");
+    }
+    fs::write(&mbox, format!("From a Mon Jan 01 00:00:00 2024\n{message}")).unwrap();
+    let mut options = MboxToEmlOptions::new(&mbox, &out);
+    options.explode_rfc822 = true;
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!(summary.embedded, MAX_EMBEDDED_DEPTH);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,119 @@
+//! Extracts an mbox holding empty messages, between separator lines in a row or followed only
+//! by blank lines, and checks what every policy for them does.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{EmptyMessages, MailfmtError, MboxParser, MboxToEmlOptions, count_mbox, mbox_to_eml};
+use std::{fs, path::PathBuf, process::Command};
+
+/// An empty message, a message, a blank one, another message, and a separator at the end
+/// followed by blank lines.
+const MBOX: &str = "\
+From a Mon Jan 01 00:00:00 2024
+From b Mon Jan 01 00:00:00 2024
+Subject: First
+
+Body
+
+From c Mon Jan 01 00:00:00 2024
+
+ \t
+
+From d Mon Jan 01 00:00:00 2024
+Subject: Second
+
+Body
+
+From e Mon Jan 01 00:00:00 2024
+
+
+";
+
+fn file_names(dir: &PathBuf) -> Vec<String> {
+    let mut names: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn a_trailing_separator_starts_no_message() {
+    let messages: Vec<_> = MboxParser::from_reader(MBOX.as_bytes())
+        .map(|message| message.map(|message| message.is_empty()))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(messages, [true, false, true, false]);
+}
+
+#[test]
+fn empty_messages_are_skipped_by_default() {
+    let dir = temp_dir("empty-skip");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    let output = dir.join("out");
+    let options = MboxToEmlOptions::new(dir.join("in.mbox"), &output);
+
+    let count = count_mbox(&options).unwrap();
+    assert_eq!((count.found, count.matching), (4, 2));
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.empty), (2, 2));
+    assert_eq!(summary.counters().invalid, 2);
+    assert_eq!(file_names(&output), ["0000_First.eml", "0001_Second.eml"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn empty_messages_can_be_kept_or_counted_as_errors() {
+    let dir = temp_dir("empty-policies");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), dir.join("kept"));
+    options.empty_messages = EmptyMessages::Keep;
+    assert_eq!(count_mbox(&options).unwrap().matching, 4);
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.empty), (4, 0));
+
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), dir.join("errors"));
+    options.empty_messages = EmptyMessages::Error;
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!(summary.converted, 2);
+    let errors: Vec<_> = summary
+        .errors
+        .iter()
+        .map(|error| match error.error {
+            MailfmtError::EmptyMessage { index, offset } => (index, offset),
+            ref error => panic!("unexpected error {error}"),
+        })
+        .collect();
+    assert_eq!(errors, [(0, 0), (2, 86)]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_reports_skipped_empty_messages() {
+    let dir = temp_dir("empty-cli");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .arg("mbox-to-eml")
+        .arg(dir.join("in.mbox"))
+        .arg(dir.join("out"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("2 empty emails were skipped"), "{stdout}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .arg("mbox-to-eml")
+        .arg(dir.join("in.mbox"))
+        .args(["--count-only", "--empty-messages", "keep"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "4");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,193 @@
+//! Runs conversions where many messages fail and checks how their errors are listed once the
+//! conversion is over, and how errors tell what caused them and which flag gets past them.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{MailfmtError, MboxToEmlOptions, mbox_to_eml};
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+fn path(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+fn run(args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .args(args)
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("mailfmt runs");
+    assert!(output.status.success(), "{output:?}");
+    output
+}
+
+/// Write an mbox of `failing` messages that are not valid UTF-8 followed by a valid one,
+/// returning its path.
+fn fixture(dir: &Path, failing: usize) -> PathBuf {
+    let mut content = Vec::new();
+    for index in 0..failing {
+        content.extend_from_slice(
+            format!("From a@example.com Mon Jan 01 00:00:00 2024\nSubject: {index}\n\n").as_bytes(),
+        );
+        content.extend_from_slice(b"Bad \xff byte\n\n");
+    }
+    content.extend_from_slice(
+        b"From b@example.com Mon Jan 01 00:00:00 2024\nSubject: Good\n\nFine\n\n",
+    );
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, content).unwrap();
+    mbox
+}
+
+#[test]
+fn errors_are_listed_after_the_conversion() {
+    let dir = temp_dir("errors-listed");
+    let mbox = fixture(&dir, 53);
+    let report = dir.join("errors.txt");
+    let output = run(&[
+        "mbox-to-eml",
+        path(&mbox),
+        path(&dir.join("out")),
+        "--invalid-utf8",
+        "strict",
+        "--error-report",
+        path(&report),
+    ]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let section = &stderr[stderr.find("Errors:\n").expect("an error section")..];
+    let listed: Vec<&str> = section
+        .lines()
+        .skip(1)
+        .take_while(|line| line.starts_with("  "))
+        .collect();
+    assert_eq!(listed.len(), 51, "{section}");
+    assert!(listed[0].contains("UTF-8"), "{}", listed[0]);
+    assert!(listed[50].contains("...and 3 more"), "{}", listed[50]);
+    assert!(listed[50].contains("errors.txt"), "{}", listed[50]);
+    // Without --verbose, errors are not also printed as they happen.
+    assert!(!stderr.contains("Error "), "{stderr}");
+
+    let report = fs::read_to_string(&report).unwrap();
+    assert_eq!(report.lines().count(), 53);
+    assert!(
+        report
+            .lines()
+            .all(|line| line.starts_with("[failed] ") && line.contains("UTF-8"))
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn error_reports_can_be_json_lines() {
+    let dir = temp_dir("errors-jsonl");
+    let mbox = fixture(&dir, 2);
+    let report = dir.join("errors.jsonl");
+    let output = run(&[
+        "mbox-to-eml",
+        path(&mbox),
+        path(&dir.join("out")),
+        "--invalid-utf8",
+        "strict",
+        "--error-report",
+        path(&report),
+    ]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Errors:\n"), "{stderr}");
+    assert!(!stderr.contains("more"), "{stderr}");
+
+    let report = fs::read_to_string(&report).unwrap();
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines.len(), 2);
+    for line in lines {
+        assert!(
+            line.starts_with("{\"category\":\"failed\",\"item\":"),
+            "{line}"
+        );
+        assert!(line.contains("\"error_kind\":\"invalid-utf8\""), "{line}");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn errors_are_told_apart_from_invalid_files() {
+    let dir = temp_dir("errors-categories");
+    let input = dir.join("in");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("good.eml"), "Subject: Good\n\nFine\n").unwrap();
+    fs::write(input.join("page.eml"), "<html></html>\n").unwrap();
+    fs::write(input.join("bad.eml"), b"Subject: Bad\n\nBad \xff byte\n").unwrap();
+    let report = dir.join("errors.txt");
+    let output = run(&[
+        "eml-to-mbox",
+        path(&input),
+        path(&dir.join("out.mbox")),
+        "--skip-invalid",
+        "--invalid-utf8",
+        "strict",
+        "--error-report",
+        path(&report),
+    ]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("Of 3 eml files, 1 converted, 1 skipped as invalid, 1 failed"),
+        "{stdout}"
+    );
+
+    let report = fs::read_to_string(&report).unwrap();
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines.len(), 2, "{report}");
+    assert!(lines[0].starts_with("[failed] ") && lines[0].contains("bad.eml"));
+    assert!(lines[1].starts_with("[invalid] ") && lines[1].contains("page.eml"));
+
+    // Files left out as invalid are not failures, so an mbox is written without them.
+    fs::remove_file(input.join("good.eml")).unwrap();
+    fs::remove_file(input.join("bad.eml")).unwrap();
+    run(&[
+        "eml-to-mbox",
+        path(&input),
+        path(&dir.join("empty.mbox")),
+        "--skip-invalid",
+    ]);
+    assert_eq!(fs::read(dir.join("empty.mbox")).unwrap(), b"");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn errors_expose_their_causes() {
+    let error = MailfmtError::MessageParse {
+        index: 3,
+        source: io::Error::other("the disk went away"),
+    };
+    assert_eq!(error.to_string(), "failed to read message 3");
+    assert_eq!(error.source().unwrap().to_string(), "the disk went away");
+}
+
+#[test]
+fn flags_are_only_suggested_by_the_cli() {
+    let dir = temp_dir("errors-hint");
+    let mbox = fixture(&dir, 0);
+    fs::create_dir(dir.join("out")).unwrap();
+    fs::write(dir.join("out/keep"), "").unwrap();
+
+    let error = mbox_to_eml(&MboxToEmlOptions::new(&mbox, dir.join("out"))).unwrap_err();
+    assert!(matches!(error, MailfmtError::OutputDirectoryExists { .. }));
+    assert!(!error.to_string().contains("--"), "{error}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .args(["mbox-to-eml", path(&mbox), path(&dir.join("out"))])
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("mailfmt runs");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Use the --overwrite flag to replace overlapping files"),
+        "{stderr}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,66 @@
+//! Gives directories and paths in missing directories where files are expected, and checks
+//! that they are refused before anything is converted.
+
+mod common;
+
+use common::temp_dir;
+use std::{
+    fs,
+    process::{Command, Output},
+};
+
+/// Run mailfmt, expecting it to fail, and return what it printed to stderr.
+fn refused(args: &[&std::ffi::OsStr]) -> String {
+    let Output { status, stderr, .. } = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .args(args)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert_eq!(
+        status.code(),
+        Some(2),
+        "{}",
+        String::from_utf8_lossy(&stderr)
+    );
+    String::from_utf8(stderr).unwrap()
+}
+
+#[test]
+fn output_files_are_checked_against_the_filesystem() {
+    let dir = temp_dir("file-arguments-output");
+    fs::create_dir_all(dir.join("in")).unwrap();
+    fs::write(dir.join("in/one.eml"), "Subject: One\n\nHello\n").unwrap();
+    let input = dir.join("in");
+
+    let stderr = refused(&["eml-to-mbox".as_ref(), input.as_ref(), dir.as_ref()]);
+    assert!(stderr.contains("is a directory, not a file"), "{stderr}");
+
+    let missing = dir.join("missing/out.mbox");
+    fs::write(dir.join("in.mbox"), "From a\nSubject: One\n\nHello\n").unwrap();
+    let stderr = refused(&[
+        "anonymize".as_ref(),
+        dir.join("in.mbox").as_ref(),
+        "-o".as_ref(),
+        missing.as_ref(),
+    ]);
+    assert!(stderr.contains("is not an existing directory"), "{stderr}");
+
+    let slash = format!("{}/", dir.join("out.mbox").display());
+    let stderr = refused(&["eml-to-mbox".as_ref(), input.as_ref(), slash.as_ref()]);
+    assert!(stderr.contains("appears to be a directory"), "{stderr}");
+    assert!(!dir.join("out.mbox").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn an_input_directory_is_not_an_mbox_file() {
+    let dir = temp_dir("file-arguments-input");
+    let stderr = refused(&[
+        "mbox-to-eml".as_ref(),
+        dir.as_ref(),
+        dir.join("out").as_ref(),
+    ]);
+    assert!(stderr.contains("is a directory, not a file"), "{stderr}");
+    assert!(!dir.join("out").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
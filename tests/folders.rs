@@ -0,0 +1,223 @@
+//! Converts a tree of eml files to an mbox recording their folders and file names, and
+//! extracts it back into the same tree.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{
+    EmlInput, EmlToMboxOptions, GroupBy, MboxParser, MboxToEmlOptions, eml_to_mbox, headers,
+    mbox_to_eml,
+};
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+/// The eml files of the fixture, by their path relative to the input directory.
+const FILES: &[&str] = &[
+    "root.eml",
+    "Clients/Acme/a.eml",
+    "Clients/b.eml",
+    "Réunions/c.eml",
+];
+
+/// Write the eml files of the fixture, each with its name as subject, returning their directory.
+fn fixture(dir: &Path) -> PathBuf {
+    let input = dir.join("in");
+    for file in FILES {
+        let path = input.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let name = Path::new(file).file_stem().unwrap().to_str().unwrap();
+        let content = match *file {
+            // A folder recorded by an earlier conversion is replaced.
+            "Clients/b.eml" => format!("X-Folder: Old\n\tFolder\nSubject: {name}\n\nBody\n"),
+            _ => format!("Subject: {name}\n\nBody\n"),
+        };
+        fs::write(path, content).unwrap();
+    }
+    input
+}
+
+/// The paths of the eml files under a directory, relative to it, with '/' separators.
+fn tree(dir: &Path, base: &Path, paths: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            tree(&path, base, paths);
+        } else if path.extension().is_some_and(|extension| extension == "eml") {
+            let relative = path.strip_prefix(base).unwrap().to_string_lossy();
+            paths.push(relative.replace('\\', "/"));
+        }
+    }
+}
+
+#[test]
+fn folders_are_recorded_and_restored() {
+    let dir = temp_dir("folders");
+    let input = fixture(&dir);
+    let mbox = dir.join("all.mbox");
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(input), &mbox);
+    options.record_folder = true;
+    eml_to_mbox(&options).unwrap();
+
+    let mut folders: Vec<(String, Option<String>)> =
+        MboxParser::from_reader(BufReader::new(File::open(&mbox).unwrap()))
+            .map(Result::unwrap)
+            .map(|message| {
+                let folder = message.header(headers::FOLDER_HEADER).map(str::to_string);
+                (message.header("subject").unwrap().to_string(), folder)
+            })
+            .collect();
+    folders.sort();
+    let decoded: Vec<_> = folders
+        .iter()
+        .map(|(subject, folder)| {
+            let folder = folder.as_deref().map(headers::decode_encoded_words);
+            (subject.as_str(), folder)
+        })
+        .collect();
+    assert_eq!(
+        decoded,
+        [
+            ("a", Some("Clients/Acme".to_string())),
+            ("b", Some("Clients".to_string())),
+            ("c", Some("Réunions".to_string())),
+            // Messages at the root of the input directory get no header.
+            ("root", None),
+        ]
+    );
+    // Folders that are not ASCII are encoded, and replaced headers are gone.
+    assert!(folders[2].1.as_deref().unwrap().starts_with("=?utf-8?"));
+    let content = fs::read_to_string(&mbox).unwrap();
+    assert!(!content.contains("Old"));
+
+    let output = dir.join("out");
+    let mut options = MboxToEmlOptions::new(&mbox, &output);
+    options.group_by = GroupBy::Folder;
+    mbox_to_eml(&options).unwrap();
+    let mut paths = Vec::new();
+    tree(&output, &output, &mut paths);
+    paths.sort();
+    assert_eq!(paths.len(), FILES.len());
+    let folders: Vec<_> = paths
+        .iter()
+        .map(|path| path.rsplit_once('/').map(|(folder, _)| folder))
+        .collect();
+    for folder in ["Clients/Acme", "Clients", "Réunions"] {
+        assert!(folders.contains(&Some(folder)), "{folder} in {paths:?}");
+    }
+    assert!(folders.contains(&None), "{paths:?}");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn recorded_folders_cannot_leave_the_output_directory() {
+    let dir = temp_dir("folders-escape");
+    let mbox = dir.join("in.mbox");
+    fs::write(
+        &mbox,
+        "From a Mon Jan 01 00:00:00 2024\nX-Folder: ../../etc/./\nSubject: up\n\nBody\n\n\
+         From a Mon Jan 01 00:00:00 2024\nX-Folder: /\nSubject: root\n\nBody\n\n",
+    )
+    .unwrap();
+    let output = dir.join("out");
+    let mut options = MboxToEmlOptions::new(&mbox, &output);
+    options.group_by = GroupBy::Folder;
+    mbox_to_eml(&options).unwrap();
+    let mut paths = Vec::new();
+    tree(&output, &output, &mut paths);
+    paths.sort();
+    assert_eq!(paths, ["0001_root.eml", "etc/0000_up.eml"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Write eml files with the given names to a new input directory, returning it.
+fn named_fixture(dir: &Path, names: &[&str]) -> PathBuf {
+    let input = dir.join("in");
+    for (index, name) in names.iter().enumerate() {
+        let path = input.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, format!("Subject: Message {index}\n\nBody\n")).unwrap();
+    }
+    input
+}
+
+/// Convert a directory of eml files to an mbox, and extract that again, returning the names
+/// written and the content of the mbox.
+fn round_trip(dir: &Path, record: bool, restore: bool) -> (Vec<String>, String) {
+    let mbox = dir.join(format!("{record}-{restore}.mbox"));
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), &mbox);
+    options.record_source = record;
+    eml_to_mbox(&options).unwrap();
+    let output = dir.join(format!("{record}-{restore}"));
+    let mut options = MboxToEmlOptions::new(&mbox, &output);
+    options.restore_names = restore;
+    mbox_to_eml(&options).unwrap();
+    let mut paths = Vec::new();
+    tree(&output, &output, &mut paths);
+    paths.sort();
+    (paths, fs::read_to_string(&mbox).unwrap())
+}
+
+#[test]
+fn source_file_names_are_recorded_and_restored() {
+    let dir = temp_dir("source-names");
+    let long = format!("Überweisung {}.EML", "sehr lange ".repeat(8).trim());
+    named_fixture(
+        &dir,
+        &[
+            "Clients/Acme/invoice.eml",
+            "Clients/invoice.eml",
+            &long,
+            "draft.v2.eml",
+        ],
+    );
+
+    let (paths, content) = round_trip(&dir, true, true);
+    // Names used twice fall back to numbers, and the extension is always the one written.
+    assert_eq!(paths.len(), 4);
+    assert!(paths.contains(&"invoice.eml".to_string()), "{paths:?}");
+    assert!(paths.contains(&"draft.v2.eml".to_string()), "{paths:?}");
+    let restored = format!("{}.eml", long.strip_suffix(".EML").unwrap());
+    assert!(paths.contains(&restored), "{paths:?}");
+    assert!(
+        paths.iter().any(|path| path.starts_with("000")),
+        "{paths:?}"
+    );
+
+    // Long and non-ASCII paths are encoded and folded.
+    let message = MboxParser::from_reader(content.as_bytes())
+        .map(Result::unwrap)
+        .find(|message| message.header("subject") == Some("Message 2"))
+        .unwrap();
+    assert!(message.lines[0].starts_with(b"X-Source-File: =?utf-8?"));
+    assert!(message.lines[1].starts_with(b" "));
+    assert!(content.contains("X-Source-File: Clients/Acme/invoice.eml\n"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn source_file_headers_are_removed_when_not_used() {
+    let dir = temp_dir("source-names-removed");
+    named_fixture(&dir, &["a.eml"]);
+
+    // Extracting without restoring names removes the header from the eml files.
+    let (paths, content) = round_trip(&dir, true, false);
+    assert!(content.contains("X-Source-File: a.eml\n"));
+    assert_eq!(paths, ["0000_Message 0.eml"]);
+    let eml = fs::read_to_string(dir.join("true-false").join(&paths[0])).unwrap();
+    assert!(!eml.contains("X-Source-File"), "{eml}");
+
+    // Converting without recording sources removes a header already in the eml files.
+    fs::write(
+        dir.join("in/a.eml"),
+        "X-Source-File: elsewhere/\n b.eml\nSubject: Message 0\n\nX-Source-File: body\n",
+    )
+    .unwrap();
+    let (_, content) = round_trip(&dir, false, false);
+    assert!(!content.contains("elsewhere"), "{content}");
+    assert!(!content.contains(" b.eml"), "{content}");
+    assert!(content.contains("\nX-Source-File: body\n"), "{content}");
+    fs::remove_dir_all(&dir).unwrap();
+}
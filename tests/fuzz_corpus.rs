@@ -0,0 +1,138 @@
+//! Runs the inputs of the fuzz corpus through what the fuzz targets call, so that the cases
+//! they were added for are checked by every test run and not only when fuzzing.
+
+use mailfmt::{
+    InvalidUtf8, MailfmtError, MboxDialect, MboxParser, MboxWriter, envelope_for,
+    headers::{get_header_value, is_header_line},
+};
+use std::{fs, path::Path};
+
+/// The files of a corpus in `fuzz/corpus`, by name.
+fn corpus(target: &str) -> Vec<(String, Vec<u8>)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fuzz/corpus")
+        .join(target);
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read(path).unwrap())
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// What the `mbox_parser` fuzz target does: parse the input and write the messages back out
+/// as mboxcl.
+fn parse_and_write(data: &[u8]) {
+    let mut writer = MboxWriter::new(Vec::new(), MboxDialect::Mboxcl);
+    for message in MboxParser::from_reader(data) {
+        let Ok(message) = message else {
+            continue;
+        };
+        let _ = message.header("subject");
+        let _ = message.header("from");
+        writer.append_message(&message).unwrap();
+    }
+}
+
+/// What the `headers` fuzz target does: look up headers in the input and make an envelope.
+fn look_up_headers(content: &str) {
+    for line in content.lines() {
+        let _ = is_header_line(line);
+    }
+    for name in ["from", "date", "subject", "x-k"] {
+        let _ = get_header_value(content, name);
+    }
+    let _ = envelope_for(content);
+}
+
+#[test]
+fn every_input_of_the_corpus_is_handled() {
+    let mbox_inputs = corpus("mbox_parser");
+    assert!(mbox_inputs.len() >= 8);
+    for (_, data) in &mbox_inputs {
+        parse_and_write(data);
+    }
+    for (name, data) in corpus("headers") {
+        look_up_headers(std::str::from_utf8(&data).unwrap_or_else(|_| panic!("{name}")));
+    }
+}
+
+#[test]
+fn reversed_angle_brackets() {
+    let mbox = include_bytes!("../fuzz/corpus/mbox_parser/reversed-angle-brackets");
+    parse_and_write(mbox);
+    let messages: Vec<_> = MboxParser::from_reader(&mbox[..])
+        .collect::<Result<_, _>>()
+        .unwrap();
+    // The separator at the very end is followed by nothing, so it starts no message.
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].header("from"), Some(">b@example.com< <"));
+    // A negative Content-Length is no length at all, so the body is read as usual.
+    let message = MboxParser::from_reader(&mbox[..])
+        .with_dialect(MboxDialect::Mboxcl)
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(&message.lines[3..], [&b"From x"[..], b">>From y"]);
+
+    let headers = include_str!("../fuzz/corpus/headers/reversed-angle-brackets");
+    assert_eq!(get_header_value(headers, "from"), Some("a> <b"));
+    // Neither the address nor the empty date can be used, so both fall back.
+    assert_eq!(
+        envelope_for(headers),
+        "unknown@example.com Mon Jan 01 00:00:00 2024"
+    );
+}
+
+#[test]
+fn multibyte_header_names() {
+    let mbox = include_bytes!("../fuzz/corpus/mbox_parser/multibyte-header-names");
+    parse_and_write(mbox);
+    let mut parser = MboxParser::from_reader(&mbox[..]);
+    let message = parser.next().unwrap().unwrap();
+    assert!(parser.next().is_none());
+    assert_eq!(message.offset, 12);
+    // Names are matched byte for byte in ASCII, so neither the Kelvin sign nor the 'é' after
+    // "Subject" gives a match.
+    assert_eq!(message.header("x-k"), None);
+    assert_eq!(message.header("subject"), None);
+
+    let headers = include_str!("../fuzz/corpus/headers/multibyte-header-names");
+    look_up_headers(headers);
+    // Neither does the dotted capital I, which lowercases to more bytes than it has.
+    assert_eq!(get_header_value(headers, "x-k"), None);
+    assert_eq!(get_header_value(headers, "subject"), None);
+}
+
+#[test]
+fn invalid_utf8_mid_message() {
+    let mbox = include_bytes!("../fuzz/corpus/mbox_parser/invalid-utf8-mid-message");
+    parse_and_write(mbox);
+    let messages: Vec<_> = MboxParser::from_reader(&mbox[..])
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].header("subject"), Some("One"));
+    assert_eq!(messages[0].lines[1], b"\xff\xfe");
+    assert_eq!(messages[0].invalid_utf8, Some(45));
+
+    let lossy = MboxParser::from_reader(&mbox[..])
+        .with_invalid_utf8(InvalidUtf8::Lossy)
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(lossy.lines[1], "\u{fffd}\u{fffd}".as_bytes());
+    assert!(matches!(
+        MboxParser::from_reader(&mbox[..])
+            .with_invalid_utf8(InvalidUtf8::Strict)
+            .next(),
+        Some(Err(MailfmtError::InvalidUtf8 {
+            index: 0,
+            offset: 45
+        }))
+    ));
+}
@@ -0,0 +1,264 @@
+//! Converts directories holding eml files that were compressed one by one with gzip, and checks
+//! that they are decompressed like `gzip -d` would, that mbox files compressed as a whole are
+//! read like the plain ones, and that extracted files compressed with gzip read back the same.
+
+mod common;
+
+use common::temp_dir;
+use flate2::write::GzEncoder;
+use mailfmt::{
+    Compression, EmlInput, EmlToMboxOptions, MailfmtError, MboxToEmlOptions, NameBy,
+    count_eml_files, count_mbox, eml_to_mbox, mbox_to_eml,
+};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// `From: a@example.com\nSubject: Short\n\nHello\n`, compressed with fixed Huffman codes.
+const SHORT: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03s+\xca\xcf\xb5RHtH\xadH\xcc-\xc8I\xd5K\xce\xcf\xe5\n.M\xcaJM.\xb1R\x08\xce\xc8/*\xe1\xe2\xf2H\xcd\xc9\xc9\xe7\x02\x00\xbeq\xc2\xda*\x00\x00\x00";
+
+/// A message with the subject `Long` and 40 numbered lines, compressed with dynamic Huffman
+/// codes.
+const LONG: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xad\xd6;\x0e\xc20\x10E\xd1>\xab\x98\x05 \xc4\xcc\xf0\xaf\xa8\xa8\xe8X\x81I\x86\xbfc\x94\x04\tv\x0f\x88-\xdc\xd6\xb2^u\x8f\xe5mW\xf2Z\x0e\x9bx\xa5\xfc\xb8\xc7\xb8.\xb9\xda?\x0f\xd7\xa8\x87\xb5\xecJ{\xaa\xaa\xdd\xa5\r\x99H9J\x92\xfb\xf7$:\xc9\xd1\xf7\xe9\x14#\xe9\xe2\x11i\x88F\x86\"9\xddB\x86sH\xb4ui\xbe\xb7\x9e}H\xf3nS\xbe\xd4\xf2;\xe9\xc7\xff-\x05\xb7\x0c\xdcrpk\nn\xcd\xc0\xad9\xb8\xb5\x00\xb7\x96\xe0\xd6\x8al\x15\r\x9f,_\xc9\xf4\x95l_\xc9\xf8\x95\xac_\xc9\xfc\x95\xec_I\x00J\n0R\x80\xa1o?)\xc0H\x01F\n0R\x80\x91\x02\x8c\x14`\xa4\x00#\x058)\xc0I\x01\x8e~\x7fH\x01N\npR\x80\x93\x02\x9c\x14\xe0\xa4\x00\x87\x04|\x00\xb9n.+!\x0c\x00\x00";
+
+#[test]
+fn compressed_and_plain_files_convert_together() {
+    let dir = temp_dir("gzip-mixed");
+    let input = dir.join("in");
+    fs::create_dir_all(input.join("nested")).unwrap();
+    fs::write(input.join("plain.eml"), "Subject: Plain\n\nHello\n").unwrap();
+    fs::write(input.join("short.eml.gz"), SHORT).unwrap();
+    fs::write(input.join("nested/long.EML.GZ"), LONG).unwrap();
+    // Compressed without being named so.
+    fs::write(input.join("renamed.eml"), SHORT).unwrap();
+    fs::write(input.join("notes.txt.gz"), SHORT).unwrap();
+    let output = dir.join("out.mbox");
+
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(input.clone()), &output);
+    options.skip_invalid = true;
+    assert_eq!(count_eml_files(&options).unwrap().matching, 4);
+    let summary = eml_to_mbox(&options).unwrap();
+    assert_eq!((summary.converted, summary.compressed), (4, 3));
+    assert!(summary.errors.is_empty(), "{:?}", summary.errors);
+
+    let mbox = fs::read_to_string(&output).unwrap();
+    assert_eq!(mbox.matches("Subject: Short\n\nHello\n").count(), 2);
+    assert!(mbox.contains("Subject: Plain\n"));
+    assert!(mbox.contains(
+        "\nLine 39 of a longer message, repeated to make the encoder use dynamic codes.\n"
+    ));
+    assert!(mbox.contains("From a@example.com "));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn damaged_files_fail_on_their_own() {
+    let dir = temp_dir("gzip-damaged");
+    let input = dir.join("in");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("good.eml.gz"), LONG).unwrap();
+    fs::write(input.join("truncated.eml.gz"), &LONG[..LONG.len() / 2]).unwrap();
+    let mut corrupt = SHORT.to_vec();
+    let crc = corrupt.len() - 8;
+    corrupt[crc] ^= 0xFF;
+    fs::write(input.join("corrupt.eml.gz"), corrupt).unwrap();
+    fs::write(
+        input.join("plain.eml.gz"),
+        "Subject: Not compressed\n\nHello\n",
+    )
+    .unwrap();
+
+    let options = EmlToMboxOptions::new(EmlInput::Directory(input), dir.join("out.mbox"));
+    let summary = eml_to_mbox(&options).unwrap();
+    assert_eq!(summary.converted, 1);
+    let mut failed: Vec<String> = summary
+        .errors
+        .iter()
+        .map(|error| {
+            assert_eq!(error.error.code(), "corrupt-gzip");
+            error.error.to_string()
+        })
+        .collect();
+    failed.sort();
+    assert_eq!(failed.len(), 3);
+    assert!(failed[0].contains("corrupt.eml.gz") && failed[0].contains("checksum"));
+    assert!(failed[1].contains("plain.eml.gz") && failed[1].contains("not gzip"));
+    assert!(failed[2].contains("truncated.eml.gz") && failed[2].contains("truncated"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn concatenated_members_are_read_as_one_file() {
+    let dir = temp_dir("gzip-members");
+    let input = dir.join("in");
+    fs::create_dir_all(&input).unwrap();
+    // As written by `cat short.gz long.gz`, padded with zeros like some tools do.
+    let mut members = [SHORT, LONG].concat();
+    members.extend_from_slice(&[0; 16]);
+    fs::write(input.join("both.eml.gz"), members).unwrap();
+
+    let output = dir.join("out.mbox");
+    let summary = eml_to_mbox(&EmlToMboxOptions::new(EmlInput::Directory(input), &output)).unwrap();
+    assert!(summary.errors.is_empty(), "{:?}", summary.errors);
+    let mbox = fs::read_to_string(&output).unwrap();
+    assert!(mbox.contains("Subject: Short\n\nHello\n"));
+    assert!(mbox.contains("\nLine 39 of a longer message"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_summary_counts_compressed_files() {
+    let dir = temp_dir("gzip-cli");
+    let input = dir.join("in");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("one.eml"), "Subject: Plain\n\nHello\n").unwrap();
+    fs::write(input.join("two.eml.gz"), SHORT).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .args(["eml-to-mbox", input.to_str().unwrap()])
+        .arg(dir.join("out.mbox"))
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("Decompressed 1 gzip-compressed eml files"),
+        "{stdout}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// An mbox of a short message and a long one that repeats itself.
+fn mbox(path: &Path) {
+    let long: String = (0..200)
+        .map(|line| format!("Line {line} of a message that is long enough to compress.\n"))
+        .collect();
+    fs::write(
+        path,
+        format!(
+            "From a@example.com Mon Jan 01 00:00:00 2024\nSubject: Short\n\nHi\n\n\
+             From b@example.com Mon Jan 01 00:00:00 2024\nSubject: Long\n\n{long}\n"
+        ),
+    )
+    .unwrap();
+}
+
+/// The names of the files in a directory, sorted.
+fn names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn extracted_files_can_be_compressed() {
+    let dir = temp_dir("gzip-output");
+    let input = dir.join("in.mbox");
+    mbox(&input);
+    let plain = dir.join("plain");
+    mbox_to_eml(&MboxToEmlOptions::new(&input, &plain)).unwrap();
+
+    let compressed = dir.join("compressed");
+    let mut options = MboxToEmlOptions::new(&input, &compressed);
+    options.compress_output = Compression::Gzip;
+    options.compress_min_size = 100;
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.compressed), (2, 1));
+    assert_eq!(names(&compressed), ["0000_Short.eml", "0001_Long.eml.gz"]);
+    let size = |path: PathBuf| fs::metadata(path).unwrap().len();
+    assert!(size(compressed.join("0001_Long.eml.gz")) * 4 < size(plain.join("0001_Long.eml")));
+
+    // Converted back, they are the same as the files written as they are.
+    let mbox = |input: &Path, output: &str| {
+        let output = dir.join(output);
+        eml_to_mbox(&EmlToMboxOptions::new(
+            EmlInput::Directory(input.to_path_buf()),
+            &output,
+        ))
+        .unwrap();
+        fs::read(output).unwrap()
+    };
+    assert_eq!(
+        mbox(&compressed, "compressed.mbox"),
+        mbox(&plain, "plain.mbox")
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compressed_files_are_named_by_the_hash_of_their_content() {
+    let dir = temp_dir("gzip-hash");
+    let input = dir.join("in.mbox");
+    mbox(&input);
+    let plain = dir.join("plain");
+    let mut options = MboxToEmlOptions::new(&input, &plain);
+    options.name_by = NameBy::Hash;
+    mbox_to_eml(&options).unwrap();
+
+    let compressed = dir.join("compressed");
+    let mut options = MboxToEmlOptions::new(&input, &compressed);
+    options.name_by = NameBy::Hash;
+    options.compress_output = Compression::Gzip;
+    mbox_to_eml(&options).unwrap();
+    let expected: Vec<String> = names(&plain)
+        .into_iter()
+        .map(|name| format!("{name}.gz"))
+        .collect();
+    assert_eq!(names(&compressed), expected);
+
+    // The compressed files are recognised as holding what would be written again.
+    options.skip_identical = true;
+    options.overwrite = true;
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.skipped), (0, 2));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Compress a file with gzip, next to it with `.gz` added to its name.
+fn gzip(path: &Path) -> PathBuf {
+    let compressed = path.with_extension("mbox.gz");
+    let mut encoder = GzEncoder::new(
+        fs::File::create(&compressed).unwrap(),
+        flate2::Compression::default(),
+    );
+    encoder.write_all(&fs::read(path).unwrap()).unwrap();
+    encoder.finish().unwrap();
+    compressed
+}
+
+#[test]
+fn compressed_mbox_files_are_read_like_plain_ones() {
+    let dir = temp_dir("gzip-mbox");
+    let input = dir.join("in.mbox");
+    mbox(&input);
+    let compressed = gzip(&input);
+
+    let plain = dir.join("plain");
+    mbox_to_eml(&MboxToEmlOptions::new(&input, &plain)).unwrap();
+    let decompressed = dir.join("decompressed");
+    let options = MboxToEmlOptions::new(&compressed, &decompressed);
+    assert_eq!(count_mbox(&options).unwrap().matching, 2);
+    assert_eq!(mbox_to_eml(&options).unwrap().converted, 2);
+    assert_eq!(names(&decompressed), names(&plain));
+    for name in names(&plain) {
+        assert_eq!(
+            fs::read(decompressed.join(&name)).unwrap(),
+            fs::read(plain.join(&name)).unwrap()
+        );
+    }
+
+    // What is compressed still has to look like an mbox.
+    fs::write(&input, "Subject: Not an mbox\n\nHello\n").unwrap();
+    let options = MboxToEmlOptions::new(gzip(&input), dir.join("not-an-mbox"));
+    assert!(matches!(
+        mbox_to_eml(&options),
+        Err(MailfmtError::NotAnMbox { .. })
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+}
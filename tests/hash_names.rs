@@ -0,0 +1,173 @@
+//! Extracts messages into files named after the SHA-256 of their content, and checks that
+//! identical messages are written once.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{MboxToEmlOptions, NameBy, mbox_to_eml};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// An mbox of a message for each subject.
+fn mbox(path: &Path, subjects: &[&str]) {
+    let mut mbox = String::new();
+    for subject in subjects {
+        mbox.push_str(&format!(
+            "From a@example.com Mon Jan 01 00:00:00 2024\nSubject: {subject}\n\nHello\n\n"
+        ));
+    }
+    fs::write(path, mbox).unwrap();
+}
+
+/// The SHA-256 of the eml file of each subject, as computed by `sha256sum`.
+const HASHES: [(&str, &str); 3] = [
+    (
+        "One",
+        "cded9fcd43ead4c1de693f0b6e0d880d345c174288011e3d129d054045d29f4c",
+    ),
+    (
+        "Two",
+        "d2028864595908d34eeb7e50c58dc5f0fc2b0fa87ba17c45f3f1686785a764f9",
+    ),
+    (
+        "Three",
+        "bb2175f45c3e852ae3e3577d2f5745db2c87330a55a87bd467d5323f75b8c67c",
+    ),
+];
+
+/// The SHA-256 of an eml file written by these tests, going by its subject.
+fn sha256(path: &Path) -> &'static str {
+    let content = fs::read_to_string(path).unwrap();
+    let subject = content
+        .lines()
+        .next()
+        .unwrap()
+        .trim_start_matches("Subject: ");
+    HASHES
+        .iter()
+        .find(|(known, _)| *known == subject)
+        .map(|(_, hash)| *hash)
+        .unwrap_or_else(|| panic!("no known hash for {}", path.display()))
+}
+
+/// The files in a directory, sorted.
+fn files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn files_are_named_after_their_hash() {
+    let dir = temp_dir("hash-names");
+    let input = dir.join("in.mbox");
+    mbox(&input, &["One", "Two", "One"]);
+    let output = dir.join("out");
+    let mut options = MboxToEmlOptions::new(&input, &output);
+    options.name_by = NameBy::Hash;
+    mbox_to_eml(&options).unwrap();
+
+    let files = files(&output);
+    assert_eq!(files.len(), 2);
+    for file in &files {
+        assert_eq!(file.file_stem().unwrap().to_str().unwrap(), sha256(file));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn identical_messages_are_written_once() {
+    let dir = temp_dir("hash-identical");
+    let output = dir.join("out");
+    let first = dir.join("first.mbox");
+    mbox(&first, &["One", "Two"]);
+    let mut options = MboxToEmlOptions::new(&first, &output);
+    options.name_by = NameBy::Hash;
+    options.short_hash = true;
+    mbox_to_eml(&options).unwrap();
+    let written = files(&output);
+    let modified = fs::metadata(&written[0]).unwrap().modified().unwrap();
+
+    let second = dir.join("second.mbox");
+    mbox(&second, &["Two", "One", "Three"]);
+    let mut options = MboxToEmlOptions::new(&second, &output);
+    options.name_by = NameBy::Hash;
+    options.short_hash = true;
+    options.skip_identical = true;
+    options.overwrite = true;
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.skipped), (1, 2));
+
+    let files = files(&output);
+    assert_eq!(files.len(), 3);
+    for file in &files {
+        let name = file.file_stem().unwrap().to_str().unwrap();
+        assert_eq!(name.len(), 16);
+        assert!(sha256(file).starts_with(name));
+    }
+    assert_eq!(
+        fs::metadata(&written[0]).unwrap().modified().unwrap(),
+        modified
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn short_hash_collisions_use_the_full_hash() {
+    let dir = temp_dir("hash-collision");
+    let input = dir.join("in.mbox");
+    mbox(&input, &["One"]);
+    let output = dir.join("out");
+    let mut options = MboxToEmlOptions::new(&input, &output);
+    options.name_by = NameBy::Hash;
+    mbox_to_eml(&options).unwrap();
+    let full = files(&output).remove(0);
+    let hash = full.file_stem().unwrap().to_str().unwrap().to_string();
+    // Another message already holds the short name.
+    fs::remove_file(&full).unwrap();
+    let short = output.join(format!("{}.eml", &hash[..16]));
+    fs::write(&short, "Subject: Other\n\nHello\n").unwrap();
+
+    options.short_hash = true;
+    options.skip_identical = true;
+    options.overwrite = true;
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!(summary.converted, 1);
+    assert_eq!(
+        fs::read_to_string(&short).unwrap(),
+        "Subject: Other\n\nHello\n"
+    );
+    assert_eq!(sha256(&full), hash);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_missing_final_newline_is_kept_and_hashed() {
+    let dir = temp_dir("hash-final-newline");
+    let input = dir.join("in.mbox");
+    fs::write(
+        &input,
+        "From a@example.com Mon Jan 01 00:00:00 2024\nSubject: Last\n\nHello",
+    )
+    .unwrap();
+    let output = dir.join("out");
+    let mut options = MboxToEmlOptions::new(&input, &output);
+    options.name_by = NameBy::Hash;
+    mbox_to_eml(&options).unwrap();
+
+    let hash = "80261c2c91c027b957e3ac4bb2e4a74df09659bf8bc35d8d4fc8f101d802d74e";
+    let file = output.join(format!("{hash}.eml"));
+    assert_eq!(fs::read(&file).unwrap(), b"Subject: Last\n\nHello");
+
+    // The file is recognized as holding the message when extracting it again.
+    options.skip_identical = true;
+    options.overwrite = true;
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.skipped), (0, 1));
+    fs::remove_dir_all(&dir).unwrap();
+}
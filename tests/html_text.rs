@@ -0,0 +1,120 @@
+//! Exports the text of HTML-only emails and checks how their markup, character references,
+//! links and charsets come out.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{HtmlText, MboxToEmlOptions, mbox_to_eml};
+use std::{fs, process::Command};
+
+/// An HTML-only email whose charset is only given by a meta tag, followed by an email with both
+/// a plain text and an HTML part, and one with neither.
+const MBOX: &str = "\
+From shop@example.com Mon Jan 01 00:00:00 2024
+Subject: Sale
+Content-Type: text/html
+Content-Transfer-Encoding: quoted-printable
+
+<html><head><meta charset=3D\"iso-8859-1\"><title>Sale</title><style>p {color: red}</styl=
+e></head>
+<body><h1>Big   sale &amp; more</h1><p>Caf=E9 prices &#150; up to 50% off&hellip; 3 < 5
+<ul><li>One<li>Two</ul>
+<p>Visit <a href=3D\"https://shop.example.com/?a=3D1&amp;b=3D2\">our shop</a> or
+<a href=3D\"https://x.example.com\">https://x.example.com</a>.<br>Write to
+<a href=3D'mailto:help@example.com'>support</a><!-- a <p> comment -->
+<pre>  keep
+    this</pre><script>alert(\"no\")</script><p>Unclosed <b>bold
+
+From alice@example.com Tue Jan 02 00:00:00 2024
+Subject: Both
+Content-Type: multipart/alternative; boundary=b
+
+--b
+Content-Type: text/plain
+
+Plain version
+--b
+Content-Type: text/html
+
+<p>HTML version</p>
+--b--
+
+From bob@example.com Wed Jan 03 00:00:00 2024
+Subject: Image
+Content-Type: image/png
+Content-Transfer-Encoding: base64
+
+iVBORw0KGgo=
+";
+
+const SALE: &str = "\
+Subject: Sale
+
+Big sale & more
+
+Café prices – up to 50% off… 3 < 5
+- One
+- Two
+
+Visit our shop (https://shop.example.com/?a=1&b=2) or https://x.example.com.
+Write to support (help@example.com)
+
+  keep
+    this
+
+Unclosed bold
+";
+
+fn export(name: &str, html_text: HtmlText) -> (mailfmt::ConversionSummary, Vec<String>) {
+    let dir = temp_dir(name);
+    let (mbox, out) = (dir.join("in.mbox"), dir.join("out"));
+    fs::write(&mbox, MBOX).unwrap();
+    let mut options = MboxToEmlOptions::new(&mbox, &out);
+    options.export_text = true;
+    options.html_text = html_text;
+    let summary = mbox_to_eml(&options).unwrap();
+    let texts = ["0000_Sale.txt", "0001_Both.txt", "0002_Image.txt"]
+        .map(|file| fs::read_to_string(out.join(file)).unwrap())
+        .to_vec();
+    fs::remove_dir_all(&dir).unwrap();
+    (summary, texts)
+}
+
+#[test]
+fn html_is_used_when_there_is_no_plain_text() {
+    let (summary, texts) = export("html-fallback", HtmlText::Fallback);
+    assert_eq!((summary.from_html, summary.without_text), (1, 1));
+    assert_eq!(texts[0], SALE);
+    assert!(texts[1].ends_with("\nPlain version\n"));
+    assert!(texts[2].ends_with("[This email has no plain text. Its parts are: image/png]\n"));
+}
+
+#[test]
+fn html_can_be_preferred_or_ignored() {
+    let (summary, texts) = export("html-always", HtmlText::Always);
+    assert_eq!((summary.from_html, summary.without_text), (2, 1));
+    assert!(texts[1].ends_with("\nHTML version\n"));
+
+    let (summary, texts) = export("html-never", HtmlText::Never);
+    assert_eq!((summary.from_html, summary.without_text), (0, 2));
+    assert!(texts[0].contains("[This email has no plain text. Its parts are: text/html]"));
+    assert!(texts[1].ends_with("\nPlain version\n"));
+}
+
+#[test]
+fn html_exports_are_counted_by_the_cli() {
+    let dir = temp_dir("html-cli");
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, MBOX).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .args(["export-text", "--html", "always"])
+        .arg(&mbox)
+        .arg(dir.join("out"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("2 emails were exported from their HTML part"));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,708 @@
+//! Uploads messages to a fake IMAP server and checks what it stores, and fetches messages
+//! from it, including after the connection breaks partway through.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{FetchOutput, ImapFetchOptions, ImapUploadOptions, fetch_from_imap, upload_to_imap};
+use serde_json::Value;
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+const MBOX: &str = "\
+From alice@example.com Mon Jan 01 00:00:00 2024
+Subject: Read
+Date: Tue, 02 Jan 2024 10:30:00 +0100
+Status: RO
+
+Hello
+
+From bob@example.com Wed Jan 03 12:00:00 2024
+Subject: Unread
+
+No Date header, so the separator's date is used.
+
+From carol@example.com Thu Jan 04 00:00:00 2024
+Subject: Answered
+Date: Thu, 04 Jan 2024 08:00:00 +0000
+Status: RO
+X-Status: AF
+
+Thanks
+";
+
+/// A message the fake server stored.
+#[derive(Clone, Debug)]
+struct Append {
+    mailbox: String,
+    /// The flags and date given before the message, such as `(\Seen) "02-Jan-2024 ..."`.
+    arguments: String,
+    data: String,
+}
+
+/// A message in the mailbox the fake server fetches from.
+#[derive(Clone, Debug)]
+struct Stored {
+    uid: u64,
+    flags: &'static str,
+    internal_date: &'static str,
+    data: String,
+}
+
+#[derive(Default)]
+struct State {
+    mailboxes: Vec<String>,
+    appends: Vec<Append>,
+    connections: usize,
+    /// The messages of INBOX, for fetching.
+    stored: Vec<Stored>,
+    uid_validity: u64,
+    /// The arguments of every UID SEARCH.
+    searches: Vec<String>,
+    /// The UID sets of every UID FETCH.
+    fetches: Vec<String>,
+}
+
+/// How the fake server behaves.
+#[derive(Clone, Default)]
+struct Behavior {
+    /// Close the connection on receiving this APPEND, counting from 1, the first time.
+    drop_on_append: Option<usize>,
+    /// Refuse messages with this subject.
+    reject_subject: Option<&'static str>,
+    /// Close the connection on receiving this UID FETCH, counting from 1, the first time.
+    drop_on_fetch: Option<usize>,
+}
+
+/// Start a fake IMAP server accepting the login alice/secret, returning its port.
+fn start_server(behavior: Behavior) -> (u16, Arc<Mutex<State>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let state = Arc::new(Mutex::new(State {
+        mailboxes: vec!["INBOX".to_string()],
+        uid_validity: 7,
+        ..State::default()
+    }));
+    let server_state = state.clone();
+    thread::spawn(move || {
+        let (mut received, mut fetched) = (0, 0);
+        for stream in listener.incoming() {
+            server_state.lock().unwrap().connections += 1;
+            let counts = (&mut received, &mut fetched);
+            serve(stream.unwrap(), &behavior, &server_state, counts);
+        }
+    });
+    (port, state)
+}
+
+fn serve(
+    stream: TcpStream,
+    behavior: &Behavior,
+    state: &Mutex<State>,
+    (received, fetched): (&mut usize, &mut usize),
+) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    write!(writer, "* OK ready\r\n").unwrap();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let line = line.trim_end().to_string();
+        let (tag, rest) = line.split_once(' ').unwrap();
+        let (command, arguments) = rest.split_once(' ').unwrap_or((rest, ""));
+        let reply = match command.to_ascii_uppercase().as_str() {
+            "CAPABILITY" => {
+                write!(writer, "* CAPABILITY IMAP4rev1\r\n").unwrap();
+                "OK done".to_string()
+            }
+            "LOGIN" => match arguments {
+                "\"alice\" \"secret\"" => "OK logged in".to_string(),
+                _ => "NO [AUTHENTICATIONFAILED] wrong password".to_string(),
+            },
+            "LIST" => {
+                let name = arguments.rsplit_once(' ').unwrap().1.trim_matches('"');
+                if state.lock().unwrap().mailboxes.iter().any(|m| m == name) {
+                    write!(writer, "* LIST () \"/\" \"{name}\"\r\n").unwrap();
+                }
+                "OK listed".to_string()
+            }
+            "CREATE" => {
+                let name = arguments.trim_matches('"').to_string();
+                state.lock().unwrap().mailboxes.push(name);
+                "OK created".to_string()
+            }
+            "APPEND" => {
+                *received += 1;
+                if behavior.drop_on_append == Some(*received) {
+                    return;
+                }
+                let (head, length) = arguments.rsplit_once(" {").unwrap();
+                write!(writer, "+ go ahead\r\n").unwrap();
+                writer.flush().unwrap();
+                let mut data = vec![0; length.trim_end_matches('}').parse().unwrap()];
+                reader.read_exact(&mut data).unwrap();
+                let mut end = String::new();
+                reader.read_line(&mut end).unwrap();
+                let data = String::from_utf8(data).unwrap();
+                let (mailbox, arguments) = head.split_once(' ').unwrap_or((head, ""));
+                let rejected = behavior
+                    .reject_subject
+                    .is_some_and(|subject| data.contains(&format!("Subject: {subject}\r\n")));
+                match rejected {
+                    true => "NO message too large".to_string(),
+                    false => {
+                        state.lock().unwrap().appends.push(Append {
+                            mailbox: mailbox.trim_matches('"').to_string(),
+                            arguments: arguments.to_string(),
+                            data,
+                        });
+                        "OK [APPENDUID 1 1] stored".to_string()
+                    }
+                }
+            }
+            "EXAMINE" => {
+                let state = state.lock().unwrap();
+                write!(writer, "* {} EXISTS\r\n", state.stored.len()).unwrap();
+                write!(
+                    writer,
+                    "* OK [UIDVALIDITY {}] valid\r\n",
+                    state.uid_validity
+                )
+                .unwrap();
+                "OK [READ-ONLY] examined".to_string()
+            }
+            "UID" => {
+                let (command, arguments) = arguments.split_once(' ').unwrap();
+                let mut state = state.lock().unwrap();
+                match command {
+                    "SEARCH" => {
+                        state.searches.push(arguments.to_string());
+                        let first: u64 = arguments
+                            .strip_prefix("UID ")
+                            .and_then(|rest| rest.split_once(':'))
+                            .and_then(|(first, _)| first.parse().ok())
+                            .unwrap();
+                        let mut uids: Vec<_> = state.stored.iter().map(|m| m.uid).collect();
+                        // As with real servers, "n:*" matches the highest UID when it is below n.
+                        if uids.iter().all(|&uid| uid < first) {
+                            uids = uids.last().copied().into_iter().collect();
+                        } else {
+                            uids.retain(|&uid| uid >= first);
+                        }
+                        let uids: Vec<_> = uids.iter().map(u64::to_string).collect();
+                        write!(writer, "* SEARCH {}\r\n", uids.join(" ")).unwrap();
+                        "OK searched".to_string()
+                    }
+                    "FETCH" => {
+                        *fetched += 1;
+                        if behavior.drop_on_fetch == Some(*fetched) {
+                            return;
+                        }
+                        let set = arguments.split_once(' ').unwrap().0;
+                        state.fetches.push(set.to_string());
+                        for uid in set.split(',') {
+                            let uid: u64 = uid.parse().unwrap();
+                            let Some(position) = state.stored.iter().position(|m| m.uid == uid)
+                            else {
+                                continue;
+                            };
+                            let message = &state.stored[position];
+                            write!(
+                                writer,
+                                "* {} FETCH (UID {uid} FLAGS ({}) INTERNALDATE \"{}\" BODY[] {{{}}}\r\n{})\r\n",
+                                position + 1,
+                                message.flags,
+                                message.internal_date,
+                                message.data.len(),
+                                message.data
+                            )
+                            .unwrap();
+                        }
+                        "OK fetched".to_string()
+                    }
+                    _ => "BAD unknown command".to_string(),
+                }
+            }
+            "LOGOUT" => {
+                // The client may hang up as soon as it reads the BYE.
+                let _ = write!(writer, "* BYE see you\r\n{tag} OK bye\r\n");
+                return;
+            }
+            _ => "BAD unknown command".to_string(),
+        };
+        write!(writer, "{tag} {reply}\r\n").unwrap();
+        writer.flush().unwrap();
+    }
+}
+
+fn options(input: PathBuf, port: u16) -> ImapUploadOptions<'static> {
+    let url = format!("imap://alice@127.0.0.1:{port}/Archive");
+    let mut options = ImapUploadOptions::new(input, url.parse().unwrap());
+    options.password = "secret".to_string();
+    options.retry_delay = Duration::from_millis(10);
+    options.timeout = Duration::from_secs(5);
+    options
+}
+
+#[test]
+fn eml_files_are_uploaded_with_their_date_and_flags() {
+    let dir = temp_dir("imap-eml");
+    let input = dir.join("in");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(
+        input.join("read.eml"),
+        "Subject: Read\nDate: Tue, 02 Jan 2024 10:30:00 +0100\nStatus: RO\n\nHello\n",
+    )
+    .unwrap();
+    fs::write(input.join("unread.eml"), "Subject: Unread\n\nNo date\n").unwrap();
+    let (port, state) = start_server(Behavior::default());
+
+    let summary = upload_to_imap(&options(input, port)).unwrap();
+    assert_eq!((summary.converted, summary.errors.len()), (2, 0));
+    assert!(summary.mailbox_created);
+    assert_eq!(summary.reconnects, 0);
+
+    let state = state.lock().unwrap();
+    assert!(state.mailboxes.contains(&"Archive".to_string()));
+    let appends = &state.appends;
+    assert_eq!(appends.len(), 2);
+    assert_eq!(appends[0].mailbox, "Archive");
+    assert_eq!(
+        appends[0].arguments,
+        "(\\Seen) \"02-Jan-2024 10:30:00 +0100\""
+    );
+    assert_eq!(
+        appends[0].data,
+        "Subject: Read\r\nDate: Tue, 02 Jan 2024 10:30:00 +0100\r\nStatus: RO\r\n\r\nHello\r\n"
+    );
+    assert_eq!(appends[1].arguments, "()");
+    assert_eq!(
+        summary.bytes_written,
+        appends
+            .iter()
+            .map(|append| append.data.len() as u64)
+            .sum::<u64>()
+    );
+}
+
+#[test]
+fn uploads_continue_after_the_connection_breaks() {
+    let dir = temp_dir("imap-reconnect");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    // The second message is announced but never sent.
+    let (port, state) = start_server(Behavior {
+        drop_on_append: Some(2),
+        ..Behavior::default()
+    });
+
+    let summary = upload_to_imap(&options(dir.join("in.mbox"), port)).unwrap();
+    assert_eq!((summary.converted, summary.errors.len()), (3, 0));
+    assert_eq!(summary.reconnects, 1);
+
+    let state = state.lock().unwrap();
+    assert_eq!(state.connections, 2);
+    let subjects: Vec<_> = state
+        .appends
+        .iter()
+        .map(|append| append.data.lines().next().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        subjects,
+        ["Subject: Read", "Subject: Unread", "Subject: Answered"]
+    );
+    let arguments: Vec<_> = state.appends.iter().map(|a| a.arguments.as_str()).collect();
+    assert_eq!(
+        arguments,
+        [
+            "(\\Seen) \"02-Jan-2024 10:30:00 +0100\"",
+            "() \"03-Jan-2024 12:00:00 +0000\"",
+            "(\\Seen \\Answered \\Flagged) \"04-Jan-2024 08:00:00 +0000\"",
+        ]
+    );
+    // The blank line before the next separator is not part of the message.
+    assert!(state.appends[0].data.ends_with("\r\nHello\r\n"));
+}
+
+#[test]
+fn rejected_messages_are_errors_and_the_rest_are_uploaded() {
+    let dir = temp_dir("imap-rejected");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    let (port, state) = start_server(Behavior {
+        reject_subject: Some("Unread"),
+        ..Behavior::default()
+    });
+
+    let summary = upload_to_imap(&options(dir.join("in.mbox"), port)).unwrap();
+    assert_eq!(summary.converted, 2);
+    assert_eq!(summary.errors.len(), 1);
+    assert_eq!(summary.errors[0].item, "uploading email 1");
+    assert_eq!(summary.errors[0].error.code(), "imap-rejected");
+    assert_eq!(state.lock().unwrap().appends.len(), 2);
+}
+
+#[test]
+fn a_refused_login_fails_the_upload() {
+    let dir = temp_dir("imap-login");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    let (port, state) = start_server(Behavior::default());
+
+    let mut options = options(dir.join("in.mbox"), port);
+    options.password = "wrong".to_string();
+    let error = upload_to_imap(&options).unwrap_err();
+    assert_eq!(error.code(), "imap-login");
+    assert!(error.to_string().contains("wrong password"), "{error}");
+    let state = state.lock().unwrap();
+    assert_eq!(state.connections, 1);
+    assert!(state.appends.is_empty());
+}
+
+#[test]
+fn passwords_are_only_sent_unencrypted_to_this_machine() {
+    let dir = temp_dir("imap-insecure");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+
+    let url = "imap://alice@imap.example.com/Archive";
+    let mut options = ImapUploadOptions::new(dir.join("in.mbox"), url.parse().unwrap());
+    options.password = "secret".to_string();
+    let error = upload_to_imap(&options).unwrap_err();
+    assert_eq!(error.code(), "imap-insecure");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .env("MAILFMT_IMAP_PASSWORD", "secret")
+        .arg("mbox-to-imap")
+        .arg(dir.join("in.mbox"))
+        .args(["--server", url])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "would send the password unencrypted. Use an imaps:// URL or the --starttls flag"
+        ),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn cli_starttls_needs_a_server_offering_it() {
+    let dir = temp_dir("imap-cli-starttls");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    let (port, state) = start_server(Behavior::default());
+
+    let upload = |url: &str| {
+        Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+            .env("NO_COLOR", "1")
+            .env("MAILFMT_IMAP_PASSWORD", "secret")
+            .arg("mbox-to-imap")
+            .arg(dir.join("in.mbox"))
+            .args(["--server", url, "--starttls"])
+            .output()
+            .unwrap()
+    };
+    let output = upload(&format!("imap://alice@127.0.0.1:{port}/Archive"));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("does not offer STARTTLS"), "{stderr}");
+    assert!(state.lock().unwrap().appends.is_empty());
+
+    let output = upload(&format!("imaps://alice@127.0.0.1:{port}/Archive"));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("connects with TLS from the start"),
+        "{stderr}"
+    );
+    assert_eq!(state.lock().unwrap().connections, 1);
+}
+
+#[test]
+fn cli_dry_run_lists_messages_without_connecting() {
+    let dir = temp_dir("imap-cli-dry-run");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+
+    // Nothing listens on port 1, so connecting would fail.
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .env_remove("MAILFMT_IMAP_PASSWORD")
+        .arg("mbox-to-imap")
+        .arg(dir.join("in.mbox"))
+        .args(["--server", "imap://alice@127.0.0.1:1/Archive", "--dry-run"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 4, "{stdout}");
+    assert!(
+        lines[0].starts_with("email 0 at byte 0: \"Read\", 02-Jan-2024 10:30:00 +0100, \\Seen,"),
+        "{stdout}"
+    );
+    assert!(lines[1].contains("\"Unread\", 03-Jan-2024 12:00:00 +0000, no flags"));
+    assert_eq!(
+        lines[3],
+        "3 emails would be uploaded to imap://alice@127.0.0.1:1/Archive"
+    );
+}
+
+#[test]
+fn cli_reads_credentials_from_a_netrc_file() {
+    let dir = temp_dir("imap-cli-netrc");
+    let input = dir.join("in");
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("a.eml"), "Subject: A\n\nBody\n").unwrap();
+    fs::write(
+        dir.join("netrc"),
+        "machine example.com login bob password nope\n\
+         macdef init\n\
+         machine 127.0.0.1 password wrong\n\
+         \n\
+         machine 127.0.0.1\n  login alice\n  password secret\n\
+         default login mallory password guess\n",
+    )
+    .unwrap();
+    let (port, state) = start_server(Behavior::default());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .env_remove("MAILFMT_IMAP_PASSWORD")
+        .arg("eml-to-imap")
+        .arg(&input)
+        .args(["--server", &format!("imap://127.0.0.1:{port}/Archive")])
+        .arg("--netrc")
+        .arg(dir.join("netrc"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("Created the mailbox \"Archive\""),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!(
+            "Upload of 1 emails (20 B) to imap://127.0.0.1:{port}/Archive completed with 0 errors"
+        )),
+        "{stdout}"
+    );
+    assert_eq!(state.lock().unwrap().appends.len(), 1);
+
+    // A password file wins over the netrc file.
+    fs::write(dir.join("password"), "wrong\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .arg("eml-to-imap")
+        .arg(&input)
+        .args([
+            "--server",
+            &format!("imap://alice@127.0.0.1:{port}/Archive"),
+        ])
+        .arg("--password-file")
+        .arg(dir.join("password"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("refused to log in \"alice\""), "{stderr}");
+}
+
+fn stored(uid: u64, flags: &'static str, data: &str) -> Stored {
+    Stored {
+        uid,
+        flags,
+        internal_date: "05-Jan-2024 09:15:00 +0100",
+        data: data.to_string(),
+    }
+}
+
+fn fetch_options(output: FetchOutput, port: u16) -> ImapFetchOptions<'static> {
+    let url = format!("imap://alice@127.0.0.1:{port}/INBOX");
+    let mut options = ImapFetchOptions::new(url.parse().unwrap(), output);
+    options.password = "secret".to_string();
+    options.retry_delay = Duration::from_millis(10);
+    options.timeout = Duration::from_secs(5);
+    options
+}
+
+#[test]
+fn fetches_into_an_mbox_continue_after_the_last_uid() {
+    let dir = temp_dir("imap-fetch-mbox");
+    let (port, state) = start_server(Behavior::default());
+    state.lock().unwrap().stored = vec![
+        stored(
+            3,
+            "\\Seen \\Answered",
+            "From: alice@example.com\r\nSubject: Read\r\nStatus: O\r\n\r\nFrom here on\r\n",
+        ),
+        stored(5, "", "Subject: Unread\r\n\r\nHello\r\n"),
+    ];
+    let output = dir.join("inbox.mbox");
+
+    let options = fetch_options(FetchOutput::Mbox(output.clone()), port);
+    let summary = fetch_from_imap(&options).unwrap();
+    assert_eq!((summary.converted, summary.errors.len()), (2, 0));
+    assert_eq!(summary.continued_after_uid, None);
+    assert_eq!(
+        fs::read_to_string(&output).unwrap(),
+        "From alice@example.com Fri Jan 05 09:15:00 2024\n\
+         From: alice@example.com\n\
+         Subject: Read\n\
+         Status: RO\n\
+         X-Status: A\n\
+         \n\
+         >From here on\n\
+         \n\
+         From unknown@example.com Fri Jan 05 09:15:00 2024\n\
+         Subject: Unread\n\
+         \n\
+         Hello\n\
+         \n"
+    );
+    assert_eq!(summary.bytes_written, fs::metadata(&output).unwrap().len());
+
+    // Only the message added since is fetched, and appended.
+    state
+        .lock()
+        .unwrap()
+        .stored
+        .push(stored(8, "\\Flagged", "Subject: New\r\n\r\nNews\r\n"));
+    let summary = fetch_from_imap(&options).unwrap();
+    assert_eq!(summary.converted, 1);
+    assert_eq!(summary.continued_after_uid, Some(5));
+    let mbox = fs::read_to_string(&output).unwrap();
+    assert!(
+        mbox.ends_with("Subject: New\nX-Status: F\n\nNews\n\n"),
+        "{mbox}"
+    );
+    assert_eq!(mbox.matches("Subject: ").count(), 3);
+
+    // Nothing new: the highest UID is matched by "9:*" but not fetched again.
+    let summary = fetch_from_imap(&options).unwrap();
+    assert_eq!(summary.converted, 0);
+    let state = state.lock().unwrap();
+    assert_eq!(state.searches, ["UID 1:*", "UID 6:*", "UID 9:*"]);
+    assert_eq!(state.fetches, ["3,5", "8"]);
+}
+
+#[test]
+fn fetches_into_eml_files_continue_after_the_connection_breaks() {
+    let dir = temp_dir("imap-fetch-eml");
+    let (port, state) = start_server(Behavior {
+        drop_on_fetch: Some(2),
+        ..Behavior::default()
+    });
+    state.lock().unwrap().stored = vec![
+        stored(1, "\\Seen", "Subject: One\r\n\r\n1\r\n"),
+        stored(2, "", "Subject: Two\r\n\r\n2\r\n"),
+        stored(4, "\\Deleted", "Subject: Three\r\n\r\n3\r\n"),
+    ];
+    let output = dir.join("out");
+
+    let mut options = fetch_options(FetchOutput::EmlDirectory(output.clone()), port);
+    options.batch_size = 2;
+    options.since = "2024-01-01".parse().ok();
+    options.before = "2024-02-01".parse().ok();
+    let summary = fetch_from_imap(&options).unwrap();
+    assert_eq!((summary.converted, summary.errors.len()), (3, 0));
+    assert_eq!(summary.reconnects, 1);
+
+    let state = state.lock().unwrap();
+    assert_eq!(state.connections, 2);
+    assert_eq!(
+        state.searches,
+        ["UID 1:* SINCE 01-Jan-2024 BEFORE 01-Feb-2024"]
+    );
+    assert_eq!(state.fetches, ["1,2", "4"]);
+    assert_eq!(
+        fs::read_to_string(output.join("0000_One.eml")).unwrap(),
+        "Subject: One\nStatus: RO\n\n1\n"
+    );
+    assert_eq!(
+        fs::read_to_string(output.join("0001_Two.eml")).unwrap(),
+        "Subject: Two\n\n2\n"
+    );
+    assert_eq!(
+        fs::read_to_string(output.join("0002_Three.eml")).unwrap(),
+        "Subject: Three\nX-Status: D\n\n3\n"
+    );
+    assert!(output.join(".mailfmt-imap.json").is_file());
+}
+
+#[test]
+fn a_replaced_mailbox_is_not_fetched_into_an_earlier_fetch() {
+    let dir = temp_dir("imap-fetch-uidvalidity");
+    let (port, state) = start_server(Behavior::default());
+    state.lock().unwrap().stored = vec![stored(1, "", "Subject: One\r\n\r\n1\r\n")];
+    let output = dir.join("inbox.mbox");
+
+    let mut options = fetch_options(FetchOutput::Mbox(output.clone()), port);
+    fetch_from_imap(&options).unwrap();
+    state.lock().unwrap().uid_validity = 8;
+    let error = fetch_from_imap(&options).unwrap_err();
+    assert_eq!(error.code(), "invalid-state");
+
+    options.overwrite = true;
+    let summary = fetch_from_imap(&options).unwrap();
+    assert_eq!(summary.converted, 1);
+    assert_eq!(
+        fs::read_to_string(&output)
+            .unwrap()
+            .matches("Subject:")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn cli_logs_every_fetched_message_in_its_span() {
+    let dir = temp_dir("imap-cli-fetch-logs");
+    let (port, state) = start_server(Behavior::default());
+    state.lock().unwrap().stored = vec![
+        stored(3, "\\Seen", "Subject: One\r\n\r\n1\r\n"),
+        stored(5, "", "Subject: Two\r\n\r\n2\r\n"),
+    ];
+
+    let fetch = |url: &str| {
+        Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+            .env("NO_COLOR", "1")
+            .env("MAILFMT_IMAP_PASSWORD", "secret")
+            .args(["--log-format", "json", "imap-to-eml", url])
+            .arg(dir.join("out"))
+            .output()
+            .unwrap()
+    };
+    // A plain connection to another machine is refused before connecting.
+    let output = fetch("imap://alice@imap.example.com/INBOX");
+    assert!(!output.status.success());
+    assert_eq!(state.lock().unwrap().connections, 0);
+
+    let output = fetch(&format!("imap://alice@127.0.0.1:{port}/INBOX"));
+    assert!(output.status.success(), "{output:?}");
+    let written: Vec<Value> = String::from_utf8(output.stderr)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter(|line| line["event"] == "written")
+        .collect();
+    let uids: Vec<_> = written
+        .iter()
+        .map(|line| {
+            let spans = line["spans"].as_array().unwrap();
+            let names: Vec<_> = spans.iter().map(|span| &span["name"]).collect();
+            assert_eq!(names, ["run", "fetch_from_imap", "message"]);
+            spans[2]["uid"].as_u64().unwrap()
+        })
+        .collect();
+    assert_eq!(uids, [3, 5]);
+}
@@ -0,0 +1,66 @@
+//! Interrupts the extraction of a mailbox large enough to still be running when the signal
+//! arrives, and checks that it stops between messages and leaves no partial file behind.
+#![cfg(unix)]
+
+mod common;
+
+use common::temp_dir;
+use std::{
+    fs,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const MESSAGES: usize = 100_000;
+
+#[test]
+fn an_interrupted_extraction_stops_between_messages() {
+    let dir = temp_dir("interrupt");
+    let out = dir.join("out");
+    let mbox: String = (0..MESSAGES)
+        .map(|index| format!("From a Mon Jan 01 00:00:00 2024\nSubject: {index}\n\nBody\n\n"))
+        .collect();
+    fs::write(dir.join("in.mbox"), mbox).unwrap();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .arg("mbox-to-eml")
+        .arg(dir.join("in.mbox"))
+        .arg(&out)
+        .env("NO_COLOR", "1")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Wait until messages are being written, so that the handler is installed.
+    let start = Instant::now();
+    while fs::read_dir(&out).map_or(0, |entries| entries.count()) < 10 {
+        assert!(
+            start.elapsed() < Duration::from_secs(30),
+            "nothing was written"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    let killed = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(130), "{stdout}");
+    assert!(
+        stdout.contains("Conversion interrupted at message"),
+        "{stdout}"
+    );
+    let written: Vec<_> = fs::read_dir(&out)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert!(written.len() < MESSAGES, "the extraction finished");
+    assert!(
+        !written.iter().any(|name| name.ends_with(".mailfmt-tmp")),
+        "{written:?}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
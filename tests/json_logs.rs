@@ -0,0 +1,68 @@
+//! Runs conversions with --log-format json and checks that every line is a JSON object, logged
+//! within the spans of the run, the conversion and the message.
+
+mod common;
+
+use common::temp_dir;
+use serde_json::Value;
+use std::{fs, process::Command};
+
+#[test]
+fn events_are_logged_within_their_spans() {
+    let dir = temp_dir("json-logs");
+    fs::write(
+        dir.join("in.mbox"),
+        b"From a Mon Jan 01 00:00:00 2024\nSubject: One\n\nFine\n\n\
+          From b Mon Jan 01 00:00:00 2024\nSubject: Two\n\nBad \xff byte\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .args(["--log-format", "json", "mbox-to-eml", "in.mbox", "out"])
+        .args(["--invalid-utf8", "strict"])
+        .current_dir(&dir)
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("mailfmt runs");
+    assert!(output.status.success(), "{output:?}");
+
+    let lines: Vec<Value> = String::from_utf8(output.stderr)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let event = |name: &str| {
+        lines
+            .iter()
+            .find(|line| line["event"] == name)
+            .unwrap_or_else(|| panic!("no {name} event in {lines:?}"))
+    };
+    let spans = |line: &Value| -> Vec<String> {
+        line["spans"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|span| span["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    assert_eq!(event("run_started")["level"], "INFO");
+    let pid = &event("run_started")["spans"][0]["pid"];
+    assert!(pid.is_u64());
+    assert!(lines.iter().all(|line| &line["spans"][0]["pid"] == pid));
+
+    let message = event("message");
+    assert_eq!(message["message_index"], 0);
+    assert_eq!(message["action"], "converted");
+    assert_eq!(spans(message), ["run", "mbox_to_eml", "message"]);
+    assert_eq!(message["spans"][1]["input"], "in.mbox");
+    assert_eq!(message["spans"][2]["position"], 0);
+
+    let failed = event("failed");
+    assert_eq!(failed["level"], "ERROR");
+    assert_eq!(failed["error_kind"], "invalid-utf8");
+    assert_eq!(failed["spans"][2]["position"], 1);
+
+    assert_eq!(event("summary")["converted"], 1);
+    assert_eq!(spans(event("run_finished")), ["run"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
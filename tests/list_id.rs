@@ -0,0 +1,92 @@
+//! Extracts the messages of some mailing lists from an mbox holding several and checks which
+//! are written and how they are counted.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{MboxToEmlOptions, mbox_to_eml};
+use std::{fs, path::Path};
+
+/// Messages from two lists, one of them with a folded List-Id, and one from no list at all.
+const MBOX: &str = "\
+From a Mon Jan 01 00:00:00 2024
+Subject: Zero
+List-Id: Rust users <rust-users.rust-lang.org>
+
+Body 0
+
+From b Mon Jan 01 00:00:00 2024
+Subject: One
+List-Id: \"Announcements <official>\"
+ <ANNOUNCE.example.com>
+
+Body 1
+
+From c Mon Jan 01 00:00:00 2024
+Subject: Two
+
+List-Id: <rust-users.rust-lang.org>
+
+From d Mon Jan 01 00:00:00 2024
+Subject: Three
+List-Id: <rust-users.rust-lang.org>
+
+Body 3
+
+From e Mon Jan 01 00:00:00 2024
+Subject: Four
+List-Id: <rust-internals.rust-lang.org>
+
+Body 4
+";
+
+/// Extract the messages on `list_ids`, returning the subjects of the files written, sorted.
+fn extract(dir: &Path, name: &str, list_ids: &[&str]) -> Vec<String> {
+    let output = dir.join(name);
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), &output);
+    options.list_ids = list_ids.iter().map(|id| id.to_string()).collect();
+    mbox_to_eml(&options).unwrap();
+    let mut subjects = fs::read_dir(&output)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .map(|name| name[5..name.len() - 4].to_string())
+        .collect::<Vec<_>>();
+    subjects.sort();
+    subjects
+}
+
+#[test]
+fn messages_are_filtered_by_list() {
+    let dir = temp_dir("list-id");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    // A header in the body does not count, and part of the identifier is enough.
+    assert_eq!(extract(&dir, "a", &["rust-users"]), ["Three", "Zero"]);
+    assert_eq!(
+        extract(&dir, "b", &["rust-lang.org"]),
+        ["Four", "Three", "Zero"]
+    );
+    // Folded headers are unfolded, and case does not matter.
+    assert_eq!(
+        extract(
+            &dir,
+            "c",
+            &["announce.example.com", "<RUST-INTERNALS.rust-lang.org>"]
+        ),
+        ["Four", "One"]
+    );
+    // Angle brackets ask for the whole identifier.
+    assert!(extract(&dir, "d", &["<rust-users>"]).is_empty());
+
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), dir.join("e"));
+    options.list_ids = vec!["rust".to_string()];
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.off_list), (3, 2));
+    assert_eq!(
+        summary.lists.into_iter().collect::<Vec<_>>(),
+        [
+            ("rust-internals.rust-lang.org".to_string(), 1),
+            ("rust-users.rust-lang.org".to_string(), 2)
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,66 @@
+//! Converts eml files into an mbox that is replaced, then watches the input, and checks that
+//! the lock is held on the new mbox rather than the file it replaced.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{EmlInput, EmlToMboxOptions, Event, MailfmtError, eml_to_mbox};
+use std::{
+    cell::RefCell,
+    fs,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+#[test]
+fn a_replaced_mbox_is_locked_while_watching() {
+    for existing in [false, true] {
+        let dir = temp_dir("locking-replaced");
+        fs::create_dir(dir.join("in")).unwrap();
+        fs::write(dir.join("in/one.eml"), "Subject: One\n\nFirst\n").unwrap();
+        let output = dir.join("out.mbox");
+        if existing {
+            fs::write(
+                &output,
+                "From a Mon Jan 01 00:00:00 2024\nSubject: Old\n\nOld\n\n",
+            )
+            .unwrap();
+        }
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let appended = RefCell::new(None);
+        let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), &output);
+        options.overwrite = existing;
+        options.watch = true;
+        options.cancel = Some(Arc::clone(&cancel));
+        options.on_event = Some(Box::new(|event| {
+            if let Event::Watching(_) = event {
+                let mut other = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), &output);
+                other.append = true;
+                other.lock_timeout = Duration::ZERO;
+                *appended.borrow_mut() = Some(eml_to_mbox(&other));
+                cancel.store(true, Ordering::Relaxed);
+            }
+            Ok(())
+        }));
+        eml_to_mbox(&options).unwrap();
+        drop(options);
+
+        let appended = appended.into_inner().expect("the conversion was watching");
+        assert!(
+            matches!(appended, Err(MailfmtError::LockTimeout { .. })),
+            "existing: {existing}, {appended:?}"
+        );
+        assert_eq!(
+            fs::read_to_string(&output)
+                .unwrap()
+                .matches("Subject:")
+                .count(),
+            1
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
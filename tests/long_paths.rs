@@ -0,0 +1,65 @@
+//! Extracts an mbox into an output directory nested deeply enough that the paths of its files
+//! are longer than the 260 characters of `MAX_PATH` on Windows.
+#![cfg(windows)]
+
+mod common;
+
+use mailfmt::{Event, GroupBy, MboxToEmlOptions, mbox_to_eml};
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The extended-length form of a path under the temporary directory, which is absolute and
+/// normalized already, so that files past `MAX_PATH` can be read and removed.
+fn extended_length(path: &Path) -> PathBuf {
+    PathBuf::from(format!(r"\\?\{}", path.display()))
+}
+
+/// [`common::temp_dir`], first removing what an earlier run left with an extended-length path,
+/// as its files are too deep to be removed otherwise.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mailfmt-test-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(extended_length(&dir));
+    common::temp_dir(name)
+}
+
+#[test]
+fn files_are_written_past_max_path() {
+    let dir = temp_dir("long-paths");
+    let subject = "A subject long enough to make a long file name ".repeat(3);
+    fs::write(
+        dir.join("in.mbox"),
+        format!(
+            "From a Mon Jan 01 00:00:00 2024\nDate: Tue, 02 Jan 2024 10:30:00 +0100\nSubject: {subject}\n\nBody\n"
+        ),
+    )
+    .unwrap();
+    let output = ["a", "b", "c"]
+        .iter()
+        .fold(dir.join("out"), |path, name| path.join(name.repeat(60)));
+
+    let written = RefCell::new(Vec::new());
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), &output);
+    options.group_by = GroupBy::Date;
+    options.on_event = Some(Box::new(|event| {
+        if let Event::Written(path) = event {
+            written.borrow_mut().push(path.to_path_buf());
+        }
+        Ok(())
+    }));
+    let summary = mbox_to_eml(&options).unwrap();
+    drop(options);
+    assert_eq!((summary.converted, summary.errors.len()), (1, 0));
+
+    let written = written.into_inner();
+    let path = &written[0];
+    assert!(path.as_os_str().len() > 300, "{path:?}");
+    // Paths reported keep their normal form.
+    assert!(path.starts_with(&output), "{path:?}");
+    assert!(!path.to_string_lossy().starts_with(r"\\?\"), "{path:?}");
+    let content = fs::read_to_string(extended_length(path)).unwrap();
+    assert!(content.ends_with("\n\nBody\n"), "{content}");
+    fs::remove_dir_all(extended_length(&dir)).unwrap();
+}
@@ -0,0 +1,123 @@
+//! Converts eml files that hold whole mailboxes, separated by "From " lines or by the trace
+//! headers that start each message, and checks that they are split with `split_embedded` and
+//! warned about without it.
+
+mod common;
+
+use common::fixture_dir;
+use mailfmt::{EmlInput, EmlToMboxOptions, Event, MboxParser, eml_to_mbox};
+use std::{cell::RefCell, fs, path::Path};
+
+const SEPARATED: &str = "\
+From: alice@example.com
+Date: Mon, 01 Jan 2024 10:00:00 +0000
+Subject: Leading
+
+One
+
+From bob@example.com Tue Jan 02 10:00:00 2024
+Subject: Second
+
+Two
+From the middle of a sentence
+
+From carol@example.com Wed Jan 03 10:00:00 2024
+Subject: Third
+
+Three
+";
+
+const TRACED: &str = "\
+Return-Path: <dave@example.com>
+Subject: Traced
+
+Four
+
+Received: from mx.example.com
+\tby mail.example.com
+Subject: Received
+
+Five
+";
+
+/// The eml files converted in each test.
+const EML_FILES: &[(&str, &str)] = &[
+    ("folder.eml", SEPARATED),
+    ("traced.eml", TRACED),
+    (
+        "single.eml",
+        "Subject: Single
+
+Received: in the body
+",
+    ),
+];
+
+/// Convert the input directory, returning how many messages were converted, the envelopes and
+/// subjects of the messages written and the warnings given.
+fn convert(dir: &Path, split: bool) -> (usize, Vec<(String, String)>, Vec<String>) {
+    let output = dir.join("out.mbox");
+    let warnings = RefCell::new(Vec::new());
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), &output);
+    options.overwrite = true;
+    options.split_embedded = split;
+    options.on_event = Some(Box::new(|event| {
+        if let Event::Warning(warning) = event {
+            warnings.borrow_mut().push(warning);
+        }
+        Ok(())
+    }));
+    let summary = eml_to_mbox(&options).unwrap();
+    drop(options);
+    assert_eq!(summary.mailboxes, 2);
+
+    let mbox = fs::read(&output).unwrap();
+    let messages = MboxParser::from_reader(mbox.as_slice())
+        .with_smart_separators(true)
+        .map(|message| {
+            let message = message.unwrap();
+            let subject = message.header("subject").unwrap().to_string();
+            (message.envelope, subject)
+        })
+        .collect();
+    (summary.converted, messages, warnings.into_inner())
+}
+
+#[test]
+fn mailboxes_are_split_into_their_messages() {
+    let dir = fixture_dir("mailbox-files-split", EML_FILES);
+    let (converted, messages, warnings) = convert(&dir, true);
+    assert_eq!(converted, 6);
+    assert!(warnings.is_empty(), "{warnings:?}");
+    let subjects: Vec<_> = messages
+        .iter()
+        .map(|(_, subject)| subject.as_str())
+        .collect();
+    assert_eq!(
+        subjects,
+        ["Leading", "Second", "Third", "Single", "Traced", "Received"]
+    );
+    assert_eq!(messages[0].0, "alice@example.com Mon Jan 01 10:00:00 2024");
+    assert_eq!(messages[1].0, "bob@example.com Tue Jan 02 10:00:00 2024");
+
+    let mbox = fs::read_to_string(dir.join("out.mbox")).unwrap();
+    assert!(mbox.contains("\n\nOne\n\nFrom bob@example.com"), "{mbox}");
+    assert!(
+        mbox.contains("\n\nTwo\n>From the middle of a sentence\n\nFrom carol"),
+        "{mbox}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn mailboxes_are_warned_about_without_splitting() {
+    let dir = fixture_dir("mailbox-files-warn", EML_FILES);
+    let (converted, _, warnings) = convert(&dir, false);
+    assert_eq!(converted, 3);
+    assert_eq!(warnings.len(), 2, "{warnings:?}");
+    assert!(
+        warnings[0].contains("folder.eml\" looks like a mailbox holding 3 messages"),
+        "{warnings:?}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
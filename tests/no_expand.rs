@@ -0,0 +1,50 @@
+//! Reads an mbox inside of a directory literally named `~`, which is only found when path
+//! arguments are used as given with `--no-expand`.
+
+mod common;
+
+use common::temp_dir;
+use std::{fs, process::Command};
+
+const MBOX: &str = "\
+From a@example.com Mon Jan 01 00:00:00 2024
+Subject: Tilde
+
+Body
+";
+
+#[test]
+fn no_expand_reads_a_directory_named_tilde() {
+    let dir = temp_dir("no-expand");
+    fs::create_dir(dir.join("~")).unwrap();
+    fs::write(dir.join("~/in.mbox"), MBOX).unwrap();
+    // The home directory has no mbox, so expanding '~' would not find one.
+    let home = dir.join("home");
+    fs::create_dir(&home).unwrap();
+
+    let convert = |out: &str, no_expand: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_mailfmt"));
+        command
+            .current_dir(&dir)
+            .env("NO_COLOR", "1")
+            .env("HOME", &home)
+            .args(["mbox-to-eml", "~/in.mbox", out]);
+        if no_expand {
+            command.arg("--no-expand");
+        }
+        command.output().unwrap()
+    };
+
+    let output = convert("expanded", false);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(!dir.join("expanded").exists());
+
+    let output = convert("literal", true);
+    assert!(output.status.success(), "{output:?}");
+    let written: Vec<_> = fs::read_dir(dir.join("literal"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(written, ["0000_Tilde.eml"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,40 @@
+//! Extracts messages whose subjects spell "Café" with a precomposed and a decomposed "é", and
+//! checks that normalization gives both files the same name after their number.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{MboxToEmlOptions, Normalization, mbox_to_eml};
+use std::fs;
+
+const MBOX: &str = "From a Mon Jan 01 00:00:00 2024\nSubject: Caf\u{e9}\n\nOne\n\n\
+                    From b Mon Jan 01 00:00:00 2024\nSubject: Cafe\u{301}\n\nTwo\n";
+
+/// The names of the extracted files without their number, in order.
+fn subjects(normalization: Normalization) -> Vec<String> {
+    let dir = temp_dir("normalization");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), dir.join("out"));
+    options.filename_normalization = normalization;
+    assert_eq!(mbox_to_eml(&options).unwrap().converted, 2);
+    let mut names: Vec<_> = fs::read_dir(dir.join("out"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    fs::remove_dir_all(&dir).unwrap();
+    names
+        .into_iter()
+        .map(|name| name.split_once('_').unwrap().1.to_string())
+        .collect()
+}
+
+#[test]
+fn precomposed_and_decomposed_subjects_collide() {
+    assert_eq!(subjects(Normalization::Nfc), ["Caf\u{e9}.eml"; 2]);
+    assert_eq!(subjects(Normalization::Nfd), ["Cafe\u{301}.eml"; 2]);
+    assert_eq!(
+        subjects(Normalization::None),
+        ["Caf\u{e9}.eml", "Cafe\u{301}.eml"]
+    );
+}
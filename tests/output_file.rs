@@ -0,0 +1,82 @@
+//! Extracts an mbox into an output path that exists as a file, with and without replacing
+//! existing output, and into a layout whose subdirectory would have to replace a file.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{GroupBy, MailfmtError, MboxToEmlOptions, mbox_to_eml};
+use std::{fs, process::Command};
+
+const MBOX: &str = "From a Mon Jan 01 00:00:00 2024\nDate: Tue, 02 Jan 2024 10:30:00 +0100\nSubject: One\n\nBody\n";
+
+#[test]
+fn an_output_file_is_not_an_output_directory() {
+    let dir = temp_dir("output-file");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    fs::write(dir.join("out"), "Not a directory").unwrap();
+
+    for overwrite in [false, true] {
+        let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), dir.join("out"));
+        options.overwrite = overwrite;
+        match mbox_to_eml(&options) {
+            Err(MailfmtError::OutputNotADirectory { path }) => assert_eq!(path, dir.join("out")),
+            result => panic!("unexpected result {result:?}"),
+        }
+
+        // A directory to be created inside of the file is refused the same way.
+        let options = MboxToEmlOptions::new(dir.join("in.mbox"), dir.join("out/nested"));
+        match mbox_to_eml(&options) {
+            Err(MailfmtError::OutputNotADirectory { path }) => assert_eq!(path, dir.join("out")),
+            result => panic!("unexpected result {result:?}"),
+        }
+    }
+    assert_eq!(
+        fs::read_to_string(dir.join("out")).unwrap(),
+        "Not a directory"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_subdirectory_in_the_way_of_a_file_is_reported() {
+    let dir = temp_dir("output-file-subdirectory");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    fs::create_dir_all(dir.join("out")).unwrap();
+    fs::write(dir.join("out/2024"), "Not a directory").unwrap();
+
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), dir.join("out"));
+    options.overwrite = true;
+    options.group_by = GroupBy::Date;
+    match mbox_to_eml(&options) {
+        Err(MailfmtError::OutputNotADirectory { path }) => {
+            assert_eq!(path, dir.join("out/2024"))
+        }
+        result => panic!("unexpected result {result:?}"),
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_names_the_file_in_the_way() {
+    let dir = temp_dir("output-file-cli");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    fs::write(dir.join("out"), "Not a directory").unwrap();
+
+    for args in [&[][..], &["--overwrite"]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+            .env("NO_COLOR", "1")
+            .arg("mbox-to-eml")
+            .arg(dir.join("in.mbox"))
+            .arg(dir.join("out"))
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "{output:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains("exists and is a file, not a directory"),
+            "{stderr}"
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,84 @@
+//! Takes the first and last messages of an mbox and checks what is printed and written.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{PeekEnd, PeekOptions, peek_mbox};
+use std::{fs, path::PathBuf};
+
+/// Five messages, the third of which quotes a "From " line in its body.
+const MBOX: &str = "\
+From a Mon Jan 01 00:00:00 2024
+Subject: Zero
+
+Body 0
+
+From b Mon Jan 01 00:00:00 2024
+Subject: One
+
+Body 1
+
+From c Mon Jan 01 00:00:00 2024
+Subject: Two
+X-Folded: a header
+  folded onto two lines
+
+>From the quoted line
+
+From d Mon Jan 01 00:00:00 2024
+Subject: Three
+
+Body 3
+
+From e Mon Jan 01 00:00:00 2024
+
+Body 4
+";
+
+fn indices(end: PeekEnd, count: usize, mbox: &PathBuf) -> Vec<usize> {
+    let peeked = peek_mbox(&PeekOptions::new(mbox, count, end)).unwrap();
+    peeked.messages.iter().map(|(index, _)| *index).collect()
+}
+
+#[test]
+fn head_and_tail_take_their_end() {
+    let dir = temp_dir("peek");
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, MBOX).unwrap();
+    assert_eq!(indices(PeekEnd::Head, 2, &mbox), [0, 1]);
+    assert_eq!(indices(PeekEnd::Tail, 2, &mbox), [3, 4]);
+    assert_eq!(indices(PeekEnd::Tail, 10, &mbox), [0, 1, 2, 3, 4]);
+    assert!(indices(PeekEnd::Head, 0, &mbox).is_empty());
+    assert!(indices(PeekEnd::Tail, 0, &mbox).is_empty());
+
+    // Printing every message gives the mbox back as it was.
+    let all = peek_mbox(&PeekOptions::new(&mbox, 5, PeekEnd::Head)).unwrap();
+    let mut out = Vec::new();
+    all.write_mbox(&mut out, false).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), format!("{MBOX}\n"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn headers_only_to_eml_files() {
+    let dir = temp_dir("peek-eml");
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, MBOX).unwrap();
+    let peeked = peek_mbox(&PeekOptions::new(&mbox, 3, PeekEnd::Tail)).unwrap();
+    let written = peeked
+        .write_eml_files(&dir.join("out"), true)
+        .unwrap()
+        .into_iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(written, ["0002_Two.eml", "0003_Three.eml", "0004.eml"]);
+    assert_eq!(
+        fs::read_to_string(dir.join("out").join("0002_Two.eml")).unwrap(),
+        "Subject: Two\nX-Folded: a header\n  folded onto two lines\n\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("out").join("0004.eml")).unwrap(),
+        "\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,177 @@
+//! Drives conversions with --porcelain and parses every line printed, as a script would.
+
+mod common;
+
+use common::temp_dir;
+use std::{fs, path::Path, process::Command};
+
+#[derive(Debug, PartialEq)]
+enum Line {
+    Progress {
+        done: u64,
+        total: Option<u64>,
+        bytes_done: Option<u64>,
+        bytes_total: Option<u64>,
+    },
+    Error {
+        index: u64,
+        message: String,
+    },
+    Done {
+        converted: u64,
+        skipped: u64,
+        errors: u64,
+    },
+}
+
+fn parse(line: &str) -> Line {
+    let known = |value: &str| match value {
+        "?" => None,
+        value => Some(value.parse().expect("a number or '?'")),
+    };
+    let number = |value: &str| value.parse().expect("a number");
+    let fields: Vec<&str> = line.splitn(3, ' ').collect();
+    match fields[0] {
+        "progress" => {
+            let values: Vec<&str> = line.split(' ').skip(1).collect();
+            assert_eq!(values.len(), 4, "unexpected progress line {line:?}");
+            Line::Progress {
+                done: number(values[0]),
+                total: known(values[1]),
+                bytes_done: known(values[2]),
+                bytes_total: known(values[3]),
+            }
+        }
+        "error" => Line::Error {
+            index: number(fields[1]),
+            message: fields[2].to_string(),
+        },
+        "done" => {
+            let values: Vec<&str> = line.split(' ').skip(1).collect();
+            assert_eq!(values.len(), 3, "unexpected done line {line:?}");
+            Line::Done {
+                converted: number(values[0]),
+                skipped: number(values[1]),
+                errors: number(values[2]),
+            }
+        }
+        _ => panic!("unexpected line {line:?}"),
+    }
+}
+
+fn run(args: &[&str]) -> Vec<Line> {
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .args(args)
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("mailfmt runs");
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout)
+        .expect("the output is UTF-8")
+        .lines()
+        .map(parse)
+        .collect()
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn mbox_to_eml() {
+    let dir = temp_dir("porcelain-mbox");
+    let mbox = dir.join("in.mbox");
+    let mut content = Vec::new();
+    content.extend_from_slice(
+        b"From a@example.com Mon Jan 01 00:00:00 2024\nSubject: One\n\nHello\n\n",
+    );
+    content.extend_from_slice(
+        b"From b@example.com Mon Jan 01 00:00:00 2024\nSubject: Two\n\nBad \xff byte\n\n",
+    );
+    content.extend_from_slice(
+        b"From c@example.com Mon Jan 01 00:00:00 2024\nSubject: Three\n\nBye\n\n",
+    );
+    fs::write(&mbox, &content).unwrap();
+
+    let lines = run(&[
+        "mbox-to-eml",
+        path(&mbox),
+        path(&dir.join("out")),
+        "--porcelain",
+        "--porcelain-every",
+        "1",
+        "--invalid-utf8",
+        "strict",
+    ]);
+    let size = content.len() as u64;
+    assert_eq!(
+        lines.first(),
+        Some(&Line::Progress {
+            done: 0,
+            total: None,
+            bytes_done: Some(0),
+            bytes_total: Some(size),
+        })
+    );
+    assert!(lines.iter().any(
+        |line| matches!(line, Line::Error { index: 1, message } if message.contains("UTF-8"))
+    ));
+    assert!(lines.contains(&Line::Progress {
+        done: 3,
+        total: None,
+        bytes_done: Some(size),
+        bytes_total: Some(size),
+    }));
+    assert_eq!(
+        lines.last(),
+        Some(&Line::Done {
+            converted: 2,
+            skipped: 0,
+            errors: 1,
+        })
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn eml_to_mbox() {
+    let dir = temp_dir("porcelain-eml");
+    let input = dir.join("in");
+    fs::create_dir_all(&input).unwrap();
+    for index in 0..5 {
+        fs::write(
+            input.join(format!("{index}.eml")),
+            format!("Subject: {index}\n\nHello\n"),
+        )
+        .unwrap();
+    }
+
+    let lines = run(&[
+        "eml-to-mbox",
+        path(&input),
+        path(&dir.join("out.mbox")),
+        "--porcelain",
+        "--porcelain-every",
+        "2",
+    ]);
+    let progress: Vec<u64> = lines
+        .iter()
+        .filter_map(|line| match line {
+            Line::Progress { done, total, .. } => {
+                assert_eq!(*total, Some(5));
+                Some(*done)
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(progress, [0, 2, 4, 5]);
+    assert_eq!(
+        lines.last(),
+        Some(&Line::Done {
+            converted: 5,
+            skipped: 0,
+            errors: 0,
+        })
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,62 @@
+//! Converts an mbox with `--print-paths` and checks that stdout lists every file written, one
+//! per line and in the order of the messages in the input.
+
+mod common;
+
+use common::temp_dir;
+use std::{fs, process::Command};
+
+/// Messages whose subjects do not sort in the order they appear in.
+const MBOX: &str = "\
+From a@example.com Mon Jan 01 00:00:00 2024
+Subject: Zeta
+
+First
+
+From b@example.com Mon Jan 01 00:00:00 2024
+Subject: Alpha
+
+Second
+
+From c@example.com Mon Jan 01 00:00:00 2024
+Subject: Mid
+
+Third
+";
+
+#[test]
+fn written_files_are_printed_in_input_order() {
+    let dir = temp_dir("print-paths");
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, MBOX).unwrap();
+    let out = dir.join("out");
+    let expected: Vec<String> = ["0000_Zeta.eml", "0001_Alpha.eml", "0002_Mid.eml"]
+        .iter()
+        .map(|name| out.join(name).display().to_string())
+        .collect();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .arg("mbox-to-eml")
+        .arg(&mbox)
+        .arg(&out)
+        .arg("--print-paths")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), expected);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .arg("mbox-to-eml")
+        .arg(&mbox)
+        .arg(&out)
+        .args(["--overwrite", "--print-paths", "--print0"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, expected.join("\0") + "\0");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,143 @@
+//! Extracts ranges of messages from an mbox and checks which files are written and how they
+//! are numbered.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{Headerless, MailfmtError, MboxToEmlOptions, MessageRange, mbox_to_eml};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Write an mbox of `count` messages, where those whose index is in `headerless` have no
+/// header block, returning its path.
+fn fixture(dir: &Path, count: usize, headerless: &[usize]) -> PathBuf {
+    let mut content = String::new();
+    for index in 0..count {
+        content.push_str("From sender@example.com Mon Jan 01 00:00:00 2024\n");
+        match headerless.contains(&index) {
+            true => content.push_str(&format!("Just text {index}\n\n")),
+            false => content.push_str(&format!("Subject: Message {index}\n\nBody {index}\n\n")),
+        }
+    }
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, content).unwrap();
+    mbox
+}
+
+/// Extract `range`, returning the names of the files written, sorted.
+fn extract(
+    mbox: &Path,
+    output: &Path,
+    range: &str,
+    configure: impl FnOnce(&mut MboxToEmlOptions),
+) -> Vec<String> {
+    let mut options = MboxToEmlOptions::new(mbox, output);
+    options.range = Some(range.parse().unwrap());
+    configure(&mut options);
+    mbox_to_eml(&options).unwrap();
+    let mut names = fs::read_dir(output)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".eml"))
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[test]
+fn ranges_are_parsed() {
+    let range: MessageRange = "3..7".parse().unwrap();
+    assert_eq!((range.start, range.end), (3, Some(7)));
+    assert_eq!("3..".parse::<MessageRange>().unwrap().end, None);
+    assert_eq!("..7".parse::<MessageRange>().unwrap().start, 0);
+    assert_eq!(range.to_string(), "3..7");
+    for invalid in ["3", "3..=7", "a..7", "7..3", "3..3"] {
+        assert!(invalid.parse::<MessageRange>().is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn files_are_numbered_after_their_position() {
+    let dir = temp_dir("range");
+    let mbox = fixture(&dir, 10, &[]);
+    assert_eq!(
+        extract(&mbox, &dir.join("a"), "3..6", |_| {}),
+        [
+            "0003_Message 3.eml",
+            "0004_Message 4.eml",
+            "0005_Message 5.eml"
+        ]
+    );
+    assert_eq!(
+        extract(&mbox, &dir.join("b"), "3..6", |options| {
+            options.renumber = true;
+        }),
+        [
+            "0000_Message 3.eml",
+            "0001_Message 4.eml",
+            "0002_Message 5.eml"
+        ]
+    );
+    assert_eq!(
+        extract(&mbox, &dir.join("c"), "8..", |_| {}),
+        ["0008_Message 8.eml", "0009_Message 9.eml"]
+    );
+    assert_eq!(
+        extract(&mbox, &dir.join("d"), "..2", |_| {}),
+        ["0000_Message 0.eml", "0001_Message 1.eml"]
+    );
+
+    let mut options = MboxToEmlOptions::new(&mbox, dir.join("e"));
+    options.range = Some("3..6".parse().unwrap());
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.out_of_range), (3, 3));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn ranges_apply_to_positions_before_skipping_headerless_messages() {
+    let dir = temp_dir("range-headerless");
+    let mbox = fixture(&dir, 8, &[1, 4]);
+    let mut options = MboxToEmlOptions::new(&mbox, dir.join("out"));
+    options.range = Some("3..6".parse().unwrap());
+    options.headerless = Headerless::Skip;
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.converted, summary.headerless), (2, 1));
+
+    assert_eq!(
+        extract(&mbox, &dir.join("again"), "3..6", |options| {
+            options.headerless = Headerless::Skip;
+        }),
+        ["0003_Message 3.eml", "0005_Message 5.eml"]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn samples_are_drawn_from_the_range() {
+    let dir = temp_dir("range-sample");
+    let mbox = fixture(&dir, 20, &[]);
+    let mut options = MboxToEmlOptions::new(&mbox, dir.join("out"));
+    options.range = Some("5..10".parse().unwrap());
+    options.sample = Some(3);
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!(summary.sampled.unwrap().population, 5);
+    assert_eq!((summary.converted, summary.out_of_range), (3, 5));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn ranges_cannot_be_resumed() {
+    let dir = temp_dir("range-resume");
+    let mbox = fixture(&dir, 3, &[]);
+    let mut options = MboxToEmlOptions::new(&mbox, dir.join("out"));
+    options.range = Some("1..".parse().unwrap());
+    options.resume = true;
+    assert!(matches!(
+        mbox_to_eml(&options),
+        Err(MailfmtError::RangeNotResumable)
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,114 @@
+//! Extracts only the read or unread messages of an mbox, after their Status and
+//! X-Mozilla-Status headers, and checks which are written and how they are counted.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{MboxToEmlOptions, ReadState, count_mbox, mbox_to_eml};
+use std::{fs, process::Command};
+
+/// Read by Status, old but unread, read by the Mozilla bitmask, replied to but unread by the
+/// bitmask, and without any status header.
+const MBOX: &str = "\
+From a Mon Jan 01 00:00:00 2024
+Subject: Read
+Status: RO
+
+Body
+
+From b Mon Jan 01 00:00:00 2024
+Subject: Old
+Status: O
+
+Body
+
+From c Mon Jan 01 00:00:00 2024
+Subject: Thunderbird read
+X-Mozilla-Status: 0001
+
+Body
+
+From d Mon Jan 01 00:00:00 2024
+Subject: Thunderbird replied
+X-Mozilla-Status: 0002
+
+Body
+
+From e Mon Jan 01 00:00:00 2024
+Subject: New
+
+Body
+";
+
+/// Extract the messages in `state`, returning the names of the files written, sorted.
+fn extract(name: &str, state: ReadState, no_status_means: ReadState) -> Vec<String> {
+    let dir = temp_dir(name);
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+    let output = dir.join("out");
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), &output);
+    options.read_state = Some(state);
+    options.no_status_means = no_status_means;
+
+    let count = count_mbox(&options).unwrap();
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!(summary.converted as u64, count.matching);
+    assert_eq!(summary.off_state, 5 - summary.converted);
+    assert_eq!(summary.counters().filtered, summary.off_state);
+    let mut names: Vec<_> = fs::read_dir(&output)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    fs::remove_dir_all(&dir).unwrap();
+    names
+}
+
+#[test]
+fn unseen_messages_are_extracted() {
+    assert_eq!(
+        extract("read-state-unseen", ReadState::Unseen, ReadState::Unseen),
+        [
+            "0000_Old.eml",
+            "0001_Thunderbird replied.eml",
+            "0002_New.eml"
+        ]
+    );
+}
+
+#[test]
+fn messages_without_status_can_count_as_seen() {
+    assert_eq!(
+        extract("read-state-seen", ReadState::Seen, ReadState::Seen),
+        ["0000_Read.eml", "0001_Thunderbird read.eml", "0002_New.eml"]
+    );
+}
+
+#[test]
+fn cli_reports_the_messages_left_out() {
+    let dir = temp_dir("read-state-cli");
+    fs::write(dir.join("in.mbox"), MBOX).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .env("NO_COLOR", "1")
+        .arg("mbox-to-eml")
+        .arg(dir.join("in.mbox"))
+        .arg(dir.join("out"))
+        .arg("--seen")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("3 emails were left out as they are not seen"),
+        "{stdout}"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .arg("mbox-to-eml")
+        .arg(dir.join("in.mbox"))
+        .args(["--count-only", "--seen", "--unseen"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    fs::remove_dir_all(&dir).unwrap();
+}
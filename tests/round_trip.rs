@@ -0,0 +1,206 @@
+//! Converts an mbox to eml files and back and checks what survives the round trip.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{
+    EmlInput, EmlToMboxOptions, Event, FromLineStyle, MboxDialect, MboxToEmlOptions, count_mbox,
+    eml_to_mbox, mbox_to_eml,
+};
+use serde_json::Value;
+use std::{cell::RefCell, fs};
+
+/// An mbox as Thunderbird writes it, with the headers recording read and flagged state.
+const THUNDERBIRD: &str = "\
+From - Mon Jan 01 00:00:00 2024
+X-Mozilla-Status: 0001
+X-Mozilla-Status2: 00000000
+X-Mozilla-Keys: $label1 important
+Status: RO
+X-Status: F
+From: Alice <alice@example.com>
+Date: Mon, 01 Jan 2024 00:00:00 +0000
+Subject: Read and flagged
+Message-ID: <one@example.com>
+
+Body one
+
+From - Tue Jan 02 00:00:00 2024
+X-Mozilla-Status: 0000
+X-Mozilla-Status2: 00000000
+X-Mozilla-Keys:
+From: Bob <bob@example.com>
+Date: Tue, 02 Jan 2024 00:00:00 +0000
+Subject: Unread
+Message-ID: <two@example.com>
+
+Body two
+";
+
+#[test]
+fn mail_client_headers_survive() {
+    let dir = temp_dir("round-trip");
+    let (mbox, emls, back) = (dir.join("in.mbox"), dir.join("eml"), dir.join("out.mbox"));
+    fs::write(&mbox, THUNDERBIRD).unwrap();
+    mbox_to_eml(&MboxToEmlOptions::new(&mbox, &emls)).unwrap();
+    let mut files: Vec<_> = fs::read_dir(&emls)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "eml"))
+        .collect();
+    files.sort();
+    eml_to_mbox(&EmlToMboxOptions::new(EmlInput::Files(files), &back)).unwrap();
+
+    let headers = |content: &str| -> Vec<String> {
+        content
+            .lines()
+            .filter(|line| {
+                ["X-Mozilla-", "Status:", "X-Status:"]
+                    .iter()
+                    .any(|name| line.starts_with(name))
+            })
+            .map(str::to_string)
+            .collect()
+    };
+    let original = headers(THUNDERBIRD);
+    assert_eq!(original.len(), 8);
+    assert_eq!(headers(&fs::read_to_string(&back).unwrap()), original);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sidecars_keep_separator_lines() {
+    let dir = temp_dir("sidecars");
+    let (mbox, emls, back) = (dir.join("in.mbox"), dir.join("eml"), dir.join("out.mbox"));
+    fs::write(&mbox, THUNDERBIRD).unwrap();
+    let mut options = MboxToEmlOptions::new(&mbox, &emls);
+    options.sidecar_metadata = true;
+    mbox_to_eml(&options).unwrap();
+
+    let sidecar = fs::read_to_string(emls.join("0000_Read and flagged.meta.json")).unwrap();
+    let sidecar = serde_json::from_str::<Value>(&sidecar).unwrap();
+    assert_eq!(sidecar.get("envelope_sender").unwrap().as_str(), Some("-"));
+    assert_eq!(sidecar.get("offset").unwrap().as_u64(), Some(0));
+    assert_eq!(
+        sidecar.get("message_id").unwrap().as_str(),
+        Some("<one@example.com>")
+    );
+    let flags: Vec<_> = (sidecar.get("flags").unwrap().as_array().unwrap().iter())
+        .filter_map(Value::as_str)
+        .collect();
+    assert_eq!(flags, ["seen", "old", "flagged"]);
+
+    eml_to_mbox(&EmlToMboxOptions::new(EmlInput::Directory(emls), &back)).unwrap();
+    let separators: Vec<_> = fs::read_to_string(&back)
+        .unwrap()
+        .lines()
+        .filter(|line| line.starts_with("From "))
+        .map(str::to_string)
+        .collect();
+    assert_eq!(
+        separators,
+        [
+            "From - Mon Jan 01 00:00:00 2024",
+            "From - Tue Jan 02 00:00:00 2024"
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn thunderbird_separator_lines_can_be_written() {
+    let dir = temp_dir("thunderbird-style");
+    let (mbox, emls, back) = (dir.join("in.mbox"), dir.join("eml"), dir.join("out.mbox"));
+    fs::write(&mbox, THUNDERBIRD).unwrap();
+    let summary = mbox_to_eml(&MboxToEmlOptions::new(&mbox, &emls)).unwrap();
+    assert_eq!(summary.converted, 2);
+
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(emls), &back);
+    options.from_line_style = FromLineStyle::Thunderbird;
+    eml_to_mbox(&options).unwrap();
+    assert_eq!(
+        fs::read_to_string(&back).unwrap(),
+        format!("{THUNDERBIRD}\n")
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn processing_names_the_current_message() {
+    let dir = temp_dir("processing");
+    let (mbox, emls, back) = (dir.join("in.mbox"), dir.join("eml"), dir.join("out.mbox"));
+    fs::write(
+        &mbox,
+        THUNDERBIRD.replace("Subject: Unread", "Subject: =?UTF-8?B?5pyq6Kqt?="),
+    )
+    .unwrap();
+    let names = RefCell::new(Vec::new());
+    let record = |event: Event<'_>| {
+        if let Event::Processing(name) = event {
+            names.borrow_mut().push(name.to_string());
+        }
+        Ok(())
+    };
+
+    let mut options = MboxToEmlOptions::new(&mbox, &emls);
+    options.on_event = Some(Box::new(record));
+    mbox_to_eml(&options).unwrap();
+    drop(options);
+    assert_eq!(names.take(), ["Read and flagged", "未読"]);
+
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(emls), &back);
+    options.on_event = Some(Box::new(record));
+    eml_to_mbox(&options).unwrap();
+    drop(options);
+    assert_eq!(
+        names.take(),
+        ["0000_Read and flagged.eml", "0001_=UTF-8B5pyq6Kqt=.eml"]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_missing_final_newline_survives_extraction() {
+    let dir = temp_dir("final-newline");
+    let (mbox, emls) = (dir.join("in.mbox"), dir.join("eml"));
+    fs::write(&mbox, THUNDERBIRD.strip_suffix('\n').unwrap()).unwrap();
+    assert_eq!(
+        mbox_to_eml(&MboxToEmlOptions::new(&mbox, &emls))
+            .unwrap()
+            .converted,
+        2
+    );
+
+    let first = fs::read_to_string(emls.join("0000_Read and flagged.eml")).unwrap();
+    let last = fs::read_to_string(emls.join("0001_Unread.eml")).unwrap();
+    assert!(first.ends_with("\n\nBody one\n\n"), "{first:?}");
+    assert!(last.ends_with("\n\nBody two"), "{last:?}");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn from_lines_survive_the_dialects_that_keep_them_apart() {
+    for dialect in [MboxDialect::Mboxrd, MboxDialect::Mboxcl2] {
+        let dir = temp_dir("dialects");
+        let (emls, mbox, back) = (dir.join("in"), dir.join("out.mbox"), dir.join("back"));
+        fs::create_dir(&emls).unwrap();
+        let body = "From here\n>From there\n>>From everywhere\n";
+        fs::write(emls.join("a.eml"), format!("Subject: A\n\n{body}")).unwrap();
+        fs::write(emls.join("b.eml"), "Subject: B\n\nIntact\n").unwrap();
+        let mut options = EmlToMboxOptions::new(EmlInput::Directory(emls), &mbox);
+        options.dialect = dialect;
+        eml_to_mbox(&options).unwrap();
+
+        let mut options = MboxToEmlOptions::new(&mbox, &back);
+        options.dialect = dialect;
+        options.precount = true;
+        assert_eq!(mbox_to_eml(&options).unwrap().converted, 2, "{dialect:?}");
+        assert_eq!(count_mbox(&options).unwrap().found, 2, "{dialect:?}");
+        let first = fs::read_to_string(back.join("0000_A.eml")).unwrap();
+        assert!(
+            first.ends_with(&format!("\n\n{body}\n")),
+            "{dialect:?}: {first:?}"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! Extracts random samples of an mbox and checks which messages they hold and the manifest
+//! recording them.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{Headerless, MailfmtError, MboxToEmlOptions, SAMPLE_MANIFEST_FILE_NAME, mbox_to_eml};
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Write an mbox of `count` messages, where those whose index is in `headerless` have no
+/// header block, returning its path.
+fn fixture(dir: &Path, count: usize, headerless: &[usize]) -> PathBuf {
+    let mut content = String::new();
+    for index in 0..count {
+        content.push_str("From sender@example.com Mon Jan 01 00:00:00 2024\n");
+        match headerless.contains(&index) {
+            true => content.push_str(&format!("Just text {index}\n\n")),
+            false => content.push_str(&format!("Subject: Message {index}\n\nBody {index}\n\n")),
+        }
+    }
+    let mbox = dir.join("in.mbox");
+    fs::write(&mbox, content).unwrap();
+    mbox
+}
+
+/// Extract a sample, returning the indices recorded in its manifest.
+fn sample(
+    mbox: &Path,
+    output: &Path,
+    size: usize,
+    seed: u64,
+    configure: impl FnOnce(&mut MboxToEmlOptions),
+) -> Vec<u64> {
+    let mut options = MboxToEmlOptions::new(mbox, output);
+    options.overwrite = true;
+    options.sample = Some(size);
+    options.sample_seed = Some(seed);
+    configure(&mut options);
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!(summary.sampled.unwrap().seed, seed);
+    fs::read_to_string(output.join(SAMPLE_MANIFEST_FILE_NAME))
+        .unwrap()
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<Value>(line)
+                .unwrap()
+                .get("index")
+                .unwrap()
+                .as_u64()
+                .unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn seeded_samples_are_reproducible() {
+    let dir = temp_dir("sample");
+    let mbox = fixture(&dir, 50, &[]);
+    let first = sample(&mbox, &dir.join("a"), 5, 42, |_| {});
+    assert_eq!(first.len(), 5);
+    assert!(first.is_sorted());
+    assert_eq!(sample(&mbox, &dir.join("b"), 5, 42, |_| {}), first);
+    assert_ne!(sample(&mbox, &dir.join("c"), 5, 7, |_| {}), first);
+
+    // Files are numbered in mbox order and traced back to their message by the manifest.
+    let manifest = fs::read_to_string(dir.join("a").join(SAMPLE_MANIFEST_FILE_NAME)).unwrap();
+    let entry = serde_json::from_str::<Value>(manifest.lines().next().unwrap()).unwrap();
+    let file = entry.get("file").unwrap().as_str().unwrap();
+    assert_eq!(file, format!("0000_Message {}.eml", first[0]));
+    let offset = entry.get("offset").unwrap().as_u64().unwrap() as usize;
+    let content = fs::read_to_string(&mbox).unwrap();
+    assert!(content[offset..].starts_with(&format!(
+        "From sender@example.com Mon Jan 01 00:00:00 2024\nSubject: Message {}\n",
+        first[0]
+    )));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn samples_are_drawn_from_every_part_of_the_input() {
+    let dir = temp_dir("sample-spread");
+    let mbox = fixture(&dir, 4, &[]);
+    let mut drawn = [0; 4];
+    for seed in 0..200 {
+        let indices = sample(&mbox, &dir.join("out"), 1, seed, |_| {});
+        drawn[indices[0] as usize] += 1;
+    }
+    assert!(drawn.iter().all(|&count| count > 25), "{drawn:?}");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn headerless_messages_are_left_out_of_the_sample() {
+    let dir = temp_dir("sample-headerless");
+    let mbox = fixture(&dir, 6, &[1, 4]);
+    let mut options = MboxToEmlOptions::new(&mbox, dir.join("out"));
+    options.sample = Some(10);
+    options.headerless = Headerless::Skip;
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!(summary.sampled.unwrap().population, 4);
+    assert_eq!((summary.converted, summary.headerless), (4, 2));
+
+    let indices = sample(&mbox, &dir.join("again"), 2, 3, |options| {
+        options.headerless = Headerless::Skip;
+    });
+    assert!(indices.iter().all(|index| ![1, 4].contains(index)));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn samples_cannot_be_resumed() {
+    let dir = temp_dir("sample-resume");
+    let mbox = fixture(&dir, 3, &[]);
+    let mut options = MboxToEmlOptions::new(&mbox, dir.join("out"));
+    options.sample = Some(1);
+    options.resume = true;
+    assert!(matches!(
+        mbox_to_eml(&options),
+        Err(MailfmtError::SampleNotResumable)
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,185 @@
+//! Parses mboxes with "From " lines in quoted-printable bodies, with and without smart
+//! separators, and checks where messages are split.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{Event, MboxParser, MboxToEmlOptions, mbox_to_eml};
+use std::{cell::Cell, fs};
+
+/// The subjects of the messages parsed from an mbox, with smart separators or without.
+fn subjects(mbox: &str, smart: bool) -> Vec<String> {
+    MboxParser::from_reader(mbox.as_bytes())
+        .with_smart_separators(smart)
+        .map(|message| {
+            message
+                .unwrap()
+                .header("subject")
+                .unwrap_or("(none)")
+                .to_string()
+        })
+        .collect()
+}
+
+const QP_HEADER: &str = "Content-Transfer-Encoding: quoted-printable";
+
+#[test]
+fn genuine_boundary_after_quoted_printable_message() {
+    let mbox = format!(
+        "From a Mon Jan 01 00:00:00 2024\nSubject: One\n{QP_HEADER}\n\nBody=20one\n\n\
+         From b Mon Jan 01 00:00:00 2024\nSubject: Two\n\nBody two\n"
+    );
+    assert_eq!(subjects(&mbox, false), ["One", "Two"]);
+    assert_eq!(subjects(&mbox, true), ["One", "Two"]);
+}
+
+#[test]
+fn from_line_followed_by_text_stays_in_body() {
+    let mbox = format!(
+        "From a Mon Jan 01 00:00:00 2024\nSubject: One\n{QP_HEADER}\n\nThe log said:\n\
+         From someone@example.com Mon Jan 01 00:00:00 2024\nand then nothing.\n\n\
+         From b Mon Jan 01 00:00:00 2024\nSubject: Two\n\nBody two\n"
+    );
+    assert_eq!(subjects(&mbox, false), ["One", "(none)", "Two"]);
+    assert_eq!(subjects(&mbox, true), ["One", "Two"]);
+    let first = MboxParser::from_reader(mbox.as_bytes())
+        .with_smart_separators(true)
+        .next()
+        .unwrap()
+        .unwrap();
+    assert!(first.lines.contains(&b"and then nothing.".to_vec()));
+}
+
+#[test]
+fn from_line_followed_by_blank_line_stays_in_body() {
+    let mbox = format!(
+        "From a Mon Jan 01 00:00:00 2024\nSubject: One\n{QP_HEADER}\n\nQuoted:\n\
+         From someone@example.com Mon Jan 01 00:00:00 2024\n\nSubject: not a header\n"
+    );
+    assert_eq!(subjects(&mbox, true), ["One"]);
+}
+
+#[test]
+fn from_line_after_soft_line_break_stays_in_body() {
+    let mbox = format!(
+        "From a Mon Jan 01 00:00:00 2024\nSubject: One\n{QP_HEADER}\n\nA line broken here=\n\
+         From the start: this looks like a header\nSubject: so does this\n"
+    );
+    assert_eq!(subjects(&mbox, false), ["One", "so does this"]);
+    assert_eq!(subjects(&mbox, true), ["One"]);
+}
+
+#[test]
+fn header_within_lookahead_starts_a_message() {
+    let mbox = format!(
+        "From a Mon Jan 01 00:00:00 2024\nSubject: One\n{QP_HEADER}\n\nBody\n\
+         From b Mon Jan 01 00:00:00 2024\nnot a header\nstill not\nSubject: Two\n\nBody two\n"
+    );
+    assert_eq!(subjects(&mbox, true), ["One", "Two"]);
+
+    let too_far = format!(
+        "From a Mon Jan 01 00:00:00 2024\nSubject: One\n{QP_HEADER}\n\nBody\n\
+         From b Mon Jan 01 00:00:00 2024\nnot\na\nheader\nSubject: Two\n"
+    );
+    assert_eq!(subjects(&too_far, true), ["One"]);
+}
+
+#[test]
+fn messages_without_quoted_printable_are_split_as_usual() {
+    let mbox = "From a Mon Jan 01 00:00:00 2024\nSubject: One\n\nThe log said:\n\
+                From someone@example.com Mon Jan 01 00:00:00 2024\nand then nothing.\n";
+    assert_eq!(subjects(mbox, true), ["One", "(none)"]);
+}
+
+#[test]
+fn quoted_printable_part_protects_the_rest_of_the_message() {
+    let mbox = format!(
+        "From a Mon Jan 01 00:00:00 2024\nSubject: One\nContent-Type: multipart/mixed; boundary=b\n\n\
+         --b\nContent-Type: text/plain\n{QP_HEADER}\n\nQuoted:\n\
+         From someone@example.com Mon Jan 01 00:00:00 2024\nin the part.\n--b--\n\n\
+         From b Mon Jan 01 00:00:00 2024\nSubject: Two\n\nBody two\n"
+    );
+    assert_eq!(subjects(&mbox, true), ["One", "Two"]);
+}
+
+#[test]
+fn quoted_printable_does_not_carry_over_to_the_next_message() {
+    let mbox = format!(
+        "From a Mon Jan 01 00:00:00 2024\nSubject: One\n{QP_HEADER}\n\nBody\n\n\
+         From b Mon Jan 01 00:00:00 2024\nSubject: Two\n\nPlain:\n\
+         From c Mon Jan 01 00:00:00 2024\ntext\n"
+    );
+    assert_eq!(subjects(&mbox, true), ["One", "Two", "(none)"]);
+}
+
+#[test]
+fn from_line_at_end_of_input_stays_in_body() {
+    let mbox = format!(
+        "From a Mon Jan 01 00:00:00 2024\nSubject: One\n{QP_HEADER}\n\nBody\n\
+         From someone@example.com Mon Jan 01 00:00:00 2024\n"
+    );
+    let messages: Vec<_> = MboxParser::from_reader(mbox.as_bytes())
+        .with_smart_separators(true)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(
+        messages[0].lines.last().unwrap(),
+        b"From someone@example.com Mon Jan 01 00:00:00 2024"
+    );
+}
+
+#[test]
+fn crlf_line_endings() {
+    let mbox = format!(
+        "From a Mon Jan 01 00:00:00 2024\r\nSubject: One\r\n{QP_HEADER}\r\n\r\nQuoted:\r\n\
+         From someone@example.com Mon Jan 01 00:00:00 2024\r\n\r\n\
+         From b Mon Jan 01 00:00:00 2024\r\nSubject: Two\r\n\r\nBody\r\n"
+    );
+    assert_eq!(subjects(&mbox, true), ["One", "Two"]);
+}
+
+#[test]
+fn offsets_and_positions_survive_lookahead() {
+    let first = format!(
+        "From a Mon Jan 01 00:00:00 2024\nSubject: One\n{QP_HEADER}\n\nQuoted:\n\
+         From someone@example.com Mon Jan 01 00:00:00 2024\ntext\n\n"
+    );
+    let mbox = format!("{first}From b Mon Jan 01 00:00:00 2024\nSubject: Two\n\nBody\n");
+    let mut parser = MboxParser::from_reader(mbox.as_bytes()).with_smart_separators(true);
+    parser.next().unwrap().unwrap();
+    assert_eq!(parser.position(), Some(first.len() as u64));
+    let second = parser.next().unwrap().unwrap();
+    assert_eq!(second.offset, first.len() as u64);
+    assert_eq!(second.envelope, "b Mon Jan 01 00:00:00 2024");
+    assert!(parser.next().is_none());
+}
+
+#[test]
+fn extraction_uses_smart_separators() {
+    let dir = temp_dir("separators");
+    fs::write(
+        dir.join("in.mbox"),
+        format!(
+            "From a Mon Jan 01 00:00:00 2024\nSubject: One\n{QP_HEADER}\n\nThe log said:\n\
+             From someone@example.com Mon Jan 01 00:00:00 2024\nand then nothing.\n\n\
+             From b Mon Jan 01 00:00:00 2024\nSubject: Two\n\nBody two\n"
+        ),
+    )
+    .unwrap();
+    let counted = Cell::new(None);
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), dir.join("out"));
+    options.smart_separators = true;
+    options.precount = true;
+    options.on_event = Some(Box::new(|event| {
+        if let Event::Counted { messages, .. } = event {
+            counted.set(Some(messages));
+        }
+        Ok(())
+    }));
+    assert_eq!(mbox_to_eml(&options).unwrap().converted, 2);
+    drop(options);
+    // The progress total is counted with smart separators too.
+    assert_eq!(counted.get(), Some(2));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,158 @@
+//! Extracts a mailbox again into a directory holding an earlier export, with
+//! `skip_existing_by`, and checks that only the messages not already there are written.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{Event, ExistingKey, MboxToEmlOptions, mbox_to_eml};
+use std::{cell::RefCell, fs, path::PathBuf, process::Command};
+
+const EARLIER: &str = "\
+From a Mon Jan 01 00:00:00 2024
+Message-ID: <one@example.com>
+Subject: One
+
+First
+
+From b Tue Jan 02 00:00:00 2024
+Message-ID: <two@example.com>
+Subject: Two
+
+Second
+";
+
+const LATER: &str = "\
+From a Mon Jan 01 00:00:00 2024
+Message-ID: <one@example.com>
+Subject: One
+
+First
+
+From b Tue Jan 02 00:00:00 2024
+Message-ID:   two@example.com
+Subject: Two
+
+Second
+
+From c Wed Jan 03 00:00:00 2024
+Message-ID: <three@example.com>
+Subject: Three
+
+Third
+
+From d Thu Jan 04 00:00:00 2024
+Subject: Anonymous
+
+Fourth
+";
+
+fn file_names(dir: &PathBuf) -> Vec<String> {
+    let mut names: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn messages_already_present_are_left_out() {
+    let dir = temp_dir("skip-existing");
+    let out = dir.join("out");
+    fs::write(dir.join("earlier.mbox"), EARLIER).unwrap();
+    fs::write(dir.join("later.mbox"), LATER).unwrap();
+    mbox_to_eml(&MboxToEmlOptions::new(dir.join("earlier.mbox"), &out)).unwrap();
+    // Files are found by what they hold, wherever they are and whatever they are named.
+    fs::create_dir(out.join("kept")).unwrap();
+    fs::rename(out.join("0000_One.eml"), out.join("kept/renamed.eml")).unwrap();
+
+    let scanned = RefCell::new(None);
+    let mut options = MboxToEmlOptions::new(dir.join("later.mbox"), &out);
+    options.skip_existing_by = Some(ExistingKey::MessageId);
+    options.on_event = Some(Box::new(|event| {
+        if let Event::Scanned {
+            files, message_ids, ..
+        } = event
+        {
+            *scanned.borrow_mut() = Some((files, message_ids));
+        }
+        Ok(())
+    }));
+    let summary = mbox_to_eml(&options).unwrap();
+    drop(options);
+    assert_eq!(scanned.into_inner(), Some((2, 2)));
+    assert_eq!(
+        (summary.converted, summary.skipped, summary.already_present),
+        (2, 2, 2)
+    );
+    assert_eq!(
+        file_names(&out),
+        [
+            "0001_Two.eml",
+            "0002_Three.eml",
+            "0003_Anonymous.eml",
+            "kept"
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_taken_file_name_is_not_replaced() {
+    let dir = temp_dir("skip-existing-taken");
+    let out = dir.join("out");
+    fs::create_dir(&out).unwrap();
+    let other = "Message-ID: <other@example.com>\nSubject: One\n\nSomething else\n";
+    fs::write(out.join("0000_One.eml"), other).unwrap();
+    fs::write(dir.join("earlier.mbox"), EARLIER).unwrap();
+
+    let warnings = RefCell::new(Vec::new());
+    let mut options = MboxToEmlOptions::new(dir.join("earlier.mbox"), &out);
+    options.skip_existing_by = Some(ExistingKey::MessageId);
+    options.on_event = Some(Box::new(|event| {
+        if let Event::Warning(warning) = event {
+            warnings.borrow_mut().push(warning);
+        }
+        Ok(())
+    }));
+    let summary = mbox_to_eml(&options).unwrap();
+    drop(options);
+    assert_eq!(
+        (summary.converted, summary.skipped, summary.already_present),
+        (1, 1, 0)
+    );
+    assert_eq!(fs::read_to_string(out.join("0000_One.eml")).unwrap(), other);
+    let warnings = warnings.into_inner();
+    assert!(
+        warnings
+            .iter()
+            .any(|warning| warning.contains("Left out message 0")),
+        "{warnings:?}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_summary_tells_how_many_were_already_present() {
+    let dir = temp_dir("skip-existing-cli");
+    let out = dir.join("out");
+    fs::write(dir.join("later.mbox"), LATER).unwrap();
+    fs::write(dir.join("earlier.mbox"), EARLIER).unwrap();
+    mbox_to_eml(&MboxToEmlOptions::new(dir.join("earlier.mbox"), &out)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .args(["mbox-to-eml", "--skip-existing-by", "message-id"])
+        .arg(dir.join("later.mbox"))
+        .arg(&out)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("Read 2 existing eml files"), "{stdout}");
+    assert!(
+        stdout.contains("2 emails were left out as already present in the output directory"),
+        "{stdout}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,210 @@
+//! Extracts mboxes into subdirectories by date and by sender and checks the trees they give.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{Event, GroupBy, MboxToEmlOptions, Numbering, mbox_to_eml};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The Date headers of the fixture, one of them missing and one of them unparseable, which are
+/// grouped after the date on their separator line.
+const DATES: &[Option<&str>] = &[
+    Some("Tue, 14 Mar 2023 09:00:00 +0000"),
+    Some("Wed, 15 Mar 2023 10:30:00 +0100"),
+    Some("Fri, 01 Dec 2023 08:00:00 -0500"),
+    // Still December 2023 in its own time zone, though January in UTC.
+    Some("Sun, 31 Dec 2023 23:30:00 -0200"),
+    Some("Mon, 01 Jan 2024 00:00:01 +0000"),
+    None,
+    Some("Thu, 29 Feb 2024 12:00:00 +0000"),
+    Some("2025-07-04T18:00:00+02:00"),
+    Some("not a date"),
+    Some("Fri, 04 Jul 2025 20:00:00 +0000"),
+];
+
+/// The From headers of the sender fixture.
+const SENDERS: &[Option<&str>] = &[
+    Some("Alice <Alice@Example.com>"),
+    Some("bob@example.com (Bob)"),
+    Some("alice@example.com"),
+    Some("Carol <carol@lists.example.org>"),
+    None,
+    Some("\"Dave <at> home\" <dave@example.net>"),
+    Some("Alice <alice@example.com>"),
+    Some("undisclosed-recipients:;"),
+];
+
+/// Write an mbox whose messages have the given headers, returning its path.
+fn fixture(dir: &Path, header: &str, values: &[Option<&str>]) -> PathBuf {
+    let mut content = String::new();
+    for (index, value) in values.iter().enumerate() {
+        content.push_str("From sender@example.com Mon Jan 01 00:00:00 2024\n");
+        if let Some(value) = value {
+            content.push_str(&format!("{header}: {value}\n"));
+        }
+        content.push_str(&format!("Subject: Message {index}\n\nBody {index}\n\n"));
+    }
+    let mbox = dir.join(format!("{header}.mbox"));
+    fs::write(&mbox, content).unwrap();
+    mbox
+}
+
+/// Extract an mbox into the output directory, returning the paths written, relative to it.
+fn extract(
+    mbox: PathBuf,
+    output: &Path,
+    configure: impl FnOnce(&mut MboxToEmlOptions),
+) -> Vec<String> {
+    let written = RefCell::new(Vec::new());
+    let mut options = MboxToEmlOptions::new(&mbox, output);
+    configure(&mut options);
+    options.on_event = Some(Box::new(|event| {
+        if let Event::Written(path) = event {
+            let relative = path.strip_prefix(output).unwrap();
+            written
+                .borrow_mut()
+                .push(relative.to_str().unwrap().replace('\\', "/"));
+        }
+        Ok(())
+    }));
+    mbox_to_eml(&options).unwrap();
+    drop(options);
+    written.into_inner()
+}
+
+/// Extract the date fixture with the given numbering.
+fn extract_by_date(dir: &Path, numbering: Numbering) -> Vec<String> {
+    extract(
+        fixture(dir, "Date", DATES),
+        &dir.join(format!("out-{numbering}")),
+        |options| {
+            options.group_by = GroupBy::Date;
+            options.numbering = numbering;
+        },
+    )
+}
+
+#[test]
+fn by_date() {
+    let dir = temp_dir("subdir-date");
+    let written = extract_by_date(&dir, Numbering::Global);
+    assert_eq!(
+        written,
+        [
+            "2023/03/0000_Message 0.eml",
+            "2023/03/0001_Message 1.eml",
+            "2023/12/0002_Message 2.eml",
+            "2023/12/0003_Message 3.eml",
+            "2024/01/0004_Message 4.eml",
+            "2024/01/0005_Message 5.eml",
+            "2024/02/0006_Message 6.eml",
+            "2025/07/0007_Message 7.eml",
+            "2024/01/0008_Message 8.eml",
+            "2025/07/0009_Message 9.eml",
+        ]
+    );
+
+    let mut per_month = BTreeMap::new();
+    let output = dir.join("out-global");
+    for year in fs::read_dir(&output).unwrap() {
+        let year = year.unwrap().path();
+        if year.file_name().unwrap() == "undated" {
+            per_month.insert("undated".to_string(), fs::read_dir(&year).unwrap().count());
+            continue;
+        }
+        for month in fs::read_dir(&year).unwrap() {
+            let month = month.unwrap().path();
+            let name = month.strip_prefix(&output).unwrap().to_str().unwrap();
+            per_month.insert(
+                name.replace('\\', "/"),
+                fs::read_dir(&month).unwrap().count(),
+            );
+        }
+    }
+    assert_eq!(
+        per_month,
+        BTreeMap::from([
+            ("2023/03".to_string(), 2),
+            ("2023/12".to_string(), 2),
+            ("2024/01".to_string(), 3),
+            ("2024/02".to_string(), 1),
+            ("2025/07".to_string(), 2),
+        ])
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn per_directory_numbering() {
+    let dir = temp_dir("subdir-numbering");
+    let written = extract_by_date(&dir, Numbering::PerDirectory);
+    assert_eq!(
+        written,
+        [
+            "2023/03/0000_Message 0.eml",
+            "2023/03/0001_Message 1.eml",
+            "2023/12/0000_Message 2.eml",
+            "2023/12/0001_Message 3.eml",
+            "2024/01/0000_Message 4.eml",
+            "2024/01/0001_Message 5.eml",
+            "2024/02/0000_Message 6.eml",
+            "2025/07/0000_Message 7.eml",
+            "2024/01/0002_Message 8.eml",
+            "2025/07/0001_Message 9.eml",
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn by_sender() {
+    let dir = temp_dir("subdir-sender");
+    let written = extract(
+        fixture(&dir, "From", SENDERS),
+        &dir.join("out"),
+        |options| {
+            options.group_by = GroupBy::Sender;
+        },
+    );
+    assert_eq!(
+        written,
+        [
+            "alice@example.com/0000_Message 0.eml",
+            "bob@example.com/0001_Message 1.eml",
+            "alice@example.com/0002_Message 2.eml",
+            "carol@lists.example.org/0003_Message 3.eml",
+            "unknown/0004_Message 4.eml",
+            "dave@example.net/0005_Message 5.eml",
+            "alice@example.com/0006_Message 6.eml",
+            "unknown/0007_Message 7.eml",
+        ]
+    );
+
+    let written = extract(
+        fixture(&dir, "From", SENDERS),
+        &dir.join("out-domain"),
+        |options| {
+            options.group_by = GroupBy::SenderDomain;
+            options.other_threshold = 2;
+        },
+    );
+    assert_eq!(
+        written,
+        [
+            "example.com/0000_Message 0.eml",
+            "example.com/0001_Message 1.eml",
+            "example.com/0002_Message 2.eml",
+            "other/0003_Message 3.eml",
+            "unknown/0004_Message 4.eml",
+            "other/0005_Message 5.eml",
+            "example.com/0006_Message 6.eml",
+            "unknown/0007_Message 7.eml",
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
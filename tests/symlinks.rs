@@ -0,0 +1,118 @@
+//! Converts input directories reached through symlinks, and checks that an output path inside
+//! of the input is refused whichever alias of the directory either path is spelled with, that
+//! symlinked directories forming a cycle are searched once and that dangling symlinks are
+//! skipped with a warning.
+#![cfg(unix)]
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{EmlInput, EmlToMboxOptions, Event, MailfmtError, eml_to_mbox};
+use std::{
+    cell::RefCell,
+    fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+fn convert(input: &Path, output: &Path) -> Result<usize, MailfmtError> {
+    let options = EmlToMboxOptions::new(EmlInput::Directory(input.to_path_buf()), output);
+    eml_to_mbox(&options).map(|summary| summary.converted)
+}
+
+#[test]
+fn an_output_inside_of_a_symlinked_alias_of_the_input_is_refused() {
+    let dir = temp_dir("symlinks-alias");
+    fs::create_dir(dir.join("mail")).unwrap();
+    fs::write(dir.join("mail/one.eml"), "Subject: One\n\nHello\n").unwrap();
+    symlink(dir.join("mail"), dir.join("alias")).unwrap();
+    symlink("alias", dir.join("alias-of-alias")).unwrap();
+
+    for (input, output) in [
+        ("alias", "mail/all.mbox"),
+        ("mail", "alias/all.mbox"),
+        ("alias-of-alias", "mail/all.mbox"),
+        ("mail", "alias/../mail/all.mbox"),
+    ] {
+        let error = convert(&dir.join(input), &dir.join(output)).unwrap_err();
+        assert!(
+            matches!(error, MailfmtError::OutputInsideInput { .. }),
+            "{input} -> {output}: {error}"
+        );
+        assert!(!dir.join("mail/all.mbox").exists());
+    }
+
+    // An alias next to the input directory is not inside of it.
+    assert_eq!(
+        convert(&dir.join("alias"), &dir.join("all.mbox")).unwrap(),
+        1
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// An input directory holding a symlink back to itself, a symlink to a directory elsewhere, a
+/// symlinked eml file and a dangling one.
+fn cyclic_input(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::create_dir_all(dir.join("mail/nested")).unwrap();
+    fs::create_dir(dir.join("elsewhere")).unwrap();
+    fs::write(dir.join("mail/one.eml"), "Subject: One\n\nHello\n").unwrap();
+    fs::write(dir.join("mail/nested/two.eml"), "Subject: Two\n\nHello\n").unwrap();
+    fs::write(dir.join("elsewhere/three.eml"), "Subject: Three\n\nHello\n").unwrap();
+    symlink("..", dir.join("mail/nested/loop")).unwrap();
+    symlink(dir.join("mail"), dir.join("mail/self")).unwrap();
+    symlink(dir.join("elsewhere"), dir.join("mail/elsewhere")).unwrap();
+    symlink(dir.join("elsewhere/three.eml"), dir.join("mail/linked.eml")).unwrap();
+    symlink("missing.eml", dir.join("mail/dangling.eml")).unwrap();
+    dir
+}
+
+fn convert_cyclic(dir: &Path, follow_symlinks: bool) -> (String, Vec<String>) {
+    let warnings = RefCell::new(Vec::new());
+    let output = dir.join(format!("follow-{follow_symlinks}.mbox"));
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("mail")), &output);
+    options.follow_symlinks = follow_symlinks;
+    options.on_event = Some(Box::new(|event| {
+        if let Event::Warning(warning) = event {
+            warnings.borrow_mut().push(warning);
+        }
+        Ok(())
+    }));
+    eml_to_mbox(&options).unwrap();
+    drop(options);
+    (fs::read_to_string(output).unwrap(), warnings.into_inner())
+}
+
+fn subjects(mbox: &str) -> Vec<&str> {
+    let mut subjects: Vec<_> = mbox
+        .lines()
+        .filter_map(|line| line.strip_prefix("Subject: "))
+        .collect();
+    subjects.sort();
+    subjects
+}
+
+#[test]
+fn symlinked_directories_are_skipped_unless_followed() {
+    let dir = cyclic_input("symlinks-skipped");
+    let (mbox, warnings) = convert_cyclic(&dir, false);
+    // The symlinked file is included, but neither the cycle nor the other directory is searched.
+    assert_eq!(subjects(&mbox), ["One", "Three", "Two"]);
+    assert_eq!(warnings.len(), 1, "{warnings:?}");
+    assert!(
+        warnings[0].contains("broken symlink") && warnings[0].contains("dangling.eml"),
+        "{warnings:?}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_directory_cycle_is_searched_once_when_followed() {
+    let dir = cyclic_input("symlinks-followed");
+    let (mbox, warnings) = convert_cyclic(&dir, true);
+    // three.eml is reached both through the symlinked directory and the symlinked file.
+    assert_eq!(subjects(&mbox), ["One", "Three", "Three", "Two"]);
+    assert_eq!(warnings.len(), 1, "{warnings:?}");
+    assert!(warnings[0].contains("dangling.eml"), "{warnings:?}");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,48 @@
+//! Transcodes text in the multibyte charsets of Chinese, Japanese and Korean mail to UTF-8.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{MboxToEmlOptions, mbox_to_eml};
+use std::fs;
+
+#[test]
+fn multibyte_charsets_are_transcoded() {
+    let texts: [(&str, &[u8], &str); 5] = [
+        ("Shift_JIS", b"\x93\xfa\x96{", "日本"),
+        ("ISO-2022-JP", b"\x1b$BF|K\\\x1b(B", "日本"),
+        ("GB2312", b"\xd6\xd0\xce\xc4", "中文"),
+        ("Big5", b"\xa4\xa4\xa4\xe5", "中文"),
+        ("EUC-KR", b"\xc7\xd1\xb1\xb9", "한국"),
+    ];
+    let dir = temp_dir("transcode");
+    let mut mbox = Vec::new();
+    for (charset, text, _) in texts {
+        mbox.extend_from_slice(b"From a Mon Jan 01 00:00:00 2024\n");
+        mbox.extend_from_slice(
+            format!("Content-Type: text/plain; charset={charset}\n\n").as_bytes(),
+        );
+        mbox.extend_from_slice(text);
+        mbox.extend_from_slice(b"\n\n");
+    }
+    fs::write(dir.join("in.mbox"), mbox).unwrap();
+
+    let mut options = MboxToEmlOptions::new(dir.join("in.mbox"), dir.join("out"));
+    options.transcode = true;
+    let summary = mbox_to_eml(&options).unwrap();
+    assert_eq!((summary.transcoded, summary.not_transcoded), (5, 0));
+    for (index, (charset, _, text)) in texts.iter().enumerate() {
+        assert_eq!(
+            fs::read_to_string(dir.join(format!("out/{index:04}.eml"))).unwrap(),
+            format!(
+                "Content-Type: text/plain; charset=utf-8\n\
+                 Content-Transfer-Encoding: 8bit\n\
+                 \n\
+                 {text}\n\
+                 \n"
+            ),
+            "{charset}"
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
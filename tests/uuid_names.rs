@@ -0,0 +1,130 @@
+//! Extracts messages into files named by UUID, and checks that the names are UUIDs of the
+//! version asked for.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{Event, MboxToEmlOptions, NameBy, UuidVersion, mbox_to_eml};
+use std::{cell::RefCell, fs, path::Path};
+
+/// An mbox of `count` messages in date order, a day apart.
+fn dated_mbox(path: &Path, count: usize) {
+    let mut mbox = String::new();
+    for day in 0..count {
+        let date = chrono::DateTime::from_timestamp(1_700_000_000 + day as i64 * 86_400, 0)
+            .unwrap()
+            .to_rfc2822();
+        mbox.push_str(&format!(
+            "From a@example.com Mon Jan 01 00:00:00 2024\nDate: {date}\nSubject: Day {day}\n\nHello\n\n"
+        ));
+    }
+    fs::write(path, mbox).unwrap();
+}
+
+/// The version of a UUID in lowercase with hyphens, checking its layout and variant.
+fn uuid_version(name: &str) -> u32 {
+    let groups: Vec<&str> = name.split('-').collect();
+    assert_eq!(
+        groups.iter().map(|group| group.len()).collect::<Vec<_>>(),
+        [8, 4, 4, 4, 12],
+        "{name}"
+    );
+    assert!(
+        groups
+            .iter()
+            .all(|group| group.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))),
+        "{name}"
+    );
+    assert!(matches!(&groups[3][..1], "8" | "9" | "a" | "b"), "{name}");
+    groups[2][..1].parse().unwrap()
+}
+
+/// The file names in a directory without their extension, sorted.
+fn sorted_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            assert_eq!(path.extension().unwrap(), "eml");
+            path.file_stem().unwrap().to_str().unwrap().to_string()
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn files_are_named_by_random_uuids() {
+    let dir = temp_dir("uuid-v4");
+    let mbox = dir.join("in.mbox");
+    dated_mbox(&mbox, 20);
+    let mut options = MboxToEmlOptions::new(&mbox, dir.join("out"));
+    options.name_by = NameBy::Uuid;
+    mbox_to_eml(&options).unwrap();
+
+    let names = sorted_names(&dir.join("out"));
+    assert_eq!(names.len(), 20);
+    assert!(names.iter().all(|name| uuid_version(name) == 4));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn time_ordered_uuids_sort_chronologically() {
+    let dir = temp_dir("uuid-v7");
+    let mbox = dir.join("in.mbox");
+    // Far more messages than are extracted in a millisecond.
+    dated_mbox(&mbox, 500);
+    let output = dir.join("out");
+    let mut options = MboxToEmlOptions::new(&mbox, &output);
+    options.name_by = NameBy::Uuid;
+    options.uuid_version = UuidVersion::V7;
+    mbox_to_eml(&options).unwrap();
+
+    let names = sorted_names(&output);
+    assert_eq!(names.len(), 500);
+    for (day, name) in names.iter().enumerate() {
+        assert_eq!(uuid_version(name), 7);
+        let content = fs::read_to_string(output.join(format!("{name}.eml"))).unwrap();
+        assert!(
+            content.contains(&format!("Subject: Day {day}\n")),
+            "{name} is not the file of day {day}"
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_warning_tells_when_nothing_maps_the_files_back() {
+    let dir = temp_dir("uuid-warning");
+    let mbox = dir.join("in.mbox");
+    dated_mbox(&mbox, 2);
+    for (msgid_index, sidecar_metadata, warned) in [
+        (false, false, true),
+        (true, false, false),
+        (false, true, false),
+    ] {
+        let warnings = RefCell::new(Vec::new());
+        let mut options = MboxToEmlOptions::new(&mbox, dir.join("out"));
+        options.overwrite = true;
+        options.name_by = NameBy::Uuid;
+        options.msgid_index = msgid_index.then(|| dir.join("index.tsv"));
+        options.sidecar_metadata = sidecar_metadata;
+        options.on_event = Some(Box::new(|event| {
+            if let Event::Warning(warning) = event {
+                warnings.borrow_mut().push(warning);
+            }
+            Ok(())
+        }));
+        mbox_to_eml(&options).unwrap();
+        drop(options);
+        let warnings = warnings.into_inner();
+        assert_eq!(
+            warnings
+                .iter()
+                .any(|warning| warning.contains("named by UUID")),
+            warned,
+            "{warnings:?}"
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,105 @@
+//! Converts eml files with `verify_content` and checks that messages which read back as they
+//! were written pass, and that those the dialect cannot keep apart from other content are
+//! reported.
+
+mod common;
+
+use common::fixture_dir;
+use mailfmt::{EmlInput, EmlToMboxOptions, Event, MailfmtError, MboxDialect, eml_to_mbox};
+use std::{cell::RefCell, fs, process::Command};
+
+#[test]
+fn quoted_lines_and_line_endings_read_back_as_written() {
+    let dir = fixture_dir(
+        "verify-content-match",
+        &[(
+            "a.eml",
+            "Subject: A\r\n\r\nFrom here\r\n>From there\r\n\r\n\r\n",
+        )],
+    );
+    fs::write(dir.join("in/b.eml"), "Subject: B\n\nNo final newline").unwrap();
+    let output = dir.join("out.mbox");
+
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), &output);
+    options.verify_content = true;
+    let summary = eml_to_mbox(&options).unwrap();
+    assert_eq!((summary.converted, summary.verified), (2, 2));
+
+    let appended = Command::new(env!("CARGO_BIN_EXE_mailfmt"))
+        .args(["eml-to-mbox", "--append", "--verify-content"])
+        .arg(dir.join("in"))
+        .arg(&output)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&appended.stdout);
+    assert!(appended.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("2 emails were read back from the mbox file and match their eml files"),
+        "{stdout}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn from_lines_within_the_content_length_read_back_as_written() {
+    let dir = fixture_dir(
+        "verify-content-length",
+        &[("a.eml", "Subject: A\n\nFirst\nFrom someone else\nSecond\n")],
+    );
+    fs::write(dir.join("in/b.eml"), "Subject: B\n\nIntact\n").unwrap();
+
+    let mut options =
+        EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), dir.join("out.mbox"));
+    options.dialect = MboxDialect::Mboxcl2;
+    options.verify_content = true;
+    let summary = eml_to_mbox(&options).unwrap();
+    assert_eq!((summary.converted, summary.verified), (2, 2));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn messages_the_dialect_cannot_tell_apart_fail_verification() {
+    for dialect in [MboxDialect::Mboxo, MboxDialect::Mboxcl] {
+        let body = "Quoted\n>From the start\n";
+        let dir = fixture_dir(
+            "verify-content-mismatch",
+            &[
+                ("a.eml", &format!("Subject: A\n\n{body}")),
+                ("b.eml", "Subject: B\n\nIntact\n"),
+            ],
+        );
+        let output = dir.join("out.mbox");
+
+        let warnings = RefCell::new(Vec::new());
+        let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), &output);
+        options.dialect = dialect;
+        options.verify_content = true;
+        options.on_event = Some(Box::new(|event| {
+            if let Event::Warning(warning) = event {
+                warnings.borrow_mut().push(warning);
+            }
+            Ok(())
+        }));
+        let error = eml_to_mbox(&options).unwrap_err();
+        drop(options);
+        assert!(
+            matches!(
+                error,
+                MailfmtError::VerificationFailed {
+                    written: 2,
+                    mismatched: 1..
+                }
+            ),
+            "{dialect:?}: {error}"
+        );
+        assert_eq!(error.code(), "verification-failed");
+        let warnings = warnings.into_inner();
+        assert!(
+            warnings.iter().any(|warning| warning.contains("a.eml")),
+            "{dialect:?}: {warnings:?}"
+        );
+        assert!(output.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+//! Watches an input directory after converting it, and checks that eml files arriving later,
+//! including in new subdirectories, are appended once they have settled.
+
+mod common;
+
+use common::temp_dir;
+use mailfmt::{EmlInput, EmlToMboxOptions, Event, MboxParser, eml_to_mbox};
+use std::{
+    fs,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn files_arriving_later_are_appended() {
+    let dir = temp_dir("watch");
+    fs::create_dir(dir.join("in")).unwrap();
+    fs::write(dir.join("in/one.eml"), "Subject: One\n\nFirst\n").unwrap();
+    let output = dir.join("out.mbox");
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    let appended = AtomicUsize::new(0);
+    let input = dir.join("in");
+    let writer = thread::spawn({
+        let cancel = Arc::clone(&cancel);
+        move || {
+            // Give the watcher time to start, so that the files are reported by it.
+            thread::sleep(Duration::from_millis(500));
+            fs::write(input.join("two.eml"), "Subject: Two\n").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            fs::write(input.join("two.eml"), "Subject: Two\n\nSecond\n").unwrap();
+            fs::create_dir(input.join("later")).unwrap();
+            fs::write(input.join("later/three.eml"), "Subject: Three\n\nThird\n").unwrap();
+            let start = Instant::now();
+            while !cancel.load(Ordering::Relaxed) && start.elapsed() < Duration::from_secs(20) {
+                thread::sleep(Duration::from_millis(50));
+            }
+            cancel.store(true, Ordering::Relaxed);
+        }
+    });
+
+    let mut options = EmlToMboxOptions::new(EmlInput::Directory(dir.join("in")), &output);
+    options.watch = true;
+    options.settle = Duration::from_millis(300);
+    options.cancel = Some(Arc::clone(&cancel));
+    options.on_event = Some(Box::new(|event| {
+        if let Event::Appended(_) = event
+            && appended.fetch_add(1, Ordering::Relaxed) == 1
+        {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }));
+    let summary = eml_to_mbox(&options).unwrap();
+    drop(options);
+    writer.join().unwrap();
+
+    assert_eq!(summary.converted, 1);
+    assert_eq!(summary.watch.map(|watch| watch.appended), Some(2));
+    let mbox = fs::read(&output).unwrap();
+    let mut subjects: Vec<_> = MboxParser::from_reader(mbox.as_slice())
+        .map(|message| message.unwrap().header("subject").unwrap().to_string())
+        .collect();
+    subjects.sort();
+    assert_eq!(subjects, ["One", "Three", "Two"]);
+    // The second file was only appended once it was written completely.
+    assert!(String::from_utf8_lossy(&mbox).contains("Second"));
+    fs::remove_dir_all(&dir).unwrap();
+}